@@ -0,0 +1,6 @@
+// Kept so existing cron jobs keep working, the same as `investments data clean`
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let mut args = vec!["clean".to_string()];
+    args.extend(std::env::args().skip(1));
+    investments::data::run(&args)
+}
@@ -0,0 +1,111 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use quantlib::calendar;
+use quantlib::data::BinReader;
+use quantlib::oanda::connection_quality::{read_connection_log, ConnectionLogEntry};
+use quantlib::util::CollectorConfig;
+
+const MINUTE: u64 = 60_000;
+const MINUTES_PER_DAY: usize = 24 * 60;
+
+// Summary of the connection for a single UTC day
+#[derive(Default)]
+struct DaySummary {
+    // Minutes in which anything was received from the stream
+    connected_minutes: BTreeSet<u64>,
+    reconnects: u64,
+    parse_errors: u64,
+    max_heartbeat_gap: u64,
+}
+
+fn day_of(time: u64) -> String {
+    calendar::utc(time).format("%Y-%m-%d").to_string()
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    // Summarises uptime and data completeness from the output of data-collection
+    // Takes the same config file as data-collection, so it knows where the data is stored
+    let config = match std::env::args().nth(1) {
+        Some(path) => CollectorConfig::load(path)?,
+        None => CollectorConfig::default(),
+    };
+    let layout = config.storage.read_only();
+    let _claim = layout.claim("connection_report")?;
+
+    let mut entries = Vec::new();
+    for path in layout.connection_logs()? {
+        entries.extend(read_connection_log(path)?);
+    }
+
+    let mut days: BTreeMap<String, DaySummary> = BTreeMap::new();
+    for entry in entries {
+        match entry {
+            ConnectionLogEntry::Minute { start, stats } => {
+                let day = days.entry(day_of(start)).or_default();
+                if stats.chunks > 0 {
+                    day.connected_minutes.insert(start);
+                }
+                day.parse_errors += stats.parse_errors;
+                day.max_heartbeat_gap = day.max_heartbeat_gap.max(stats.max_heartbeat_gap);
+            }
+            ConnectionLogEntry::Reconnect { time, .. } => {
+                days.entry(day_of(time)).or_default().reconnects += 1;
+            }
+        }
+    }
+
+    println!("Connection");
+    println!(
+        "{:<12}{:>10}{:>12}{:>14}{:>16}",
+        "day", "uptime", "reconnects", "parse errors", "max hb gap (s)"
+    );
+    for (day, summary) in &days {
+        println!(
+            "{:<12}{:>9.1}%{:>12}{:>14}{:>16.1}",
+            day,
+            100.0 * summary.connected_minutes.len() as f64 / MINUTES_PER_DAY as f64,
+            summary.reconnects,
+            summary.parse_errors,
+            summary.max_heartbeat_gap as f64 / 1000.0
+        );
+    }
+
+    // Completeness is the share of connected minutes in which an instrument had at least one tick.
+    // Quiet instruments will naturally score lower, what matters is a sudden drop.
+    let files = layout.bin_files()?;
+
+    println!();
+    println!("Data completeness");
+    println!(
+        "{:<12}{:<10}{:>10}{:>14}",
+        "day", "instrument", "ticks", "completeness"
+    );
+    for (instrument, path) in files {
+        let reader = BinReader::open_instrument(&path, &instrument)?;
+
+        let mut ticks: BTreeMap<String, (u64, BTreeSet<u64>)> = BTreeMap::new();
+        for price in reader {
+            let (count, minutes) = ticks.entry(day_of(price.time)).or_default();
+            *count += 1;
+            minutes.insert(price.time - price.time % MINUTE);
+        }
+
+        for (day, (count, minutes)) in &ticks {
+            // Ticks logged before connection logging existed have nothing to compare against
+            let completeness = match days.get(day) {
+                Some(summary) if !summary.connected_minutes.is_empty() => format!(
+                    "{:.1}%",
+                    100.0 * minutes.intersection(&summary.connected_minutes).count() as f64
+                        / summary.connected_minutes.len() as f64
+                ),
+                _ => "-".to_string(),
+            };
+            println!(
+                "{:<12}{:<10}{:>10}{:>14}",
+                day, instrument, count, completeness
+            );
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,173 @@
+use std::collections::BTreeMap;
+use std::io::Write;
+
+use chrono::{Datelike, Weekday};
+use serde::Serialize;
+
+use quantlib::calendar;
+use quantlib::data::BinReader;
+use quantlib::oanda::objects::Price;
+use quantlib::util::CollectorConfig;
+
+const HOUR: u64 = 3_600_000;
+
+// Gaps between ticks shorter than this are normal for quiet instruments
+const GAP_THRESHOLD: u64 = 5 * 60_000;
+
+#[derive(Serialize, Default)]
+struct HourSummary {
+    ticks: u64,
+
+    // Longest time without a tick within the hour, in milliseconds
+    #[serde(rename = "maxGap")]
+    max_gap: u64,
+}
+
+#[derive(Serialize)]
+struct Gap {
+    start: u64,
+    end: u64,
+    minutes: f64,
+
+    // The FX market is closed over the weekend, so these gaps are expected
+    weekend: bool,
+}
+
+#[derive(Serialize)]
+struct InstrumentSummary {
+    instrument: String,
+    ticks: u64,
+    first: u64,
+    last: u64,
+
+    // Share of the hours between first and last tick (excluding Saturdays) that have any ticks
+    completeness: f64,
+
+    // Tick counts keyed by the start of each hour, for rendering as a heatmap
+    hours: BTreeMap<u64, HourSummary>,
+    gaps: Vec<Gap>,
+}
+
+fn is_weekend(start: u64, end: u64) -> bool {
+    // Any gap that covers a Saturday (UTC) spans the weekend close
+    let mut time = start;
+    while time <= end {
+        if calendar::utc(time).weekday() == Weekday::Sat {
+            return true;
+        }
+        time += HOUR;
+    }
+    false
+}
+
+// Files of a dated layout are read one after the other, so prices are assumed to be in order
+fn summarise(instrument: String, prices: impl Iterator<Item = Price>) -> Option<InstrumentSummary> {
+    let mut hours: BTreeMap<u64, HourSummary> = BTreeMap::new();
+    let mut gaps = Vec::new();
+    let mut ticks = 0;
+    let mut first = None;
+    let mut last: Option<u64> = None;
+
+    for price in prices {
+        ticks += 1;
+        first.get_or_insert(price.time);
+
+        let hour = hours.entry(price.time - price.time % HOUR).or_default();
+        hour.ticks += 1;
+        if let Some(last) = last {
+            let gap = price.time.saturating_sub(last);
+            hour.max_gap = hour.max_gap.max(gap);
+            if gap >= GAP_THRESHOLD {
+                gaps.push(Gap {
+                    start: last,
+                    end: price.time,
+                    minutes: gap as f64 / 60_000.0,
+                    weekend: is_weekend(last, price.time),
+                });
+            }
+        }
+        last = Some(price.time);
+    }
+
+    let (first, last) = (first?, last?);
+    let expected = (first - first % HOUR..=last - last % HOUR)
+        .step_by(HOUR as usize)
+        .filter(|hour| !is_weekend(*hour, *hour))
+        .count();
+    let covered = hours
+        .keys()
+        .filter(|hour| !is_weekend(**hour, **hour))
+        .count();
+
+    Some(InstrumentSummary {
+        instrument,
+        ticks,
+        first,
+        last,
+        completeness: if expected > 0 {
+            covered as f64 / expected as f64
+        } else {
+            0.0
+        },
+        hours,
+        gaps,
+    })
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    // Summarises which collected series are usable, as data for a completeness heatmap
+    let args: Vec<String> = std::env::args().collect();
+    if args.len() < 2 {
+        eprintln!("Usage: {} <output name> [collector config]", args[0]);
+        std::process::exit(1);
+    }
+    let config = match args.get(2) {
+        Some(path) => CollectorConfig::load(path)?,
+        None => CollectorConfig::default(),
+    };
+
+    let _claim = config.storage.clone().read_only().claim("data_quality")?;
+
+    // Files of the same instrument (e.g. with a dated layout) are summarised together
+    let mut files: BTreeMap<String, Vec<std::path::PathBuf>> = BTreeMap::new();
+    for (instrument, path) in config.storage.bin_files()? {
+        files.entry(instrument).or_default().push(path);
+    }
+
+    let mut summaries = Vec::new();
+    for (instrument, paths) in files {
+        let readers = paths
+            .iter()
+            .map(|path| BinReader::open_instrument(path, &instrument))
+            .collect::<Result<Vec<_>, _>>()?;
+        let prices = readers.into_iter().flatten();
+        if let Some(summary) = summarise(instrument, prices) {
+            println!(
+                "{:<10}{:>12} ticks{:>8.1}% complete{:>6} gaps",
+                summary.instrument,
+                summary.ticks,
+                summary.completeness * 100.0,
+                summary.gaps.iter().filter(|gap| !gap.weekend).count()
+            );
+            summaries.push(summary);
+        }
+    }
+
+    // One row per instrument and hour, the format most plotting tools expect for a heatmap
+    let mut writer = std::io::BufWriter::new(std::fs::File::create(format!("{}.csv", args[1]))?);
+    writeln!(writer, "instrument,hour,ticks,max_gap")?;
+    for summary in &summaries {
+        for (hour, stats) in &summary.hours {
+            writeln!(
+                writer,
+                "{},{},{},{}",
+                summary.instrument, hour, stats.ticks, stats.max_gap
+            )?;
+        }
+    }
+    writer.flush()?;
+
+    let writer = std::io::BufWriter::new(std::fs::File::create(format!("{}.json", args[1]))?);
+    serde_json::to_writer_pretty(writer, &summaries)?;
+    Ok(())
+}
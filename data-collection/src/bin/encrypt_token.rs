@@ -0,0 +1,42 @@
+use quantlib::secrets;
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    // Encrypts the OANDA token in a settings file in place. Without a recipient it asks for a
+    // passphrase (or takes OANDA_TOKEN_PASSPHRASE), which is needed again at every start. With
+    // an age recipient (age1..., from age-keygen) the processes decrypt it with the matching
+    // identity in OANDA_TOKEN_KEY.
+    let args: Vec<String> = std::env::args().collect();
+    if args.len() < 2 {
+        eprintln!("Usage: {} <settings file> [age recipient]", args[0]);
+        std::process::exit(1);
+    }
+    let path = &args[1];
+
+    let mut settings: serde_json::Value = serde_json::from_str(&std::fs::read_to_string(path)?)?;
+    // Settings in sections keep it under credentials, those in the old flat format at the top
+    let pointer = match settings.pointer("/credentials/oanda/authorization") {
+        Some(_) => "/credentials/oanda/authorization",
+        None => "/oanda/authorization",
+    };
+    let token = match settings.pointer(pointer).and_then(|token| token.as_str()) {
+        Some(token) => token.to_string(),
+        None => {
+            eprintln!("No credentials.oanda.authorization in {}", path);
+            std::process::exit(1);
+        }
+    };
+    if secrets::is_encrypted(&token) {
+        eprintln!("The token in {} is already encrypted", path);
+        std::process::exit(1);
+    }
+
+    let encrypted = secrets::encrypt(&token, args.get(2).map(|s| s.as_str()))?;
+    *settings.pointer_mut(pointer).unwrap() = serde_json::Value::String(encrypted);
+
+    // Replaced in one go, so the settings are never left half written
+    let temporary = format!("{}.tmp", path);
+    std::fs::write(&temporary, serde_json::to_string_pretty(&settings)?)?;
+    std::fs::rename(&temporary, path)?;
+    println!("Encrypted the token in {}", path);
+    Ok(())
+}
@@ -0,0 +1,149 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::iter::Peekable;
+use std::path::PathBuf;
+
+use quantlib::calendar;
+use quantlib::data::{self, BinReader, IntegrityReport, PriceWriter, TICK};
+use quantlib::oanda::objects::Price;
+use quantlib::util::CollectorConfig;
+
+const DAY: u64 = 86_400_000;
+
+// Stretches without a tick longer than this in the most complete source are filled from the others
+const GAP: u64 = 30_000;
+
+// An instrument's prices in one collector's data, read a UTC day at a time so that only a day of
+// each source is ever held in memory
+struct DayReader {
+    prices: Option<Peekable<BinReader>>,
+}
+
+impl DayReader {
+    fn open(instrument: &str, paths: &[PathBuf]) -> Result<Self, Box<dyn std::error::Error>> {
+        let prices = match paths {
+            [] => None,
+            _ => Some(
+                BinReader::open_sequence(paths.to_vec(), instrument)?
+                    .with_overlap()
+                    .peekable(),
+            ),
+        };
+        Ok(DayReader { prices })
+    }
+
+    // The day of the next price, None once they've all been read
+    fn next_day(&mut self) -> Option<u64> {
+        let price = self.prices.as_mut()?.peek()?;
+        Some(price.time - price.time % DAY)
+    }
+
+    // The clean prices of `date`. Any of an earlier day still to be read are out of order, and
+    // dropped rather than written into this day.
+    fn read_day(&mut self, date: u64, integrity: &mut IntegrityReport) -> Vec<Price> {
+        let mut prices = Vec::new();
+        let mut late = 0;
+        if let Some(reader) = &mut self.prices {
+            while let Some(price) = reader.next_if(|price| price.time < date + DAY) {
+                if price.time < date {
+                    late += 1;
+                } else {
+                    prices.push(price);
+                }
+            }
+        }
+        let (prices, mut report) = data::clean_prices(prices);
+        report.records += late;
+        report.out_of_order += late;
+        integrity.add(&report);
+        prices
+    }
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    // Combines the data of collectors run side by side (e.g. on different hosts, each with its own
+    // data directory, copied to one place) into one canonical dataset with as few gaps as possible.
+    // Each instrument and day is based on whichever collector recorded the most ticks, with any
+    // gaps filled from the others. The output is laid out like a collector's, so everything that
+    // reads collected data (e.g. weekly_pipeline) can read it, and is replaced on every run.
+    // Sources are read and the output written a day at a time, so any amount of data fits.
+    let args: Vec<String> = std::env::args().collect();
+    if args.len() < 3 {
+        eprintln!(
+            "Usage: {} <output collector config> <collector config>...",
+            args[0]
+        );
+        std::process::exit(1);
+    }
+    investments::common::configure_logging("merge_collectors")?;
+    let output = CollectorConfig::load(&args[1])?.storage;
+    let sources = args[2..]
+        .iter()
+        .map(|path| CollectorConfig::load(path).map(|config| config.storage.read_only()))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let _claims = sources
+        .iter()
+        .map(|source| source.claim("merge_collectors"))
+        .collect::<Result<Vec<_>, _>>()?;
+    let _output = output.claim("merge_collectors")?;
+
+    let mut files: Vec<BTreeMap<String, Vec<PathBuf>>> = Vec::new();
+    for source in &sources {
+        let mut source_files: BTreeMap<String, Vec<PathBuf>> = BTreeMap::new();
+        for (instrument, path) in source.bin_files()? {
+            source_files.entry(instrument).or_default().push(path);
+        }
+        files.push(source_files);
+    }
+    let instruments: BTreeSet<&String> = files.iter().flat_map(|files| files.keys()).collect();
+
+    let mut integrity = IntegrityReport::default();
+    for instrument in instruments {
+        let mut sources = Vec::new();
+        for source_files in &files {
+            let paths = source_files.get(instrument).map_or(&[][..], |paths| paths);
+            sources.push(DayReader::open(instrument, paths)?);
+        }
+
+        // Days that share an output file (e.g. without {date} in the layout) are written to it in
+        // turn, and it's finished once the days move on to another
+        let mut writer: Option<PriceWriter> = None;
+        while let Some(date) = sources.iter_mut().filter_map(DayReader::next_day).min() {
+            let recorded: Vec<Vec<Price>> = sources
+                .iter_mut()
+                .map(|source| source.read_day(date, &mut integrity))
+                .collect();
+            let (merged, report) = data::merge_sources(&recorded, GAP);
+            println!(
+                "{:<10}{:>12}{:>12} ticks from sources {:?}, {} based on source {}, {} filled",
+                instrument,
+                calendar::utc(date).format("%Y-%m-%d"),
+                report.records,
+                report.sources,
+                report.records - report.filled,
+                report.primary + 1,
+                report.filled
+            );
+            let path = output.bin_path(instrument, TICK, date);
+            if writer.as_ref().is_some_and(|writer| writer.path() != path) {
+                if let Some(finished) = writer.take() {
+                    finished.finish()?;
+                }
+            }
+            if writer.is_none() {
+                writer = Some(PriceWriter::create(&path, output.time_precision)?);
+            }
+            if let Some(writer) = &mut writer {
+                writer.write(&merged)?;
+            }
+        }
+        if let Some(writer) = writer {
+            writer.finish()?;
+        }
+    }
+
+    if !integrity.is_clean() {
+        log::warn!("Sources: {:?}", integrity);
+    }
+    Ok(())
+}
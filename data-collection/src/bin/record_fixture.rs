@@ -0,0 +1,49 @@
+use quantlib::oanda::fixtures::StreamFixture;
+use quantlib::oanda::ChunkSource;
+use quantlib::util::CollectorConfig;
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    // Records a few chunks of the price stream, boundaries and all, as a test fixture. Connects
+    // the same way the collector does (to its relay if the config has one), for the instruments
+    // in settings.json.
+    let args: Vec<String> = std::env::args().collect();
+    if args.len() < 3 {
+        eprintln!(
+            "Usage: {} <fixture file> <chunks> [collector config]",
+            args[0]
+        );
+        std::process::exit(1);
+    }
+    let count: usize = args[2].parse()?;
+    let config = match args.get(3) {
+        Some(path) => CollectorConfig::load(path)?,
+        None => CollectorConfig::default(),
+    };
+    let settings = quantlib::util::read_settings()?;
+
+    let mut source = ChunkSource::connect(
+        &settings.data.instruments,
+        &settings.credentials.oanda,
+        config.relay_address.as_deref(),
+    )
+    .await?;
+    let mut fixture = StreamFixture {
+        description: format!(
+            "{} chunks of {}",
+            count,
+            settings.data.instruments.join(", ")
+        ),
+        chunks: Vec::new(),
+    };
+    while fixture.chunks.len() < count {
+        match source.chunk().await? {
+            Some(chunk) => fixture.record(&chunk),
+            None => break,
+        }
+    }
+
+    fixture.save(&args[1])?;
+    println!("Recorded {} chunks to {}", fixture.chunks.len(), args[1]);
+    Ok(())
+}
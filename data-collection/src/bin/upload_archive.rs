@@ -0,0 +1,98 @@
+use std::path::PathBuf;
+
+use quantlib::data::{self, TICK};
+use quantlib::upload::UploadOutcome;
+use quantlib::util::CollectorConfig;
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    // Copies the collector's rotated raw logs and tick files, compressed, to every sink in the
+    // config's "archiveSinks". Files are rotated when their template contains {date}, and only
+    // files of past days are copied. Safe to run as often as needed, e.g. from cron:
+    // 30 0 * * * upload_archive /etc/collector.json
+    let args: Vec<String> = std::env::args().collect();
+    if args.len() < 2 {
+        eprintln!("Usage: {} <collector config>", args[0]);
+        std::process::exit(1);
+    }
+    investments::common::configure_logging("upload_archive")?;
+    let config = CollectorConfig::load(&args[1])?;
+    if config.archive_sinks.is_empty() {
+        eprintln!("No archiveSinks in {}", args[1]);
+        std::process::exit(1);
+    }
+
+    let storage = config.storage.clone().read_only();
+    let _claim = storage.claim("upload_archive")?;
+    let now = chrono::Utc::now().timestamp_millis() as u64;
+
+    // The files being written today are the ones today's date renders to
+    let mut files: Vec<PathBuf> = Vec::new();
+    if storage.raw_log.contains("{date}") {
+        let current = storage.raw_log_path(now);
+        files.extend(
+            storage
+                .raw_logs()?
+                .into_iter()
+                .filter(|path| *path != current),
+        );
+    } else {
+        log::warn!("rawLog has no {{date}}, so raw logs are never rotated or uploaded");
+    }
+    if storage.bin.contains("{date}") {
+        for (instrument, path) in storage.bin_files()? {
+            if path != storage.bin_path(&instrument, TICK, now) {
+                files.push(path);
+            }
+        }
+    } else {
+        log::warn!("bin has no {{date}}, so tick files are never rotated or uploaded");
+    }
+
+    // Each file is compressed into here before it's uploaded, rather than in memory
+    let staged = std::env::temp_dir().join(format!(
+        "upload_archive-{}.{}",
+        std::process::id(),
+        data::COMPRESSED_EXTENSION
+    ));
+    let mut uploaded = 0;
+    let mut unchanged = 0;
+    let mut failed = 0;
+    for path in files {
+        // Files compressed by compact_data are uploaded as they were before, so nothing changes
+        let relative = path
+            .strip_prefix(&storage.root)?
+            .to_string_lossy()
+            .replace('\\', "/");
+        let relative = relative
+            .strip_suffix(&format!(".{}", data::COMPRESSED_EXTENSION))
+            .unwrap_or(&relative);
+        let checksum = data::compress_file(&path, &staged)?;
+        let key = format!("{}.{}", relative, data::COMPRESSED_EXTENSION);
+
+        for sink in &config.archive_sinks {
+            match sink.put(&key, &staged, &checksum).await {
+                Ok(UploadOutcome::Uploaded) => {
+                    log::info!("[{}] Uploaded {}", sink.name(), key);
+                    uploaded += 1;
+                }
+                Ok(UploadOutcome::Unchanged) => unchanged += 1,
+                Err(e) => {
+                    log::error!("[{}] Failed to upload {}: {}", sink.name(), key, e);
+                    failed += 1;
+                }
+            }
+        }
+    }
+
+    let _ = std::fs::remove_file(&staged);
+
+    println!(
+        "{} uploaded, {} unchanged, {} failed",
+        uploaded, unchanged, failed
+    );
+    if failed > 0 {
+        std::process::exit(1);
+    }
+    Ok(())
+}
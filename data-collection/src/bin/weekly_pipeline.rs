@@ -0,0 +1,253 @@
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::iter::Peekable;
+use std::path::{Path, PathBuf};
+
+use quantlib::calendar;
+use quantlib::catalog::{Catalog, CatalogEntry, DatasetManifest};
+use quantlib::claims::DirectoryClaim;
+use quantlib::data::{self, BinReader, IntegrityReport};
+use quantlib::oanda::objects::Price;
+use quantlib::upload;
+use quantlib::util::CollectorConfig;
+
+// An instrument's collected prices, read a trading week at a time so that only a week is ever held
+// in memory. Files are read in the order of their paths, which with a layout by date is the order
+// they were collected in.
+struct WeekReader {
+    prices: Peekable<BinReader>,
+}
+
+impl WeekReader {
+    fn open(instrument: &str, paths: &[PathBuf]) -> Result<Self, Box<dyn std::error::Error>> {
+        let prices = BinReader::open_sequence(paths.to_vec(), instrument)?
+            .with_overlap()
+            .peekable();
+        Ok(WeekReader { prices })
+    }
+
+    // The week of the next price, None once they've all been read
+    fn next_week(&mut self) -> Option<(i32, u32)> {
+        let price = self.prices.peek()?;
+        Some(calendar::trading_week(price.time))
+    }
+
+    // The prices of a week, and apart from them any of an earlier week still to be read
+    fn read_week(&mut self, year: i32, week: u32) -> (Vec<Price>, Vec<Price>) {
+        let (start, end) = (
+            calendar::week_start(year, week),
+            calendar::week_end(year, week),
+        );
+        let mut prices = Vec::new();
+        let mut late = Vec::new();
+        while let Some(price) = self.prices.next_if(|price| price.time < end) {
+            if price.time < start {
+                late.push(price);
+            } else {
+                prices.push(price);
+            }
+        }
+        (prices, late)
+    }
+}
+
+// The archive being written, and the catalog of it
+struct Pipeline {
+    archive: PathBuf,
+    config: CollectorConfig,
+    catalog: Catalog,
+    now: u64,
+}
+
+impl Pipeline {
+    // Merge an instrument's newly collected prices of a week with those already archived, and
+    // write the week unless it's unchanged. Returns whether it was written.
+    fn archive_week(
+        &mut self,
+        instrument: &str,
+        paths: &[PathBuf],
+        (year, week): (i32, u32),
+        prices: Vec<Price>,
+    ) -> Result<bool, Box<dyn std::error::Error>> {
+        let compress = self.config.compress_archive;
+        let relative = weekly_path(instrument, year, week, compress);
+        let path = self.archive.join(&relative);
+        // The week may have been archived before compression was turned on or off
+        let other = self
+            .archive
+            .join(weekly_path(instrument, year, week, !compress));
+
+        let mut merged = prices;
+        for existing in [&path, &other] {
+            if existing.exists() {
+                merged.extend(BinReader::open_instrument(existing, instrument)?);
+            }
+        }
+        let (merged, _) = data::clean_prices(merged);
+
+        // Weeks compressed by compact_data since are left compressed until they change
+        let complete = calendar::week_end(year, week) <= self.now;
+        let unchanged = self
+            .catalog
+            .get(instrument, year, week)
+            .is_some_and(|entry| {
+                entry.records == merged.len() as u64
+                    && entry.complete == complete
+                    && (entry.path == relative || data::is_compressed(&entry.path))
+                    && entry.manifest.is_some()
+                    && self.archive.join(&entry.path).exists()
+            });
+        if unchanged {
+            return Ok(false);
+        }
+
+        // The week's sources are those it was built from before as well as today's
+        let mut sources: Vec<String> = paths
+            .iter()
+            .map(|path| path.to_string_lossy().to_string())
+            .collect();
+        for existing in [&path, &other] {
+            if let Some(manifest) = DatasetManifest::load(existing)? {
+                sources.extend(manifest.source_files);
+            }
+        }
+        sources.sort();
+        sources.dedup();
+
+        data::write_prices(&path, &merged, self.config.storage.time_precision)?;
+        if other.exists() {
+            std::fs::remove_file(&other)?;
+            std::fs::remove_file(DatasetManifest::path(&other)).ok();
+        }
+        let manifest = DatasetManifest::new(
+            instrument,
+            year,
+            week,
+            &merged,
+            sources,
+            upload::sha256_hex(&std::fs::read(&path)?),
+            complete,
+        );
+        manifest.save(&path)?;
+        self.catalog.upsert(CatalogEntry {
+            instrument: instrument.to_string(),
+            year,
+            week,
+            path: relative,
+            records: merged.len() as u64,
+            first: merged.first().map_or(0, |price| price.time),
+            last: merged.last().map_or(0, |price| price.time),
+            complete,
+            manifest: Some(manifest),
+        });
+        Ok(true)
+    }
+}
+
+fn weekly_path(instrument: &str, year: i32, week: u32, compressed: bool) -> PathBuf {
+    let name = format!("{}.bin", calendar::week_name(year, week));
+    let path = Path::new("weekly").join(instrument).join(name);
+    if compressed {
+        path.with_extension(format!("bin.{}", data::COMPRESSED_EXTENSION))
+    } else {
+        path
+    }
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    // Replaces the manual clean_data / end_of_week.py steps. Safe to run as often as needed, e.g.
+    // from cron: 0 * * * * weekly_pipeline /data/archive /etc/collector.json
+    // Weeks that were already archived are merged with the newly collected data, so rerunning
+    // over the same files leaves the archive unchanged. Collected data is read a week at a time, so
+    // any amount of it fits.
+    let args: Vec<String> = std::env::args().collect();
+    if args.len() < 2 {
+        eprintln!("Usage: {} <archive dir> [collector config]", args[0]);
+        std::process::exit(1);
+    }
+    investments::common::configure_logging("weekly_pipeline")?;
+    let archive = PathBuf::from(&args[1]);
+    let config = match args.get(2) {
+        Some(path) => CollectorConfig::load(path)?,
+        None => CollectorConfig::default(),
+    };
+
+    // Read the collector's data alongside it, and make sure no other pipeline run writes the archive
+    let _source = config
+        .storage
+        .clone()
+        .read_only()
+        .claim("weekly_pipeline")?;
+    let _archive = DirectoryClaim::writer(&archive, "weekly_pipeline")?;
+    let mut pipeline = Pipeline {
+        catalog: Catalog::load(&archive)?,
+        archive,
+        config,
+        now: chrono::Utc::now().timestamp_millis() as u64,
+    };
+
+    let mut files: BTreeMap<String, Vec<PathBuf>> = BTreeMap::new();
+    for (instrument, path) in pipeline.config.storage.bin_files()? {
+        files.entry(instrument).or_default().push(path);
+    }
+
+    let mut integrity = IntegrityReport::default();
+    let mut weeks_written = 0;
+    let mut weeks_unchanged = 0;
+    for (instrument, paths) in files {
+        let mut reader = WeekReader::open(&instrument, &paths)?;
+        let mut report = IntegrityReport::default();
+        // Prices of a week already archived on this run, merged into it once the rest are read
+        let mut late: BTreeMap<(i32, u32), Vec<Price>> = BTreeMap::new();
+        // Whether each week was written, on either pass
+        let mut weeks: BTreeMap<(i32, u32), bool> = BTreeMap::new();
+        while let Some((year, week)) = reader.next_week() {
+            let (prices, earlier) = reader.read_week(year, week);
+            let (prices, week_report) = data::clean_prices(prices);
+            report.add(&week_report);
+            report.records += earlier.len() as u64;
+            report.out_of_order += earlier.len() as u64;
+            for price in earlier {
+                late.entry(calendar::trading_week(price.time))
+                    .or_default()
+                    .push(price);
+            }
+            let written = pipeline.archive_week(&instrument, &paths, (year, week), prices)?;
+            *weeks.entry((year, week)).or_default() |= written;
+        }
+        for (week, prices) in late {
+            let written = pipeline.archive_week(&instrument, &paths, week, prices)?;
+            *weeks.entry(week).or_default() |= written;
+        }
+
+        if !report.is_clean() {
+            log::warn!("{}: {:?}", instrument, report);
+        }
+        integrity.add(&report);
+        let written = weeks.values().filter(|written| **written).count();
+        weeks_written += written;
+        weeks_unchanged += weeks.len() - written;
+    }
+
+    pipeline.catalog.save(&pipeline.archive)?;
+
+    let summary = format!(
+        "{} records={} outOfOrder={} duplicates={} invalid={} weeksWritten={} weeksUnchanged={}",
+        chrono::Utc::now().to_rfc3339(),
+        integrity.records,
+        integrity.out_of_order,
+        integrity.duplicates,
+        integrity.invalid,
+        weeks_written,
+        weeks_unchanged
+    );
+    println!("{}", summary);
+    log::info!("{}", summary);
+
+    let mut log = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(pipeline.archive.join("pipeline.log"))?;
+    writeln!(log, "{}", summary)?;
+    Ok(())
+}
@@ -0,0 +1,41 @@
+use quantlib::accounts::aggregate;
+use quantlib::util::read_settings;
+
+use crate::common;
+
+const USAGE: &str = "accounts [--notify] [--json <path>]";
+
+// Combined equity, exposure by currency and a breakdown of every account in the settings'
+// "reporting" config, as the trader journals at midnight UTC. With --notify the report is also sent
+// to its notification sinks, and with --json it's saved.
+pub async fn run(args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+    let (notify, args) = common::take_flag(args, "--notify");
+    let json = match args.as_slice() {
+        [] => None,
+        [flag, path] if flag == "--json" => Some(path.clone()),
+        _ => common::usage(USAGE),
+    };
+
+    common::configure_logging("accounts")?;
+    let settings = read_settings()?;
+    let reporting = match &settings.reporting {
+        Some(reporting) => reporting,
+        None => {
+            eprintln!("No accounts to report on, add a \"reporting\" config to settings.json");
+            std::process::exit(1);
+        }
+    };
+
+    let report = aggregate(&settings.credentials.oanda, reporting).await?;
+    println!("{}", report.text());
+    if let Some(path) = json {
+        std::fs::write(&path, serde_json::to_string_pretty(&report)?)?;
+    }
+    if notify {
+        report.notify(&reporting.notify).await;
+    }
+    if !report.errors.is_empty() {
+        std::process::exit(2);
+    }
+    Ok(())
+}
@@ -0,0 +1,19 @@
+use quantlib::backtest::{BacktestConfig, REPORT_SCHEMA};
+use research::backtesting::run_scenarios;
+
+use crate::common;
+
+// Backtest a config, and each of its stress scenarios if given, saving the reports. Arguments
+// are those after "backtest".
+pub fn run(args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+    if args.len() == 1 && args[0] == "--schema" {
+        println!("{}", REPORT_SCHEMA);
+        return Ok(());
+    }
+    if args.len() < 2 || args.len() > 3 {
+        common::usage("backtest <backtest config> <output name> [scenarios] | --schema");
+    }
+
+    let config = BacktestConfig::load(&args[0])?;
+    run_scenarios(config, &args[1], args.get(2).map(String::as_str))
+}
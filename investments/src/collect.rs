@@ -0,0 +1,244 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use log::Level;
+use quantlib::alerts::Alerts;
+use quantlib::logging;
+use quantlib::oanda::objects::StreamItem;
+use quantlib::oanda::{self, LoggingPriceStream, ReconnectPolicy, StreamErrorClass};
+use quantlib::util::read_settings;
+
+use crate::common;
+
+fn get_instruments() -> Vec<String> {
+    vec![
+        "AUD_CAD".to_string(),
+        "AUD_CHF".to_string(),
+        "AUD_HKD".to_string(),
+        "AUD_JPY".to_string(),
+        "AUD_NZD".to_string(),
+        "AUD_SGD".to_string(),
+        "AUD_USD".to_string(),
+        "CAD_CHF".to_string(),
+        "CAD_HKD".to_string(),
+        "CAD_JPY".to_string(),
+        "CAD_SGD".to_string(),
+        "CHF_HKD".to_string(),
+        "CHF_JPY".to_string(),
+        "CHF_ZAR".to_string(),
+        "EUR_AUD".to_string(),
+        "EUR_CAD".to_string(),
+        "EUR_CHF".to_string(),
+        "EUR_CZK".to_string(),
+        "EUR_DKK".to_string(),
+        "EUR_GBP".to_string(),
+        "EUR_HKD".to_string(),
+        "EUR_HUF".to_string(),
+        "EUR_JPY".to_string(),
+        "EUR_NOK".to_string(),
+        "EUR_NZD".to_string(),
+        "EUR_PLN".to_string(),
+        "EUR_SEK".to_string(),
+        "EUR_SGD".to_string(),
+        "EUR_TRY".to_string(),
+        "EUR_USD".to_string(),
+        "EUR_ZAR".to_string(),
+        "GBP_AUD".to_string(),
+        "GBP_CAD".to_string(),
+        "GBP_CHF".to_string(),
+        "GBP_HKD".to_string(),
+        "GBP_JPY".to_string(),
+        "GBP_NZD".to_string(),
+        "GBP_PLN".to_string(),
+        "GBP_SGD".to_string(),
+        "GBP_USD".to_string(),
+        "GBP_ZAR".to_string(),
+        "HKD_JPY".to_string(),
+        "NZD_CAD".to_string(),
+        "NZD_CHF".to_string(),
+        "NZD_HKD".to_string(),
+        "NZD_JPY".to_string(),
+        "NZD_SGD".to_string(),
+        "NZD_USD".to_string(),
+        "SGD_CHF".to_string(),
+        "SGD_JPY".to_string(),
+        "TRY_JPY".to_string(),
+        "USD_CAD".to_string(),
+        "USD_CHF".to_string(),
+        "USD_CNH".to_string(),
+        "USD_CZK".to_string(),
+        "USD_DKK".to_string(),
+        "USD_HKD".to_string(),
+        "USD_HUF".to_string(),
+        "USD_JPY".to_string(),
+        "USD_MXN".to_string(),
+        "USD_NOK".to_string(),
+        "USD_PLN".to_string(),
+        "USD_SEK".to_string(),
+        "USD_SGD".to_string(),
+        "USD_THB".to_string(),
+        "USD_TRY".to_string(),
+        "USD_ZAR".to_string(),
+        "ZAR_JPY".to_string(),
+    ]
+}
+
+// Record OANDA's prices for every instrument until stopped, to the storage of the collector
+// config if one is given. Arguments are those after "collect".
+pub async fn run(args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+    if args.len() > 1 {
+        common::usage("collect [collector config]");
+    }
+
+    // Handle SIGINT
+    let running = Arc::new(AtomicBool::new(true));
+    let r = running.clone();
+
+    ctrlc::set_handler(move || {
+        r.store(false, Ordering::SeqCst);
+    })?;
+
+    common::configure_logging("data-collection")?;
+
+    // Storage layout and relay are configured by an optional config file, defaulting to data/
+    let config = common::collector_config(args.first().map(String::as_str))?;
+    log::info!("Saving data to {}...", config.storage.root.display());
+    // Only one collector can write to a data directory, others sharing it must be read only
+    let _claim = config.storage.claim("data-collection")?;
+    if let Some(api_usage) = &config.api_usage {
+        oanda::usage::configure(api_usage.clone());
+    }
+    logging::configure_ticks(config.tick_log.clone());
+
+    let mut alerts = config.alerts.clone().map(Alerts::new);
+    if alerts.as_ref().is_some_and(Alerts::watches_positions) {
+        log::warn!("The collector has no positions, ignoring position alerts");
+    }
+
+    // Read settings
+    let settings = read_settings().unwrap_or_else(|err| {
+        log::error!("Failed to read settings: {}", err);
+        std::process::exit(1);
+    });
+    if let Some(alerts) = &mut alerts {
+        alerts.add_sinks(&settings.notifications.sinks);
+    }
+
+    // Optionally share the stream of a stream-relay running on this host
+    if let Some(relay_address) = &config.relay_address {
+        log::info!("Receiving prices from relay at {}...", relay_address);
+    }
+
+    // Highest priority first, so trimming the stream drops the least important instruments
+    // Every instrument known of unless the collector config says otherwise. The settings' data
+    // instruments aren't used here, as a migrated trader's list would narrow what's recorded.
+    let instruments = if config.instruments.is_empty() {
+        get_instruments()
+    } else {
+        config.instruments.clone()
+    };
+    let instruments = oanda::prioritize(instruments, &config.instrument_priority);
+    // Lines of a stream whose format changed are kept alongside the data
+    let payload_directory = config.storage.root.clone();
+    log::info!(
+        "Starting logging price stream for {} instruments...",
+        instruments.len()
+    );
+    let mut logging_price_stream = LoggingPriceStream::with_layout(
+        instruments,
+        config.storage,
+        10_000, // 10 second timeout, we expect a heartbeat every 5 seconds
+        &settings.credentials.oanda,
+        config.relay_address.as_deref(),
+    )
+    .await?;
+    let mut policy = ReconnectPolicy::default();
+
+    while let Some(item) = logging_price_stream.next() {
+        log::trace!("Received item from stream...");
+        match item {
+            Ok(StreamItem::Price(price)) => {
+                logging::log_tick(Level::Info, "", &price);
+                if let Some(alerts) = &mut alerts {
+                    alerts.price(&price);
+                }
+            }
+            Ok(StreamItem::Backfill(price)) => {
+                logging::log_tick(Level::Info, " Backfilled", &price);
+            }
+            Ok(StreamItem::Dropped(dropped)) => {
+                log::warn!("Relay dropped prices: {:?}", dropped.dropped);
+            }
+            Ok(StreamItem::Heartbeat(_)) => {
+                log::debug!("Heartbeat received.");
+            }
+            Err(e) => {
+                let e = match StreamErrorClass::of(e.as_ref()).is_fatal() {
+                    true => Some(e),
+                    false => reconnect(&mut logging_price_stream, &mut policy, e)
+                        .await
+                        .err(),
+                };
+                if let Some(e) = e {
+                    let sinks = match &alerts {
+                        Some(alerts) => alerts.config().notify.clone(),
+                        None => settings.notifications.sinks.clone(),
+                    };
+                    oanda::report_fatal(e.as_ref(), "collector", &sinks, &payload_directory);
+                    logging_price_stream.flush()?;
+                    oanda::usage::finish();
+                    return Err(e);
+                }
+            }
+        }
+
+        if let Some(alerts) = &mut alerts {
+            let now = chrono::Utc::now().timestamp_millis() as u64;
+            if alerts.check_due(now) {
+                alerts.check_stale(now);
+            }
+        }
+
+        // Handle SIGINT elegantly
+        if !running.load(Ordering::SeqCst) {
+            log::info!("Received SIGINT, flushing buffers and exiting...");
+            logging_price_stream.flush()?;
+            oanda::usage::finish();
+            break;
+        }
+
+        log::trace!("Waiting for next item from stream...");
+    }
+
+    Ok(())
+}
+
+// Reconnect after a transient error, backing off for as long as reconnecting fails transiently,
+// returning the error if it turns fatal
+async fn reconnect(
+    stream: &mut LoggingPriceStream<'_>,
+    policy: &mut ReconnectPolicy,
+    error: Box<dyn std::error::Error>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let reason = oanda::reconnect_reason(error.as_ref());
+    log::error!("{} ({}), reconnecting...", error, reason);
+    stream.connection_log.record_reconnect(reason);
+    loop {
+        match stream.refresh_connection().await {
+            Ok(()) => {
+                policy.connected();
+                return Ok(());
+            }
+            Err(e) if !StreamErrorClass::of(e.as_ref()).is_fatal() => {
+                let delay = policy.delay();
+                log::error!(
+                    "Failed to reconnect: {}, retrying in {}s...",
+                    e,
+                    delay.as_secs()
+                );
+                tokio::time::sleep(delay).await;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
@@ -0,0 +1,39 @@
+use std::path::Path;
+
+use quantlib::catalog::Catalog;
+use quantlib::util::CollectorConfig;
+
+// Log to logs/<name>.log, the same file whichever binary a command is run from
+pub fn configure_logging(name: &str) -> Result<(), Box<dyn std::error::Error>> {
+    quantlib::logging::configure_logger(&format!("logs/{}.log", name))
+}
+
+// Print how a subcommand is used and exit, e.g. usage("trade <config> [--paper]")
+pub fn usage(command: &str) -> ! {
+    eprintln!("Usage: investments {}", command);
+    std::process::exit(1);
+}
+
+// The collector config at `path`, the defaults (data/ and OANDA directly) without one
+pub fn collector_config(path: Option<&str>) -> Result<CollectorConfig, Box<dyn std::error::Error>> {
+    match path {
+        Some(path) => CollectorConfig::load(path),
+        None => Ok(CollectorConfig::default()),
+    }
+}
+
+// The catalog of an archive, failing if there's none rather than treating it as empty
+pub fn catalog<P: AsRef<Path>>(archive: P) -> Result<Catalog, Box<dyn std::error::Error>> {
+    let archive = archive.as_ref();
+    if !archive.is_dir() {
+        return Err(format!("No archive at {}", archive.display()).into());
+    }
+    Catalog::load(archive)
+}
+
+// Whether a flag such as --dry-run was given, and the arguments without it
+pub fn take_flag(args: &[String], flag: &str) -> (bool, Vec<String>) {
+    let given = args.iter().any(|arg| arg == flag);
+    let rest = args.iter().filter(|arg| *arg != flag).cloned().collect();
+    (given, rest)
+}
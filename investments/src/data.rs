@@ -0,0 +1,250 @@
+use std::path::PathBuf;
+
+use quantlib::calendar;
+use quantlib::claims::DirectoryClaim;
+use quantlib::data::{self, BinReader, GapCause, TICK};
+use quantlib::export::{export_archive, ExportKey, ExportPolicy};
+use quantlib::retention::{Compaction, CompactionAction};
+use quantlib::upload;
+
+use crate::common;
+
+const USAGE: &str = "data <verify <archive> | clean <collector config> [archive] [--dry-run] | \
+                     export <archive> <output dir> [export policy] | \
+                     gaps <collector config> <instrument> [min seconds]>";
+
+// Tools for collected and archived data. Arguments are those after "data".
+pub fn run(args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+    match args.first().map(String::as_str) {
+        Some("verify") => verify(&args[1..]),
+        Some("clean") => clean(&args[1..]),
+        Some("export") => export(&args[1..]),
+        Some("gaps") => gaps(&args[1..]),
+        _ => common::usage(USAGE),
+    }
+}
+
+// Checks every week in an archive's catalog against its manifest: that the file is there, that
+// its checksum matches and that it has the ticks the catalog says. Exits with 2 if any week
+// fails, so it can be run from cron after the weekly pipeline.
+fn verify(args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+    if args.len() != 1 {
+        common::usage(USAGE);
+    }
+    let archive = PathBuf::from(&args[0]);
+    let _claim = DirectoryClaim::reader(&archive, "data verify")?;
+    let catalog = common::catalog(&archive)?;
+
+    let mut failed = 0;
+    let mut unverified = 0;
+    for entry in &catalog.entries {
+        let path = archive.join(&entry.path);
+        let problem = match &entry.manifest {
+            _ if !path.exists() => Some("missing".to_string()),
+            None => {
+                unverified += 1;
+                None
+            }
+            Some(manifest) => {
+                let checksum = upload::sha256_hex(&std::fs::read(&path)?);
+                if checksum != manifest.checksum {
+                    Some(format!("checksum {} != {}", checksum, manifest.checksum))
+                } else if manifest.ticks != entry.records {
+                    Some(format!(
+                        "{} ticks in the manifest, {} in the catalog",
+                        manifest.ticks, entry.records
+                    ))
+                } else {
+                    None
+                }
+            }
+        };
+        if let Some(problem) = problem {
+            failed += 1;
+            println!(
+                "{:<10}{}-W{:02}  {}: {}",
+                entry.instrument,
+                entry.year,
+                entry.week,
+                entry.path.display(),
+                problem
+            );
+        }
+    }
+
+    println!(
+        "{} weeks: {} failed, {} without a manifest",
+        catalog.entries.len(),
+        failed,
+        unverified
+    );
+    if failed > 0 {
+        std::process::exit(2);
+    }
+    Ok(())
+}
+
+// Enforces the config's "retention" on the collector's data directory, and on the archive if
+// one is given, deleting and compressing files and updating the archive's catalog. With
+// --dry-run nothing is changed, only what would be and the space it would free are printed.
+// Safe to run as often as needed, e.g. from cron:
+// 0 2 * * * investments data clean /etc/collector.json /data/archive
+fn clean(args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+    let (dry_run, args) = common::take_flag(args, "--dry-run");
+    if args.is_empty() || args.len() > 2 {
+        common::usage(USAGE);
+    }
+    let config = common::collector_config(Some(&args[0]))?;
+    let now = chrono::Utc::now().timestamp_millis() as u64;
+
+    // The collector keeps writing today's files alongside, which are never touched
+    let storage = config.storage.clone().read_only();
+    let _claim = storage.claim("compact_data")?;
+    let mut compactions = config.retention.compact_storage(&storage, now, dry_run)?;
+
+    if let Some(archive) = args.get(1) {
+        let archive = PathBuf::from(archive);
+        let _archive = DirectoryClaim::writer(&archive, "compact_data")?;
+        compactions.extend(config.retention.compact_archive(&archive, now, dry_run)?);
+    }
+
+    for compaction in &compactions {
+        println!(
+            "{:<10}{:>14}{:>14}  {}",
+            action_name(compaction),
+            compaction.size,
+            compaction.reclaimed,
+            compaction.path.display()
+        );
+    }
+
+    let count = |action| {
+        compactions
+            .iter()
+            .filter(|compaction| compaction.action == action)
+            .count()
+    };
+    let reclaimed: u64 = compactions
+        .iter()
+        .map(|compaction| compaction.reclaimed)
+        .sum();
+    println!(
+        "{} {} deleted, {} compressed, {:.1} MB {}",
+        if dry_run { "Dry run:" } else { "Done:" },
+        count(CompactionAction::Delete),
+        count(CompactionAction::Compress),
+        reclaimed as f64 / 1_000_000.0,
+        if dry_run { "reclaimable" } else { "reclaimed" }
+    );
+    Ok(())
+}
+
+// Copies an archive's weeks to a directory to share with others, one file per instrument, as
+// the export policy (see ExportPolicy) allows. Without a policy every instrument is anonymized.
+// The directory gets a manifest.json of what was done to each file, and the key to map aliases
+// and prices back is written beside it, to be kept rather than shared.
+fn export(args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+    if args.len() < 2 || args.len() > 3 {
+        common::usage(USAGE);
+    }
+    let archive = PathBuf::from(&args[0]);
+    let output = PathBuf::from(&args[1]);
+    let policy = match args.get(2) {
+        Some(path) => ExportPolicy::load(path)?,
+        None => ExportPolicy::default(),
+    };
+    let _claim = DirectoryClaim::reader(&archive, "data export")?;
+    let catalog = common::catalog(&archive)?;
+
+    let (manifest, _) = export_archive(&archive, &catalog, &output, &policy)?;
+    for file in &manifest.files {
+        let transforms: Vec<&str> = file
+            .transforms
+            .iter()
+            .map(|transform| transform.description())
+            .collect();
+        println!(
+            "{:<10}{:>12}  {}",
+            file.instrument,
+            file.ticks,
+            transforms.join(", ")
+        );
+    }
+    println!(
+        "Exported {} instruments to {}, {} withheld. Keep {} to yourself.",
+        manifest.files.len(),
+        output.display(),
+        manifest.withheld,
+        ExportKey::path(&output).display()
+    );
+    Ok(())
+}
+
+// Lists the gaps of at least min seconds (60 by default) between an instrument's collected ticks,
+// each with whether the market was silent or the connection was down as told by the heartbeats
+// recorded alongside, see data::explain_gap
+fn gaps(args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+    if args.len() < 2 || args.len() > 3 {
+        common::usage(USAGE);
+    }
+    let config = common::collector_config(Some(&args[0]))?;
+    let instrument = &args[1];
+    let min_gap = match args.get(2) {
+        Some(seconds) => seconds.parse::<u64>()? * 1000,
+        None => 60_000,
+    };
+    let storage = config.storage.clone().read_only();
+    let _claim = storage.claim("data gaps")?;
+
+    let paths = storage
+        .bin_files()?
+        .into_iter()
+        .filter(|(name, path)| {
+            name == instrument && storage.granularity(path).as_deref() == Some(TICK)
+        })
+        .map(|(_, path)| path)
+        .collect();
+    let heartbeats = storage.heartbeats()?;
+
+    let mut causes = Vec::new();
+    let mut previous: Option<u64> = None;
+    for price in BinReader::open_sequence(paths, instrument)? {
+        if let Some(start) = previous.filter(|start| price.time.saturating_sub(*start) >= min_gap) {
+            let cause = data::explain_gap(&heartbeats, start, price.time);
+            causes.push(cause);
+            println!(
+                "{}  {}  {:>8.1} min  {}",
+                calendar::utc(start).format("%Y-%m-%d %H:%M:%S"),
+                calendar::utc(price.time).format("%Y-%m-%d %H:%M:%S"),
+                (price.time - start) as f64 / 60_000.0,
+                cause_name(cause)
+            );
+        }
+        previous = Some(price.time);
+    }
+
+    let count = |cause| causes.iter().filter(|c| **c == cause).count();
+    println!(
+        "{} gaps: {} silent, {} disconnected, {} without heartbeats",
+        causes.len(),
+        count(GapCause::Silent),
+        count(GapCause::Disconnected),
+        count(GapCause::Unrecorded)
+    );
+    Ok(())
+}
+
+fn cause_name(cause: GapCause) -> &'static str {
+    match cause {
+        GapCause::Silent => "silent",
+        GapCause::Disconnected => "disconnected",
+        GapCause::Unrecorded => "unrecorded",
+    }
+}
+
+fn action_name(compaction: &Compaction) -> &'static str {
+    match compaction.action {
+        CompactionAction::Delete => "delete",
+        CompactionAction::Compress => "compress",
+    }
+}
@@ -0,0 +1,19 @@
+// The subcommands are behind features, so a binary that only needs some of them builds only
+// what those use: "collect" (collect, data), "trade" (trade, accounts) and "research" (backtest,
+// optimize, report). All are on by default; settings is always built.
+#[cfg(feature = "trade")]
+pub mod accounts;
+#[cfg(feature = "research")]
+pub mod backtest;
+#[cfg(feature = "collect")]
+pub mod collect;
+pub mod common;
+#[cfg(feature = "collect")]
+pub mod data;
+#[cfg(feature = "research")]
+pub mod optimize;
+#[cfg(feature = "research")]
+pub mod report;
+pub mod settings;
+#[cfg(feature = "trade")]
+pub mod trade;
@@ -0,0 +1,62 @@
+use investments::settings;
+#[cfg(feature = "trade")]
+use investments::{accounts, trade};
+#[cfg(feature = "research")]
+use investments::{backtest, optimize, report};
+#[cfg(feature = "collect")]
+use investments::{collect, data};
+
+const COMMANDS: &str = "Commands:
+  trade <config> [--paper | --replay <data dir>] [--standby]
+  collect [collector config]
+  backtest <backtest config> <output name> [scenarios] | --schema
+  optimize <backtest config> <output config> <modelConfig key>... [--iterations N]
+  data verify <archive>
+  data clean <collector config> [archive] [--dry-run]
+  data export <archive> <output dir> [export policy]
+  data gaps <collector config> <instrument> [min seconds]
+  report <backtest report>...
+  accounts [--notify] [--json <path>]
+  settings <check | migrate> [settings file]";
+
+// One binary for everything run day to day, so each command loads configs, sets up logging and
+// reads catalogs the same way
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let args: Vec<String> = std::env::args().collect();
+    let rest = args.get(2..).unwrap_or_default();
+    match args.get(1).map(String::as_str) {
+        #[cfg(feature = "trade")]
+        Some("trade") => trade::run(rest).await,
+        #[cfg(feature = "collect")]
+        Some("collect") => collect::run(rest).await,
+        #[cfg(feature = "research")]
+        Some("backtest") => backtest::run(rest),
+        #[cfg(feature = "research")]
+        Some("optimize") => optimize::run(rest),
+        #[cfg(feature = "collect")]
+        Some("data") => data::run(rest),
+        #[cfg(feature = "research")]
+        Some("report") => report::run(rest),
+        #[cfg(feature = "trade")]
+        Some("accounts") => accounts::run(rest).await,
+        Some("settings") => settings::run(rest),
+        // Only reached in a build without some of the features
+        #[allow(unreachable_patterns)]
+        Some(
+            command @ ("trade" | "collect" | "backtest" | "optimize" | "data" | "report"
+            | "accounts"),
+        ) => {
+            eprintln!(
+                "{} isn't built, see the features of the investments crate",
+                command
+            );
+            std::process::exit(1);
+        }
+        _ => {
+            eprintln!("Usage: {} <command> [arguments]", args[0]);
+            eprintln!("{}", COMMANDS);
+            std::process::exit(1);
+        }
+    }
+}
@@ -0,0 +1,113 @@
+use quantlib::backtest::BacktestConfig;
+use research::backtesting;
+use research::optimization;
+
+use crate::common;
+
+const USAGE: &str =
+    "optimize <backtest config> <output config> <modelConfig key>... [--iterations N]";
+
+// Tune up to four of the strategy's modelConfig parameters for the best Sharpe ratio over the
+// backtest, and write the config with the best of them to the output. The swarm moves each
+// parameter as a multiple of its starting value, so parameters of different sizes move alike,
+// and ones that start as whole numbers stay whole. Arguments are those after "optimize".
+pub fn run(args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+    let (iterations, args) = take_iterations(args)?;
+    if args.len() < 3 {
+        common::usage(USAGE);
+    }
+
+    let config = BacktestConfig::load(&args[0])?;
+    let keys = &args[2..];
+    let mut initial = Vec::with_capacity(keys.len());
+    for key in keys {
+        match config.strategy.model_config[key.as_str()].as_f64() {
+            Some(value) => initial.push(value),
+            None => return Err(format!("No number at modelConfig.{}", key).into()),
+        }
+    }
+
+    let parameters = Parameters {
+        config,
+        keys: keys.to_vec(),
+        initial,
+    };
+    let best = match keys.len() {
+        1 => parameters.optimize::<1>(iterations),
+        2 => parameters.optimize::<2>(iterations),
+        3 => parameters.optimize::<3>(iterations),
+        4 => parameters.optimize::<4>(iterations),
+        _ => return Err("At most four parameters can be optimized at once".into()),
+    };
+
+    std::fs::write(&args[1], serde_json::to_string_pretty(&best)?)?;
+    println!("Wrote the best config to {}", args[1]);
+    Ok(())
+}
+
+fn take_iterations(args: &[String]) -> Result<(usize, Vec<String>), Box<dyn std::error::Error>> {
+    match args.iter().position(|arg| arg == "--iterations") {
+        Some(index) => {
+            let iterations = match args.get(index + 1) {
+                Some(iterations) => iterations.parse()?,
+                None => common::usage(USAGE),
+            };
+            let mut rest = args.to_vec();
+            rest.drain(index..index + 2);
+            Ok((iterations, rest))
+        }
+        None => Ok((10, args.to_vec())),
+    }
+}
+
+struct Parameters {
+    config: BacktestConfig,
+    keys: Vec<String>,
+    initial: Vec<f64>,
+}
+
+impl Parameters {
+    fn optimize<const N: usize>(&self, iterations: usize) -> BacktestConfig {
+        let fitness = |multipliers: [f64; N]| self.fitness(&multipliers);
+        let best = optimization::optimize_iterations([1.0; N], fitness, 0.5, 0.1, iterations);
+
+        // How much worse the Sharpe ratio gets within ±10% of each parameter of the best
+        let report = optimization::sensitivity(best, fitness, 0.1, 5, 0.25);
+        let names: Vec<&str> = self.keys.iter().map(String::as_str).collect();
+        report.print(&names);
+
+        let config = self.config_at(&best);
+        for key in &self.keys {
+            println!("{} = {}", key, config.strategy.model_config[key.as_str()]);
+        }
+        config
+    }
+
+    // The backtest config with each parameter at its multiple of the starting value
+    fn config_at(&self, multipliers: &[f64]) -> BacktestConfig {
+        let mut config = self.config.clone();
+        for ((key, initial), multiplier) in self.keys.iter().zip(&self.initial).zip(multipliers) {
+            let value = initial * multiplier;
+            config.strategy.model_config[key.as_str()] = if initial.fract() == 0.0 {
+                serde_json::json!(value.round() as i64)
+            } else {
+                serde_json::json!(value)
+            };
+        }
+        config
+    }
+
+    // The negative Sharpe ratio, as the optimizer minimizes
+    fn fitness(&self, multipliers: &[f64]) -> f64 {
+        let mut config = self.config_at(multipliers);
+        config.save_checkpoint = None;
+        config.save_state = None;
+        match backtesting::run(config) {
+            Ok(report) => -report.metrics.sharpe_ratio.unwrap_or(0.0),
+            Err(e) => {
+                log::warn!("Backtest at {:?} failed: {}", multipliers, e);
+                f64::INFINITY
+            }
+        }
+    }
+}
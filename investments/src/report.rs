@@ -0,0 +1,21 @@
+use quantlib::backtest::BacktestReport;
+use research::backtesting::print_metrics;
+
+use crate::common;
+
+// Print the metrics of saved backtest reports (their .json), e.g. to compare runs side by side
+pub fn run(args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+    if args.is_empty() {
+        common::usage("report <backtest report>...");
+    }
+
+    for (index, path) in args.iter().enumerate() {
+        if index > 0 {
+            println!();
+        }
+        let report = BacktestReport::load_json(path)?;
+        println!("{}", path);
+        print_metrics(&report);
+    }
+    Ok(())
+}
@@ -0,0 +1,63 @@
+use quantlib::util::{migrate_settings, Settings};
+
+use crate::common;
+
+const USAGE: &str = "settings <check | migrate> [settings file]";
+
+// Tools for settings.json, see quantlib::util::Settings. Arguments are those after "settings".
+pub fn run(args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+    let path = match args.len() {
+        1 => "settings.json",
+        2 => args[1].as_str(),
+        _ => common::usage(USAGE),
+    };
+    match args[0].as_str() {
+        "check" => check(path),
+        "migrate" => migrate(path),
+        _ => common::usage(USAGE),
+    }
+}
+
+// Reports everything wrong with the settings at once, without decrypting any secrets
+fn check(path: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let settings = Settings::load(path)?;
+    println!(
+        "{} is valid: account {}, {} data instruments, {} units",
+        path,
+        settings.credentials.oanda.account_id,
+        settings.data.instruments.len(),
+        settings.trading.units
+    );
+    Ok(())
+}
+
+// Rewrites settings in the flat format of before as sections, keeping the original as .bak.
+// Encrypted secrets are moved as they are.
+fn migrate(path: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let flat: serde_json::Value = serde_json::from_str(&std::fs::read_to_string(path)?)?;
+    let sectioned = match migrate_settings(&flat) {
+        Some(sectioned) => sectioned,
+        None => {
+            println!("{} is already in sections", path);
+            return Ok(());
+        }
+    };
+
+    let backup = format!("{}.bak", path);
+    std::fs::copy(path, &backup)?;
+    // Replaced in one go, so the settings are never left half written
+    let temporary = format!("{}.tmp", path);
+    std::fs::write(&temporary, serde_json::to_string_pretty(&sectioned)?)?;
+    std::fs::rename(&temporary, path)?;
+    println!(
+        "Converted {} to sections, the original is in {}",
+        path, backup
+    );
+
+    // Anything the old format let through is only found now
+    if let Err(e) = Settings::load(path) {
+        eprintln!("{}", e);
+        std::process::exit(2);
+    }
+    Ok(())
+}
@@ -0,0 +1,330 @@
+use quantlib::broker::{BinanceBroker, Broker, BrokerConfig, FixBroker, OandaBroker};
+use quantlib::data::{MergedReader, StorageLayout};
+use quantlib::engine::{
+    self, BrokerExecution, Execution, PaperExecution, PriceSource, Shadow, TradingEngine,
+};
+use quantlib::logging;
+use quantlib::models::{OrderSizer, PortfolioBuilder, TrailingStopManager};
+use quantlib::oanda::objects::OandaSettings;
+use quantlib::oanda::{self, FastPriceStream, OrderClient, ShardedPriceStream, TransactionStream};
+use quantlib::util::{read_settings, Settings, TradingConfig};
+use std::error::Error;
+use std::time::Duration;
+
+use crate::common;
+
+// How often an idle connection to the orders endpoint is used, to stop it being closed before
+// the next order
+const ORDER_CONNECTION_KEEPALIVE: Duration = Duration::from_secs(30);
+
+// Trade the strategies of a trading config until stopped: live, on a paper account or over
+// recorded prices. Arguments are those after "trade".
+pub async fn run(args: &[String]) -> Result<(), Box<dyn Error>> {
+    if args.is_empty() {
+        common::usage("trade <config> [--paper | --replay <data dir>] [--standby]");
+    }
+    let paper = args.iter().any(|arg| arg == "--paper");
+    // Run everything but execution until "activate" is sent to the control socket
+    let standby = args.iter().any(|arg| arg == "--standby");
+    let replay = args
+        .iter()
+        .position(|arg| arg == "--replay")
+        .and_then(|index| args.get(index + 1));
+
+    common::configure_logging("trading")?;
+    let settings = read_settings()?;
+    let mut config = TradingConfig::load(&args[0])?;
+    if standby && config.control_socket.is_none() {
+        eprintln!("--standby needs a controlSocket in the config to be activated through");
+        std::process::exit(1);
+    }
+    // Paper fills must not reach the live journal, nor a replay overwrite the live checkpoint.
+    // A standby keeps its own too, starting from the live trader's checkpoint until it has one.
+    let live_checkpoint = config.checkpoint.clone().filter(|_| standby);
+    let mode = match (replay, paper, standby) {
+        (Some(_), _, _) => Some("replay"),
+        (None, true, _) => Some("paper"),
+        (None, false, true) => Some("standby"),
+        (None, false, false) => None,
+    };
+    if let Some(mode) = mode {
+        config.separate_state(mode);
+        log::info!("Journaling to {}", config.journal.display());
+    }
+    if let Some(control_socket) = config.control_socket.as_ref().filter(|_| standby) {
+        log::info!(
+            "Send \"activate\" to {} to take over",
+            control_socket.display()
+        );
+    }
+    // Highest priority first, so trimming a stream drops the least important instruments
+    let instruments = oanda::prioritize(config.instruments.clone(), &config.instrument_priority);
+    if let Some(api_usage) = &config.api_usage {
+        oanda::usage::configure(api_usage.clone());
+    }
+    logging::configure_ticks(config.tick_log.clone());
+
+    // Trade through a broker backend if one is configured, otherwise through OANDA's portfolio
+    // builder below
+    let live = !paper && replay.is_none();
+    let broker = match &config.broker {
+        Some(broker) => Some(connect_broker(
+            broker,
+            &settings,
+            &config,
+            &instruments,
+            live,
+        )?),
+        None => None,
+    };
+
+    // Where a "reload" sends the new instruments, only OANDA streams opened here can take them
+    let mut subscriptions = None;
+    let prices: PriceSource = match (replay, &broker, &config.relay_address) {
+        // Recorded prices, as fast as they can be read
+        (Some(data), _, _) => {
+            engine::replay(MergedReader::open(&StorageLayout::new(data).read_only())?)
+        }
+        // The backend's own stream, as the relay only carries OANDA's prices
+        (None, Some(broker), _) => broker.price_stream(&instruments)?,
+        // Share a stream relay's connection if one is configured, otherwise connect to OANDA directly
+        (None, None, Some(relay_address)) => Box::new(
+            FastPriceStream::with_relay(
+                instruments.clone(),
+                &settings.credentials.oanda,
+                relay_address,
+                10_000,
+            )
+            .await?,
+        ),
+        (None, None, None) => {
+            let stream = ShardedPriceStream::new(
+                instruments.clone(),
+                &settings.credentials.oanda,
+                config.instruments_per_connection,
+                10_000, // 10 second timeout, we expect a heartbeat every 5 seconds
+            );
+            subscriptions = Some(stream.subscriptions());
+            Box::new(stream)
+        }
+    };
+
+    let execution = if paper || replay.is_some() {
+        let mut paper = PaperExecution::from_config(&config, settings.trading.units)
+            .with_max_units(settings.risk.max_units);
+        if let Some(distance) = &config.trailing_stop {
+            paper = paper.with_trailing_stops(TrailingStopManager::new(distance.clone()));
+        }
+        Execution::Paper(Box::new(paper))
+    } else if let Some(broker) = &broker {
+        log::info!("Trading through {}", broker.name());
+        if config.trailing_stop.is_some() {
+            log::warn!("Trailing stops aren't supported through a broker backend, ignoring them");
+        }
+        if config.passive_execution.is_some() {
+            log::warn!("Passive execution isn't supported through a broker backend, ignoring it");
+        }
+        if config
+            .cost_guard
+            .as_ref()
+            .is_some_and(|guard| guard.estimate)
+        {
+            return Err(
+                "The cost guard can only estimate costs from OANDA's book, set \"estimate\" to \
+                 false to guard by the spread through a broker backend"
+                    .into(),
+            );
+        }
+        let instrument_limits = broker.instruments(&instruments).await?;
+        let mut execution = BrokerExecution::new(broker.as_ref(), settings.trading.units)
+            .with_order_sizer(OrderSizer::new(instrument_limits, config.unit_rounding))
+            .with_margin(config.margin.clone())
+            .with_cost_guard(config.cost_guard.clone())
+            .with_position_sizing(&config.position_sizing)
+            .with_volatility_target(config.volatility_target.clone())
+            .with_forecast_mapping(&config.forecast_mapping)
+            .with_max_units(settings.risk.max_units)
+            .with_target_tolerance(&config.target_tolerance)
+            .with_target_smoothing(&config.target_smoothing);
+        execution.update_positions().await?;
+        Execution::Broker(Box::new(execution))
+    } else {
+        let transactions = TransactionStream::new(&settings.credentials.oanda, 10_000).await?;
+        let instrument_limits =
+            oanda::get_instruments(&instruments, &settings.credentials.oanda).await?;
+        let mut portfolio = PortfolioBuilder::new(&settings)
+            .with_reconcile_interval(Duration::from_secs(config.reconcile_interval))
+            .with_order_sizer(OrderSizer::new(instrument_limits, config.unit_rounding))
+            .with_position_sizing(&config.position_sizing)
+            .with_volatility_target(config.volatility_target.clone())
+            .with_forecast_mapping(&config.forecast_mapping)
+            .with_margin(config.margin.clone())
+            .with_cost_guard(config.cost_guard.clone())
+            .with_target_tolerance(&config.target_tolerance)
+            .with_target_smoothing(&config.target_smoothing)
+            .with_passive_execution(config.passive_execution.clone())
+            .with_order_client(order_client(&settings.credentials.oanda, &instruments)?);
+        if let Some(distance) = &config.trailing_stop {
+            portfolio = portfolio.with_trailing_stop(distance.clone());
+        }
+        portfolio.update_account_mode().await?;
+        portfolio.update_positions().await?; // TODO: this should be done automatically by the portfolio builder
+        Execution::Live {
+            portfolio: Box::new(portfolio),
+            transactions: Box::new(transactions),
+        }
+    };
+
+    // Candidate strategies compared with the configured ones on paper while they trade
+    let shadow = match &config.shadow {
+        Some(shadow) => {
+            log::info!(
+                "Running the strategies of {} in shadow",
+                shadow.candidate.display()
+            );
+            Some(Shadow::new(shadow, &config, settings.trading.units)?)
+        }
+        None => None,
+    };
+
+    let bootstrap = config.bootstrap.clone();
+    let mut engine = TradingEngine::new(config, prices, execution)?
+        .with_config_file(&args[0], subscriptions)
+        .with_notifications(&settings.notifications.sinks);
+    if let Some(shadow) = shadow {
+        engine = engine.with_shadow(shadow);
+    }
+    // Only the trader placing orders reports, so the accounts are reported on once a day
+    if let Some(reporting) = settings.reporting.as_ref().filter(|_| live && !standby) {
+        engine = engine.with_reporting(&settings.credentials.oanda, reporting);
+    }
+    if standby {
+        log::info!("Starting on standby");
+        engine = engine.with_standby();
+    }
+    engine.cancel_protective_stops().await?;
+
+    // A replay always starts from scratch, as a checkpoint is of the strategies at a later time
+    let restored = replay.is_none()
+        && (engine.restore_checkpoint()?
+            || match &live_checkpoint {
+                Some(path) => engine.restore_checkpoint_from(path)?,
+                None => false,
+            });
+    if replay.is_none() && !restored {
+        // A cold start, warmed up on recent candles before the first streamed price
+        if let Some(bootstrap) = &bootstrap {
+            let mut candles = Vec::new();
+            if broker.is_some() {
+                log::warn!(
+                    "No candles to warm up on through a broker backend, warming up on live prices"
+                );
+            } else {
+                let now = chrono::Utc::now().timestamp_millis() as u64;
+                for instrument in &instruments {
+                    match oanda::recent_prices(
+                        instrument,
+                        &bootstrap.granularity,
+                        bootstrap.candles,
+                        now,
+                        &settings.credentials.oanda,
+                    )
+                    .await
+                    {
+                        Ok(prices) => {
+                            log::info!("[{}] Warming up on {} candles", instrument, prices.len());
+                            candles.extend(prices);
+                        }
+                        Err(e) => log::warn!(
+                            "[{}] Failed to fetch candles, warming up on live prices: {}",
+                            instrument,
+                            e
+                        ),
+                    }
+                }
+            }
+            candles.sort_by_key(|price| price.time);
+            engine.bootstrap(&candles)?;
+        }
+        // Start from current prices rather than from empty state on the first streamed tick
+        let snapshot = match &broker {
+            Some(broker) => broker.latest_prices(&instruments).await?,
+            None => oanda::get_latest_prices(&instruments, &settings.credentials.oanda).await?,
+        };
+        log::info!("Warming up strategies from {} prices", snapshot.len());
+        engine.warm_up(&snapshot)?;
+    }
+
+    // Stop cleanly on SIGINT or SIGTERM, dealing with positions as configured
+    let handle = engine.handle();
+    ctrlc::set_handler(move || handle.shutdown())?;
+
+    let report = engine.run().await?;
+    log::info!(
+        "Shut down with {} open positions, {} closed and {} protected",
+        report.open_positions.len(),
+        report.fills.len(),
+        report.protective_stops.len()
+    );
+    if let Execution::Paper(paper) = engine.execution() {
+        log::info!(
+            "Paper account: balance {:.2}, NAV {:.2}",
+            paper.account().balance,
+            paper.account().nav()
+        );
+    }
+    oanda::usage::finish();
+    Ok(())
+}
+
+// An order client for the instruments with its connection to OANDA opened and kept open. Must be
+// called within the runtime.
+fn order_client(
+    settings: &OandaSettings,
+    instruments: &[String],
+) -> Result<OrderClient, Box<dyn Error>> {
+    let order_client = OrderClient::new(settings)?.with_templates(instruments);
+    order_client.keep_warm(ORDER_CONNECTION_KEEPALIVE);
+    Ok(order_client)
+}
+
+// Orders are only placed through the broker if `live`, otherwise it's only used for prices
+fn connect_broker(
+    broker: &BrokerConfig,
+    settings: &Settings,
+    config: &TradingConfig,
+    instruments: &[String],
+    live: bool,
+) -> Result<Box<dyn Broker>, Box<dyn Error>> {
+    Ok(match broker {
+        BrokerConfig::Oanda => {
+            let mut oanda = OandaBroker::new(&settings.credentials.oanda)
+                .with_instruments_per_connection(config.instruments_per_connection);
+            if live {
+                oanda = oanda
+                    .with_order_client(order_client(&settings.credentials.oanda, instruments)?);
+            }
+            Box::new(oanda)
+        }
+        BrokerConfig::Binance { testnet } => {
+            let binance =
+                settings.credentials.binance.as_ref().ok_or(
+                    "Trading through Binance needs \"binance\" credentials in settings.json",
+                )?;
+            let mut broker = BinanceBroker::new(binance, instruments);
+            if *testnet {
+                broker = broker.with_testnet();
+            }
+            Box::new(broker)
+        }
+        BrokerConfig::Fix { data } => {
+            let fix = settings
+                .credentials
+                .fix
+                .as_ref()
+                .ok_or("Routing orders over FIX needs \"fix\" credentials in settings.json")?;
+            let data = connect_broker(data, settings, config, instruments, false)?;
+            Box::new(FixBroker::new(fix, data))
+        }
+    })
+}
@@ -0,0 +1,355 @@
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+use criterion::{black_box, BatchSize, Criterion, Throughput};
+use serde::{Deserialize, Serialize};
+
+use quantlib::backtest::{RegimeConfig, RegimeLabeler};
+use quantlib::broker::OrderTags;
+use quantlib::data::{self, TimePrecision, RECORD_SIZE};
+use quantlib::models::{
+    AlphaModels, ExponentialMovingAverage, RandomStrategy, TickMomentum, WeightedConsensus,
+};
+use quantlib::oanda::objects::{OandaSettings, PositionFill, Price, PriceStatus, StreamItem};
+use quantlib::oanda::{OrderClient, StreamParser};
+
+// Microbenchmarks of what runs for every tick: parsing the stream, decoding tick files, building
+// bars and each alpha model's tick(), and of preparing an order. Run with `cargo bench -p quantlib`, after which each result
+// is compared to benches/baseline.json and those more than REGRESSION slower are flagged.
+// Timings depend on the machine, so save a baseline of your own before making changes with
+// `BENCH_SAVE_BASELINE=1 cargo bench -p quantlib`.
+
+const TICKS: usize = 1_000;
+const INSTRUMENTS: [&str; 3] = ["EUR_USD", "GBP_USD", "USD_JPY"];
+
+// Slowdown relative to the baseline that counts as a regression
+const REGRESSION: f64 = 0.10;
+
+// Prices wandering around 1.1 for each instrument in turn, a second apart
+fn prices() -> Vec<Price> {
+    (0..TICKS)
+        .map(|i| {
+            let mid = 1.1 + 0.001 * (i as f64 / 25.0).sin() + 0.0002 * (i as f64 / 3.0).cos();
+            Price {
+                bid: (mid - 0.00005) as f32,
+                ask: (mid + 0.00005) as f32,
+                time: 1_704_193_200_000 + i as u64 * 1_000,
+                nanos: 0,
+                instrument: INSTRUMENTS[i % INSTRUMENTS.len()].to_string(),
+                tradeable: true,
+                status: PriceStatus::Tradeable,
+            }
+        })
+        .collect()
+}
+
+// The prices as OANDA streams them, in a single chunk
+fn stream_chunk(prices: &[Price]) -> Vec<u8> {
+    prices
+        .iter()
+        .map(|price| {
+            let time = chrono::DateTime::from_timestamp_millis(price.time as i64)
+                .unwrap()
+                .format("%Y-%m-%dT%H:%M:%S%.9fZ");
+            format!(
+                "{{\"type\":\"PRICE\",\"time\":\"{}\",\"bids\":[{{\"price\":\"{:.5}\",\"liquidity\":1000000}}],\"asks\":[{{\"price\":\"{:.5}\",\"liquidity\":1000000}}],\"closeoutBid\":\"{:.5}\",\"closeoutAsk\":\"{:.5}\",\"status\":\"tradeable\",\"tradeable\":true,\"instrument\":\"{}\"}}\n",
+                time, price.bid, price.ask, price.bid, price.ask, price.instrument
+            )
+        })
+        .collect::<String>()
+        .into_bytes()
+}
+
+fn parse(c: &mut Criterion) {
+    let prices = prices();
+    let chunk = stream_chunk(&prices);
+    let records: Vec<[u8; RECORD_SIZE]> = prices
+        .iter()
+        .map(|price| data::encode_price(price, TimePrecision::Nanoseconds))
+        .collect();
+
+    let mut group = c.benchmark_group("parse");
+    group.throughput(Throughput::Elements(TICKS as u64));
+    group.bench_function("stream_chunk", |b| {
+        let mut parser = StreamParser::<StreamItem>::new();
+        b.iter(|| parser.parse(black_box(&chunk)).unwrap())
+    });
+    group.bench_function("tick_records", |b| {
+        b.iter(|| {
+            for record in &records {
+                black_box(data::decode_price(
+                    black_box(record),
+                    "EUR_USD",
+                    TimePrecision::Nanoseconds,
+                ));
+            }
+        })
+    });
+    group.finish();
+}
+
+fn bars(c: &mut Criterion) {
+    let prices = prices();
+    let config = RegimeConfig {
+        bar_seconds: 60,
+        ..RegimeConfig::default()
+    };
+
+    let mut group = c.benchmark_group("bars");
+    group.throughput(Throughput::Elements(TICKS as u64));
+    group.bench_function("regime_labeler", |b| {
+        b.iter_batched(
+            || RegimeLabeler::new(config.clone()),
+            |mut labeler| {
+                for price in &prices {
+                    labeler.tick(black_box(price));
+                }
+                labeler
+            },
+            BatchSize::SmallInput,
+        )
+    });
+    group.finish();
+}
+
+type ModelFactory = fn() -> AlphaModels;
+
+fn ema() -> AlphaModels {
+    AlphaModels::ExponentialMovingAverage(ExponentialMovingAverage::new(0.01, 0.1))
+}
+
+fn tick_momentum() -> AlphaModels {
+    AlphaModels::TickMomentum(TickMomentum::new(50, 0.3, 1.0))
+}
+
+fn random() -> AlphaModels {
+    AlphaModels::Random(RandomStrategy {
+        buy_threshold: 0.1,
+        sell_threshold: 0.9,
+        rng: rand::thread_rng(),
+    })
+}
+
+fn models(c: &mut Criterion) {
+    let prices = prices();
+    let mut group = c.benchmark_group("models");
+    group.throughput(Throughput::Elements(TICKS as u64));
+
+    let models: [(&str, ModelFactory); 3] = [
+        ("ema", ema),
+        ("tick_momentum", tick_momentum),
+        ("random", random),
+    ];
+    for (name, model) in models {
+        group.bench_function(name, |b| {
+            let mut model = model();
+            b.iter(|| {
+                for price in &prices {
+                    black_box(quantlib::models::AlphaModel::tick(&mut model, price).unwrap());
+                }
+            })
+        });
+    }
+
+    group.bench_function("weighted_consensus", |b| {
+        let mut consensus = WeightedConsensus::new()
+            .add_model(ema(), 1.0)
+            .add_model(tick_momentum(), 1.0);
+        b.iter(|| {
+            for price in &prices {
+                black_box(consensus.tick(price).unwrap());
+            }
+        })
+    });
+    group.finish();
+}
+
+// The body of an order as it was built before order templates, in full for every order
+fn json_body(instrument: &str, units: f64, client_order_id: &str, tags: &OrderTags) -> String {
+    serde_json::json!({
+        "order": {
+            "units": units.to_string(),
+            "instrument": instrument,
+            "timeInForce": "FOK",
+            "type": "MARKET",
+            "positionFill": PositionFill::Default.as_str(),
+            "clientExtensions": {
+                "id": client_order_id,
+                "tag": tags.strategy,
+                "comment": format!("signal {} config {}", tags.signal, tags.config_version),
+            },
+            "tradeClientExtensions": {
+                "tag": tags.strategy,
+                "comment": format!("signal {} config {}", tags.signal, tags.config_version),
+            },
+        }
+    })
+    .to_string()
+}
+
+// What it costs to get an order ready to send: building its body in full or from the
+// instrument's template, and making a client for each order, which OrderClient saves. A new
+// client also has to open a connection, DNS, TCP and TLS, which costs far more again but needs
+// OANDA to measure.
+fn orders(c: &mut Criterion) {
+    let settings = OandaSettings {
+        account_id: "101-004-1234567-001".to_string(),
+        authorization: "token".to_string(),
+    };
+    let instruments: Vec<String> = INSTRUMENTS.iter().map(|i| i.to_string()).collect();
+    let order_client = OrderClient::new(&settings)
+        .unwrap()
+        .with_templates(&instruments);
+    let tags = OrderTags {
+        strategy: "ema".to_string(),
+        signal: "EUR_USD-1704193200000".to_string(),
+        config_version: "3f2a9c1b04de".to_string(),
+    };
+    let id = "ql-1704193200000-0badf00d";
+
+    let mut group = c.benchmark_group("orders");
+    group.bench_function("json_body", |b| {
+        b.iter(|| json_body(black_box("EUR_USD"), black_box(1000.0), id, &tags))
+    });
+    group.bench_function("template_body", |b| {
+        b.iter(|| {
+            order_client.body(
+                black_box("EUR_USD"),
+                black_box(1000.0),
+                PositionFill::Default,
+                id,
+                &tags,
+            )
+        })
+    });
+    group.bench_function("client_new", |b| b.iter(reqwest::Client::new));
+    group.finish();
+}
+
+// Median time per iteration of each benchmark, in nanoseconds
+#[derive(Serialize, Deserialize, Debug, Default)]
+struct Baseline {
+    benchmarks: BTreeMap<String, f64>,
+}
+
+#[derive(Deserialize)]
+struct Estimates {
+    median: Estimate,
+}
+
+#[derive(Deserialize)]
+struct Estimate {
+    point_estimate: f64,
+}
+
+// Where criterion keeps its results: CRITERION_HOME, or criterion in the target directory, which
+// is the working directory's target if criterion couldn't ask cargo where it is
+fn criterion_dir() -> PathBuf {
+    if let Some(home) = std::env::var_os("CRITERION_HOME") {
+        return PathBuf::from(home);
+    }
+    let workspace_target = match std::env::var_os("CARGO_TARGET_DIR") {
+        Some(target) => PathBuf::from(target),
+        None => PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("../target"),
+    };
+    [workspace_target, PathBuf::from("target")]
+        .into_iter()
+        .map(|target| target.join("criterion"))
+        .find(|dir| dir.exists())
+        .unwrap_or_default()
+}
+
+// Results of the benchmarks that have run, by name, e.g. "models/ema"
+fn current_results() -> Baseline {
+    let mut baseline = Baseline::default();
+    let root = criterion_dir();
+    let groups = std::fs::read_dir(&root).into_iter().flatten().flatten();
+    for group in groups {
+        let benchmarks = std::fs::read_dir(group.path())
+            .into_iter()
+            .flatten()
+            .flatten();
+        for benchmark in benchmarks {
+            let path = benchmark.path().join("new").join("estimates.json");
+            let estimates: Estimates = match std::fs::read_to_string(&path)
+                .ok()
+                .and_then(|estimates| serde_json::from_str(&estimates).ok())
+            {
+                Some(estimates) => estimates,
+                None => continue,
+            };
+            let name = format!(
+                "{}/{}",
+                group.file_name().to_string_lossy(),
+                benchmark.file_name().to_string_lossy()
+            );
+            baseline
+                .benchmarks
+                .insert(name, estimates.median.point_estimate.round());
+        }
+    }
+    baseline
+}
+
+fn compare_with_baseline() -> Result<(), Box<dyn std::error::Error>> {
+    let path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("benches/baseline.json");
+    let current = current_results();
+    if std::env::var_os("BENCH_SAVE_BASELINE").is_some() {
+        std::fs::write(&path, serde_json::to_string_pretty(&current)? + "\n")?;
+        println!(
+            "Saved {} results to {}",
+            current.benchmarks.len(),
+            path.display()
+        );
+        return Ok(());
+    }
+    if !path.exists() {
+        return Ok(());
+    }
+
+    let baseline: Baseline = serde_json::from_str(&std::fs::read_to_string(&path)?)?;
+    println!();
+    println!(
+        "{:<28}{:>14}{:>14}{:>10}",
+        "benchmark", "baseline ns", "current ns", "change"
+    );
+    let mut regressions = 0;
+    for (name, current) in &current.benchmarks {
+        let baseline = match baseline.benchmarks.get(name) {
+            Some(baseline) => *baseline,
+            None => continue,
+        };
+        let change = current / baseline - 1.0;
+        let regressed = change > REGRESSION;
+        if regressed {
+            regressions += 1;
+        }
+        println!(
+            "{:<28}{:>14.0}{:>14.0}{:>9.1}%{}",
+            name,
+            baseline,
+            current,
+            change * 100.0,
+            if regressed { "  REGRESSED" } else { "" }
+        );
+    }
+    println!(
+        "{} regressed by more than {:.0}%",
+        regressions,
+        REGRESSION * 100.0
+    );
+    Ok(())
+}
+
+fn main() {
+    let mut criterion = Criterion::default().configure_from_args();
+    parse(&mut criterion);
+    bars(&mut criterion);
+    models(&mut criterion);
+    orders(&mut criterion);
+    criterion.final_summary();
+
+    if let Err(e) = compare_with_baseline() {
+        eprintln!("Failed to compare with the baseline: {}", e);
+    }
+}
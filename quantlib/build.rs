@@ -0,0 +1,57 @@
+fn main() {
+    #[cfg(feature = "grpc")]
+    engine_service();
+}
+
+// Stubs of the gRPC service in proto/engine.proto, built without protoc from the messages in
+// src/grpc.rs
+#[cfg(feature = "grpc")]
+fn engine_service() {
+    use tonic_build::manual::{Builder, Method, Service};
+
+    let method = |name: &str, route: &str, input: &str, output: &str| {
+        Method::builder()
+            .name(name)
+            .route_name(route)
+            .input_type(format!("crate::grpc::{}", input))
+            .output_type(format!("crate::grpc::{}", output))
+            .codec_path("tonic::codec::ProstCodec")
+            .build()
+    };
+    let service = Service::builder()
+        .name("Engine")
+        .package("quantlib")
+        .method(method(
+            "get_status",
+            "GetStatus",
+            "StatusRequest",
+            "StatusReply",
+        ))
+        .method(method(
+            "get_positions",
+            "GetPositions",
+            "PositionsRequest",
+            "PositionsReply",
+        ))
+        .method(method(
+            "recent_decisions",
+            "RecentDecisions",
+            "DecisionsRequest",
+            "DecisionsReply",
+        ))
+        .method(method(
+            "send_command",
+            "SendCommand",
+            "CommandRequest",
+            "CommandReply",
+        ))
+        .method(method(
+            "submit_backtest",
+            "SubmitBacktest",
+            "BacktestRequest",
+            "BacktestReply",
+        ))
+        .build();
+    Builder::new().compile(&[service]);
+    println!("cargo:rerun-if-changed=proto/engine.proto");
+}
@@ -0,0 +1,275 @@
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::fx::{split_instrument, Converter};
+use crate::notify::{deliver, Notification, NotificationSink};
+use crate::oanda;
+use crate::oanda::objects::{AccountSummary, OandaSettings, Position};
+
+// Accounts reported on together, "reporting" in settings.json, e.g.
+// {"currency": "GBP", "accounts": [{"name": "carry", "account_id": "...", "authorization": "..."}],
+// "conversions": ["GBP_USD", "EUR_GBP"], "notify": [{"type": "webhook", "url": "..."}]}
+// The account in the credentials is always included, as "main". Amounts are converted into
+// `currency` at the mid prices of the positions' instruments and of `conversions`, which should
+// connect each account's currency to it. A live trader journals the report at midnight UTC and
+// sends it to `notify`. Run "investments accounts" for the report at any other time.
+#[derive(Deserialize, Debug, Clone)]
+pub struct ReportingSettings {
+    pub currency: String,
+
+    #[serde(default)]
+    pub accounts: Vec<AccountSettings>,
+
+    #[serde(default)]
+    pub conversions: Vec<String>,
+
+    #[serde(default)]
+    pub notify: Vec<NotificationSink>,
+}
+
+// Another OANDA account, its token in plain text or age encrypted like the main one's
+#[derive(Deserialize, Debug, Clone)]
+pub struct AccountSettings {
+    pub name: String,
+
+    #[serde(flatten)]
+    pub oanda: OandaSettings,
+}
+
+impl ReportingSettings {
+    // Every account reported on, the main one first
+    pub fn all_accounts(&self, main: &OandaSettings) -> Vec<AccountSettings> {
+        let mut accounts = vec![AccountSettings {
+            name: "main".to_string(),
+            oanda: main.clone(),
+        }];
+        accounts.extend(
+            self.accounts
+                .iter()
+                .filter(|account| account.oanda.account_id != main.account_id)
+                .cloned(),
+        );
+        accounts
+    }
+}
+
+// One account's figures in its own currency, and its NAV in the reporting currency (None
+// without a rate to convert it at)
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct AccountBreakdown {
+    pub name: String,
+    pub currency: String,
+    pub balance: f64,
+    pub nav: f64,
+
+    #[serde(rename = "unrealizedPl")]
+    pub unrealized_pl: f64,
+
+    #[serde(rename = "marginUsed")]
+    pub margin_used: f64,
+
+    #[serde(rename = "openPositions")]
+    pub open_positions: u32,
+
+    pub equity: Option<f64>,
+}
+
+// Net amount of a currency held across every account's positions, long the base currency and
+// short the quote currency of a long position, and its value in the reporting currency
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CurrencyExposure {
+    pub currency: String,
+    pub amount: f64,
+    pub value: Option<f64>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct AggregateReport {
+    pub time: String,
+    pub currency: String,
+
+    // Totals of the accounts that could be converted into the reporting currency
+    pub equity: f64,
+
+    #[serde(rename = "unrealizedPl")]
+    pub unrealized_pl: f64,
+
+    #[serde(rename = "marginUsed")]
+    pub margin_used: f64,
+
+    pub accounts: Vec<AccountBreakdown>,
+    pub exposure: Vec<CurrencyExposure>,
+
+    // Accounts that couldn't be read, and why
+    pub errors: Vec<String>,
+}
+
+impl AggregateReport {
+    // Combine the accounts' summaries and positions, converting at the converter's rates
+    pub fn build(
+        currency: &str,
+        accounts: &[(String, AccountSummary, Vec<Position>)],
+        converter: &Converter,
+    ) -> Self {
+        let mut report = AggregateReport {
+            time: chrono::Utc::now().to_rfc3339(),
+            currency: currency.to_string(),
+            equity: 0.0,
+            unrealized_pl: 0.0,
+            margin_used: 0.0,
+            accounts: Vec::new(),
+            exposure: Vec::new(),
+            errors: Vec::new(),
+        };
+
+        let mut exposure: BTreeMap<String, f64> = BTreeMap::new();
+        for (name, summary, positions) in accounts {
+            let rate = converter.rate(&summary.currency, currency);
+            match rate {
+                Some(rate) => {
+                    report.equity += summary.nav * rate;
+                    report.unrealized_pl += summary.unrealized_pl * rate;
+                    report.margin_used += summary.margin_used * rate;
+                }
+                None => log::warn!(
+                    "No rate to convert {} into {}, leaving account {} out of the totals",
+                    summary.currency,
+                    currency,
+                    name
+                ),
+            }
+            report.accounts.push(AccountBreakdown {
+                name: name.clone(),
+                currency: summary.currency.clone(),
+                balance: summary.balance,
+                nav: summary.nav,
+                unrealized_pl: summary.unrealized_pl,
+                margin_used: summary.margin_used,
+                open_positions: summary.open_position_count,
+                equity: rate.map(|rate| summary.nav * rate),
+            });
+
+            for position in positions {
+                let units = position.units();
+                let (base, quote) = match split_instrument(&position.instrument) {
+                    Some(currencies) if units != 0.0 => currencies,
+                    _ => continue,
+                };
+                *exposure.entry(base.to_string()).or_default() += units;
+                match converter.rate(base, quote) {
+                    Some(price) => {
+                        *exposure.entry(quote.to_string()).or_default() -= units * price;
+                    }
+                    None => log::warn!("No price of {} for its exposure", position.instrument),
+                }
+            }
+        }
+
+        report.exposure = exposure
+            .into_iter()
+            .filter(|(_, amount)| *amount != 0.0)
+            .map(|(exposed, amount)| CurrencyExposure {
+                value: converter.convert(amount, &exposed, currency),
+                currency: exposed,
+                amount,
+            })
+            .collect();
+        report
+    }
+
+    pub fn text(&self) -> String {
+        let mut lines = vec![format!(
+            "Equity {:.2} {} across {} accounts, unrealized P&L {:.2}, margin used {:.2}",
+            self.equity,
+            self.currency,
+            self.accounts.len(),
+            self.unrealized_pl,
+            self.margin_used
+        )];
+        for account in &self.accounts {
+            let equity = match account.equity {
+                Some(equity) => format!(" ({:.2} {})", equity, self.currency),
+                None => String::new(),
+            };
+            lines.push(format!(
+                "  {}: NAV {:.2} {}{}, unrealized P&L {:.2}, margin used {:.2}, {} positions",
+                account.name,
+                account.nav,
+                account.currency,
+                equity,
+                account.unrealized_pl,
+                account.margin_used,
+                account.open_positions
+            ));
+        }
+        if !self.exposure.is_empty() {
+            lines.push("Exposure by currency:".to_string());
+        }
+        for exposure in &self.exposure {
+            let value = match exposure.value {
+                Some(value) => format!(" ({:.2} {})", value, self.currency),
+                None => String::new(),
+            };
+            lines.push(format!(
+                "  {} {:+.0}{}",
+                exposure.currency, exposure.amount, value
+            ));
+        }
+        for error in &self.errors {
+            lines.push(format!("  {}", error));
+        }
+        lines.join("\n")
+    }
+
+    // Send the report's text to the sinks, waiting for it to arrive
+    pub async fn notify(&self, sinks: &[NotificationSink]) {
+        let notification = Notification {
+            time: self.time.clone(),
+            source: "accounts".to_string(),
+            instrument: None,
+            text: self.text(),
+        };
+        deliver(sinks, &notification).await;
+    }
+}
+
+// Read every configured account and combine them into one report. An account that can't be read
+// is listed in the report's errors rather than failing the rest.
+pub async fn aggregate(
+    main: &OandaSettings,
+    reporting: &ReportingSettings,
+) -> Result<AggregateReport, Box<dyn std::error::Error>> {
+    let mut accounts = Vec::new();
+    let mut errors = Vec::new();
+    let mut instruments = reporting.conversions.clone();
+    for account in reporting.all_accounts(main) {
+        let read = async {
+            let summary = oanda::get_account_summary(&account.oanda).await?;
+            let positions = oanda::get_positions(&account.oanda).await?;
+            Ok::<_, Box<dyn std::error::Error>>((summary, positions))
+        };
+        match read.await {
+            Ok((summary, positions)) => {
+                for position in &positions {
+                    if !instruments.contains(&position.instrument) {
+                        instruments.push(position.instrument.clone());
+                    }
+                }
+                accounts.push((account.name, summary, positions));
+            }
+            Err(e) => {
+                log::error!("Failed to read account {}: {}", account.name, e);
+                errors.push(format!("Failed to read account {}: {}", account.name, e));
+            }
+        }
+    }
+
+    let mut converter = Converter::new();
+    if !instruments.is_empty() {
+        converter.update_all(&oanda::get_latest_prices(&instruments, main).await?);
+    }
+    let mut report = AggregateReport::build(&reporting.currency, &accounts, &converter);
+    report.errors = errors;
+    Ok(report)
+}
@@ -0,0 +1,332 @@
+use std::collections::{HashMap, HashSet};
+
+use serde::{Deserialize, Serialize};
+
+use crate::calendar;
+use crate::notify::{notify, Notification, NotificationSink};
+use crate::oanda::objects::Price;
+
+// Rules watching prices and positions for an operator, whatever the strategies are doing,
+// "alerts" in the trading or collector config, e.g.
+// {"rules": [{"type": "priceCross", "instrument": "EUR_USD", "level": 1.1},
+//            {"type": "spread", "maxSpread": 0.0005},
+//            {"type": "stale", "instrument": "USD_JPY", "seconds": 120},
+//            {"name": "eurLoss", "type": "positionPl", "instrument": "EUR_USD", "min": -500}],
+//  "notify": [{"type": "log"}, {"type": "webhook", "url": "https://..."}]}
+// A rule alerts when its condition starts to hold and again only once it has stopped holding,
+// and at most once every `cooldown` seconds for each instrument. Rules without an instrument
+// apply to every instrument. Stale prices and positions are looked at every `checkInterval`
+// seconds, and positions only by the trader.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct AlertConfig {
+    pub rules: Vec<AlertRule>,
+
+    #[serde(default = "default_sinks")]
+    pub notify: Vec<NotificationSink>,
+
+    #[serde(default = "default_cooldown")]
+    pub cooldown: u64,
+
+    #[serde(default = "default_check_interval")]
+    #[serde(rename = "checkInterval")]
+    pub check_interval: u64,
+}
+
+fn default_sinks() -> Vec<NotificationSink> {
+    vec![NotificationSink::Log]
+}
+
+fn default_cooldown() -> u64 {
+    60
+}
+
+fn default_check_interval() -> u64 {
+    10
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct AlertRule {
+    // Shown in the alert, the type of rule without one
+    #[serde(default)]
+    pub name: Option<String>,
+
+    #[serde(flatten)]
+    pub condition: AlertCondition,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum AlertCondition {
+    // The mid price crosses `level`, either way
+    PriceCross {
+        instrument: String,
+        level: f64,
+    },
+
+    // The spread (ask - bid) is wider than maxSpread, in the instrument's price
+    Spread {
+        #[serde(default)]
+        instrument: Option<String>,
+
+        #[serde(rename = "maxSpread")]
+        max_spread: f64,
+    },
+
+    // No price for `seconds`. Without an instrument, any instrument that has had a price.
+    Stale {
+        #[serde(default)]
+        instrument: Option<String>,
+
+        seconds: u64,
+    },
+
+    // The unrealized profit of a position is below `min` or above `max`, in the account currency
+    PositionPl {
+        #[serde(default)]
+        instrument: Option<String>,
+
+        #[serde(default)]
+        min: Option<f64>,
+
+        #[serde(default)]
+        max: Option<f64>,
+    },
+}
+
+impl AlertCondition {
+    fn kind(&self) -> &'static str {
+        match self {
+            AlertCondition::PriceCross { .. } => "priceCross",
+            AlertCondition::Spread { .. } => "spread",
+            AlertCondition::Stale { .. } => "stale",
+            AlertCondition::PositionPl { .. } => "positionPl",
+        }
+    }
+
+    fn instrument(&self) -> Option<&str> {
+        match self {
+            AlertCondition::PriceCross { instrument, .. } => Some(instrument),
+            AlertCondition::Spread { instrument, .. }
+            | AlertCondition::Stale { instrument, .. }
+            | AlertCondition::PositionPl { instrument, .. } => instrument.as_deref(),
+        }
+    }
+
+    fn applies(&self, instrument: &str) -> bool {
+        self.instrument().is_none_or(|only| only == instrument)
+    }
+}
+
+impl AlertRule {
+    pub fn name(&self) -> &str {
+        self.name.as_deref().unwrap_or(self.condition.kind())
+    }
+}
+
+// Evaluates the alert rules on the prices and positions it's given, and sends what they alert
+pub struct Alerts {
+    config: AlertConfig,
+
+    // Latest mid and time of each instrument's prices
+    mids: HashMap<String, f64>,
+    last_seen: HashMap<String, u64>,
+
+    // When rules were first evaluated, what an instrument never priced is stale since
+    started: Option<u64>,
+    last_check: Option<u64>,
+
+    // (rule, instrument) whose condition holds, and when each last alerted
+    active: HashSet<(usize, String)>,
+    last_sent: HashMap<(usize, String), u64>,
+}
+
+impl Alerts {
+    pub fn new(config: AlertConfig) -> Self {
+        Alerts {
+            config,
+            mids: HashMap::new(),
+            last_seen: HashMap::new(),
+            started: None,
+            last_check: None,
+            active: HashSet::new(),
+            last_sent: HashMap::new(),
+        }
+    }
+
+    pub fn config(&self) -> &AlertConfig {
+        &self.config
+    }
+
+    // Notify these as well as the configured sinks, skipping any already there
+    pub fn add_sinks(&mut self, sinks: &[NotificationSink]) {
+        for sink in sinks {
+            if !self.config.notify.contains(sink) {
+                self.config.notify.push(sink.clone());
+            }
+        }
+    }
+
+    // Whether any rule looks at positions, which are only known to the trader
+    pub fn watches_positions(&self) -> bool {
+        self.config
+            .rules
+            .iter()
+            .any(|rule| matches!(rule.condition, AlertCondition::PositionPl { .. }))
+    }
+
+    // Check the price rules against a price, returning the alerts sent
+    pub fn price(&mut self, price: &Price) -> Vec<Notification> {
+        self.started.get_or_insert(price.time);
+        let instrument = &price.instrument;
+        let mid = (price.bid as f64 + price.ask as f64) / 2.0;
+        let spread = (price.ask - price.bid) as f64;
+        let previous = self.mids.insert(instrument.clone(), mid);
+        self.last_seen.insert(instrument.clone(), price.time);
+
+        let mut sent = Vec::new();
+        for index in 0..self.config.rules.len() {
+            let rule = &self.config.rules[index];
+            if !rule.condition.applies(instrument) {
+                continue;
+            }
+            match rule.condition {
+                AlertCondition::PriceCross { level, .. } => {
+                    let crossed = previous.is_some_and(|previous| {
+                        (previous < level && mid >= level) || (previous > level && mid <= level)
+                    });
+                    if crossed {
+                        let direction = if mid >= level { "above" } else { "below" };
+                        let text = format!("mid {:.5} crossed {} {}", mid, direction, level);
+                        sent.extend(self.send(index, instrument, price.time, text));
+                    }
+                }
+                AlertCondition::Spread { max_spread, .. } => {
+                    let text = format!("spread {:.5} wider than {}", spread, max_spread);
+                    sent.extend(self.update(
+                        index,
+                        instrument,
+                        price.time,
+                        spread > max_spread,
+                        text,
+                    ));
+                }
+                _ => {}
+            }
+        }
+        sent
+    }
+
+    // Whether stale prices and positions are due a look
+    pub fn check_due(&self, now: u64) -> bool {
+        self.last_check
+            .is_none_or(|last| now >= last + self.config.check_interval * 1000)
+    }
+
+    // Check the stale price rules at `now`, returning the alerts sent
+    pub fn check_stale(&mut self, now: u64) -> Vec<Notification> {
+        let started = *self.started.get_or_insert(now);
+        self.last_check = Some(now);
+
+        let mut sent = Vec::new();
+        for index in 0..self.config.rules.len() {
+            let rule = &self.config.rules[index];
+            let seconds = match rule.condition {
+                AlertCondition::Stale { seconds, .. } => seconds,
+                _ => continue,
+            };
+            let instruments: Vec<String> = match rule.condition.instrument() {
+                Some(instrument) => vec![instrument.to_string()],
+                None => self.last_seen.keys().cloned().collect(),
+            };
+            for instrument in instruments {
+                let since = self.last_seen.get(&instrument).copied().unwrap_or(started);
+                let age = now.saturating_sub(since) / 1000;
+                let text = format!("no price for {}s", age);
+                sent.extend(self.update(index, &instrument, now, age >= seconds, text));
+            }
+        }
+        sent
+    }
+
+    // Check the position rules against the unrealized profit of each open position, returning
+    // the alerts sent
+    pub fn check_positions(&mut self, now: u64, positions: &[(String, f64)]) -> Vec<Notification> {
+        self.last_check = Some(now);
+
+        let mut sent = Vec::new();
+        for index in 0..self.config.rules.len() {
+            let rule = &self.config.rules[index];
+            let (min, max) = match rule.condition {
+                AlertCondition::PositionPl { min, max, .. } => (min, max),
+                _ => continue,
+            };
+            // Positions that were closed no longer breach anything
+            let held: Vec<(String, f64)> = positions
+                .iter()
+                .filter(|(instrument, _)| rule.condition.applies(instrument))
+                .cloned()
+                .collect();
+            self.active.retain(|(rule, instrument)| {
+                *rule != index || held.iter().any(|(held, _)| held == instrument)
+            });
+            for (instrument, pl) in held {
+                let breached = min.is_some_and(|min| pl < min) || max.is_some_and(|max| pl > max);
+                let text = format!("unrealized P&L {:.2}", pl);
+                sent.extend(self.update(index, &instrument, now, breached, text));
+            }
+        }
+        sent
+    }
+
+    // Alert when a condition starts to hold, and re-arm once it stops
+    fn update(
+        &mut self,
+        rule: usize,
+        instrument: &str,
+        time: u64,
+        holds: bool,
+        text: String,
+    ) -> Option<Notification> {
+        let key = (rule, instrument.to_string());
+        if !holds {
+            self.active.remove(&key);
+            return None;
+        }
+        if !self.active.insert(key) {
+            return None;
+        }
+        self.send(rule, instrument, time, text)
+    }
+
+    fn send(
+        &mut self,
+        rule: usize,
+        instrument: &str,
+        time: u64,
+        text: String,
+    ) -> Option<Notification> {
+        let key = (rule, instrument.to_string());
+        if self
+            .last_sent
+            .get(&key)
+            .is_some_and(|last| time < last + self.config.cooldown * 1000)
+        {
+            return None;
+        }
+        self.last_sent.insert(key, time);
+
+        let notification = Notification {
+            time: calendar::utc(time).to_rfc3339(),
+            source: "alert".to_string(),
+            instrument: Some(instrument.to_string()),
+            text: format!(
+                "{} [{}]: {}",
+                instrument,
+                self.config.rules[rule].name(),
+                text
+            ),
+        };
+        notify(&self.config.notify, &notification);
+        Some(notification)
+    }
+}
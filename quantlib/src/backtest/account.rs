@@ -0,0 +1,414 @@
+use std::collections::{BTreeMap, HashMap};
+
+use serde::{Deserialize, Serialize};
+
+use crate::fx::{split_instrument, Converter};
+use crate::models::{margin_utilization, MarginConfig};
+use crate::oanda::objects::{Price, PriceStatus};
+
+// An order executed by the simulated account
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Fill {
+    pub time: u64,
+    pub instrument: String,
+    pub units: f64,
+    pub price: f64,
+
+    // Profit realized by reducing or closing a position, in the account currency. 0 when there
+    // was no rate to convert it at, see `unconverted_pl`.
+    #[serde(rename = "realizedPl")]
+    pub realized_pl: f64,
+
+    // Profit realized in the instrument's quote currency with no rate into the account currency
+    // yet, which the account holds and adds to the balance once there is one
+    #[serde(default)]
+    #[serde(rename = "unconvertedPl")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub unconverted_pl: Option<f64>,
+
+    // What caused the order, e.g. "signal" or "trailingStop"
+    pub reason: String,
+
+    // Cost of crossing half the spread from the mid price, in the account currency, 0 when there
+    // was no rate to convert it at (it's in the unconverted profit instead)
+    #[serde(default)]
+    #[serde(rename = "spreadCost")]
+    pub spread_cost: f64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct SimulatedPosition {
+    pub units: f64,
+
+    #[serde(rename = "averagePrice")]
+    pub average_price: f64,
+}
+
+impl SimulatedPosition {
+    // Add a fill of `units` at `price` to the position, returning the profit it realizes in the
+    // instrument's quote currency. Reducing, closing or flipping the position realizes profit on
+    // the closed units, and adding to it averages the price.
+    pub fn fill(&mut self, units: f64, price: f64) -> f64 {
+        let mut realized_pl = 0.0;
+        if self.units != 0.0 && self.units.signum() != units.signum() {
+            let closed = units.abs().min(self.units.abs()) * self.units.signum();
+            realized_pl = closed * (price - self.average_price);
+        }
+
+        let new_units = self.units + units;
+        if new_units == 0.0 {
+            self.average_price = 0.0;
+        } else if self.units == 0.0 || new_units.signum() != self.units.signum() {
+            self.average_price = price;
+        } else if new_units.abs() > self.units.abs() {
+            self.average_price = (self.average_price * self.units + price * units) / new_units;
+        }
+        self.units = new_units;
+        realized_pl
+    }
+}
+
+// What the account holds, to carry a backtest on from where it stopped. The latest prices are
+// kept for valuing positions and converting currencies before new prices arrive.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct AccountState {
+    pub balance: f64,
+    pub positions: HashMap<String, SimulatedPosition>,
+    pub prices: Vec<QuotedPrice>,
+
+    #[serde(rename = "refusedOrders")]
+    pub refused_orders: u64,
+
+    #[serde(default)]
+    pub unconverted: BTreeMap<String, f64>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct QuotedPrice {
+    pub instrument: String,
+    pub time: u64,
+    pub bid: f32,
+    pub ask: f32,
+}
+
+impl QuotedPrice {
+    pub fn price(&self) -> Price {
+        Price {
+            bid: self.bid,
+            ask: self.ask,
+            time: self.time,
+            nanos: 0,
+            instrument: self.instrument.clone(),
+            tradeable: true,
+            status: PriceStatus::Tradeable,
+        }
+    }
+}
+
+// Simulates a netting account like the live PortfolioBuilder trades: every order is the change
+// from the current to the target position, so one order can close a position and open the
+// opposite one. Buys fill at the ask, sells at the bid, and positions are valued
+// at the price they could be closed at. Profit is converted into the account currency using the
+// most recent prices seen. Profit realized before there's a rate for its currency is held, out of
+// the balance, until there is one.
+pub struct SimulatedAccount {
+    pub currency: String,
+    pub balance: f64,
+    unconverted: BTreeMap<String, f64>,
+    positions: HashMap<String, SimulatedPosition>,
+    prices: HashMap<String, Price>,
+    converter: Converter,
+
+    // Time of the latest price of any instrument, which orders fill at even when their own
+    // instrument's price is older
+    time: u64,
+
+    // Orders that would use more margin than allowed are refused
+    margin: Option<MarginConfig>,
+    pub refused_orders: u64,
+}
+
+impl SimulatedAccount {
+    pub fn new(currency: &str, balance: f64) -> Self {
+        SimulatedAccount {
+            currency: currency.to_string(),
+            balance,
+            unconverted: BTreeMap::new(),
+            positions: HashMap::new(),
+            prices: HashMap::new(),
+            converter: Converter::new(),
+            time: 0,
+            margin: None,
+            refused_orders: 0,
+        }
+    }
+
+    pub fn with_margin(mut self, margin: Option<MarginConfig>) -> Self {
+        self.margin = margin;
+        self
+    }
+
+    pub fn update_price(&mut self, price: &Price) {
+        self.converter.update(price);
+        self.time = self.time.max(price.time);
+        self.prices.insert(price.instrument.clone(), price.clone());
+
+        // Book profit held for a rate now that there may be one
+        let converter = &self.converter;
+        let currency = &self.currency;
+        let mut converted = 0.0;
+        self.unconverted.retain(
+            |from, amount| match converter.convert(*amount, from, currency) {
+                Some(amount) => {
+                    converted += amount;
+                    false
+                }
+                None => true,
+            },
+        );
+        self.balance += converted;
+    }
+
+    // Realized profit held by the currency it's in until there's a rate into the account currency
+    pub fn unconverted(&self) -> &BTreeMap<String, f64> {
+        &self.unconverted
+    }
+
+    pub fn price(&self, instrument: &str) -> Option<&Price> {
+        self.prices.get(instrument)
+    }
+
+    pub fn units(&self, instrument: &str) -> f64 {
+        self.positions.get(instrument).map_or(0.0, |p| p.units)
+    }
+
+    pub fn positions(&self) -> &HashMap<String, SimulatedPosition> {
+        &self.positions
+    }
+
+    pub fn state(&self) -> AccountState {
+        let mut prices: Vec<QuotedPrice> = self
+            .prices
+            .values()
+            .map(|price| QuotedPrice {
+                instrument: price.instrument.clone(),
+                time: price.time,
+                bid: price.bid,
+                ask: price.ask,
+            })
+            .collect();
+        prices.sort_by(|a, b| a.instrument.cmp(&b.instrument));
+        AccountState {
+            balance: self.balance,
+            positions: self.positions.clone(),
+            prices,
+            refused_orders: self.refused_orders,
+            unconverted: self.unconverted.clone(),
+        }
+    }
+
+    pub fn restore(&mut self, state: &AccountState) {
+        self.balance = state.balance;
+        self.positions = state.positions.clone();
+        self.refused_orders = state.refused_orders;
+        self.unconverted = state.unconverted.clone();
+        for price in &state.prices {
+            self.update_price(&price.price());
+        }
+    }
+
+    // Fill a market order at the latest price, returns None if no price has been seen yet or the
+    // order would use more margin than allowed
+    pub fn market_order(&mut self, instrument: &str, units: f64, reason: &str) -> Option<Fill> {
+        let price = self.prices.get(instrument)?;
+        let fill_price = if units > 0.0 { price.ask } else { price.bid } as f64;
+        let spread_cost = units.abs() * (price.ask - price.bid) as f64 / 2.0;
+        self.fill_at(instrument, units, fill_price, spread_cost, reason)
+    }
+
+    // Fill a limit order at its price, which the latest price has traded through. Its spread
+    // cost is what it paid over the mid, negative when it captured some of the spread.
+    pub fn limit_fill(
+        &mut self,
+        instrument: &str,
+        units: f64,
+        price: f64,
+        reason: &str,
+    ) -> Option<Fill> {
+        let quote = self.prices.get(instrument)?;
+        let mid = (quote.bid as f64 + quote.ask as f64) / 2.0;
+        self.fill_at(instrument, units, price, units * (price - mid), reason)
+    }
+
+    fn fill_at(
+        &mut self,
+        instrument: &str,
+        mut units: f64,
+        fill_price: f64,
+        spread_cost: f64,
+        reason: &str,
+    ) -> Option<Fill> {
+        if units == 0.0 {
+            return None;
+        }
+
+        if let Some(margin) = &self.margin {
+            let current = self.units(instrument);
+            if let Err(reason) = self.check_margin(margin, instrument, current, current + units) {
+                self.refused_orders += 1;
+                // A flip still closes the position, only the opposite one is refused
+                if current * (current + units) >= 0.0 {
+                    log::warn!(
+                        "[{}] Refusing order of {} units: {}",
+                        instrument,
+                        units,
+                        reason
+                    );
+                    return None;
+                }
+                log::warn!(
+                    "[{}] Only closing the position with an order of {} units: {}",
+                    instrument,
+                    units,
+                    reason
+                );
+                units = -current;
+            }
+        }
+
+        if !self.prices.contains_key(instrument) {
+            return None;
+        }
+        let time = self.time;
+
+        let position = self.positions.entry(instrument.to_string()).or_default();
+        let realized_pl = position.fill(units, fill_price);
+        if position.units == 0.0 {
+            self.positions.remove(instrument);
+        }
+
+        let (realized_pl, unconverted_pl) = match self.to_account_currency(instrument, realized_pl)
+        {
+            Some(realized_pl) => (realized_pl, None),
+            None => {
+                let quote = quote_currency(instrument).to_string();
+                *self.unconverted.entry(quote).or_insert(0.0) += realized_pl;
+                (0.0, Some(realized_pl))
+            }
+        };
+        let spread_cost = self
+            .to_account_currency(instrument, spread_cost)
+            .unwrap_or(0.0);
+        self.balance += realized_pl;
+
+        Some(Fill {
+            time,
+            instrument: instrument.to_string(),
+            units,
+            price: fill_price,
+            realized_pl,
+            unconverted_pl,
+            reason: reason.to_string(),
+            spread_cost,
+        })
+    }
+
+    // Profit from closing every open position at the latest prices, in the account currency
+    pub fn unrealized_pl(&self) -> f64 {
+        self.positions
+            .keys()
+            .filter_map(|instrument| self.position_pl(instrument))
+            .sum()
+    }
+
+    // Profit from closing the position in an instrument at its latest price, in the account
+    // currency, None without a position, price or rate to convert it at
+    pub fn position_pl(&self, instrument: &str) -> Option<f64> {
+        let position = self.positions.get(instrument)?;
+        let price = self.prices.get(instrument)?;
+        let exit_price = if position.units > 0.0 {
+            price.bid
+        } else {
+            price.ask
+        } as f64;
+        let pl = position.units * (exit_price - position.average_price);
+        self.to_account_currency(instrument, pl)
+    }
+
+    pub fn nav(&self) -> f64 {
+        self.balance + self.unrealized_pl()
+    }
+
+    // Margin held against the open positions in the account currency, 0 without a margin config
+    pub fn margin_used(&self) -> f64 {
+        match &self.margin {
+            Some(margin) => self
+                .positions
+                .iter()
+                .fold(0.0, |used, (instrument, position)| {
+                    used + self.position_margin(margin, instrument, position.units)
+                }),
+            None => 0.0,
+        }
+    }
+
+    pub fn margin_utilization(&self) -> f64 {
+        margin_utilization(self.margin_used(), self.nav())
+    }
+
+    // Positions that can't be converted into the account currency yet don't count towards the
+    // margin reported, though orders adding margin are refused until they can
+    fn position_margin(&self, margin: &MarginConfig, instrument: &str, units: f64) -> f64 {
+        margin
+            .position_margin(&self.converter, instrument, units, &self.currency)
+            .unwrap_or(0.0)
+    }
+
+    // Whether the instrument's position may go from `current` to `target` units. Margin that
+    // can't be valued yet, of the position or of any other, refuses it unless it only reduces the
+    // position.
+    fn check_margin(
+        &self,
+        margin: &MarginConfig,
+        instrument: &str,
+        current: f64,
+        target: f64,
+    ) -> Result<(), String> {
+        if current * target >= 0.0 && target.abs() <= current.abs() {
+            return Ok(());
+        }
+        let unknown = || format!("no conversion rate into {} yet", self.currency);
+        let mut before = 0.0;
+        for (held, position) in &self.positions {
+            before += margin
+                .position_margin(&self.converter, held, position.units, &self.currency)
+                .ok_or_else(unknown)?;
+        }
+        let change = margin
+            .position_margin(&self.converter, instrument, target, &self.currency)
+            .zip(margin.position_margin(&self.converter, instrument, current, &self.currency))
+            .map(|(after, held)| after - held)
+            .ok_or_else(unknown)?;
+        margin.check(before, before + change, self.nav())
+    }
+
+    // Total absolute units held across all instruments
+    pub fn exposure(&self) -> f64 {
+        self.positions
+            .values()
+            .fold(0.0, |exposure, p| exposure + p.units.abs())
+    }
+
+    // Convert an amount in the instrument's quote currency into the account currency, None while
+    // there's no rate to convert it at
+    fn to_account_currency(&self, instrument: &str, amount: f64) -> Option<f64> {
+        self.converter
+            .convert(amount, quote_currency(instrument), &self.currency)
+    }
+}
+
+// The currency an instrument's profit is in, the instrument itself if it isn't a currency pair,
+// which nothing converts
+pub(crate) fn quote_currency(instrument: &str) -> &str {
+    split_instrument(instrument).map_or(instrument, |(_, quote)| quote)
+}
@@ -0,0 +1,200 @@
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+use sha2::{Digest, Sha256};
+
+use super::{BacktestConfig, BacktestReport, Backtester};
+use crate::catalog::Catalog;
+use crate::errors::Context;
+use crate::util::TradingConfig;
+
+// Directory of the backtest cache, unset for no caching
+pub const CACHE_VARIABLE: &str = "BACKTEST_CACHE";
+
+// Reports of finished backtests stored by what they were run with, so that running the same
+// config over the same data again (as an optimization does across iterations) returns the stored
+// report instead. The key is a hash of the config, the files it reads (a warm start checkpoint,
+// strategy scripts) and the checksums in the manifests of the archived weeks it runs over. Only
+// archive backtests whose weeks all have manifests are cached, as collected data keeps growing.
+// Reports are stored by the build that ran them, a hash of the running executable, so changing a
+// strategy's code and rebuilding runs its backtests afresh.
+pub struct BacktestCache {
+    dir: PathBuf,
+}
+
+impl BacktestCache {
+    pub fn new<P: AsRef<Path>>(dir: P) -> Self {
+        BacktestCache {
+            dir: dir.as_ref().to_path_buf(),
+        }
+    }
+
+    // The cache in the directory named by BACKTEST_CACHE, if set
+    pub fn from_env() -> Option<Self> {
+        std::env::var_os(CACHE_VARIABLE).map(Self::new)
+    }
+
+    // Run a backtest, or return the stored report of the same one. The report has the config
+    // it's asked for, whatever paths the stored one was run with.
+    pub fn run(
+        &self,
+        config: BacktestConfig,
+    ) -> Result<BacktestReport, Box<dyn std::error::Error>> {
+        let key = cache_key(&config)?;
+        if let Some(key) = &key {
+            if let Some(mut report) = self.get(key) {
+                log::info!("Using cached backtest {}", key);
+                report.config = config;
+                return Ok(report);
+            }
+        }
+
+        let report = {
+            let _claim = config.claim_data("backtest")?;
+            let prices = config.open_prices()?;
+            Backtester::new(config)?.run(prices)?
+        };
+        if let Some(key) = &key {
+            if let Err(e) = self.put(key, &report) {
+                log::warn!("Failed to cache backtest {}: {}", key, e);
+            }
+        }
+        Ok(report)
+    }
+
+    // A stored report, None if there's none or it can't be read
+    pub fn get(&self, key: &str) -> Option<BacktestReport> {
+        let path = self.path(key);
+        if !path.exists() {
+            return None;
+        }
+        match BacktestReport::load_json(&path) {
+            Ok(report) => Some(report),
+            Err(e) => {
+                log::warn!("Ignoring cached backtest {}: {}", path.display(), e);
+                None
+            }
+        }
+    }
+
+    // Written to a temporary file first so that a backtest interrupted while storing isn't
+    // mistaken for a finished one
+    pub fn put(
+        &self,
+        key: &str,
+        report: &BacktestReport,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        std::fs::create_dir_all(&self.dir)
+            .with_context(|| format!("Creating {}", self.dir.display()))?;
+        let path = self.path(key);
+        let temporary = path.with_extension("json.tmp");
+        report.save_json(&temporary)?;
+        std::fs::rename(&temporary, &path)?;
+        Ok(())
+    }
+
+    fn path(&self, key: &str) -> PathBuf {
+        self.dir.join(format!("{}.json", key))
+    }
+}
+
+// What a backtest's result depends on, hashed, or None if it can't be cached
+pub fn cache_key(config: &BacktestConfig) -> Result<Option<String>, Box<dyn std::error::Error>> {
+    let archive = match &config.archive {
+        Some(archive) => archive,
+        None => {
+            log::debug!("Only backtests of an archive are cached");
+            return Ok(None);
+        }
+    };
+    // A stored report can't write the checkpoint or state
+    if config.save_checkpoint.is_some() || config.save_state.is_some() {
+        return Ok(None);
+    }
+
+    let build = match build() {
+        Some(build) => build,
+        None => return Ok(None),
+    };
+    let mut hasher = Sha256::new();
+    hasher.update(build);
+
+    // Where files are is left out, what's in them is hashed instead
+    let mut settings = serde_json::to_value(config)?;
+    if let Some(settings) = settings.as_object_mut() {
+        for location in [
+            "storage",
+            "archive",
+            "warmStart",
+            "saveCheckpoint",
+            "resume",
+            "saveState",
+        ] {
+            settings.remove(location);
+        }
+    }
+    hasher.update(settings.to_string());
+    let mut files: Vec<PathBuf> = config.warm_start.iter().cloned().collect();
+    files.extend(config.resume.clone());
+    files.extend(scripts(&config.strategy));
+    for path in files {
+        let contents =
+            std::fs::read(&path).with_context(|| format!("Reading {}", path.display()))?;
+        hasher.update(contents);
+    }
+
+    for entry in Catalog::load(archive)?.select(&config.quality).entries {
+        let manifest = match entry.manifest {
+            Some(manifest) => manifest,
+            None => {
+                log::info!(
+                    "Not caching the backtest, {} has no manifest",
+                    entry.path.display()
+                );
+                return Ok(None);
+            }
+        };
+        hasher.update(format!(
+            "{} {} {} {}",
+            entry.instrument, entry.year, entry.week, manifest.checksum
+        ));
+    }
+    Ok(Some(hex::encode(hasher.finalize())))
+}
+
+// A hash of the running executable, None if it can't be read. It's hashed once per run.
+fn build() -> Option<&'static str> {
+    static BUILD: OnceLock<Option<String>> = OnceLock::new();
+    let build = BUILD.get_or_init(|| {
+        let hash = || -> Result<String, Box<dyn std::error::Error>> {
+            let mut file = std::fs::File::open(std::env::current_exe()?)?;
+            let mut hasher = Sha256::new();
+            std::io::copy(&mut file, &mut hasher)?;
+            Ok(hex::encode(hasher.finalize()))
+        };
+        match hash() {
+            Ok(hash) => Some(hash),
+            Err(e) => {
+                log::warn!("Not caching backtests, the executable can't be read: {}", e);
+                None
+            }
+        }
+    });
+    build.as_deref()
+}
+
+// Scripts of the config's script strategies
+fn scripts(config: &TradingConfig) -> Vec<PathBuf> {
+    let mut models = vec![(&config.model, &config.model_config)];
+    models.extend(
+        config
+            .strategies
+            .iter()
+            .map(|strategy| (&strategy.model, &strategy.model_config)),
+    );
+    models
+        .into_iter()
+        .filter(|(model, _)| model.as_str() == "script")
+        .filter_map(|(_, settings)| settings["script"].as_str().map(PathBuf::from))
+        .collect()
+}
@@ -0,0 +1,306 @@
+use std::collections::{BTreeMap, HashMap};
+
+use serde::{Deserialize, Serialize};
+
+use crate::engine::format_time;
+use crate::journal::JournalEntry;
+use crate::models::SignalBus;
+use crate::oanda::objects::Price;
+use crate::util::TradingConfig;
+
+// How the live trader's signals compared with the same strategies replayed over the recorded
+// prices of the same period. Divergences mean the live and backtest engines no longer behave the
+// same, or the recorded data isn't what the trader saw.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct DriftReport {
+    pub from: String,
+    pub to: String,
+
+    // Recorded prices replayed within the period, after warming up
+    pub ticks: u64,
+
+    #[serde(rename = "liveSignals")]
+    pub live_signals: usize,
+
+    #[serde(rename = "replayedSignals")]
+    pub replayed_signals: usize,
+
+    pub matched: usize,
+
+    // Signals the replay resolved that the trader didn't, and the other way round
+    pub missing: Vec<SignalDrift>,
+    pub unexpected: Vec<SignalDrift>,
+
+    // Signals resolved at the same time live and in the replay, but with different forecasts
+    pub mismatched: Vec<SignalDrift>,
+
+    // Live decisions taken on a price that isn't in the recorded data
+    #[serde(rename = "unrecordedTicks")]
+    pub unrecorded_ticks: Vec<SignalDrift>,
+
+    // Matched signals that came in a different order live than in the replay
+    #[serde(rename = "outOfOrder")]
+    pub out_of_order: usize,
+
+    // How long after the replayed signal the live one was journaled, in milliseconds
+    #[serde(rename = "maxLag")]
+    pub max_lag: i64,
+
+    #[serde(rename = "meanLag")]
+    pub mean_lag: f64,
+
+    // Prices the live strategy workers dropped while behind, by instrument, in the journaled
+    // periods overlapping the compared one. The replay ticks every one of them, so these are
+    // signals the trader could never have matched.
+    #[serde(default)]
+    #[serde(rename = "droppedTicks")]
+    pub dropped_ticks: BTreeMap<String, u64>,
+
+    // Dropped prices as a share of the recorded ticks replayed
+    #[serde(default)]
+    #[serde(rename = "droppedShare")]
+    pub dropped_share: f64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SignalDrift {
+    pub time: String,
+    pub instrument: String,
+    pub forecast: f64,
+
+    // The other side's forecast, for mismatches
+    #[serde(default)]
+    #[serde(rename = "otherForecast")]
+    pub other_forecast: Option<f64>,
+}
+
+impl DriftReport {
+    pub fn has_drift(&self) -> bool {
+        !self.missing.is_empty()
+            || !self.unexpected.is_empty()
+            || !self.mismatched.is_empty()
+            || !self.unrecorded_ticks.is_empty()
+            || self.out_of_order > 0
+    }
+
+    pub fn save_json<P: AsRef<std::path::Path>>(
+        &self,
+        path: P,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        std::fs::write(path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+}
+
+// A resolved signal, live or replayed
+#[derive(Debug, Clone)]
+struct Signal {
+    time: u64,
+    instrument: String,
+    forecast: f64,
+    bid: f64,
+    ask: f64,
+}
+
+impl Signal {
+    fn drift(&self, other_forecast: Option<f64>) -> SignalDrift {
+        SignalDrift {
+            time: format_time(self.time),
+            instrument: self.instrument.clone(),
+            forecast: self.forecast,
+            other_forecast,
+        }
+    }
+}
+
+// Replay `prices` through the config's strategies and compare the signals resolved between
+// `from` and `to` (milliseconds since the epoch) with the decisions in the live journal. Prices
+// from `warm_up` on are run through the strategies first, so their state has caught up with the
+// live trader's by `from`. Strategies paused, halted and enabled live are paused, halted and
+// enabled at the same times in the replay. Signals are matched if they're at most `tolerance`
+// milliseconds apart.
+pub fn compare_with_journal<I>(
+    config: &TradingConfig,
+    prices: I,
+    journal: &[JournalEntry],
+    warm_up: u64,
+    from: u64,
+    to: u64,
+    tolerance: u64,
+) -> Result<DriftReport, Box<dyn std::error::Error>>
+where
+    I: IntoIterator<Item = Price>,
+{
+    let mut live: Vec<Signal> = Vec::new();
+    let mut switches: Vec<(u64, Vec<String>, bool)> = Vec::new();
+    let mut dropped_ticks: BTreeMap<String, u64> = BTreeMap::new();
+    for entry in journal {
+        match entry {
+            JournalEntry::Decision {
+                time,
+                instrument,
+                bid,
+                ask,
+                forecast,
+                ..
+            } => {
+                let time = parse_journal_time(time)?;
+                if time >= from && time <= to + tolerance {
+                    live.push(Signal {
+                        time,
+                        instrument: instrument.clone(),
+                        forecast: *forecast,
+                        bid: *bid,
+                        ask: *ask,
+                    });
+                }
+            }
+            JournalEntry::Paused { time, strategy, .. } => {
+                switches.push((parse_journal_time(time)?, vec![strategy.clone()], false));
+            }
+            JournalEntry::CircuitBreaker {
+                time, strategies, ..
+            } => switches.push((*time, strategies.clone(), false)),
+            JournalEntry::Enabled { time, strategy } => {
+                switches.push((parse_journal_time(time)?, vec![strategy.clone()], true));
+            }
+            JournalEntry::DroppedTicks { period, .. } if period.start < to && period.end > from => {
+                for (instrument, count) in &period.dropped {
+                    *dropped_ticks.entry(instrument.clone()).or_default() += count;
+                }
+            }
+            _ => {}
+        }
+    }
+    switches.sort_by_key(|(time, _, _)| *time);
+
+    let mut bus = SignalBus::from_config(config)?;
+    let mut switches = switches.into_iter().peekable();
+    let mut replayed: Vec<Signal> = Vec::new();
+    let mut recorded: HashMap<String, Vec<(u64, f32, f32)>> = HashMap::new();
+    let mut report = DriftReport {
+        from: format_time(from),
+        to: format_time(to),
+        dropped_ticks,
+        ..Default::default()
+    };
+    for price in prices {
+        if price.time < warm_up {
+            continue;
+        }
+        if price.time > to {
+            break;
+        }
+        while let Some((_, strategies, enable)) =
+            switches.next_if(|(time, _, _)| *time <= price.time)
+        {
+            for name in strategies {
+                if enable {
+                    bus.resume(&name);
+                } else {
+                    bus.halt(&name);
+                }
+            }
+        }
+
+        // Signals while warming up only build up the strategies' standing forecasts
+        let resolved = bus.tick(&price)?;
+        if price.time < from {
+            continue;
+        }
+        report.ticks += 1;
+        recorded
+            .entry(price.instrument.clone())
+            .or_default()
+            .push((price.time, price.bid, price.ask));
+        if let Some(resolved) = resolved {
+            replayed.push(Signal {
+                time: price.time,
+                instrument: price.instrument.clone(),
+                forecast: resolved.signal.forecast,
+                bid: price.bid as f64,
+                ask: price.ask as f64,
+            });
+        }
+    }
+
+    report.live_signals = live.len();
+    report.replayed_signals = replayed.len();
+    if report.ticks > 0 {
+        let dropped: u64 = report.dropped_ticks.values().sum();
+        report.dropped_share = dropped as f64 / report.ticks as f64;
+    }
+
+    // Live decisions are journaled when they're taken, a little after the price they were
+    // taken on, which is what the recorded price should be. Recorded prices are single precision,
+    // the journal's are compared at the same precision.
+    for signal in &live {
+        let seen = recorded.get(&signal.instrument).is_some_and(|prices| {
+            prices.iter().any(|(time, bid, ask)| {
+                *time <= signal.time
+                    && signal.time - time <= tolerance
+                    && *bid == signal.bid as f32
+                    && *ask == signal.ask as f32
+            })
+        });
+        if !seen {
+            report.unrecorded_ticks.push(signal.drift(None));
+        }
+    }
+
+    // Each replayed signal takes the first live signal of its instrument within the tolerance,
+    // preferring one with the same forecast
+    let mut used = vec![false; live.len()];
+    let mut matches: Vec<(u64, u64)> = Vec::new();
+    for signal in &replayed {
+        let candidates: Vec<usize> = (0..live.len())
+            .filter(|&index| {
+                !used[index]
+                    && live[index].instrument == signal.instrument
+                    && live[index].time.abs_diff(signal.time) <= tolerance
+            })
+            .collect();
+        let same = candidates
+            .iter()
+            .copied()
+            .find(|&index| live[index].forecast == signal.forecast);
+        match (same, candidates.first()) {
+            (Some(index), _) => {
+                used[index] = true;
+                report.matched += 1;
+                matches.push((signal.time, live[index].time));
+            }
+            (None, Some(&index)) => {
+                used[index] = true;
+                report
+                    .mismatched
+                    .push(signal.drift(Some(live[index].forecast)));
+            }
+            (None, None) => report.missing.push(signal.drift(None)),
+        }
+    }
+    for (index, signal) in live.iter().enumerate() {
+        if !used[index] && signal.time <= to {
+            report.unexpected.push(signal.drift(None));
+        }
+    }
+
+    // Replayed signals are in order already, the live ones should be too
+    report.out_of_order = matches
+        .windows(2)
+        .filter(|pair| pair[1].1 < pair[0].1)
+        .count();
+    let lags: Vec<i64> = matches
+        .iter()
+        .map(|(replayed, live)| *live as i64 - *replayed as i64)
+        .collect();
+    report.max_lag = lags.iter().copied().max().unwrap_or(0);
+    if !lags.is_empty() {
+        report.mean_lag = lags.iter().sum::<i64>() as f64 / lags.len() as f64;
+    }
+    Ok(report)
+}
+
+fn parse_journal_time(time: &str) -> Result<u64, Box<dyn std::error::Error>> {
+    Ok(chrono::DateTime::parse_from_rfc3339(time)?.timestamp_millis() as u64)
+}
@@ -0,0 +1,325 @@
+use std::collections::BTreeSet;
+use std::fmt::Write as _;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use crate::backtest::BacktestReport;
+use crate::calendar;
+use crate::models::PassiveStats;
+
+// Size of each chart in the page, in SVG units
+const WIDTH: f64 = 960.0;
+const HEIGHT: f64 = 260.0;
+const MARGIN: f64 = 48.0;
+
+const STYLE: &str = "body{font-family:sans-serif;margin:24px;color:#222}\
+h1{font-size:20px}h2{font-size:16px;margin-top:28px}\
+table{border-collapse:collapse}td{padding:3px 12px;border-bottom:1px solid #ddd}\
+td:first-child{color:#666}td:last-child{text-align:right}\
+svg{background:#fafafa;border:1px solid #ddd}text{font-size:11px;fill:#666}";
+
+impl BacktestReport {
+    // Render the report as a single HTML page with no outside dependencies: the metrics table,
+    // the equity curve, the drawdown and each instrument's price with its fills marked, buys as
+    // green triangles pointing up and sells as red ones pointing down. Reports saved before rows
+    // carried prices only have the fills to draw.
+    pub fn to_html(&self) -> String {
+        let mut html = String::new();
+        let instruments = self.config.strategy.instruments.join(", ");
+        let _ = write!(
+            html,
+            "<!DOCTYPE html><html><head><meta charset=\"utf-8\"><title>Backtest {}</title>\
+             <style>{}</style></head><body><h1>Backtest {}</h1>",
+            escape(&instruments),
+            STYLE,
+            escape(&instruments)
+        );
+        if let (Some(first), Some(last)) = (self.rows.first(), self.rows.last()) {
+            let _ = write!(
+                html,
+                "<p>{} to {}, model {}</p>",
+                date(first.time),
+                date(last.time),
+                escape(&self.config.strategy.model)
+            );
+        }
+
+        html.push_str("<h2>Metrics</h2>");
+        html.push_str(&self.metrics_table());
+
+        let equity: Vec<(u64, f64)> = self.rows.iter().map(|row| (row.time, row.nav)).collect();
+        html.push_str("<h2>Equity</h2>");
+        html.push_str(&Chart::new(&equity).line(&equity, "#1f77b4").render());
+
+        let mut peak = self.metrics.initial_balance;
+        let drawdown: Vec<(u64, f64)> = self
+            .rows
+            .iter()
+            .map(|row| {
+                peak = peak.max(row.nav);
+                let drawdown = if peak > 0.0 {
+                    (row.nav - peak) / peak
+                } else {
+                    0.0
+                };
+                (row.time, drawdown * 100.0)
+            })
+            .collect();
+        html.push_str("<h2>Drawdown (%)</h2>");
+        html.push_str(&Chart::new(&drawdown).line(&drawdown, "#d62728").render());
+
+        let traded: BTreeSet<&str> = self
+            .fills
+            .iter()
+            .map(|fill| fill.instrument.as_str())
+            .collect();
+        for instrument in traded {
+            let prices: Vec<(u64, f64)> = self
+                .rows
+                .iter()
+                .filter(|row| row.instrument == instrument)
+                .filter_map(|row| Some((row.time, row.mid?)))
+                .collect();
+            let fills: Vec<(u64, f64)> = self
+                .fills
+                .iter()
+                .filter(|fill| fill.instrument == instrument)
+                .map(|fill| (fill.time, fill.price))
+                .collect();
+            let mut chart = Chart::new(&prices.iter().chain(&fills).copied().collect::<Vec<_>>())
+                .line(&prices, "#555");
+            for fill in self
+                .fills
+                .iter()
+                .filter(|fill| fill.instrument == instrument)
+            {
+                let title = format!(
+                    "{} {} units at {} ({}), realized {:.2}",
+                    date(fill.time),
+                    fill.units,
+                    fill.price,
+                    fill.reason,
+                    fill.realized_pl
+                );
+                chart = chart.marker(fill.time, fill.price, fill.units > 0.0, &title);
+            }
+            let _ = write!(html, "<h2>{}</h2>", escape(instrument));
+            html.push_str(&chart.render());
+        }
+
+        html.push_str("</body></html>\n");
+        html
+    }
+
+    // Save the report as a self contained HTML page, see to_html
+    pub fn save_html<P: AsRef<Path>>(&self, path: P) -> Result<(), Box<dyn std::error::Error>> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        writer.write_all(self.to_html().as_bytes())?;
+        writer.flush()?;
+        Ok(())
+    }
+
+    fn metrics_table(&self) -> String {
+        let metrics = &self.metrics;
+        let optional = |value: Option<f64>| value.map_or("-".to_string(), |v| format!("{:.2}", v));
+        let rows = [
+            ("Ticks", metrics.ticks.to_string()),
+            ("Fills", metrics.fills.to_string()),
+            ("Initial balance", format!("{:.2}", metrics.initial_balance)),
+            ("Final NAV", format!("{:.2}", metrics.final_nav)),
+            ("Total return", percent(metrics.total_return)),
+            ("Max drawdown", percent(metrics.max_drawdown)),
+            ("Sharpe ratio", optional(metrics.sharpe_ratio)),
+            ("Spread cost", format!("{:.2}", metrics.spread_cost)),
+            (
+                "Max margin utilization",
+                percent(metrics.max_margin_utilization),
+            ),
+            ("Refused orders", metrics.refused_orders.to_string()),
+            ("Unfilled orders", metrics.unfilled_orders.to_string()),
+            (
+                "Passive fill ratio",
+                metrics
+                    .passive
+                    .as_ref()
+                    .and_then(PassiveStats::fill_ratio)
+                    .map_or("-".to_string(), percent),
+            ),
+            ("Trades", metrics.trades.count.to_string()),
+            ("Win rate", percent(metrics.trades.win_rate)),
+            ("Average P&L", format!("{:.2}", metrics.trades.average_pl)),
+            ("Average win", format!("{:.2}", metrics.trades.average_win)),
+            (
+                "Average loss",
+                format!("{:.2}", metrics.trades.average_loss),
+            ),
+            ("Profit factor", optional(metrics.trades.profit_factor)),
+            (
+                "Average duration",
+                format!("{:.1}h", metrics.trades.average_duration / 3_600_000.0),
+            ),
+        ];
+        let mut table = String::from("<table>");
+        for (name, value) in rows {
+            let _ = write!(
+                table,
+                "<tr><td>{}</td><td>{}</td></tr>",
+                escape(name),
+                value
+            );
+        }
+        table.push_str("</table>");
+        table
+    }
+}
+
+// An SVG chart of values over time, scaled to fit every point it was created with
+struct Chart {
+    start: u64,
+    end: u64,
+    low: f64,
+    high: f64,
+    body: String,
+}
+
+impl Chart {
+    fn new(points: &[(u64, f64)]) -> Self {
+        let mut chart = Chart {
+            start: points.iter().map(|(time, _)| *time).min().unwrap_or(0),
+            end: points.iter().map(|(time, _)| *time).max().unwrap_or(0),
+            low: points.iter().map(|(_, v)| *v).fold(f64::INFINITY, f64::min),
+            high: points
+                .iter()
+                .map(|(_, v)| *v)
+                .fold(f64::NEG_INFINITY, f64::max),
+            body: String::new(),
+        };
+        if !chart.low.is_finite() {
+            chart.low = 0.0;
+            chart.high = 1.0;
+        } else if chart.high == chart.low {
+            chart.low -= 1.0;
+            chart.high += 1.0;
+        }
+        chart
+    }
+
+    fn x(&self, time: u64) -> f64 {
+        let span = self.end.saturating_sub(self.start).max(1) as f64;
+        MARGIN + time.saturating_sub(self.start) as f64 / span * (WIDTH - 2.0 * MARGIN)
+    }
+
+    fn y(&self, value: f64) -> f64 {
+        HEIGHT - MARGIN + (self.low - value) / (self.high - self.low) * (HEIGHT - 2.0 * MARGIN)
+    }
+
+    fn line(mut self, points: &[(u64, f64)], colour: &str) -> Self {
+        if points.is_empty() {
+            return self;
+        }
+        let path: Vec<String> = points
+            .iter()
+            .map(|(time, value)| format!("{:.1},{:.1}", self.x(*time), self.y(*value)))
+            .collect();
+        let _ = write!(
+            self.body,
+            "<polyline fill=\"none\" stroke=\"{}\" stroke-width=\"1.2\" points=\"{}\"/>",
+            colour,
+            path.join(" ")
+        );
+        self
+    }
+
+    // A triangle at a fill, with its details shown on hover
+    fn marker(mut self, time: u64, value: f64, buy: bool, title: &str) -> Self {
+        let (x, y) = (self.x(time), self.y(value));
+        let (points, colour) = if buy {
+            (
+                format!(
+                    "{:.1},{:.1} {:.1},{:.1} {:.1},{:.1}",
+                    x,
+                    y,
+                    x - 5.0,
+                    y + 9.0,
+                    x + 5.0,
+                    y + 9.0
+                ),
+                "#2ca02c",
+            )
+        } else {
+            (
+                format!(
+                    "{:.1},{:.1} {:.1},{:.1} {:.1},{:.1}",
+                    x,
+                    y,
+                    x - 5.0,
+                    y - 9.0,
+                    x + 5.0,
+                    y - 9.0
+                ),
+                "#d62728",
+            )
+        };
+        let _ = write!(
+            self.body,
+            "<polygon fill=\"{}\" points=\"{}\"><title>{}</title></polygon>",
+            colour,
+            points,
+            escape(title)
+        );
+        self
+    }
+
+    fn render(&self) -> String {
+        let bottom = HEIGHT - MARGIN;
+        let right = WIDTH - MARGIN;
+        let mut svg = format!(
+            "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{}\" height=\"{}\" viewBox=\"0 0 {} {}\">",
+            WIDTH, HEIGHT, WIDTH, HEIGHT
+        );
+        let _ = write!(
+            svg,
+            "<path fill=\"none\" stroke=\"#bbb\" d=\"M{m},{m}V{b}H{r}\"/>\
+             <text x=\"4\" y=\"{top}\">{high}</text><text x=\"4\" y=\"{b}\">{low}</text>\
+             <text x=\"{m}\" y=\"{label}\">{start}</text>\
+             <text x=\"{r}\" y=\"{label}\" text-anchor=\"end\">{end}</text>",
+            m = MARGIN,
+            b = bottom,
+            r = right,
+            top = MARGIN + 4.0,
+            label = bottom + 16.0,
+            high = label(self.high),
+            low = label(self.low),
+            start = date(self.start),
+            end = date(self.end)
+        );
+        svg.push_str(&self.body);
+        svg.push_str("</svg>");
+        svg
+    }
+}
+
+fn date(time: u64) -> String {
+    calendar::utc(time).format("%Y-%m-%d %H:%M").to_string()
+}
+
+fn percent(fraction: f64) -> String {
+    format!("{:.2}%", fraction * 100.0)
+}
+
+// Enough significant digits to tell prices apart as well as balances
+fn label(value: f64) -> String {
+    if value.abs() >= 100.0 {
+        format!("{:.0}", value)
+    } else {
+        format!("{:.5}", value)
+    }
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
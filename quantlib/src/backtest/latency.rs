@@ -0,0 +1,32 @@
+use rand::rngs::StdRng;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+// Time between a signal and its order being filled, in milliseconds, e.g.
+// {"type": "fixed", "ms": 150} or {"type": "exponential", "min": 50, "mean": 120}
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum Latency {
+    Fixed { ms: u64 },
+
+    // Equally likely anywhere between min and max
+    Uniform { min: u64, max: u64 },
+
+    // At least min, with an exponentially distributed delay on top averaging mean - min,
+    // which gives the long tail typical of network round trips
+    Exponential { min: u64, mean: u64 },
+}
+
+impl Latency {
+    pub fn sample(&self, rng: &mut StdRng) -> u64 {
+        match self {
+            Latency::Fixed { ms } => *ms,
+            Latency::Uniform { min, max } => rng.gen_range(*min..=(*max).max(*min)),
+            Latency::Exponential { min, mean } => {
+                let scale = mean.saturating_sub(*min) as f64;
+                let uniform: f64 = rng.gen();
+                min + (-(1.0 - uniform).ln() * scale).round() as u64
+            }
+        }
+    }
+}
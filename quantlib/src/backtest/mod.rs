@@ -0,0 +1,894 @@
+pub mod account;
+pub mod cache;
+pub mod drift;
+pub mod html;
+pub mod latency;
+pub mod reconstruction;
+pub mod regimes;
+pub mod report;
+pub mod resting;
+pub mod resume;
+pub mod scenarios;
+pub mod trades;
+
+pub use account::*;
+pub use cache::*;
+pub use drift::*;
+pub use latency::*;
+pub use reconstruction::*;
+pub use regimes::*;
+pub use report::*;
+pub use resting::*;
+pub use resume::*;
+pub use scenarios::*;
+pub use trades::*;
+
+use std::collections::BTreeSet;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use serde::{Deserialize, Serialize};
+
+use crate::calendar;
+use crate::catalog::{Catalog, QualityFilter};
+use crate::claims::DirectoryClaim;
+use crate::data::{MergedReader, StorageLayout};
+use crate::engine::{format_time, DrawdownScaler};
+use crate::errors::Context;
+use crate::models::{
+    CircuitBreaker, PassiveOutcome, PassiveStats, PositionSizer, ResolvedSignal, SignalBus,
+    StrategyCheckpoint, TargetSmoother, TrailingStopManager, WorkingTargets, WorkingUpdate,
+};
+use crate::oanda::objects::Price;
+use crate::util::TradingConfig;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct BacktestConfig {
+    // The same config the trading binary runs the strategy with
+    pub strategy: TradingConfig,
+
+    // Position size taken on a signal, as settings.json's trading units. The strategy's
+    // "positionSizing" can override it per instrument or size positions by notional value instead.
+    pub units: f64,
+
+    #[serde(default = "default_initial_balance")]
+    #[serde(rename = "initialBalance")]
+    pub initial_balance: f64,
+
+    #[serde(default = "default_account_currency")]
+    #[serde(rename = "accountCurrency")]
+    pub account_currency: String,
+
+    // Milliseconds of data between rows of the report
+    #[serde(default = "default_sample_interval")]
+    #[serde(rename = "sampleInterval")]
+    pub sample_interval: u64,
+
+    // Where the collected data to run over is stored
+    #[serde(default)]
+    pub storage: StorageLayout,
+
+    // Weekly archive written by the weekly pipeline to run over instead of storage
+    #[serde(default)]
+    pub archive: Option<PathBuf>,
+
+    // Which of the archive's weeks to run over, by the quality of their data
+    #[serde(default)]
+    pub quality: QualityFilter,
+
+    // Stress scenario injected into the historical prices
+    #[serde(default)]
+    pub scenario: Option<Scenario>,
+
+    // How periods are labelled for the per-regime breakdown of the report
+    #[serde(default)]
+    pub regimes: RegimeConfig,
+
+    // Strategy checkpoint to start from, e.g. one saved by the live trader. Only prices after the
+    // checkpoint are traded on, earlier ones still update the account's conversion rates. The
+    // account starts flat, and takes the positions the checkpoint's forecasts ask for on the first
+    // price of each instrument.
+    #[serde(default)]
+    #[serde(rename = "warmStart")]
+    pub warm_start: Option<PathBuf>,
+
+    // Where to save the strategies' state at the end of the backtest
+    #[serde(default)]
+    #[serde(rename = "saveCheckpoint")]
+    pub save_checkpoint: Option<PathBuf>,
+
+    // State saved by an earlier run of this backtest to carry on from, trading only on prices
+    // after it. Archived weeks it had finished are skipped. Can be the same file as saveState, to
+    // extend the run with each new week of data.
+    #[serde(default)]
+    pub resume: Option<PathBuf>,
+
+    // Where to save the strategies, account and results at the end of the backtest, for resume
+    #[serde(default)]
+    #[serde(rename = "saveState")]
+    pub save_state: Option<PathBuf>,
+
+    // Delay between a signal and its fill. Orders fill at the first price after the delay
+    // instead of the price that caused them.
+    #[serde(default)]
+    pub latency: Option<Latency>,
+
+    // Seed for random latencies, so that runs are reproducible
+    #[serde(default)]
+    pub seed: u64,
+
+    // Signals placed as resting limit or stop orders rather than filled at the market. Their
+    // latency delays when they start working. Orders the strategy's "passiveExecution" applies to
+    // are posted as it configures instead.
+    #[serde(default)]
+    #[serde(rename = "restingOrders")]
+    pub resting_orders: Option<RestingOrderConfig>,
+}
+
+fn default_initial_balance() -> f64 {
+    100_000.0
+}
+
+fn default_account_currency() -> String {
+    "USD".to_string()
+}
+
+fn default_sample_interval() -> u64 {
+    60_000
+}
+
+impl BacktestConfig {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, Box<dyn std::error::Error>> {
+        log::info!("Loading backtest config from {:?}", path.as_ref());
+        let path = path.as_ref();
+        let file = File::open(path).with_context(|| format!("Opening {}", path.display()))?;
+        let mut config: Self = serde_json::from_reader(BufReader::new(file))
+            .with_context(|| format!("Parsing {}", path.display()))?;
+        config.strategy.expand_universes()?;
+        Ok(config)
+    }
+
+    // Claim the data being run over for reading, for as long as the claim is held
+    pub fn claim_data(&self, process: &str) -> Result<DirectoryClaim, Box<dyn std::error::Error>> {
+        match &self.archive {
+            Some(archive) => DirectoryClaim::reader(archive, process),
+            None => self.storage.clone().read_only().claim(process),
+        }
+    }
+
+    // Every price of the archive's selected weeks if there is an archive, otherwise of storage,
+    // in timestamp order
+    pub fn open_prices(&self) -> Result<MergedReader, Box<dyn std::error::Error>> {
+        match &self.archive {
+            Some(archive) => {
+                let mut catalog = Catalog::load(archive)?.select(&self.quality);
+                if let Some(path) = &self.resume {
+                    let time = BacktestState::load(path)?.time;
+                    catalog.entries.retain(|entry| entry.last > time);
+                }
+                catalog.open_prices(archive)
+            }
+            None => {
+                if !self.quality.is_empty() {
+                    log::warn!("Data quality criteria only apply to an archive, ignoring them");
+                }
+                MergedReader::open(&self.storage)
+            }
+        }
+    }
+}
+
+// Runs a strategy over historical prices against a simulated account.
+// Signals are turned into positions the same way as PortfolioBuilder does live.
+pub struct Backtester {
+    config: BacktestConfig,
+    strategy: SignalBus,
+    trailing_stops: Option<TrailingStopManager>,
+    circuit_breaker: Option<CircuitBreaker>,
+    drawdown: Option<DrawdownScaler>,
+    account: SimulatedAccount,
+    position_sizer: PositionSizer,
+    target_smoother: TargetSmoother,
+    regimes: RegimeLabeler,
+
+    fills: Vec<Fill>,
+    rows: Vec<BacktestRow>,
+    ticks: u64,
+    last_time: u64,
+    last_instrument: String,
+    next_sample: u64,
+
+    // Time of the warm start checkpoint, prices up to it were already seen by the strategies,
+    // and the instruments whose standing signals are still to be traded from the checkpoint
+    start_after: u64,
+    warm_started: BTreeSet<String>,
+
+    // Instruments a strategy halted by the circuit breaker had a forecast in, see tick
+    restating: BTreeSet<String>,
+
+    // Orders waiting out their latency, in the order they were placed
+    pending: Vec<PendingOrder>,
+    rng: StdRng,
+
+    // Limit and stop orders waiting to fill, and how many were replaced or expired before they did
+    resting: Vec<RestingOrder>,
+    unfilled_orders: u64,
+
+    // How the orders posted by passive execution ended, None without it
+    passive: Option<PassiveStats>,
+
+    // Signals with a validity, or a smoothed target, still being worked towards their targets
+    working: WorkingTargets,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub(crate) struct PendingOrder {
+    instrument: String,
+    units: f64,
+    reason: String,
+    strategies: Vec<String>,
+
+    #[serde(rename = "fillAfter")]
+    fill_after: u64,
+}
+
+impl Backtester {
+    pub fn new(config: BacktestConfig) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut strategy = SignalBus::from_config(&config.strategy)?;
+        if let Some(model_state) = &config.strategy.model_state {
+            // A resumed backtest carries on the recording of the run it resumes
+            strategy = strategy.with_model_state(model_state, config.resume.is_some())?;
+        }
+        let mut start_after = 0;
+        let mut warm_started = BTreeSet::new();
+        if let Some(path) = &config.warm_start {
+            if config.resume.is_some() {
+                return Err("A backtest can't both warm start and resume".into());
+            }
+            let checkpoint = StrategyCheckpoint::load(path)?;
+            strategy.restore(&checkpoint)?;
+            // The simulated account starts flat, so the checkpoint's forecasts are traded afresh
+            // on each instrument's first tradeable price after it
+            strategy.forget_resolved();
+            start_after = checkpoint.time;
+            warm_started = config.strategy.instruments.iter().cloned().collect();
+        }
+        let trailing_stops = config
+            .strategy
+            .trailing_stop
+            .clone()
+            .map(TrailingStopManager::new);
+        let account = SimulatedAccount::new(&config.account_currency, config.initial_balance)
+            .with_margin(config.strategy.margin.clone());
+        let position_sizer = PositionSizer::new(&config.strategy.position_sizing, config.units)
+            .with_volatility_target(config.strategy.volatility_target.clone())
+            .with_forecast_mapping(&config.strategy.forecast_mapping);
+        let target_smoother = TargetSmoother::new(&config.strategy.target_smoothing);
+        let smoothed = config.strategy.target_smoothing.working_validity();
+        let config_seed = config.seed;
+        let circuit_breaker = config
+            .strategy
+            .order_rate_limit
+            .clone()
+            .map(CircuitBreaker::new);
+        let drawdown = config
+            .strategy
+            .drawdown_scaling
+            .clone()
+            .map(DrawdownScaler::new);
+        let regimes = RegimeLabeler::new(config.regimes.clone())
+            .with_price_basis(config.strategy.price_basis);
+        let passive = config
+            .strategy
+            .passive_execution
+            .as_ref()
+            .map(|_| PassiveStats::default());
+
+        let mut backtester = Backtester {
+            config,
+            strategy,
+            trailing_stops,
+            circuit_breaker,
+            drawdown,
+            account,
+            position_sizer,
+            target_smoother,
+            regimes,
+            fills: Vec::new(),
+            rows: Vec::new(),
+            ticks: 0,
+            last_time: 0,
+            last_instrument: String::new(),
+            next_sample: 0,
+            start_after,
+            warm_started,
+            restating: BTreeSet::new(),
+            pending: Vec::new(),
+            rng: StdRng::seed_from_u64(config_seed),
+            resting: Vec::new(),
+            unfilled_orders: 0,
+            passive,
+            working: WorkingTargets::default().with_fallback(smoothed),
+        };
+        if let Some(path) = backtester.config.resume.clone() {
+            backtester.resume(BacktestState::load(path)?)?;
+        }
+        Ok(backtester)
+    }
+
+    // Carry on from the end of an earlier run
+    fn resume(&mut self, state: BacktestState) -> Result<(), Box<dyn std::error::Error>> {
+        log::info!(
+            "Resuming backtest from {} after {} ticks",
+            format_time(state.time),
+            state.ticks
+        );
+        self.strategy.restore(&state.strategies)?;
+        self.account.restore(&state.account);
+        for price in &state.account.prices {
+            self.position_sizer.update(&price.price());
+        }
+        // After the prices, which would otherwise take a volatility sample of their own
+        if let Some(volatility) = state.volatility {
+            self.position_sizer.restore_volatility(volatility);
+        }
+        if let (Some(trailing_stops), Some(state)) =
+            (&mut self.trailing_stops, state.trailing_stops)
+        {
+            trailing_stops.restore(state);
+        }
+        self.target_smoother.restore(state.target_smoother);
+        if let (Some(drawdown), Some(state)) = (&mut self.drawdown, state.drawdown) {
+            drawdown.restore(state);
+            self.position_sizer.set_risk_scale(drawdown.scale());
+        }
+        if let (Some(circuit_breaker), Some(state)) =
+            (&mut self.circuit_breaker, state.circuit_breaker)
+        {
+            circuit_breaker.restore(state);
+        }
+        self.fills = state.fills;
+        self.rows = state.rows;
+        self.ticks = state.ticks;
+        self.last_time = state.time;
+        self.next_sample = state.next_sample;
+        self.start_after = state.time;
+        self.pending = state.pending;
+        self.resting = state.resting;
+        self.unfilled_orders = state.unfilled_orders;
+        if self.passive.is_some() {
+            self.passive = Some(state.passive.unwrap_or_default());
+        }
+        self.working = WorkingTargets::new(state.working)
+            .with_fallback(self.config.strategy.target_smoothing.working_validity());
+        // Latencies continue from a different seed than the first run's
+        self.rng = StdRng::seed_from_u64(self.config.seed.wrapping_add(state.ticks));
+        Ok(())
+    }
+
+    // What the backtest has done so far, to resume from
+    pub fn state(&self) -> BacktestState {
+        BacktestState {
+            time: self.last_time,
+            ticks: self.ticks,
+            next_sample: self.next_sample,
+            strategies: self.strategy.checkpoint(),
+            account: self.account.state(),
+            fills: self.fills.clone(),
+            rows: self.rows.clone(),
+            pending: self.pending.clone(),
+            resting: self.resting.clone(),
+            unfilled_orders: self.unfilled_orders,
+            passive: self.passive.clone(),
+            working: self.working.targets(),
+            trailing_stops: self.trailing_stops.as_ref().map(TrailingStopManager::state),
+            target_smoother: self.target_smoother.state(),
+            volatility: self.position_sizer.volatility_state(),
+            drawdown: self.drawdown.as_ref().map(DrawdownScaler::state),
+            circuit_breaker: self.circuit_breaker.as_ref().map(CircuitBreaker::state),
+        }
+    }
+
+    pub fn account(&self) -> &SimulatedAccount {
+        &self.account
+    }
+
+    // Prices for instruments the strategy doesn't trade are still used for currency conversion.
+    // Signals a strategy halted on the price contributed to are restated without it after it.
+    pub fn tick(&mut self, price: &Price) -> Result<(), Box<dyn std::error::Error>> {
+        self.step(price)?;
+        while let Some(instrument) = self.restating.pop_first() {
+            self.trade_standing(&instrument);
+        }
+        Ok(())
+    }
+
+    fn step(&mut self, price: &Price) -> Result<(), Box<dyn std::error::Error>> {
+        self.position_sizer.update(price);
+        self.target_smoother.update(price);
+        if price.time <= self.start_after {
+            self.account.update_price(price);
+            // Positions only exist from here on, so the first scale applies to the first signals
+            self.position_sizer.take_rescaled();
+            return Ok(());
+        }
+
+        self.ticks += 1;
+        self.last_time = price.time;
+        self.last_instrument.clone_from(&price.instrument);
+        self.account.update_price(price);
+
+        if price.time >= self.next_sample {
+            self.record_row(price.time, &price.instrument);
+            let interval = self.config.sample_interval.max(1);
+            self.next_sample = price.time - price.time % interval + interval;
+        }
+
+        // Checked on the same schedule as the trading engine's
+        if let Some(drawdown) = &mut self.drawdown {
+            if drawdown.due(price.time) {
+                let change = drawdown
+                    .record(price.time, self.account.nav())
+                    .filter(|change| change.rescaled);
+                if let Some(change) = change {
+                    log::info!(
+                        "{} Equity {:.2} is {:.2}% below its peak of {:.2}, scaling positions by {}",
+                        format_time(price.time),
+                        change.equity,
+                        change.drawdown,
+                        change.peak,
+                        change.scale
+                    );
+                    self.position_sizer.set_risk_scale(change.scale);
+                    self.trade_standings();
+                }
+            }
+        }
+        // As for the drawdown scale, open positions follow a new volatility scale
+        if self.position_sizer.take_rescaled() {
+            self.trade_standings();
+        }
+
+        if !self.config.strategy.instruments.contains(&price.instrument) {
+            return Ok(());
+        }
+        self.fill_pending(price);
+        self.fill_resting(price);
+        self.regimes.tick(price);
+
+        if let Some(trailing_stops) = &mut self.trailing_stops {
+            let units = self.account.units(&price.instrument);
+            if let Some(exit_units) = trailing_stops.tick(price, units) {
+                let exit_units = exit_units - self.pending_units(&price.instrument);
+                self.order(
+                    price.time,
+                    &price.instrument,
+                    exit_units,
+                    "trailingStop",
+                    &[],
+                );
+            }
+        }
+
+        let ticked = self.strategy.tick(price)?;
+        let resolved = match ticked.or_else(|| self.warm_start(price)) {
+            Some(resolved) => {
+                self.warm_started.remove(&price.instrument);
+                self.working.resolved(&resolved, price);
+                resolved
+            }
+            None => match self.working.update(price, self.strategy.policy()) {
+                Some(WorkingUpdate::Retry(resolved)) => resolved,
+                Some(WorkingUpdate::Expired(target, reason)) => {
+                    self.expire(price.time, &target.instrument, &reason);
+                    return Ok(());
+                }
+                None => return Ok(()),
+            },
+        };
+        self.trade(price, resolved);
+        Ok(())
+    }
+
+    // The checkpoint's standing signal for the instrument on its first tradeable price after a
+    // warm start, None once it's been traded or while the strategies are flat
+    fn warm_start(&mut self, price: &Price) -> Option<ResolvedSignal> {
+        if !price.is_tradeable() || !self.warm_started.remove(&price.instrument) {
+            return None;
+        }
+        self.strategy.standing(&price.instrument)
+    }
+
+    // Trade the standing signal of every instrument traded or held at its latest price, e.g. to
+    // resize positions for a new scale. Instruments that are flat and should be are left alone.
+    fn trade_standings(&mut self) {
+        let mut instruments = self.config.strategy.instruments.clone();
+        for instrument in self.account.positions().keys() {
+            if !instruments.contains(instrument) {
+                instruments.push(instrument.clone());
+            }
+        }
+        for instrument in instruments {
+            self.trade_standing(&instrument);
+        }
+    }
+
+    // Trade the instrument's standing signal at its latest price, unless it's flat and should be
+    fn trade_standing(&mut self, instrument: &str) {
+        let price = match self.account.price(instrument) {
+            Some(price) => price.clone(),
+            None => return,
+        };
+        let resolved = self.strategy.restate(instrument);
+        let held = self.account.units(instrument) + self.pending_units(instrument);
+        if resolved.signal.forecast == 0.0 && held == 0.0 {
+            return;
+        }
+        self.working.resolved(&resolved, &price);
+        self.trade(&price, resolved);
+    }
+
+    // Move the signal's instrument towards its target position from this price of it
+    fn trade(&mut self, price: &Price, resolved: ResolvedSignal) {
+        let signal = resolved.signal;
+        // A new signal replaces the orders still resting for the last one
+        self.cancel_resting(price.time, &signal.instrument, "replaced");
+        let target = match self
+            .position_sizer
+            .target(&signal.instrument, signal.forecast)
+        {
+            Some(target) => target,
+            None => {
+                log::warn!(
+                    "[{}] Can't size a position without conversion rates yet, ignoring signal",
+                    signal.instrument
+                );
+                return;
+            }
+        };
+        // Orders still in flight count towards the position, as they will by the time this fills
+        let current_units =
+            self.account.units(&signal.instrument) + self.pending_units(&signal.instrument);
+        let tolerance = &self.config.strategy.target_tolerance;
+        if tolerance.within(current_units, target) {
+            self.working.reached(&signal.instrument);
+        }
+        let desired_units = self
+            .target_smoother
+            .smooth(&signal.instrument, current_units, target);
+        if tolerance.within(current_units, desired_units) {
+            return;
+        }
+        let required_units = desired_units - current_units;
+        // Posted if passive execution applies, otherwise placed as a resting order if configured
+        let passive = self
+            .config
+            .strategy
+            .passive_execution
+            .clone()
+            .filter(|passive| passive.applies(required_units));
+        let order = match (passive, self.config.resting_orders.clone()) {
+            (Some(passive), _) => RestingOrder {
+                strategies: resolved.strategies.clone(),
+                forecast: signal.forecast,
+                ..passive.order(
+                    &signal.instrument,
+                    required_units,
+                    price.bid as f64,
+                    price.ask as f64,
+                    self.active_after(price.time),
+                )
+            },
+            (None, Some(resting)) => resting.order(
+                price,
+                required_units,
+                self.active_after(price.time),
+                "signal",
+                &resolved.strategies,
+            ),
+            (None, None) => {
+                self.order(
+                    price.time,
+                    &signal.instrument,
+                    required_units,
+                    "signal",
+                    &resolved.strategies,
+                );
+                return;
+            }
+        };
+        self.resting.push(order);
+    }
+
+    // When an order placed at `time` starts working, once it has waited out any latency
+    fn active_after(&mut self, time: u64) -> u64 {
+        match &self.config.latency {
+            Some(latency) => time + latency.sample(&mut self.rng),
+            None => time,
+        }
+    }
+
+    // A working signal ran out of validity, so the orders for it still waiting out their latency
+    // are cancelled rather than filled
+    fn expire(&mut self, time: u64, instrument: &str, reason: &str) {
+        let before = self.pending.len();
+        self.pending
+            .retain(|order| order.instrument != instrument || order.reason != "signal");
+        log::info!(
+            "{} [{}] Signal expired ({}), cancelled {} orders waiting to fill",
+            format_time(time),
+            instrument,
+            reason,
+            before - self.pending.len()
+        );
+        self.cancel_resting(time, instrument, "expired signal");
+    }
+
+    // Cancel the resting orders of an instrument's signals
+    fn cancel_resting(&mut self, time: u64, instrument: &str, reason: &str) {
+        let (cancelled, resting): (Vec<RestingOrder>, Vec<RestingOrder>) =
+            std::mem::take(&mut self.resting)
+                .into_iter()
+                .partition(|order| order.instrument == instrument && order.reason == "signal");
+        self.resting = resting;
+        if cancelled.is_empty() {
+            return;
+        }
+        log::debug!(
+            "{} [{}] Cancelled {} resting orders ({})",
+            format_time(time),
+            instrument,
+            cancelled.len(),
+            reason
+        );
+        for order in cancelled {
+            self.unfilled(&order, PassiveOutcome::Cancelled);
+        }
+    }
+
+    // A resting order that ended without filling, a posted one with the outcome it had
+    fn unfilled(&mut self, order: &RestingOrder, outcome: PassiveOutcome) {
+        match &mut self.passive {
+            Some(passive) if order.passive => passive.record(order, outcome),
+            _ => self.unfilled_orders += 1,
+        }
+    }
+
+    // Fill the resting orders for this price's instrument that it reaches, and drop those whose
+    // time in force has run out. Posted orders fill at their own price, and what's left of them
+    // once they time out is sent at the market.
+    fn fill_resting(&mut self, price: &Price) {
+        let mut triggered = Vec::new();
+        let mut expired = Vec::new();
+        self.resting.retain(|order| {
+            if order.instrument != price.instrument {
+                return true;
+            }
+            match order.update(price) {
+                RestingUpdate::Waiting => true,
+                RestingUpdate::Triggered => {
+                    triggered.push(order.clone());
+                    false
+                }
+                RestingUpdate::Expired => {
+                    expired.push(order.clone());
+                    false
+                }
+            }
+        });
+
+        for order in expired {
+            if !order.passive {
+                self.unfilled(&order, PassiveOutcome::Cancelled);
+                continue;
+            }
+            self.unfilled(&order, PassiveOutcome::MarketFallback);
+            self.execute(
+                price.time,
+                &order.instrument,
+                order.remaining(),
+                "passiveFallback",
+                &order.strategies,
+            );
+        }
+        for mut order in triggered {
+            log::debug!(
+                "{} [{}] {:?} order for {} units at {} triggered",
+                format_time(price.time),
+                order.instrument,
+                order.kind,
+                order.units,
+                order.price
+            );
+            if !order.passive {
+                self.execute(
+                    price.time,
+                    &order.instrument,
+                    order.units,
+                    &order.reason,
+                    &order.strategies,
+                );
+                continue;
+            }
+            let fill = self.account.limit_fill(
+                &order.instrument,
+                order.remaining(),
+                order.price,
+                "passive",
+            );
+            let outcome = match fill {
+                Some(fill) => {
+                    order.filled = fill.units;
+                    self.record_fill(price.time, fill, &order.strategies);
+                    PassiveOutcome::Filled
+                }
+                None => PassiveOutcome::Cancelled,
+            };
+            if let Some(passive) = &mut self.passive {
+                passive.record(&order, outcome);
+            }
+        }
+    }
+
+    fn pending_units(&self, instrument: &str) -> f64 {
+        self.pending
+            .iter()
+            .filter(|order| order.instrument == instrument)
+            .fold(0.0, |units, order| units + order.units)
+    }
+
+    // Fill the orders for this price's instrument whose latency has passed
+    fn fill_pending(&mut self, price: &Price) {
+        let (due, pending): (Vec<PendingOrder>, Vec<PendingOrder>) =
+            std::mem::take(&mut self.pending)
+                .into_iter()
+                .partition(|order| {
+                    order.instrument == price.instrument && order.fill_after <= price.time
+                });
+        self.pending = pending;
+
+        for order in due {
+            self.execute(
+                price.time,
+                &order.instrument,
+                order.units,
+                &order.reason,
+                &order.strategies,
+            );
+        }
+    }
+
+    pub fn run<I: IntoIterator<Item = Price>>(
+        mut self,
+        prices: I,
+    ) -> Result<BacktestReport, Box<dyn std::error::Error>> {
+        let scenario = self.config.scenario.clone();
+        for price in prices {
+            let price = match &scenario {
+                Some(scenario) => match scenario.apply(price) {
+                    Some(price) => price,
+                    None => continue,
+                },
+                None => price,
+            };
+            self.tick(&price).with_context(|| {
+                let (year, week) = calendar::trading_week(price.time);
+                format!(
+                    "Backtest week {} on {} at {}",
+                    calendar::week_name(year, week),
+                    price.instrument,
+                    format_time(price.time)
+                )
+            })?;
+        }
+
+        if let Some(path) = &self.config.save_checkpoint {
+            self.checkpoint().save(path)?;
+        }
+        if let Some(path) = &self.config.save_state {
+            self.state().save(path)?;
+        }
+        Ok(self.finish())
+    }
+
+    // The strategies' state as of the last price, to continue from in another backtest or live
+    pub fn checkpoint(&self) -> StrategyCheckpoint {
+        self.strategy.checkpoint()
+    }
+
+    pub fn finish(mut self) -> BacktestReport {
+        // Always end on the final state of the account
+        if self.ticks > 0 {
+            let instrument = std::mem::take(&mut self.last_instrument);
+            self.record_row(self.last_time, &instrument);
+        }
+
+        let mut report = BacktestReport::new(self.config, self.fills, self.rows, self.ticks);
+        report.metrics.refused_orders = self.account.refused_orders;
+        report.metrics.unfilled_orders = self.unfilled_orders;
+        report.metrics.passive = self.passive;
+        report.regimes = RegimeBreakdown::calculate(&report.trades, &self.regimes.finish());
+        report
+    }
+
+    // Place an order, filled now or once its latency has passed
+    fn order(
+        &mut self,
+        time: u64,
+        instrument: &str,
+        units: f64,
+        reason: &str,
+        strategies: &[String],
+    ) {
+        if units == 0.0 {
+            return;
+        }
+        match &self.config.latency {
+            Some(latency) => {
+                let fill_after = time + latency.sample(&mut self.rng);
+                self.pending.push(PendingOrder {
+                    instrument: instrument.to_string(),
+                    units,
+                    reason: reason.to_string(),
+                    strategies: strategies.to_vec(),
+                    fill_after,
+                });
+            }
+            None => self.execute(time, instrument, units, reason, strategies),
+        }
+    }
+
+    fn execute(
+        &mut self,
+        time: u64,
+        instrument: &str,
+        units: f64,
+        reason: &str,
+        strategies: &[String],
+    ) {
+        if let Some(fill) = self.account.market_order(instrument, units, reason) {
+            self.record_fill(time, fill, strategies);
+        }
+    }
+
+    fn record_fill(&mut self, time: u64, fill: Fill, strategies: &[String]) {
+        let instrument = fill.instrument.clone();
+        log::debug!(
+            "[{}] Filled {} units at {} ({})",
+            fill.instrument,
+            fill.units,
+            fill.price,
+            fill.reason
+        );
+        self.fills.push(fill);
+
+        if let Some(circuit_breaker) = &mut self.circuit_breaker {
+            if !strategies.is_empty() {
+                let trip = circuit_breaker.record_order(time, &instrument, strategies);
+                for name in trip.iter().flat_map(|trip| &trip.strategies) {
+                    let withdrawn = self.strategy.halt(name);
+                    self.restating.extend(withdrawn);
+                }
+            }
+        }
+    }
+
+    fn record_row(&mut self, time: u64, instrument: &str) {
+        self.rows.push(BacktestRow {
+            time,
+            balance: self.account.balance,
+            nav: self.account.nav(),
+            exposure: self.account.exposure(),
+            margin_used: self.account.margin_used(),
+            margin_utilization: self.account.margin_utilization(),
+            instrument: instrument.to_string(),
+            mid: self
+                .account
+                .price(instrument)
+                .map(|price| (price.bid as f64 + price.ask as f64) / 2.0),
+        });
+    }
+}
@@ -0,0 +1,514 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::backtest::account::quote_currency;
+use crate::backtest::SimulatedPosition;
+use crate::engine::format_time;
+use crate::fx::{split_instrument, Converter};
+use crate::journal::JournalEntry;
+use crate::oanda::objects::{Price, Transaction};
+
+// A change of position recorded in the journal, by one of the trader's orders or from outside
+#[derive(Debug, Clone)]
+pub struct JournalFill {
+    pub time: u64,
+    pub instrument: String,
+    pub units: f64,
+    pub price: Option<f64>,
+
+    // Profit OANDA says the fill realized, in the account currency
+    pub pl: Option<f64>,
+
+    pub transaction_id: Option<String>,
+    pub external: bool,
+}
+
+impl JournalFill {
+    // Every fill in a journal, oldest first
+    pub fn from_journal(entries: &[JournalEntry]) -> Result<Vec<Self>, Box<dyn std::error::Error>> {
+        let mut fills = Vec::new();
+        for entry in entries {
+            match entry {
+                JournalEntry::Order {
+                    time,
+                    instrument,
+                    units,
+                    price,
+                    transaction_id,
+                    pl,
+                    ..
+                } => fills.push(JournalFill {
+                    time: parse_journal_time(time)?,
+                    instrument: instrument.clone(),
+                    units: *units,
+                    price: *price,
+                    pl: *pl,
+                    transaction_id: transaction_id.clone(),
+                    external: false,
+                }),
+                JournalEntry::External {
+                    time,
+                    instrument,
+                    units,
+                    transaction_id,
+                    ..
+                } => fills.push(JournalFill {
+                    time: parse_journal_time(time)?,
+                    instrument: instrument.clone(),
+                    units: *units,
+                    price: None,
+                    pl: None,
+                    transaction_id: transaction_id.clone(),
+                    external: true,
+                }),
+                _ => {}
+            }
+        }
+        fills.sort_by_key(|fill| fill.time);
+        Ok(fills)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PositionSnapshot {
+    pub instrument: String,
+    pub units: f64,
+
+    #[serde(rename = "averagePrice")]
+    pub average_price: f64,
+
+    // The latest recorded price of the instrument, None if there's none yet
+    #[serde(default)]
+    pub bid: Option<f64>,
+
+    #[serde(default)]
+    pub ask: Option<f64>,
+
+    // Profit from closing the position at that price, in the account currency
+    #[serde(default)]
+    #[serde(rename = "unrealizedPl")]
+    pub unrealized_pl: Option<f64>,
+
+    // What the position is worth at that price, in the account currency
+    #[serde(default)]
+    pub notional: Option<f64>,
+}
+
+impl PositionSnapshot {
+    // A position held before the journal starts, e.g. from an earlier snapshot
+    pub fn held(instrument: &str, units: f64, average_price: f64) -> Self {
+        PositionSnapshot {
+            instrument: instrument.to_string(),
+            units,
+            average_price,
+            bid: None,
+            ask: None,
+            unrealized_pl: None,
+            notional: None,
+        }
+    }
+}
+
+// The account as the journal and the recorded prices have it at a moment. The balance is the
+// starting balance plus the profit realized by fills since, so it leaves out financing and
+// anything else OANDA charges or pays outside of fills.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct AccountSnapshot {
+    pub time: String,
+    pub balance: f64,
+
+    #[serde(rename = "realizedPl")]
+    pub realized_pl: f64,
+
+    // Of the positions that have a price, see `unpriced`
+    #[serde(rename = "unrealizedPl")]
+    pub unrealized_pl: f64,
+
+    pub nav: f64,
+
+    // Total notional held across all instruments, in the account currency, of the positions
+    // that can be valued, see `unpriced`
+    pub exposure: f64,
+
+    pub positions: Vec<PositionSnapshot>,
+
+    // Instruments held without a recorded price or a rate into the account currency to value
+    // them at, so NAV and exposure leave them out
+    pub unpriced: Vec<String>,
+
+    // Realized profit by the currency it's in, where there's been no rate into the account
+    // currency yet, left out of the balance until there is
+    #[serde(default)]
+    pub unconverted: BTreeMap<String, f64>,
+
+    // Fills up to the moment, and those without a price in the journal or recorded data, which
+    // are assumed to realize nothing
+    pub fills: usize,
+
+    #[serde(rename = "unpricedFills")]
+    pub unpriced_fills: usize,
+}
+
+// Replays a journal's fills and the recorded prices in time order to rebuild the account at any
+// moment, e.g. what was held during a spike. Fills are priced as journaled, and external fills,
+// which aren't, at the recorded price they'd have crossed. Realized profit is OANDA's where the
+// journal has it, and otherwise worked out like the simulated account does.
+pub struct Reconstruction {
+    currency: String,
+    initial_balance: f64,
+    realized_pl: f64,
+    unconverted: BTreeMap<String, f64>,
+    positions: BTreeMap<String, SimulatedPosition>,
+    prices: HashMap<String, Price>,
+    converter: Converter,
+    fills: usize,
+    unpriced_fills: usize,
+}
+
+impl Reconstruction {
+    pub fn new(currency: &str, initial_balance: f64) -> Self {
+        Reconstruction {
+            currency: currency.to_string(),
+            initial_balance,
+            realized_pl: 0.0,
+            unconverted: BTreeMap::new(),
+            positions: BTreeMap::new(),
+            prices: HashMap::new(),
+            converter: Converter::new(),
+            fills: 0,
+            unpriced_fills: 0,
+        }
+    }
+
+    // Start from positions already held when the journal starts, which its fills would
+    // otherwise miss
+    pub fn with_positions(mut self, positions: &[PositionSnapshot]) -> Self {
+        for held in positions.iter().filter(|p| p.units != 0.0) {
+            self.positions.insert(
+                held.instrument.clone(),
+                SimulatedPosition {
+                    units: held.units,
+                    average_price: held.average_price,
+                },
+            );
+        }
+        self
+    }
+
+    pub fn update_price(&mut self, price: &Price) {
+        self.converter.update(price);
+        self.prices.insert(price.instrument.clone(), price.clone());
+
+        // Book profit held for a rate now that there may be one
+        let converter = &self.converter;
+        let currency = &self.currency;
+        let mut converted = 0.0;
+        self.unconverted.retain(
+            |from, amount| match converter.convert(*amount, from, currency) {
+                Some(amount) => {
+                    converted += amount;
+                    false
+                }
+                None => true,
+            },
+        );
+        self.realized_pl += converted;
+    }
+
+    pub fn apply(&mut self, fill: &JournalFill) {
+        self.fills += 1;
+        let recorded = self.prices.get(&fill.instrument).map(|price| {
+            if fill.units > 0.0 {
+                price.ask as f64
+            } else {
+                price.bid as f64
+            }
+        });
+        let position = self.positions.entry(fill.instrument.clone()).or_default();
+        let price = match fill.price.or(recorded) {
+            Some(price) => price,
+            None => {
+                self.unpriced_fills += 1;
+                position.average_price
+            }
+        };
+
+        let realized_pl = position.fill(fill.units, price);
+        if position.units == 0.0 {
+            self.positions.remove(&fill.instrument);
+        }
+        match fill.pl {
+            Some(pl) => self.realized_pl += pl,
+            None => match self.to_account_currency(&fill.instrument, realized_pl) {
+                Some(pl) => self.realized_pl += pl,
+                None => {
+                    let quote = quote_currency(&fill.instrument).to_string();
+                    *self.unconverted.entry(quote).or_insert(0.0) += realized_pl;
+                }
+            },
+        }
+    }
+
+    pub fn snapshot(&self, time: u64) -> AccountSnapshot {
+        let mut positions = Vec::new();
+        let mut unpriced = Vec::new();
+        for (instrument, position) in &self.positions {
+            let price = self.prices.get(instrument);
+            let unrealized_pl = price.and_then(|price| {
+                let exit_price = if position.units > 0.0 {
+                    price.bid
+                } else {
+                    price.ask
+                } as f64;
+                let pl = position.units * (exit_price - position.average_price);
+                self.to_account_currency(instrument, pl)
+            });
+            let notional = split_instrument(instrument).and_then(|(base, _)| {
+                self.converter
+                    .convert(position.units.abs(), base, &self.currency)
+            });
+            if unrealized_pl.is_none() || notional.is_none() {
+                unpriced.push(instrument.clone());
+            }
+            positions.push(PositionSnapshot {
+                instrument: instrument.clone(),
+                units: position.units,
+                average_price: position.average_price,
+                bid: price.map(|price| price.bid as f64),
+                ask: price.map(|price| price.ask as f64),
+                unrealized_pl,
+                notional,
+            });
+        }
+
+        let balance = self.initial_balance + self.realized_pl;
+        let unrealized_pl: f64 = positions.iter().filter_map(|p| p.unrealized_pl).sum();
+        AccountSnapshot {
+            time: format_time(time),
+            balance,
+            realized_pl: self.realized_pl,
+            unrealized_pl,
+            nav: balance + unrealized_pl,
+            exposure: positions.iter().filter_map(|p| p.notional).sum(),
+            positions,
+            unpriced,
+            unconverted: self.unconverted.clone(),
+            fills: self.fills,
+            unpriced_fills: self.unpriced_fills,
+        }
+    }
+
+    // Convert an amount in the instrument's quote currency into the account currency, None
+    // while there's no rate to convert it at
+    fn to_account_currency(&self, instrument: &str, amount: f64) -> Option<f64> {
+        self.converter
+            .convert(amount, quote_currency(instrument), &self.currency)
+    }
+}
+
+// The account at each of `times` (milliseconds since the epoch), replaying `fills` and `prices`,
+// both oldest first, from the `start` positions held before the first fill. A price and a fill at
+// the same millisecond are taken in that order.
+pub fn reconstruct<I>(
+    fills: &[JournalFill],
+    prices: I,
+    times: &[u64],
+    currency: &str,
+    initial_balance: f64,
+    start: &[PositionSnapshot],
+) -> Vec<AccountSnapshot>
+where
+    I: IntoIterator<Item = Price>,
+{
+    let mut times = times.to_vec();
+    times.sort_unstable();
+
+    let mut reconstruction = Reconstruction::new(currency, initial_balance).with_positions(start);
+    let mut prices = prices.into_iter().peekable();
+    let mut fills = fills.iter().peekable();
+    let mut snapshots = Vec::with_capacity(times.len());
+    for time in times {
+        loop {
+            let next_fill = fills.peek().map(|fill| fill.time).filter(|t| *t <= time);
+            let next_price = prices.peek().map(|price| price.time).filter(|t| *t <= time);
+            let price_first = match (next_fill, next_price) {
+                (None, None) => break,
+                (Some(fill), Some(price)) => price <= fill,
+                (None, Some(_)) => true,
+                (Some(_), None) => false,
+            };
+            if price_first {
+                if let Some(price) = prices.next() {
+                    reconstruction.update_price(&price);
+                }
+            } else if let Some(fill) = fills.next() {
+                reconstruction.apply(fill);
+            }
+        }
+        snapshots.push(reconstruction.snapshot(time));
+    }
+    snapshots
+}
+
+// OANDA's transactions of the account, from a saved response of its transactions endpoints
+// ({"transactions": [...]}) or a plain list of them
+pub fn load_statement<P: AsRef<Path>>(
+    path: P,
+) -> Result<Vec<Transaction>, Box<dyn std::error::Error>> {
+    let value: serde_json::Value = serde_json::from_str(&std::fs::read_to_string(path)?)?;
+    let transactions = match value {
+        serde_json::Value::Object(mut object) => object
+            .remove("transactions")
+            .ok_or("No transactions in the statement")?,
+        value => value,
+    };
+    // Amounts are strings parsed without copying them, which needs them read from text
+    Ok(serde_json::from_str(&transactions.to_string())?)
+}
+
+// The positions held before `before` (milliseconds since the epoch) by the ORDER_FILLs of an
+// OANDA statement, to start a reconstruction from when the statement goes back further than the
+// journal
+pub fn statement_positions(
+    statement: &[Transaction],
+    before: u64,
+) -> Result<Vec<PositionSnapshot>, Box<dyn std::error::Error>> {
+    let mut order_fills = Vec::new();
+    for transaction in statement.iter().filter(|t| t.is_order_fill()) {
+        let time = parse_journal_time(&transaction.time)?;
+        if time < before {
+            order_fills.push((time, transaction));
+        }
+    }
+    order_fills.sort_by_key(|(time, _)| *time);
+
+    let mut positions: BTreeMap<String, SimulatedPosition> = BTreeMap::new();
+    for (_, transaction) in order_fills {
+        let (instrument, units) = match (&transaction.instrument, transaction.units) {
+            (Some(instrument), Some(units)) => (instrument, units),
+            _ => continue,
+        };
+        let position = positions.entry(instrument.clone()).or_default();
+        let price = transaction.price.unwrap_or(position.average_price);
+        position.fill(units, price);
+    }
+    Ok(positions
+        .into_iter()
+        .filter(|(_, position)| position.units != 0.0)
+        .map(|(instrument, p)| PositionSnapshot::held(&instrument, p.units, p.average_price))
+        .collect())
+}
+
+// Positions saved as an account snapshot ({"positions": [...]}, e.g. one printed by an earlier
+// reconstruction) or a plain list of them
+pub fn load_positions<P: AsRef<Path>>(
+    path: P,
+) -> Result<Vec<PositionSnapshot>, Box<dyn std::error::Error>> {
+    let value: serde_json::Value = serde_json::from_str(&std::fs::read_to_string(path)?)?;
+    let positions = match value {
+        serde_json::Value::Object(mut object) => object
+            .remove("positions")
+            .ok_or("No positions in the snapshot")?,
+        value => value,
+    };
+    Ok(serde_json::from_value(positions)?)
+}
+
+// Where the journal and OANDA's record of a fill disagree
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct StatementMismatch {
+    #[serde(rename = "transactionId")]
+    pub transaction_id: String,
+
+    pub time: String,
+    pub instrument: String,
+    pub problem: String,
+}
+
+// Compare the journal's fills with the ORDER_FILLs of an OANDA statement by transaction ID.
+// Journal fills without an ID (paper trading) or outside the period the statement covers aren't
+// compared.
+pub fn compare_with_statement(
+    fills: &[JournalFill],
+    statement: &[Transaction],
+) -> Result<Vec<StatementMismatch>, Box<dyn std::error::Error>> {
+    let mut order_fills = Vec::new();
+    for transaction in statement.iter().filter(|t| t.is_order_fill()) {
+        order_fills.push((parse_journal_time(&transaction.time)?, transaction));
+    }
+    let from = order_fills.iter().map(|(time, _)| *time).min();
+    let to = order_fills.iter().map(|(time, _)| *time).max();
+
+    let journaled: HashMap<&str, &JournalFill> = fills
+        .iter()
+        .filter_map(|fill| Some((fill.transaction_id.as_deref()?, fill)))
+        .collect();
+    let mut mismatches = Vec::new();
+    let mut seen: HashSet<String> = HashSet::new();
+    for (time, transaction) in &order_fills {
+        let id = transaction.id.clone().unwrap_or_default();
+        let instrument = transaction.instrument.clone().unwrap_or_default();
+        let mismatch = |problem: String| StatementMismatch {
+            transaction_id: id.clone(),
+            time: format_time(*time),
+            instrument: instrument.clone(),
+            problem,
+        };
+        let fill = match journaled.get(id.as_str()) {
+            Some(fill) => fill,
+            None => {
+                mismatches.push(mismatch("not in the journal".to_string()));
+                continue;
+            }
+        };
+        seen.insert(id.clone());
+
+        let units = transaction.units.unwrap_or(0.0);
+        if fill.instrument != instrument {
+            mismatches.push(mismatch(format!("journaled for {}", fill.instrument)));
+        }
+        if fill.units != units {
+            mismatches.push(mismatch(format!(
+                "{} units, journaled {}",
+                units, fill.units
+            )));
+        }
+        if let (Some(price), Some(journaled)) = (transaction.price, fill.price) {
+            if (price - journaled).abs() > 1e-9 * price.abs() {
+                mismatches.push(mismatch(format!(
+                    "price {}, journaled {}",
+                    price, journaled
+                )));
+            }
+        }
+        if let (Some(pl), Some(journaled)) = (transaction.pl, fill.pl) {
+            if (pl - journaled).abs() >= 0.01 {
+                mismatches.push(mismatch(format!("pl {}, journaled {}", pl, journaled)));
+            }
+        }
+    }
+
+    if let (Some(from), Some(to)) = (from, to) {
+        for fill in fills {
+            let id = match &fill.transaction_id {
+                Some(id) => id,
+                None => continue,
+            };
+            if fill.time >= from && fill.time <= to && !seen.contains(id) {
+                mismatches.push(StatementMismatch {
+                    transaction_id: id.clone(),
+                    time: format_time(fill.time),
+                    instrument: fill.instrument.clone(),
+                    problem: "not in the statement".to_string(),
+                });
+            }
+        }
+    }
+    Ok(mismatches)
+}
+
+fn parse_journal_time(time: &str) -> Result<u64, Box<dyn std::error::Error>> {
+    Ok(chrono::DateTime::parse_from_rfc3339(time)?.timestamp_millis() as u64)
+}
@@ -0,0 +1,309 @@
+use std::collections::{BTreeMap, HashMap};
+
+use serde::{Deserialize, Serialize};
+
+use crate::backtest::{Trade, TradeStatistics};
+use crate::models::PriceBasis;
+use crate::oanda::objects::Price;
+
+// Periods are labelled from bars of the strategy's price basis. A bar is trending if its ADX is
+// at least the threshold, and its volatility (ATR relative to price) is bucketed into terciles
+// over all bars of the same instrument.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RegimeConfig {
+    #[serde(default = "default_bar_seconds")]
+    #[serde(rename = "barSeconds")]
+    pub bar_seconds: u64,
+
+    #[serde(default = "default_period")]
+    pub period: usize,
+
+    #[serde(default = "default_adx_threshold")]
+    #[serde(rename = "adxThreshold")]
+    pub adx_threshold: f64,
+}
+
+fn default_bar_seconds() -> u64 {
+    3600
+}
+
+fn default_period() -> usize {
+    14
+}
+
+fn default_adx_threshold() -> f64 {
+    25.0
+}
+
+impl Default for RegimeConfig {
+    fn default() -> Self {
+        RegimeConfig {
+            bar_seconds: default_bar_seconds(),
+            period: default_period(),
+            adx_threshold: default_adx_threshold(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "camelCase")]
+pub enum Trend {
+    Trending,
+    Ranging,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "camelCase")]
+pub enum Volatility {
+    Low,
+    Medium,
+    High,
+}
+
+// A single bar of an instrument and the regime it was in
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RegimePeriod {
+    pub instrument: String,
+    pub start: u64,
+    pub end: u64,
+    pub adx: f64,
+
+    // Average true range as a fraction of the closing price
+    pub volatility: f64,
+
+    pub trend: Trend,
+    #[serde(rename = "volatilityBucket")]
+    pub volatility_bucket: Volatility,
+}
+
+// Wilder smoothing, a simple average until `period` values have been seen
+#[derive(Default)]
+struct Smoothed {
+    value: f64,
+    count: usize,
+}
+
+impl Smoothed {
+    fn update(&mut self, x: f64, period: usize) -> Option<f64> {
+        self.count += 1;
+        let n = self.count.min(period) as f64;
+        self.value = (self.value * (n - 1.0) + x) / n;
+        (self.count >= period).then_some(self.value)
+    }
+}
+
+// Bars of a single instrument along with the indicators calculated from them
+#[derive(Default)]
+struct InstrumentBars {
+    start: u64,
+    high: f64,
+    low: f64,
+    close: f64,
+    open: bool,
+
+    previous: Option<(f64, f64, f64)>,
+    true_range: Smoothed,
+    plus_dm: Smoothed,
+    minus_dm: Smoothed,
+    adx: Smoothed,
+
+    // (start, end, adx, volatility) of every bar with a full set of indicators
+    bars: Vec<(u64, u64, f64, f64)>,
+}
+
+impl InstrumentBars {
+    fn close_bar(&mut self, end: u64, period: usize) {
+        let (high, low, close) = (self.high, self.low, self.close);
+        if let Some((previous_high, previous_low, previous_close)) = self.previous {
+            let up = high - previous_high;
+            let down = previous_low - low;
+            let plus_dm = if up > down && up > 0.0 { up } else { 0.0 };
+            let minus_dm = if down > up && down > 0.0 { down } else { 0.0 };
+            let true_range = (high - low)
+                .max((high - previous_close).abs())
+                .max((low - previous_close).abs());
+
+            let true_range = self.true_range.update(true_range, period);
+            let plus_dm = self.plus_dm.update(plus_dm, period);
+            let minus_dm = self.minus_dm.update(minus_dm, period);
+            if let (Some(true_range), Some(plus_dm), Some(minus_dm)) =
+                (true_range, plus_dm, minus_dm)
+            {
+                if true_range > 0.0 {
+                    let plus_di = 100.0 * plus_dm / true_range;
+                    let minus_di = 100.0 * minus_dm / true_range;
+                    let dx = if plus_di + minus_di > 0.0 {
+                        100.0 * (plus_di - minus_di).abs() / (plus_di + minus_di)
+                    } else {
+                        0.0
+                    };
+                    if let Some(adx) = self.adx.update(dx, period) {
+                        self.bars.push((self.start, end, adx, true_range / close));
+                    }
+                }
+            }
+        }
+        self.previous = Some((high, low, close));
+        self.open = false;
+    }
+}
+
+// Labels historical prices by regime, fed one price at a time like the backtester
+pub struct RegimeLabeler {
+    config: RegimeConfig,
+    price_basis: PriceBasis,
+    instruments: HashMap<String, InstrumentBars>,
+}
+
+impl RegimeLabeler {
+    pub fn new(config: RegimeConfig) -> Self {
+        RegimeLabeler {
+            config,
+            price_basis: PriceBasis::default(),
+            instruments: HashMap::new(),
+        }
+    }
+
+    // Bars are built from the same side of the quote as the strategy being backtested
+    pub fn with_price_basis(mut self, price_basis: PriceBasis) -> Self {
+        self.price_basis = price_basis;
+        self
+    }
+
+    pub fn tick(&mut self, price: &Price) {
+        let bar_length = self.config.bar_seconds.max(1) * 1000;
+        let bar_start = price.time - price.time % bar_length;
+        let value = self.price_basis.value(price);
+
+        let bars = self
+            .instruments
+            .entry(price.instrument.clone())
+            .or_default();
+        if bars.open && bar_start != bars.start {
+            bars.close_bar(bars.start + bar_length, self.config.period);
+        }
+
+        if !bars.open {
+            bars.start = bar_start;
+            bars.high = value;
+            bars.low = value;
+            bars.open = true;
+        }
+        bars.high = bars.high.max(value);
+        bars.low = bars.low.min(value);
+        bars.close = value;
+    }
+
+    // Label every completed bar. Volatility terciles are taken over the whole history,
+    // so labels are for analysis only and not something a strategy could have known at the time.
+    pub fn finish(self) -> Regimes {
+        let mut periods = HashMap::new();
+        for (instrument, bars) in self.instruments {
+            let mut volatilities: Vec<f64> = bars.bars.iter().map(|bar| bar.3).collect();
+            volatilities.sort_by(|a, b| a.partial_cmp(b).unwrap());
+            let tercile = |fraction: f64| {
+                let index = (volatilities.len() as f64 * fraction) as usize;
+                volatilities
+                    .get(index.min(volatilities.len().saturating_sub(1)))
+                    .copied()
+                    .unwrap_or(0.0)
+            };
+            let (lower, upper) = (tercile(1.0 / 3.0), tercile(2.0 / 3.0));
+
+            let instrument_periods = bars
+                .bars
+                .iter()
+                .map(|(start, end, adx, volatility)| RegimePeriod {
+                    instrument: instrument.clone(),
+                    start: *start,
+                    end: *end,
+                    adx: *adx,
+                    volatility: *volatility,
+                    trend: if *adx >= self.config.adx_threshold {
+                        Trend::Trending
+                    } else {
+                        Trend::Ranging
+                    },
+                    volatility_bucket: if *volatility < lower {
+                        Volatility::Low
+                    } else if *volatility < upper {
+                        Volatility::Medium
+                    } else {
+                        Volatility::High
+                    },
+                })
+                .collect();
+            periods.insert(instrument, instrument_periods);
+        }
+        Regimes { periods }
+    }
+}
+
+pub struct Regimes {
+    periods: HashMap<String, Vec<RegimePeriod>>,
+}
+
+impl Regimes {
+    // The period an instrument was in at the given time, None during the indicator warm up
+    pub fn at(&self, instrument: &str, time: u64) -> Option<&RegimePeriod> {
+        let periods = self.periods.get(instrument)?;
+        let index = periods.partition_point(|period| period.end <= time);
+        periods.get(index).filter(|period| period.start <= time)
+    }
+
+    // Every labelled period, ordered by instrument and time
+    pub fn periods(&self) -> Vec<&RegimePeriod> {
+        let mut instruments: Vec<&String> = self.periods.keys().collect();
+        instruments.sort();
+        instruments
+            .into_iter()
+            .flat_map(|instrument| self.periods[instrument].iter())
+            .collect()
+    }
+}
+
+// Trade statistics split by the regime each trade was entered in
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct RegimeBreakdown {
+    pub trend: BTreeMap<Trend, TradeStatistics>,
+    pub volatility: BTreeMap<Volatility, TradeStatistics>,
+
+    // Trades entered before the regime could be determined
+    pub unlabelled: usize,
+}
+
+impl RegimeBreakdown {
+    pub fn calculate(trades: &[Trade], regimes: &Regimes) -> Self {
+        let mut by_trend: BTreeMap<Trend, Vec<Trade>> = BTreeMap::new();
+        let mut by_volatility: BTreeMap<Volatility, Vec<Trade>> = BTreeMap::new();
+        let mut unlabelled = 0;
+
+        for trade in trades {
+            match regimes.at(&trade.instrument, trade.entry_time) {
+                Some(period) => {
+                    by_trend
+                        .entry(period.trend)
+                        .or_default()
+                        .push(trade.clone());
+                    by_volatility
+                        .entry(period.volatility_bucket)
+                        .or_default()
+                        .push(trade.clone());
+                }
+                None => unlabelled += 1,
+            }
+        }
+
+        RegimeBreakdown {
+            trend: by_trend
+                .into_iter()
+                .map(|(trend, trades)| (trend, TradeStatistics::calculate(&trades)))
+                .collect(),
+            volatility: by_volatility
+                .into_iter()
+                .map(|(bucket, trades)| (bucket, TradeStatistics::calculate(&trades)))
+                .collect(),
+            unlabelled,
+        }
+    }
+}
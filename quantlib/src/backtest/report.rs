@@ -0,0 +1,269 @@
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Write};
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::backtest::{
+    reconstruct_trades, BacktestConfig, Fill, RegimeBreakdown, Trade, TradeStatistics,
+};
+use crate::models::PassiveStats;
+
+// Version of the JSON report format. Adding fields is backwards compatible, anything else
+// (renaming, removing or changing the meaning of a field) must bump the version.
+pub const REPORT_SCHEMA_VERSION: u32 = 1;
+
+// JSON Schema describing the report, for tools consuming the JSON output
+pub const REPORT_SCHEMA: &str = include_str!("report.schema.json");
+
+// The state of the account at a point in the backtest
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct BacktestRow {
+    pub time: u64,
+    pub balance: f64,
+    pub nav: f64,
+    pub exposure: f64,
+
+    // Margin held in the account currency and as a fraction of NAV, 0 without a margin config
+    #[serde(default)]
+    #[serde(rename = "marginUsed")]
+    pub margin_used: f64,
+
+    #[serde(default)]
+    #[serde(rename = "marginUtilization")]
+    pub margin_utilization: f64,
+
+    // The instrument whose price the row was recorded on and its mid, to draw fills over, see
+    // BacktestReport::to_html
+    #[serde(default)]
+    pub instrument: String,
+
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mid: Option<f64>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct BacktestMetrics {
+    pub ticks: u64,
+    pub fills: usize,
+
+    #[serde(rename = "initialBalance")]
+    pub initial_balance: f64,
+
+    #[serde(rename = "finalNav")]
+    pub final_nav: f64,
+
+    // Fractional change in NAV over the whole backtest, 0.1 = 10%
+    #[serde(rename = "totalReturn")]
+    pub total_return: f64,
+
+    // Largest fractional fall in NAV from a previous high
+    #[serde(rename = "maxDrawdown")]
+    pub max_drawdown: f64,
+
+    // Annualized from the returns between rows, None if NAV never changed
+    #[serde(rename = "sharpeRatio")]
+    pub sharpe_ratio: Option<f64>,
+
+    // Total paid in spread over all fills, already included in the NAV
+    #[serde(default)]
+    #[serde(rename = "spreadCost")]
+    pub spread_cost: f64,
+
+    #[serde(default)]
+    #[serde(rename = "maxMarginUtilization")]
+    pub max_margin_utilization: f64,
+
+    // Orders refused for using more margin than the strategy's margin config allows
+    #[serde(default)]
+    #[serde(rename = "refusedOrders")]
+    pub refused_orders: u64,
+
+    // Resting limit and stop orders replaced or expired before they filled
+    #[serde(default)]
+    #[serde(rename = "unfilledOrders")]
+    pub unfilled_orders: u64,
+
+    // How the orders posted by the strategy's passive execution ended, None without it. What
+    // their fills saved on the spread is in spreadCost.
+    #[serde(default)]
+    pub passive: Option<PassiveStats>,
+
+    #[serde(default)]
+    pub trades: TradeStatistics,
+}
+
+impl BacktestMetrics {
+    pub fn calculate(
+        config: &BacktestConfig,
+        rows: &[BacktestRow],
+        fills: &[Fill],
+        trades: &[Trade],
+        ticks: u64,
+    ) -> Self {
+        let initial_balance = config.initial_balance;
+        let final_nav = rows.last().map_or(initial_balance, |row| row.nav);
+
+        let mut peak = initial_balance;
+        let mut max_drawdown: f64 = 0.0;
+        for row in rows {
+            peak = peak.max(row.nav);
+            if peak > 0.0 {
+                max_drawdown = max_drawdown.max((peak - row.nav) / peak);
+            }
+        }
+
+        let returns: Vec<f64> = rows
+            .windows(2)
+            .filter(|pair| pair[0].nav != 0.0)
+            .map(|pair| pair[1].nav / pair[0].nav - 1.0)
+            .collect();
+        let sharpe_ratio = if returns.len() > 1 {
+            let mean = returns.iter().sum::<f64>() / returns.len() as f64;
+            let variance = returns.iter().map(|r| (r - mean).powi(2)).sum::<f64>()
+                / (returns.len() - 1) as f64;
+
+            // Rows are sampled at most once per sample interval, over 252 trading days a year
+            let periods_per_year = 252.0 * 86_400_000.0 / config.sample_interval.max(1) as f64;
+            if variance > 0.0 {
+                Some(mean / variance.sqrt() * periods_per_year.sqrt())
+            } else {
+                None
+            }
+        } else {
+            None
+        };
+
+        BacktestMetrics {
+            ticks,
+            fills: fills.len(),
+            initial_balance,
+            final_nav,
+            total_return: final_nav / initial_balance - 1.0,
+            max_drawdown,
+            sharpe_ratio,
+            spread_cost: fills.iter().map(|fill| fill.spread_cost).sum(),
+            max_margin_utilization: rows
+                .iter()
+                .fold(0.0, |max, row| row.margin_utilization.max(max)),
+            refused_orders: 0,
+            unfilled_orders: 0,
+            passive: None,
+            trades: TradeStatistics::calculate(trades),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct BacktestReport {
+    #[serde(rename = "schemaVersion")]
+    pub schema_version: u32,
+    pub config: BacktestConfig,
+    pub metrics: BacktestMetrics,
+    pub fills: Vec<Fill>,
+
+    // Round trip trades closed during the backtest
+    #[serde(default)]
+    pub trades: Vec<Trade>,
+
+    // Trade statistics by the regime trades were entered in
+    #[serde(default)]
+    pub regimes: RegimeBreakdown,
+
+    pub rows: Vec<BacktestRow>,
+}
+
+impl BacktestReport {
+    pub fn new(
+        config: BacktestConfig,
+        fills: Vec<Fill>,
+        rows: Vec<BacktestRow>,
+        ticks: u64,
+    ) -> Self {
+        let trades = reconstruct_trades(&fills);
+        let metrics = BacktestMetrics::calculate(&config, &rows, &fills, &trades, ticks);
+        BacktestReport {
+            schema_version: REPORT_SCHEMA_VERSION,
+            config,
+            metrics,
+            fills,
+            trades,
+            regimes: RegimeBreakdown::default(),
+            rows,
+        }
+    }
+
+    // Save the rows as CSV, for plotting the equity curve
+    pub fn save_csv<P: AsRef<Path>>(&self, path: P) -> Result<(), Box<dyn std::error::Error>> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        writeln!(
+            writer,
+            "time,balance,nav,exposure,margin_used,margin_utilization"
+        )?;
+        for row in &self.rows {
+            writeln!(
+                writer,
+                "{},{},{},{},{},{}",
+                row.time,
+                row.balance,
+                row.nav,
+                row.exposure,
+                row.margin_used,
+                row.margin_utilization
+            )?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+
+    // Save the round trip trades as CSV
+    pub fn save_trades_csv<P: AsRef<Path>>(
+        &self,
+        path: P,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        writeln!(
+            writer,
+            "instrument,long,entry_time,entry_price,exit_time,exit_price,max_units,pl,duration"
+        )?;
+        for trade in &self.trades {
+            writeln!(
+                writer,
+                "{},{},{},{},{},{},{},{},{}",
+                trade.instrument,
+                trade.long,
+                trade.entry_time,
+                trade.entry_price,
+                trade.exit_time,
+                trade.exit_price,
+                trade.max_units,
+                trade.pl,
+                trade.duration
+            )?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+
+    // Save the full report as JSON, see REPORT_SCHEMA for the format
+    pub fn save_json<P: AsRef<Path>>(&self, path: P) -> Result<(), Box<dyn std::error::Error>> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        serde_json::to_writer_pretty(&mut writer, self)?;
+        writer.flush()?;
+        Ok(())
+    }
+
+    pub fn load_json<P: AsRef<Path>>(path: P) -> Result<Self, Box<dyn std::error::Error>> {
+        let reader = BufReader::new(File::open(path)?);
+        let report: BacktestReport = serde_json::from_reader(reader)?;
+        if report.schema_version > REPORT_SCHEMA_VERSION {
+            return Err(format!(
+                "Report has schema version {}, only versions up to {} are supported",
+                report.schema_version, REPORT_SCHEMA_VERSION
+            )
+            .into());
+        }
+        Ok(report)
+    }
+}
@@ -0,0 +1,164 @@
+use serde::{Deserialize, Serialize};
+
+use crate::broker::OrderTags;
+use crate::oanda::objects::Price;
+
+// Signals placed as resting limit or stop orders instead of market orders, "restingOrders" in
+// the backtest config, e.g. {"type": "limit", "offset": 0.0001, "timeInForce": "GTD",
+// "seconds": 60}. A buy limit rests `offset` below the bid and a sell limit above the ask, so
+// they fill once the other side of the market comes to them, and stops rest `offset` beyond
+// the side they'd fill on. An order fills at the first price whose ask (for buys) or bid (for
+// sells) reaches its level, at that price, as OANDA fills triggered orders at the market. Orders
+// rest until they fill, a new signal for the instrument replaces them or their time in force
+// runs out: GTC never, GTD after `seconds`, and IOC unless the first price they see fills them.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RestingOrderConfig {
+    #[serde(rename = "type")]
+    pub kind: RestingOrderType,
+
+    #[serde(default)]
+    pub offset: f64,
+
+    #[serde(default)]
+    #[serde(rename = "timeInForce")]
+    pub time_in_force: TimeInForce,
+
+    #[serde(default)]
+    pub seconds: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum RestingOrderType {
+    Limit,
+    Stop,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum TimeInForce {
+    #[default]
+    Gtc,
+    Gtd,
+    Ioc,
+}
+
+impl RestingOrderConfig {
+    // An order for `units` at the level this config puts it at from `price`, working from
+    // `active_after` once it has waited out any latency
+    pub(crate) fn order(
+        &self,
+        price: &Price,
+        units: f64,
+        active_after: u64,
+        reason: &str,
+        strategies: &[String],
+    ) -> RestingOrder {
+        let (bid, ask) = (price.bid as f64, price.ask as f64);
+        let level = match (self.kind, units > 0.0) {
+            (RestingOrderType::Limit, true) => bid - self.offset,
+            (RestingOrderType::Limit, false) => ask + self.offset,
+            (RestingOrderType::Stop, true) => ask + self.offset,
+            (RestingOrderType::Stop, false) => bid - self.offset,
+        };
+        let expires = match self.time_in_force {
+            TimeInForce::Gtd => Some(active_after + self.seconds.unwrap_or(0) * 1000),
+            TimeInForce::Gtc | TimeInForce::Ioc => None,
+        };
+        RestingOrder {
+            instrument: price.instrument.clone(),
+            units,
+            kind: self.kind,
+            price: level,
+            reason: reason.to_string(),
+            strategies: strategies.to_vec(),
+            active_after,
+            expires,
+            immediate: self.time_in_force == TimeInForce::Ioc,
+            passive: false,
+            order_id: None,
+            forecast: 0.0,
+            tags: OrderTags::default(),
+            filled: 0.0,
+        }
+    }
+}
+
+// A limit or stop order waiting for a fill, whether a backtest's resting order or one posted by
+// passive execution (see PassiveExecutionConfig) on paper, live or in a backtest
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RestingOrder {
+    pub instrument: String,
+    pub units: f64,
+    pub kind: RestingOrderType,
+    pub price: f64,
+    pub reason: String,
+    pub strategies: Vec<String>,
+
+    // When it was posted or, after a latency, starts working, in milliseconds
+    #[serde(rename = "activeAfter")]
+    pub active_after: u64,
+
+    #[serde(default)]
+    pub expires: Option<u64>,
+
+    // Cancelled if the first price it sees doesn't fill it
+    #[serde(default)]
+    pub immediate: bool,
+
+    // Posted by passive execution: it fills at its own price, and what's left of it is sent at
+    // the market once it expires rather than dropped
+    #[serde(default)]
+    pub passive: bool,
+
+    // The broker's ID of a posted order, paper and backtest orders have none
+    #[serde(default)]
+    #[serde(rename = "orderId")]
+    pub order_id: Option<String>,
+
+    // What it was posted for, for the journal once it ends
+    #[serde(default)]
+    pub forecast: f64,
+
+    #[serde(default)]
+    pub tags: OrderTags,
+
+    // Units filled at its price so far
+    #[serde(default)]
+    pub filled: f64,
+}
+
+// What became of a resting order at a price of its instrument
+#[derive(Debug, PartialEq, Eq)]
+pub enum RestingUpdate {
+    Waiting,
+    Triggered,
+    Expired,
+}
+
+impl RestingOrder {
+    pub fn remaining(&self) -> f64 {
+        self.units - self.filled
+    }
+
+    pub fn update(&self, price: &Price) -> RestingUpdate {
+        if price.time < self.active_after {
+            return RestingUpdate::Waiting;
+        }
+        if self.expires.is_some_and(|expires| price.time >= expires) {
+            return RestingUpdate::Expired;
+        }
+        let (bid, ask) = (price.bid as f64, price.ask as f64);
+        let triggered = match (self.kind, self.units > 0.0) {
+            (RestingOrderType::Limit, true) => ask <= self.price,
+            (RestingOrderType::Limit, false) => bid >= self.price,
+            (RestingOrderType::Stop, true) => ask >= self.price,
+            (RestingOrderType::Stop, false) => bid <= self.price,
+        };
+        match triggered {
+            true => RestingUpdate::Triggered,
+            false if self.immediate => RestingUpdate::Expired,
+            false => RestingUpdate::Waiting,
+        }
+    }
+}
@@ -0,0 +1,100 @@
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Write};
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use super::{AccountState, BacktestRow, Fill, PendingOrder, RestingOrder};
+use crate::engine::DrawdownState;
+use crate::errors::Context;
+use crate::models::{
+    CircuitBreakerState, PassiveStats, StrategyCheckpoint, TargetSmootherState, TrailingStopState,
+    VolatilityState, WorkingTarget,
+};
+
+// Everything a finished backtest needs to carry on over newer prices, saved by "saveState" and
+// continued from by "resume": the strategies and account as of the last price, and the fills and
+// rows so far, so the continued run's report covers both. Resuming every week from the state the
+// previous week saved keeps a backtest of the live strategy up to date without rerunning it from
+// the start. The trailing stops, smoothed targets, volatility estimates, drawdown level and order
+// rate circuit breaker carry on too, only the regime labels start over on resuming.
+#[derive(Serialize, Deserialize)]
+pub struct BacktestState {
+    // Time of the last price traded on
+    pub time: u64,
+    pub ticks: u64,
+
+    #[serde(rename = "nextSample")]
+    pub next_sample: u64,
+
+    pub strategies: StrategyCheckpoint,
+    pub account: AccountState,
+
+    pub fills: Vec<Fill>,
+    pub rows: Vec<BacktestRow>,
+
+    // Orders still waiting out their latency
+    #[serde(default)]
+    pub(crate) pending: Vec<PendingOrder>,
+
+    // Limit and stop orders still waiting to fill, and how many never did
+    #[serde(default)]
+    pub(crate) resting: Vec<RestingOrder>,
+
+    #[serde(default)]
+    #[serde(rename = "unfilledOrders")]
+    pub unfilled_orders: u64,
+
+    // How the orders posted by passive execution ended so far, None without it
+    #[serde(default)]
+    pub passive: Option<PassiveStats>,
+
+    // Signals still being worked towards their targets
+    #[serde(default)]
+    pub working: Vec<WorkingTarget>,
+
+    // None without trailing stops or a volatility target
+    #[serde(default)]
+    #[serde(rename = "trailingStops")]
+    pub trailing_stops: Option<TrailingStopState>,
+
+    #[serde(default)]
+    #[serde(rename = "targetSmoother")]
+    pub target_smoother: TargetSmootherState,
+
+    #[serde(default)]
+    pub volatility: Option<VolatilityState>,
+
+    // None without drawdown scaling or an order rate limit
+    #[serde(default)]
+    pub drawdown: Option<DrawdownState>,
+
+    #[serde(default)]
+    #[serde(rename = "circuitBreaker")]
+    pub circuit_breaker: Option<CircuitBreakerState>,
+}
+
+impl BacktestState {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, Box<dyn std::error::Error>> {
+        let path = path.as_ref();
+        let file = File::open(path).with_context(|| format!("Opening {}", path.display()))?;
+        let state = serde_json::from_reader(BufReader::new(file))
+            .with_context(|| format!("Parsing {}", path.display()))?;
+        Ok(state)
+    }
+
+    // Written to a temporary file first, as the state resumed from is often the one replaced
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), Box<dyn std::error::Error>> {
+        let path = path.as_ref();
+        let temporary = path.with_extension("tmp");
+        let mut writer = BufWriter::new(
+            File::create(&temporary)
+                .with_context(|| format!("Creating {}", temporary.display()))?,
+        );
+        serde_json::to_writer(&mut writer, self)?;
+        writer.flush()?;
+        drop(writer);
+        std::fs::rename(&temporary, path)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,120 @@
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::oanda::objects::Price;
+
+// A synthetic disturbance applied to historical prices.
+// Shocks apply to every instrument unless one is given.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum Shock {
+    // Prices jump by a fraction (0.02 = 2% up, -0.02 = 2% down) at start and stay shifted
+    Gap {
+        #[serde(default)]
+        instrument: Option<String>,
+        start: DateTime<Utc>,
+        size: f64,
+    },
+
+    // The spread around the mid price is multiplied for a number of minutes
+    SpreadWidening {
+        #[serde(default)]
+        instrument: Option<String>,
+        start: DateTime<Utc>,
+        minutes: u64,
+        multiple: f64,
+    },
+
+    // No prices are received for a number of minutes
+    Outage {
+        #[serde(default)]
+        instrument: Option<String>,
+        start: DateTime<Utc>,
+        minutes: u64,
+    },
+}
+
+impl Shock {
+    fn applies_to(&self, price: &Price) -> bool {
+        let (instrument, start, minutes) = match self {
+            Shock::Gap {
+                instrument, start, ..
+            } => (instrument, start, None),
+            Shock::SpreadWidening {
+                instrument,
+                start,
+                minutes,
+                ..
+            } => (instrument, start, Some(*minutes)),
+            Shock::Outage {
+                instrument,
+                start,
+                minutes,
+            } => (instrument, start, Some(*minutes)),
+        };
+
+        if let Some(instrument) = instrument {
+            if *instrument != price.instrument {
+                return false;
+            }
+        }
+
+        let start = start.timestamp_millis() as u64;
+        match minutes {
+            Some(minutes) => price.time >= start && price.time < start + minutes * 60_000,
+            None => price.time >= start,
+        }
+    }
+
+    // Returns None if the price should be dropped
+    fn apply(&self, mut price: Price) -> Option<Price> {
+        if !self.applies_to(&price) {
+            return Some(price);
+        }
+
+        match self {
+            Shock::Gap { size, .. } => {
+                price.bid *= 1.0 + *size as f32;
+                price.ask *= 1.0 + *size as f32;
+            }
+            Shock::SpreadWidening { multiple, .. } => {
+                let mid = (price.bid + price.ask) / 2.0;
+                let half_spread = (price.ask - price.bid) / 2.0 * *multiple as f32;
+                price.bid = mid - half_spread;
+                price.ask = mid + half_spread;
+            }
+            Shock::Outage { .. } => return None,
+        }
+        Some(price)
+    }
+}
+
+// A named set of shocks to run a backtest under
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Scenario {
+    pub name: String,
+    pub shocks: Vec<Shock>,
+}
+
+impl Scenario {
+    pub fn apply(&self, price: Price) -> Option<Price> {
+        self.shocks
+            .iter()
+            .try_fold(price, |price, shock| shock.apply(price))
+    }
+}
+
+// Scenarios are defined in their own file, so the same set can be run against any backtest config
+pub fn load_scenarios<P: AsRef<Path>>(
+    path: P,
+) -> Result<Vec<Scenario>, Box<dyn std::error::Error>> {
+    log::info!("Loading scenarios from {:?}", path.as_ref());
+    let file = File::open(path)?;
+    let reader = BufReader::new(file);
+    let scenarios = serde_json::from_reader(reader)?;
+    Ok(scenarios)
+}
@@ -0,0 +1,190 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::backtest::Fill;
+
+// A round trip from flat to flat (or until the position flips sides) in a single instrument.
+// Positions can be added to and partially reduced along the way, so entry and exit prices are
+// the unit weighted averages of the fills on each side.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Trade {
+    pub instrument: String,
+    pub long: bool,
+
+    #[serde(rename = "entryTime")]
+    pub entry_time: u64,
+    #[serde(rename = "entryPrice")]
+    pub entry_price: f64,
+    #[serde(rename = "exitTime")]
+    pub exit_time: u64,
+    #[serde(rename = "exitPrice")]
+    pub exit_price: f64,
+
+    // Largest absolute position held during the trade
+    #[serde(rename = "maxUnits")]
+    pub max_units: f64,
+
+    // Realized profit in the account currency
+    pub pl: f64,
+
+    // Milliseconds from the first entry to the final exit
+    pub duration: u64,
+}
+
+// A trade that hasn't been closed yet
+struct OpenTrade {
+    long: bool,
+    entry_time: u64,
+    units: f64,
+    max_units: f64,
+    entered_units: f64,
+    entry_value: f64,
+    exited_units: f64,
+    exit_value: f64,
+    pl: f64,
+}
+
+impl OpenTrade {
+    fn new(time: u64, units: f64, price: f64) -> Self {
+        OpenTrade {
+            long: units > 0.0,
+            entry_time: time,
+            units,
+            max_units: units.abs(),
+            entered_units: units.abs(),
+            entry_value: units.abs() * price,
+            exited_units: 0.0,
+            exit_value: 0.0,
+            pl: 0.0,
+        }
+    }
+
+    fn close(self, instrument: &str, time: u64) -> Trade {
+        Trade {
+            instrument: instrument.to_string(),
+            long: self.long,
+            entry_time: self.entry_time,
+            entry_price: self.entry_value / self.entered_units,
+            exit_time: time,
+            exit_price: self.exit_value / self.exited_units,
+            max_units: self.max_units,
+            pl: self.pl,
+            duration: time.saturating_sub(self.entry_time),
+        }
+    }
+}
+
+// Rebuild round trip trades from the fills of a netting account, in the order they were closed.
+// Trades still open after the last fill are not included.
+pub fn reconstruct_trades(fills: &[Fill]) -> Vec<Trade> {
+    let mut open: HashMap<&str, OpenTrade> = HashMap::new();
+    let mut trades = Vec::new();
+
+    for fill in fills {
+        let instrument = fill.instrument.as_str();
+        let mut trade = match open.remove(instrument) {
+            Some(trade) => trade,
+            None => {
+                open.insert(
+                    instrument,
+                    OpenTrade::new(fill.time, fill.units, fill.price),
+                );
+                continue;
+            }
+        };
+
+        if trade.units.signum() == fill.units.signum() {
+            // Adding to the position
+            trade.units += fill.units;
+            trade.max_units = trade.max_units.max(trade.units.abs());
+            trade.entered_units += fill.units.abs();
+            trade.entry_value += fill.units.abs() * fill.price;
+            open.insert(instrument, trade);
+            continue;
+        }
+
+        // Reducing, closing or flipping the position
+        let closed = fill.units.abs().min(trade.units.abs());
+        trade.exited_units += closed;
+        trade.exit_value += closed * fill.price;
+        trade.pl += fill.realized_pl;
+        trade.units += fill.units;
+
+        if trade.units == 0.0 || trade.units.signum() == fill.units.signum() {
+            // Whatever is left over after closing opens a new trade on the other side
+            let remaining = fill.units.abs() - closed;
+            trades.push(trade.close(instrument, fill.time));
+            if remaining > 0.0 {
+                let units = remaining * fill.units.signum();
+                open.insert(instrument, OpenTrade::new(fill.time, units, fill.price));
+            }
+        } else {
+            open.insert(instrument, trade);
+        }
+    }
+
+    trades
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct TradeStatistics {
+    pub count: usize,
+
+    // Fraction of trades with a positive profit
+    #[serde(rename = "winRate")]
+    pub win_rate: f64,
+
+    #[serde(rename = "averagePl")]
+    pub average_pl: f64,
+
+    #[serde(rename = "averageWin")]
+    pub average_win: f64,
+
+    #[serde(rename = "averageLoss")]
+    pub average_loss: f64,
+
+    // Gross profit over gross loss, None if there were no losing trades
+    #[serde(rename = "profitFactor")]
+    pub profit_factor: Option<f64>,
+
+    // Milliseconds
+    #[serde(rename = "averageDuration")]
+    pub average_duration: f64,
+}
+
+impl TradeStatistics {
+    pub fn calculate(trades: &[Trade]) -> Self {
+        if trades.is_empty() {
+            return TradeStatistics::default();
+        }
+
+        let count = trades.len();
+        let wins: Vec<f64> = trades.iter().map(|t| t.pl).filter(|pl| *pl > 0.0).collect();
+        let losses: Vec<f64> = trades.iter().map(|t| t.pl).filter(|pl| *pl < 0.0).collect();
+        let gross_profit: f64 = wins.iter().sum();
+        let gross_loss: f64 = -losses.iter().sum::<f64>();
+
+        TradeStatistics {
+            count,
+            win_rate: wins.len() as f64 / count as f64,
+            average_pl: trades.iter().map(|t| t.pl).sum::<f64>() / count as f64,
+            average_win: if wins.is_empty() {
+                0.0
+            } else {
+                gross_profit / wins.len() as f64
+            },
+            average_loss: if losses.is_empty() {
+                0.0
+            } else {
+                -gross_loss / losses.len() as f64
+            },
+            profit_factor: if gross_loss > 0.0 {
+                Some(gross_profit / gross_loss)
+            } else {
+                None
+            },
+            average_duration: trades.iter().map(|t| t.duration as f64).sum::<f64>() / count as f64,
+        }
+    }
+}
@@ -0,0 +1,865 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use futures::StreamExt;
+use hmac::{Hmac, Mac};
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use sha2::Sha256;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tokio::time::timeout;
+use tokio_tungstenite::tungstenite::Message;
+
+use crate::broker::{
+    AccountProvider, AccountState, BinanceSettings, BrokerFill, BrokerPosition, BrokerPriceStream,
+    MarketDataProvider, OrderExecutor,
+};
+use crate::calendar;
+use crate::errors::{self, Context};
+use crate::oanda::errors::{OrderStateUnknownError, StreamTimeoutError};
+use crate::oanda::generate_client_order_id;
+use crate::oanda::helpers::{deserialize_f64_from_string, deserialize_option_f64_from_string};
+use crate::oanda::objects::{Heartbeat, Instrument, Price, PriceStatus, StreamItem};
+
+const API_URL: &str = "https://api.binance.com";
+const STREAMING_URL: &str = "wss://stream.binance.com:9443";
+const TESTNET_API_URL: &str = "https://testnet.binance.vision";
+const TESTNET_STREAMING_URL: &str = "wss://stream.testnet.binance.vision";
+
+// Delay before reconnecting a stream whose connection could not be opened
+const RECONNECT_DELAY: Duration = Duration::from_secs(1);
+
+// How long Binance accepts a signed request for after its timestamp, in milliseconds
+const RECEIVE_WINDOW: u64 = 5_000;
+
+// Spot trading on Binance, through its REST API and the book ticker websocket stream. Crypto
+// trades around the clock, so the same strategies can be run (and their prices recorded) over
+// weekends too.
+//
+// A spot account holds assets rather than positions: the position in BTC_USDT is the BTC held,
+// which can't be negative. Sells are limited to what's held, so short forecasts end up flat.
+//
+// The collector only records OANDA's prices, so there are no tick files of Binance instruments
+// to backtest on: strategies for them can be tried on paper or on the testnet only.
+pub struct BinanceBroker {
+    settings: BinanceSettings,
+    instruments: Vec<String>,
+    api_url: String,
+    streaming_url: String,
+    timeout_duration: u64,
+    client: reqwest::Client,
+
+    // Order size limits of each instrument, fetched with its first order
+    filters: Mutex<HashMap<String, SymbolFilters>>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct SymbolFilters {
+    step_size: f64,
+    tick_size: f64,
+}
+
+impl BinanceBroker {
+    // `instruments` are those traded, which positions are reported for
+    pub fn new(settings: &BinanceSettings, instruments: &[String]) -> Self {
+        BinanceBroker {
+            settings: settings.clone(),
+            instruments: instruments.to_vec(),
+            api_url: API_URL.to_string(),
+            streaming_url: STREAMING_URL.to_string(),
+            timeout_duration: 60_000,
+            client: reqwest::Client::new(),
+            filters: Mutex::new(HashMap::new()),
+        }
+    }
+
+    // Trade on the spot testnet, which needs an API key of its own
+    pub fn with_testnet(mut self) -> Self {
+        self.api_url = TESTNET_API_URL.to_string();
+        self.streaming_url = TESTNET_STREAMING_URL.to_string();
+        self
+    }
+
+    async fn public<T: DeserializeOwned>(
+        &self,
+        endpoint: &str,
+        query: &[(&str, String)],
+    ) -> Result<T, Box<dyn Error>> {
+        let response = self
+            .client
+            .get(format!("{}{}", self.api_url, endpoint))
+            .query(query)
+            .send()
+            .await?;
+        parse_response(response, endpoint).await
+    }
+
+    // A request signed with the secret key, as anything touching the account must be
+    async fn signed<T: DeserializeOwned>(
+        &self,
+        method: reqwest::Method,
+        endpoint: &str,
+        params: &[(&str, String)],
+    ) -> Result<T, Box<dyn Error>> {
+        let timestamp = chrono::Utc::now().timestamp_millis();
+        let mut query: Vec<String> = params
+            .iter()
+            .map(|(key, value)| format!("{}={}", key, value))
+            .collect();
+        query.push(format!("recvWindow={}", RECEIVE_WINDOW));
+        query.push(format!("timestamp={}", timestamp));
+        let query = query.join("&");
+        let signature = hex::encode(sign(self.settings.secret_key.as_bytes(), &query));
+
+        let response = self
+            .client
+            .request(
+                method,
+                format!(
+                    "{}{}?{}&signature={}",
+                    self.api_url, endpoint, query, signature
+                ),
+            )
+            .header("X-MBX-APIKEY", &self.settings.api_key)
+            .send()
+            .await?;
+        parse_response(response, endpoint).await
+    }
+
+    async fn filters(&self, instrument: &str) -> Result<SymbolFilters, Box<dyn Error>> {
+        if let Some(filters) = self.filters.lock().unwrap().get(instrument) {
+            return Ok(*filters);
+        }
+        let info: ExchangeInfo = self
+            .public("/api/v3/exchangeInfo", &[("symbol", symbol(instrument))])
+            .await
+            .with_context(|| format!("Fetching the order size limits of {}", instrument))?;
+        let filters = info
+            .symbols
+            .first()
+            .map(|info| SymbolFilters {
+                step_size: info
+                    .filter("LOT_SIZE", |filter| filter.step_size)
+                    .unwrap_or(0.0),
+                tick_size: info
+                    .filter("PRICE_FILTER", |filter| filter.tick_size)
+                    .unwrap_or(0.0),
+            })
+            .ok_or_else(|| format!("Binance doesn't list {}", instrument))?;
+        self.filters
+            .lock()
+            .unwrap()
+            .insert(instrument.to_string(), filters);
+        Ok(filters)
+    }
+
+    async fn balances(&self) -> Result<HashMap<String, Balance>, Box<dyn Error>> {
+        let account: BinanceAccount = self
+            .signed(reqwest::Method::GET, "/api/v3/account", &[])
+            .await?;
+        Ok(account
+            .balances
+            .into_iter()
+            .map(|balance| (balance.asset.clone(), balance))
+            .collect())
+    }
+
+    // The order placed with `client_order_id`, None if Binance has no such order
+    async fn find_order(
+        &self,
+        instrument: &str,
+        client_order_id: &str,
+    ) -> Result<Option<OrderResponse>, Box<dyn Error>> {
+        let order = self
+            .signed(
+                reqwest::Method::GET,
+                "/api/v3/order",
+                &[
+                    ("symbol", symbol(instrument)),
+                    ("origClientOrderId", client_order_id.to_string()),
+                ],
+            )
+            .await;
+        match order {
+            Ok(order) => Ok(Some(order)),
+            Err(e) if ApiError::code_of(e.as_ref()) == Some(ORDER_DOES_NOT_EXIST) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    // Units of the instrument's base asset that are free to sell
+    async fn held(&self, instrument: &str) -> Result<f64, Box<dyn Error>> {
+        let (base, _) = assets(instrument)?;
+        Ok(self
+            .balances()
+            .await?
+            .get(base)
+            .map(|balance| balance.free)
+            .unwrap_or(0.0))
+    }
+}
+
+#[async_trait(?Send)]
+impl MarketDataProvider for BinanceBroker {
+    fn name(&self) -> &str {
+        "binance"
+    }
+
+    async fn latest_prices(&self, instruments: &[String]) -> Result<Vec<Price>, Box<dyn Error>> {
+        let tickers: Vec<BookTicker> = self
+            .public(
+                "/api/v3/ticker/bookTicker",
+                &[("symbols", symbol_list(instruments))],
+            )
+            .await?;
+        let names = instrument_names(instruments);
+        let now = chrono::Utc::now().timestamp_millis() as u64;
+        Ok(tickers
+            .iter()
+            .filter_map(|ticker| ticker.price(&names, now))
+            .collect())
+    }
+
+    async fn instruments(&self, instruments: &[String]) -> Result<Vec<Instrument>, Box<dyn Error>> {
+        let info: ExchangeInfo = self
+            .public(
+                "/api/v3/exchangeInfo",
+                &[("symbols", symbol_list(instruments))],
+            )
+            .await?;
+        let names = instrument_names(instruments);
+        Ok(info
+            .symbols
+            .iter()
+            .filter_map(|info| {
+                let step_size = info.filter("LOT_SIZE", |filter| filter.step_size)?;
+                Some(Instrument {
+                    name: names.get(&info.symbol)?.clone(),
+                    trade_units_precision: decimals(step_size),
+                    minimum_trade_size: info.filter("LOT_SIZE", |filter| filter.min_qty)?,
+                    maximum_order_units: info.filter("LOT_SIZE", |filter| filter.max_qty)?,
+                    financing: None,
+                })
+            })
+            .collect())
+    }
+
+    fn price_stream(&self, instruments: &[String]) -> Result<BrokerPriceStream, Box<dyn Error>> {
+        let streams: Vec<String> = instruments
+            .iter()
+            .map(|instrument| format!("{}@bookTicker", symbol(instrument).to_lowercase()))
+            .collect();
+        let url = format!(
+            "{}/stream?streams={}",
+            self.streaming_url,
+            streams.join("/")
+        );
+        Ok(Box::new(BinancePriceStream::new(
+            url,
+            instrument_names(instruments),
+            self.timeout_duration,
+        )))
+    }
+}
+
+#[async_trait(?Send)]
+impl OrderExecutor for BinanceBroker {
+    async fn market_order(
+        &self,
+        instrument: &str,
+        units: f64,
+    ) -> Result<Option<BrokerFill>, Box<dyn Error>> {
+        let mut units = units;
+        if units < 0.0 {
+            let held = self.held(instrument).await?;
+            if -units > held {
+                log::warn!(
+                    "[{}] Can't sell {} units holding {}, selling what's held",
+                    instrument,
+                    -units,
+                    held
+                );
+                units = -held;
+            }
+        }
+
+        let filters = self.filters(instrument).await?;
+        let quantity = round_down(units.abs(), filters.step_size);
+        if quantity == 0.0 {
+            return Ok(None);
+        }
+
+        // Sent with an ID of our own, so that an order whose answer was lost can be looked up
+        let client_order_id = generate_client_order_id();
+        let side = if units > 0.0 { "BUY" } else { "SELL" };
+        let placed = self
+            .signed(
+                reqwest::Method::POST,
+                "/api/v3/order",
+                &[
+                    ("symbol", symbol(instrument)),
+                    ("side", side.to_string()),
+                    ("type", "MARKET".to_string()),
+                    ("quantity", format_decimal(quantity, filters.step_size)),
+                    ("newClientOrderId", client_order_id.clone()),
+                    ("newOrderRespType", "RESULT".to_string()),
+                ],
+            )
+            .await;
+        let order: OrderResponse = match placed {
+            Ok(order) => order,
+            Err(e) if may_have_been_placed(e.as_ref()) => {
+                log::warn!(
+                    "[{}] Order {} may have reached Binance: {}, looking it up...",
+                    instrument,
+                    client_order_id,
+                    e
+                );
+                match self.find_order(instrument, &client_order_id).await {
+                    Ok(Some(order)) => order,
+                    Ok(None) => {
+                        return Err(e).with_context(|| {
+                            format!("Placing a market order for {} {}", units, instrument)
+                        })
+                    }
+                    Err(lookup) => {
+                        return Err(Box::new(OrderStateUnknownError {
+                            client_order_id,
+                            message: format!("{} (looking it up failed: {})", e, lookup),
+                        }))
+                    }
+                }
+            }
+            Err(e) => {
+                return Err(e).with_context(|| {
+                    format!("Placing a market order for {} {}", units, instrument)
+                })
+            }
+        };
+
+        if order.executed_qty == 0.0 {
+            log::warn!(
+                "[{}] Market order {} wasn't filled",
+                instrument,
+                order.status
+            );
+            return Ok(None);
+        }
+        Ok(Some(order.fill(instrument, units.signum())))
+    }
+
+    async fn order_outcome(
+        &self,
+        instrument: &str,
+        client_order_id: &str,
+    ) -> Result<Option<Option<BrokerFill>>, Box<dyn Error>> {
+        let order = match self.find_order(instrument, client_order_id).await? {
+            Some(order) => order,
+            None => return Ok(None),
+        };
+        if order.executed_qty == 0.0 {
+            return Ok(Some(None));
+        }
+        let sign = if order.side == "SELL" { -1.0 } else { 1.0 };
+        Ok(Some(Some(order.fill(instrument, sign))))
+    }
+
+    async fn close_position(&self, instrument: &str) -> Result<Vec<BrokerFill>, Box<dyn Error>> {
+        let held = self.held(instrument).await?;
+        Ok(self
+            .market_order(instrument, -held)
+            .await?
+            .into_iter()
+            .collect())
+    }
+
+    async fn protective_stop(
+        &self,
+        instrument: &str,
+        units: f64,
+        price: f64,
+    ) -> Result<String, Box<dyn Error>> {
+        if units >= 0.0 {
+            return Err(format!(
+                "[{}] A spot account can only place stops that sell",
+                instrument
+            )
+            .into());
+        }
+        let filters = self.filters(instrument).await?;
+        let order: OrderResponse = self
+            .signed(
+                reqwest::Method::POST,
+                "/api/v3/order",
+                &[
+                    ("symbol", symbol(instrument)),
+                    ("side", "SELL".to_string()),
+                    ("type", "STOP_LOSS".to_string()),
+                    (
+                        "quantity",
+                        format_decimal(round_down(-units, filters.step_size), filters.step_size),
+                    ),
+                    (
+                        "stopPrice",
+                        format_decimal(round_down(price, filters.tick_size), filters.tick_size),
+                    ),
+                ],
+            )
+            .await
+            .with_context(|| format!("Placing a stop order for {} {}", units, instrument))?;
+        Ok(order.order_id.to_string())
+    }
+
+    async fn cancel_order(
+        &self,
+        instrument: &str,
+        _units: f64,
+        order_id: &str,
+    ) -> Result<bool, Box<dyn Error>> {
+        let cancelled: Result<OrderResponse, _> = self
+            .signed(
+                reqwest::Method::DELETE,
+                "/api/v3/order",
+                &[
+                    ("symbol", symbol(instrument)),
+                    ("orderId", order_id.to_string()),
+                ],
+            )
+            .await;
+        match cancelled {
+            Ok(_) => Ok(true),
+            Err(e) if ApiError::code_of(e.as_ref()) == Some(UNKNOWN_ORDER_SENT) => Ok(false),
+            Err(e) => Err(e).with_context(|| format!("Cancelling order {}", order_id)),
+        }
+    }
+}
+
+#[async_trait(?Send)]
+impl AccountProvider for BinanceBroker {
+    // The quote asset of the first instrument is taken as the account currency, and the NAV
+    // counts the assets of the traded instruments only
+    async fn account(&self) -> Result<AccountState, Box<dyn Error>> {
+        let first = self
+            .instruments
+            .first()
+            .ok_or("No instruments to value the account in")?;
+        let (_, currency) = assets(first)?;
+        let balances = self.balances().await?;
+        let cash = balances.get(currency);
+        let balance = cash.map(|cash| cash.free + cash.locked).unwrap_or(0.0);
+
+        let mut nav = balance;
+        for price in self.latest_prices(&self.instruments).await? {
+            let (base, quote) = assets(&price.instrument)?;
+            if quote == currency {
+                let held = balances
+                    .get(base)
+                    .map(|balance| balance.free + balance.locked)
+                    .unwrap_or(0.0);
+                nav += held * price.bid as f64;
+            }
+        }
+
+        Ok(AccountState {
+            currency: currency.to_string(),
+            balance,
+            nav,
+            margin_used: 0.0,
+            margin_available: cash.map(|cash| cash.free).unwrap_or(0.0),
+        })
+    }
+
+    async fn positions(&self) -> Result<Vec<BrokerPosition>, Box<dyn Error>> {
+        let balances = self.balances().await?;
+        let mut positions = Vec::new();
+        for instrument in &self.instruments {
+            let (base, _) = assets(instrument)?;
+            let units = balances
+                .get(base)
+                .map(|balance| balance.free + balance.locked)
+                .unwrap_or(0.0);
+            if units != 0.0 {
+                positions.push(BrokerPosition {
+                    instrument: instrument.clone(),
+                    units,
+                    unrealized_pl: 0.0,
+                });
+            }
+        }
+        Ok(positions)
+    }
+}
+
+// Best bid and ask of each instrument from Binance's combined book ticker stream. The stream
+// reconnects on its own, Binance closes every connection after a day. Book ticker updates carry
+// no timestamp, so prices are timed when they're received, and prices missed while reconnecting
+// aren't backfilled.
+pub struct BinancePriceStream {
+    receiver: mpsc::Receiver<StreamItem>,
+    task: JoinHandle<()>,
+    timeout_duration: u64,
+}
+
+impl BinancePriceStream {
+    // Must be called from within a tokio runtime, as the connection runs in a task of its own
+    fn new(url: String, names: HashMap<String, String>, timeout_duration: u64) -> Self {
+        let (sender, receiver) = mpsc::channel(4096);
+        let task = tokio::spawn(run_stream(url, names, timeout_duration, sender));
+        BinancePriceStream {
+            receiver,
+            task,
+            timeout_duration,
+        }
+    }
+
+    pub async fn next_item(&mut self) -> Result<StreamItem, Box<dyn Error>> {
+        match timeout(
+            Duration::from_millis(self.timeout_duration),
+            self.receiver.recv(),
+        )
+        .await
+        {
+            Ok(Some(item)) => Ok(item),
+            Ok(None) => Err("The Binance stream has stopped".into()),
+            Err(_) => Err(Box::new(StreamTimeoutError {
+                message: "No data received from Binance".to_string(),
+            })),
+        }
+    }
+}
+
+impl Drop for BinancePriceStream {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+impl Iterator for BinancePriceStream {
+    type Item = Result<StreamItem, Box<dyn Error>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        Some(futures::executor::block_on(self.next_item()))
+    }
+}
+
+// Stream book ticker updates forever, reconnecting whenever the connection fails or goes quiet
+async fn run_stream(
+    url: String,
+    names: HashMap<String, String>,
+    timeout_duration: u64,
+    sender: mpsc::Sender<StreamItem>,
+) {
+    loop {
+        let mut socket = match tokio_tungstenite::connect_async(url.as_str()).await {
+            Ok((socket, _)) => socket,
+            Err(err) => {
+                log::error!("[binance] Failed to open stream: {}", err);
+                tokio::time::sleep(RECONNECT_DELAY).await;
+                continue;
+            }
+        };
+        log::info!("[binance] Connected, streaming {} instruments", names.len());
+
+        loop {
+            let message =
+                match timeout(Duration::from_millis(timeout_duration), socket.next()).await {
+                    Ok(Some(Ok(message))) => message,
+                    Ok(Some(Err(err))) => {
+                        log::warn!("[binance] Stream error: {}, reconnecting...", err);
+                        break;
+                    }
+                    Ok(None) => {
+                        log::warn!("[binance] Stream closed, reconnecting...");
+                        break;
+                    }
+                    Err(_) => {
+                        log::warn!(
+                            "[binance] No data for {}ms, reconnecting...",
+                            timeout_duration
+                        );
+                        break;
+                    }
+                };
+
+            // Pings are answered by the websocket itself, and stand in for OANDA's heartbeats
+            let item = match message {
+                Message::Text(text) => match serde_json::from_str::<StreamMessage>(&text) {
+                    Ok(message) => message
+                        .data
+                        .price(&names, chrono::Utc::now().timestamp_millis() as u64)
+                        .map(StreamItem::Price),
+                    Err(err) => {
+                        log::warn!("[binance] Failed to parse {}: {}", text, err);
+                        None
+                    }
+                },
+                Message::Ping(_) => Some(StreamItem::Heartbeat(Heartbeat {
+                    time: chrono::Utc::now().to_rfc3339(),
+                })),
+                _ => None,
+            };
+            if let Some(item) = item {
+                if sender.send(item).await.is_err() {
+                    return;
+                }
+            }
+        }
+    }
+}
+
+// Binance's error codes: a cancel of an order that's no longer open, and a lookup of one that
+// was never placed
+const UNKNOWN_ORDER_SENT: i64 = -2011;
+const ORDER_DOES_NOT_EXIST: i64 = -2013;
+
+// A request Binance answered with an error, e.g. {"code": -2013, "msg": "Order does not exist."}
+#[derive(Debug)]
+struct ApiError {
+    endpoint: String,
+    status: reqwest::StatusCode,
+    code: Option<i64>,
+    body: String,
+}
+
+impl ApiError {
+    fn code_of(error: &(dyn Error + 'static)) -> Option<i64> {
+        errors::find::<ApiError>(error).and_then(|error| error.code)
+    }
+}
+
+impl std::fmt::Display for ApiError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "Received non-success status code {} from {}: {}",
+            self.status, self.endpoint, self.body
+        )
+    }
+}
+
+impl Error for ApiError {}
+
+#[derive(Deserialize)]
+struct ErrorBody {
+    code: i64,
+}
+
+// Whether an order that failed with `error` may still have been placed: the request left but no
+// answer came back, or Binance answered with a server error, after which it documents the
+// order's state as unknown
+fn may_have_been_placed(error: &(dyn Error + 'static)) -> bool {
+    if let Some(error) = errors::find::<ApiError>(error) {
+        return error.status.is_server_error();
+    }
+    errors::find::<reqwest::Error>(error).is_some_and(|error| !error.is_connect())
+}
+
+async fn parse_response<T: DeserializeOwned>(
+    response: reqwest::Response,
+    endpoint: &str,
+) -> Result<T, Box<dyn Error>> {
+    let status = response.status();
+    let body = response.text().await?;
+    if !status.is_success() {
+        return Err(Box::new(ApiError {
+            endpoint: endpoint.to_string(),
+            status,
+            code: serde_json::from_str::<ErrorBody>(&body)
+                .ok()
+                .map(|body| body.code),
+            body,
+        }));
+    }
+    serde_json::from_str(&body).with_context(|| format!("Parsing the response from {}", endpoint))
+}
+
+fn sign(key: &[u8], message: &str) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC takes keys of any length");
+    mac.update(message.as_bytes());
+    mac.finalize().into_bytes().to_vec()
+}
+
+// Binance's name for an instrument, e.g. "BTCUSDT" for BTC_USDT
+fn symbol(instrument: &str) -> String {
+    instrument.replace('_', "")
+}
+
+// A JSON list of symbols, as the endpoints taking several expect
+fn symbol_list(instruments: &[String]) -> String {
+    let symbols: Vec<String> = instruments
+        .iter()
+        .map(|instrument| format!("\"{}\"", symbol(instrument)))
+        .collect();
+    format!("[{}]", symbols.join(","))
+}
+
+// Instruments by their Binance symbol
+fn instrument_names(instruments: &[String]) -> HashMap<String, String> {
+    instruments
+        .iter()
+        .map(|instrument| (symbol(instrument), instrument.clone()))
+        .collect()
+}
+
+// The base and quote assets of an instrument
+fn assets(instrument: &str) -> Result<(&str, &str), Box<dyn Error>> {
+    instrument
+        .split_once('_')
+        .ok_or_else(|| format!("{} isn't named BASE_QUOTE", instrument).into())
+}
+
+fn round_down(value: f64, step: f64) -> f64 {
+    if step <= 0.0 {
+        return value;
+    }
+    // Nudged so that values a rounding error short of a step aren't rounded down a whole step
+    (value / step + 1e-9).floor() * step
+}
+
+// Decimal places of a step size, e.g. 3 for 0.001
+fn decimals(step: f64) -> i32 {
+    if step <= 0.0 || step >= 1.0 {
+        0
+    } else {
+        (-step.log10()).round() as i32
+    }
+}
+
+fn format_decimal(value: f64, step: f64) -> String {
+    format!("{:.*}", decimals(step) as usize, value)
+}
+
+#[derive(Deserialize)]
+struct StreamMessage {
+    data: BookTicker,
+}
+
+#[derive(Deserialize)]
+struct BookTicker {
+    #[serde(alias = "s")]
+    symbol: String,
+
+    #[serde(alias = "b")]
+    #[serde(rename = "bidPrice")]
+    #[serde(deserialize_with = "deserialize_f64_from_string")]
+    bid: f64,
+
+    #[serde(alias = "a")]
+    #[serde(rename = "askPrice")]
+    #[serde(deserialize_with = "deserialize_f64_from_string")]
+    ask: f64,
+}
+
+impl BookTicker {
+    fn price(&self, names: &HashMap<String, String>, time: u64) -> Option<Price> {
+        Some(Price {
+            bid: self.bid as f32,
+            ask: self.ask as f32,
+            time,
+            nanos: 0,
+            instrument: names.get(&self.symbol)?.clone(),
+            tradeable: true,
+            status: PriceStatus::Tradeable,
+        })
+    }
+}
+
+#[derive(Deserialize)]
+struct ExchangeInfo {
+    symbols: Vec<SymbolInfo>,
+}
+
+#[derive(Deserialize)]
+struct SymbolInfo {
+    symbol: String,
+    filters: Vec<SymbolFilter>,
+}
+
+impl SymbolInfo {
+    fn filter<F: Fn(&SymbolFilter) -> Option<f64>>(&self, kind: &str, field: F) -> Option<f64> {
+        self.filters
+            .iter()
+            .find(|filter| filter.filter_type == kind)
+            .and_then(field)
+    }
+}
+
+#[derive(Deserialize)]
+struct SymbolFilter {
+    #[serde(rename = "filterType")]
+    filter_type: String,
+
+    #[serde(default, deserialize_with = "deserialize_option_f64_from_string")]
+    #[serde(rename = "minQty")]
+    min_qty: Option<f64>,
+
+    #[serde(default, deserialize_with = "deserialize_option_f64_from_string")]
+    #[serde(rename = "maxQty")]
+    max_qty: Option<f64>,
+
+    #[serde(default, deserialize_with = "deserialize_option_f64_from_string")]
+    #[serde(rename = "stepSize")]
+    step_size: Option<f64>,
+
+    #[serde(default, deserialize_with = "deserialize_option_f64_from_string")]
+    #[serde(rename = "tickSize")]
+    tick_size: Option<f64>,
+}
+
+#[derive(Deserialize)]
+struct BinanceAccount {
+    balances: Vec<Balance>,
+}
+
+#[derive(Deserialize)]
+struct Balance {
+    asset: String,
+    #[serde(deserialize_with = "deserialize_f64_from_string")]
+    free: f64,
+    #[serde(deserialize_with = "deserialize_f64_from_string")]
+    locked: f64,
+}
+
+#[derive(Deserialize)]
+struct OrderResponse {
+    #[serde(rename = "orderId")]
+    order_id: u64,
+
+    #[serde(default)]
+    status: String,
+
+    // When it was placed, or last updated for an order that was looked up
+    #[serde(default)]
+    #[serde(rename = "transactTime", alias = "updateTime")]
+    transact_time: u64,
+
+    #[serde(default, deserialize_with = "deserialize_f64_from_string")]
+    #[serde(rename = "executedQty")]
+    executed_qty: f64,
+
+    #[serde(default, deserialize_with = "deserialize_f64_from_string")]
+    #[serde(rename = "cummulativeQuoteQty")]
+    cummulative_quote_qty: f64,
+
+    // BUY or SELL
+    #[serde(default)]
+    side: String,
+}
+
+impl OrderResponse {
+    // The fill of an order that executed, `sign` being the direction it was placed in
+    fn fill(&self, instrument: &str, sign: f64) -> BrokerFill {
+        BrokerFill {
+            time: calendar::utc(self.transact_time).to_rfc3339(),
+            instrument: instrument.to_string(),
+            units: self.executed_qty * sign,
+            price: Some(self.cummulative_quote_qty / self.executed_qty),
+            id: Some(self.order_id.to_string()),
+            pl: None,
+        }
+    }
+}
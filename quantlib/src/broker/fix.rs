@@ -0,0 +1,1013 @@
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::error::Error;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::sync::{mpsc, oneshot};
+use tokio::task::JoinHandle;
+use tokio::time::timeout;
+
+use crate::broker::{
+    AccountProvider, AccountState, Broker, BrokerFill, BrokerPosition, BrokerPriceStream,
+    MarketDataProvider, OrderExecutor,
+};
+use crate::oanda::errors::OrderStateUnknownError;
+use crate::oanda::objects::{Instrument, Price};
+
+// Orders routed over a FIX 4.4 session, for brokers and prime services that only accept FIX.
+// Prices and the account still come from another backend, whichever the FIX session fills into,
+// e.g. OANDA's FIX gateway fills into the same account its REST API reports on.
+//
+// The session runs in a task of its own, logging on, answering heartbeats and test requests and
+// reconnecting whenever the connection fails. Sequence numbers are saved to a file as every
+// message is sent or received, so a restart carries on where the last session stopped instead
+// of logging on afresh. Messages the counterparty missed are never resent: a resend request is
+// answered with a gap fill, as a market order sent again late would fill at a price nobody
+// decided on. Gaps in what the counterparty sent are asked for again with a resend request.
+
+const BEGIN_STRING: &str = "FIX.4.4";
+const SOH: u8 = 0x01;
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+// How long an order waits for its final execution report, including any wait for a logon
+const ORDER_TIMEOUT: Duration = Duration::from_secs(30);
+
+// FIX session with a broker, "fix" in settings.json's credentials
+#[derive(Deserialize, Clone)]
+pub struct FixSettings {
+    pub host: String,
+    pub port: u16,
+
+    #[serde(rename = "senderCompId")]
+    pub sender_comp_id: String,
+
+    #[serde(rename = "targetCompId")]
+    pub target_comp_id: String,
+
+    // Sent on logon if given, the password in plain text or age encrypted (see secrets)
+    #[serde(default)]
+    pub username: Option<String>,
+
+    #[serde(default)]
+    pub password: Option<String>,
+
+    // Account orders are placed for, if the counterparty needs one
+    #[serde(default)]
+    pub account: Option<String>,
+
+    #[serde(default = "default_tls")]
+    pub tls: bool,
+
+    #[serde(default = "default_heartbeat")]
+    #[serde(rename = "heartbeatSeconds")]
+    pub heartbeat_seconds: u64,
+
+    // Where sequence numbers are kept between sessions
+    #[serde(default = "default_sequence_store")]
+    #[serde(rename = "sequenceStore")]
+    pub sequence_store: PathBuf,
+
+    // Start both sequences from 1 on every logon, for counterparties that reset daily
+    #[serde(default)]
+    #[serde(rename = "resetOnLogon")]
+    pub reset_on_logon: bool,
+}
+
+fn default_tls() -> bool {
+    true
+}
+
+fn default_heartbeat() -> u64 {
+    30
+}
+
+fn default_sequence_store() -> PathBuf {
+    PathBuf::from("fix_sequence.json")
+}
+
+// Never print the password
+impl std::fmt::Debug for FixSettings {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("FixSettings")
+            .field("host", &self.host)
+            .field("port", &self.port)
+            .field("sender_comp_id", &self.sender_comp_id)
+            .field("target_comp_id", &self.target_comp_id)
+            .field("username", &self.username)
+            .field("password", &self.password.as_ref().map(|_| "[REDACTED]"))
+            .finish()
+    }
+}
+
+// A FIX message as tag=value fields, without the header and trailer fields the session adds
+#[derive(Debug, Clone)]
+pub struct FixMessage {
+    pub msg_type: String,
+    pub fields: Vec<(u32, String)>,
+}
+
+impl FixMessage {
+    pub fn new(msg_type: &str) -> Self {
+        FixMessage {
+            msg_type: msg_type.to_string(),
+            fields: Vec::new(),
+        }
+    }
+
+    pub fn with(mut self, tag: u32, value: impl ToString) -> Self {
+        self.fields.push((tag, value.to_string()));
+        self
+    }
+
+    pub fn get(&self, tag: u32) -> Option<&str> {
+        self.fields
+            .iter()
+            .find(|(field, _)| *field == tag)
+            .map(|(_, value)| value.as_str())
+    }
+
+    fn get_f64(&self, tag: u32) -> Option<f64> {
+        self.get(tag).and_then(|value| value.parse().ok())
+    }
+
+    // The full message with BeginString, BodyLength and CheckSum
+    pub fn encode(&self, sender: &str, target: &str, seq: u64, sending_time: &str) -> Vec<u8> {
+        let mut body = format!(
+            "35={}\x0149={}\x0156={}\x0134={}\x0152={}\x01",
+            self.msg_type, sender, target, seq, sending_time
+        );
+        for (tag, value) in &self.fields {
+            body.push_str(&format!("{}={}\x01", tag, value));
+        }
+        let mut message = format!("8={}\x019={}\x01{}", BEGIN_STRING, body.len(), body);
+        let checksum = message.bytes().fold(0u32, |sum, byte| sum + byte as u32) % 256;
+        message.push_str(&format!("10={:03}\x01", checksum));
+        message.into_bytes()
+    }
+
+    // A complete message as received, checking its checksum
+    pub fn parse(raw: &[u8]) -> Result<Self, String> {
+        let text = String::from_utf8_lossy(raw);
+        let mut fields = Vec::new();
+        for field in text.split('\x01').filter(|field| !field.is_empty()) {
+            let (tag, value) = field
+                .split_once('=')
+                .ok_or_else(|| format!("Malformed field {}", field))?;
+            let tag: u32 = tag.parse().map_err(|_| format!("Malformed tag {}", tag))?;
+            fields.push((tag, value.to_string()));
+        }
+
+        // Found in the raw bytes, as the text above may not line up with them if some weren't
+        // valid UTF-8
+        let checksum_start = raw
+            .windows(4)
+            .rposition(|window| window == b"\x0110=")
+            .map(|index| index + 1)
+            .ok_or("Message without a checksum")?;
+        let expected = raw[..checksum_start]
+            .iter()
+            .fold(0u32, |sum, byte| sum + *byte as u32)
+            % 256;
+        let checksum = fields
+            .last()
+            .filter(|(tag, _)| *tag == 10)
+            .and_then(|(_, value)| value.parse::<u32>().ok());
+        if checksum != Some(expected) {
+            return Err(format!("Bad checksum, expected {:03}", expected));
+        }
+
+        let msg_type = fields
+            .iter()
+            .find(|(tag, _)| *tag == 35)
+            .map(|(_, value)| value.clone())
+            .ok_or("Message without a MsgType")?;
+        Ok(FixMessage { msg_type, fields })
+    }
+
+    fn seq(&self) -> Option<u64> {
+        self.get(34).and_then(|seq| seq.parse().ok())
+    }
+
+    fn is_possible_duplicate(&self) -> bool {
+        self.get(43) == Some("Y")
+    }
+}
+
+// Take the first complete message off the front of what has been received, if there is one
+pub fn take_message(buffer: &mut Vec<u8>) -> Result<Option<Vec<u8>>, String> {
+    // 8=FIX.4.4|9=<length>| then the body, then 10=xxx|
+    let header_end = match buffer
+        .iter()
+        .enumerate()
+        .filter(|(_, byte)| **byte == SOH)
+        .nth(1)
+    {
+        Some((index, _)) => index + 1,
+        None => return Ok(None),
+    };
+    let header = String::from_utf8_lossy(&buffer[..header_end]).to_string();
+    let length: usize = header
+        .split('\x01')
+        .find_map(|field| field.strip_prefix("9="))
+        .and_then(|length| length.parse().ok())
+        .ok_or_else(|| format!("Malformed header {}", header.replace('\x01', "|")))?;
+    let end = header_end + length + "10=000\x01".len();
+    if buffer.len() < end {
+        return Ok(None);
+    }
+    Ok(Some(buffer.drain(..end).collect()))
+}
+
+// Next sequence number to send and to receive
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SequenceNumbers {
+    pub outgoing: u64,
+    pub incoming: u64,
+}
+
+impl Default for SequenceNumbers {
+    fn default() -> Self {
+        SequenceNumbers {
+            outgoing: 1,
+            incoming: 1,
+        }
+    }
+}
+
+impl SequenceNumbers {
+    pub fn load(path: &Path) -> Result<Self, Box<dyn Error>> {
+        if !path.exists() {
+            return Ok(SequenceNumbers::default());
+        }
+        Ok(serde_json::from_str(&std::fs::read_to_string(path)?)?)
+    }
+
+    // Written under a temporary name and renamed into place, so a crash never leaves half a file
+    pub fn save(&self, path: &Path) -> std::io::Result<()> {
+        let mut temporary = path.as_os_str().to_os_string();
+        temporary.push(".tmp");
+        std::fs::write(&temporary, serde_json::to_string(self)?)?;
+        std::fs::rename(&temporary, path)
+    }
+}
+
+trait FixStream: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> FixStream for T {}
+
+// An order waiting to be sent, and where its execution reports go
+struct FixRequest {
+    message: FixMessage,
+    cl_ord_id: String,
+
+    // Resting orders are done once accepted, others once filled, cancelled or rejected
+    resting: bool,
+    deadline: Instant,
+    reply: oneshot::Sender<Result<Vec<FixMessage>, String>>,
+}
+
+struct PendingOrder {
+    seq: u64,
+    resting: bool,
+    reports: Vec<FixMessage>,
+    reply: oneshot::Sender<Result<Vec<FixMessage>, String>>,
+}
+
+struct Session<'a> {
+    settings: &'a FixSettings,
+    sequences: &'a mut SequenceNumbers,
+    stream: Box<dyn FixStream>,
+    buffer: Vec<u8>,
+    logged_on: bool,
+    last_sent: Instant,
+    last_received: Instant,
+    test_request: Option<String>,
+
+    // The last sequence number a resend request covers, while it's outstanding
+    resend_until: Option<u64>,
+    pending: HashMap<String, PendingOrder>,
+}
+
+impl Session<'_> {
+    fn sending_time() -> String {
+        chrono::Utc::now().format("%Y%m%d-%H:%M:%S%.3f").to_string()
+    }
+
+    async fn send(&mut self, message: &FixMessage) -> Result<u64, String> {
+        let seq = self.sequences.outgoing;
+        self.send_as(message, seq).await?;
+        self.sequences.outgoing += 1;
+        self.save()?;
+        Ok(seq)
+    }
+
+    async fn send_as(&mut self, message: &FixMessage, seq: u64) -> Result<(), String> {
+        let raw = message.encode(
+            &self.settings.sender_comp_id,
+            &self.settings.target_comp_id,
+            seq,
+            &Self::sending_time(),
+        );
+        log::debug!(
+            "[fix] -> {}",
+            String::from_utf8_lossy(&raw).replace('\x01', "|")
+        );
+        self.stream
+            .write_all(&raw)
+            .await
+            .map_err(|e| format!("Failed to send: {}", e))?;
+        self.last_sent = Instant::now();
+        Ok(())
+    }
+
+    fn save(&self) -> Result<(), String> {
+        self.sequences
+            .save(&self.settings.sequence_store)
+            .map_err(|e| {
+                format!(
+                    "Failed to save sequence numbers to {}: {}",
+                    self.settings.sequence_store.display(),
+                    e
+                )
+            })
+    }
+
+    async fn logon(&mut self) -> Result<(), String> {
+        if self.settings.reset_on_logon {
+            *self.sequences = SequenceNumbers::default();
+        }
+        let mut logon = FixMessage::new("A")
+            .with(98, 0)
+            .with(108, self.settings.heartbeat_seconds);
+        if self.settings.reset_on_logon {
+            logon = logon.with(141, "Y");
+        }
+        if let Some(username) = &self.settings.username {
+            logon = logon.with(553, username);
+        }
+        if let Some(password) = &self.settings.password {
+            logon = logon.with(554, password);
+        }
+        self.send(&logon).await?;
+        Ok(())
+    }
+
+    // Read what has arrived and handle every complete message in it
+    async fn receive(&mut self, bytes: &[u8]) -> Result<(), String> {
+        if bytes.is_empty() {
+            return Err("Connection closed by the counterparty".to_string());
+        }
+        self.buffer.extend_from_slice(bytes);
+        self.last_received = Instant::now();
+        while let Some(raw) = take_message(&mut self.buffer)? {
+            log::debug!(
+                "[fix] <- {}",
+                String::from_utf8_lossy(&raw).replace('\x01', "|")
+            );
+            let message = FixMessage::parse(&raw)?;
+            self.handle(message).await?;
+        }
+        Ok(())
+    }
+
+    async fn handle(&mut self, message: FixMessage) -> Result<(), String> {
+        let seq = message.seq().ok_or("Message without a MsgSeqNum")?;
+
+        // A sequence reset moves the expected number whatever the message's own number is
+        if message.msg_type == "4" {
+            let next = message
+                .get(36)
+                .and_then(|next| next.parse().ok())
+                .ok_or("SequenceReset without NewSeqNo")?;
+            if next > self.sequences.incoming {
+                self.sequences.incoming = next;
+                self.save()?;
+            }
+            if self.resend_until.is_some_and(|until| next > until) {
+                self.resend_until = None;
+            }
+            return Ok(());
+        }
+        if message.msg_type == "A" && message.get(141) == Some("Y") {
+            self.sequences.incoming = seq;
+        }
+
+        if seq < self.sequences.incoming {
+            if message.is_possible_duplicate() {
+                return Ok(());
+            }
+            return Err(format!(
+                "Received sequence number {}, expected {}",
+                seq, self.sequences.incoming
+            ));
+        }
+        if seq > self.sequences.incoming {
+            // Logons and logouts are acted on even out of sequence, the rest arrives again
+            if self.resend_until.is_none() {
+                log::warn!(
+                    "[fix] Received sequence number {}, expected {}, asking for a resend",
+                    seq,
+                    self.sequences.incoming
+                );
+                let resend = FixMessage::new("2")
+                    .with(7, self.sequences.incoming)
+                    .with(16, 0);
+                self.send(&resend).await?;
+                self.resend_until = Some(seq);
+            }
+            return match message.msg_type.as_str() {
+                "A" => {
+                    self.logged_on = true;
+                    Ok(())
+                }
+                "5" => Err(format!(
+                    "Logged out: {}",
+                    message.get(58).unwrap_or("no reason given")
+                )),
+                _ => Ok(()),
+            };
+        }
+
+        self.sequences.incoming += 1;
+        self.save()?;
+        if self.resend_until.is_some_and(|until| seq >= until) {
+            self.resend_until = None;
+        }
+
+        match message.msg_type.as_str() {
+            "A" => {
+                self.logged_on = true;
+                log::info!(
+                    "[fix] Logged on to {} as {}",
+                    self.settings.target_comp_id,
+                    self.settings.sender_comp_id
+                );
+            }
+            "0" => {
+                if message.get(112).is_some() && message.get(112) == self.test_request.as_deref() {
+                    self.test_request = None;
+                }
+            }
+            "1" => {
+                let mut heartbeat = FixMessage::new("0");
+                if let Some(id) = message.get(112) {
+                    heartbeat = heartbeat.with(112, id);
+                }
+                self.send(&heartbeat).await?;
+            }
+            "2" => self.gap_fill(&message).await?,
+            "3" | "j" => self.reject(&message),
+            "5" => {
+                return Err(format!(
+                    "Logged out: {}",
+                    message.get(58).unwrap_or("no reason given")
+                ))
+            }
+            "8" => self.execution_report(message),
+            "9" => self.cancel_reject(message),
+            other => log::debug!("[fix] Ignoring message of type {}", other),
+        }
+        Ok(())
+    }
+
+    // Answer a resend request by skipping everything asked for
+    async fn gap_fill(&mut self, request: &FixMessage) -> Result<(), String> {
+        let begin: u64 = request
+            .get(7)
+            .and_then(|begin| begin.parse().ok())
+            .ok_or("ResendRequest without BeginSeqNo")?;
+        log::warn!(
+            "[fix] Counterparty asked for messages from {}, skipping them with a gap fill",
+            begin
+        );
+        let gap_fill = FixMessage::new("4")
+            .with(43, "Y")
+            .with(122, Self::sending_time())
+            .with(123, "Y")
+            .with(36, self.sequences.outgoing);
+        self.send_as(&gap_fill, begin).await
+    }
+
+    fn reject(&mut self, reject: &FixMessage) {
+        let text = reject.get(58).unwrap_or("no reason given").to_string();
+        let seq: Option<u64> = reject.get(45).and_then(|seq| seq.parse().ok());
+        let cl_ord_id = self
+            .pending
+            .iter()
+            .find(|(_, order)| Some(order.seq) == seq)
+            .map(|(cl_ord_id, _)| cl_ord_id.clone());
+        match cl_ord_id.and_then(|cl_ord_id| self.pending.remove(&cl_ord_id)) {
+            Some(order) => {
+                let _ = order.reply.send(Err(format!("Order rejected: {}", text)));
+            }
+            None => log::warn!("[fix] Message {:?} rejected: {}", seq, text),
+        }
+    }
+
+    fn execution_report(&mut self, report: FixMessage) {
+        let cl_ord_id = match report.get(11) {
+            Some(cl_ord_id) => cl_ord_id.to_string(),
+            None => return,
+        };
+        let order = match self.pending.get_mut(&cl_ord_id) {
+            Some(order) => order,
+            None => {
+                log::info!(
+                    "[fix] Execution report for order {} that isn't waiting: status {}",
+                    cl_ord_id,
+                    report.get(39).unwrap_or("unknown")
+                );
+                return;
+            }
+        };
+
+        // OrdStatus: 0 new, 2 filled, 4 cancelled, 8 rejected, C expired
+        let status = report.get(39).unwrap_or_default().to_string();
+        order.reports.push(report);
+        let done = match status.as_str() {
+            "2" | "4" | "8" | "C" => true,
+            "0" => order.resting,
+            _ => false,
+        };
+        if done {
+            if let Some(order) = self.pending.remove(&cl_ord_id) {
+                let _ = order.reply.send(Ok(order.reports));
+            }
+        }
+    }
+
+    // An OrderCancelReject ends the cancel request, and goes back with its reports for the
+    // router to tell an order that had already gone from one that couldn't be cancelled
+    fn cancel_reject(&mut self, reject: FixMessage) {
+        let order = reject
+            .get(11)
+            .and_then(|cl_ord_id| self.pending.remove(cl_ord_id));
+        match order {
+            Some(mut order) => {
+                order.reports.push(reject);
+                let _ = order.reply.send(Ok(order.reports));
+            }
+            None => log::info!(
+                "[fix] Cancel of order {} rejected: {}",
+                reject.get(41).unwrap_or("unknown"),
+                reject.get(58).unwrap_or("no reason given")
+            ),
+        }
+    }
+
+    async fn place(&mut self, request: FixRequest) -> Result<(), String> {
+        if Instant::now() >= request.deadline {
+            let _ = request.reply.send(Err(
+                "Timed out before the session was logged on, not sent".to_string()
+            ));
+            return Ok(());
+        }
+        let seq = self.send(&request.message).await?;
+        self.pending.insert(
+            request.cl_ord_id,
+            PendingOrder {
+                seq,
+                resting: request.resting,
+                reports: Vec::new(),
+                reply: request.reply,
+            },
+        );
+        Ok(())
+    }
+
+    // Stop waiting on orders the router has given up on, see FixOrderRouter::send. A late report
+    // for one is only logged, the router has already had the positions reconciled.
+    fn forget_abandoned(&mut self) {
+        self.pending.retain(|cl_ord_id, order| {
+            if order.reply.is_closed() {
+                log::warn!("[fix] No longer waiting for order {}", cl_ord_id);
+            }
+            !order.reply.is_closed()
+        });
+    }
+
+    // Keep the connection alive while it's idle, and notice when the counterparty goes quiet
+    async fn keep_alive(&mut self) -> Result<(), String> {
+        let interval = Duration::from_secs(self.settings.heartbeat_seconds.max(1));
+        if self.last_received.elapsed() > interval * 2 && self.test_request.is_some() {
+            return Err("No response to a test request".to_string());
+        }
+        if self.last_received.elapsed() > interval + interval / 5 && self.test_request.is_none() {
+            let id = chrono::Utc::now().timestamp_millis().to_string();
+            self.send(&FixMessage::new("1").with(112, &id)).await?;
+            self.test_request = Some(id);
+        }
+        if self.last_sent.elapsed() >= interval {
+            self.send(&FixMessage::new("0")).await?;
+        }
+        Ok(())
+    }
+
+    // Run until the connection fails, or until nothing can send orders any more
+    async fn run(&mut self, requests: &mut mpsc::Receiver<FixRequest>) -> Result<bool, String> {
+        self.logon().await?;
+        let mut ticker = tokio::time::interval(Duration::from_secs(1));
+        let mut chunk = vec![0u8; 8192];
+        loop {
+            tokio::select! {
+                read = self.stream.read(&mut chunk) => {
+                    let read = read.map_err(|e| format!("Failed to read: {}", e))?;
+                    self.receive(&chunk[..read]).await?;
+                }
+                request = requests.recv(), if self.logged_on => match request {
+                    Some(request) => self.place(request).await?,
+                    None => {
+                        let _ = self.send(&FixMessage::new("5")).await;
+                        return Ok(false);
+                    }
+                },
+                _ = ticker.tick() => {
+                    self.forget_abandoned();
+                    self.keep_alive().await?;
+                }
+            }
+        }
+    }
+}
+
+async fn connect(settings: &FixSettings) -> Result<Box<dyn FixStream>, String> {
+    let address = format!("{}:{}", settings.host, settings.port);
+    let tcp = timeout(CONNECT_TIMEOUT, TcpStream::connect(&address))
+        .await
+        .map_err(|_| format!("Timed out connecting to {}", address))?
+        .map_err(|e| format!("Failed to connect to {}: {}", address, e))?;
+    if !settings.tls {
+        return Ok(Box::new(tcp));
+    }
+    let connector = tokio_native_tls::native_tls::TlsConnector::new()
+        .map_err(|e| format!("Failed to set up TLS: {}", e))?;
+    let tls = tokio_native_tls::TlsConnector::from(connector)
+        .connect(&settings.host, tcp)
+        .await
+        .map_err(|e| format!("TLS handshake with {} failed: {}", address, e))?;
+    Ok(Box::new(tls))
+}
+
+// Keep a session up until every FixOrderRouter is gone
+async fn run_session(settings: FixSettings, mut requests: mpsc::Receiver<FixRequest>) {
+    let mut sequences = match SequenceNumbers::load(&settings.sequence_store) {
+        Ok(sequences) => sequences,
+        Err(e) => {
+            log::error!(
+                "[fix] Failed to read {}: {}, starting sequences from 1",
+                settings.sequence_store.display(),
+                e
+            );
+            SequenceNumbers::default()
+        }
+    };
+    loop {
+        let stream = match connect(&settings).await {
+            Ok(stream) => stream,
+            Err(e) => {
+                log::error!("[fix] {}", e);
+                tokio::time::sleep(RECONNECT_DELAY).await;
+                continue;
+            }
+        };
+        let mut session = Session {
+            settings: &settings,
+            sequences: &mut sequences,
+            stream,
+            buffer: Vec::new(),
+            logged_on: false,
+            last_sent: Instant::now(),
+            last_received: Instant::now(),
+            test_request: None,
+            resend_until: None,
+            pending: HashMap::new(),
+        };
+        let result = session.run(&mut requests).await;
+
+        // Orders sent but not answered may or may not have filled
+        for (cl_ord_id, order) in session.pending.drain() {
+            let _ = order.reply.send(Err(format!(
+                "Session lost waiting for order {} to complete",
+                cl_ord_id
+            )));
+        }
+        match result {
+            Ok(_) => return,
+            Err(e) => {
+                log::error!("[fix] Session failed: {}, reconnecting...", e);
+                tokio::time::sleep(RECONNECT_DELAY).await;
+            }
+        }
+    }
+}
+
+// Places orders over the FIX session, which runs as long as the router does
+pub struct FixOrderRouter {
+    requests: mpsc::Sender<FixRequest>,
+    task: JoinHandle<()>,
+    account: Option<String>,
+    next_id: Cell<u64>,
+}
+
+impl FixOrderRouter {
+    // Must be called from within a tokio runtime, as the session runs in a task of its own
+    pub fn new(settings: &FixSettings) -> Self {
+        let (requests, receiver) = mpsc::channel(64);
+        let task = tokio::spawn(run_session(settings.clone(), receiver));
+        FixOrderRouter {
+            requests,
+            task,
+            account: settings.account.clone(),
+            next_id: Cell::new(0),
+        }
+    }
+
+    // Unique across restarts, as the counterparty may reject a ClOrdID it has seen before
+    fn cl_ord_id(&self) -> String {
+        let id = self.next_id.get();
+        self.next_id.set(id + 1);
+        format!("{}-{}", chrono::Utc::now().timestamp_millis(), id)
+    }
+
+    fn order(&self, instrument: &str, units: f64, cl_ord_id: &str) -> FixMessage {
+        let mut message = FixMessage::new("D").with(11, cl_ord_id);
+        if let Some(account) = &self.account {
+            message = message.with(1, account);
+        }
+        message
+            .with(55, symbol(instrument))
+            .with(54, if units > 0.0 { 1 } else { 2 })
+            .with(60, Session::sending_time())
+            .with(38, units.abs())
+    }
+
+    // Send an order and wait for its execution reports
+    async fn send(
+        &self,
+        message: FixMessage,
+        cl_ord_id: String,
+        resting: bool,
+    ) -> Result<Vec<FixMessage>, Box<dyn Error>> {
+        let (reply, response) = oneshot::channel();
+        let request = FixRequest {
+            message,
+            cl_ord_id: cl_ord_id.clone(),
+            resting,
+            deadline: Instant::now() + ORDER_TIMEOUT,
+            reply,
+        };
+        self.requests
+            .send(request)
+            .await
+            .map_err(|_| "The FIX session has stopped")?;
+        let unknown = |message: String| -> Box<dyn Error> {
+            Box::new(OrderStateUnknownError {
+                client_order_id: cl_ord_id.clone(),
+                message,
+            })
+        };
+        match timeout(ORDER_TIMEOUT * 2, response).await {
+            Ok(Ok(Ok(reports))) => Ok(reports),
+            Ok(Ok(Err(e))) if e.starts_with("Session lost") => Err(unknown(e)),
+            Ok(Ok(Err(e))) => Err(e.into()),
+            Ok(Err(_)) => Err(unknown("The FIX session stopped".to_string())),
+            Err(_) => Err(unknown("No execution report in time".to_string())),
+        }
+    }
+
+    // Buy (positive units) or sell at the market, immediate or cancel
+    pub async fn market_order(
+        &self,
+        instrument: &str,
+        units: f64,
+    ) -> Result<Option<BrokerFill>, Box<dyn Error>> {
+        let cl_ord_id = self.cl_ord_id();
+        let message = self
+            .order(instrument, units, &cl_ord_id)
+            .with(40, 1)
+            .with(59, 3);
+        let reports = self.send(message, cl_ord_id, false).await?;
+
+        // ExecType F is a fill, of LastQty at LastPx
+        let fills: Vec<&FixMessage> = reports
+            .iter()
+            .filter(|report| report.get(150) == Some("F"))
+            .collect();
+        let quantity: f64 = fills.iter().filter_map(|fill| fill.get_f64(32)).sum();
+        if quantity == 0.0 {
+            let last = reports.last();
+            log::warn!(
+                "[{}] Market order wasn't filled: {}",
+                instrument,
+                last.and_then(|report| report.get(58))
+                    .or(last.and_then(|report| report.get(39)))
+                    .unwrap_or("no reason given")
+            );
+            return Ok(None);
+        }
+        let value: f64 = fills
+            .iter()
+            .filter_map(|fill| Some(fill.get_f64(32)? * fill.get_f64(31)?))
+            .sum();
+        let last = fills.last().copied();
+        Ok(Some(BrokerFill {
+            time: fills
+                .iter()
+                .rev()
+                .find_map(|fill| fill.get(60).and_then(fix_time))
+                .unwrap_or_else(|| chrono::Utc::now().to_rfc3339()),
+            instrument: instrument.to_string(),
+            units: quantity * units.signum(),
+            price: Some(value / quantity),
+            id: last.and_then(|fill| fill.get(37)).map(str::to_string),
+            pl: None,
+        }))
+    }
+
+    // A good till cancelled stop order, returning its ClOrdID once accepted, which is what a
+    // cancel refers to
+    pub async fn stop_order(
+        &self,
+        instrument: &str,
+        units: f64,
+        price: f64,
+    ) -> Result<String, Box<dyn Error>> {
+        let cl_ord_id = self.cl_ord_id();
+        let message = self
+            .order(instrument, units, &cl_ord_id)
+            .with(40, 3)
+            .with(99, price)
+            .with(59, 1);
+        let reports = self.send(message, cl_ord_id.clone(), true).await?;
+        let report = reports.last().ok_or("No execution report")?;
+        match report.get(39) {
+            Some("0") => Ok(cl_ord_id),
+            _ => Err(format!(
+                "[{}] Stop order not accepted: {}",
+                instrument,
+                report.get(58).unwrap_or("no reason given")
+            )
+            .into()),
+        }
+    }
+
+    // Cancel the order placed as `orig_cl_ord_id`, false if it was no longer open
+    pub async fn cancel_order(
+        &self,
+        instrument: &str,
+        units: f64,
+        orig_cl_ord_id: &str,
+    ) -> Result<bool, Box<dyn Error>> {
+        let cl_ord_id = self.cl_ord_id();
+        let mut message = FixMessage::new("F")
+            .with(11, &cl_ord_id)
+            .with(41, orig_cl_ord_id);
+        if let Some(account) = &self.account {
+            message = message.with(1, account);
+        }
+        let message = message
+            .with(55, symbol(instrument))
+            .with(54, if units > 0.0 { 1 } else { 2 })
+            .with(60, Session::sending_time())
+            .with(38, units.abs());
+        let reports = self.send(message, cl_ord_id, false).await?;
+        let report = reports.last().ok_or("No execution report")?;
+        if report.msg_type != "9" {
+            return Ok(report.get(39) == Some("4"));
+        }
+        // CxlRejReason 1 is an unknown order, OrdStatus 2, 4 and C one that's done already
+        match (report.get(102), report.get(39)) {
+            (Some("1"), _) | (_, Some("2" | "4" | "C")) => Ok(false),
+            _ => Err(format!(
+                "[{}] Cancel of order {} rejected: {}",
+                instrument,
+                orig_cl_ord_id,
+                report.get(58).unwrap_or("no reason given")
+            )
+            .into()),
+        }
+    }
+}
+
+impl Drop for FixOrderRouter {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+// Instruments are sent as BASE/QUOTE, e.g. EUR/USD
+fn symbol(instrument: &str) -> String {
+    instrument.replace('_', "/")
+}
+
+// TransactTime, e.g. 20240102-13:45:01.123, as RFC 3339
+fn fix_time(time: &str) -> Option<String> {
+    let format = match time.contains('.') {
+        true => "%Y%m%d-%H:%M:%S%.f",
+        false => "%Y%m%d-%H:%M:%S",
+    };
+    let time = chrono::NaiveDateTime::parse_from_str(time, format).ok()?;
+    Some(time.and_utc().to_rfc3339())
+}
+
+// Orders over FIX, prices and the account through another backend, "broker" in the trading
+// config as {"type": "fix", "data": {"type": "oanda"}}
+pub struct FixBroker {
+    data: Box<dyn Broker>,
+    router: FixOrderRouter,
+}
+
+impl FixBroker {
+    // Must be called from within a tokio runtime, see FixOrderRouter
+    pub fn new(settings: &FixSettings, data: Box<dyn Broker>) -> Self {
+        FixBroker {
+            data,
+            router: FixOrderRouter::new(settings),
+        }
+    }
+}
+
+#[async_trait(?Send)]
+impl MarketDataProvider for FixBroker {
+    fn name(&self) -> &str {
+        "fix"
+    }
+
+    async fn latest_prices(&self, instruments: &[String]) -> Result<Vec<Price>, Box<dyn Error>> {
+        self.data.latest_prices(instruments).await
+    }
+
+    async fn instruments(&self, instruments: &[String]) -> Result<Vec<Instrument>, Box<dyn Error>> {
+        self.data.instruments(instruments).await
+    }
+
+    fn price_stream(&self, instruments: &[String]) -> Result<BrokerPriceStream, Box<dyn Error>> {
+        self.data.price_stream(instruments)
+    }
+}
+
+#[async_trait(?Send)]
+impl OrderExecutor for FixBroker {
+    async fn market_order(
+        &self,
+        instrument: &str,
+        units: f64,
+    ) -> Result<Option<BrokerFill>, Box<dyn Error>> {
+        self.router.market_order(instrument, units).await
+    }
+
+    async fn close_position(&self, instrument: &str) -> Result<Vec<BrokerFill>, Box<dyn Error>> {
+        let held = self
+            .data
+            .positions()
+            .await?
+            .into_iter()
+            .find(|position| position.instrument == instrument)
+            .map(|position| position.units)
+            .unwrap_or(0.0);
+        if held == 0.0 {
+            return Ok(Vec::new());
+        }
+        Ok(self
+            .router
+            .market_order(instrument, -held)
+            .await?
+            .into_iter()
+            .collect())
+    }
+
+    async fn protective_stop(
+        &self,
+        instrument: &str,
+        units: f64,
+        price: f64,
+    ) -> Result<String, Box<dyn Error>> {
+        self.router.stop_order(instrument, units, price).await
+    }
+
+    async fn cancel_order(
+        &self,
+        instrument: &str,
+        units: f64,
+        order_id: &str,
+    ) -> Result<bool, Box<dyn Error>> {
+        self.router.cancel_order(instrument, units, order_id).await
+    }
+}
+
+#[async_trait(?Send)]
+impl AccountProvider for FixBroker {
+    async fn account(&self) -> Result<AccountState, Box<dyn Error>> {
+        self.data.account().await
+    }
+
+    async fn positions(&self) -> Result<Vec<BrokerPosition>, Box<dyn Error>> {
+        self.data.positions().await
+    }
+}
@@ -0,0 +1,211 @@
+use std::error::Error;
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+
+use crate::oanda::objects::{Instrument, Price, StreamItem};
+
+#[cfg(feature = "trading")]
+pub mod binance;
+#[cfg(feature = "trading")]
+pub use binance::*;
+
+#[cfg(feature = "trading")]
+pub mod fix;
+#[cfg(feature = "trading")]
+pub use fix::*;
+
+#[cfg(feature = "trading")]
+pub mod oanda;
+#[cfg(feature = "trading")]
+pub use oanda::*;
+
+// Broker-agnostic access to prices, orders and the account, so that strategies and the engine
+// can trade through any backend. OANDA is the first (see broker::oanda) and Binance spot the
+// second (see broker::binance), others only need to implement the three traits below. Orders
+// can also be routed over FIX with another backend's prices and account (see broker::fix).
+// Instruments are named BASE_QUOTE as OANDA names them, e.g. "EUR_USD" or "BTC_USDT", so that
+// position sizing and currency conversion work the same for every backend.
+
+// Items of a backend's price stream, the same items OANDA's streams give. The stream owns what it
+// needs, so the trader can read it on a thread of its own.
+pub type BrokerPriceStream = Box<dyn Iterator<Item = Result<StreamItem, Box<dyn Error>>> + Send>;
+
+// An order filled by a broker
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct BrokerFill {
+    pub time: String,
+    pub instrument: String,
+    pub units: f64,
+    pub price: Option<f64>,
+
+    // The broker's ID of the fill or of the order that caused it
+    pub id: Option<String>,
+
+    // Profit realized by the fill, in the account currency
+    pub pl: Option<f64>,
+}
+
+// Net position in an instrument, negative units for short
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct BrokerPosition {
+    pub instrument: String,
+    pub units: f64,
+
+    #[serde(rename = "unrealizedPl")]
+    pub unrealized_pl: f64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct AccountState {
+    pub currency: String,
+    pub balance: f64,
+    pub nav: f64,
+
+    #[serde(rename = "marginUsed")]
+    pub margin_used: f64,
+
+    #[serde(rename = "marginAvailable")]
+    pub margin_available: f64,
+}
+
+#[async_trait(?Send)]
+pub trait MarketDataProvider {
+    // Name of the backend, for logs
+    fn name(&self) -> &str;
+
+    // Latest price of each of the instruments
+    async fn latest_prices(&self, instruments: &[String]) -> Result<Vec<Price>, Box<dyn Error>>;
+
+    // Trading limits of the instruments, for sizing orders
+    async fn instruments(&self, instruments: &[String]) -> Result<Vec<Instrument>, Box<dyn Error>>;
+
+    // Prices of the instruments as they change, reconnecting on its own. Must be called from
+    // within a tokio runtime.
+    fn price_stream(&self, instruments: &[String]) -> Result<BrokerPriceStream, Box<dyn Error>>;
+}
+
+// What an order was placed for, so that it can be traced back to the strategy from the broker's
+// side. OANDA shows them as the client extensions of the order and the trade it opens. Empty
+// fields are left out.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct OrderTags {
+    // Strategies whose forecasts decided the order, comma separated
+    pub strategy: String,
+
+    // The signal the order was placed on, "<instrument>-<price time>" as in the journal
+    pub signal: String,
+
+    #[serde(rename = "configVersion")]
+    pub config_version: String,
+}
+
+#[async_trait(?Send)]
+pub trait OrderExecutor {
+    // Buy (positive units) or sell at the market, None if the order wasn't filled
+    async fn market_order(
+        &self,
+        instrument: &str,
+        units: f64,
+    ) -> Result<Option<BrokerFill>, Box<dyn Error>>;
+
+    // A market order carrying tags, for backends that can attach them to orders
+    async fn tagged_market_order(
+        &self,
+        instrument: &str,
+        units: f64,
+        _tags: &OrderTags,
+    ) -> Result<Option<BrokerFill>, Box<dyn Error>> {
+        self.market_order(instrument, units).await
+    }
+
+    // Close the whole position in an instrument
+    async fn close_position(&self, instrument: &str) -> Result<Vec<BrokerFill>, Box<dyn Error>>;
+
+    // Place a stop order for `units` at `price` that stays with the broker, returning its ID
+    async fn protective_stop(
+        &self,
+        instrument: &str,
+        units: f64,
+        price: f64,
+    ) -> Result<String, Box<dyn Error>>;
+
+    // Cancel a resting order placed for `units` of the instrument, false if it was no longer
+    // resting, e.g. already filled or cancelled
+    async fn cancel_order(
+        &self,
+        instrument: &str,
+        units: f64,
+        order_id: &str,
+    ) -> Result<bool, Box<dyn Error>>;
+
+    // What became of a market order whose state was unknown (see OrderStateUnknownError), looked
+    // up by the client order ID it was sent with: None if it never reached the broker, Some(None)
+    // if it didn't fill. Backends that can't look orders up say so with an error.
+    async fn order_outcome(
+        &self,
+        _instrument: &str,
+        client_order_id: &str,
+    ) -> Result<Option<Option<BrokerFill>>, Box<dyn Error>> {
+        Err(format!("Can't look up order {} by its client ID", client_order_id).into())
+    }
+}
+
+#[async_trait(?Send)]
+pub trait AccountProvider {
+    async fn account(&self) -> Result<AccountState, Box<dyn Error>>;
+
+    // Open positions, netted per instrument
+    async fn positions(&self) -> Result<Vec<BrokerPosition>, Box<dyn Error>>;
+}
+
+// Everything the engine needs from a backend
+pub trait Broker: MarketDataProvider + OrderExecutor + AccountProvider {}
+
+impl<T: MarketDataProvider + OrderExecutor + AccountProvider> Broker for T {}
+
+// Which backend to trade through, "broker" in the trading config, e.g. {"type": "oanda"}.
+// Without one the trader uses OANDA through the portfolio builder.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum BrokerConfig {
+    // The account in settings.json
+    Oanda,
+
+    // The Binance spot account of the API key in settings.json, or the spot testnet's
+    Binance {
+        #[serde(default)]
+        testnet: bool,
+    },
+
+    // Orders over the FIX session in settings.json, prices and the account from `data`
+    Fix {
+        #[serde(default = "default_fix_data")]
+        data: Box<BrokerConfig>,
+    },
+}
+
+fn default_fix_data() -> Box<BrokerConfig> {
+    Box::new(BrokerConfig::Oanda)
+}
+
+// API key of a Binance account, "binance" in settings.json's credentials
+#[derive(Deserialize, Clone)]
+pub struct BinanceSettings {
+    #[serde(rename = "apiKey")]
+    pub api_key: String,
+
+    // Signs requests, in plain text or age encrypted (see secrets)
+    #[serde(rename = "secretKey")]
+    pub secret_key: String,
+}
+
+// Never print the secret
+impl std::fmt::Debug for BinanceSettings {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("BinanceSettings")
+            .field("api_key", &self.api_key)
+            .field("secret_key", &"[REDACTED]")
+            .finish()
+    }
+}
@@ -0,0 +1,199 @@
+use std::error::Error;
+
+use async_trait::async_trait;
+
+use crate::broker::{
+    AccountProvider, AccountState, BrokerFill, BrokerPosition, BrokerPriceStream,
+    MarketDataProvider, OrderExecutor, OrderTags,
+};
+use crate::oanda::objects::{
+    Instrument, OandaSettings, PositionFill, PositionSide, Price, Transaction,
+};
+use crate::oanda::{self, OrderClient, ShardedPriceStream};
+
+// The OANDA account in settings.json as a broker backend
+pub struct OandaBroker {
+    settings: OandaSettings,
+    instruments_per_connection: usize,
+    timeout_duration: u64,
+
+    // Orders go through this when given, otherwise through a client made for each order
+    order_client: Option<OrderClient>,
+}
+
+impl OandaBroker {
+    pub fn new(settings: &OandaSettings) -> Self {
+        OandaBroker {
+            settings: settings.clone(),
+            instruments_per_connection: 20,
+            timeout_duration: 10_000,
+            order_client: None,
+        }
+    }
+
+    // Split the price stream over connections of at most this many instruments
+    pub fn with_instruments_per_connection(mut self, instruments: usize) -> Self {
+        self.instruments_per_connection = instruments;
+        self
+    }
+
+    // Place orders over a long-lived client with its connection kept open, see OrderClient
+    pub fn with_order_client(mut self, order_client: OrderClient) -> Self {
+        self.order_client = Some(order_client);
+        self
+    }
+}
+
+impl From<&Transaction> for BrokerFill {
+    fn from(transaction: &Transaction) -> Self {
+        BrokerFill {
+            time: transaction.time.clone(),
+            instrument: transaction.instrument.clone().unwrap_or_default(),
+            units: transaction.units.unwrap_or(0.0),
+            price: transaction.price,
+            id: transaction.id.clone(),
+            pl: transaction.pl,
+        }
+    }
+}
+
+#[async_trait(?Send)]
+impl MarketDataProvider for OandaBroker {
+    fn name(&self) -> &str {
+        "oanda"
+    }
+
+    async fn latest_prices(&self, instruments: &[String]) -> Result<Vec<Price>, Box<dyn Error>> {
+        oanda::get_latest_prices(instruments, &self.settings).await
+    }
+
+    async fn instruments(&self, instruments: &[String]) -> Result<Vec<Instrument>, Box<dyn Error>> {
+        oanda::get_instruments(instruments, &self.settings).await
+    }
+
+    fn price_stream(&self, instruments: &[String]) -> Result<BrokerPriceStream, Box<dyn Error>> {
+        Ok(Box::new(ShardedPriceStream::new(
+            instruments.to_vec(),
+            &self.settings,
+            self.instruments_per_connection,
+            self.timeout_duration,
+        )))
+    }
+}
+
+#[async_trait(?Send)]
+impl OrderExecutor for OandaBroker {
+    async fn market_order(
+        &self,
+        instrument: &str,
+        units: f64,
+    ) -> Result<Option<BrokerFill>, Box<dyn Error>> {
+        self.tagged_market_order(instrument, units, &OrderTags::default())
+            .await
+    }
+
+    async fn tagged_market_order(
+        &self,
+        instrument: &str,
+        units: f64,
+        tags: &OrderTags,
+    ) -> Result<Option<BrokerFill>, Box<dyn Error>> {
+        let fill = match &self.order_client {
+            Some(order_client) => {
+                order_client
+                    .market_order(instrument, units, PositionFill::Default, tags)
+                    .await?
+            }
+            None => {
+                oanda::place_market_order_with_fill(
+                    instrument,
+                    units,
+                    PositionFill::Default,
+                    tags,
+                    &self.settings,
+                )
+                .await?
+            }
+        };
+        Ok(fill.as_ref().map(BrokerFill::from))
+    }
+
+    async fn close_position(&self, instrument: &str) -> Result<Vec<BrokerFill>, Box<dyn Error>> {
+        // Hedging accounts can hold both sides at once, so each open side is closed
+        let position = match oanda::get_positions(&self.settings)
+            .await?
+            .into_iter()
+            .find(|position| position.instrument == instrument)
+        {
+            Some(position) => position,
+            None => return Ok(Vec::new()),
+        };
+        let mut fills = Vec::new();
+        for (side, units) in [
+            (PositionSide::Long, position.long.units),
+            (PositionSide::Short, position.short.units),
+        ] {
+            if units != 0.0 {
+                let transactions =
+                    oanda::close_position(instrument, side, None, &self.settings).await?;
+                fills.extend(transactions.iter().map(BrokerFill::from));
+            }
+        }
+        Ok(fills)
+    }
+
+    async fn protective_stop(
+        &self,
+        instrument: &str,
+        units: f64,
+        price: f64,
+    ) -> Result<String, Box<dyn Error>> {
+        oanda::place_protective_stop(instrument, units, price, &self.settings).await
+    }
+
+    async fn cancel_order(
+        &self,
+        _instrument: &str,
+        _units: f64,
+        order_id: &str,
+    ) -> Result<bool, Box<dyn Error>> {
+        oanda::cancel_order(order_id, &self.settings).await
+    }
+
+    async fn order_outcome(
+        &self,
+        _instrument: &str,
+        client_order_id: &str,
+    ) -> Result<Option<Option<BrokerFill>>, Box<dyn Error>> {
+        let outcome = oanda::get_order_outcome(client_order_id, &self.settings).await?;
+        Ok(outcome.map(|fill| fill.as_ref().map(BrokerFill::from)))
+    }
+}
+
+#[async_trait(?Send)]
+impl AccountProvider for OandaBroker {
+    async fn account(&self) -> Result<AccountState, Box<dyn Error>> {
+        let summary = oanda::get_account_summary(&self.settings).await?;
+        Ok(AccountState {
+            currency: summary.currency,
+            balance: summary.balance,
+            nav: summary.nav,
+            margin_used: summary.margin_used,
+            margin_available: summary.margin_available,
+        })
+    }
+
+    async fn positions(&self) -> Result<Vec<BrokerPosition>, Box<dyn Error>> {
+        // OANDA reports short units as negative already
+        Ok(oanda::get_positions(&self.settings)
+            .await?
+            .into_iter()
+            .map(|position| BrokerPosition {
+                units: position.long.units + position.short.units,
+                unrealized_pl: position.long.unrealized_pl + position.short.unrealized_pl,
+                instrument: position.instrument,
+            })
+            .filter(|position| position.units != 0.0)
+            .collect())
+    }
+}
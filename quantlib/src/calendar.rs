@@ -0,0 +1,59 @@
+use chrono::{DateTime, Datelike, Duration, NaiveDate, Utc, Weekday};
+
+// Every date in the data (file names, trading weeks, gaps, the hour of day) is in UTC, never the
+// local time zone, so conversions from timestamps all go through here
+pub fn utc(time: u64) -> DateTime<Utc> {
+    DateTime::from_timestamp_millis(time as i64).unwrap_or_default()
+}
+
+// The FX market opens on Sunday evening (UTC) and closes on Friday evening.
+// Data is grouped by trading week, which is the ISO week of the trading day: ticks from
+// Sunday's open belong to the week that follows, so a week is never split over two files.
+pub fn trading_week(time: u64) -> (i32, u32) {
+    let date = utc(time).date_naive();
+    let date = if date.weekday() == Weekday::Sun {
+        date + Duration::days(1)
+    } else {
+        date
+    };
+    let week = date.iso_week();
+    (week.year(), week.week())
+}
+
+// Milliseconds since the UNIX epoch at which a trading week begins (Sunday 00:00 UTC)
+pub fn week_start(year: i32, week: u32) -> u64 {
+    let monday = NaiveDate::from_isoywd_opt(year, week, Weekday::Mon).unwrap_or_default();
+    let sunday = monday - Duration::days(1);
+    sunday
+        .and_hms_opt(0, 0, 0)
+        .unwrap()
+        .and_utc()
+        .timestamp_millis() as u64
+}
+
+// Milliseconds since the UNIX epoch at which a trading week ends (the following Sunday 00:00 UTC)
+pub fn week_end(year: i32, week: u32) -> u64 {
+    week_start(year, week) + 7 * 86_400_000
+}
+
+// Milliseconds since the UNIX epoch at which the market opens for a trading week, taken as
+// Sunday 22:00 UTC. OANDA opens an hour earlier while New York is on summer time.
+pub fn week_open(year: i32, week: u32) -> u64 {
+    week_start(year, week) + 22 * 3_600_000
+}
+
+// Milliseconds since the UNIX epoch at which the market closes for the weekend at the end of a
+// trading week, taken as Friday 22:00 UTC. OANDA closes an hour earlier while New York is on
+// summer time.
+pub fn week_close(year: i32, week: u32) -> u64 {
+    week_start(year, week) + 5 * 86_400_000 + 22 * 3_600_000
+}
+
+// The name used for a week's files, matching the archive layout of end_of_week.py
+pub fn week_name(year: i32, week: u32) -> String {
+    format!("{}-{}", year, week)
+}
+
+pub fn current_week() -> (i32, u32) {
+    trading_week(Utc::now().timestamp_millis() as u64)
+}
@@ -0,0 +1,273 @@
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::calendar;
+use crate::data::{BinReader, MergedReader};
+use crate::errors::Context;
+use crate::oanda::objects::Price;
+
+// A week of cleaned data for one instrument
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CatalogEntry {
+    pub instrument: String,
+    pub year: i32,
+    pub week: u32,
+
+    // Relative to the archive directory
+    pub path: PathBuf,
+
+    pub records: u64,
+    pub first: u64,
+    pub last: u64,
+
+    // Whether the week had ended when the file was written
+    pub complete: bool,
+
+    // The week's manifest, read from beside its file when the catalog is loaded. Weeks archived
+    // before manifests were written have none until they're rewritten.
+    #[serde(skip)]
+    pub manifest: Option<DatasetManifest>,
+}
+
+// Describes a week's file, and is stored next to it as <file>.manifest.json, so weeks can be
+// checked and chosen between by their quality without reading them
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DatasetManifest {
+    pub instrument: String,
+    pub year: i32,
+    pub week: u32,
+
+    // Bounds of the trading week, milliseconds since the epoch
+    pub start: u64,
+    pub end: u64,
+
+    // Times of the first and last ticks
+    pub first: u64,
+    pub last: u64,
+
+    pub ticks: u64,
+
+    // Longest time between consecutive ticks, in milliseconds
+    #[serde(rename = "maxGap")]
+    pub max_gap: u64,
+
+    // Collected files the week was last built from
+    #[serde(rename = "sourceFiles")]
+    pub source_files: Vec<String>,
+
+    // SHA-256 of the week's file, hex encoded
+    pub checksum: String,
+
+    // Whether the week had ended when the file was written
+    pub complete: bool,
+}
+
+impl DatasetManifest {
+    // `prices` are the week's cleaned prices, in timestamp order
+    pub fn new(
+        instrument: &str,
+        year: i32,
+        week: u32,
+        prices: &[Price],
+        source_files: Vec<String>,
+        checksum: String,
+        complete: bool,
+    ) -> Self {
+        DatasetManifest {
+            instrument: instrument.to_string(),
+            year,
+            week,
+            start: calendar::week_start(year, week),
+            end: calendar::week_end(year, week),
+            first: prices.first().map_or(0, |price| price.time),
+            last: prices.last().map_or(0, |price| price.time),
+            ticks: prices.len() as u64,
+            max_gap: prices
+                .windows(2)
+                .map(|pair| pair[1].time.saturating_sub(pair[0].time))
+                .max()
+                .unwrap_or(0),
+            source_files,
+            checksum,
+            complete,
+        }
+    }
+
+    // Where the manifest of a data file is kept
+    pub fn path<P: AsRef<Path>>(data_path: P) -> PathBuf {
+        let mut name = data_path.as_ref().as_os_str().to_owned();
+        name.push(".manifest.json");
+        PathBuf::from(name)
+    }
+
+    // The manifest of a data file, None if it has none
+    pub fn load<P: AsRef<Path>>(data_path: P) -> Result<Option<Self>, Box<dyn std::error::Error>> {
+        let path = Self::path(data_path);
+        if !path.exists() {
+            return Ok(None);
+        }
+        let reader = BufReader::new(
+            File::open(&path).with_context(|| format!("Opening {}", path.display()))?,
+        );
+        serde_json::from_reader(reader).with_context(|| format!("Parsing {}", path.display()))
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, data_path: P) -> Result<(), Box<dyn std::error::Error>> {
+        let path = Self::path(data_path);
+        std::fs::write(&path, serde_json::to_string_pretty(self)?)
+            .with_context(|| format!("Writing {}", path.display()))
+    }
+}
+
+// Criteria the weeks of an archive must meet to be used, e.g. "quality": {"maxGap": 120000}
+// for weeks without a gap of 2 minutes or more. Weeks without a manifest can't be checked for
+// gaps, so they're left out when maxGap is set.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct QualityFilter {
+    // Longest allowed time between ticks, in milliseconds
+    #[serde(default)]
+    #[serde(rename = "maxGap")]
+    pub max_gap: Option<u64>,
+
+    #[serde(default)]
+    #[serde(rename = "minTicks")]
+    pub min_ticks: Option<u64>,
+
+    // Leave out the current week, which is still being collected
+    #[serde(default)]
+    #[serde(rename = "completeOnly")]
+    pub complete_only: bool,
+}
+
+impl QualityFilter {
+    pub fn is_empty(&self) -> bool {
+        self.max_gap.is_none() && self.min_ticks.is_none() && !self.complete_only
+    }
+
+    pub fn accepts(&self, entry: &CatalogEntry) -> bool {
+        if self.complete_only && !entry.complete {
+            return false;
+        }
+        if self
+            .min_ticks
+            .is_some_and(|min_ticks| entry.records < min_ticks)
+        {
+            return false;
+        }
+        match self.max_gap {
+            Some(max_gap) => entry
+                .manifest
+                .as_ref()
+                .is_some_and(|manifest| manifest.max_gap < max_gap),
+            None => true,
+        }
+    }
+}
+
+// Index of the weekly files in the archive, stored as catalog.json in the archive directory
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct Catalog {
+    pub entries: Vec<CatalogEntry>,
+}
+
+impl Catalog {
+    // An archive without a catalog yet has an empty one
+    pub fn load<P: AsRef<Path>>(archive_dir: P) -> Result<Self, Box<dyn std::error::Error>> {
+        let path = archive_dir.as_ref().join("catalog.json");
+        if !path.exists() {
+            return Ok(Catalog::default());
+        }
+
+        let reader = BufReader::new(
+            File::open(&path).with_context(|| format!("Opening {}", path.display()))?,
+        );
+        let mut catalog: Catalog = serde_json::from_reader(reader)
+            .with_context(|| format!("Parsing {}", path.display()))?;
+        for entry in catalog.entries.iter_mut() {
+            entry.manifest = DatasetManifest::load(archive_dir.as_ref().join(&entry.path))?;
+        }
+        Ok(catalog)
+    }
+
+    // Written to a temporary file first so a crash never leaves a truncated catalog behind
+    pub fn save<P: AsRef<Path>>(&self, archive_dir: P) -> Result<(), Box<dyn std::error::Error>> {
+        let path = archive_dir.as_ref().join("catalog.json");
+        let temporary = archive_dir.as_ref().join("catalog.json.tmp");
+
+        let mut writer = BufWriter::new(File::create(&temporary)?);
+        serde_json::to_writer_pretty(&mut writer, self)?;
+        writer.flush()?;
+        drop(writer);
+
+        std::fs::rename(temporary, path)?;
+        Ok(())
+    }
+
+    pub fn get(&self, instrument: &str, year: i32, week: u32) -> Option<&CatalogEntry> {
+        self.entries
+            .iter()
+            .find(|e| e.instrument == instrument && e.year == year && e.week == week)
+    }
+
+    // Add an entry, replacing any existing entry for the same instrument and week
+    pub fn upsert(&mut self, entry: CatalogEntry) {
+        self.entries.retain(|e| {
+            !(e.instrument == entry.instrument && e.year == entry.year && e.week == entry.week)
+        });
+        self.entries.push(entry);
+        self.entries
+            .sort_by(|a, b| (&a.instrument, a.year, a.week).cmp(&(&b.instrument, b.year, b.week)));
+    }
+
+    // The weeks that meet the filter's criteria
+    pub fn select(&self, filter: &QualityFilter) -> Catalog {
+        let entries: Vec<CatalogEntry> = self
+            .entries
+            .iter()
+            .filter(|entry| filter.accepts(entry))
+            .cloned()
+            .collect();
+        log::info!(
+            "Selected {} of {} archived weeks with {:?}",
+            entries.len(),
+            self.entries.len(),
+            filter
+        );
+        Catalog { entries }
+    }
+
+    pub fn instrument(&self, instrument: &str) -> Vec<&CatalogEntry> {
+        self.entries
+            .iter()
+            .filter(|e| e.instrument == instrument)
+            .collect()
+    }
+
+    // Stream every archived week in timestamp order, straight from the (possibly compressed)
+    // weekly files. Only one file per instrument is open at a time.
+    pub fn open_prices<P: AsRef<Path>>(
+        &self,
+        archive_dir: P,
+    ) -> Result<MergedReader, Box<dyn std::error::Error>> {
+        let mut instruments: Vec<&str> =
+            self.entries.iter().map(|e| e.instrument.as_str()).collect();
+        instruments.dedup();
+
+        let readers = instruments
+            .into_iter()
+            .map(|instrument| {
+                // Entries are kept sorted by week, so the files are in order
+                let paths = self
+                    .instrument(instrument)
+                    .into_iter()
+                    .map(|e| archive_dir.as_ref().join(&e.path))
+                    .collect();
+                BinReader::open_sequence(paths, instrument)
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(MergedReader::new(readers))
+    }
+}
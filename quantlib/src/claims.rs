@@ -0,0 +1,196 @@
+use std::fs::{File, OpenOptions, TryLockError};
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+// Lock held by the one process allowed to write to a data directory, for as long as it runs
+const WRITER_LOCK: &str = ".writer.lock";
+
+// Lock held briefly while the manifest is updated
+const MANIFEST_LOCK: &str = ".claims.lock";
+
+// Which processes are using a data directory and how
+const MANIFEST: &str = "claims.json";
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum ClaimMode {
+    Writer,
+    Reader,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Claim {
+    pub process: String,
+    pub pid: u32,
+    pub mode: ClaimMode,
+    pub since: String,
+}
+
+// Claims on a data directory, kept in claims.json at its root
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct ClaimManifest {
+    pub claims: Vec<Claim>,
+}
+
+impl ClaimManifest {
+    pub fn load<P: AsRef<Path>>(root: P) -> Result<Self, Box<dyn std::error::Error>> {
+        let path = root.as_ref().join(MANIFEST);
+        if !path.exists() {
+            return Ok(ClaimManifest::default());
+        }
+        Ok(serde_json::from_reader(BufReader::new(File::open(path)?))?)
+    }
+
+    pub fn writer(&self) -> Option<&Claim> {
+        self.claims
+            .iter()
+            .find(|claim| claim.mode == ClaimMode::Writer)
+    }
+
+    fn save(&self, root: &Path) -> Result<(), Box<dyn std::error::Error>> {
+        let path = root.join(MANIFEST);
+        let temporary = path.with_extension("tmp");
+        serde_json::to_writer_pretty(File::create(&temporary)?, self)?;
+        std::fs::rename(temporary, path)?;
+        Ok(())
+    }
+
+    // Claims of processes that have exited without releasing them can't hold any locks,
+    // so they are dropped whenever the manifest is updated
+    fn prune(&mut self) {
+        let proc = Path::new("/proc");
+        if proc.exists() {
+            self.claims
+                .retain(|claim| proc.join(claim.pid.to_string()).exists());
+        }
+    }
+}
+
+// A process's claim on a data directory, released when dropped. Only one process can hold the
+// writer claim of a directory at a time, any number can read it alongside.
+pub struct DirectoryClaim {
+    root: PathBuf,
+    mode: ClaimMode,
+
+    // Kept open to hold the writer lock, which the OS also releases if the process dies
+    _lock: Option<File>,
+}
+
+impl DirectoryClaim {
+    pub fn writer<P: AsRef<Path>>(
+        root: P,
+        process: &str,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let root = root.as_ref();
+        std::fs::create_dir_all(root)?;
+
+        let lock = open_lock(&root.join(WRITER_LOCK))?;
+        match lock.try_lock() {
+            Ok(()) => {}
+            Err(TryLockError::WouldBlock) => {
+                let holder = ClaimManifest::load(root)?
+                    .writer()
+                    .map(|claim| format!("{} (pid {})", claim.process, claim.pid))
+                    .unwrap_or_else(|| "another process".to_string());
+                return Err(
+                    format!("{} is already being written by {}", root.display(), holder).into(),
+                );
+            }
+            Err(TryLockError::Error(e)) => return Err(e.into()),
+        }
+
+        update_manifest(root, |manifest| {
+            // Holding the lock means any writer still in the manifest is gone
+            manifest
+                .claims
+                .retain(|claim| claim.mode != ClaimMode::Writer);
+            manifest.claims.push(new_claim(process, ClaimMode::Writer));
+        })?;
+        log::info!("Claimed {} for writing", root.display());
+
+        Ok(DirectoryClaim {
+            root: root.to_path_buf(),
+            mode: ClaimMode::Writer,
+            _lock: Some(lock),
+        })
+    }
+
+    // Readers never modify the directory, so they only record themselves in the manifest
+    pub fn reader<P: AsRef<Path>>(
+        root: P,
+        process: &str,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let root = root.as_ref();
+        if !root.exists() {
+            return Err(format!("Data directory {} doesn't exist", root.display()).into());
+        }
+
+        update_manifest(root, |manifest| {
+            manifest.claims.push(new_claim(process, ClaimMode::Reader));
+        })?;
+
+        Ok(DirectoryClaim {
+            root: root.to_path_buf(),
+            mode: ClaimMode::Reader,
+            _lock: None,
+        })
+    }
+
+    pub fn mode(&self) -> ClaimMode {
+        self.mode
+    }
+}
+
+impl Drop for DirectoryClaim {
+    fn drop(&mut self) {
+        let pid = std::process::id();
+        let mode = self.mode;
+        let released = update_manifest(&self.root, |manifest| {
+            if let Some(index) = manifest
+                .claims
+                .iter()
+                .position(|claim| claim.pid == pid && claim.mode == mode)
+            {
+                manifest.claims.remove(index);
+            }
+        });
+        if let Err(e) = released {
+            log::warn!("Failed to release claim on {}: {}", self.root.display(), e);
+        }
+    }
+}
+
+fn new_claim(process: &str, mode: ClaimMode) -> Claim {
+    Claim {
+        process: process.to_string(),
+        pid: std::process::id(),
+        mode,
+        since: chrono::Utc::now().to_rfc3339(),
+    }
+}
+
+fn open_lock(path: &Path) -> Result<File, Box<dyn std::error::Error>> {
+    Ok(OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(path)?)
+}
+
+fn update_manifest<F: FnOnce(&mut ClaimManifest)>(
+    root: &Path,
+    update: F,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let lock = open_lock(&root.join(MANIFEST_LOCK))?;
+    lock.lock()?;
+
+    let mut manifest = ClaimManifest::load(root).unwrap_or_else(|e| {
+        log::warn!("Replacing unreadable claim manifest: {}", e);
+        ClaimManifest::default()
+    });
+    manifest.prune();
+    update(&mut manifest);
+    manifest.save(root)
+}
@@ -0,0 +1,82 @@
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+// Ticks dropped before reaching what they were meant for, counted by instrument over periods.
+// The trader's strategy workers conflate prices to the latest of each instrument once they fall
+// behind (see StrategyWorkersConfig), and the stream relay drops them for subscribers that can't
+// keep up. Either way the live strategies see fewer ticks than a backtest of the same recorded
+// prices, and these counts are how many fewer.
+#[derive(Debug, Default)]
+pub struct DroppedTicks {
+    // Length of a period in milliseconds
+    period: u64,
+    start: Option<u64>,
+    current: BTreeMap<String, u64>,
+    totals: BTreeMap<String, u64>,
+}
+
+// The ticks of each instrument dropped between two times, in milliseconds since the epoch
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct DroppedPeriod {
+    pub start: u64,
+    pub end: u64,
+    pub dropped: BTreeMap<String, u64>,
+}
+
+impl DroppedPeriod {
+    pub fn total(&self) -> u64 {
+        self.dropped.values().sum()
+    }
+
+    // e.g. "EUR_USD 12, GBP_USD 3", most dropped first
+    pub fn summary(&self) -> String {
+        let mut dropped: Vec<(&String, &u64)> = self.dropped.iter().collect();
+        dropped.sort_by(|a, b| b.1.cmp(a.1));
+        dropped
+            .iter()
+            .map(|(instrument, count)| format!("{} {}", instrument, count))
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+}
+
+impl DroppedTicks {
+    pub fn new(period: u64) -> Self {
+        DroppedTicks {
+            period,
+            ..Default::default()
+        }
+    }
+
+    pub fn record(&mut self, instrument: &str, count: u64) {
+        if count == 0 {
+            return;
+        }
+        *self.current.entry(instrument.to_string()).or_default() += count;
+        *self.totals.entry(instrument.to_string()).or_default() += count;
+    }
+
+    // Whether the current period is over, the first call starting it
+    pub fn due(&mut self, now: u64) -> bool {
+        now.saturating_sub(*self.start.get_or_insert(now)) >= self.period
+    }
+
+    // The current period's counts, starting the next at `now`. None if nothing was dropped in it.
+    pub fn roll(&mut self, now: u64) -> Option<DroppedPeriod> {
+        let start = self.start.replace(now).unwrap_or(now);
+        if self.current.is_empty() {
+            return None;
+        }
+        Some(DroppedPeriod {
+            start,
+            end: now,
+            dropped: std::mem::take(&mut self.current),
+        })
+    }
+
+    // Every tick dropped since the start, by instrument
+    pub fn totals(&self) -> &BTreeMap<String, u64> {
+        &self.totals
+    }
+}
@@ -0,0 +1,210 @@
+#[cfg(unix)]
+use std::io::{BufRead, BufReader, Write};
+#[cfg(unix)]
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+
+use serde::{Deserialize, Serialize};
+
+use crate::engine::SharedDecisionHistory;
+
+// A command sent to a running trader over its control socket, one per line, e.g.
+// echo "enable fastEma" | nc -U trader.sock or echo "disable instrument EUR_TRY" | nc -U trader.sock
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ControlCommand {
+    // Resume a strategy paused by a guard or circuit breaker
+    Enable(String),
+
+    // Pause a strategy until it is enabled again
+    Pause(String),
+
+    // Stop executing signals for an instrument, and start again. Strategies keep running on its
+    // prices and positions are left as they are.
+    EnableInstrument(String),
+    DisableInstrument(String),
+
+    // Start executing orders on a trader running on standby
+    Activate,
+
+    // Re-read the trading config's instruments, e.g. after editing the universes file, and stream
+    // the new ones without restarting
+    Reload,
+
+    // The recent decisions of an instrument, or of all of them, answered by the socket itself
+    Status(Option<String>),
+
+    // Log 1 in N ticks of each instrument ("ticks 100", or "ticks off" for none), and every tick
+    // of one instrument or back to sampling it ("ticks EUR_USD full", "ticks EUR_USD sampled"),
+    // see TickLogConfig
+    TickSample(u64),
+    TickFull(String, bool),
+}
+
+impl ControlCommand {
+    pub fn parse(line: &str) -> Result<Self, String> {
+        let mut words = line.split_whitespace();
+        match (words.next(), words.next(), words.next()) {
+            (Some("activate"), None, None) => Ok(ControlCommand::Activate),
+            (Some("reload"), None, None) => Ok(ControlCommand::Reload),
+            (Some("enable"), Some("instrument"), Some(instrument)) => {
+                Ok(ControlCommand::EnableInstrument(instrument.into()))
+            }
+            (Some("disable"), Some("instrument"), Some(instrument)) => {
+                Ok(ControlCommand::DisableInstrument(instrument.into()))
+            }
+            (Some("enable"), Some(strategy), None) => Ok(ControlCommand::Enable(strategy.into())),
+            (Some("pause"), Some(strategy), None) => Ok(ControlCommand::Pause(strategy.into())),
+            (Some("ticks"), Some("off"), None) => Ok(ControlCommand::TickSample(0)),
+            (Some("ticks"), Some(sample), None) if sample.parse::<u64>().is_ok() => Ok(
+                ControlCommand::TickSample(sample.parse().unwrap_or_default()),
+            ),
+            (Some("ticks"), Some(instrument), Some("full")) => {
+                Ok(ControlCommand::TickFull(instrument.into(), true))
+            }
+            (Some("ticks"), Some(instrument), Some("sampled")) => {
+                Ok(ControlCommand::TickFull(instrument.into(), false))
+            }
+            (Some("status"), instrument, None) => {
+                Ok(ControlCommand::Status(instrument.map(Into::into)))
+            }
+            _ => Err(format!("Unknown command: {}", line.trim())),
+        }
+    }
+}
+
+// The gRPC service of a running trader, "grpc" in the trading config, e.g.
+// {"address": "0.0.0.0:50051", "token": "age1...", "tls": {"cert": "grpc.pem", "key": "grpc.key"}}.
+// It answers what the control socket does, plus the trader's status and backtests, to callers
+// sending the token as a bearer token. The token may be encrypted like the settings' secrets.
+// Without "tls" the service is plaintext and only binds to loopback addresses, as the token
+// would otherwise cross the network in the clear. Needs the trader built with quantlib's "grpc"
+// feature, see proto/engine.proto.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct GrpcConfig {
+    #[serde(default = "default_grpc_address")]
+    pub address: String,
+    pub token: String,
+
+    #[serde(default)]
+    pub tls: Option<GrpcTls>,
+}
+
+// The server's certificate chain and its PKCS #8 private key, both PEM files. The handshake is
+// the system TLS library's, which doesn't negotiate ALPN: clients that insist on "h2" being
+// negotiated, such as recent grpc-go ones, need that check turned off.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct GrpcTls {
+    pub cert: PathBuf,
+    pub key: PathBuf,
+}
+
+fn default_grpc_address() -> String {
+    "127.0.0.1:50051".to_string()
+}
+
+// Accepts commands on a Unix socket in a background thread, to be picked up by the trading loop.
+// Status queries are answered straight from the shared decision history, one JSON decision per
+// line followed by "ok". Binding refuses a socket another trader is listening on, and the socket
+// file is removed when this is dropped. Only Unix systems have one, binding fails elsewhere.
+pub struct ControlSocket {
+    path: PathBuf,
+    receiver: mpsc::Receiver<ControlCommand>,
+}
+
+impl ControlSocket {
+    pub fn bind<P: AsRef<Path>>(
+        path: P,
+        history: SharedDecisionHistory,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let path = path.as_ref().to_path_buf();
+        let (sender, receiver) = mpsc::channel();
+        listen(&path, sender, history)?;
+        log::info!("Listening for control commands on {:?}", path);
+
+        Ok(ControlSocket { path, receiver })
+    }
+
+    // Commands received since the last call
+    pub fn commands(&self) -> Vec<ControlCommand> {
+        self.receiver.try_iter().collect()
+    }
+}
+
+impl Drop for ControlSocket {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+#[cfg(unix)]
+fn listen(
+    path: &Path,
+    sender: mpsc::Sender<ControlCommand>,
+    history: SharedDecisionHistory,
+) -> Result<(), Box<dyn std::error::Error>> {
+    // A socket left behind by a previous run would make binding fail, but one that's still
+    // answering belongs to a running trader and is left to it
+    if path.exists() {
+        if UnixStream::connect(path).is_ok() {
+            return Err(format!("Another trader is listening on {:?}", path).into());
+        }
+        std::fs::remove_file(path)?;
+    }
+    let listener = UnixListener::bind(path)?;
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    if let Err(e) = handle_connection(stream, &sender, &history) {
+                        log::warn!("Control connection error: {}", e);
+                    }
+                }
+                Err(e) => log::warn!("Failed to accept control connection: {}", e),
+            }
+        }
+    });
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn listen(
+    _path: &Path,
+    _sender: mpsc::Sender<ControlCommand>,
+    _history: SharedDecisionHistory,
+) -> Result<(), Box<dyn std::error::Error>> {
+    Err("Control sockets are Unix sockets, use the gRPC service on this system".into())
+}
+
+#[cfg(unix)]
+fn handle_connection(
+    stream: UnixStream,
+    sender: &mpsc::Sender<ControlCommand>,
+    history: &SharedDecisionHistory,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut writer = stream.try_clone()?;
+    for line in BufReader::new(stream).lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        match ControlCommand::parse(&line) {
+            Ok(ControlCommand::Status(instrument)) => {
+                let history = history
+                    .lock()
+                    .map_err(|_| "Decision history lock poisoned")?;
+                for decision in history.recent(instrument.as_deref()) {
+                    writeln!(writer, "{}", serde_json::to_string(decision)?)?;
+                }
+                writeln!(writer, "ok")?;
+            }
+            Ok(command) => {
+                log::info!("Received control command: {:?}", command);
+                sender.send(command)?;
+                writeln!(writer, "ok")?;
+            }
+            Err(e) => writeln!(writer, "error: {}", e)?,
+        }
+    }
+    Ok(())
+}
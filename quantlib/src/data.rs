@@ -0,0 +1,907 @@
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap, HashSet, VecDeque};
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::calendar;
+use crate::claims::DirectoryClaim;
+use crate::errors::{self, Context};
+use crate::oanda::objects::{Price, PriceStatus};
+
+// Binary tick format written by the collector, one file per instrument:
+// u64 timestamp (since the UNIX epoch, UTC), f32 bid, f32 ask, all big endian.
+// Version 1 files are nothing but records, with timestamps in milliseconds. Later versions start
+// with a header the size of a record: HEADER_MAGIC, the version as a big endian u32 and 4 unused
+// bytes. Version 2 timestamps are in nanoseconds. A version 1 timestamp's first byte is always
+// 0, so the two can't be mistaken for each other.
+pub const RECORD_SIZE: usize = 16;
+pub const HEADER_MAGIC: [u8; 8] = *b"QLTICKS\0";
+
+// Granularity of the prices written by the collector, as used in storage layout templates
+pub const TICK: &str = "tick";
+
+// Precision of the timestamps in a binary tick file, which decides the file's version
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "camelCase")]
+pub enum TimePrecision {
+    // Version 1, readable by everything that reads tick files
+    #[default]
+    Milliseconds,
+
+    // Version 2
+    Nanoseconds,
+}
+
+impl TimePrecision {
+    pub fn version(&self) -> u32 {
+        match self {
+            TimePrecision::Milliseconds => 1,
+            TimePrecision::Nanoseconds => 2,
+        }
+    }
+
+    pub fn from_version(version: u32) -> Result<Self, Box<dyn std::error::Error>> {
+        match version {
+            1 => Ok(TimePrecision::Milliseconds),
+            2 => Ok(TimePrecision::Nanoseconds),
+            _ => Err(format!("Unsupported tick file version {}", version).into()),
+        }
+    }
+
+    // What a file of this precision starts with, version 1 files have no header
+    pub fn header(&self) -> Option<[u8; RECORD_SIZE]> {
+        if *self == TimePrecision::Milliseconds {
+            return None;
+        }
+        let mut header = [0; RECORD_SIZE];
+        header[0..8].copy_from_slice(&HEADER_MAGIC);
+        header[8..12].copy_from_slice(&self.version().to_be_bytes());
+        Some(header)
+    }
+
+    // The precision given by a file's first record, None if it's an ordinary record
+    fn from_header(record: &[u8; RECORD_SIZE]) -> Option<Result<Self, Box<dyn std::error::Error>>> {
+        (record[0..8] == HEADER_MAGIC)
+            .then(|| Self::from_version(u32::from_be_bytes(record[8..12].try_into().unwrap())))
+    }
+}
+
+pub fn encode_price(price: &Price, precision: TimePrecision) -> [u8; RECORD_SIZE] {
+    let time = match precision {
+        TimePrecision::Milliseconds => price.time,
+        TimePrecision::Nanoseconds => price.timestamp_nanos(),
+    };
+    let mut record = [0; RECORD_SIZE];
+    record[0..8].copy_from_slice(&time.to_be_bytes());
+    record[8..12].copy_from_slice(&price.bid.to_be_bytes());
+    record[12..16].copy_from_slice(&price.ask.to_be_bytes());
+    record
+}
+
+pub fn decode_price(
+    record: &[u8; RECORD_SIZE],
+    instrument: &str,
+    precision: TimePrecision,
+) -> Price {
+    let time = u64::from_be_bytes(record[0..8].try_into().unwrap());
+    let (time, nanos) = match precision {
+        TimePrecision::Milliseconds => (time, 0),
+        TimePrecision::Nanoseconds => (time / 1_000_000, (time % 1_000_000) as u32),
+    };
+    Price {
+        time,
+        nanos,
+        bid: f32::from_be_bytes(record[8..12].try_into().unwrap()),
+        ask: f32::from_be_bytes(record[12..16].try_into().unwrap()),
+        instrument: instrument.to_string(),
+        tradeable: true,
+        status: PriceStatus::Tradeable,
+    }
+}
+
+// Precision of an existing tick file, None if nothing has been written to it yet
+pub fn file_precision(path: &Path) -> Result<Option<TimePrecision>, Box<dyn std::error::Error>> {
+    let mut record = [0; RECORD_SIZE];
+    let mut reader = open_bin_file(path)?;
+    let mut read = 0;
+    while read < RECORD_SIZE {
+        match reader
+            .read(&mut record[read..])
+            .with_context(|| format!("Reading {}", path.display()))?
+        {
+            0 => break,
+            n => read += n,
+        }
+    }
+    match read {
+        0 => Ok(None),
+        RECORD_SIZE => TimePrecision::from_header(&record)
+            .transpose()
+            .with_context(|| format!("Reading the header of {}", path.display()))
+            .map(|precision| Some(precision.unwrap_or_default())),
+        _ => Ok(Some(TimePrecision::Milliseconds)),
+    }
+}
+
+// Extension of binary tick files compressed with Snappy's framing format, e.g. 2024-W05.bin.sz
+pub const COMPRESSED_EXTENSION: &str = "sz";
+
+pub fn is_compressed(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|extension| extension == COMPRESSED_EXTENSION)
+}
+
+// Where a file is kept once compressed, e.g. 2024-W05.bin.sz for 2024-W05.bin
+pub fn compressed_path(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(".");
+    name.push(COMPRESSED_EXTENSION);
+    PathBuf::from(name)
+}
+
+// Compress a whole file's contents the same way compressed tick files are written
+pub fn compress(data: &[u8]) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let mut encoder = snap::write::FrameEncoder::new(Vec::new());
+    encoder.write_all(data)?;
+    Ok(encoder.into_inner().map_err(|e| e.to_string())?)
+}
+
+// Compress a file (decompressing it first if it's compressed) the same way compressed tick files
+// are written, streaming it into `destination` rather than reading it all into memory. Returns
+// the SHA-256 of its uncompressed contents.
+pub fn compress_file(
+    path: &Path,
+    destination: &Path,
+) -> Result<String, Box<dyn std::error::Error>> {
+    let mut reader = open_bin_file(path)?;
+    let file =
+        File::create(destination).with_context(|| format!("Creating {}", destination.display()))?;
+    let mut encoder = snap::write::FrameEncoder::new(BufWriter::new(file));
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0; 64 * 1024];
+    loop {
+        let read = reader.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+        encoder.write_all(&buffer[..read])?;
+    }
+    encoder.into_inner().map_err(|e| e.to_string())?.flush()?;
+    Ok(hex::encode(hasher.finalize()))
+}
+
+// A whole file's contents, decompressed if it's compressed
+pub fn read_uncompressed(path: &Path) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let mut contents = Vec::new();
+    open_bin_file(path)?.read_to_end(&mut contents)?;
+    Ok(contents)
+}
+
+// Compressed files are decompressed as they're read, without extracting them anywhere
+fn open_bin_file(path: &Path) -> Result<Box<dyn Read + Send>, Box<dyn std::error::Error>> {
+    let file = File::open(path).with_context(|| format!("Opening {}", path.display()))?;
+    if is_compressed(path) {
+        Ok(Box::new(snap::read::FrameDecoder::new(file)))
+    } else {
+        Ok(Box::new(file))
+    }
+}
+
+// Reads the prices in a binary tick file in order
+pub struct BinReader {
+    reader: BufReader<Box<dyn Read + Send>>,
+    instrument: String,
+
+    // The current file and how far into it has been read, for errors. The offset of a compressed
+    // file is into its decompressed contents.
+    path: PathBuf,
+    offset: u64,
+
+    // Files to read once the current one is exhausted, e.g. the following weeks of an archive
+    remaining: VecDeque<PathBuf>,
+    last: u64,
+    boundary: u64,
+    overlap: bool,
+
+    // Precision of the current file, known once its first record (or header) has been read
+    precision: Option<TimePrecision>,
+}
+
+impl BinReader {
+    // The instrument is taken from the file name, e.g. data/bin/EUR_USD.bin or EUR_USD.bin.sz
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, Box<dyn std::error::Error>> {
+        let instrument = uncompressed_path(path.as_ref())
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .ok_or("Binary file name is not an instrument")?
+            .to_string();
+        Self::open_instrument(path, &instrument)
+    }
+
+    pub fn open_instrument<P: AsRef<Path>>(
+        path: P,
+        instrument: &str,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        Ok(BinReader {
+            reader: BufReader::with_capacity(64 * 1024, open_bin_file(path.as_ref())?),
+            instrument: instrument.to_string(),
+            path: path.as_ref().to_path_buf(),
+            offset: 0,
+            remaining: VecDeque::new(),
+            last: 0,
+            boundary: 0,
+            overlap: false,
+            precision: None,
+        })
+    }
+
+    // Read several consecutive files of an instrument as one, opening each only once the previous
+    // is finished. Prices older than the end of the previous file are dropped, so files that
+    // overlap at their boundaries still give prices in order.
+    pub fn open_sequence(
+        paths: Vec<PathBuf>,
+        instrument: &str,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut remaining = VecDeque::from(paths);
+        let first = remaining.pop_front().ok_or("No files to read")?;
+        let mut reader = Self::open_instrument(first, instrument)?;
+        reader.remaining = remaining;
+        Ok(reader)
+    }
+
+    // Keep the prices of a sequence's files that are older than the end of the file before, for
+    // files whose prices may be out of order, e.g. as collected
+    pub fn with_overlap(mut self) -> Self {
+        self.overlap = true;
+        self
+    }
+
+    pub fn instrument(&self) -> &str {
+        &self.instrument
+    }
+
+    // Where an error reading the current record happened
+    fn context<E: Into<Box<dyn std::error::Error>>>(&self, error: E) -> Box<dyn std::error::Error> {
+        errors::wrap(
+            error,
+            format!("Reading {} at offset {}", self.path.display(), self.offset),
+        )
+    }
+}
+
+impl Iterator for BinReader {
+    type Item = Price;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        // A partial record at the end of the file is still being written, so it is ignored
+        let mut record = [0; RECORD_SIZE];
+        loop {
+            match self.reader.read_exact(&mut record) {
+                Ok(()) => {
+                    self.offset += RECORD_SIZE as u64;
+                    let precision = match self.precision {
+                        Some(precision) => precision,
+                        None => match TimePrecision::from_header(&record) {
+                            Some(Ok(precision)) => {
+                                self.precision = Some(precision);
+                                continue;
+                            }
+                            // Nothing in the file can be read, so move on to the next one
+                            Some(Err(e)) => {
+                                log::error!("{}", self.context(e));
+                                self.reader = BufReader::new(Box::new(std::io::empty()));
+                                continue;
+                            }
+                            None => *self.precision.insert(TimePrecision::Milliseconds),
+                        },
+                    };
+                    let price = decode_price(&record, &self.instrument, precision);
+                    if !self.overlap && price.time < self.boundary {
+                        continue;
+                    }
+                    self.last = price.time;
+                    return Some(price);
+                }
+                Err(e) => {
+                    // Anything but running out of records means the rest of the file is unreadable,
+                    // e.g. corrupt compressed data
+                    if e.kind() != std::io::ErrorKind::UnexpectedEof {
+                        log::error!("{}", self.context(e));
+                    }
+                    let path = self.remaining.pop_front()?;
+                    match open_bin_file(&path) {
+                        Ok(file) => {
+                            self.reader = BufReader::with_capacity(64 * 1024, file);
+                            self.path = path;
+                            self.offset = 0;
+                            self.boundary = self.last;
+                            self.precision = None;
+                        }
+                        Err(e) => log::error!("Skipping {}: {}", self.instrument, e),
+                    }
+                }
+            }
+        }
+    }
+}
+
+// Write prices to a binary tick file, replacing it. The file is written under a temporary name
+// and renamed into place, so readers never see a partially written file. Files with the
+// compressed extension are compressed as they're written.
+pub fn write_prices<P: AsRef<Path>>(
+    path: P,
+    prices: &[Price],
+    precision: TimePrecision,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut writer = PriceWriter::create(path, precision)?;
+    writer.write(prices)?;
+    writer.finish()
+}
+
+// Writes a binary tick file a batch of prices at a time, for files too big to hold in memory, the
+// same way as write_prices. The file replaces any other at its path once finished.
+pub struct PriceWriter {
+    path: PathBuf,
+    temporary: PathBuf,
+    writer: BufWriter<Box<dyn Write>>,
+    precision: TimePrecision,
+}
+
+impl PriceWriter {
+    pub fn create<P: AsRef<Path>>(
+        path: P,
+        precision: TimePrecision,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let path = path.as_ref();
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+
+        let temporary = path.with_extension("tmp");
+        let file = File::create(&temporary)?;
+        let mut writer: BufWriter<Box<dyn Write>> = if is_compressed(path) {
+            BufWriter::new(Box::new(snap::write::FrameEncoder::new(file)))
+        } else {
+            BufWriter::new(Box::new(file))
+        };
+        if let Some(header) = precision.header() {
+            writer.write_all(&header)?;
+        }
+        Ok(PriceWriter {
+            path: path.to_path_buf(),
+            temporary,
+            writer,
+            precision,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn write(&mut self, prices: &[Price]) -> Result<(), Box<dyn std::error::Error>> {
+        for price in prices {
+            self.writer
+                .write_all(&encode_price(price, self.precision))?;
+        }
+        Ok(())
+    }
+
+    // Flush the file and rename it into place
+    pub fn finish(mut self) -> Result<(), Box<dyn std::error::Error>> {
+        self.writer.flush()?;
+        drop(self.writer);
+        std::fs::rename(self.temporary, self.path)?;
+        Ok(())
+    }
+}
+
+// Heartbeats are kept out of the tick files, which every reader expects to hold nothing but
+// prices, and are written by the collector to a sidecar file beside them instead: one u64 per
+// heartbeat, OANDA's time of the heartbeat in milliseconds since the UNIX epoch, big endian.
+// OANDA sends one every 5 seconds on a live connection whether or not prices are moving, so a
+// gap in the ticks with heartbeats throughout is the market being quiet, and one without them is
+// the connection being down.
+pub const HEARTBEAT_SIZE: usize = 8;
+
+// Longest time between heartbeats on a live connection, as the collector's stream timeout
+pub const HEARTBEAT_TIMEOUT: u64 = 10_000;
+
+pub fn encode_heartbeat(time: u64) -> [u8; HEARTBEAT_SIZE] {
+    time.to_be_bytes()
+}
+
+// Every heartbeat in a heartbeat file, compressed or not, in the order they were received
+pub fn read_heartbeats(path: &Path) -> Result<Vec<u64>, Box<dyn std::error::Error>> {
+    let contents =
+        read_uncompressed(path).with_context(|| format!("Reading {}", path.display()))?;
+    Ok(contents
+        .chunks_exact(HEARTBEAT_SIZE)
+        .map(|record| u64::from_be_bytes(record.try_into().unwrap()))
+        .collect())
+}
+
+// Why there were no ticks between two times
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum GapCause {
+    // Heartbeats kept arriving, so the connection was up and nothing was quoted
+    Silent,
+
+    // Heartbeats stopped for longer than HEARTBEAT_TIMEOUT at some point
+    Disconnected,
+
+    // No heartbeats were recorded around it, e.g. it's older than heartbeat files
+    Unrecorded,
+}
+
+// The cause of a gap in the ticks from `start` to `end`, given the sorted times of the
+// heartbeats recorded over the same period
+pub fn explain_gap(heartbeats: &[u64], start: u64, end: u64) -> GapCause {
+    let first = heartbeats.partition_point(|time| *time < start);
+    let last = heartbeats.partition_point(|time| *time <= end);
+    let within = &heartbeats[first..last];
+    if within.is_empty() && (first == 0 || last == heartbeats.len()) {
+        return GapCause::Unrecorded;
+    }
+
+    // The ticks either side of the gap show the connection was up at its ends
+    let mut previous = start;
+    for time in within.iter().copied().chain([end]) {
+        if time.saturating_sub(previous) > HEARTBEAT_TIMEOUT {
+            return GapCause::Disconnected;
+        }
+        previous = time;
+    }
+    GapCause::Silent
+}
+
+// Problems found while cleaning a series of prices
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct IntegrityReport {
+    pub records: u64,
+
+    #[serde(rename = "outOfOrder")]
+    pub out_of_order: u64,
+
+    // Records identical to another record, e.g. from a reconnect replaying the latest price
+    pub duplicates: u64,
+
+    // Zero, negative, non-finite or crossed (bid above ask) prices
+    pub invalid: u64,
+}
+
+impl IntegrityReport {
+    pub fn is_clean(&self) -> bool {
+        self.out_of_order == 0 && self.duplicates == 0 && self.invalid == 0
+    }
+
+    pub fn add(&mut self, other: &IntegrityReport) {
+        self.records += other.records;
+        self.out_of_order += other.out_of_order;
+        self.duplicates += other.duplicates;
+        self.invalid += other.invalid;
+    }
+}
+
+// Sort prices by time and remove invalid and duplicate records
+pub fn clean_prices(mut prices: Vec<Price>) -> (Vec<Price>, IntegrityReport) {
+    let mut report = IntegrityReport {
+        records: prices.len() as u64,
+        ..Default::default()
+    };
+
+    report.out_of_order = prices
+        .windows(2)
+        .filter(|pair| pair[1].timestamp_nanos() < pair[0].timestamp_nanos())
+        .count() as u64;
+
+    let valid = |price: &Price| {
+        price.bid.is_finite() && price.ask.is_finite() && price.bid > 0.0 && price.ask >= price.bid
+    };
+    let before = prices.len();
+    prices.retain(valid);
+    report.invalid = (before - prices.len()) as u64;
+
+    // A stable sort keeps ticks with the same timestamp in the order they were received.
+    // Duplicates needn't be next to each other, another tick of the same time can be between
+    // them, so the first of each is kept wherever the rest are among the ticks of its time.
+    prices.sort_by_key(|price| price.timestamp_nanos());
+    let before = prices.len();
+    let mut time = None;
+    let mut seen = HashSet::new();
+    prices.retain(|price| {
+        if time.replace(price.timestamp_nanos()) != Some(price.timestamp_nanos()) {
+            seen.clear();
+        }
+        seen.insert((
+            price.instrument.clone(),
+            price.bid.to_bits(),
+            price.ask.to_bits(),
+        ))
+    });
+    report.duplicates = (before - prices.len()) as u64;
+
+    (prices, report)
+}
+
+// How a series recorded by several collectors was merged
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct MergeReport {
+    // Records of each source, in the order they were given
+    pub sources: Vec<u64>,
+
+    // The source the merge is based on
+    pub primary: usize,
+
+    // Records taken from the other sources to fill the primary's gaps
+    pub filled: u64,
+
+    pub records: u64,
+}
+
+// Merge the same series recorded by several collectors (e.g. on different hosts) into one. The
+// most complete source, the one with the most records, is taken as it is and the others only
+// fill its gaps: stretches of more than `gap` milliseconds without a tick, including before its
+// first and after its last. There is never more than one tick for a timestamp, the earlier
+// source's wins. Sources must be clean, see clean_prices.
+pub fn merge_sources(sources: &[Vec<Price>], gap: u64) -> (Vec<Price>, MergeReport) {
+    let mut report = MergeReport {
+        sources: sources.iter().map(|source| source.len() as u64).collect(),
+        ..Default::default()
+    };
+
+    // A stable sort, so ties go to the source given first
+    let mut order: Vec<usize> = (0..sources.len()).collect();
+    order.sort_by_key(|index| Reverse(sources[*index].len()));
+    let primary = match order.first() {
+        Some(primary) => *primary,
+        None => return (Vec::new(), report),
+    };
+    report.primary = primary;
+
+    let mut merged = sources[primary].clone();
+    for index in &order[1..] {
+        let times: Vec<u64> = merged.iter().map(Price::timestamp_nanos).collect();
+        let in_gap = |price: &&Price| {
+            let time = price.timestamp_nanos();
+            let next = times.partition_point(|other| *other < time);
+            if times.get(next) == Some(&time) {
+                return false;
+            }
+            match (
+                next.checked_sub(1).map(|before| times[before]),
+                times.get(next),
+            ) {
+                (Some(before), Some(after)) => after - before > gap * 1_000_000,
+                _ => true,
+            }
+        };
+        let fill: Vec<Price> = sources[*index].iter().filter(in_gap).cloned().collect();
+        report.filled += fill.len() as u64;
+        merged.extend(fill);
+        merged.sort_by_key(Price::timestamp_nanos);
+    }
+
+    report.records = merged.len() as u64;
+    (merged, report)
+}
+
+// Merges several tick files into a single stream of prices in timestamp order
+pub struct MergedReader {
+    readers: Vec<BinReader>,
+    next: BinaryHeap<Reverse<(u64, usize)>>,
+    heads: Vec<Option<Price>>,
+}
+
+impl MergedReader {
+    pub fn new(mut readers: Vec<BinReader>) -> Self {
+        let mut next = BinaryHeap::new();
+        let heads = readers
+            .iter_mut()
+            .enumerate()
+            .map(|(index, reader)| {
+                let head = reader.next();
+                if let Some(price) = &head {
+                    next.push(Reverse((price.time, index)));
+                }
+                head
+            })
+            .collect();
+
+        MergedReader {
+            readers,
+            next,
+            heads,
+        }
+    }
+
+    // Open every binary file of the layout
+    pub fn open(layout: &StorageLayout) -> Result<Self, Box<dyn std::error::Error>> {
+        let readers = layout
+            .bin_files()?
+            .into_iter()
+            .map(|(instrument, path)| BinReader::open_instrument(path, &instrument))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Self::new(readers))
+    }
+}
+
+impl Iterator for MergedReader {
+    type Item = Price;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let Reverse((_, index)) = self.next.pop()?;
+        let price = self.heads[index].take();
+
+        self.heads[index] = self.readers[index].next();
+        if let Some(head) = &self.heads[index] {
+            self.next.push(Reverse((head.time, index)));
+        }
+        price
+    }
+}
+
+// Where collected data is stored. Paths are templates relative to root, which can contain
+// {instrument}, {date} (UTC, YYYY-MM-DD) and {granularity}, e.g. "{date}/{instrument}.bin"
+// to start new files every day. Collected prices have the granularity "tick".
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct StorageLayout {
+    #[serde(default = "default_root")]
+    pub root: PathBuf,
+
+    #[serde(default = "default_raw_log")]
+    #[serde(rename = "rawLog")]
+    pub raw_log: String,
+
+    #[serde(default = "default_bin")]
+    pub bin: String,
+
+    #[serde(default = "default_connection_log")]
+    #[serde(rename = "connectionLog")]
+    pub connection_log: String,
+
+    // Heartbeats of the stream, see read_heartbeats. Shouldn't match the bin template, or it'd
+    // be taken for an instrument's ticks.
+    #[serde(default = "default_heartbeats")]
+    pub heartbeats: String,
+
+    // Consumers sharing a collector's data directory only read it, and can't be used to write to it
+    #[serde(default)]
+    #[serde(rename = "readOnly")]
+    pub read_only: bool,
+
+    // Precision of the timestamps in new tick files. Files keep the precision they were created
+    // with, so changing this takes effect with the next file of the layout.
+    #[serde(default)]
+    #[serde(rename = "timePrecision")]
+    pub time_precision: TimePrecision,
+}
+
+fn default_root() -> PathBuf {
+    PathBuf::from("data")
+}
+
+fn default_raw_log() -> String {
+    "raw.log".to_string()
+}
+
+fn default_bin() -> String {
+    "bin/{instrument}.bin".to_string()
+}
+
+fn default_connection_log() -> String {
+    "connection.log".to_string()
+}
+
+fn default_heartbeats() -> String {
+    "heartbeats.dat".to_string()
+}
+
+impl Default for StorageLayout {
+    fn default() -> Self {
+        StorageLayout::new(default_root())
+    }
+}
+
+impl StorageLayout {
+    // The original layout: raw.log, connection.log, heartbeats.dat and bin/<instrument>.bin in
+    // root
+    pub fn new<P: Into<PathBuf>>(root: P) -> Self {
+        StorageLayout {
+            root: root.into(),
+            raw_log: default_raw_log(),
+            bin: default_bin(),
+            connection_log: default_connection_log(),
+            heartbeats: default_heartbeats(),
+            read_only: false,
+            time_precision: TimePrecision::default(),
+        }
+    }
+
+    pub fn read_only(mut self) -> Self {
+        self.read_only = true;
+        self
+    }
+
+    // Claim root for the lifetime of the returned claim, for reading if the layout is read only
+    // and for writing otherwise. Fails if another process is already writing to it.
+    pub fn claim(&self, process: &str) -> Result<DirectoryClaim, Box<dyn std::error::Error>> {
+        if self.read_only {
+            DirectoryClaim::reader(&self.root, process)
+        } else {
+            DirectoryClaim::writer(&self.root, process)
+        }
+    }
+
+    pub fn raw_log_path(&self, time: u64) -> PathBuf {
+        self.root.join(render(&self.raw_log, "", TICK, time))
+    }
+
+    pub fn bin_path(&self, instrument: &str, granularity: &str, time: u64) -> PathBuf {
+        self.root
+            .join(render(&self.bin, instrument, granularity, time))
+    }
+
+    pub fn connection_log_path(&self, time: u64) -> PathBuf {
+        self.root.join(render(&self.connection_log, "", TICK, time))
+    }
+
+    pub fn heartbeats_path(&self, time: u64) -> PathBuf {
+        self.root.join(render(&self.heartbeats, "", TICK, time))
+    }
+
+    // Every binary file under root matching the bin template, as (instrument, path), sorted by path.
+    // Files compressed since they were written (see retention) are included, as <file>.sz.
+    pub fn bin_files(&self) -> Result<Vec<(String, PathBuf)>, Box<dyn std::error::Error>> {
+        self.find(&self.bin, true)
+    }
+
+    // Every raw log under root, sorted by path
+    pub fn raw_logs(&self) -> Result<Vec<PathBuf>, Box<dyn std::error::Error>> {
+        let logs = self.find(&self.raw_log, false)?;
+        Ok(logs.into_iter().map(|(_, path)| path).collect())
+    }
+
+    // Every connection log under root, sorted by path
+    pub fn connection_logs(&self) -> Result<Vec<PathBuf>, Box<dyn std::error::Error>> {
+        let logs = self.find(&self.connection_log, false)?;
+        Ok(logs.into_iter().map(|(_, path)| path).collect())
+    }
+
+    // Every heartbeat file under root, compressed or not, sorted by path
+    pub fn heartbeat_files(&self) -> Result<Vec<PathBuf>, Box<dyn std::error::Error>> {
+        let files = self.find(&self.heartbeats, true)?;
+        Ok(files.into_iter().map(|(_, path)| path).collect())
+    }
+
+    // The times of every heartbeat under root, sorted
+    pub fn heartbeats(&self) -> Result<Vec<u64>, Box<dyn std::error::Error>> {
+        let mut heartbeats = Vec::new();
+        for path in self.heartbeat_files()? {
+            heartbeats.extend(read_heartbeats(&path)?);
+        }
+        heartbeats.sort_unstable();
+        Ok(heartbeats)
+    }
+
+    // Granularity of a binary file under root, "tick" if the bin template has no {granularity}
+    pub fn granularity(&self, path: &Path) -> Option<String> {
+        let relative = relative_path(&self.root, &uncompressed_path(path))?;
+        let values = match_template(&self.bin, &relative)?;
+        Some(
+            values
+                .get("granularity")
+                .map_or(TICK, String::as_str)
+                .to_string(),
+        )
+    }
+
+    fn find(
+        &self,
+        template: &str,
+        compressed: bool,
+    ) -> Result<Vec<(String, PathBuf)>, Box<dyn std::error::Error>> {
+        let mut files = Vec::new();
+        let mut directories = vec![self.root.clone()];
+        while let Some(directory) = directories.pop() {
+            for entry in std::fs::read_dir(&directory)? {
+                let path = entry?.path();
+                if path.is_dir() {
+                    directories.push(path);
+                    continue;
+                }
+
+                let matched = match compressed {
+                    true => uncompressed_path(&path),
+                    false => path.clone(),
+                };
+                let relative = match relative_path(&self.root, &matched) {
+                    Some(relative) => relative,
+                    None => continue,
+                };
+                if let Some(instrument) = match_instrument(template, &relative) {
+                    files.push((instrument, path));
+                }
+            }
+        }
+
+        files.sort_by(|a, b| a.1.cmp(&b.1));
+        Ok(files)
+    }
+}
+
+fn render(template: &str, instrument: &str, granularity: &str, time: u64) -> String {
+    let date = calendar::utc(time).format("%Y-%m-%d").to_string();
+
+    template
+        .replace("{instrument}", instrument)
+        .replace("{date}", &date)
+        .replace("{granularity}", granularity)
+}
+
+// A path under root, with forward slashes as templates have them
+fn relative_path(root: &Path, path: &Path) -> Option<String> {
+    let relative = path.strip_prefix(root).ok()?.to_str()?;
+    Some(relative.replace('\\', "/"))
+}
+
+// The path a compressed file had before it was compressed
+fn uncompressed_path(path: &Path) -> PathBuf {
+    match is_compressed(path) {
+        true => path.with_extension(""),
+        false => path.to_path_buf(),
+    }
+}
+
+// Match a path against a template, returning the instrument if it matches
+fn match_instrument(template: &str, path: &str) -> Option<String> {
+    let values = match_template(template, path)?;
+    Some(values.get("instrument").cloned().unwrap_or_default())
+}
+
+// Match a path against a template, returning the value of each placeholder if it matches.
+// Placeholders match anything except a path separator.
+fn match_template(template: &str, path: &str) -> Option<HashMap<String, String>> {
+    let mut values = HashMap::new();
+    let mut template = template;
+    let mut path = path;
+
+    loop {
+        let start = match template.find('{') {
+            Some(start) => start,
+            None => return (template == path).then_some(values),
+        };
+
+        // The literal text before the placeholder must match exactly
+        if !path.starts_with(&template[..start]) {
+            return None;
+        }
+        path = &path[start..];
+
+        let end = start + template[start..].find('}')?;
+        let placeholder = &template[start + 1..end];
+        template = &template[end + 1..];
+
+        // The placeholder ends where the next literal text begins
+        let literal_end = template.find('{').unwrap_or(template.len());
+        let literal = &template[..literal_end];
+        let value_end = if literal.is_empty() {
+            path.find('/').unwrap_or(path.len())
+        } else {
+            path.find(literal)?
+        };
+        let value = &path[..value_end];
+        if value.is_empty() || value.contains('/') {
+            return None;
+        }
+
+        values.insert(placeholder.to_string(), value.to_string());
+        path = &path[value_end..];
+    }
+}
@@ -0,0 +1,37 @@
+use crate::calendar;
+
+// Where the engine gets the current time from, in milliseconds since the UNIX epoch.
+// Simulated time follows the prices being replayed, so anything timed (checkpoints, journal
+// entries) happens at the same point in the data however fast it's replayed.
+#[derive(Debug, Clone, Default)]
+pub enum Clock {
+    #[default]
+    System,
+    Simulated {
+        now: u64,
+    },
+}
+
+impl Clock {
+    pub fn simulated() -> Self {
+        Clock::Simulated { now: 0 }
+    }
+
+    pub fn now(&self) -> u64 {
+        match self {
+            Clock::System => chrono::Utc::now().timestamp_millis() as u64,
+            Clock::Simulated { now } => *now,
+        }
+    }
+
+    // Move simulated time on to a price's time, it never goes backwards
+    pub fn advance(&mut self, time: u64) {
+        if let Clock::Simulated { now } = self {
+            *now = (*now).max(time);
+        }
+    }
+}
+
+pub fn format_time(time: u64) -> String {
+    calendar::utc(time).to_rfc3339()
+}
@@ -0,0 +1,137 @@
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+
+#[cfg(feature = "trading")]
+use crate::accounts::AggregateReport;
+use crate::control::ControlCommand;
+use crate::engine::ExecutionFill;
+use crate::models::{AccountCost, ExternalActivity, PassiveReport, ResolvedSignal};
+use crate::oanda::objects::Price;
+
+// Which events are acted on first when several are waiting. Commands from an operator come
+// before anything else, then what the account's transaction stream says happened, then the
+// engine's timers (which may stop trading altogether), then signals the strategy workers have
+// already resolved and last whatever came from the price stream. Events of the same priority
+// are acted on in the order they were queued.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum EventPriority {
+    Control,
+    Fills,
+    Timers,
+    Signals,
+    Prices,
+}
+
+// Periodic checks, each queued once the clock says it's due
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Timer {
+    KillSwitch,
+    Alerts,
+    Weekend,
+    Drawdown,
+    Session,
+    #[cfg(feature = "trading")]
+    AccountReport,
+    Checkpoint,
+    Status,
+    DroppedTicks,
+}
+
+pub enum EngineEvent {
+    Control(ControlCommand),
+
+    // A position change the engine didn't make, and a cost charged outside its own fills
+    External(ExternalActivity),
+    Cost(AccountCost),
+
+    // A posted order that ended, with the fills it led to
+    Passive(PassiveReport<ExecutionFill>),
+
+    Timer(Timer),
+
+    // The day's report on every account, once it's been read
+    #[cfg(feature = "trading")]
+    AccountReport(AggregateReport),
+
+    // A signal the strategy workers resolved on an earlier price
+    Signal(Price, ResolvedSignal),
+
+    Price(Price),
+    Backfill(Price),
+    Heartbeat,
+    StreamError(String),
+}
+
+impl EngineEvent {
+    pub fn priority(&self) -> EventPriority {
+        match self {
+            EngineEvent::Control(_) => EventPriority::Control,
+            EngineEvent::External(_) | EngineEvent::Cost(_) | EngineEvent::Passive(_) => {
+                EventPriority::Fills
+            }
+            EngineEvent::Timer(_) => EventPriority::Timers,
+            #[cfg(feature = "trading")]
+            EngineEvent::AccountReport(_) => EventPriority::Timers,
+            EngineEvent::Signal(..) => EventPriority::Signals,
+            EngineEvent::Price(_)
+            | EngineEvent::Backfill(_)
+            | EngineEvent::Heartbeat
+            | EngineEvent::StreamError(_) => EventPriority::Prices,
+        }
+    }
+}
+
+struct Queued {
+    priority: EventPriority,
+    sequence: u64,
+    event: EngineEvent,
+}
+
+impl PartialEq for Queued {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Queued {}
+
+impl PartialOrd for Queued {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+// Reversed, so the heap pops the highest priority and then the earliest queued
+impl Ord for Queued {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (other.priority, other.sequence).cmp(&(self.priority, self.sequence))
+    }
+}
+
+// Everything waiting to be acted on, in priority order. The order only depends on what was
+// queued and when, so a simulation over recorded prices acts on events in the same order every
+// time it's run.
+#[derive(Default)]
+pub struct EventQueue {
+    events: BinaryHeap<Queued>,
+    sequence: u64,
+}
+
+impl EventQueue {
+    pub fn push(&mut self, event: EngineEvent) {
+        self.events.push(Queued {
+            priority: event.priority(),
+            sequence: self.sequence,
+            event,
+        });
+        self.sequence += 1;
+    }
+
+    pub fn pop(&mut self) -> Option<EngineEvent> {
+        self.events.pop().map(|queued| queued.event)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.events.is_empty()
+    }
+}
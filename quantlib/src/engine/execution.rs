@@ -0,0 +1,1040 @@
+use std::collections::HashMap;
+use std::error::Error;
+
+use serde::{Deserialize, Serialize};
+
+use crate::backtest::{Fill, RestingOrder, RestingUpdate, SimulatedAccount};
+use crate::broker::{Broker, BrokerFill, OrderTags};
+use crate::engine::{format_time, ProtectiveStop};
+use crate::errors;
+use crate::fx::Converter;
+#[cfg(feature = "trading")]
+use crate::models::PortfolioBuilder;
+use crate::models::{
+    pip_size, spread_cost, AccountCost, CostGuardConfig, ExternalActivity, ForecastMapping,
+    MarginConfig, OrderSizer, PassiveExecutionConfig, PassiveOrders, PassiveOutcome, PassiveReport,
+    PositionSizer, PositionSizing, TargetSmoother, TargetSmoothing, TargetTolerance, TradingSignal,
+    TrailingStopManager, VolatilityTarget,
+};
+use crate::oanda::errors::OrderStateUnknownError;
+use crate::oanda::objects::{Price, Transaction};
+#[cfg(feature = "trading")]
+use crate::oanda::{self, TransactionStream};
+use crate::util::TradingConfig;
+
+// An executed order, from the live account or a paper one
+#[derive(Serialize, Debug, Clone)]
+pub struct ExecutionFill {
+    pub time: String,
+    pub instrument: String,
+    pub units: f64,
+    pub price: Option<f64>,
+
+    #[serde(rename = "transactionId")]
+    pub transaction_id: Option<String>,
+
+    // Profit realized by the fill, in the account currency
+    pub pl: Option<f64>,
+
+    // Commission charged for the fill, and financing paid (negative) or received on the trades
+    // it closed, in the account currency
+    #[serde(default)]
+    pub commission: Option<f64>,
+
+    #[serde(default)]
+    pub financing: Option<f64>,
+}
+
+impl ExecutionFill {
+    // Realized profit after the fill's costs, None if it reported neither
+    pub fn net_pl(&self) -> Option<f64> {
+        if self.pl.is_none() && self.commission.is_none() && self.financing.is_none() {
+            return None;
+        }
+        Some(
+            self.pl.unwrap_or(0.0) - self.commission.unwrap_or(0.0) + self.financing.unwrap_or(0.0),
+        )
+    }
+}
+
+impl From<&Transaction> for ExecutionFill {
+    fn from(transaction: &Transaction) -> Self {
+        ExecutionFill {
+            time: transaction.time.clone(),
+            instrument: transaction.instrument.clone().unwrap_or_default(),
+            units: transaction.units.unwrap_or(0.0),
+            price: transaction.price,
+            transaction_id: transaction.id.clone(),
+            pl: transaction.pl,
+            commission: transaction.commission,
+            financing: transaction.financing,
+        }
+    }
+}
+
+impl From<&Fill> for ExecutionFill {
+    fn from(fill: &Fill) -> Self {
+        ExecutionFill {
+            time: format_time(fill.time),
+            instrument: fill.instrument.clone(),
+            units: fill.units,
+            price: Some(fill.price),
+            transaction_id: None,
+            pl: fill.unconverted_pl.is_none().then_some(fill.realized_pl),
+            commission: None,
+            financing: None,
+        }
+    }
+}
+
+impl From<&BrokerFill> for ExecutionFill {
+    fn from(fill: &BrokerFill) -> Self {
+        ExecutionFill {
+            time: fill.time.clone(),
+            instrument: fill.instrument.clone(),
+            units: fill.units,
+            price: fill.price,
+            transaction_id: fill.id.clone(),
+            pl: fill.pl,
+            commission: None,
+            financing: None,
+        }
+    }
+}
+
+// Simulated account for paper trading, e.g. "paper": {"accountCurrency": "GBP"}
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PaperConfig {
+    #[serde(default = "default_account_currency")]
+    #[serde(rename = "accountCurrency")]
+    pub account_currency: String,
+
+    #[serde(default = "default_initial_balance")]
+    #[serde(rename = "initialBalance")]
+    pub initial_balance: f64,
+}
+
+fn default_account_currency() -> String {
+    "USD".to_string()
+}
+
+fn default_initial_balance() -> f64 {
+    100_000.0
+}
+
+impl Default for PaperConfig {
+    fn default() -> Self {
+        PaperConfig {
+            account_currency: default_account_currency(),
+            initial_balance: default_initial_balance(),
+        }
+    }
+}
+
+// Fills orders against a simulated account at the latest price, e.g. to run a strategy on live
+// prices without risking money or to replay recorded prices through the whole engine
+pub struct PaperExecution {
+    account: SimulatedAccount,
+    units: f64,
+    position_sizer: PositionSizer,
+    target_tolerance: TargetTolerance,
+    target_smoother: TargetSmoother,
+    trailing_stops: Option<TrailingStopManager>,
+
+    // Small orders posted rather than crossing the spread, see PassiveExecutionConfig
+    passive: Option<PassiveOrders<Fill>>,
+    passive_reports: Vec<PassiveReport<Fill>>,
+}
+
+impl PaperExecution {
+    pub fn new(config: &PaperConfig, units: f64) -> Self {
+        PaperExecution {
+            account: SimulatedAccount::new(&config.account_currency, config.initial_balance),
+            units,
+            position_sizer: PositionSizer::new(&PositionSizing::default(), units),
+            target_tolerance: TargetTolerance::default(),
+            target_smoother: TargetSmoother::new(&TargetSmoothing::default()),
+            trailing_stops: None,
+            passive: None,
+            passive_reports: Vec::new(),
+        }
+    }
+
+    // A paper account sized and traded as the config has it, apart from trailing stops
+    pub fn from_config(config: &TradingConfig, units: f64) -> Self {
+        PaperExecution::new(&config.paper, units)
+            .with_position_sizing(&config.position_sizing)
+            .with_volatility_target(config.volatility_target.clone())
+            .with_forecast_mapping(&config.forecast_mapping)
+            .with_margin(config.margin.clone())
+            .with_target_tolerance(&config.target_tolerance)
+            .with_target_smoothing(&config.target_smoothing)
+            .with_passive_execution(config.passive_execution.clone())
+    }
+
+    // Size positions as configured instead of `units` for every instrument
+    pub fn with_position_sizing(mut self, sizing: &PositionSizing) -> Self {
+        self.position_sizer = PositionSizer::new(sizing, self.units);
+        self
+    }
+
+    // Scale positions to a portfolio volatility target, after with_position_sizing
+    pub fn with_volatility_target(mut self, target: Option<VolatilityTarget>) -> Self {
+        self.position_sizer = self.position_sizer.with_volatility_target(target);
+        self
+    }
+
+    // Map forecasts to positions as configured, after with_position_sizing
+    pub fn with_forecast_mapping(mut self, mapping: &ForecastMapping) -> Self {
+        self.position_sizer = self.position_sizer.with_forecast_mapping(mapping);
+        self
+    }
+
+    // Cap positions at the settings' "risk" "maxUnits", after with_position_sizing
+    pub fn with_max_units(mut self, max_units: Option<f64>) -> Self {
+        self.position_sizer = self.position_sizer.with_max_units(max_units);
+        self
+    }
+
+    pub fn with_margin(mut self, margin: Option<MarginConfig>) -> Self {
+        self.account = self.account.with_margin(margin);
+        self
+    }
+
+    pub fn with_target_tolerance(mut self, tolerance: &TargetTolerance) -> Self {
+        self.target_tolerance = tolerance.clone();
+        self
+    }
+
+    pub fn with_target_smoothing(mut self, smoothing: &TargetSmoothing) -> Self {
+        self.target_smoother = TargetSmoother::new(smoothing);
+        self
+    }
+
+    pub fn with_trailing_stops(mut self, trailing_stops: TrailingStopManager) -> Self {
+        self.trailing_stops = Some(trailing_stops);
+        self
+    }
+
+    pub fn with_passive_execution(mut self, config: Option<PassiveExecutionConfig>) -> Self {
+        self.passive = config.map(PassiveOrders::new);
+        self
+    }
+
+    // Fill the instrument's resting order if the latest price trades through it, or send what's
+    // left at the market once it has timed out
+    fn check_resting(&mut self, price: &Price) {
+        let passive = match &mut self.passive {
+            Some(passive) => passive,
+            None => return,
+        };
+        let order = match passive.get(&price.instrument) {
+            Some(order) => order.clone(),
+            None => return,
+        };
+        match order.update(price) {
+            RestingUpdate::Triggered => {
+                let remaining = order.remaining();
+                let outcome = match self.account.limit_fill(
+                    &price.instrument,
+                    remaining,
+                    order.price,
+                    "passive",
+                ) {
+                    Some(fill) => {
+                        passive.fill(&price.instrument, remaining, fill);
+                        PassiveOutcome::Filled
+                    }
+                    None => PassiveOutcome::Cancelled,
+                };
+                self.passive_reports
+                    .extend(passive.finish(&price.instrument, outcome));
+            }
+            RestingUpdate::Expired => {
+                if let Some(mut report) =
+                    passive.finish(&price.instrument, PassiveOutcome::MarketFallback)
+                {
+                    report.fills.extend(self.account.market_order(
+                        &price.instrument,
+                        order.remaining(),
+                        "passiveFallback",
+                    ));
+                    self.passive_reports.push(report);
+                }
+            }
+            RestingUpdate::Waiting => {}
+        }
+    }
+
+    pub fn account(&self) -> &SimulatedAccount {
+        &self.account
+    }
+}
+
+// Trades through any backend implementing the broker traits. Positions are sized like paper
+// ones, tracked from the fills of the orders placed, and refreshed from the broker when settling.
+// Orders go through the same margin, cost and order size checks as the portfolio builder's.
+pub struct BrokerExecution<'a> {
+    broker: &'a dyn Broker,
+    units: f64,
+    position_sizer: PositionSizer,
+    target_tolerance: TargetTolerance,
+    target_smoother: TargetSmoother,
+    positions: HashMap<String, f64>,
+
+    // The pre-trade checks of the portfolio builder, the cost guard only by the latest spread
+    order_sizer: Option<OrderSizer>,
+    margin: Option<MarginConfig>,
+    cost_guard: Option<CostGuardConfig>,
+    converter: Converter,
+    quotes: HashMap<String, (f64, f64)>,
+
+    // Differences found reconciling after an order whose outcome wasn't known, until polled
+    external: Vec<ExternalActivity>,
+}
+
+impl<'a> BrokerExecution<'a> {
+    pub fn new(broker: &'a dyn Broker, units: f64) -> Self {
+        BrokerExecution {
+            broker,
+            units,
+            position_sizer: PositionSizer::new(&PositionSizing::default(), units),
+            target_tolerance: TargetTolerance::default(),
+            target_smoother: TargetSmoother::new(&TargetSmoothing::default()),
+            positions: HashMap::new(),
+            order_sizer: None,
+            margin: None,
+            cost_guard: None,
+            converter: Converter::new(),
+            quotes: HashMap::new(),
+            external: Vec::new(),
+        }
+    }
+
+    // Size positions as configured instead of `units` for every instrument
+    pub fn with_position_sizing(mut self, sizing: &PositionSizing) -> Self {
+        self.position_sizer = PositionSizer::new(sizing, self.units);
+        self
+    }
+
+    // Scale positions to a portfolio volatility target, after with_position_sizing
+    pub fn with_volatility_target(mut self, target: Option<VolatilityTarget>) -> Self {
+        self.position_sizer = self.position_sizer.with_volatility_target(target);
+        self
+    }
+
+    // Map forecasts to positions as configured, after with_position_sizing
+    pub fn with_forecast_mapping(mut self, mapping: &ForecastMapping) -> Self {
+        self.position_sizer = self.position_sizer.with_forecast_mapping(mapping);
+        self
+    }
+
+    // Cap positions at the settings' "risk" "maxUnits", after with_position_sizing
+    pub fn with_max_units(mut self, max_units: Option<f64>) -> Self {
+        self.position_sizer = self.position_sizer.with_max_units(max_units);
+        self
+    }
+
+    pub fn with_target_tolerance(mut self, tolerance: &TargetTolerance) -> Self {
+        self.target_tolerance = tolerance.clone();
+        self
+    }
+
+    pub fn with_target_smoothing(mut self, smoothing: &TargetSmoothing) -> Self {
+        self.target_smoother = TargetSmoother::new(smoothing);
+        self
+    }
+
+    // Fit orders to the instruments' trading limits, as the broker reports them
+    pub fn with_order_sizer(mut self, order_sizer: OrderSizer) -> Self {
+        self.order_sizer = Some(order_sizer);
+        self
+    }
+
+    pub fn with_margin(mut self, margin: Option<MarginConfig>) -> Self {
+        self.margin = margin;
+        self
+    }
+
+    // Guard against costly orders by the latest spread, the broker's book being unavailable
+    pub fn with_cost_guard(mut self, cost_guard: Option<CostGuardConfig>) -> Self {
+        self.cost_guard = cost_guard;
+        self
+    }
+
+    pub fn broker(&self) -> &'a dyn Broker {
+        self.broker
+    }
+
+    // The position the margin limits and cost guard allow on the way to `target` units, as the
+    // portfolio builder checks them: all of it, None if they refuse it, or flat if they refuse a
+    // flip so the position is still closed. The margin check asks the broker for the account.
+    async fn check_limits(
+        &self,
+        instrument: &str,
+        target: f64,
+    ) -> Result<Option<f64>, Box<dyn Error>> {
+        let held = self.positions.get(instrument).copied().unwrap_or(0.0);
+        let flip = held * target < 0.0;
+        if !flip && target.abs() <= held.abs() {
+            return Ok(Some(target));
+        }
+
+        let mut result = Ok(());
+        if let Some(margin) = &self.margin {
+            let account = self.broker.account().await?;
+            let change = margin
+                .position_margin(&self.converter, instrument, target, &account.currency)
+                .zip(margin.position_margin(&self.converter, instrument, held, &account.currency))
+                .map(|(after, before)| after - before);
+            result = match change {
+                Some(change) => margin.check(
+                    account.margin_used,
+                    account.margin_used + change,
+                    account.nav,
+                ),
+                None => Err(format!("no conversion rate into {} yet", account.currency)),
+            };
+        }
+        if let (Ok(()), Some(guard)) = (&result, &self.cost_guard) {
+            result = match self
+                .quotes
+                .get(instrument)
+                .and_then(|(bid, ask)| spread_cost(*bid, *ask))
+            {
+                Some(estimate) => guard.check(instrument, &estimate),
+                None => Err("its cost can't be estimated, there is no price".to_string()),
+            };
+        }
+        match result {
+            Ok(()) => Ok(Some(target)),
+            Err(reason) if flip => {
+                log::warn!(
+                    "[{}] Closing rather than moving to {} units: {}",
+                    instrument,
+                    target,
+                    reason
+                );
+                Ok(Some(0.0))
+            }
+            Err(reason) => {
+                log::warn!(
+                    "[{}] Not moving to {} units: {}",
+                    instrument,
+                    target,
+                    reason
+                );
+                Ok(None)
+            }
+        }
+    }
+
+    // Replace the tracked positions with the broker's, returning the differences as changes
+    // made outside the engine
+    pub async fn update_positions(&mut self) -> Result<Vec<ExternalActivity>, Box<dyn Error>> {
+        let positions: HashMap<String, f64> = self
+            .broker
+            .positions()
+            .await?
+            .into_iter()
+            .map(|position| (position.instrument, position.units))
+            .collect();
+        let mut instruments: Vec<&String> = positions.keys().chain(self.positions.keys()).collect();
+        instruments.sort();
+        instruments.dedup();
+
+        let mut external = Vec::new();
+        for instrument in instruments {
+            let tracked = self.positions.get(instrument).copied().unwrap_or(0.0);
+            let actual = positions.get(instrument).copied().unwrap_or(0.0);
+            if tracked != actual {
+                log::warn!(
+                    "[{}] {} position is {} units, expected {}",
+                    instrument,
+                    self.broker.name(),
+                    actual,
+                    tracked
+                );
+                external.push(ExternalActivity {
+                    time: chrono::Utc::now().to_rfc3339(),
+                    instrument: instrument.clone(),
+                    units: actual - tracked,
+                    transaction_id: None,
+                    reason: Some("RECONCILE".to_string()),
+                });
+            }
+        }
+        self.positions = positions;
+        Ok(external)
+    }
+
+    // Work out what became of an order whose state was unknown from its client order ID, and if
+    // the broker can't say, refresh the positions taking a change to the instrument's position
+    // as the order's fill rather than as external activity
+    async fn resolve_unknown_order(
+        &mut self,
+        instrument: &str,
+        client_order_id: &str,
+    ) -> Result<Option<BrokerFill>, Box<dyn Error>> {
+        match self.broker.order_outcome(instrument, client_order_id).await {
+            Ok(outcome) => Ok(outcome.flatten()),
+            Err(e) => {
+                log::warn!(
+                    "[{}] Couldn't look up order {}: {}, reconciling positions...",
+                    instrument,
+                    client_order_id,
+                    e
+                );
+                for activity in self.update_positions().await? {
+                    if activity.instrument == instrument {
+                        log::warn!(
+                            "[{}] Taking the change of {} units as order {}'s",
+                            instrument,
+                            activity.units,
+                            client_order_id
+                        );
+                    } else {
+                        self.external.push(activity);
+                    }
+                }
+                Ok(None)
+            }
+        }
+    }
+
+    fn apply_fills(&mut self, fills: &[BrokerFill]) {
+        for fill in fills {
+            *self.positions.entry(fill.instrument.clone()).or_default() += fill.units;
+        }
+        self.positions.retain(|_, units| *units != 0.0);
+    }
+}
+
+// Where the engine's orders go. Live orders are placed with OANDA by the portfolio builder,
+// which also keeps its cached positions up to date from the transaction stream, or through a
+// broker backend. Only paper and broker execution are available without the "trading" feature.
+pub enum Execution<'a> {
+    #[cfg(feature = "trading")]
+    Live {
+        portfolio: Box<PortfolioBuilder<'a>>,
+        transactions: Box<TransactionStream<'a>>,
+    },
+    Paper(Box<PaperExecution>),
+    Broker(Box<BrokerExecution<'a>>),
+
+    // Stands in for live execution without the "trading" feature, and can't be created
+    #[cfg(not(feature = "trading"))]
+    Unavailable(std::convert::Infallible, std::marker::PhantomData<&'a ()>),
+}
+
+impl<'a> Execution<'a> {
+    // Bring positions up to date from the account's transactions, reconciling them with the
+    // account if it's time to. Returns position changes the engine didn't make.
+    pub async fn poll_account(&mut self) -> Result<Vec<ExternalActivity>, Box<dyn Error>> {
+        match self {
+            #[cfg(not(feature = "trading"))]
+            Execution::Unavailable(never, _) => match *never {},
+            #[cfg(feature = "trading")]
+            Execution::Live {
+                portfolio,
+                transactions,
+            } => {
+                match transactions.poll_transactions().await {
+                    Ok(transactions) => portfolio.apply_transactions(&transactions),
+                    Err(e) => {
+                        log::warn!("Transaction stream error: {}, reconnecting...", e);
+                        transactions.refresh_connection().await?;
+                    }
+                }
+                portfolio.reconcile_if_due().await?;
+                Ok(portfolio.take_external_activity())
+            }
+            Execution::Paper(_) => Ok(Vec::new()),
+            Execution::Broker(broker) => Ok(std::mem::take(&mut broker.external)),
+        }
+    }
+
+    // Act on a price before any signal on it, closing any position whose trailing stop it hits.
+    // Returns the fills of those closes.
+    pub async fn handle_price(
+        &mut self,
+        price: &Price,
+    ) -> Result<Vec<ExecutionFill>, Box<dyn Error>> {
+        match self {
+            #[cfg(not(feature = "trading"))]
+            Execution::Unavailable(never, _) => match *never {},
+            #[cfg(feature = "trading")]
+            Execution::Live { portfolio, .. } => {
+                let fills = portfolio.handle_price(price).await?;
+                Ok(fills.iter().map(ExecutionFill::from).collect())
+            }
+            Execution::Paper(paper) => {
+                paper.account.update_price(price);
+                paper.position_sizer.update(price);
+                paper.target_smoother.update(price);
+                paper.check_resting(price);
+                let mut fills = Vec::new();
+                if let Some(trailing_stops) = &mut paper.trailing_stops {
+                    let units = paper.account.units(&price.instrument);
+                    if let Some(exit_units) = trailing_stops.tick(price, units) {
+                        fills.extend(
+                            paper
+                                .account
+                                .market_order(&price.instrument, exit_units, "trailingStop")
+                                .as_ref()
+                                .map(ExecutionFill::from),
+                        );
+                    }
+                }
+                Ok(fills)
+            }
+            Execution::Broker(broker) => {
+                broker.position_sizer.update(price);
+                broker.target_smoother.update(price);
+                broker.converter.update(price);
+                broker.quotes.insert(
+                    price.instrument.clone(),
+                    (price.bid as f64, price.ask as f64),
+                );
+                Ok(Vec::new())
+            }
+        }
+    }
+
+    // Move the signal's instrument to its target position, returning the fills of any orders.
+    // Live orders carry the tags, paper orders have nowhere to put them.
+    pub async fn execute(
+        &mut self,
+        signal: TradingSignal,
+        tags: &OrderTags,
+    ) -> Result<Vec<ExecutionFill>, Box<dyn Error>> {
+        match self {
+            #[cfg(not(feature = "trading"))]
+            Execution::Unavailable(never, _) => match *never {},
+            #[cfg(feature = "trading")]
+            Execution::Live { portfolio, .. } => {
+                let instrument = signal.instrument.clone();
+                match portfolio.handle_tagged_signal(signal, tags).await {
+                    Ok(fills) => Ok(fills.iter().map(ExecutionFill::from).collect()),
+                    // We don't know whether the order went through, so ask OANDA by its client ID
+                    Err(e) => match errors::find::<OrderStateUnknownError>(e.as_ref()) {
+                        Some(unknown) => {
+                            log::error!("{}, looking it up...", e);
+                            let fills = portfolio
+                                .resolve_unknown_order(&instrument, &unknown.client_order_id)
+                                .await?;
+                            Ok(fills.iter().map(ExecutionFill::from).collect())
+                        }
+                        None => Err(e),
+                    },
+                }
+            }
+            Execution::Paper(paper) => {
+                let target = match paper
+                    .position_sizer
+                    .target(&signal.instrument, signal.forecast)
+                {
+                    Some(target) => target,
+                    None => {
+                        log::warn!(
+                            "[{}] Can't size a position without conversion rates yet, ignoring signal",
+                            signal.instrument
+                        );
+                        return Ok(Vec::new());
+                    }
+                };
+                // A newer signal replaces the instrument's resting order
+                if let Some(passive) = &mut paper.passive {
+                    paper
+                        .passive_reports
+                        .extend(passive.finish(&signal.instrument, PassiveOutcome::Cancelled));
+                }
+                let current = paper.account.units(&signal.instrument);
+                let target = paper
+                    .target_smoother
+                    .smooth(&signal.instrument, current, target);
+                if paper.target_tolerance.within(current, target) {
+                    return Ok(Vec::new());
+                }
+                let units = target - current;
+                let quote = paper.account.price(&signal.instrument);
+                if let (Some(passive), Some(quote)) = (&mut paper.passive, quote) {
+                    if passive.config().applies(units) {
+                        let (bid, ask) = (quote.bid as f64, quote.ask as f64);
+                        let order =
+                            passive
+                                .config()
+                                .order(&signal.instrument, units, bid, ask, quote.time);
+                        passive.post(RestingOrder {
+                            forecast: signal.forecast,
+                            tags: tags.clone(),
+                            ..order
+                        });
+                        return Ok(Vec::new());
+                    }
+                }
+                let fill = paper
+                    .account
+                    .market_order(&signal.instrument, units, "signal");
+                Ok(fill.iter().map(ExecutionFill::from).collect())
+            }
+            Execution::Broker(broker) => {
+                let target = match broker
+                    .position_sizer
+                    .target(&signal.instrument, signal.forecast)
+                {
+                    Some(target) => target,
+                    None => {
+                        log::warn!(
+                            "[{}] Can't size a position without conversion rates yet, ignoring signal",
+                            signal.instrument
+                        );
+                        return Ok(Vec::new());
+                    }
+                };
+                let held = broker
+                    .positions
+                    .get(&signal.instrument)
+                    .copied()
+                    .unwrap_or(0.0);
+                let target = broker
+                    .target_smoother
+                    .smooth(&signal.instrument, held, target);
+                if broker.target_tolerance.within(held, target) {
+                    return Ok(Vec::new());
+                }
+                let target = match broker.check_limits(&signal.instrument, target).await? {
+                    Some(target) => target,
+                    None => return Ok(Vec::new()),
+                };
+                let units = match &broker.order_sizer {
+                    Some(order_sizer) => order_sizer.size(&signal.instrument, target - held),
+                    None => Some(target - held).filter(|units| *units != 0.0),
+                };
+                let units = match units {
+                    Some(units) => units,
+                    None => return Ok(Vec::new()),
+                };
+                let fill = match broker
+                    .broker
+                    .tagged_market_order(&signal.instrument, units, tags)
+                    .await
+                {
+                    Ok(fill) => fill,
+                    // As for live orders, look it up by its client ID
+                    Err(e) => match errors::find::<OrderStateUnknownError>(e.as_ref()) {
+                        Some(unknown) => {
+                            log::error!("{}, looking it up...", e);
+                            broker
+                                .resolve_unknown_order(&signal.instrument, &unknown.client_order_id)
+                                .await?
+                        }
+                        None => return Err(e),
+                    },
+                };
+                broker.apply_fills(fill.as_slice());
+                Ok(fill.iter().map(ExecutionFill::from).collect())
+            }
+        }
+    }
+
+    // Apply any transactions still in flight and refresh positions from the account, so
+    // positions are known exactly before shutting down
+    pub async fn settle(&mut self) -> Result<Vec<ExternalActivity>, Box<dyn Error>> {
+        match self {
+            #[cfg(not(feature = "trading"))]
+            Execution::Unavailable(never, _) => match *never {},
+            #[cfg(feature = "trading")]
+            Execution::Live {
+                portfolio,
+                transactions,
+            } => {
+                if let Ok(transactions) = transactions.poll_transactions().await {
+                    portfolio.apply_transactions(&transactions);
+                }
+                portfolio.update_positions().await?;
+                Ok(portfolio.take_external_activity())
+            }
+            Execution::Paper(_) => Ok(Vec::new()),
+            Execution::Broker(broker) => broker.update_positions().await,
+        }
+    }
+
+    // Financing and commissions the account was charged outside our own fills since the last
+    // call, only known from OANDA's transaction stream
+    pub fn take_costs(&mut self) -> Vec<AccountCost> {
+        match self {
+            #[cfg(not(feature = "trading"))]
+            Execution::Unavailable(never, _) => match *never {},
+            #[cfg(feature = "trading")]
+            Execution::Live { portfolio, .. } => portfolio.take_costs(),
+            Execution::Paper(_) | Execution::Broker(_) => Vec::new(),
+        }
+    }
+
+    // Posted orders that ended since the last call, with the fills they led to
+    pub fn take_passive_reports(&mut self) -> Vec<PassiveReport<ExecutionFill>> {
+        match self {
+            #[cfg(not(feature = "trading"))]
+            Execution::Unavailable(never, _) => match *never {},
+            #[cfg(feature = "trading")]
+            Execution::Live { portfolio, .. } => portfolio
+                .take_passive_reports()
+                .into_iter()
+                .map(|report| report.map_fills(|fill| ExecutionFill::from(fill)))
+                .collect(),
+            Execution::Paper(paper) => std::mem::take(&mut paper.passive_reports)
+                .into_iter()
+                .map(|report| report.map_fills(|fill| ExecutionFill::from(fill)))
+                .collect(),
+            Execution::Broker(_) => Vec::new(),
+        }
+    }
+
+    // The instrument's posted order waiting for a fill, if it has one
+    pub fn resting_order(&self, instrument: &str) -> Option<&RestingOrder> {
+        match self {
+            #[cfg(not(feature = "trading"))]
+            Execution::Unavailable(never, _) => match *never {},
+            #[cfg(feature = "trading")]
+            Execution::Live { portfolio, .. } => portfolio.resting_order(instrument),
+            Execution::Paper(paper) => paper.passive.as_ref()?.get(instrument),
+            Execution::Broker(_) => None,
+        }
+    }
+
+    // Instruments with an open position, and their net units
+    pub fn open_positions(&self) -> Vec<(String, f64)> {
+        match self {
+            #[cfg(not(feature = "trading"))]
+            Execution::Unavailable(never, _) => match *never {},
+            #[cfg(feature = "trading")]
+            Execution::Live { portfolio, .. } => portfolio.open_positions(),
+            Execution::Paper(paper) => paper
+                .account
+                .positions()
+                .iter()
+                .map(|(instrument, position)| (instrument.clone(), position.units))
+                .collect(),
+            Execution::Broker(broker) => broker
+                .positions
+                .iter()
+                .map(|(instrument, units)| (instrument.clone(), *units))
+                .collect(),
+        }
+    }
+
+    // Whether the signal's instrument already holds the signal's full target, as opposed to
+    // having been moved part of the way by smoothing or not at all
+    pub fn target_reached(&mut self, signal: &TradingSignal) -> bool {
+        let (position_sizer, tolerance, current) = match self {
+            #[cfg(not(feature = "trading"))]
+            Execution::Unavailable(never, _) => match *never {},
+            #[cfg(feature = "trading")]
+            Execution::Live { portfolio, .. } => return portfolio.target_reached(signal),
+            Execution::Paper(paper) => (
+                &mut paper.position_sizer,
+                &paper.target_tolerance,
+                paper.account.units(&signal.instrument),
+            ),
+            Execution::Broker(broker) => (
+                &mut broker.position_sizer,
+                &broker.target_tolerance,
+                broker
+                    .positions
+                    .get(&signal.instrument)
+                    .copied()
+                    .unwrap_or(0.0),
+            ),
+        };
+        position_sizer
+            .target(&signal.instrument, signal.forecast)
+            .is_some_and(|target| tolerance.within(current, target))
+    }
+
+    // The signal's full, unsmoothed target position, or None if the instrument can't be sized yet
+    pub fn target(&mut self, signal: &TradingSignal) -> Option<f64> {
+        match self {
+            #[cfg(not(feature = "trading"))]
+            Execution::Unavailable(never, _) => match *never {},
+            #[cfg(feature = "trading")]
+            Execution::Live { portfolio, .. } => portfolio.target(signal),
+            Execution::Paper(paper) => paper
+                .position_sizer
+                .target(&signal.instrument, signal.forecast),
+            Execution::Broker(broker) => broker
+                .position_sizer
+                .target(&signal.instrument, signal.forecast),
+        }
+    }
+
+    // The currency the account is valued in, for the notional limits
+    pub async fn account_currency(&self) -> Result<String, Box<dyn Error>> {
+        match self {
+            #[cfg(not(feature = "trading"))]
+            Execution::Unavailable(never, _) => match *never {},
+            #[cfg(feature = "trading")]
+            Execution::Live { portfolio, .. } => portfolio.account_currency().await,
+            Execution::Paper(paper) => Ok(paper.account.currency.clone()),
+            Execution::Broker(broker) => Ok(broker.broker.account().await?.currency),
+        }
+    }
+
+    // Unrealized profit of each open position in the account currency, for alerts
+    pub async fn position_pl(&self) -> Result<Vec<(String, f64)>, Box<dyn Error>> {
+        match self {
+            #[cfg(not(feature = "trading"))]
+            Execution::Unavailable(never, _) => match *never {},
+            #[cfg(feature = "trading")]
+            Execution::Live { portfolio, .. } => portfolio.position_pl().await,
+            Execution::Paper(paper) => Ok(paper
+                .account
+                .positions()
+                .keys()
+                .filter_map(|instrument| {
+                    let pl = paper.account.position_pl(instrument)?;
+                    Some((instrument.clone(), pl))
+                })
+                .collect()),
+            Execution::Broker(broker) => Ok(broker
+                .broker
+                .positions()
+                .await?
+                .into_iter()
+                .filter(|position| position.units != 0.0)
+                .map(|position| (position.instrument, position.unrealized_pl))
+                .collect()),
+        }
+    }
+
+    // The account's equity in the account currency, for drawdown scaling
+    pub async fn equity(&self) -> Result<f64, Box<dyn Error>> {
+        match self {
+            #[cfg(not(feature = "trading"))]
+            Execution::Unavailable(never, _) => match *never {},
+            #[cfg(feature = "trading")]
+            Execution::Live { portfolio, .. } => portfolio.equity().await,
+            Execution::Paper(paper) => Ok(paper.account.nav()),
+            Execution::Broker(broker) => Ok(broker.broker.account().await?.nav),
+        }
+    }
+
+    // Scale every position sized from now on, e.g. for the account's drawdown
+    pub fn set_risk_scale(&mut self, scale: f64) {
+        match self {
+            #[cfg(not(feature = "trading"))]
+            Execution::Unavailable(never, _) => match *never {},
+            #[cfg(feature = "trading")]
+            Execution::Live { portfolio, .. } => portfolio.set_risk_scale(scale),
+            Execution::Paper(paper) => paper.position_sizer.set_risk_scale(scale),
+            Execution::Broker(broker) => broker.position_sizer.set_risk_scale(scale),
+        }
+    }
+
+    // Whether the volatility target has rescaled positions since the last call, so open ones
+    // need resizing
+    pub fn take_rescaled(&mut self) -> bool {
+        match self {
+            #[cfg(not(feature = "trading"))]
+            Execution::Unavailable(never, _) => match *never {},
+            #[cfg(feature = "trading")]
+            Execution::Live { portfolio, .. } => portfolio.take_rescaled(),
+            Execution::Paper(paper) => paper.position_sizer.take_rescaled(),
+            Execution::Broker(broker) => broker.position_sizer.take_rescaled(),
+        }
+    }
+
+    // Close the position in an instrument, returning the fills
+    pub async fn flatten(
+        &mut self,
+        instrument: &str,
+    ) -> Result<Vec<ExecutionFill>, Box<dyn Error>> {
+        match self {
+            #[cfg(not(feature = "trading"))]
+            Execution::Unavailable(never, _) => match *never {},
+            #[cfg(feature = "trading")]
+            Execution::Live { portfolio, .. } => {
+                portfolio.cancel_resting(instrument).await?;
+                let fills = portfolio.close(instrument).await?;
+                Ok(fills.iter().map(ExecutionFill::from).collect())
+            }
+            Execution::Paper(paper) => {
+                if let Some(passive) = &mut paper.passive {
+                    paper
+                        .passive_reports
+                        .extend(passive.finish(instrument, PassiveOutcome::Cancelled));
+                }
+                let units = -paper.account.units(instrument);
+                let fill = paper.account.market_order(instrument, units, "shutdown");
+                Ok(fill.iter().map(ExecutionFill::from).collect())
+            }
+            Execution::Broker(broker) => {
+                let fills = broker.broker.close_position(instrument).await?;
+                broker.apply_fills(&fills);
+                Ok(fills.iter().map(ExecutionFill::from).collect())
+            }
+        }
+    }
+
+    // Place a stop order the given number of pips beyond the price for closing the position
+    pub async fn protect(
+        &mut self,
+        instrument: &str,
+        units: f64,
+        price: &Price,
+        stop_pips: f64,
+    ) -> Result<ProtectiveStop, Box<dyn Error>> {
+        let distance = stop_pips * pip_size(instrument);
+        let stop_price = if units > 0.0 {
+            price.bid as f64 - distance
+        } else {
+            price.ask as f64 + distance
+        };
+        let order_id = match self {
+            #[cfg(not(feature = "trading"))]
+            Execution::Unavailable(never, _) => match *never {},
+            #[cfg(feature = "trading")]
+            Execution::Live { portfolio, .. } => {
+                oanda::place_protective_stop(
+                    instrument,
+                    -units,
+                    stop_price,
+                    &portfolio.settings().credentials.oanda,
+                )
+                .await?
+            }
+            Execution::Broker(broker) => {
+                broker
+                    .broker
+                    .protective_stop(instrument, -units, stop_price)
+                    .await?
+            }
+            Execution::Paper(_) => return Err("Paper accounts can't hold stop orders".into()),
+        };
+        Ok(ProtectiveStop {
+            instrument: instrument.to_string(),
+            units: -units,
+            price: stop_price,
+            order_id,
+        })
+    }
+
+    // Cancel a stop placed by `protect`, false if it was no longer resting
+    pub async fn cancel_stop(&mut self, stop: &ProtectiveStop) -> Result<bool, Box<dyn Error>> {
+        match self {
+            #[cfg(not(feature = "trading"))]
+            Execution::Unavailable(never, _) => match *never {},
+            #[cfg(feature = "trading")]
+            Execution::Live { portfolio, .. } => {
+                oanda::cancel_order(&stop.order_id, &portfolio.settings().credentials.oanda).await
+            }
+            Execution::Broker(broker) => {
+                broker
+                    .broker
+                    .cancel_order(&stop.instrument, stop.units, &stop.order_id)
+                    .await
+            }
+            Execution::Paper(_) => Ok(false),
+        }
+    }
+}
@@ -0,0 +1,266 @@
+use std::collections::VecDeque;
+
+use serde::{Deserialize, Serialize};
+
+// How the health of the price connection is scored and what the risk manager does while it's
+// poor, "connectionHealth" in the trading config, e.g. {"threshold": 0.5, "action": {"type":
+// "tightenLimits", "scale": 0.5}}. The score runs from 1 (healthy) down to 0 and is the product
+// of three parts over the last `window` seconds: the longest gap between heartbeats, 1 up to
+// `heartbeatInterval` and 0 from `maxHeartbeatGap`; the average delay of prices behind their
+// timestamps, 1 up to `goodLatency` and 0 from `maxLatency`; and the stream errors (malformed
+// lines and failed reads), 0 from `maxErrors`. Times are in milliseconds. Price sources without
+// heartbeats aren't marked down for them, and replays aren't for latency. Without a threshold
+// the score is only reported.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ConnectionHealthConfig {
+    #[serde(default = "default_window")]
+    pub window: u64,
+
+    #[serde(default = "default_heartbeat_interval")]
+    #[serde(rename = "heartbeatInterval")]
+    pub heartbeat_interval: u64,
+
+    #[serde(default = "default_max_heartbeat_gap")]
+    #[serde(rename = "maxHeartbeatGap")]
+    pub max_heartbeat_gap: u64,
+
+    #[serde(default = "default_good_latency")]
+    #[serde(rename = "goodLatency")]
+    pub good_latency: u64,
+
+    #[serde(default = "default_max_latency")]
+    #[serde(rename = "maxLatency")]
+    pub max_latency: u64,
+
+    #[serde(default = "default_max_errors")]
+    #[serde(rename = "maxErrors")]
+    pub max_errors: u64,
+
+    // Score below which the action is taken, until it's back above
+    #[serde(default)]
+    pub threshold: Option<f64>,
+
+    #[serde(default)]
+    pub action: HealthAction,
+}
+
+fn default_window() -> u64 {
+    300
+}
+
+// OANDA sends a heartbeat every 5 seconds
+fn default_heartbeat_interval() -> u64 {
+    5_000
+}
+
+fn default_max_heartbeat_gap() -> u64 {
+    30_000
+}
+
+fn default_good_latency() -> u64 {
+    250
+}
+
+fn default_max_latency() -> u64 {
+    2_000
+}
+
+fn default_max_errors() -> u64 {
+    10
+}
+
+impl Default for ConnectionHealthConfig {
+    fn default() -> Self {
+        ConnectionHealthConfig {
+            window: default_window(),
+            heartbeat_interval: default_heartbeat_interval(),
+            max_heartbeat_gap: default_max_heartbeat_gap(),
+            good_latency: default_good_latency(),
+            max_latency: default_max_latency(),
+            max_errors: default_max_errors(),
+            threshold: None,
+            action: HealthAction::default(),
+        }
+    }
+}
+
+// What the risk manager does while the connection is unhealthy
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum HealthAction {
+    // Signals can close positions but not open or add to them. Those held back are traded to the
+    // strategies' standing forecasts once the connection recovers.
+    #[default]
+    PauseEntries,
+
+    // Multiply every position by `scale`, on top of any drawdown scaling, resizing open positions
+    // as soon as the connection turns unhealthy and again once it recovers
+    TightenLimits {
+        scale: f64,
+    },
+}
+
+// A move of the score across the threshold
+#[derive(Debug, Clone)]
+pub struct HealthChange {
+    pub score: f64,
+    pub healthy: bool,
+}
+
+// The rolling connection health score, from what the engine receives from its price source
+pub struct ConnectionHealth {
+    config: ConnectionHealthConfig,
+    last_heartbeat: Option<u64>,
+
+    // (time received, milliseconds) of each heartbeat gap and price delay in the window
+    gaps: VecDeque<(u64, u64)>,
+    latencies: VecDeque<(u64, u64)>,
+    errors: VecDeque<u64>,
+
+    // Malformed lines counted by the stream parsers when last seen
+    malformed: u64,
+    score: f64,
+    healthy: bool,
+}
+
+impl ConnectionHealth {
+    pub fn new(config: ConnectionHealthConfig) -> Self {
+        ConnectionHealth {
+            config,
+            last_heartbeat: None,
+            gaps: VecDeque::new(),
+            latencies: VecDeque::new(),
+            errors: VecDeque::new(),
+            malformed: 0,
+            score: 1.0,
+            healthy: true,
+        }
+    }
+
+    pub fn record_heartbeat(&mut self, now: u64) {
+        if let Some(last) = self.last_heartbeat {
+            self.gaps.push_back((now, now.saturating_sub(last)));
+        }
+        self.last_heartbeat = Some(now);
+    }
+
+    // A price received at `now` that was quoted at `time`
+    pub fn record_price(&mut self, now: u64, time: u64) {
+        self.latencies.push_back((now, now.saturating_sub(time)));
+    }
+
+    pub fn record_error(&mut self, now: u64) {
+        self.errors.push_back(now);
+    }
+
+    // Pick up the malformed lines the stream parsers have skipped since last time, from their
+    // running total
+    pub fn record_malformed(&mut self, now: u64, total: u64) {
+        for _ in self.malformed.min(total)..total {
+            self.errors.push_back(now);
+        }
+        self.malformed = total;
+    }
+
+    // Score the window ending now, returning the change if it crosses the threshold
+    pub fn update(&mut self, now: u64) -> Option<HealthChange> {
+        let start = now.saturating_sub(self.config.window * 1000);
+        while self.gaps.front().is_some_and(|(time, _)| *time < start) {
+            self.gaps.pop_front();
+        }
+        while self
+            .latencies
+            .front()
+            .is_some_and(|(time, _)| *time < start)
+        {
+            self.latencies.pop_front();
+        }
+        while self.errors.front().is_some_and(|time| *time < start) {
+            self.errors.pop_front();
+        }
+
+        // The wait for the next heartbeat counts as a gap once heartbeats have been seen
+        let waiting = self.last_heartbeat.map(|last| now.saturating_sub(last));
+        let heartbeats = match self.gaps.iter().map(|(_, gap)| *gap).chain(waiting).max() {
+            Some(gap) => falloff(
+                gap as f64,
+                self.config.heartbeat_interval as f64,
+                self.config.max_heartbeat_gap as f64,
+            ),
+            None => 1.0,
+        };
+        let latency = match self.latencies.len() {
+            0 => 1.0,
+            n => falloff(
+                self.latencies
+                    .iter()
+                    .map(|(_, delay)| *delay as f64)
+                    .sum::<f64>()
+                    / n as f64,
+                self.config.good_latency as f64,
+                self.config.max_latency as f64,
+            ),
+        };
+        let errors = falloff(
+            self.errors.len() as f64,
+            0.0,
+            self.config.max_errors.max(1) as f64,
+        );
+        self.score = heartbeats * latency * errors;
+
+        let threshold = self.config.threshold?;
+        // Back to healthy only once it's above the threshold, not just level with it
+        let healthy = match self.healthy {
+            true => self.score >= threshold,
+            false => self.score > threshold,
+        };
+        if healthy == self.healthy {
+            return None;
+        }
+        self.healthy = healthy;
+        Some(HealthChange {
+            score: self.score,
+            healthy,
+        })
+    }
+
+    pub fn score(&self) -> f64 {
+        self.score
+    }
+
+    // Whether the action applies, never without a threshold
+    pub fn is_unhealthy(&self) -> bool {
+        !self.healthy
+    }
+
+    pub fn has_threshold(&self) -> bool {
+        self.config.threshold.is_some()
+    }
+
+    pub fn action(&self) -> &HealthAction {
+        &self.config.action
+    }
+
+    // Multiplier of every position for the connection's health
+    pub fn scale(&self) -> f64 {
+        match (&self.config.action, self.healthy) {
+            (HealthAction::TightenLimits { scale }, false) => *scale,
+            _ => 1.0,
+        }
+    }
+
+    pub fn pauses_entries(&self) -> bool {
+        !self.healthy && self.config.action == HealthAction::PauseEntries
+    }
+}
+
+// 1 up to `good`, falling in a straight line to 0 at `bad`
+fn falloff(value: f64, good: f64, bad: f64) -> f64 {
+    if value <= good {
+        1.0
+    } else if value >= bad {
+        0.0
+    } else {
+        (bad - value) / (bad - good)
+    }
+}
@@ -0,0 +1,57 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+
+use crate::journal::JournalEntry;
+
+// The most recent decisions of each instrument, oldest first, so a running trader can be asked
+// why it is or isn't trading without reading through the journal. Older decisions are dropped
+// once an instrument has `capacity` of them.
+#[derive(Debug, Default)]
+pub struct DecisionHistory {
+    capacity: usize,
+    decisions: HashMap<String, VecDeque<JournalEntry>>,
+}
+
+// Shared between the engine recording decisions and the control socket answering queries
+pub type SharedDecisionHistory = Arc<Mutex<DecisionHistory>>;
+
+impl DecisionHistory {
+    pub fn new(capacity: usize) -> Self {
+        DecisionHistory {
+            capacity,
+            decisions: HashMap::new(),
+        }
+    }
+
+    pub fn shared(capacity: usize) -> SharedDecisionHistory {
+        Arc::new(Mutex::new(Self::new(capacity)))
+    }
+
+    pub fn record(&mut self, instrument: &str, decision: &JournalEntry) {
+        if self.capacity == 0 {
+            return;
+        }
+        let decisions = self.decisions.entry(instrument.to_string()).or_default();
+        if decisions.len() == self.capacity {
+            decisions.pop_front();
+        }
+        decisions.push_back(decision.clone());
+    }
+
+    // Decisions of one instrument, or of every instrument ordered by instrument
+    pub fn recent(&self, instrument: Option<&str>) -> Vec<&JournalEntry> {
+        let mut instruments: Vec<&String> = match instrument {
+            Some(instrument) => self
+                .decisions
+                .keys()
+                .filter(|name| name.as_str() == instrument)
+                .collect(),
+            None => self.decisions.keys().collect(),
+        };
+        instruments.sort();
+        instruments
+            .into_iter()
+            .flat_map(|instrument| &self.decisions[instrument])
+            .collect()
+    }
+}
@@ -0,0 +1,54 @@
+use std::collections::HashSet;
+
+use crate::journal::JournalEntry;
+
+// Which instruments signals are executed for. Instruments start out as configured by
+// "disabledInstruments" and are switched on and off over the control socket. Switches are
+// journaled, and replaying the journal on a restart brings them back. An instrument switched on
+// is traded to the strategies' standing forecast straight away.
+#[derive(Debug, Default, Clone)]
+pub struct InstrumentSwitches {
+    disabled: HashSet<String>,
+}
+
+impl InstrumentSwitches {
+    pub fn new(disabled: &[String]) -> Self {
+        InstrumentSwitches {
+            disabled: disabled.iter().cloned().collect(),
+        }
+    }
+
+    // Apply the switches recorded in the journal, in order
+    pub fn replay(&mut self, entries: &[JournalEntry]) {
+        for entry in entries {
+            match entry {
+                JournalEntry::InstrumentDisabled { instrument, .. } => {
+                    self.disabled.insert(instrument.clone());
+                }
+                JournalEntry::InstrumentEnabled { instrument, .. } => {
+                    self.disabled.remove(instrument);
+                }
+                _ => {}
+            }
+        }
+    }
+
+    pub fn enable(&mut self, instrument: &str) {
+        self.disabled.remove(instrument);
+    }
+
+    pub fn disable(&mut self, instrument: &str) {
+        self.disabled.insert(instrument.to_string());
+    }
+
+    pub fn is_enabled(&self, instrument: &str) -> bool {
+        !self.disabled.contains(instrument)
+    }
+
+    // Disabled instruments, sorted
+    pub fn disabled(&self) -> Vec<&String> {
+        let mut disabled: Vec<&String> = self.disabled.iter().collect();
+        disabled.sort();
+        disabled
+    }
+}
@@ -0,0 +1,147 @@
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use serde::{Deserialize, Serialize};
+
+use super::EngineHandle;
+
+// A way to stop the trader from outside when the control socket or the network to the host
+// can't be relied on, "killSwitch" in the trading config, e.g. {"file": "/run/trader/kill",
+// "heartbeat": "/run/trader/watchdog", "maxHeartbeatAge": 60, "flatten": true}. It trips once
+// `file` exists, or once `heartbeat`, which an external watchdog keeps touching, is missing or
+// hasn't been modified for `maxHeartbeatAge` seconds. From then on no orders are placed until
+// the trader is restarted, and with `flatten` every position is closed. A restart trips it again
+// while the file is still there or the watchdog still isn't running.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct KillSwitchConfig {
+    #[serde(default)]
+    pub file: Option<PathBuf>,
+
+    #[serde(default)]
+    pub heartbeat: Option<PathBuf>,
+
+    #[serde(default = "default_max_heartbeat_age")]
+    #[serde(rename = "maxHeartbeatAge")]
+    pub max_heartbeat_age: u64,
+
+    #[serde(default)]
+    pub flatten: bool,
+
+    // Milliseconds between looks at the files, which happen on a thread of their own so nothing
+    // the engine is waiting on delays them
+    #[serde(default = "default_check_interval")]
+    #[serde(rename = "checkInterval")]
+    pub check_interval: u64,
+}
+
+fn default_max_heartbeat_age() -> u64 {
+    60
+}
+
+fn default_check_interval() -> u64 {
+    1_000
+}
+
+impl KillSwitchConfig {
+    // Why the switch should trip now, if it should
+    fn reason(&self) -> Option<String> {
+        if let Some(file) = self.file.as_ref().filter(|file| file.exists()) {
+            return Some(format!("{} exists", file.display()));
+        }
+        let heartbeat = self.heartbeat.as_ref()?;
+        let modified = match std::fs::metadata(heartbeat).and_then(|meta| meta.modified()) {
+            Ok(modified) => modified,
+            Err(_) => return Some(format!("no watchdog heartbeat at {}", heartbeat.display())),
+        };
+        // Touched in the future as far as this host's clock is concerned counts as just now
+        let age = SystemTime::now()
+            .duration_since(modified)
+            .unwrap_or_default();
+        (age > Duration::from_secs(self.max_heartbeat_age)).then(|| {
+            format!(
+                "watchdog heartbeat {} not touched for {}s",
+                heartbeat.display(),
+                age.as_secs()
+            )
+        })
+    }
+}
+
+// Watches the kill switch files in real time on a thread of its own, whatever clock the engine
+// runs on and whatever it's busy with. Tripping halts the engine through its handle, so no order
+// goes out from then on, and the engine journals it and flattens the next time it looks.
+pub struct KillSwitch {
+    config: Option<KillSwitchConfig>,
+    handle: EngineHandle,
+    watching: Arc<AtomicBool>,
+    reported: bool,
+}
+
+impl KillSwitch {
+    pub fn new(config: Option<KillSwitchConfig>, handle: &EngineHandle) -> Self {
+        KillSwitch {
+            config,
+            handle: handle.clone(),
+            watching: Arc::new(AtomicBool::new(false)),
+            reported: false,
+        }
+    }
+
+    // Start looking at the files, once. The thread stops when the switch trips or is dropped.
+    pub fn watch(&self) {
+        let config = match &self.config {
+            Some(config) if !self.watching.swap(true, Ordering::SeqCst) => config.clone(),
+            _ => return,
+        };
+        let handle = self.handle.clone();
+        let watching = self.watching.clone();
+        std::thread::Builder::new()
+            .name("kill-switch".to_string())
+            .spawn(move || {
+                while watching.load(Ordering::SeqCst) && handle.halted().is_none() {
+                    if let Some(reason) = config.reason() {
+                        handle.halt(reason);
+                        break;
+                    }
+                    std::thread::sleep(Duration::from_millis(config.check_interval));
+                }
+            })
+            .expect("Failed to spawn the kill switch thread");
+    }
+
+    // Tripped and not yet acted on
+    pub fn is_due(&self) -> bool {
+        !self.reported && self.handle.halted().is_some()
+    }
+
+    // The reason the switch tripped if it has since the last call, for the engine to act on once
+    pub fn check(&mut self) -> Option<String> {
+        if self.reported {
+            return None;
+        }
+        let reason = self.handle.halted()?;
+        self.reported = true;
+        Some(reason)
+    }
+
+    pub fn is_configured(&self) -> bool {
+        self.config.is_some()
+    }
+
+    // Why it tripped, None until it has
+    pub fn tripped(&self) -> Option<String> {
+        self.handle.halted()
+    }
+
+    pub fn flattens(&self) -> bool {
+        self.config.as_ref().is_some_and(|config| config.flatten)
+    }
+}
+
+impl Drop for KillSwitch {
+    fn drop(&mut self) {
+        self.watching.store(false, Ordering::SeqCst);
+    }
+}
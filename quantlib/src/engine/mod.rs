@@ -0,0 +1,2191 @@
+pub mod clock;
+pub mod events;
+pub mod execution;
+pub mod health;
+pub mod history;
+pub mod instruments;
+pub mod kill_switch;
+#[cfg(feature = "trading")]
+pub mod reporting;
+pub mod risk;
+pub mod session;
+pub mod shadow;
+pub mod shutdown;
+pub mod signal_log;
+pub mod staleness;
+pub mod status;
+pub mod warm_up;
+pub mod weekend;
+
+pub use clock::*;
+pub use events::*;
+pub use execution::*;
+pub use health::*;
+pub use history::*;
+pub use instruments::*;
+pub use kill_switch::*;
+#[cfg(feature = "trading")]
+pub use reporting::*;
+pub use risk::*;
+pub use session::*;
+pub use shadow::*;
+pub use shutdown::*;
+pub use signal_log::*;
+pub use staleness::*;
+pub use status::*;
+pub use warm_up::*;
+pub use weekend::*;
+
+use std::collections::{BTreeSet, HashMap};
+use std::error::Error;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use log::Level;
+use tokio::sync::mpsc;
+
+#[cfg(feature = "trading")]
+use crate::accounts::ReportingSettings;
+use crate::alerts::Alerts;
+use crate::broker::OrderTags;
+use crate::conflation::DroppedTicks;
+use crate::control::{ControlCommand, ControlSocket};
+use crate::fx::{split_instrument, Converter};
+#[cfg(feature = "grpc")]
+use crate::grpc::GrpcServer;
+use crate::journal::{read_journal, DecisionOutcome, Journal, JournalEntry, RiskCheck};
+use crate::logging;
+use crate::models::{
+    AccountCost, NonTradeablePrices, PassiveOutcome, PassiveReport, PassiveStats, ResolvedSignal,
+    SignalBus, StrategyCheckpoint, WorkingTargets, WorkingUpdate,
+};
+use crate::notify::NotificationSink;
+use crate::oanda::malformed_lines;
+#[cfg(feature = "trading")]
+use crate::oanda::objects::OandaSettings;
+use crate::oanda::objects::{Price, StreamItem};
+use crate::oanda::prioritize;
+use crate::oanda::stream_errors::{self, StreamErrorClass};
+use crate::util::TradingConfig;
+
+// How long the engine waits for the stream before handling everything else due anyway
+const IDLE_TICK: Duration = Duration::from_millis(250);
+// Items read ahead of the engine, enough for a burst of prices while an order is placed
+const PRICE_BUFFER: usize = 1024;
+
+// Stream of prices (and heartbeats) the engine trades on. It's read on a thread of its own, so
+// waiting on it never holds up the rest of the engine.
+pub type PriceSource = Box<dyn Iterator<Item = Result<StreamItem, Box<dyn Error>>> + Send>;
+
+// Replay recorded prices as a price source, e.g. MergedReader::open(&layout)?
+pub fn replay<I>(prices: I) -> PriceSource
+where
+    I: IntoIterator<Item = Price>,
+    I::IntoIter: Send + 'static,
+{
+    Box::new(prices.into_iter().map(|price| Ok(StreamItem::Price(price))))
+}
+
+// Until the strategy workers have ticked a price, never without them
+async fn notified(completions: &Option<Arc<tokio::sync::Notify>>) {
+    match completions {
+        Some(completions) => completions.notified().await,
+        None => std::future::pending().await,
+    }
+}
+
+// Pauses, halts or stops a running engine from elsewhere, e.g. a signal handler
+#[derive(Clone, Default)]
+pub struct EngineHandle {
+    paused: Arc<AtomicBool>,
+    shutdown: Arc<AtomicBool>,
+    halted: Arc<Mutex<Option<String>>>,
+}
+
+impl EngineHandle {
+    // Strategies keep running on every price while paused, but their signals aren't executed
+    pub fn pause(&self) {
+        self.paused.store(true, Ordering::SeqCst);
+    }
+
+    pub fn resume(&self) {
+        self.paused.store(false, Ordering::SeqCst);
+    }
+
+    // The engine stops after the price it's handling
+    pub fn shutdown(&self) {
+        self.shutdown.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused.load(Ordering::SeqCst)
+    }
+
+    pub fn is_shutdown(&self) -> bool {
+        self.shutdown.load(Ordering::SeqCst)
+    }
+
+    // No more orders until the trader is restarted, the first reason given is the one kept. This
+    // is what the kill switch trips, whatever the engine is doing at the time.
+    pub fn halt(&self, reason: String) {
+        let mut halted = self.halted.lock().unwrap();
+        if halted.is_none() {
+            *halted = Some(reason);
+        }
+    }
+
+    // Why the engine was halted, None unless it has been
+    pub fn halted(&self) -> Option<String> {
+        self.halted.lock().unwrap().clone()
+    }
+}
+
+// Runs the configured strategies on a price source, sending their resolved signals through the
+// risk checks to an execution model and journaling everything it does. The same engine trades
+// live, on paper and over recorded prices.
+pub struct TradingEngine<'a> {
+    config: TradingConfig,
+    prices: PriceSource,
+    strategy: SignalBus,
+    risk: RiskManager,
+    kill_switch: KillSwitch,
+    staleness: Option<StalenessGuard>,
+
+    // Prices seen by each instrument's strategies since a cold start, see BootstrapConfig
+    warm_up: Option<WarmUp>,
+    weekend: Option<Weekend>,
+    alerts: Option<Alerts>,
+
+    // Sinks from the settings, see NotificationSettings
+    notifications: Vec<NotificationSink>,
+
+    execution: Execution<'a>,
+    journal: Journal,
+    control: Option<ControlSocket>,
+    #[cfg(feature = "grpc")]
+    grpc: Option<GrpcServer>,
+    history: SharedDecisionHistory,
+    status: SharedEngineStatus,
+    instruments: InstrumentSwitches,
+    handle: EngineHandle,
+    clock: Clock,
+
+    // On standby everything runs but execution, which is left to another trader until an
+    // "activate" command arrives on the control socket
+    standby: bool,
+    last_checkpoint: Option<u64>,
+    last_status: Option<u64>,
+
+    // Reports on the main account and the others in the settings at the end of each day
+    #[cfg(feature = "trading")]
+    account_reports: Option<AccountReports>,
+
+    // Latest price of each instrument, to place protective stops from on shutdown
+    last_prices: HashMap<String, Price>,
+
+    // What the exposure limit is valued in, asked of the account the first time it's needed
+    account_currency: Option<String>,
+
+    // Everything waiting to be acted on, see EventPriority
+    events: EventQueue,
+
+    // Strategies behind the last order in each instrument, which financing and other costs
+    // charged on its position are attributed to
+    holders: HashMap<String, Vec<String>>,
+
+    // Signals with a validity, or a smoothed target, still being worked towards their targets.
+    // They don't outlive a restart, as the market they were resolved in will have moved on.
+    working: WorkingTargets,
+
+    // Instruments whose signals were held back while the connection was unhealthy, traded to
+    // their standing signals once it recovers
+    held_back: BTreeSet<String>,
+
+    // Instruments a halted strategy had a forecast in, traded to their signals without it once
+    // the event that halted it has been handled
+    restating: BTreeSet<String>,
+
+    // Write-ahead log of the signals handled, and the signals a crashed run left in flight,
+    // taken up again on the next price of their instrument
+    signal_log: Option<SignalLog>,
+    recovering: HashMap<String, InFlightSignal>,
+
+    // How orders posted inside the spread ended, None without passive execution
+    passive: Option<PassiveStats>,
+
+    // Candidate strategies compared with these on paper, see ShadowConfig
+    shadow: Option<Shadow>,
+
+    // Prices the strategy workers dropped, or the stream relay did reading through it
+    dropped_ticks: DroppedTicks,
+
+    // Tagged on orders, see TradingConfig::version
+    config_version: String,
+
+    // Where the config is re-read from on "reload", and where the price source takes new
+    // instruments, see ShardedPriceStream::subscriptions
+    config_file: Option<PathBuf>,
+    subscriptions: Option<mpsc::UnboundedSender<Vec<String>>>,
+}
+
+impl<'a> TradingEngine<'a> {
+    pub fn new(
+        config: TradingConfig,
+        prices: PriceSource,
+        execution: Execution<'a>,
+    ) -> Result<Self, Box<dyn Error>> {
+        // Journaled every minute without strategy workers
+        let dropped_ticks = DroppedTicks::new(
+            config
+                .strategy_workers
+                .as_ref()
+                .map_or(60, |workers| workers.dropped_ticks_period)
+                * 1000,
+        );
+        let mut strategy = match &config.strategy_workers {
+            Some(workers) => SignalBus::pooled(&config, workers)?,
+            None => SignalBus::from_config(&config)?,
+        };
+        if let Some(model_state) = &config.model_state {
+            strategy = strategy.with_model_state(model_state, true)?;
+        }
+        let mut risk = RiskManager::from_config(&config);
+        let mut instruments = InstrumentSwitches::new(&config.disabled_instruments);
+        let mut weekend = config.weekend.clone().map(Weekend::new);
+        let mut holders = HashMap::new();
+        if config.journal.exists() {
+            // Strategies paused and instruments disabled before a restart stay that way
+            let entries = read_journal(&config.journal)?;
+            for name in risk.replay(&entries)? {
+                log::warn!(
+                    "Strategy {} is paused, enable it over the control socket",
+                    name
+                );
+                strategy.halt(&name);
+            }
+            instruments.replay(&entries);
+            if let Some(weekend) = &mut weekend {
+                weekend.replay(&entries)?;
+            }
+            for entry in entries {
+                if let JournalEntry::Order {
+                    instrument,
+                    strategies,
+                    ..
+                } = entry
+                {
+                    holders.insert(instrument, strategies);
+                }
+            }
+        }
+        for instrument in instruments.disabled() {
+            log::warn!(
+                "Instrument {} is disabled, enable it over the control socket",
+                instrument
+            );
+        }
+        let journal = Journal::open(&config.journal)?;
+        let (signal_log, recovering) = match &config.signal_log {
+            Some(signal_log) => {
+                let (signal_log, recovering) = SignalLog::open(signal_log, Clock::System.now())?;
+                (Some(signal_log), recovering)
+            }
+            None => (None, HashMap::new()),
+        };
+        let history = DecisionHistory::shared(config.decision_history);
+        let control = match &config.control_socket {
+            Some(path) => Some(ControlSocket::bind(path, history.clone())?),
+            None => None,
+        };
+        let status = SharedEngineStatus::default();
+        #[cfg(feature = "grpc")]
+        let grpc = match &config.grpc {
+            Some(grpc) => Some(GrpcServer::start(grpc, history.clone(), status.clone())?),
+            None => None,
+        };
+        #[cfg(not(feature = "grpc"))]
+        if config.grpc.is_some() {
+            log::warn!("Built without the gRPC service, ignoring the grpc config");
+        }
+
+        let config_version = config.version()?;
+        log::info!("Trading config version {}", config_version);
+        let handle = EngineHandle::default();
+        let kill_switch = KillSwitch::new(config.kill_switch.clone(), &handle);
+        let alerts = config.alerts.clone().map(Alerts::new);
+        let staleness = config.price_staleness.clone().map(StalenessGuard::new);
+        let passive = config
+            .passive_execution
+            .as_ref()
+            .map(|_| PassiveStats::default());
+        let smoothed = config.target_smoothing.working_validity();
+
+        Ok(TradingEngine {
+            config,
+            prices,
+            strategy,
+            risk,
+            kill_switch,
+            staleness,
+            warm_up: None,
+            weekend,
+            alerts,
+            notifications: Vec::new(),
+            execution,
+            journal,
+            control,
+            #[cfg(feature = "grpc")]
+            grpc,
+            history,
+            status,
+            instruments,
+            handle,
+            clock: Clock::System,
+            standby: false,
+            last_checkpoint: None,
+            last_status: None,
+            #[cfg(feature = "trading")]
+            account_reports: None,
+            last_prices: HashMap::new(),
+            account_currency: None,
+            events: EventQueue::default(),
+            holders,
+            working: WorkingTargets::default().with_fallback(smoothed),
+            signal_log,
+            held_back: BTreeSet::new(),
+            restating: BTreeSet::new(),
+            recovering,
+            passive,
+            shadow: None,
+            dropped_ticks,
+            config_version,
+            config_file: None,
+            subscriptions: None,
+        })
+    }
+
+    // Run the configured strategies over recorded prices as fast as they can be read, trading a
+    // paper account of the given position size on simulated time. Everything else (risk checks,
+    // journal, checkpoints) is the same as live, which makes this a full end to end run.
+    pub fn simulation<I>(
+        config: TradingConfig,
+        prices: I,
+        units: f64,
+    ) -> Result<Self, Box<dyn Error>>
+    where
+        I: IntoIterator<Item = Price>,
+        I::IntoIter: Send + 'static,
+    {
+        let paper = PaperExecution::from_config(&config, units);
+        let execution = Execution::Paper(Box::new(paper));
+        Ok(Self::new(config, replay(prices), execution)?.with_clock(Clock::simulated()))
+    }
+
+    // Alert to these as well as to the alerts config's sinks, and report fatal stream errors to
+    // them without one, see NotificationSettings
+    pub fn with_notifications(mut self, sinks: &[NotificationSink]) -> Self {
+        if let Some(alerts) = &mut self.alerts {
+            alerts.add_sinks(sinks);
+        }
+        self.notifications = sinks.to_vec();
+        self
+    }
+
+    // Run the configured candidate strategies alongside, see ShadowConfig
+    pub fn with_shadow(mut self, shadow: Shadow) -> Self {
+        self.shadow = Some(shadow);
+        self
+    }
+
+    // Report on every account in the settings' "reporting" config at the end of each UTC day,
+    // see AccountReports
+    #[cfg(feature = "trading")]
+    pub fn with_reporting(mut self, main: &OandaSettings, reporting: &ReportingSettings) -> Self {
+        self.account_reports = Some(AccountReports::new(main, reporting));
+        self
+    }
+
+    pub fn with_clock(mut self, clock: Clock) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    // Keep the strategies, risk checks and journal running on every price without placing or
+    // managing any orders, e.g. to have a new version ready to take over from the running one.
+    // The config should keep its state apart from the running trader's, see
+    // TradingConfig::separate_state.
+    pub fn with_standby(mut self) -> Self {
+        self.standby = true;
+        self
+    }
+
+    // Accept "reload" commands, which re-read the instruments from the config file and send them
+    // to the price source if it can take them
+    pub fn with_config_file<P: Into<PathBuf>>(
+        mut self,
+        path: P,
+        subscriptions: Option<mpsc::UnboundedSender<Vec<String>>>,
+    ) -> Self {
+        self.config_file = Some(path.into());
+        self.subscriptions = subscriptions;
+        self
+    }
+
+    pub fn is_standby(&self) -> bool {
+        self.standby
+    }
+
+    // Restore the strategies from the configured checkpoint, returning whether there was one
+    pub fn restore_checkpoint(&mut self) -> Result<bool, Box<dyn Error>> {
+        match self.config.checkpoint.clone() {
+            Some(path) => self.restore_checkpoint_from(&path),
+            None => Ok(false),
+        }
+    }
+
+    // Restore the strategies from another trader's checkpoint, e.g. the live one a standby takes
+    // over from, returning whether there was one
+    pub fn restore_checkpoint_from(&mut self, path: &Path) -> Result<bool, Box<dyn Error>> {
+        if !path.exists() {
+            return Ok(false);
+        }
+        self.strategy.restore(&StrategyCheckpoint::load(path)?)?;
+        Ok(true)
+    }
+
+    // Cancel the protective stops the last shutdown left resting, before trading resumes, so they
+    // don't stack up over restarts. The record is kept until every stop is dealt with, and an
+    // error stops the start, as trading around a stale stop would have it close positions.
+    pub async fn cancel_protective_stops(&mut self) -> Result<(), Box<dyn Error>> {
+        if self.standby {
+            return Ok(());
+        }
+        let path = protective_stops_path(&self.config.journal);
+        let stops = match load_protective_stops(&path)? {
+            Some(stops) => stops,
+            None => return Ok(()),
+        };
+        let mut gone = false;
+        for stop in &stops {
+            if self.execution.cancel_stop(stop).await? {
+                log::info!(
+                    "[{}] Cancelled the protective stop {} left on shutdown",
+                    stop.instrument,
+                    stop.order_id
+                );
+            } else {
+                log::info!(
+                    "[{}] Protective stop {} left on shutdown is no longer resting",
+                    stop.instrument,
+                    stop.order_id
+                );
+                gone = true;
+            }
+        }
+        // A stop that went may have closed a position while the engine was down
+        if gone {
+            for activity in self.execution.settle().await? {
+                self.journal.record(&JournalEntry::external(&activity))?;
+            }
+        }
+        std::fs::remove_file(&path)?;
+        Ok(())
+    }
+
+    // Feed prices to the strategies without acting on them, e.g. a pricing snapshot at startup
+    pub fn warm_up(&mut self, prices: &[Price]) -> Result<(), Box<dyn Error>> {
+        self.shadow_warm_up(prices);
+        self.strategy.warm_up(prices)
+    }
+
+    // Warm the strategies up on recent candles after a cold start, see BootstrapConfig. Without
+    // a bootstrap config nothing is held back.
+    pub fn bootstrap(&mut self, prices: &[Price]) -> Result<(), Box<dyn Error>> {
+        let candles = match &self.config.bootstrap {
+            Some(config) => config.candles,
+            None => return Ok(()),
+        };
+        let mut warm_up = WarmUp::new(candles, &self.config.instruments);
+        for price in prices {
+            warm_up.record(&price.instrument);
+        }
+        self.strategy.warm_up(prices)?;
+        self.shadow_warm_up(prices);
+        let status = warm_up.status();
+        if status.ready {
+            log::info!("Strategies warmed up on {} prices each", candles);
+        } else {
+            log::warn!(
+                "Not ordering {:?} until their strategies have seen {} prices",
+                status.warming(),
+                candles
+            );
+        }
+        self.warm_up = Some(warm_up);
+        Ok(())
+    }
+
+    pub fn handle(&self) -> EngineHandle {
+        self.handle.clone()
+    }
+
+    pub fn pause(&self) {
+        self.handle.pause();
+    }
+
+    pub fn resume(&self) {
+        self.handle.resume();
+    }
+
+    pub fn shutdown(&self) {
+        self.handle.shutdown();
+    }
+
+    pub fn execution(&self) -> &Execution<'a> {
+        &self.execution
+    }
+
+    // Trade on every price until the source ends or the engine is shut down, then shut down as
+    // configured. The stream is read on a thread of its own, and each item from it is queued
+    // along with everything else due by then (control commands, the account's transactions,
+    // timers and the strategy workers' signals) and they're acted on in priority order, see
+    // EventPriority. Without prices the engine still wakes every IDLE_TICK for the rest, so a
+    // quiet or stalled stream holds nothing up, and as soon as the strategy workers have finished
+    // a price for its signals. A shutdown takes effect once the event being
+    // handled (and any order it caused) is finished. A fatal stream error (see StreamErrorClass)
+    // is alerted and ends the run like the source ending.
+    pub async fn run(&mut self) -> Result<ShutdownReport, Box<dyn Error>> {
+        self.kill_switch.watch();
+        let mut prices = self.read_prices();
+        let completions = self.strategy.completions();
+        let mut fatal = None;
+        while !self.handle.is_shutdown() {
+            // On simulated time nothing happens between prices, so there's nothing to wake for
+            let item = match self.clock {
+                Clock::Simulated { .. } => Some(prices.recv().await),
+                Clock::System => tokio::select! {
+                    item = prices.recv() => Some(item),
+                    _ = notified(&completions) => None,
+                    _ = tokio::time::sleep(IDLE_TICK) => None,
+                },
+            };
+            let ended = match item {
+                Some(Some(Ok(StreamItem::Price(price)))) => {
+                    // Timers are due as of the price, and acted on before it
+                    self.clock.advance(price.time);
+                    self.events.push(EngineEvent::Price(price));
+                    false
+                }
+                Some(Some(Ok(StreamItem::Backfill(price)))) => {
+                    self.events.push(EngineEvent::Backfill(price));
+                    false
+                }
+                Some(Some(Ok(StreamItem::Dropped(dropped)))) => {
+                    for (instrument, count) in &dropped.dropped {
+                        self.dropped_ticks.record(instrument, *count);
+                    }
+                    false
+                }
+                Some(Some(Ok(StreamItem::Heartbeat(_)))) => {
+                    self.events.push(EngineEvent::Heartbeat);
+                    false
+                }
+                Some(Some(Err(e))) if StreamErrorClass::of(e.as_ref()).is_fatal() => {
+                    self.report_fatal(e.as_ref());
+                    fatal = Some(e.to_string());
+                    true
+                }
+                Some(Some(Err(e))) => {
+                    self.events.push(EngineEvent::StreamError(e.to_string()));
+                    false
+                }
+                Some(None) => true,
+                None => false,
+            };
+            // Once the source ends, the signals of the last prices still with the strategy
+            // workers are waited for. On simulated time they always are, so each price's signals
+            // are acted on before the next price whatever the workers' timing.
+            let wait = ended || matches!(self.clock, Clock::Simulated { .. });
+            self.queue_due(wait).await?;
+            while let Some(event) = self.events.pop() {
+                self.dispatch(event).await?;
+                if self.handle.is_shutdown() {
+                    break;
+                }
+            }
+            if ended {
+                break;
+            }
+        }
+
+        let reason = match fatal {
+            Some(e) => format!("stream error: {}", e),
+            None if self.handle.is_shutdown() => "shutdown requested".to_string(),
+            None => "price source ended".to_string(),
+        };
+        self.finish(&reason).await
+    }
+
+    // Read the price source on a thread of its own, as its iterator blocks until the next item.
+    // Errors cross over as their message, apart from the fatal ones which keep what reporting
+    // them needs. The thread ends with the source, or with the next item once the receiver has
+    // gone.
+    fn read_prices(&mut self) -> mpsc::Receiver<Result<StreamItem, Box<dyn Error + Send + Sync>>> {
+        let source = std::mem::replace(&mut self.prices, Box::new(std::iter::empty()));
+        let (sender, receiver) = mpsc::channel(PRICE_BUFFER);
+        // Streams reconnecting with tokio's timers need its runtime on this thread too
+        let runtime = tokio::runtime::Handle::try_current().ok();
+        std::thread::Builder::new()
+            .name("price-source".to_string())
+            .spawn(move || {
+                let _runtime = runtime.as_ref().map(|runtime| runtime.enter());
+                for item in source {
+                    let item = item.map_err(|e| {
+                        stream_errors::fatal_error(e.as_ref())
+                            .unwrap_or_else(|| e.to_string().into())
+                    });
+                    if sender.blocking_send(item).is_err() {
+                        break;
+                    }
+                }
+            })
+            .expect("Failed to spawn the price source thread");
+        receiver
+    }
+
+    // Alert a stream error no reconnect will fix, keeping a protocol error's payload next to the
+    // journal
+    fn report_fatal(&self, error: &(dyn Error + 'static)) {
+        let sinks = match &self.alerts {
+            Some(alerts) => alerts.config().notify.clone(),
+            None => self.notifications.clone(),
+        };
+        let directory = self.config.journal.parent().unwrap_or(Path::new(""));
+        stream_errors::report_fatal(error, "trader", &sinks, directory);
+    }
+
+    // Settle positions, checkpoint the strategies and deal with open positions
+    async fn finish(&mut self, reason: &str) -> Result<ShutdownReport, Box<dyn Error>> {
+        log::info!("Shutting down: {}", reason);
+        let shutdown = self.config.shutdown.clone();
+        let mut report = ShutdownReport {
+            time: format_time(self.clock.now()),
+            reason: reason.to_string(),
+            positions: shutdown.positions,
+            open_positions: Vec::new(),
+            fills: Vec::new(),
+            protective_stops: Vec::new(),
+            checkpoint: self.config.checkpoint.clone(),
+            errors: Vec::new(),
+        };
+
+        // Fills and costs already taken from the account would be lost with the queue, so they
+        // are acted on. Prices, signals, timers and commands stop with the run.
+        while let Some(event) = self.events.pop() {
+            if event.priority() != EventPriority::Fills {
+                continue;
+            }
+            if let Err(e) = self.dispatch(event).await {
+                report
+                    .errors
+                    .push(format!("Failed to record account activity: {}", e));
+            }
+        }
+
+        let settled = if self.standby {
+            Ok(Vec::new())
+        } else {
+            self.execution.settle().await
+        };
+        match settled {
+            Ok(external) => {
+                for activity in external {
+                    self.journal.record(&JournalEntry::external(&activity))?;
+                }
+                self.record_costs()?;
+            }
+            Err(e) => report
+                .errors
+                .push(format!("Failed to settle positions: {}", e)),
+        }
+
+        if let Some(path) = &self.config.checkpoint {
+            if let Err(e) = self
+                .strategy
+                .sync()
+                .and_then(|_| self.strategy.checkpoint().save(path))
+            {
+                report
+                    .errors
+                    .push(format!("Failed to save checkpoint: {}", e));
+            }
+        }
+
+        // The positions belong to the active trader
+        let positions = if self.standby {
+            Vec::new()
+        } else {
+            self.execution.open_positions()
+        };
+        for (instrument, units) in positions {
+            report.open_positions.push(OpenPosition {
+                instrument: instrument.clone(),
+                units,
+            });
+            match shutdown.positions {
+                ShutdownPositions::Keep => {}
+                ShutdownPositions::Flatten => match self.flatten(&instrument).await {
+                    Ok(fills) => report.fills.extend(fills),
+                    Err(e) => report
+                        .errors
+                        .push(format!("[{}] Failed to flatten: {}", instrument, e)),
+                },
+                ShutdownPositions::Protect => {
+                    let price = match self.last_prices.get(&instrument) {
+                        Some(price) => price.clone(),
+                        None => {
+                            report
+                                .errors
+                                .push(format!("[{}] No price to place a stop from", instrument));
+                            continue;
+                        }
+                    };
+                    match self
+                        .execution
+                        .protect(&instrument, units, &price, shutdown.stop_pips)
+                        .await
+                    {
+                        Ok(stop) => report.protective_stops.push(stop),
+                        Err(e) => report
+                            .errors
+                            .push(format!("[{}] Failed to place stop: {}", instrument, e)),
+                    }
+                }
+            }
+        }
+
+        if !report.protective_stops.is_empty() {
+            let path = protective_stops_path(&self.config.journal);
+            if let Err(e) = save_protective_stops(&path, &report.protective_stops) {
+                report.errors.push(format!(
+                    "Failed to record the protective stops in {}: {}",
+                    path.display(),
+                    e
+                ));
+            }
+        }
+
+        if let Err(e) = self.record_dropped_ticks(self.clock.now()) {
+            report
+                .errors
+                .push(format!("Failed to journal dropped ticks: {}", e));
+        }
+
+        if let Some(shadow) = &mut self.shadow {
+            match shadow.report(self.clock.now()) {
+                Ok(shadow) => shadow.log(),
+                Err(e) => report
+                    .errors
+                    .push(format!("Failed to write the shadow report: {}", e)),
+            }
+        }
+
+        for error in &report.errors {
+            log::error!("{}", error);
+        }
+        if let Some(path) = &shutdown.report {
+            report.save(path)?;
+        }
+        Ok(report)
+    }
+
+    // Journal the prices dropped in the period, if any
+    fn record_dropped_ticks(&mut self, now: u64) -> Result<(), Box<dyn Error>> {
+        let period = match self.dropped_ticks.roll(now) {
+            Some(period) => period,
+            None => return Ok(()),
+        };
+        log::warn!(
+            "Strategies missed {} prices since {}: {}",
+            period.total(),
+            format_time(period.start),
+            period.summary()
+        );
+        self.journal.record(&JournalEntry::DroppedTicks {
+            time: format_time(now),
+            period,
+        })
+    }
+
+    // Journal the fills of a trailing stop's close, which count against the strategies that held
+    // the position like the fills of their own signals
+    fn record_stops(
+        &mut self,
+        price: &Price,
+        fills: &[ExecutionFill],
+    ) -> Result<(), Box<dyn Error>> {
+        if fills.is_empty() {
+            return Ok(());
+        }
+        let strategies = self
+            .holders
+            .insert(price.instrument.clone(), Vec::new())
+            .unwrap_or_default();
+        log::info!(
+            "[{}] Trailing stop hit, closed for {:?}",
+            price.instrument,
+            strategies
+        );
+        for fill in fills {
+            self.journal.record(&JournalEntry::order(
+                fill,
+                0.0,
+                self.strategy.policy(),
+                &strategies,
+                None,
+            ))?;
+        }
+        let now = self.clock.now();
+        let breaches = self
+            .risk
+            .record_fills(now, &price.instrument, &strategies, fills);
+        for breach in breaches {
+            log::error!("[{}] Halting {:?}", price.instrument, breach.strategies);
+            for name in &breach.strategies {
+                self.halt_strategy(name);
+            }
+            self.journal.record(&breach.entry)?;
+        }
+        Ok(())
+    }
+
+    // Close a position, journaling the fills
+    async fn flatten(&mut self, instrument: &str) -> Result<Vec<ExecutionFill>, Box<dyn Error>> {
+        let fills = self.execution.flatten(instrument).await?;
+        if !fills.is_empty() {
+            self.holders.insert(instrument.to_string(), Vec::new());
+        }
+        for fill in &fills {
+            self.journal.record(&JournalEntry::order(
+                fill,
+                0.0,
+                self.strategy.policy(),
+                &[],
+                None,
+            ))?;
+        }
+        Ok(fills)
+    }
+
+    // Stop placing orders once the kill switch trips, closing every position if configured. A
+    // position that can't be closed is logged and the rest are still tried.
+    async fn check_kill_switch(&mut self) -> Result<(), Box<dyn Error>> {
+        let reason = match self.kill_switch.check() {
+            Some(reason) => reason,
+            None => return Ok(()),
+        };
+        let flatten = self.kill_switch.flattens() && !self.standby;
+        log::error!(
+            "Kill switch tripped ({}), no more orders{}",
+            reason,
+            if flatten { ", flattening" } else { "" }
+        );
+        self.journal.record(&JournalEntry::KillSwitch {
+            time: format_time(self.clock.now()),
+            reason,
+            flatten,
+        })?;
+
+        if flatten {
+            for (instrument, _) in self.execution.open_positions() {
+                if let Err(e) = self.flatten(&instrument).await {
+                    log::error!("[{}] Failed to flatten: {}", instrument, e);
+                }
+            }
+        }
+        self.publish_status();
+        Ok(())
+    }
+
+    // Look for stale prices and positions breaching the alert rules if it's time to. Positions
+    // are looked at whether or not this trader is the active one, they're the account's.
+    async fn check_alerts(&mut self) {
+        let now = self.clock.now();
+        let alerts = match &mut self.alerts {
+            Some(alerts) if alerts.check_due(now) => alerts,
+            _ => return,
+        };
+        alerts.check_stale(now);
+        if !alerts.watches_positions() {
+            return;
+        }
+        match self.execution.position_pl().await {
+            Ok(positions) => {
+                if let Some(alerts) = &mut self.alerts {
+                    alerts.check_positions(now, &positions);
+                }
+            }
+            Err(e) => log::warn!("Failed to get positions for alerts: {}", e),
+        }
+    }
+
+    // Close the positions the weekend policy covers once it's time to before the weekend close
+    async fn check_weekend(&mut self) -> Result<(), Box<dyn Error>> {
+        let now = self.clock.now();
+        // Left to whichever trader is active, and no orders at all once the kill switch trips
+        if self.standby || self.kill_switch.tripped().is_some() {
+            return Ok(());
+        }
+        let instruments: Vec<String> = match &self.weekend {
+            Some(weekend) if weekend.flatten_due(now) => self
+                .execution
+                .open_positions()
+                .into_iter()
+                .map(|(instrument, _)| instrument)
+                .filter(|instrument| weekend.applies(instrument))
+                .collect(),
+            _ => return Ok(()),
+        };
+
+        log::info!("Closing {} positions for the weekend", instruments.len());
+        let mut flattened = Vec::new();
+        let mut failed = Vec::new();
+        for instrument in instruments {
+            match self.flatten(&instrument).await {
+                Ok(_) => flattened.push(instrument),
+                Err(e) => {
+                    log::error!("[{}] Failed to flatten, will retry: {}", instrument, e);
+                    failed.push(instrument);
+                }
+            }
+        }
+        if let Some(weekend) = &mut self.weekend {
+            weekend.flattened(now, &flattened, &failed);
+        }
+        self.journal.record(&JournalEntry::WeekendFlatten {
+            time: format_time(now),
+            instruments: flattened,
+            failed,
+        })?;
+        self.publish_status();
+        Ok(())
+    }
+
+    // The strategies' standing signal for an instrument whose position was closed or whose
+    // signals were held back for the weekend, once it's time to trade it again
+    fn weekend_reentry(&mut self, price: &Price) -> Option<ResolvedSignal> {
+        if !self
+            .weekend
+            .as_mut()?
+            .reentry_due(price.time, &price.instrument)
+        {
+            return None;
+        }
+        let resolved = self.strategy.standing(&price.instrument)?;
+        log::info!(
+            "[{}] Reopening at a forecast of {} after the weekend",
+            price.instrument,
+            resolved.signal.forecast
+        );
+        Some(resolved)
+    }
+
+    // Trade the candidate strategies on the price, and compare them with these if it's time to
+    async fn shadow_price(&mut self, price: &Price) {
+        let shadow = match &mut self.shadow {
+            Some(shadow) => shadow,
+            None => return,
+        };
+        let now = self.clock.now();
+        let result = match shadow.price(price).await {
+            Ok(()) if shadow.report_due(now) => shadow.report(now).map(|report| report.log()),
+            result => result,
+        };
+        if let Err(e) = result {
+            self.stop_shadow(e);
+        }
+    }
+
+    fn shadow_warm_up(&mut self, prices: &[Price]) {
+        if let Some(Err(e)) = self.shadow.as_mut().map(|shadow| shadow.warm_up(prices)) {
+            self.stop_shadow(e);
+        }
+    }
+
+    // The candidate strategies never get in the way of these: once anything of theirs fails,
+    // they're stopped for the rest of the run
+    fn stop_shadow(&mut self, error: Box<dyn Error>) {
+        log::error!("Shadow strategies failed, stopping them: {}", error);
+        self.shadow = None;
+    }
+
+    // Prices missed during a reconnect catch the strategies up, but are too old to trade on
+    fn handle_backfill(&mut self, price: &Price) -> Result<(), Box<dyn Error>> {
+        logging::log_tick(Level::Debug, "[BACKFILL]", price);
+        self.shadow_warm_up(std::slice::from_ref(price));
+        self.strategy.warm_up(std::slice::from_ref(price))
+    }
+
+    // Queue everything due as of now besides the stream's items: control commands, what the
+    // account's transaction stream reports, timers and the signals the strategy workers have
+    // resolved, waiting for every price sent to the workers if `wait`
+    async fn queue_due(&mut self, wait: bool) -> Result<(), Box<dyn Error>> {
+        for command in self.commands() {
+            self.events.push(EngineEvent::Control(command));
+        }
+        if !self.standby {
+            for activity in self.execution.poll_account().await? {
+                self.events.push(EngineEvent::External(activity));
+            }
+        }
+        for cost in self.execution.take_costs() {
+            self.events.push(EngineEvent::Cost(cost));
+        }
+        for report in self.execution.take_passive_reports() {
+            self.events.push(EngineEvent::Passive(report));
+        }
+        for timer in self.due_timers() {
+            self.events.push(EngineEvent::Timer(timer));
+        }
+        #[cfg(feature = "trading")]
+        if let Some(reports) = &mut self.account_reports {
+            for report in reports.take() {
+                self.events.push(EngineEvent::AccountReport(report));
+            }
+        }
+        for (price, resolved) in self.strategy.completed(wait)? {
+            self.events.push(EngineEvent::Signal(price, resolved));
+        }
+        for (instrument, count) in self.strategy.take_dropped() {
+            self.dropped_ticks.record(&instrument, count);
+        }
+        Ok(())
+    }
+
+    // The timers due on the clock, in the order they're acted on. The kill switch is due as soon
+    // as its thread has tripped it, whatever the clock.
+    fn due_timers(&mut self) -> Vec<Timer> {
+        let now = self.clock.now();
+        let mut due = Vec::new();
+        if self.kill_switch.is_due() {
+            due.push(Timer::KillSwitch);
+        }
+        if self
+            .alerts
+            .as_ref()
+            .is_some_and(|alerts| alerts.check_due(now))
+        {
+            due.push(Timer::Alerts);
+        }
+        if self
+            .weekend
+            .as_ref()
+            .is_some_and(|weekend| weekend.flatten_due(now))
+        {
+            due.push(Timer::Weekend);
+        }
+        if !self.standby && self.risk.equity_due(now) {
+            due.push(Timer::Drawdown);
+        }
+        if self.risk.session().is_some() {
+            due.push(Timer::Session);
+        }
+        #[cfg(feature = "trading")]
+        if self
+            .account_reports
+            .as_ref()
+            .is_some_and(|reports| reports.due(now))
+        {
+            due.push(Timer::AccountReport);
+        }
+        let checkpoint_interval = self.config.checkpoint_interval * 1000;
+        if self.config.checkpoint.is_some()
+            && self
+                .last_checkpoint
+                .is_none_or(|last| now.saturating_sub(last) >= checkpoint_interval)
+        {
+            due.push(Timer::Checkpoint);
+        }
+        if self.last_status.is_none_or(|last| now >= last + 1000) {
+            due.push(Timer::Status);
+        }
+        if self.dropped_ticks.due(now) {
+            due.push(Timer::DroppedTicks);
+        }
+        due
+    }
+
+    // Act on an event, then on the signals of instruments whose strategies it halted
+    async fn dispatch(&mut self, event: EngineEvent) -> Result<(), Box<dyn Error>> {
+        self.handle_event(event).await?;
+        while let Some(instrument) = self.restating.pop_first() {
+            self.execute_standing(&instrument).await?;
+        }
+        Ok(())
+    }
+
+    async fn handle_event(&mut self, event: EngineEvent) -> Result<(), Box<dyn Error>> {
+        match event {
+            EngineEvent::Control(command) => self.handle_command(command).await,
+            EngineEvent::External(activity) => {
+                log::warn!(
+                    "[{}][EXTERNAL] {} units ({})",
+                    activity.instrument,
+                    activity.units,
+                    activity.reason.as_deref().unwrap_or("unknown reason")
+                );
+                self.journal.record(&JournalEntry::external(&activity))
+            }
+            EngineEvent::Cost(cost) => self.record_cost(&cost),
+            EngineEvent::Passive(report) => self.record_passive(report),
+            EngineEvent::Timer(timer) => self.handle_timer(timer).await,
+            #[cfg(feature = "trading")]
+            EngineEvent::AccountReport(report) => {
+                self.journal.record(&JournalEntry::AccountReport {
+                    time: format_time(self.clock.now()),
+                    report,
+                })
+            }
+            EngineEvent::Signal(price, resolved) => {
+                self.handle_signal(&price, Some(resolved)).await
+            }
+            EngineEvent::Price(price) => self.handle_price(&price).await,
+            EngineEvent::Backfill(price) => self.handle_backfill(&price),
+            EngineEvent::Heartbeat => {
+                let now = self.clock.now();
+                self.risk.health().record_heartbeat(now);
+                self.update_health().await
+            }
+            EngineEvent::StreamError(e) => {
+                log::warn!("Price stream error: {}", e);
+                let now = self.clock.now();
+                self.risk.health().record_error(now);
+                self.update_health().await
+            }
+        }
+    }
+
+    async fn handle_timer(&mut self, timer: Timer) -> Result<(), Box<dyn Error>> {
+        let now = self.clock.now();
+        match timer {
+            Timer::KillSwitch => self.check_kill_switch().await?,
+            Timer::Alerts => self.check_alerts().await,
+            Timer::Weekend => self.check_weekend().await?,
+            Timer::Drawdown => self.check_drawdown(now).await?,
+            Timer::Session => {
+                if let Some(summary) = self.risk.roll_session(now) {
+                    log::info!(
+                        "Day {} over: {} trades, realized P&L {:.2}, max exposure {:.0}",
+                        summary.date,
+                        summary.trades,
+                        summary.realized_pl,
+                        summary.max_exposure
+                    );
+                    self.journal.record(&JournalEntry::DailySummary {
+                        time: format_time(now),
+                        summary,
+                    })?;
+                }
+            }
+            #[cfg(feature = "trading")]
+            Timer::AccountReport => {
+                if let Some(reports) = &mut self.account_reports {
+                    reports.roll(now);
+                }
+            }
+            Timer::Checkpoint => {
+                let last_checkpoint = *self.last_checkpoint.get_or_insert(now);
+                if now.saturating_sub(last_checkpoint) >= self.config.checkpoint_interval * 1000 {
+                    if let Some(path) = &self.config.checkpoint {
+                        self.strategy.sync()?;
+                        self.strategy.checkpoint().save(path)?;
+                    }
+                    self.last_checkpoint = Some(now);
+                }
+            }
+            Timer::Status => self.publish_status(),
+            Timer::DroppedTicks => self.record_dropped_ticks(now)?,
+        }
+        Ok(())
+    }
+
+    async fn handle_price(&mut self, price: &Price) -> Result<(), Box<dyn Error>> {
+        logging::log_tick(Level::Debug, "[PRICE]", price);
+        // A replayed price's delay is how far behind the replay is, not the connection
+        if let Clock::System = self.clock {
+            self.risk
+                .health()
+                .record_price(self.clock.now(), price.time);
+        }
+        self.update_health().await?;
+        if let Some(alerts) = &mut self.alerts {
+            alerts.price(price);
+        }
+        self.last_prices
+            .insert(price.instrument.clone(), price.clone());
+        if let Some(warm_up) = &mut self.warm_up {
+            if warm_up.record(&price.instrument) {
+                log::info!("[{}] Strategies warmed up", price.instrument);
+            }
+        }
+        if !self.standby {
+            let stops = self.execution.handle_price(price).await?;
+            self.record_stops(price, &stops)?;
+            // Open positions follow a new volatility scale rather than waiting for new signals
+            if self.execution.take_rescaled() {
+                self.execute_standings().await?;
+            }
+        }
+        self.shadow_price(price).await;
+        if let Some(signal) = self
+            .recovering
+            .remove(&price.instrument)
+            .filter(|_| price.is_tradeable())
+        {
+            self.recover(price, signal).await?;
+        }
+
+        let resolved = match self.config.non_tradeable_prices {
+            _ if price.is_tradeable() => self.strategy.tick(price)?,
+            NonTradeablePrices::Trade => self.strategy.tick(price)?,
+            NonTradeablePrices::Observe => {
+                self.strategy.warm_up(std::slice::from_ref(price))?;
+                None
+            }
+            NonTradeablePrices::Skip => {
+                log::debug!(
+                    "[{}] Skipping {} price",
+                    price.instrument,
+                    price.status.as_str()
+                );
+                None
+            }
+        };
+        self.handle_signal(price, resolved).await
+    }
+
+    // Execute the signal resolved on a price, or the signal being worked towards if there isn't
+    // one, through the risk checks
+    async fn handle_signal(
+        &mut self,
+        price: &Price,
+        resolved: Option<ResolvedSignal>,
+    ) -> Result<(), Box<dyn Error>> {
+        let resolved = match resolved.or_else(|| self.weekend_reentry(price)) {
+            Some(resolved) => {
+                self.working.resolved(&resolved, price);
+                resolved
+            }
+            None if price.is_tradeable() => {
+                match self.working.update(price, self.strategy.policy()) {
+                    Some(WorkingUpdate::Retry(resolved)) => resolved,
+                    Some(WorkingUpdate::Expired(target, reason)) => {
+                        log::info!(
+                            "[{}] Signal of {} expired before reaching its target: {}",
+                            target.instrument,
+                            target.forecast,
+                            reason
+                        );
+                        self.journal.record(&JournalEntry::signal_expired(
+                            self.clock.now(),
+                            &target,
+                            &reason,
+                        ))?;
+                        let id = format!("{}-{}", target.instrument, target.since);
+                        let expired = SignalStatus::Expired;
+                        let record =
+                            SignalRecord::status(self.clock.now(), &id, expired, Some(reason));
+                        return self.log_signal(record);
+                    }
+                    None => return Ok(()),
+                }
+            }
+            None => return Ok(()),
+        };
+        if let Some(shadow) = &mut self.shadow {
+            if let Err(e) = shadow.production(&resolved).await {
+                self.stop_shadow(e);
+            }
+        }
+        let id = format!("{}-{}", price.instrument, price.time);
+        self.log_signal(SignalRecord::pending(
+            self.clock.now(),
+            &id,
+            &resolved,
+            price.time,
+        ))?;
+        self.act_on(price, resolved, &id).await
+    }
+
+    // Take up a signal the last run left in flight, unless it's too old to still stand
+    async fn recover(
+        &mut self,
+        price: &Price,
+        signal: InFlightSignal,
+    ) -> Result<(), Box<dyn Error>> {
+        let max_age = self.signal_log.as_ref().map_or(0, SignalLog::max_age);
+        let age = price.time.saturating_sub(signal.signal.price_time);
+        if age > max_age {
+            log::warn!(
+                "[{}] Signal {} left {:?} is {}s old, expiring it",
+                price.instrument,
+                signal.id,
+                signal.status,
+                age / 1000
+            );
+            let detail = format!("{}s old on recovery", age / 1000);
+            return self.log_signal(SignalRecord::status(
+                self.clock.now(),
+                &signal.id,
+                SignalStatus::Expired,
+                Some(detail),
+            ));
+        }
+        log::warn!(
+            "[{}] Recovering signal {} left {:?}",
+            price.instrument,
+            signal.id,
+            signal.status
+        );
+        self.act_on(price, signal.signal.resolved(), &signal.id)
+            .await
+    }
+
+    // Execute a signal through the risk checks, logging what became of it under its id
+    async fn act_on(
+        &mut self,
+        price: &Price,
+        resolved: ResolvedSignal,
+        id: &str,
+    ) -> Result<(), Box<dyn Error>> {
+        let forecast = resolved.signal.forecast;
+        log::info!(
+            "[{}][SIGNAL] Forecast: {} ({:?} of {:?})",
+            resolved.signal.instrument,
+            forecast,
+            resolved.policy,
+            resolved.strategies
+        );
+        if self.standby {
+            log::info!(
+                "[{}] On standby, not executing signal",
+                resolved.signal.instrument
+            );
+            let checks = vec![RiskCheck {
+                check: "standby".to_string(),
+                passed: false,
+                detail: None,
+            }];
+            let outcome = DecisionOutcome::Suppressed {
+                reason: "on standby".to_string(),
+            };
+            let decision = self.decision(price, &resolved, checks, outcome);
+            return self.record_decision(&price.instrument, id, &decision);
+        }
+        if let Some(reason) = self.kill_switch.tripped() {
+            log::info!(
+                "[{}] Kill switch tripped, not executing signal",
+                resolved.signal.instrument
+            );
+            let checks = vec![RiskCheck {
+                check: "killSwitch".to_string(),
+                passed: false,
+                detail: Some(reason),
+            }];
+            let outcome = DecisionOutcome::Suppressed {
+                reason: "kill switch tripped".to_string(),
+            };
+            let decision = self.decision(price, &resolved, checks, outcome);
+            return self.record_decision(&price.instrument, id, &decision);
+        }
+        if self.handle.is_paused() {
+            log::info!(
+                "[{}] Paused, not executing signal",
+                resolved.signal.instrument
+            );
+            let checks = vec![RiskCheck {
+                check: "paused".to_string(),
+                passed: false,
+                detail: None,
+            }];
+            let outcome = DecisionOutcome::Suppressed {
+                reason: "engine paused".to_string(),
+            };
+            let decision = self.decision(price, &resolved, checks, outcome);
+            return self.record_decision(&price.instrument, id, &decision);
+        }
+        if !self.instruments.is_enabled(&price.instrument) {
+            log::info!(
+                "[{}] Instrument disabled, not executing signal",
+                resolved.signal.instrument
+            );
+            let checks = vec![RiskCheck {
+                check: "instrumentEnabled".to_string(),
+                passed: false,
+                detail: None,
+            }];
+            let outcome = DecisionOutcome::Suppressed {
+                reason: "instrument disabled".to_string(),
+            };
+            let decision = self.decision(price, &resolved, checks, outcome);
+            return self.record_decision(&price.instrument, id, &decision);
+        }
+        if self
+            .warm_up
+            .as_ref()
+            .is_some_and(|warm_up| !warm_up.is_ready(&price.instrument))
+        {
+            log::info!(
+                "[{}] Strategies warming up, not executing signal",
+                resolved.signal.instrument
+            );
+            let checks = vec![RiskCheck {
+                check: "warmUp".to_string(),
+                passed: false,
+                detail: None,
+            }];
+            let outcome = DecisionOutcome::Suppressed {
+                reason: "warming up".to_string(),
+            };
+            let decision = self.decision(price, &resolved, checks, outcome);
+            return self.record_decision(&price.instrument, id, &decision);
+        }
+        // Aged by the latest price, which a signal from a worker may be behind
+        let latest = self
+            .last_prices
+            .get(&price.instrument)
+            .map_or(price.time, |latest| latest.time.max(price.time));
+        let now = self.clock.now();
+        if let Some(reason) = self
+            .staleness
+            .as_mut()
+            .and_then(|staleness| staleness.check(now, &price.instrument, latest))
+        {
+            log::warn!(
+                "[{}] Price stale, not executing signal: {}",
+                resolved.signal.instrument,
+                reason
+            );
+            let checks = vec![RiskCheck {
+                check: "priceStaleness".to_string(),
+                passed: false,
+                detail: Some(reason),
+            }];
+            let outcome = DecisionOutcome::Suppressed {
+                reason: "price stale".to_string(),
+            };
+            let decision = self.decision(price, &resolved, checks, outcome);
+            return self.record_decision(&price.instrument, id, &decision);
+        }
+
+        // While the connection is unhealthy, over the weekend or once the day's limits are
+        // reached, positions can be closed but not opened or added to
+        let positions = self.execution.open_positions();
+        let held = positions
+            .iter()
+            .find(|(instrument, _)| *instrument == resolved.signal.instrument)
+            .map_or(0.0, |(_, units)| *units);
+        let projected = if self.risk.limits_exposure() {
+            let exposure = self.exposure(&positions).await?;
+            if let Some(exposure) = exposure {
+                self.risk.record_exposure(exposure);
+            }
+            // Only a signal adding to its position can take the exposure over the limit
+            match self.execution.target(&resolved.signal) {
+                Some(target) if target.abs() > held.abs() => {
+                    let mut projected = positions.clone();
+                    projected.retain(|(instrument, _)| *instrument != resolved.signal.instrument);
+                    projected.push((resolved.signal.instrument.clone(), target));
+                    Some(self.exposure(&projected).await?)
+                }
+                _ => None,
+            }
+        } else {
+            None
+        };
+        let entry_check = if self.risk.entries_paused() {
+            Some((self.connection_check(false), "connection unhealthy"))
+        } else if self
+            .weekend
+            .as_ref()
+            .is_some_and(|weekend| weekend.closed(price.time, &price.instrument))
+        {
+            let check = RiskCheck {
+                check: "weekend".to_string(),
+                passed: false,
+                detail: None,
+            };
+            Some((check, "closed for the weekend"))
+        } else {
+            let breach = self
+                .risk
+                .session_breach()
+                .or_else(|| projected.and_then(|projected| self.risk.exposure_breach(projected)));
+            breach.map(|reason| {
+                let check = RiskCheck {
+                    check: "sessionLimits".to_string(),
+                    passed: false,
+                    detail: Some(reason),
+                };
+                (check, "session limit reached")
+            })
+        };
+        let mut signal = resolved.signal.clone();
+        if let Some((check, reason)) = entry_check.filter(|_| signal.forecast != 0.0) {
+            // Whatever's held back over the weekend is traded at the reopen, and whatever's held
+            // back for the connection once it recovers
+            if let Some(weekend) = self.weekend.as_mut().filter(|_| check.check == "weekend") {
+                weekend.suppressed(price.time, &signal.instrument);
+            }
+            if check.check == "connectionHealth" {
+                self.held_back.insert(signal.instrument.clone());
+            }
+            if held == 0.0 || held.signum() == signal.forecast.signum() {
+                log::info!("[{}] {}, not entering on signal", signal.instrument, reason);
+                let outcome = DecisionOutcome::Suppressed {
+                    reason: reason.to_string(),
+                };
+                let decision = self.decision(price, &resolved, vec![check], outcome);
+                return self.record_decision(&price.instrument, id, &decision);
+            }
+            log::info!(
+                "[{}] {}, closing rather than reversing",
+                signal.instrument,
+                reason
+            );
+            signal.forecast = 0.0;
+        }
+
+        let tags = OrderTags {
+            strategy: resolved.strategies.join(","),
+            signal: id.to_string(),
+            config_version: self.config_version.clone(),
+        };
+        // Left ordered if execution fails, so a restart finds it in flight
+        self.log_signal(SignalRecord::status(
+            self.clock.now(),
+            id,
+            SignalStatus::Ordered,
+            None,
+        ))?;
+        let fills = self.execution.execute(signal, &tags).await?;
+        if self.execution.target_reached(&resolved.signal) {
+            self.working.reached(&price.instrument);
+        }
+        for fill in &fills {
+            self.journal.record(&JournalEntry::order(
+                fill,
+                forecast,
+                resolved.policy,
+                &resolved.strategies,
+                Some(&tags.signal),
+            ))?;
+        }
+        if !fills.is_empty() {
+            self.holders
+                .insert(price.instrument.clone(), resolved.strategies.clone());
+        }
+
+        // Counted at the time of the order, like the decision it's journaled after, which is later
+        // than the price's when the price waited in the queue
+        let now = self.clock.now();
+        let breaches = self
+            .risk
+            .record_fills(now, &price.instrument, &resolved.strategies, &fills);
+        let mut checks = vec![
+            RiskCheck {
+                check: "paused".to_string(),
+                passed: true,
+                detail: None,
+            },
+            RiskCheck {
+                check: "instrumentEnabled".to_string(),
+                passed: true,
+                detail: None,
+            },
+        ];
+        if self.kill_switch.is_configured() {
+            checks.push(RiskCheck {
+                check: "killSwitch".to_string(),
+                passed: true,
+                detail: None,
+            });
+        }
+        if self.warm_up.is_some() {
+            checks.push(RiskCheck {
+                check: "warmUp".to_string(),
+                passed: true,
+                detail: None,
+            });
+        }
+        if self.staleness.is_some() {
+            checks.push(RiskCheck {
+                check: "priceStaleness".to_string(),
+                passed: true,
+                detail: None,
+            });
+        }
+        for check in self.risk.checks() {
+            let halted: Vec<&String> = breaches
+                .iter()
+                .filter(|breach| breach.check == check)
+                .flat_map(|breach| &breach.strategies)
+                .collect();
+            checks.push(RiskCheck {
+                check: check.to_string(),
+                passed: halted.is_empty(),
+                detail: (!halted.is_empty()).then(|| format!("halted {:?}", halted)),
+            });
+        }
+        if let Some(healthy) = self.risk.health_check() {
+            checks.push(self.connection_check(healthy));
+        }
+        if let Some(scale) = self.risk.drawdown_scale() {
+            checks.push(RiskCheck {
+                check: "drawdownScaling".to_string(),
+                passed: scale > 0.0,
+                detail: Some(format!("scale {}", scale)),
+            });
+        }
+        let posted = self
+            .execution
+            .resting_order(&price.instrument)
+            .filter(|order| order.tags.signal == id);
+        let outcome = if let Some(order) = posted.filter(|_| fills.is_empty()) {
+            DecisionOutcome::Posted {
+                units: order.units,
+                price: order.price,
+            }
+        } else if fills.is_empty() {
+            DecisionOutcome::Suppressed {
+                reason: "no order placed".to_string(),
+            }
+        } else {
+            DecisionOutcome::Ordered {
+                units: fills.iter().map(|fill| fill.units).sum(),
+                transaction_ids: fills
+                    .iter()
+                    .filter_map(|fill| fill.transaction_id.clone())
+                    .collect(),
+            }
+        };
+        let decision = self.decision(price, &resolved, checks, outcome);
+        self.record_decision(&price.instrument, id, &decision)?;
+
+        for breach in breaches {
+            log::error!("[{}] Halting {:?}", price.instrument, breach.strategies);
+            for name in &breach.strategies {
+                self.halt_strategy(name);
+            }
+            self.journal.record(&breach.entry)?;
+        }
+        Ok(())
+    }
+
+    // Journal the financing and commissions charged outside our own fills since the last call
+    fn record_costs(&mut self) -> Result<(), Box<dyn Error>> {
+        for cost in self.execution.take_costs() {
+            self.record_cost(&cost)?;
+        }
+        Ok(())
+    }
+
+    // Journal a cost, counting it against the strategies holding the instrument
+    fn record_cost(&mut self, cost: &AccountCost) -> Result<(), Box<dyn Error>> {
+        let strategies = self
+            .holders
+            .get(&cost.instrument)
+            .cloned()
+            .unwrap_or_default();
+        log::info!(
+            "[{}] {:?} of {:.2} attributed to {:?}",
+            cost.instrument,
+            cost.kind,
+            cost.amount,
+            strategies
+        );
+        self.journal
+            .record(&JournalEntry::cost(cost, &strategies))?;
+        let now = self.clock.now();
+        for breach in self.risk.record_cost(now, &strategies, cost.amount) {
+            log::error!("[{}] Halting {:?}", cost.instrument, breach.strategies);
+            for name in &breach.strategies {
+                self.halt_strategy(name);
+            }
+            self.journal.record(&breach.entry)?;
+        }
+        Ok(())
+    }
+
+    // Journal a posted order that ended and the fills it led to, which count against the
+    // strategies it was posted for like any other fill, and log its signal as filled or expired
+    fn record_passive(
+        &mut self,
+        report: PassiveReport<ExecutionFill>,
+    ) -> Result<(), Box<dyn Error>> {
+        let now = self.clock.now();
+        let order = &report.order;
+        let strategies: Vec<String> = order
+            .tags
+            .strategy
+            .split(',')
+            .filter(|name| !name.is_empty())
+            .map(str::to_string)
+            .collect();
+        if let Some(stats) = &mut self.passive {
+            stats.record(order, report.outcome);
+        }
+        for fill in &report.fills {
+            self.journal.record(&JournalEntry::order(
+                fill,
+                order.forecast,
+                self.strategy.policy(),
+                &strategies,
+                Some(&order.tags.signal),
+            ))?;
+        }
+        self.journal
+            .record(&JournalEntry::passive_order(now, &report))?;
+        if !report.fills.is_empty() {
+            self.holders
+                .insert(order.instrument.clone(), strategies.clone());
+        }
+
+        let (status, detail) = if !report.fills.is_empty() {
+            let units: f64 = report.fills.iter().map(|fill| fill.units).sum();
+            (
+                SignalStatus::Filled,
+                format!("{} units, {:?}", units, report.outcome),
+            )
+        } else if report.outcome == PassiveOutcome::Cancelled {
+            (SignalStatus::Expired, "replaced".to_string())
+        } else {
+            (SignalStatus::Rejected, "posted order unfilled".to_string())
+        };
+        if !order.tags.signal.is_empty() {
+            let record = SignalRecord::status(now, &order.tags.signal, status, Some(detail));
+            self.log_signal(record)?;
+        }
+
+        let breaches = self
+            .risk
+            .record_fills(now, &order.instrument, &strategies, &report.fills);
+        for breach in breaches {
+            log::error!("[{}] Halting {:?}", order.instrument, breach.strategies);
+            for name in &breach.strategies {
+                self.halt_strategy(name);
+            }
+            self.journal.record(&breach.entry)?;
+        }
+        Ok(())
+    }
+
+    // Scale positions for the account's drawdown. Equity that can't be fetched is checked again
+    // at the next interval rather than stopping the trader.
+    async fn check_drawdown(&mut self, time: u64) -> Result<(), Box<dyn Error>> {
+        let equity = match self.execution.equity().await {
+            Ok(equity) => equity,
+            Err(e) => {
+                log::warn!("Failed to fetch equity for drawdown scaling: {}", e);
+                self.risk.defer_equity(time);
+                return Ok(());
+            }
+        };
+        let change = match self.risk.record_equity(time, equity) {
+            Some(change) => change,
+            None => return Ok(()),
+        };
+        // New peaks are journaled too, so a restart measures the drawdown from the same one
+        self.journal
+            .record(&JournalEntry::drawdown_scale(time, &change))?;
+        if change.rescaled {
+            log::warn!(
+                "Equity {:.2} is {:.2}% below its peak of {:.2}, scaling positions by {}",
+                change.equity,
+                change.drawdown,
+                change.peak,
+                change.scale
+            );
+            self.execution.set_risk_scale(self.risk.position_scale());
+            self.execute_standings().await?;
+        }
+        Ok(())
+    }
+
+    // Stop a strategy, by hand or for a breach, and have the signals it contributed to restated
+    // without it, so positions it asked for don't outlive it
+    fn halt_strategy(&mut self, name: &str) {
+        let withdrawn = self.strategy.halt(name);
+        self.restating.extend(withdrawn);
+    }
+
+    // Execute the standing signal of every traded instrument and every open position, e.g. to
+    // resize positions for a new scale
+    async fn execute_standings(&mut self) -> Result<(), Box<dyn Error>> {
+        let mut instruments = self.config.instruments.clone();
+        for (instrument, _) in self.execution.open_positions() {
+            if !instruments.contains(&instrument) {
+                instruments.push(instrument);
+            }
+        }
+        for instrument in instruments {
+            self.execute_standing(&instrument).await?;
+        }
+        Ok(())
+    }
+
+    // Notional of the positions across all instruments in the account currency, by the latest
+    // prices, or None if one of them can't be converted yet
+    async fn exposure(
+        &mut self,
+        positions: &[(String, f64)],
+    ) -> Result<Option<f64>, Box<dyn Error>> {
+        let currency = match &self.account_currency {
+            Some(currency) => currency.clone(),
+            None => {
+                let currency = self.execution.account_currency().await?;
+                self.account_currency.insert(currency).clone()
+            }
+        };
+        let mut converter = Converter::new();
+        for price in self.last_prices.values() {
+            converter.update(price);
+        }
+        // A unit of an instrument is a unit of its base currency
+        Ok(positions
+            .iter()
+            .map(|(instrument, units)| {
+                let (base, _) = split_instrument(instrument)?;
+                Some(units.abs() * converter.rate(base, &currency)?)
+            })
+            .sum())
+    }
+
+    // Execute an instrument's signal as the strategies' forecasts stand, at its latest price,
+    // through the same checks as any other signal, e.g. once whatever held its last signal back
+    // has cleared or positions are sized differently. An instrument that's flat and should be is
+    // left alone.
+    async fn execute_standing(&mut self, instrument: &str) -> Result<(), Box<dyn Error>> {
+        let price = match self.last_prices.get(instrument) {
+            Some(price) => price.clone(),
+            None => return Ok(()),
+        };
+        let resolved = self.strategy.restate(instrument);
+        let held = self
+            .execution
+            .open_positions()
+            .iter()
+            .any(|(held, _)| held == instrument);
+        if resolved.signal.forecast == 0.0 && !held {
+            return Ok(());
+        }
+        self.working.resolved(&resolved, &price);
+        let now = self.clock.now();
+        let id = format!("{}-{}", instrument, now);
+        self.log_signal(SignalRecord::pending(now, &id, &resolved, price.time))?;
+        self.act_on(&price, resolved, &id).await
+    }
+
+    // Score the connection with the stream errors since the last item, and act on the score
+    // crossing the threshold. Tightened limits apply to open positions straight away, and on
+    // recovery they're sized back up and the signals held back meanwhile are traded.
+    async fn update_health(&mut self) -> Result<(), Box<dyn Error>> {
+        let now = self.clock.now();
+        let health = self.risk.health();
+        health.record_malformed(now, malformed_lines());
+        let change = match health.update(now) {
+            Some(change) => change,
+            None => return Ok(()),
+        };
+        if change.healthy {
+            log::info!("Connection health recovered to {:.2}", change.score);
+        } else {
+            log::warn!(
+                "Connection health fell to {:.2}, {:?}",
+                change.score,
+                self.risk.health().action()
+            );
+        }
+        self.execution.set_risk_scale(self.risk.position_scale());
+        if let HealthAction::TightenLimits { .. } = self.risk.health().action() {
+            return self.execute_standings().await;
+        }
+        if change.healthy {
+            for instrument in std::mem::take(&mut self.held_back) {
+                self.execute_standing(&instrument).await?;
+            }
+        }
+        Ok(())
+    }
+
+    fn connection_check(&self, passed: bool) -> RiskCheck {
+        RiskCheck {
+            check: "connectionHealth".to_string(),
+            passed,
+            detail: Some(format!("score {:.2}", self.risk.connection_health())),
+        }
+    }
+
+    // Audit entry for a resolved signal, taken before any strategies it breached are halted
+    fn decision(
+        &self,
+        price: &Price,
+        resolved: &ResolvedSignal,
+        checks: Vec<RiskCheck>,
+        outcome: DecisionOutcome,
+    ) -> JournalEntry {
+        JournalEntry::Decision {
+            time: format_time(self.clock.now()),
+            instrument: price.instrument.clone(),
+            bid: price.bid as f64,
+            ask: price.ask as f64,
+            inputs: self.strategy.snapshot(&price.instrument),
+            policy: resolved.policy,
+            strategies: resolved.strategies.clone(),
+            forecast: resolved.signal.forecast,
+            checks,
+            outcome,
+        }
+    }
+
+    // Journal a decision, and log the signal it was on as filled or rejected
+    fn record_decision(
+        &mut self,
+        instrument: &str,
+        signal: &str,
+        decision: &JournalEntry,
+    ) -> Result<(), Box<dyn Error>> {
+        if let Ok(mut history) = self.history.lock() {
+            history.record(instrument, decision);
+        }
+        self.journal.record(decision)?;
+        let (status, detail) = match decision {
+            JournalEntry::Decision {
+                outcome:
+                    DecisionOutcome::Ordered {
+                        units,
+                        transaction_ids,
+                    },
+                ..
+            } => {
+                let mut detail = format!("{} units", units);
+                if !transaction_ids.is_empty() {
+                    detail.push_str(&format!(" in {}", transaction_ids.join(",")));
+                }
+                (SignalStatus::Filled, detail)
+            }
+            JournalEntry::Decision {
+                outcome: DecisionOutcome::Suppressed { reason },
+                ..
+            } => (SignalStatus::Rejected, reason.clone()),
+            _ => return Ok(()),
+        };
+        let record = SignalRecord::status(self.clock.now(), signal, status, Some(detail));
+        self.log_signal(record)
+    }
+
+    fn log_signal(&mut self, record: SignalRecord) -> Result<(), Box<dyn Error>> {
+        match &mut self.signal_log {
+            Some(signal_log) => signal_log.record(&record),
+            None => Ok(()),
+        }
+    }
+
+    // Take over execution from standby: positions are brought up to date from the account first,
+    // then traded to the signals the strategies stand at, so the positions taken over follow
+    // these strategies straight away rather than on their next signals
+    async fn activate(&mut self) -> Result<(), Box<dyn Error>> {
+        if !self.standby {
+            log::warn!("Already active");
+            return Ok(());
+        }
+        log::info!("Activating, reconciling positions...");
+        for activity in self.execution.settle().await? {
+            self.journal.record(&JournalEntry::external(&activity))?;
+        }
+        self.record_costs()?;
+        self.standby = false;
+
+        let positions: Vec<OpenPosition> = self
+            .execution
+            .open_positions()
+            .into_iter()
+            .map(|(instrument, units)| OpenPosition { instrument, units })
+            .collect();
+        log::info!("Active with {} open positions", positions.len());
+        self.journal.record(&JournalEntry::Activated {
+            time: format_time(self.clock.now()),
+            positions,
+        })?;
+        self.execute_standings().await
+    }
+
+    // Take up the instruments of the config file as it is now. Positions in instruments that are
+    // dropped are left as they are, without prices to manage them. The rest of the config only
+    // changes with a restart.
+    fn reload(&mut self) {
+        let path = match &self.config_file {
+            Some(path) => path,
+            None => {
+                log::warn!("No config file to reload from");
+                return;
+            }
+        };
+        let config = match TradingConfig::load(path) {
+            Ok(config) => config,
+            Err(e) => {
+                log::error!(
+                    "Failed to reload {:?}, keeping the current config: {}",
+                    path,
+                    e
+                );
+                return;
+            }
+        };
+        if config.instruments == self.config.instruments
+            && config.instrument_priority == self.config.instrument_priority
+        {
+            log::info!("Reloaded {:?}, the instruments are unchanged", path);
+            return;
+        }
+
+        for (instrument, units) in self.execution.open_positions() {
+            if !config.instruments.contains(&instrument) {
+                log::warn!(
+                    "[{}] No longer traded, {} units are left open",
+                    instrument,
+                    units
+                );
+            }
+        }
+        log::info!("Trading {}", config.instruments.join(","));
+        self.config.instruments = config.instruments;
+        self.config.instrument_priority = config.instrument_priority;
+
+        let instruments = prioritize(
+            self.config.instruments.clone(),
+            &self.config.instrument_priority,
+        );
+        match &self.subscriptions {
+            Some(subscriptions) if subscriptions.send(instruments).is_ok() => {}
+            _ => log::warn!("The price source can't change instruments, restart to stream them"),
+        }
+    }
+
+    // Publish what the engine is doing for status queries
+    fn publish_status(&mut self) {
+        let positions = if self.standby {
+            Vec::new()
+        } else {
+            self.execution.open_positions()
+        };
+        let status = EngineStatus {
+            time: format_time(self.clock.now()),
+            standby: self.standby,
+            paused: self.handle.is_paused(),
+            positions: positions
+                .into_iter()
+                .map(|(instrument, units)| OpenPosition { instrument, units })
+                .collect(),
+            halted_strategies: self.strategy.halted(),
+            disabled_instruments: self.instruments.disabled().into_iter().cloned().collect(),
+            drawdown_scale: self.risk.drawdown_scale(),
+            connection_health: self.risk.connection_health(),
+            kill_switch: self.kill_switch.tripped(),
+            passive: self.passive.clone(),
+            warm_up: self.warm_up.as_ref().map(WarmUp::status),
+            dropped_ticks: self.dropped_ticks.totals().clone(),
+        };
+        if let Ok(mut shared) = self.status.lock() {
+            *shared = status;
+        }
+        self.last_status = Some(self.clock.now());
+    }
+
+    // Commands from the control socket and the gRPC service since they were last taken
+    fn commands(&self) -> Vec<ControlCommand> {
+        let mut commands = Vec::new();
+        if let Some(control) = &self.control {
+            commands.extend(control.commands());
+        }
+        #[cfg(feature = "grpc")]
+        if let Some(grpc) = &self.grpc {
+            commands.extend(grpc.commands());
+        }
+        commands
+    }
+
+    async fn handle_command(&mut self, command: ControlCommand) -> Result<(), Box<dyn Error>> {
+        match command {
+            ControlCommand::Enable(name) if self.strategy.has_strategy(&name) => {
+                log::info!("Enabling strategy {}", name);
+                self.strategy.resume(&name);
+                self.risk.enable(&name);
+                self.journal
+                    .record(&JournalEntry::enabled(self.clock.now(), &name))?;
+            }
+            ControlCommand::Pause(name) if self.strategy.has_strategy(&name) => {
+                log::info!("Pausing strategy {}", name);
+                self.halt_strategy(&name);
+                self.journal.record(&JournalEntry::paused(
+                    self.clock.now(),
+                    &name,
+                    "paused by hand",
+                ))?;
+            }
+            ControlCommand::Enable(name) | ControlCommand::Pause(name) => {
+                log::warn!("Unknown strategy {}", name);
+            }
+            ControlCommand::EnableInstrument(instrument)
+                if self.config.instruments.contains(&instrument) =>
+            {
+                log::info!("Enabling instrument {}", instrument);
+                self.instruments.enable(&instrument);
+                self.journal.record(&JournalEntry::instrument_enabled(
+                    self.clock.now(),
+                    &instrument,
+                ))?;
+                // Signals suppressed while it was disabled aren't sent again, so catch up now
+                self.execute_standing(&instrument).await?;
+            }
+            ControlCommand::DisableInstrument(instrument)
+                if self.config.instruments.contains(&instrument) =>
+            {
+                log::info!("Disabling instrument {}", instrument);
+                self.instruments.disable(&instrument);
+                self.journal.record(&JournalEntry::instrument_disabled(
+                    self.clock.now(),
+                    &instrument,
+                ))?;
+            }
+            ControlCommand::EnableInstrument(instrument)
+            | ControlCommand::DisableInstrument(instrument) => {
+                log::warn!("Unknown instrument {}", instrument);
+            }
+            ControlCommand::Activate => self.activate().await?,
+            ControlCommand::Reload => self.reload(),
+            ControlCommand::TickSample(sample) => {
+                log::info!("Logging 1 in {} ticks of each instrument", sample);
+                logging::set_tick_sample(sample);
+            }
+            ControlCommand::TickFull(instrument, full) => {
+                log::info!(
+                    "Logging {} ticks of {}",
+                    if full {
+                        "every one of the"
+                    } else {
+                        "a sample of the"
+                    },
+                    instrument
+                );
+                logging::set_tick_full(&instrument, full);
+            }
+            // Answered by the control socket
+            ControlCommand::Status(_) => {}
+        }
+        self.publish_status();
+        Ok(())
+    }
+}
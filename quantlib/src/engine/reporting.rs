@@ -0,0 +1,78 @@
+use std::time::Duration;
+
+use tokio::sync::mpsc;
+
+use crate::accounts::{aggregate, AggregateReport, ReportingSettings};
+use crate::oanda::objects::OandaSettings;
+
+const DAY: u64 = 86_400_000;
+// How long reading the accounts, and then sending the report, may each take before it's given up
+const REPORT_TIMEOUT: Duration = Duration::from_secs(60);
+
+// The report on every account in the settings' "reporting" config at the end of each UTC day. The
+// accounts are read and the report sent to the config's sinks on a task of its own, so prices,
+// fills and commands are handled meanwhile, and the report comes back to be journaled once read.
+pub struct AccountReports {
+    main: OandaSettings,
+    settings: ReportingSettings,
+    day: Option<u64>,
+    sender: mpsc::UnboundedSender<AggregateReport>,
+    receiver: mpsc::UnboundedReceiver<AggregateReport>,
+}
+
+impl AccountReports {
+    pub fn new(main: &OandaSettings, settings: &ReportingSettings) -> Self {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        AccountReports {
+            main: main.clone(),
+            settings: settings.clone(),
+            day: None,
+            sender,
+            receiver,
+        }
+    }
+
+    // Whether `time` is on another day than the last one seen
+    pub fn due(&self, time: u64) -> bool {
+        self.day != Some(time / DAY)
+    }
+
+    // Start on the day `time` is on, reporting on the accounts if a day the trader ran in ended
+    pub fn roll(&mut self, time: u64) {
+        if self.day.replace(time / DAY).is_none() {
+            return;
+        }
+        let main = self.main.clone();
+        let settings = self.settings.clone();
+        let sender = self.sender.clone();
+        tokio::spawn(async move {
+            let read = tokio::time::timeout(REPORT_TIMEOUT, aggregate(&main, &settings));
+            let report = match read.await {
+                Ok(Ok(report)) => report,
+                Ok(Err(e)) => {
+                    log::error!("Failed to report on the accounts: {}", e);
+                    return;
+                }
+                Err(_) => {
+                    log::error!("Timed out reading the accounts to report on");
+                    return;
+                }
+            };
+            log::info!("{}", report.text());
+            let _ = sender.send(report.clone());
+            let notify = report.notify(&settings.notify);
+            if tokio::time::timeout(REPORT_TIMEOUT, notify).await.is_err() {
+                log::error!("Timed out sending the account report");
+            }
+        });
+    }
+
+    // The reports read since the last call
+    pub fn take(&mut self) -> Vec<AggregateReport> {
+        let mut reports = Vec::new();
+        while let Ok(report) = self.receiver.try_recv() {
+            reports.push(report);
+        }
+        reports
+    }
+}
@@ -0,0 +1,383 @@
+use serde::{Deserialize, Serialize};
+
+use crate::engine::{ConnectionHealth, ExecutionFill, Session, SessionSummary};
+use crate::journal::JournalEntry;
+use crate::models::{CircuitBreaker, StrategyGuard};
+use crate::util::TradingConfig;
+
+// Position sizing cut back as the account's equity falls from its peak, "drawdownScaling" in the
+// trading config, e.g. {"levels": [{"percent": 5, "scale": 0.5}, {"percent": 10, "scale": 0.25},
+// {"percent": 15, "scale": 0}]} to halve positions 5% below the peak, quarter them at 10% and
+// stop trading at 15%, where every position is closed. Open positions are resized to the new
+// scale as soon as the level changes. A level is left once equity is back to `recovery` percent
+// above it. Equity is checked every `interval` seconds of price time, so backtests scale
+// positions exactly as live trading does.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DrawdownScaling {
+    pub levels: Vec<DrawdownLevel>,
+
+    #[serde(default)]
+    pub recovery: f64,
+
+    #[serde(default = "default_drawdown_interval")]
+    pub interval: u64,
+}
+
+fn default_drawdown_interval() -> u64 {
+    60
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DrawdownLevel {
+    // Drawdown from the peak the level starts at
+    pub percent: f64,
+
+    // Multiplier of every position while at the level
+    pub scale: f64,
+}
+
+// A move to another drawdown level, or a new peak to journal at the same level
+#[derive(Debug, Clone)]
+pub struct DrawdownChange {
+    pub equity: f64,
+    pub peak: f64,
+    pub drawdown: f64,
+    pub scale: f64,
+
+    // Whether the level changed, and positions are to be resized to the scale
+    pub rescaled: bool,
+}
+
+// The drawdown level an account is at, from its equity over time
+pub struct DrawdownScaler {
+    scaling: DrawdownScaling,
+    peak: f64,
+    level: Option<usize>,
+    next_check: u64,
+}
+
+// The peak and level of a DrawdownScaler, for a backtest to resume with. The scale follows from
+// the level.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct DrawdownState {
+    pub peak: f64,
+    pub level: Option<usize>,
+    pub next_check: u64,
+}
+
+impl DrawdownScaler {
+    pub fn new(mut scaling: DrawdownScaling) -> Self {
+        scaling
+            .levels
+            .sort_by(|a, b| a.percent.total_cmp(&b.percent));
+        DrawdownScaler {
+            scaling,
+            peak: 0.0,
+            level: None,
+            next_check: 0,
+        }
+    }
+
+    pub fn state(&self) -> DrawdownState {
+        DrawdownState {
+            peak: self.peak,
+            level: self.level,
+            next_check: self.next_check,
+        }
+    }
+
+    // A level the config no longer has is taken as its deepest
+    pub fn restore(&mut self, state: DrawdownState) {
+        self.peak = state.peak;
+        self.level = state
+            .level
+            .map(|level| level.min(self.scaling.levels.len().saturating_sub(1)))
+            .filter(|_| !self.scaling.levels.is_empty());
+        self.next_check = state.next_check;
+    }
+
+    pub fn scale(&self) -> f64 {
+        match self.level {
+            Some(level) => self.scaling.levels[level].scale,
+            None => 1.0,
+        }
+    }
+
+    // Whether equity should be checked at this time
+    pub fn due(&self, time: u64) -> bool {
+        time >= self.next_check
+    }
+
+    pub fn defer(&mut self, time: u64) {
+        self.next_check = time + self.scaling.interval.max(1) * 1000;
+    }
+
+    // Record the account's equity, returning the change if it moves the account to another level
+    // or sets a new peak
+    pub fn record(&mut self, time: u64, equity: f64) -> Option<DrawdownChange> {
+        self.defer(time);
+        let new_peak = equity > self.peak;
+        self.peak = self.peak.max(equity);
+        if self.peak <= 0.0 {
+            return None;
+        }
+        let drawdown = (self.peak - equity) / self.peak * 100.0;
+
+        let levels = &self.scaling.levels;
+        let mut level = self.level;
+        while let Some(current) = level {
+            if drawdown > levels[current].percent - self.scaling.recovery {
+                break;
+            }
+            level = current.checked_sub(1);
+        }
+        let next = level.map_or(0, |current| current + 1);
+        for (index, candidate) in levels.iter().enumerate().skip(next) {
+            if drawdown >= candidate.percent {
+                level = Some(index);
+            }
+        }
+
+        let rescaled = level != self.level;
+        if !rescaled && !new_peak {
+            return None;
+        }
+        self.level = level;
+        Some(DrawdownChange {
+            equity,
+            peak: self.peak,
+            drawdown,
+            scale: self.scale(),
+            rescaled,
+        })
+    }
+
+    // Pick up the peak journaled before a restart, so the drawdown isn't measured afresh. The
+    // level follows from the next equity checked.
+    pub fn replay(&mut self, entries: &[JournalEntry]) {
+        for entry in entries {
+            if let JournalEntry::DrawdownScale { peak, .. } = entry {
+                self.peak = self.peak.max(*peak);
+            }
+        }
+    }
+}
+
+// Strategies stopped by a risk check, and the journal entry recording why
+pub struct RiskBreach {
+    pub check: &'static str,
+    pub strategies: Vec<String>,
+    pub entry: JournalEntry,
+}
+
+// The checks that stop strategies once they have traded: the order rate circuit breaker and
+// the realized loss limits, the drawdown scaling of every position and the daily session
+// limits, each only if configured. The connection's health is always scored, and holds back
+// orders only with a threshold.
+pub struct RiskManager {
+    circuit_breaker: Option<CircuitBreaker>,
+    guard: Option<StrategyGuard>,
+    drawdown: Option<DrawdownScaler>,
+    session: Option<Session>,
+    health: ConnectionHealth,
+}
+
+impl RiskManager {
+    pub fn from_config(config: &TradingConfig) -> Self {
+        RiskManager {
+            circuit_breaker: config.order_rate_limit.clone().map(CircuitBreaker::new),
+            guard: config.strategy_limits.clone().map(StrategyGuard::new),
+            drawdown: config.drawdown_scaling.clone().map(DrawdownScaler::new),
+            session: config.session_limits.clone().map(Session::new),
+            health: ConnectionHealth::new(config.connection_health.clone()),
+        }
+    }
+
+    // Names of the configured checks, as they're configured
+    pub fn checks(&self) -> Vec<&'static str> {
+        let mut checks = Vec::new();
+        if self.guard.is_some() {
+            checks.push("strategyLimits");
+        }
+        if self.circuit_breaker.is_some() {
+            checks.push("orderRateLimit");
+        }
+        if self.session.is_some() {
+            checks.push("sessionLimits");
+        }
+        checks
+    }
+
+    // Pick up where the journal left off, so a restart doesn't give a failing strategy a clean
+    // slate. Returns the strategies that are still paused.
+    pub fn replay(&mut self, entries: &[JournalEntry]) -> Result<Vec<String>, String> {
+        if let Some(drawdown) = &mut self.drawdown {
+            drawdown.replay(entries);
+        }
+        if let Some(session) = &mut self.session {
+            session.replay(entries)?;
+        }
+        Ok(match &mut self.guard {
+            Some(guard) => {
+                guard.replay(entries)?;
+                guard.paused().cloned().collect()
+            }
+            None => Vec::new(),
+        })
+    }
+
+    // Whether the account's equity should be checked for drawdown scaling at this time
+    pub fn equity_due(&self, time: u64) -> bool {
+        self.drawdown
+            .as_ref()
+            .is_some_and(|drawdown| drawdown.due(time))
+    }
+
+    // Skip the check due at this time, e.g. when equity couldn't be fetched
+    pub fn defer_equity(&mut self, time: u64) {
+        if let Some(drawdown) = &mut self.drawdown {
+            drawdown.defer(time);
+        }
+    }
+
+    // Record the account's equity, returning the change of scale or peak if there is one
+    pub fn record_equity(&mut self, time: u64, equity: f64) -> Option<DrawdownChange> {
+        self.drawdown.as_mut()?.record(time, equity)
+    }
+
+    // Multiplier of every position for the current drawdown, None without drawdown scaling
+    pub fn drawdown_scale(&self) -> Option<f64> {
+        self.drawdown.as_ref().map(DrawdownScaler::scale)
+    }
+
+    // Multiplier of every position for the drawdown and the connection's health together
+    pub fn position_scale(&self) -> f64 {
+        self.drawdown_scale().unwrap_or(1.0) * self.health.scale()
+    }
+
+    pub fn health(&mut self) -> &mut ConnectionHealth {
+        &mut self.health
+    }
+
+    pub fn connection_health(&self) -> f64 {
+        self.health.score()
+    }
+
+    // Whether the threshold is configured, and if so whether the connection is above it
+    pub fn health_check(&self) -> Option<bool> {
+        self.health
+            .has_threshold()
+            .then(|| !self.health.is_unhealthy())
+    }
+
+    // Start the session of a new UTC day if `time` is on one, returning the day that ended
+    pub fn roll_session(&mut self, time: u64) -> Option<SessionSummary> {
+        self.session.as_mut()?.roll(time)
+    }
+
+    // Record the notional held across all instruments against the session's exposure limit
+    pub fn record_exposure(&mut self, exposure: f64) {
+        if let Some(session) = &mut self.session {
+            session.record_exposure(exposure);
+        }
+    }
+
+    // Why only signals closing positions are executed for the session limits, if they are
+    pub fn session_breach(&self) -> Option<String> {
+        self.session.as_ref()?.breach()
+    }
+
+    // Why a signal adding to its position isn't executed for the session's exposure limit, see
+    // Session::exposure_breach
+    pub fn exposure_breach(&self, projected: Option<f64>) -> Option<String> {
+        self.session.as_ref()?.exposure_breach(projected)
+    }
+
+    // Whether signals are checked against the session's exposure limit
+    pub fn limits_exposure(&self) -> bool {
+        self.session.as_ref().is_some_and(Session::limits_exposure)
+    }
+
+    // The day so far, None without session limits
+    pub fn session(&self) -> Option<&SessionSummary> {
+        self.session.as_ref().map(Session::summary)
+    }
+
+    // Whether only signals closing positions are executed, for the connection's health
+    pub fn entries_paused(&self) -> bool {
+        self.health.pauses_entries()
+    }
+
+    // Check the fills of an order placed on a signal from the given strategies
+    pub fn record_fills(
+        &mut self,
+        time: u64,
+        instrument: &str,
+        strategies: &[String],
+        fills: &[ExecutionFill],
+    ) -> Vec<RiskBreach> {
+        if let Some(session) = &mut self.session {
+            session.record_fills(time, fills);
+        }
+
+        let mut breaches = Vec::new();
+        if let Some(guard) = &mut self.guard {
+            for pl in fills.iter().filter_map(ExecutionFill::net_pl) {
+                for (strategy, reason) in guard.record(time, strategies, pl) {
+                    breaches.push(RiskBreach {
+                        check: "strategyLimits",
+                        entry: JournalEntry::paused(time, &strategy, &reason),
+                        strategies: vec![strategy],
+                    });
+                }
+            }
+        }
+
+        if let Some(circuit_breaker) = &mut self.circuit_breaker {
+            if !fills.is_empty() {
+                if let Some(trip) = circuit_breaker.record_order(time, instrument, strategies) {
+                    breaches.push(RiskBreach {
+                        check: "orderRateLimit",
+                        entry: JournalEntry::circuit_breaker(&trip),
+                        strategies: trip.strategies,
+                    });
+                }
+            }
+        }
+        breaches
+    }
+
+    // Count a cost charged outside our fills, e.g. financing, against the strategies it's
+    // attributed to
+    pub fn record_cost(
+        &mut self,
+        time: u64,
+        strategies: &[String],
+        amount: f64,
+    ) -> Vec<RiskBreach> {
+        let guard = match &mut self.guard {
+            Some(guard) => guard,
+            None => return Vec::new(),
+        };
+        guard
+            .record(time, strategies, amount)
+            .into_iter()
+            .map(|(strategy, reason)| RiskBreach {
+                check: "strategyLimits",
+                entry: JournalEntry::paused(time, &strategy, &reason),
+                strategies: vec![strategy],
+            })
+            .collect()
+    }
+
+    // Clear a strategy's breaches so it can trade again
+    pub fn enable(&mut self, strategy: &str) {
+        if let Some(guard) = &mut self.guard {
+            guard.enable(strategy);
+        }
+        if let Some(circuit_breaker) = &mut self.circuit_breaker {
+            circuit_breaker.reset(strategy);
+        }
+    }
+}
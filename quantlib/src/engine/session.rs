@@ -0,0 +1,199 @@
+use serde::{Deserialize, Serialize};
+
+use crate::calendar;
+use crate::engine::ExecutionFill;
+use crate::journal::JournalEntry;
+use crate::oanda::helpers::parse_time;
+
+const DAY: u64 = 86_400_000;
+
+// Limits on each UTC day of trading, "sessionLimits" in the trading config, e.g.
+// {"maxLoss": 1000, "maxTrades": 50, "maxExposure": 300000}. Once the day's realized loss (in
+// the account currency) or number of fills reaches its limit, signals can close positions but
+// not open or add to them until midnight UTC. maxExposure is the notional held across all
+// instruments, in the account currency: a signal that would take it over the limit can close
+// its position but not open or add to it, as can any signal adding to it before the rates to
+// value it are known. The day's totals survive a restart through the journal, and are journaled
+// as a dailySummary when the day ends.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SessionLimits {
+    #[serde(default)]
+    #[serde(rename = "maxLoss")]
+    pub max_loss: Option<f64>,
+
+    #[serde(default)]
+    #[serde(rename = "maxTrades")]
+    pub max_trades: Option<usize>,
+
+    #[serde(default)]
+    #[serde(rename = "maxExposure")]
+    pub max_exposure: Option<f64>,
+}
+
+// What was traded on a UTC day, and the limits it reached
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct SessionSummary {
+    // e.g. "2024-01-02"
+    pub date: String,
+
+    #[serde(rename = "realizedPl")]
+    pub realized_pl: f64,
+
+    pub trades: usize,
+
+    // Most notional held across all instruments at once in the account currency, as seen by the
+    // signals of the day
+    #[serde(rename = "maxExposure")]
+    pub max_exposure: f64,
+
+    #[serde(rename = "limitsReached")]
+    pub limits_reached: Vec<String>,
+}
+
+// The day's trading against the session limits
+pub struct Session {
+    limits: SessionLimits,
+    day: Option<u64>,
+    summary: SessionSummary,
+    exposure: f64,
+}
+
+impl Session {
+    pub fn new(limits: SessionLimits) -> Self {
+        Session {
+            limits,
+            day: None,
+            summary: SessionSummary::default(),
+            exposure: 0.0,
+        }
+    }
+
+    // Carry on with the day the journal ends in, unless it was already summarized
+    pub fn replay(&mut self, entries: &[JournalEntry]) -> Result<(), String> {
+        for entry in entries {
+            match entry {
+                JournalEntry::Order { time, pl, .. } => {
+                    let (time, _) = parse_time(time)?;
+                    self.roll(time);
+                    self.summary.trades += 1;
+                    self.summary.realized_pl += pl.unwrap_or(0.0);
+                }
+                JournalEntry::DailySummary { summary, .. } if self.is_day(&summary.date) => {
+                    self.day = None;
+                    self.summary = SessionSummary::default();
+                }
+                _ => {}
+            }
+        }
+        self.update_limits();
+        Ok(())
+    }
+
+    // Start a new day if `time` is on one, returning the summary of the day that ended
+    pub fn roll(&mut self, time: u64) -> Option<SessionSummary> {
+        let day = time / DAY;
+        if self.day == Some(day) {
+            return None;
+        }
+        let ended = self
+            .day
+            .replace(day)
+            .map(|_| std::mem::take(&mut self.summary));
+        self.summary.date = calendar::utc(day * DAY).format("%Y-%m-%d").to_string();
+        self.summary.max_exposure = self.exposure;
+        self.update_limits();
+        ended
+    }
+
+    pub fn record_fills(&mut self, time: u64, fills: &[ExecutionFill]) {
+        self.roll(time);
+        for fill in fills {
+            self.summary.trades += 1;
+            self.summary.realized_pl += fill.pl.unwrap_or(0.0);
+        }
+        self.update_limits();
+    }
+
+    // The notional held across all instruments, in the account currency
+    pub fn record_exposure(&mut self, exposure: f64) {
+        self.exposure = exposure;
+        self.summary.max_exposure = self.summary.max_exposure.max(exposure);
+        self.update_limits();
+    }
+
+    // Why only closing signals are executed, if they are
+    pub fn breach(&self) -> Option<String> {
+        let loss = -self.summary.realized_pl;
+        if let Some(max_loss) = self.limits.max_loss.filter(|max| loss >= *max) {
+            return Some(format!("lost {:.2} today, limit is {:.2}", loss, max_loss));
+        }
+        if let Some(max_trades) = self
+            .limits
+            .max_trades
+            .filter(|max| self.summary.trades >= *max)
+        {
+            return Some(format!(
+                "{} trades today, limit is {}",
+                self.summary.trades, max_trades
+            ));
+        }
+        None
+    }
+
+    // Why a signal adding to its position isn't executed for the exposure limit, if it isn't.
+    // `projected` is the notional held across all instruments once it is, None if it can't be
+    // valued yet.
+    pub fn exposure_breach(&self, projected: Option<f64>) -> Option<String> {
+        let max_exposure = self.limits.max_exposure?;
+        match projected {
+            Some(projected) if projected > max_exposure => Some(format!(
+                "{:.0} held after the signal, limit is {:.0}",
+                projected, max_exposure
+            )),
+            Some(_) => None,
+            None => Some("the exposure can't be valued without conversion rates yet".to_string()),
+        }
+    }
+
+    // Whether signals are checked against an exposure limit
+    pub fn limits_exposure(&self) -> bool {
+        self.limits.max_exposure.is_some()
+    }
+
+    pub fn summary(&self) -> &SessionSummary {
+        &self.summary
+    }
+
+    // Note each limit the day has reached
+    fn update_limits(&mut self) {
+        let loss = -self.summary.realized_pl;
+        let reached = [
+            (
+                "maxLoss",
+                self.limits.max_loss.is_some_and(|max| loss >= max),
+            ),
+            (
+                "maxTrades",
+                self.limits
+                    .max_trades
+                    .is_some_and(|max| self.summary.trades >= max),
+            ),
+            (
+                "maxExposure",
+                self.limits
+                    .max_exposure
+                    .is_some_and(|max| self.exposure >= max),
+            ),
+        ];
+        for (limit, reached) in reached {
+            if reached && !self.summary.limits_reached.iter().any(|l| l == limit) {
+                log::warn!("Session limit {} reached for {}", limit, self.summary.date);
+                self.summary.limits_reached.push(limit.to_string());
+            }
+        }
+    }
+
+    fn is_day(&self, date: &str) -> bool {
+        self.day.is_some() && self.summary.date == date
+    }
+}
@@ -0,0 +1,297 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::backtest::SimulatedAccount;
+use crate::broker::OrderTags;
+use crate::engine::{format_time, Execution, PaperExecution};
+use crate::models::{ResolvedSignal, SignalBus, TrailingStopManager};
+use crate::oanda::objects::Price;
+use crate::util::TradingConfig;
+
+// Runs a candidate's strategies on the same prices as the production ones while production
+// keeps trading for real, "shadow" in the trading config, e.g.
+// {"candidate": "candidate.json", "period": 604800, "report": "shadow.json"}
+// The candidate is another trading config, of which only the strategies and how positions are
+// sized and paper traded are used. Its signals are traded on a paper account, and so are the
+// production strategies' signals on one of their own, so the two are compared like for like
+// rather than against production's real fills. Neither goes through the risk checks. Every
+// `period` seconds, and on shutdown, a report comparing them since the start is written to
+// `report`. The candidate's strategies aren't checkpointed, so after a restart they and the
+// comparison start again. Anything failing in the shadow stops it, never production.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ShadowConfig {
+    pub candidate: PathBuf,
+
+    #[serde(default = "default_period")]
+    pub period: u64,
+
+    #[serde(default = "default_report")]
+    pub report: PathBuf,
+}
+
+fn default_period() -> u64 {
+    7 * 86_400
+}
+
+fn default_report() -> PathBuf {
+    PathBuf::from("shadow.json")
+}
+
+pub struct Shadow {
+    config: ShadowConfig,
+    candidate: SignalBus,
+    books: [ShadowBook; 2],
+    started: Option<u64>,
+    last_report: Option<u64>,
+
+    // Signals after which both had a forecast of the same sign for the instrument
+    compared: u64,
+    agreed: u64,
+}
+
+const PRODUCTION: usize = 0;
+const CANDIDATE: usize = 1;
+
+impl Shadow {
+    // Positions of `units` as in PaperExecution, for the production strategies as sized by the
+    // production config and for the candidate's as sized by its own
+    pub fn new(
+        config: &ShadowConfig,
+        production: &TradingConfig,
+        units: f64,
+    ) -> Result<Self, Box<dyn Error>> {
+        let candidate = TradingConfig::load(&config.candidate)?;
+        Ok(Shadow {
+            config: config.clone(),
+            candidate: SignalBus::from_config(&candidate)?,
+            books: [
+                ShadowBook::new(production, units),
+                ShadowBook::new(&candidate, units),
+            ],
+            started: None,
+            last_report: None,
+            compared: 0,
+            agreed: 0,
+        })
+    }
+
+    pub fn warm_up(&mut self, prices: &[Price]) -> Result<(), Box<dyn Error>> {
+        self.candidate.warm_up(prices)
+    }
+
+    // Mark both accounts to the price and trade the candidate's signal on it, if any
+    pub async fn price(&mut self, price: &Price) -> Result<(), Box<dyn Error>> {
+        self.started.get_or_insert(price.time);
+        for book in &mut self.books {
+            let stops = book.execution.handle_price(price).await?;
+            book.fills += stops.len() as u64;
+            book.take_passive_fills();
+        }
+        if !price.is_tradeable() {
+            return self.candidate.warm_up(std::slice::from_ref(price));
+        }
+        if let Some(resolved) = self.candidate.tick(price)? {
+            self.signal(CANDIDATE, &resolved).await?;
+        }
+        for book in &mut self.books {
+            book.mark();
+        }
+        Ok(())
+    }
+
+    // Trade a signal of the production strategies on their paper account
+    pub async fn production(&mut self, resolved: &ResolvedSignal) -> Result<(), Box<dyn Error>> {
+        self.signal(PRODUCTION, resolved).await
+    }
+
+    async fn signal(
+        &mut self,
+        side: usize,
+        resolved: &ResolvedSignal,
+    ) -> Result<(), Box<dyn Error>> {
+        let signal = resolved.signal.clone();
+        let instrument = signal.instrument.clone();
+        let tags = OrderTags {
+            strategy: resolved.strategies.join(","),
+            signal: String::new(),
+            config_version: String::new(),
+        };
+        let book = &mut self.books[side];
+        book.signals += 1;
+        book.forecasts.insert(instrument.clone(), signal.forecast);
+        book.fills += book.execution.execute(signal, &tags).await?.len() as u64;
+
+        // A missing forecast is flat, which only agrees with another flat one
+        let forecast = |book: &ShadowBook| book.forecasts.get(&instrument).copied().unwrap_or(0.0);
+        let production = forecast(&self.books[PRODUCTION]);
+        let candidate = forecast(&self.books[CANDIDATE]);
+        self.compared += 1;
+        if production.partial_cmp(&0.0) == candidate.partial_cmp(&0.0) {
+            self.agreed += 1;
+        }
+        Ok(())
+    }
+
+    pub fn report_due(&self, now: u64) -> bool {
+        let since = self.last_report.or(self.started);
+        since.is_some_and(|since| now.saturating_sub(since) >= self.config.period * 1000)
+    }
+
+    // Compare the two since the start, writing the report to the configured path
+    pub fn report(&mut self, now: u64) -> Result<ShadowReport, Box<dyn Error>> {
+        self.last_report = Some(now);
+        let report = ShadowReport {
+            start: format_time(self.started.unwrap_or(now)),
+            end: format_time(now),
+            candidate_config: self.config.candidate.clone(),
+            production: self.books[PRODUCTION].result(),
+            candidate: self.books[CANDIDATE].result(),
+            agreement: (self.compared > 0).then(|| self.agreed as f64 / self.compared as f64),
+        };
+        report.save(&self.config.report)?;
+        Ok(report)
+    }
+}
+
+// One side's paper account and what its strategies did on it
+struct ShadowBook {
+    execution: Execution<'static>,
+    initial_balance: f64,
+    signals: u64,
+    fills: u64,
+    forecasts: HashMap<String, f64>,
+    peak: f64,
+    max_drawdown: f64,
+}
+
+impl ShadowBook {
+    fn new(config: &TradingConfig, units: f64) -> Self {
+        let mut paper = PaperExecution::from_config(config, units);
+        if let Some(distance) = &config.trailing_stop {
+            paper = paper.with_trailing_stops(TrailingStopManager::new(distance.clone()));
+        }
+        ShadowBook {
+            execution: Execution::Paper(Box::new(paper)),
+            initial_balance: config.paper.initial_balance,
+            signals: 0,
+            fills: 0,
+            forecasts: HashMap::new(),
+            peak: config.paper.initial_balance,
+            max_drawdown: 0.0,
+        }
+    }
+
+    fn account(&self) -> &SimulatedAccount {
+        match &self.execution {
+            Execution::Paper(paper) => paper.account(),
+            _ => unreachable!("Shadow accounts are always paper"),
+        }
+    }
+
+    // Orders posted inside the spread fill on later prices
+    fn take_passive_fills(&mut self) {
+        for report in self.execution.take_passive_reports() {
+            self.fills += report.fills.len() as u64;
+        }
+    }
+
+    fn mark(&mut self) {
+        let nav = self.account().nav();
+        self.peak = self.peak.max(nav);
+        if self.peak > 0.0 {
+            self.max_drawdown = self.max_drawdown.max((self.peak - nav) / self.peak);
+        }
+    }
+
+    fn result(&self) -> ShadowResult {
+        let account = self.account();
+        let nav = account.nav();
+        ShadowResult {
+            signals: self.signals,
+            fills: self.fills,
+            realized_pl: account.balance - self.initial_balance,
+            unrealized_pl: account.unrealized_pl(),
+            nav,
+            total_return: match self.initial_balance > 0.0 {
+                true => nav / self.initial_balance - 1.0,
+                false => 0.0,
+            },
+            max_drawdown: self.max_drawdown,
+        }
+    }
+}
+
+// How the production strategies and the candidate's did on their paper accounts
+#[derive(Serialize, Debug, Clone)]
+pub struct ShadowReport {
+    pub start: String,
+    pub end: String,
+
+    #[serde(rename = "candidateConfig")]
+    pub candidate_config: PathBuf,
+
+    pub production: ShadowResult,
+    pub candidate: ShadowResult,
+
+    // Fraction of signals after which both sides' forecasts for the instrument had the same
+    // sign, None before any signal
+    pub agreement: Option<f64>,
+}
+
+impl ShadowReport {
+    pub fn log(&self) {
+        let agreement = self.agreement.map_or("-".to_string(), |agreement| {
+            format!("{:.0}%", agreement * 100.0)
+        });
+        for (name, result) in [
+            ("Production", &self.production),
+            ("Candidate", &self.candidate),
+        ] {
+            log::info!(
+                "[SHADOW] {}: {} signals, {} fills, return {:.2}%, max drawdown {:.2}%",
+                name,
+                result.signals,
+                result.fills,
+                result.total_return * 100.0,
+                result.max_drawdown * 100.0
+            );
+        }
+        log::info!(
+            "[SHADOW] Forecasts agreed after {} of signals since {}",
+            agreement,
+            self.start
+        );
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), Box<dyn Error>> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        serde_json::to_writer_pretty(&mut writer, self)?;
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct ShadowResult {
+    pub signals: u64,
+    pub fills: u64,
+
+    #[serde(rename = "realizedPl")]
+    pub realized_pl: f64,
+
+    #[serde(rename = "unrealizedPl")]
+    pub unrealized_pl: f64,
+
+    pub nav: f64,
+
+    #[serde(rename = "totalReturn")]
+    pub total_return: f64,
+
+    #[serde(rename = "maxDrawdown")]
+    pub max_drawdown: f64,
+}
@@ -0,0 +1,127 @@
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::engine::ExecutionFill;
+
+// What to do with open positions when the engine stops
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "camelCase")]
+pub enum ShutdownPositions {
+    // Leave them as they are
+    #[default]
+    Keep,
+
+    // Close every position
+    Flatten,
+
+    // Leave them open with a resting stop order stopPips away from the current price
+    Protect,
+}
+
+// e.g. "shutdown": {"positions": "protect", "stopPips": 30, "report": "shutdown.json"}
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ShutdownConfig {
+    #[serde(default)]
+    pub positions: ShutdownPositions,
+
+    #[serde(default = "default_stop_pips")]
+    #[serde(rename = "stopPips")]
+    pub stop_pips: f64,
+
+    // Where to write the shutdown report
+    #[serde(default)]
+    pub report: Option<PathBuf>,
+}
+
+fn default_stop_pips() -> f64 {
+    50.0
+}
+
+impl Default for ShutdownConfig {
+    fn default() -> Self {
+        ShutdownConfig {
+            positions: ShutdownPositions::default(),
+            stop_pips: default_stop_pips(),
+            report: None,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct OpenPosition {
+    pub instrument: String,
+    pub units: f64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ProtectiveStop {
+    pub instrument: String,
+    pub units: f64,
+    pub price: f64,
+
+    #[serde(rename = "orderId")]
+    pub order_id: String,
+}
+
+// What the engine did on its way out
+#[derive(Serialize, Debug, Clone)]
+pub struct ShutdownReport {
+    pub time: String,
+    pub reason: String,
+    pub positions: ShutdownPositions,
+
+    // Positions held once in-flight orders had settled, before any were closed
+    #[serde(rename = "openPositions")]
+    pub open_positions: Vec<OpenPosition>,
+
+    pub fills: Vec<ExecutionFill>,
+
+    #[serde(rename = "protectiveStops")]
+    pub protective_stops: Vec<ProtectiveStop>,
+
+    pub checkpoint: Option<PathBuf>,
+
+    // Anything that failed, e.g. a position that couldn't be closed. The engine still tries the
+    // remaining positions, so one failure doesn't leave everything else unhandled.
+    pub errors: Vec<String>,
+}
+
+// The stops placed on shutdown are kept next to the journal, e.g. journal.jsonl.stops, until the
+// next start cancels them. Without this every restart would leave another stop behind.
+pub fn protective_stops_path(journal: &Path) -> PathBuf {
+    let mut name = journal.as_os_str().to_owned();
+    name.push(".stops");
+    PathBuf::from(name)
+}
+
+pub fn save_protective_stops(
+    path: &Path,
+    stops: &[ProtectiveStop],
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    serde_json::to_writer_pretty(&mut writer, stops)?;
+    writer.flush()?;
+    Ok(())
+}
+
+// None if no stops were left resting
+pub fn load_protective_stops(
+    path: &Path,
+) -> Result<Option<Vec<ProtectiveStop>>, Box<dyn std::error::Error>> {
+    if !path.exists() {
+        return Ok(None);
+    }
+    Ok(Some(serde_json::from_str(&std::fs::read_to_string(path)?)?))
+}
+
+impl ShutdownReport {
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), Box<dyn std::error::Error>> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        serde_json::to_writer_pretty(&mut writer, self)?;
+        writer.flush()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,252 @@
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+
+use crate::engine::format_time;
+use crate::models::{ConflictPolicy, ResolvedSignal, TradingSignal};
+
+// Write-ahead log of the signals the engine acts on, "signalLog" in the trading config, e.g.
+// {"path": "logs/signals.wal", "maxAge": 300}. A signal is logged as pending before any check,
+// as ordered just before its order is sent, then as filled, rejected or expired, each line
+// synced to disk before going on. After a crash, the signals still pending or ordered are
+// handled again on the first price of their instrument. Orders move a position to a target, so
+// one filled just before the crash finds its target already reached and places nothing more,
+// and every signal is acted on exactly once. Signals more than `maxAge` seconds old by then
+// are expired instead, the market they were resolved in having moved on.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SignalLogConfig {
+    pub path: PathBuf,
+
+    #[serde(default = "default_max_age")]
+    #[serde(rename = "maxAge")]
+    pub max_age: u64,
+}
+
+fn default_max_age() -> u64 {
+    300
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum SignalStatus {
+    Pending,
+    Ordered,
+    Filled,
+    Rejected,
+    Expired,
+}
+
+impl SignalStatus {
+    // Whether nothing more will be done for the signal
+    pub fn is_final(&self) -> bool {
+        matches!(
+            self,
+            SignalStatus::Filled | SignalStatus::Rejected | SignalStatus::Expired
+        )
+    }
+}
+
+// A signal as logged when it's first seen, enough to act on it again
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct LoggedSignal {
+    pub instrument: String,
+    pub forecast: f64,
+    pub policy: ConflictPolicy,
+    pub strategies: Vec<String>,
+
+    // Time of the price it was resolved on
+    #[serde(rename = "priceTime")]
+    pub price_time: u64,
+}
+
+impl LoggedSignal {
+    pub fn resolved(&self) -> ResolvedSignal {
+        ResolvedSignal {
+            signal: TradingSignal {
+                instrument: self.instrument.clone(),
+                forecast: self.forecast,
+            },
+            policy: self.policy,
+            strategies: self.strategies.clone(),
+            validity: None,
+        }
+    }
+}
+
+// One line of the log: a signal, "<instrument>-<price time>" as tagged on its orders, reaching
+// a status. Pending lines carry the signal itself, the others why they ended as they did, e.g.
+// the transactions filling it.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SignalRecord {
+    pub id: String,
+    pub status: SignalStatus,
+    pub time: String,
+
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub signal: Option<LoggedSignal>,
+
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+}
+
+impl SignalRecord {
+    pub fn pending(time: u64, id: &str, resolved: &ResolvedSignal, price_time: u64) -> Self {
+        SignalRecord {
+            id: id.to_string(),
+            status: SignalStatus::Pending,
+            time: format_time(time),
+            signal: Some(LoggedSignal {
+                instrument: resolved.signal.instrument.clone(),
+                forecast: resolved.signal.forecast,
+                policy: resolved.policy,
+                strategies: resolved.strategies.clone(),
+                price_time,
+            }),
+            detail: None,
+        }
+    }
+
+    pub fn status(time: u64, id: &str, status: SignalStatus, detail: Option<String>) -> Self {
+        SignalRecord {
+            id: id.to_string(),
+            status,
+            time: format_time(time),
+            signal: None,
+            detail,
+        }
+    }
+}
+
+// A signal the last run left pending or ordered
+#[derive(Debug, Clone)]
+pub struct InFlightSignal {
+    pub id: String,
+    pub status: SignalStatus,
+    pub signal: LoggedSignal,
+}
+
+pub struct SignalLog {
+    file: File,
+    max_age: u64,
+}
+
+impl SignalLog {
+    // Open the log, returning the signals left in flight by the last run, the latest of each
+    // instrument's. Older ones were superseded by the time they'd be acted on and are logged as
+    // expired. The log is rewritten with only the signals in flight, so it stays small.
+    pub fn open(
+        config: &SignalLogConfig,
+        now: u64,
+    ) -> Result<(Self, HashMap<String, InFlightSignal>), Box<dyn std::error::Error>> {
+        let mut in_flight: Vec<InFlightSignal> = Vec::new();
+        if config.path.exists() {
+            let mut signals: HashMap<String, InFlightSignal> = HashMap::new();
+            let mut order = Vec::new();
+            for line in BufReader::new(File::open(&config.path)?).lines() {
+                // A line cut off by the crash is the last one, and its status was never reached
+                let record: SignalRecord = match serde_json::from_str(&line?) {
+                    Ok(record) => record,
+                    Err(_) => continue,
+                };
+                match (record.signal, signals.get_mut(&record.id)) {
+                    (Some(signal), None) => {
+                        order.push(record.id.clone());
+                        signals.insert(
+                            record.id.clone(),
+                            InFlightSignal {
+                                id: record.id,
+                                status: record.status,
+                                signal,
+                            },
+                        );
+                    }
+                    (_, Some(known)) => known.status = record.status,
+                    (None, None) => log::warn!("Signal {} was never logged as pending", record.id),
+                }
+            }
+            in_flight = order
+                .into_iter()
+                .filter_map(|id| signals.remove(&id))
+                .filter(|signal| !signal.status.is_final())
+                .collect();
+        } else if let Some(parent) = config.path.parent() {
+            if !parent.as_os_str().is_empty() {
+                std::fs::create_dir_all(parent)?;
+            }
+        }
+
+        let mut latest: HashMap<String, InFlightSignal> = HashMap::new();
+        let mut superseded = Vec::new();
+        for signal in in_flight {
+            match latest.get(&signal.signal.instrument) {
+                Some(known) if known.signal.price_time > signal.signal.price_time => {
+                    superseded.push(signal)
+                }
+                _ => {
+                    if let Some(older) = latest.insert(signal.signal.instrument.clone(), signal) {
+                        superseded.push(older);
+                    }
+                }
+            }
+        }
+
+        // Written aside and renamed over the log, so a crash now leaves one or the other
+        let compacted = config.path.with_extension("compacting");
+        let mut file = File::create(&compacted)?;
+        for signal in latest.values() {
+            let mut record =
+                SignalRecord::status(now, &signal.id, signal.status, Some("in flight".into()));
+            record.signal = Some(signal.signal.clone());
+            writeln!(file, "{}", serde_json::to_string(&record)?)?;
+        }
+        file.sync_all()?;
+        std::fs::rename(&compacted, &config.path)?;
+
+        let file = OpenOptions::new().append(true).open(&config.path)?;
+        let mut log = SignalLog {
+            file,
+            max_age: config.max_age,
+        };
+        for signal in superseded {
+            log::warn!(
+                "Signal {} left {:?} by the last run was superseded",
+                signal.id,
+                signal.status
+            );
+            log.record(&SignalRecord::status(
+                now,
+                &signal.id,
+                SignalStatus::Expired,
+                Some("superseded".to_string()),
+            ))?;
+        }
+        for signal in latest.values() {
+            log::warn!(
+                "Signal {} was left {:?} by the last run, it's handled again on the next price of {}",
+                signal.id,
+                signal.status,
+                signal.signal.instrument
+            );
+        }
+        Ok((log, latest))
+    }
+
+    // How old a signal left in flight can be and still be acted on, in milliseconds
+    pub fn max_age(&self) -> u64 {
+        self.max_age * 1000
+    }
+
+    // Append a record, on disk before this returns
+    pub fn record(&mut self, record: &SignalRecord) -> Result<(), Box<dyn std::error::Error>> {
+        let mut line = serde_json::to_string(record)?;
+        line.push('\n');
+        self.file.write_all(line.as_bytes())?;
+        self.file.sync_data()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,66 @@
+use std::collections::HashSet;
+
+use serde::{Deserialize, Serialize};
+
+use crate::engine::format_time;
+use crate::notify::{notify, Notification, NotificationSink};
+
+// Refuses to act on a signal while its instrument's latest price is older than `maxAge` seconds,
+// "priceStaleness" in the trading config, e.g. {"maxAge": 10, "notify": [{"type": "webhook",
+// "url": "https://..."}]}. A stream that goes quiet without disconnecting leaves the last price
+// standing, and a signal resolved on it would be ordered at a market long gone. The age is
+// taken when the order would be placed, so it also covers signals that waited for a strategy
+// worker. An instrument is alerted on when its price goes stale, and again only once a fresh
+// price has been acted on.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct StalenessConfig {
+    #[serde(rename = "maxAge")]
+    pub max_age: f64,
+
+    #[serde(default = "default_sinks")]
+    pub notify: Vec<NotificationSink>,
+}
+
+fn default_sinks() -> Vec<NotificationSink> {
+    vec![NotificationSink::Log]
+}
+
+pub struct StalenessGuard {
+    config: StalenessConfig,
+
+    // Instruments alerted on since their price was last fresh
+    stale: HashSet<String>,
+}
+
+impl StalenessGuard {
+    pub fn new(config: StalenessConfig) -> Self {
+        StalenessGuard {
+            config,
+            stale: HashSet::new(),
+        }
+    }
+
+    // Why an instrument whose latest price is from `price_time` can't be ordered at `now`, if it
+    // can't, alerting the first time
+    pub fn check(&mut self, now: u64, instrument: &str, price_time: u64) -> Option<String> {
+        let age = now.saturating_sub(price_time) as f64 / 1000.0;
+        if age <= self.config.max_age {
+            self.stale.remove(instrument);
+            return None;
+        }
+        let reason = format!(
+            "latest price {:.1}s old, over {}s",
+            age, self.config.max_age
+        );
+        if self.stale.insert(instrument.to_string()) {
+            let notification = Notification {
+                time: format_time(now),
+                source: "staleness".to_string(),
+                instrument: Some(instrument.to_string()),
+                text: format!("Not ordering {}: {}", instrument, reason),
+            };
+            notify(&self.config.notify, &notification);
+        }
+        Some(reason)
+    }
+}
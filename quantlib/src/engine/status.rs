@@ -0,0 +1,51 @@
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+
+use serde::Serialize;
+
+use super::{OpenPosition, WarmUpStatus};
+use crate::models::PassiveStats;
+
+// What a running trader is doing, published by the engine for the gRPC service to answer status
+// queries from without waiting on the trading loop
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct EngineStatus {
+    // When it was published, empty until the first price
+    pub time: String,
+    pub standby: bool,
+    pub paused: bool,
+    pub positions: Vec<OpenPosition>,
+
+    #[serde(rename = "haltedStrategies")]
+    pub halted_strategies: Vec<String>,
+
+    #[serde(rename = "disabledInstruments")]
+    pub disabled_instruments: Vec<String>,
+
+    // None without drawdown scaling
+    #[serde(rename = "drawdownScale")]
+    pub drawdown_scale: Option<f64>,
+
+    // Score of the price connection from 1 (healthy) to 0, see ConnectionHealthConfig
+    #[serde(rename = "connectionHealth")]
+    pub connection_health: f64,
+
+    // Why the kill switch tripped, None while it hasn't
+    #[serde(rename = "killSwitch")]
+    pub kill_switch: Option<String>,
+
+    // How posted orders went, None without passive execution
+    pub passive: Option<PassiveStats>,
+
+    // Prices seen by each instrument's strategies since a cold start, None without one
+    #[serde(rename = "warmUp")]
+    pub warm_up: Option<WarmUpStatus>,
+
+    // Prices of each instrument the strategy workers or the stream relay dropped while behind
+    // since the start
+    #[serde(rename = "droppedTicks")]
+    pub dropped_ticks: BTreeMap<String, u64>,
+}
+
+// Shared between the engine publishing its status and the gRPC service answering queries
+pub type SharedEngineStatus = Arc<Mutex<EngineStatus>>;
@@ -0,0 +1,95 @@
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+// Warms the strategies up on recent OANDA candles when the trader starts with no checkpoint to
+// restore, e.g. on a new host, "bootstrap" in the trading config, e.g.
+// {"candles": 500, "granularity": "M1"}. The close of each of the last `candles` complete
+// candles of every instrument is run through the strategies before the first streamed price,
+// and an instrument isn't ordered until its strategies have seen `candles` prices between the
+// candles and the stream, so one whose candles couldn't be fetched warms up on live prices
+// instead. Only OANDA accounts have candles to warm up on, through a broker backend every
+// instrument warms up on live prices.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct BootstrapConfig {
+    #[serde(default = "default_candles")]
+    pub candles: u64,
+
+    // One of OANDA's candle granularities, e.g. "S5", "M1" or "H1"
+    #[serde(default = "default_granularity")]
+    pub granularity: String,
+}
+
+fn default_candles() -> u64 {
+    500
+}
+
+fn default_granularity() -> String {
+    "M1".to_string()
+}
+
+// How many prices each instrument's strategies have seen since a cold start
+pub struct WarmUp {
+    required: u64,
+    seen: BTreeMap<String, u64>,
+}
+
+impl WarmUp {
+    pub fn new(required: u64, instruments: &[String]) -> Self {
+        WarmUp {
+            required,
+            seen: instruments
+                .iter()
+                .map(|instrument| (instrument.clone(), 0))
+                .collect(),
+        }
+    }
+
+    // Count a price the strategies have seen, returning whether it finished the instrument's
+    // warm up
+    pub fn record(&mut self, instrument: &str) -> bool {
+        let seen = self.seen.entry(instrument.to_string()).or_default();
+        if *seen >= self.required {
+            return false;
+        }
+        *seen += 1;
+        *seen == self.required
+    }
+
+    pub fn is_ready(&self, instrument: &str) -> bool {
+        self.seen
+            .get(instrument)
+            .is_some_and(|seen| *seen >= self.required)
+    }
+
+    pub fn status(&self) -> WarmUpStatus {
+        WarmUpStatus {
+            ready: self.seen.values().all(|seen| *seen >= self.required),
+            required: self.required,
+            instruments: self.seen.clone(),
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct WarmUpStatus {
+    // Whether every instrument has warmed up
+    pub ready: bool,
+
+    // Prices each instrument's strategies must see before it's ordered
+    pub required: u64,
+
+    // Prices each has seen, from candles and the stream
+    pub instruments: BTreeMap<String, u64>,
+}
+
+impl WarmUpStatus {
+    // The instruments still warming up
+    pub fn warming(&self) -> Vec<String> {
+        self.instruments
+            .iter()
+            .filter(|(_, seen)| **seen < self.required)
+            .map(|(instrument, _)| instrument.clone())
+            .collect()
+    }
+}
@@ -0,0 +1,169 @@
+use std::collections::BTreeSet;
+
+use serde::{Deserialize, Serialize};
+
+use crate::calendar;
+use crate::journal::{DecisionOutcome, JournalEntry};
+use crate::oanda::helpers::parse_time;
+
+// How long after failing to close a position for the weekend it's tried again
+const RETRY_INTERVAL: u64 = 60_000;
+
+// Closing positions over the weekend, "weekend" in the trading config, e.g.
+// {"minutesBefore": 90, "instruments": ["EUR_USD", "GBP_USD"], "reenter": true,
+// "reenterAfter": 60}. `minutesBefore` the Friday close (22:00 UTC, see calendar::week_close)
+// every position in the listed instruments, or in every instrument if none are listed, is closed
+// so none is held through the weekend gap, trying again every minute until the close for any that
+// couldn't be. From then until `reenterAfter` minutes past the Sunday open signals can close
+// positions but not open them, and the instruments whose signals were held back are traded to
+// the strategies' forecasts as of their first price after that. With `reenter`, so are the
+// positions closed, rather than waiting for a new signal. The default of 90 minutes also flattens in time while
+// New York is on summer time and the market closes an hour earlier.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct WeekendConfig {
+    #[serde(default = "default_minutes_before")]
+    #[serde(rename = "minutesBefore")]
+    pub minutes_before: u64,
+
+    #[serde(default)]
+    pub instruments: Vec<String>,
+
+    #[serde(default)]
+    pub reenter: bool,
+
+    #[serde(default = "default_reenter_after")]
+    #[serde(rename = "reenterAfter")]
+    pub reenter_after: u64,
+}
+
+fn default_minutes_before() -> u64 {
+    90
+}
+
+fn default_reenter_after() -> u64 {
+    60
+}
+
+// Where the engine is in the weekend policy: whether this week's positions have been closed,
+// when to try again for those that couldn't be, and which instruments are still to be traded
+// again at the reopen
+pub struct Weekend {
+    config: WeekendConfig,
+    flattened: Option<(i32, u32)>,
+    retry_at: Option<u64>,
+    reentering: BTreeSet<String>,
+}
+
+impl Weekend {
+    pub fn new(config: WeekendConfig) -> Self {
+        Weekend {
+            config,
+            flattened: None,
+            retry_at: None,
+            reentering: BTreeSet::new(),
+        }
+    }
+
+    // Carry on from the weekend flattens and held back signals in the journal, without
+    // re-entering instruments that have traded since
+    pub fn replay(&mut self, entries: &[JournalEntry]) -> Result<(), String> {
+        let mut week = None;
+        for entry in entries {
+            match entry {
+                JournalEntry::WeekendFlatten {
+                    time,
+                    instruments,
+                    failed,
+                } => {
+                    // Only the last weekend's are still to be opened again
+                    let (time, _) = parse_time(time)?;
+                    if week.replace(calendar::trading_week(time))
+                        != Some(calendar::trading_week(time))
+                    {
+                        self.reentering.clear();
+                    }
+                    self.flattened(time, instruments, failed);
+                }
+                JournalEntry::Decision {
+                    time,
+                    instrument,
+                    checks,
+                    outcome: DecisionOutcome::Suppressed { .. },
+                    ..
+                } if checks
+                    .iter()
+                    .any(|check| check.check == "weekend" && !check.passed) =>
+                {
+                    let (time, _) = parse_time(time)?;
+                    self.suppressed(time, instrument);
+                }
+                JournalEntry::Order { instrument, .. } => {
+                    self.reentering.remove(instrument);
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    pub fn applies(&self, instrument: &str) -> bool {
+        self.config.instruments.is_empty()
+            || self.config.instruments.iter().any(|i| i == instrument)
+    }
+
+    // Whether it's time to close positions for the weekend, and they haven't all been this week
+    pub fn flatten_due(&self, time: u64) -> bool {
+        let (year, week) = calendar::trading_week(time);
+        self.flattened != Some((year, week))
+            && time >= self.flatten_time(year, week)
+            && self.retry_at.is_none_or(|retry_at| time >= retry_at)
+    }
+
+    // Note the instruments closed, to be opened again if configured, and the ones that failed to
+    // be tried again shortly. Once none are left, or the market has closed, the week is done.
+    pub fn flattened(&mut self, time: u64, instruments: &[String], failed: &[String]) {
+        if self.config.reenter {
+            self.reentering.extend(instruments.iter().cloned());
+        }
+        let (year, week) = calendar::trading_week(time);
+        if failed.is_empty() || time >= calendar::week_close(year, week) {
+            self.flattened = Some((year, week));
+            self.retry_at = None;
+        } else {
+            self.retry_at = Some(time + RETRY_INTERVAL);
+        }
+    }
+
+    // Note a signal held back while the instrument is closed, to be acted on at the reopen
+    pub fn suppressed(&mut self, time: u64, instrument: &str) {
+        if self.closed(time, instrument) {
+            self.reentering.insert(instrument.to_string());
+        }
+    }
+
+    // Whether signals can only close positions in the instrument at this time
+    pub fn closed(&self, time: u64, instrument: &str) -> bool {
+        if !self.applies(instrument) {
+            return false;
+        }
+        let (year, week) = calendar::trading_week(time);
+        time >= self.flatten_time(year, week) || time < self.reopen_time(year, week)
+    }
+
+    // Whether this price is the one to trade the instrument to its standing forecast again on,
+    // after its position was closed or its signals held back. Only true once.
+    pub fn reentry_due(&mut self, time: u64, instrument: &str) -> bool {
+        if self.closed(time, instrument) {
+            return false;
+        }
+        self.reentering.remove(instrument)
+    }
+
+    fn flatten_time(&self, year: i32, week: u32) -> u64 {
+        calendar::week_close(year, week).saturating_sub(self.config.minutes_before * 60_000)
+    }
+
+    fn reopen_time(&self, year: i32, week: u32) -> u64 {
+        calendar::week_open(year, week) + self.config.reenter_after * 60_000
+    }
+}
@@ -0,0 +1,69 @@
+use std::error::Error;
+use std::fmt;
+
+// An error with what was being done when it happened, e.g. the file and byte offset being read or
+// the endpoint being called. Contexts wrap each other as an error is passed up, and the error is
+// displayed as the whole chain on one line:
+// "Backtest week 12: Reading EUR_USD.bin at offset 123456: Unsupported tick file version 7"
+// The original error stays reachable through source(), so it can still be found with `find`.
+#[derive(Debug)]
+pub struct ContextError {
+    pub context: String,
+    pub source: Box<dyn Error>,
+}
+
+impl fmt::Display for ContextError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.context, self.source)
+    }
+}
+
+impl Error for ContextError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        Some(self.source.as_ref())
+    }
+}
+
+// Adds context to the error of a result, converting it to the boxed errors used throughout
+pub trait Context<T> {
+    fn context<C: fmt::Display>(self, context: C) -> Result<T, Box<dyn Error>>;
+
+    // Like context, only building it if there's an error
+    fn with_context<C: fmt::Display, F: FnOnce() -> C>(
+        self,
+        context: F,
+    ) -> Result<T, Box<dyn Error>>;
+}
+
+impl<T, E: Into<Box<dyn Error>>> Context<T> for Result<T, E> {
+    fn context<C: fmt::Display>(self, context: C) -> Result<T, Box<dyn Error>> {
+        self.map_err(|e| wrap(e, context))
+    }
+
+    fn with_context<C: fmt::Display, F: FnOnce() -> C>(
+        self,
+        context: F,
+    ) -> Result<T, Box<dyn Error>> {
+        self.map_err(|e| wrap(e, context()))
+    }
+}
+
+pub fn wrap<E: Into<Box<dyn Error>>, C: fmt::Display>(error: E, context: C) -> Box<dyn Error> {
+    Box::new(ContextError {
+        context: context.to_string(),
+        source: error.into(),
+    })
+}
+
+// The first error of type T in an error's chain of sources, including the error itself. Errors
+// that have had context added no longer downcast directly to what caused them.
+pub fn find<'a, T: Error + 'static>(error: &'a (dyn Error + 'static)) -> Option<&'a T> {
+    let mut current = Some(error);
+    while let Some(error) = current {
+        if let Some(found) = error.downcast_ref::<T>() {
+            return Some(found);
+        }
+        current = error.source();
+    }
+    None
+}
@@ -0,0 +1,327 @@
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::catalog::Catalog;
+use crate::data::{encode_price, BinReader, TimePrecision};
+use crate::errors::Context;
+
+// Version of the export manifest's format
+pub const EXPORT_MANIFEST_VERSION: u32 = 1;
+
+// What an instrument's data licence allows it to be shared as
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum Licence {
+    // As it was collected, under its own name
+    Share,
+
+    // Only under an alias, with its prices normalized
+    Anonymize,
+
+    // Not at all
+    Withhold,
+}
+
+// Which instruments an export may include and how, e.g.
+// {"default": "anonymize", "instruments": {"EUR_USD": "share", "XAU_USD": "withhold"}}
+// Instruments not listed get the default licence, which is to anonymize them. Once any
+// instrument is anonymized the times of every file are made relative to the export's first
+// tick, as absolute times would let an alias be matched back to its instrument's history.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ExportPolicy {
+    #[serde(default = "default_licence")]
+    pub default: Licence,
+
+    #[serde(default)]
+    pub instruments: BTreeMap<String, Licence>,
+}
+
+fn default_licence() -> Licence {
+    Licence::Anonymize
+}
+
+impl Default for ExportPolicy {
+    fn default() -> Self {
+        ExportPolicy {
+            default: default_licence(),
+            instruments: BTreeMap::new(),
+        }
+    }
+}
+
+impl ExportPolicy {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, Box<dyn std::error::Error>> {
+        let path = path.as_ref();
+        let reader = BufReader::new(
+            File::open(path).with_context(|| format!("Opening {}", path.display()))?,
+        );
+        serde_json::from_reader(reader).with_context(|| format!("Parsing {}", path.display()))
+    }
+
+    pub fn licence(&self, instrument: &str) -> Licence {
+        self.instruments
+            .get(instrument)
+            .copied()
+            .unwrap_or(self.default)
+    }
+}
+
+// A change made to the data on its way out
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "camelCase")]
+pub enum Transform {
+    // The instrument is named by an alias, e.g. "series_03"
+    Alias,
+
+    // Times are milliseconds since the export's first tick rather than since the epoch
+    RelativeTime,
+
+    // Bids and asks are divided by the file's first mid price, so it starts at 1
+    NormalizedPrices,
+}
+
+impl Transform {
+    pub fn description(&self) -> &'static str {
+        match self {
+            Transform::Alias => "instrument replaced by an alias",
+            Transform::RelativeTime => "times relative to the first tick of the export",
+            Transform::NormalizedPrices => "prices divided by the first mid price of the file",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct AppliedTransform {
+    pub transform: Transform,
+    pub description: String,
+}
+
+// One exported tick file, in the collector's binary format
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ExportedFile {
+    // As named in the export, an alias if the instrument was anonymized
+    pub instrument: String,
+
+    // Relative to the export directory
+    pub path: PathBuf,
+
+    pub ticks: u64,
+
+    // Times of the first and last ticks, after any transform
+    pub first: u64,
+    pub last: u64,
+
+    pub transforms: Vec<Transform>,
+
+    // SHA-256 of the file, hex encoded
+    pub checksum: String,
+}
+
+// Shared with the data as manifest.json, describing every file and what was done to it.
+// Withheld instruments are only counted, not named.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ExportManifest {
+    pub version: u32,
+    pub created: String,
+
+    #[serde(rename = "timePrecision")]
+    pub time_precision: TimePrecision,
+
+    pub transforms: Vec<AppliedTransform>,
+    pub files: Vec<ExportedFile>,
+    pub withheld: usize,
+}
+
+// Kept by whoever made the export and never shared with it: what each alias stands for and what
+// was taken off times and divided into prices, to map results on the export back to the data
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct ExportKey {
+    pub aliases: BTreeMap<String, String>,
+
+    #[serde(rename = "timeOrigin")]
+    pub time_origin: Option<u64>,
+
+    pub scales: BTreeMap<String, f64>,
+}
+
+impl ExportKey {
+    // Kept beside the export directory rather than in it, e.g. shared/ has shared.key.json
+    pub fn path(output: &Path) -> PathBuf {
+        let mut name = output.as_os_str().to_owned();
+        name.push(".key.json");
+        PathBuf::from(name)
+    }
+}
+
+// Export an archive's weeks to `output`, an instrument to a file, as its licence in the policy
+// allows. The directory must not exist yet or be empty. Writes manifest.json into it and the
+// key beside it, see ExportKey::path.
+pub fn export_archive(
+    archive: &Path,
+    catalog: &Catalog,
+    output: &Path,
+    policy: &ExportPolicy,
+) -> Result<(ExportManifest, ExportKey), Box<dyn std::error::Error>> {
+    if output.exists() && std::fs::read_dir(output)?.next().is_some() {
+        return Err(format!("{} already has files in it", output.display()).into());
+    }
+    std::fs::create_dir_all(output)?;
+
+    let mut instruments: Vec<&str> = catalog
+        .entries
+        .iter()
+        .map(|entry| entry.instrument.as_str())
+        .collect();
+    instruments.dedup();
+
+    let (exported, withheld): (Vec<&str>, Vec<&str>) = instruments
+        .into_iter()
+        .partition(|instrument| policy.licence(instrument) != Licence::Withhold);
+    for instrument in &withheld {
+        log::info!("Withholding {} from the export", instrument);
+    }
+
+    let anonymized: Vec<&str> = exported
+        .iter()
+        .copied()
+        .filter(|instrument| policy.licence(instrument) == Licence::Anonymize)
+        .collect();
+    let aliases = assign_aliases(&anonymized);
+
+    let mut key = ExportKey::default();
+    if !anonymized.is_empty() {
+        key.time_origin = catalog
+            .entries
+            .iter()
+            .filter(|entry| exported.contains(&entry.instrument.as_str()))
+            .map(|entry| entry.first)
+            .min();
+    }
+
+    let precision = TimePrecision::default();
+    let mut files = Vec::new();
+    for instrument in exported {
+        let alias = aliases.get(instrument);
+        let name = alias.map_or(instrument, String::as_str);
+        let paths = catalog
+            .instrument(instrument)
+            .into_iter()
+            .map(|entry| archive.join(&entry.path))
+            .collect();
+        let prices = BinReader::open_sequence(paths, instrument)?;
+
+        let mut transforms = Vec::new();
+        if alias.is_some() {
+            transforms.push(Transform::Alias);
+            transforms.push(Transform::NormalizedPrices);
+            key.aliases.insert(name.to_string(), instrument.to_string());
+        }
+        if key.time_origin.is_some() {
+            transforms.push(Transform::RelativeTime);
+        }
+        transforms.sort();
+
+        let path = PathBuf::from(format!("{}.bin", name));
+        let mut writer = BufWriter::new(File::create(output.join(&path))?);
+        let mut hasher = Sha256::new();
+        if let Some(header) = precision.header() {
+            writer.write_all(&header)?;
+            hasher.update(header);
+        }
+        let mut file = ExportedFile {
+            instrument: name.to_string(),
+            path,
+            ticks: 0,
+            first: 0,
+            last: 0,
+            transforms,
+            checksum: String::new(),
+        };
+        let mut scale = None;
+        for mut price in prices {
+            if let Some(origin) = key.time_origin {
+                price.time = price.time.saturating_sub(origin);
+            }
+            if alias.is_some() {
+                let scale = *scale.get_or_insert((price.bid as f64 + price.ask as f64) / 2.0);
+                price.bid = (price.bid as f64 / scale) as f32;
+                price.ask = (price.ask as f64 / scale) as f32;
+            }
+            let record = encode_price(&price, precision);
+            writer.write_all(&record)?;
+            hasher.update(record);
+            if file.ticks == 0 {
+                file.first = price.time;
+            }
+            file.last = price.time;
+            file.ticks += 1;
+        }
+        writer.flush()?;
+        if let Some(scale) = scale {
+            key.scales.insert(name.to_string(), scale);
+        }
+        file.checksum = hex::encode(hasher.finalize());
+        log::info!(
+            "Exported {} ticks of {} as {}",
+            file.ticks,
+            instrument,
+            name
+        );
+        files.push(file);
+    }
+
+    // In alias order, as the instruments' order would give their aliases away
+    files.sort_by(|a, b| a.instrument.cmp(&b.instrument));
+    let mut applied: Vec<Transform> = files
+        .iter()
+        .flat_map(|file| file.transforms.iter().copied())
+        .collect();
+    applied.sort();
+    applied.dedup();
+    let manifest = ExportManifest {
+        version: EXPORT_MANIFEST_VERSION,
+        created: chrono::Utc::now().to_rfc3339(),
+        time_precision: precision,
+        transforms: applied
+            .into_iter()
+            .map(|transform| AppliedTransform {
+                transform,
+                description: transform.description().to_string(),
+            })
+            .collect(),
+        files,
+        withheld: withheld.len(),
+    };
+    std::fs::write(
+        output.join("manifest.json"),
+        serde_json::to_string_pretty(&manifest)?,
+    )?;
+    std::fs::write(ExportKey::path(output), serde_json::to_string_pretty(&key)?)?;
+    Ok((manifest, key))
+}
+
+// Aliases numbered in an order that changes with every export, so neither the numbers nor the
+// order of the files give away which instrument is which
+fn assign_aliases(instruments: &[&str]) -> BTreeMap<String, String> {
+    let salt: u64 = rand::random();
+    let mut shuffled: Vec<(String, &str)> = instruments
+        .iter()
+        .map(|instrument| {
+            let digest = Sha256::digest(format!("{}{}", salt, instrument));
+            (hex::encode(digest), *instrument)
+        })
+        .collect();
+    shuffled.sort();
+    shuffled
+        .into_iter()
+        .enumerate()
+        .map(|(index, (_, instrument))| {
+            (instrument.to_string(), format!("series_{:02}", index + 1))
+        })
+        .collect()
+}
@@ -0,0 +1,97 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use crate::oanda::objects::Price;
+
+// Split an OANDA instrument name ("EUR_USD") into its base and quote currencies
+pub fn split_instrument(instrument: &str) -> Option<(&str, &str)> {
+    let mut parts = instrument.split('_');
+    match (parts.next(), parts.next(), parts.next()) {
+        (Some(base), Some(quote), None) => Some((base, quote)),
+        _ => None,
+    }
+}
+
+// Converts amounts between currencies using the latest mid rates seen on the price stream.
+// Currencies that aren't quoted against each other directly are converted through intermediate
+// currencies (e.g. NZD -> USD -> JPY), preferring the path with the fewest conversions.
+#[derive(Debug, Default)]
+pub struct Converter {
+    // rates[base][quote] = amount of quote currency per unit of base currency
+    rates: HashMap<String, HashMap<String, f64>>,
+}
+
+impl Converter {
+    pub fn new() -> Self {
+        Converter {
+            rates: HashMap::new(),
+        }
+    }
+
+    // Record the latest mid rate for the price's instrument
+    pub fn update(&mut self, price: &Price) {
+        let (base, quote) = match split_instrument(&price.instrument) {
+            Some(currencies) => currencies,
+            None => return,
+        };
+
+        let mid = (price.bid as f64 + price.ask as f64) / 2.0;
+        if mid <= 0.0 {
+            return;
+        }
+
+        self.rates
+            .entry(base.to_string())
+            .or_default()
+            .insert(quote.to_string(), mid);
+        self.rates
+            .entry(quote.to_string())
+            .or_default()
+            .insert(base.to_string(), 1.0 / mid);
+    }
+
+    pub fn update_all(&mut self, prices: &[Price]) {
+        for price in prices {
+            self.update(price);
+        }
+    }
+
+    // Amount of `to` currency per unit of `from` currency, if the two are connected
+    pub fn rate(&self, from: &str, to: &str) -> Option<f64> {
+        if from == to {
+            return Some(1.0);
+        }
+
+        // Breadth first search, so the first time we reach `to` it is via the fewest conversions
+        let mut visited = HashSet::new();
+        let mut queue = VecDeque::new();
+        visited.insert(from);
+        queue.push_back((from, 1.0));
+
+        while let Some((currency, rate)) = queue.pop_front() {
+            let neighbours = match self.rates.get(currency) {
+                Some(neighbours) => neighbours,
+                None => continue,
+            };
+
+            for (next, next_rate) in neighbours {
+                if next == to {
+                    return Some(rate * next_rate);
+                }
+                if visited.insert(next.as_str()) {
+                    queue.push_back((next.as_str(), rate * next_rate));
+                }
+            }
+        }
+
+        None
+    }
+
+    pub fn convert(&self, amount: f64, from: &str, to: &str) -> Option<f64> {
+        self.rate(from, to).map(|rate| amount * rate)
+    }
+
+    // Currencies for which at least one rate has been seen
+    pub fn currencies(&self) -> Vec<&str> {
+        self.rates.keys().map(|c| c.as_str()).collect()
+    }
+}
@@ -0,0 +1,499 @@
+use std::collections::HashMap;
+use std::pin::Pin;
+use std::sync::{mpsc, Arc};
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{oneshot, Semaphore};
+use tokio_native_tls::{native_tls, TlsAcceptor, TlsStream};
+use tokio_stream::wrappers::{ReceiverStream, TcpListenerStream};
+use tonic::transport::server::Connected;
+use tonic::{Request, Response, Status};
+
+use crate::backtest::{BacktestConfig, Backtester};
+use crate::control::{ControlCommand, GrpcConfig, GrpcTls};
+use crate::engine::{SharedDecisionHistory, SharedEngineStatus};
+use crate::errors::Context as _;
+use crate::journal::{DecisionOutcome, JournalEntry};
+use crate::secrets;
+
+// Server and client stubs of the Engine service, generated by build.rs
+include!(concat!(env!("OUT_DIR"), "/quantlib.Engine.rs"));
+
+use engine_server::{Engine, EngineServer};
+
+// The messages of proto/engine.proto, by hand as the stubs are built without protoc. Field tags
+// must match the proto.
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct StatusRequest {}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Position {
+    #[prost(string, tag = "1")]
+    pub instrument: String,
+    #[prost(double, tag = "2")]
+    pub units: f64,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct StatusReply {
+    #[prost(string, tag = "1")]
+    pub time: String,
+    #[prost(bool, tag = "2")]
+    pub standby: bool,
+    #[prost(bool, tag = "3")]
+    pub paused: bool,
+    #[prost(message, repeated, tag = "4")]
+    pub positions: Vec<Position>,
+    #[prost(string, repeated, tag = "5")]
+    pub halted_strategies: Vec<String>,
+    #[prost(string, repeated, tag = "6")]
+    pub disabled_instruments: Vec<String>,
+    #[prost(double, optional, tag = "7")]
+    pub drawdown_scale: Option<f64>,
+    #[prost(double, tag = "8")]
+    pub connection_health: f64,
+    #[prost(string, optional, tag = "9")]
+    pub kill_switch: Option<String>,
+    #[prost(string, repeated, tag = "10")]
+    pub warming_up: Vec<String>,
+    #[prost(map = "string, uint64", tag = "11")]
+    pub dropped_ticks: HashMap<String, u64>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct PositionsRequest {}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct PositionsReply {
+    #[prost(message, repeated, tag = "1")]
+    pub positions: Vec<Position>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct DecisionsRequest {
+    #[prost(string, optional, tag = "1")]
+    pub instrument: Option<String>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Decision {
+    #[prost(string, tag = "1")]
+    pub time: String,
+    #[prost(string, tag = "2")]
+    pub instrument: String,
+    #[prost(double, tag = "3")]
+    pub forecast: f64,
+    #[prost(string, repeated, tag = "4")]
+    pub strategies: Vec<String>,
+    #[prost(bool, tag = "5")]
+    pub ordered: bool,
+    #[prost(double, tag = "6")]
+    pub units: f64,
+    #[prost(string, tag = "7")]
+    pub reason: String,
+    #[prost(string, tag = "8")]
+    pub json: String,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct DecisionsReply {
+    #[prost(message, repeated, tag = "1")]
+    pub decisions: Vec<Decision>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct CommandRequest {
+    #[prost(string, tag = "1")]
+    pub command: String,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct CommandReply {}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct BacktestRequest {
+    #[prost(string, tag = "1")]
+    pub config: String,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct BacktestReply {
+    #[prost(uint64, tag = "1")]
+    pub ticks: u64,
+    #[prost(uint64, tag = "2")]
+    pub fills: u64,
+    #[prost(double, tag = "3")]
+    pub final_nav: f64,
+    #[prost(double, tag = "4")]
+    pub total_return: f64,
+    #[prost(double, tag = "5")]
+    pub max_drawdown: f64,
+    #[prost(double, optional, tag = "6")]
+    pub sharpe_ratio: Option<f64>,
+    #[prost(uint64, tag = "7")]
+    pub trades: u64,
+    #[prost(string, tag = "8")]
+    pub metrics: String,
+}
+
+// Serves the Engine service on a tokio runtime's worker threads, to be polled by the trading
+// loop for commands like the control socket is. The server stops when this is dropped.
+pub struct GrpcServer {
+    receiver: mpsc::Receiver<ControlCommand>,
+    _shutdown: oneshot::Sender<()>,
+}
+
+impl GrpcServer {
+    // Must be called from within a tokio runtime
+    pub fn start(
+        config: &GrpcConfig,
+        history: SharedDecisionHistory,
+        status: SharedEngineStatus,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let runtime = tokio::runtime::Handle::try_current()
+            .map_err(|_| "The gRPC service needs a tokio runtime")?;
+        let mut token = config.token.clone();
+        if secrets::is_encrypted(&token) {
+            token = secrets::decrypt(&token)?;
+        }
+        secrets::register(&token);
+
+        // Bound here so that an address in use fails at startup rather than in the background
+        let listener = std::net::TcpListener::bind(&config.address)?;
+        if config.tls.is_none() && !listener.local_addr()?.ip().is_loopback() {
+            return Err(format!(
+                "gRPC on {} needs \"tls\", only loopback addresses are served in plaintext",
+                config.address
+            )
+            .into());
+        }
+        let acceptor = config.tls.as_ref().map(tls_acceptor).transpose()?;
+        listener.set_nonblocking(true)?;
+        let listener = {
+            let _guard = runtime.enter();
+            TcpListener::from_std(listener)?
+        };
+        log::info!(
+            "Serving gRPC on {}{}",
+            config.address,
+            if acceptor.is_some() { " over TLS" } else { "" }
+        );
+
+        let (sender, receiver) = mpsc::channel();
+        let service = EngineService {
+            history,
+            status,
+            commands: sender,
+            backtests: Arc::new(Semaphore::new(1)),
+        };
+        let expected = format!("Bearer {}", token);
+        // Status is what tonic's interceptors return
+        #[allow(clippy::result_large_err)]
+        let authenticate = move |request: Request<()>| {
+            let authorized = request
+                .metadata()
+                .get("authorization")
+                .is_some_and(|value| same_token(value.as_bytes(), expected.as_bytes()));
+            match authorized {
+                true => Ok(request),
+                false => Err(Status::unauthenticated("Invalid or missing token")),
+            }
+        };
+
+        let (shutdown, stopped) = oneshot::channel::<()>();
+        runtime.spawn(async move {
+            let server = tonic::transport::Server::builder()
+                .add_service(EngineServer::with_interceptor(service, authenticate));
+            let stopped = async {
+                let _ = stopped.await;
+            };
+            let result = match acceptor {
+                Some(acceptor) => {
+                    server
+                        .serve_with_incoming_shutdown(tls_connections(listener, acceptor), stopped)
+                        .await
+                }
+                None => {
+                    server
+                        .serve_with_incoming_shutdown(TcpListenerStream::new(listener), stopped)
+                        .await
+                }
+            };
+            if let Err(e) = result {
+                log::error!("gRPC server failed: {}", e);
+            }
+        });
+
+        Ok(GrpcServer {
+            receiver,
+            _shutdown: shutdown,
+        })
+    }
+
+    // Commands received since the last call
+    pub fn commands(&self) -> Vec<ControlCommand> {
+        self.receiver.try_iter().collect()
+    }
+}
+
+// How long a client has to finish its TLS handshake
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+fn tls_acceptor(tls: &GrpcTls) -> Result<TlsAcceptor, Box<dyn std::error::Error>> {
+    let cert = std::fs::read(&tls.cert)
+        .with_context(|| format!("Reading the gRPC certificate {}", tls.cert.display()))?;
+    let key = std::fs::read(&tls.key)
+        .with_context(|| format!("Reading the gRPC key {}", tls.key.display()))?;
+    let identity = native_tls::Identity::from_pkcs8(&cert, &key)?;
+    Ok(TlsAcceptor::from(native_tls::TlsAcceptor::new(identity)?))
+}
+
+// Accepted connections once their TLS handshake is done, each in a task of its own so that a
+// slow client can't hold up the others. Connections that fail it are dropped.
+fn tls_connections(
+    listener: TcpListener,
+    acceptor: TlsAcceptor,
+) -> ReceiverStream<Result<TlsConnection, std::io::Error>> {
+    let (sender, receiver) = tokio::sync::mpsc::channel(16);
+    tokio::spawn(async move {
+        loop {
+            let accepted = tokio::select! {
+                accepted = listener.accept() => accepted,
+                _ = sender.closed() => return,
+            };
+            let (tcp, address) = match accepted {
+                Ok(accepted) => accepted,
+                Err(e) => {
+                    log::warn!("gRPC failed to accept a connection: {}", e);
+                    tokio::time::sleep(Duration::from_millis(100)).await;
+                    continue;
+                }
+            };
+            let acceptor = acceptor.clone();
+            let sender = sender.clone();
+            tokio::spawn(async move {
+                match tokio::time::timeout(HANDSHAKE_TIMEOUT, acceptor.accept(tcp)).await {
+                    Ok(Ok(tls)) => {
+                        let _ = sender.send(Ok(TlsConnection(tls))).await;
+                    }
+                    Ok(Err(e)) => log::warn!("gRPC TLS handshake with {} failed: {}", address, e),
+                    Err(_) => log::warn!("gRPC TLS handshake with {} timed out", address),
+                }
+            });
+        }
+    });
+    ReceiverStream::new(receiver)
+}
+
+// A TLS connection tonic can serve on
+struct TlsConnection(TlsStream<TcpStream>);
+
+impl Connected for TlsConnection {
+    type ConnectInfo = ();
+
+    fn connect_info(&self) -> Self::ConnectInfo {}
+}
+
+impl AsyncRead for TlsConnection {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.0).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for TlsConnection {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        Pin::new(&mut self.0).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.0).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.0).poll_shutdown(cx)
+    }
+}
+
+// Compared in constant time, so that how long a refusal takes doesn't give away how much of the
+// token was right
+fn same_token(given: &[u8], expected: &[u8]) -> bool {
+    given.len() == expected.len()
+        && given
+            .iter()
+            .zip(expected)
+            .fold(0u8, |difference, (a, b)| difference | (a ^ b))
+            == 0
+}
+
+struct EngineService {
+    history: SharedDecisionHistory,
+    status: SharedEngineStatus,
+    commands: mpsc::Sender<ControlCommand>,
+
+    // Backtests run one at a time, so they can't starve the trader of CPU
+    backtests: Arc<Semaphore>,
+}
+
+#[tonic::async_trait]
+impl Engine for EngineService {
+    async fn get_status(
+        &self,
+        _request: Request<StatusRequest>,
+    ) -> Result<Response<StatusReply>, Status> {
+        let status = self
+            .status
+            .lock()
+            .map_err(|_| Status::internal("Status lock poisoned"))?
+            .clone();
+        Ok(Response::new(StatusReply {
+            time: status.time,
+            standby: status.standby,
+            paused: status.paused,
+            positions: status.positions.into_iter().map(position).collect(),
+            halted_strategies: status.halted_strategies,
+            disabled_instruments: status.disabled_instruments,
+            drawdown_scale: status.drawdown_scale,
+            connection_health: status.connection_health,
+            kill_switch: status.kill_switch,
+            warming_up: status
+                .warm_up
+                .map(|warm_up| warm_up.warming())
+                .unwrap_or_default(),
+            dropped_ticks: status.dropped_ticks.into_iter().collect(),
+        }))
+    }
+
+    async fn get_positions(
+        &self,
+        _request: Request<PositionsRequest>,
+    ) -> Result<Response<PositionsReply>, Status> {
+        let status = self
+            .status
+            .lock()
+            .map_err(|_| Status::internal("Status lock poisoned"))?;
+        Ok(Response::new(PositionsReply {
+            positions: status.positions.iter().cloned().map(position).collect(),
+        }))
+    }
+
+    async fn recent_decisions(
+        &self,
+        request: Request<DecisionsRequest>,
+    ) -> Result<Response<DecisionsReply>, Status> {
+        let instrument = request.into_inner().instrument;
+        let history = self
+            .history
+            .lock()
+            .map_err(|_| Status::internal("Decision history lock poisoned"))?;
+        let decisions = history
+            .recent(instrument.as_deref())
+            .into_iter()
+            .filter_map(decision)
+            .collect();
+        Ok(Response::new(DecisionsReply { decisions }))
+    }
+
+    async fn send_command(
+        &self,
+        request: Request<CommandRequest>,
+    ) -> Result<Response<CommandReply>, Status> {
+        let command = match ControlCommand::parse(&request.into_inner().command) {
+            // Answered by RecentDecisions
+            Ok(ControlCommand::Status(_)) => {
+                return Err(Status::invalid_argument("Use RecentDecisions for status"))
+            }
+            Ok(command) => command,
+            Err(e) => return Err(Status::invalid_argument(e)),
+        };
+        log::info!("Received gRPC command: {:?}", command);
+        self.commands
+            .send(command)
+            .map_err(|_| Status::unavailable("The trader is shutting down"))?;
+        Ok(Response::new(CommandReply {}))
+    }
+
+    async fn submit_backtest(
+        &self,
+        request: Request<BacktestRequest>,
+    ) -> Result<Response<BacktestReply>, Status> {
+        let config: BacktestConfig = serde_json::from_str(&request.into_inner().config)
+            .map_err(|e| Status::invalid_argument(format!("Invalid backtest config: {}", e)))?;
+        let _permit = self
+            .backtests
+            .clone()
+            .try_acquire_owned()
+            .map_err(|_| Status::resource_exhausted("A backtest is already running"))?;
+
+        log::info!("Running backtest submitted over gRPC");
+        let report = tokio::task::spawn_blocking(move || {
+            let run = || -> Result<_, Box<dyn std::error::Error>> {
+                let _claim = config.claim_data("grpc")?;
+                let prices = config.open_prices()?;
+                Backtester::new(config)?.run(prices)
+            };
+            run().map_err(|e| e.to_string())
+        })
+        .await
+        .map_err(|e| Status::internal(e.to_string()))?
+        .map_err(Status::failed_precondition)?;
+
+        let metrics = &report.metrics;
+        Ok(Response::new(BacktestReply {
+            ticks: metrics.ticks,
+            fills: metrics.fills as u64,
+            final_nav: metrics.final_nav,
+            total_return: metrics.total_return,
+            max_drawdown: metrics.max_drawdown,
+            sharpe_ratio: metrics.sharpe_ratio,
+            trades: report.trades.len() as u64,
+            metrics: serde_json::to_string(metrics).map_err(|e| Status::internal(e.to_string()))?,
+        }))
+    }
+}
+
+fn position(position: crate::engine::OpenPosition) -> Position {
+    Position {
+        instrument: position.instrument,
+        units: position.units,
+    }
+}
+
+fn decision(entry: &JournalEntry) -> Option<Decision> {
+    let JournalEntry::Decision {
+        time,
+        instrument,
+        strategies,
+        forecast,
+        outcome,
+        ..
+    } = entry
+    else {
+        return None;
+    };
+    let (ordered, units, reason) = match outcome {
+        DecisionOutcome::Ordered { units, .. } => (true, *units, String::new()),
+        DecisionOutcome::Posted { units, price } => (true, *units, format!("posted at {}", price)),
+        DecisionOutcome::Suppressed { reason } => (false, 0.0, reason.clone()),
+    };
+    Some(Decision {
+        time: time.clone(),
+        instrument: instrument.clone(),
+        forecast: *forecast,
+        strategies: strategies.clone(),
+        ordered,
+        units,
+        reason,
+        json: serde_json::to_string(entry).ok()?,
+    })
+}
@@ -0,0 +1,416 @@
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+#[cfg(feature = "trading")]
+use crate::accounts::AggregateReport;
+use crate::conflation::DroppedPeriod;
+use crate::engine::{format_time, DrawdownChange, ExecutionFill, OpenPosition, SessionSummary};
+use crate::models::{
+    AccountCost, CircuitBreakerTrip, ConflictPolicy, CostKind, ExternalActivity, PassiveOutcome,
+    PassiveReport, StrategySnapshot, WorkingTarget,
+};
+
+// A record of what the trader did and why, one JSON object per line
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum JournalEntry {
+    // An order placed in response to a resolved signal
+    Order {
+        time: String,
+        instrument: String,
+        units: f64,
+
+        #[serde(default)]
+        price: Option<f64>,
+
+        #[serde(default)]
+        #[serde(rename = "transactionId")]
+        transaction_id: Option<String>,
+
+        forecast: f64,
+
+        // Profit realized by the fill, in the account currency
+        #[serde(default)]
+        pl: Option<f64>,
+
+        // The fill's costs, as reported by OANDA: the commission charged and the financing paid
+        // (negative) or received on the trades it closed
+        #[serde(default)]
+        commission: Option<f64>,
+
+        #[serde(default)]
+        financing: Option<f64>,
+
+        // How conflicts between strategies were resolved, and which strategies decided the order
+        policy: ConflictPolicy,
+        strategies: Vec<String>,
+
+        // The signal the order was placed on, also in the order's tags at the broker
+        #[serde(default)]
+        signal: Option<String>,
+    },
+
+    // A position change the trader didn't make, e.g. a manual close in the OANDA web UI
+    External {
+        time: String,
+        instrument: String,
+        units: f64,
+
+        #[serde(default)]
+        #[serde(rename = "transactionId")]
+        transaction_id: Option<String>,
+
+        #[serde(default)]
+        reason: Option<String>,
+    },
+
+    // Financing or commission charged outside our own fills, e.g. the daily financing of a held
+    // position, as the change to the balance in the account currency. It's attributed to the
+    // strategies behind the last order in the instrument.
+    Cost {
+        time: String,
+        instrument: String,
+        kind: CostKind,
+        amount: f64,
+
+        #[serde(default)]
+        #[serde(rename = "transactionId")]
+        transaction_id: Option<String>,
+
+        strategies: Vec<String>,
+    },
+
+    // A limit order posted for a signal that ended, after `waited` seconds, with `filled` of its
+    // units filled at its price. Its fills are journaled as orders, any market fallback too.
+    PassiveOrder {
+        time: String,
+        instrument: String,
+        units: f64,
+        price: f64,
+        filled: f64,
+        outcome: PassiveOutcome,
+        waited: f64,
+
+        #[serde(default)]
+        signal: Option<String>,
+    },
+
+    // Strategies halted for placing orders too quickly
+    CircuitBreaker {
+        time: u64,
+        instrument: String,
+        orders: usize,
+        strategies: Vec<String>,
+    },
+
+    // A strategy paused for breaching its loss limits, or by hand
+    Paused {
+        time: String,
+        strategy: String,
+        reason: String,
+    },
+
+    // A paused or halted strategy re-enabled by hand
+    Enabled {
+        time: String,
+        strategy: String,
+    },
+
+    // An instrument disabled by hand, its signals aren't executed until it's enabled again
+    InstrumentDisabled {
+        time: String,
+        instrument: String,
+    },
+
+    InstrumentEnabled {
+        time: String,
+        instrument: String,
+    },
+
+    // Position sizing scaled for a change in the account's drawdown, or a new peak at the same
+    // scale, equity in the account currency and the drawdown from its peak in percent
+    DrawdownScale {
+        time: String,
+        equity: f64,
+        peak: f64,
+        drawdown: f64,
+        scale: f64,
+    },
+
+    // The kill switch tripping, after which no orders are placed, and whether every position
+    // was closed
+    KillSwitch {
+        time: String,
+        reason: String,
+        flatten: bool,
+    },
+
+    // The totals of a UTC day of trading, journaled once the day is over
+    DailySummary {
+        time: String,
+
+        #[serde(flatten)]
+        summary: SessionSummary,
+    },
+
+    // The report on every account in the settings' "reporting" config as a UTC day ended
+    #[cfg(feature = "trading")]
+    AccountReport {
+        time: String,
+        report: AggregateReport,
+    },
+
+    // Positions closed ahead of the weekend close, and those that couldn't be and are tried
+    // again until the close
+    WeekendFlatten {
+        time: String,
+        instruments: Vec<String>,
+
+        #[serde(default)]
+        failed: Vec<String>,
+    },
+
+    // A signal whose validity ran out before its target was reached, after which nothing more
+    // was traded for it
+    SignalExpired {
+        time: String,
+        instrument: String,
+        forecast: f64,
+        strategies: Vec<String>,
+        reason: String,
+    },
+
+    // Prices the strategy workers or the stream relay dropped while behind, by instrument, see
+    // DroppedTicks
+    DroppedTicks {
+        time: String,
+
+        #[serde(flatten)]
+        period: DroppedPeriod,
+    },
+
+    // A trader on standby taking over execution, with the positions it took over
+    Activated {
+        time: String,
+        positions: Vec<OpenPosition>,
+    },
+
+    // Everything that went into a resolved signal and what became of it, so any trade (or
+    // missing trade) can be explained after the fact
+    Decision {
+        time: String,
+        instrument: String,
+
+        // The price the signal was resolved on
+        bid: f64,
+        ask: f64,
+
+        // Each strategy's forecast and model state, the policy combining them, the strategies
+        // that decided the outcome and the resulting target forecast
+        inputs: Vec<StrategySnapshot>,
+        policy: ConflictPolicy,
+        strategies: Vec<String>,
+        forecast: f64,
+
+        checks: Vec<RiskCheck>,
+        outcome: DecisionOutcome,
+    },
+}
+
+// A risk check applied to a decision. Checks on fills don't stop the order that triggered
+// them, they halt the strategies behind it.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RiskCheck {
+    pub check: String,
+    pub passed: bool,
+
+    #[serde(default)]
+    pub detail: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "result", rename_all = "camelCase")]
+pub enum DecisionOutcome {
+    // Units filled, and the transactions filling them
+    Ordered {
+        units: f64,
+
+        #[serde(rename = "transactionIds")]
+        transaction_ids: Vec<String>,
+    },
+
+    // Posted as a limit order at `price`, see PassiveExecutionConfig
+    Posted {
+        units: f64,
+        price: f64,
+    },
+
+    Suppressed {
+        reason: String,
+    },
+}
+
+impl JournalEntry {
+    pub fn order(
+        fill: &ExecutionFill,
+        forecast: f64,
+        policy: ConflictPolicy,
+        strategies: &[String],
+        signal: Option<&str>,
+    ) -> Self {
+        JournalEntry::Order {
+            time: fill.time.clone(),
+            instrument: fill.instrument.clone(),
+            units: fill.units,
+            price: fill.price,
+            transaction_id: fill.transaction_id.clone(),
+            forecast,
+            pl: fill.pl,
+            commission: fill.commission,
+            financing: fill.financing,
+            policy,
+            strategies: strategies.to_vec(),
+            signal: signal.map(str::to_string),
+        }
+    }
+
+    pub fn external(activity: &ExternalActivity) -> Self {
+        JournalEntry::External {
+            time: activity.time.clone(),
+            instrument: activity.instrument.clone(),
+            units: activity.units,
+            transaction_id: activity.transaction_id.clone(),
+            reason: activity.reason.clone(),
+        }
+    }
+
+    pub fn cost(cost: &AccountCost, strategies: &[String]) -> Self {
+        JournalEntry::Cost {
+            time: cost.time.clone(),
+            instrument: cost.instrument.clone(),
+            kind: cost.kind,
+            amount: cost.amount,
+            transaction_id: cost.transaction_id.clone(),
+            strategies: strategies.to_vec(),
+        }
+    }
+
+    pub fn circuit_breaker(trip: &CircuitBreakerTrip) -> Self {
+        JournalEntry::CircuitBreaker {
+            time: trip.time,
+            instrument: trip.instrument.clone(),
+            orders: trip.orders,
+            strategies: trip.strategies.clone(),
+        }
+    }
+
+    pub fn paused(time: u64, strategy: &str, reason: &str) -> Self {
+        JournalEntry::Paused {
+            time: format_time(time),
+            strategy: strategy.to_string(),
+            reason: reason.to_string(),
+        }
+    }
+
+    pub fn enabled(time: u64, strategy: &str) -> Self {
+        JournalEntry::Enabled {
+            time: format_time(time),
+            strategy: strategy.to_string(),
+        }
+    }
+
+    pub fn instrument_disabled(time: u64, instrument: &str) -> Self {
+        JournalEntry::InstrumentDisabled {
+            time: format_time(time),
+            instrument: instrument.to_string(),
+        }
+    }
+
+    pub fn instrument_enabled(time: u64, instrument: &str) -> Self {
+        JournalEntry::InstrumentEnabled {
+            time: format_time(time),
+            instrument: instrument.to_string(),
+        }
+    }
+
+    pub fn passive_order(time: u64, report: &PassiveReport<ExecutionFill>) -> Self {
+        let order = &report.order;
+        JournalEntry::PassiveOrder {
+            time: format_time(time),
+            instrument: order.instrument.clone(),
+            units: order.units,
+            price: order.price,
+            filled: order.filled,
+            outcome: report.outcome,
+            waited: time.saturating_sub(order.active_after) as f64 / 1000.0,
+            signal: (!order.tags.signal.is_empty()).then(|| order.tags.signal.clone()),
+        }
+    }
+
+    pub fn signal_expired(time: u64, target: &WorkingTarget, reason: &str) -> Self {
+        JournalEntry::SignalExpired {
+            time: format_time(time),
+            instrument: target.instrument.clone(),
+            forecast: target.forecast,
+            strategies: target.strategies.clone(),
+            reason: reason.to_string(),
+        }
+    }
+
+    pub fn drawdown_scale(time: u64, change: &DrawdownChange) -> Self {
+        JournalEntry::DrawdownScale {
+            time: format_time(time),
+            equity: change.equity,
+            peak: change.peak,
+            drawdown: change.drawdown,
+            scale: change.scale,
+        }
+    }
+}
+
+// Append-only journal file. Every entry is flushed as it's written, so the journal survives a crash.
+pub struct Journal {
+    file: File,
+}
+
+impl Journal {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, Box<dyn std::error::Error>> {
+        if let Some(parent) = path.as_ref().parent() {
+            if !parent.as_os_str().is_empty() {
+                std::fs::create_dir_all(parent)?;
+            }
+        }
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Journal { file })
+    }
+
+    pub fn record(&mut self, entry: &JournalEntry) -> Result<(), Box<dyn std::error::Error>> {
+        let mut line = serde_json::to_string(entry)?;
+        line.push('\n');
+        self.file.write_all(line.as_bytes())?;
+        self.file.flush()?;
+        Ok(())
+    }
+}
+
+// Read every entry of a journal, skipping lines that can't be parsed (e.g. one cut off by a crash)
+pub fn read_journal<P: AsRef<Path>>(
+    path: P,
+) -> Result<Vec<JournalEntry>, Box<dyn std::error::Error>> {
+    let reader = BufReader::new(File::open(path)?);
+    let mut entries = Vec::new();
+    for line in reader.lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        match serde_json::from_str(&line) {
+            Ok(entry) => entries.push(entry),
+            Err(e) => log::warn!("Skipping journal entry that can't be read: {}", e),
+        }
+    }
+    Ok(entries)
+}
@@ -1,4 +1,42 @@
+// Modules beyond the OANDA objects, settings and logging everything needs are behind features,
+// so each binary builds only what it uses: "data" (tick files, archives), "streaming" (price
+// streams and the relay), "backtest" (strategies, the engine with paper execution, backtests)
+// and "trading" (live orders). All are on by default.
+#[cfg(feature = "trading")]
+pub mod accounts;
+pub mod alerts;
+#[cfg(feature = "backtest")]
+pub mod backtest;
+pub mod broker;
+pub mod calendar;
+#[cfg(feature = "data")]
+pub mod catalog;
+pub mod claims;
+pub mod conflation;
+#[cfg(feature = "backtest")]
+pub mod control;
+#[cfg(feature = "data")]
+pub mod data;
+#[cfg(feature = "backtest")]
+pub mod engine;
+pub mod errors;
+#[cfg(feature = "data")]
+pub mod export;
+pub mod fx;
+#[cfg(feature = "grpc")]
+pub mod grpc;
+#[cfg(feature = "backtest")]
+pub mod journal;
 pub mod logging;
+#[cfg(feature = "backtest")]
 pub mod models;
+pub mod notify;
 pub mod oanda;
+#[cfg(feature = "data")]
+pub mod retention;
+pub mod secrets;
+#[cfg(feature = "data")]
+pub mod universe;
+#[cfg(feature = "data")]
+pub mod upload;
 pub mod util;
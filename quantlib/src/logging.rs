@@ -1,18 +1,40 @@
-use log::LevelFilter;
+use log::{Level, LevelFilter};
 use log4rs::append::console::ConsoleAppender;
 use log4rs::append::file::FileAppender;
 use log4rs::config::{Appender, Config, Root};
 use log4rs::encode::pattern::PatternEncoder;
+use log4rs::encode::writer::simple::SimpleWriter;
+use log4rs::encode::{self, Encode};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
 use std::error::Error;
+use std::sync::Mutex;
+
+use crate::oanda::objects::Price;
+use crate::secrets;
+
+// Formats records with a pattern, then removes any secrets (e.g. the OANDA token) from the line
+#[derive(Debug)]
+struct RedactingEncoder(PatternEncoder);
+
+impl Encode for RedactingEncoder {
+    fn encode(&self, w: &mut dyn encode::Write, record: &log::Record) -> anyhow::Result<()> {
+        let mut line = SimpleWriter(Vec::new());
+        self.0.encode(&mut line, record)?;
+        w.write_all(secrets::redact(&String::from_utf8_lossy(&line.0)).as_bytes())?;
+        Ok(())
+    }
+}
 
 pub fn configure_logger(logfile: &str) -> Result<(), Box<dyn Error>> {
-    let log_pattern = "[{d(%Y-%m-%d %H:%M:%S)}][{l}] {m}{n}";
+    // Timestamps are in UTC like everything else
+    let log_pattern = "[{d(%Y-%m-%d %H:%M:%S)(utc)}][{l}] {m}{n}";
     let logfile = FileAppender::builder()
-        .encoder(Box::new(PatternEncoder::new(log_pattern)))
+        .encoder(Box::new(RedactingEncoder(PatternEncoder::new(log_pattern))))
         .build(logfile)?;
 
     let stdout = ConsoleAppender::builder()
-        .encoder(Box::new(PatternEncoder::new(log_pattern)))
+        .encoder(Box::new(RedactingEncoder(PatternEncoder::new(log_pattern))))
         .build();
 
     let config = Config::builder()
@@ -28,3 +50,141 @@ pub fn configure_logger(logfile: &str) -> Result<(), Box<dyn Error>> {
     log4rs::init_config(config)?;
     Ok(())
 }
+
+// How each streamed price is logged, "tickLog" in the trading and collector configs, e.g.
+// {"sample": 100, "summaryInterval": 60, "full": ["EUR_USD"]}. Logging every tick of dozens of
+// instruments floods the log and slows the loop down, so by default only a count of the ticks of
+// each instrument is logged every `summaryInterval` seconds (of price time, 0 for none). With
+// `sample` set 1 in that many ticks of each instrument is logged too, and the instruments in
+// `full` have every tick logged, e.g. to debug one of them. A trader's control socket changes
+// these while it runs, see ControlCommand::TickSample and ControlCommand::TickFull.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TickLogConfig {
+    #[serde(default)]
+    pub sample: u64,
+
+    #[serde(default = "default_summary_interval")]
+    #[serde(rename = "summaryInterval")]
+    pub summary_interval: u64,
+
+    #[serde(default)]
+    pub full: BTreeSet<String>,
+}
+
+fn default_summary_interval() -> u64 {
+    60
+}
+
+impl Default for TickLogConfig {
+    fn default() -> Self {
+        TickLogConfig {
+            sample: 0,
+            summary_interval: default_summary_interval(),
+            full: BTreeSet::new(),
+        }
+    }
+}
+
+struct TickLog {
+    config: TickLogConfig,
+
+    // Ticks of each instrument ever logged, to sample from, and since the last summary
+    seen: BTreeMap<String, u64>,
+    window: BTreeMap<String, u64>,
+    window_start: Option<u64>,
+}
+
+// Prices are logged from wherever they're read, so the settings are process wide
+static TICK_LOG: Mutex<TickLog> = Mutex::new(TickLog {
+    config: TickLogConfig {
+        sample: 0,
+        summary_interval: 60,
+        full: BTreeSet::new(),
+    },
+    seen: BTreeMap::new(),
+    window: BTreeMap::new(),
+    window_start: None,
+});
+
+pub fn configure_ticks(config: TickLogConfig) {
+    if let Ok(mut ticks) = TICK_LOG.lock() {
+        ticks.config = config;
+    }
+}
+
+// Log 1 in `sample` ticks of each instrument, none if 0
+pub fn set_tick_sample(sample: u64) {
+    if let Ok(mut ticks) = TICK_LOG.lock() {
+        ticks.config.sample = sample;
+    }
+}
+
+// Log every tick of an instrument, or go back to sampling it
+pub fn set_tick_full(instrument: &str, full: bool) {
+    if let Ok(mut ticks) = TICK_LOG.lock() {
+        if full {
+            ticks.config.full.insert(instrument.to_string());
+        } else {
+            ticks.config.full.remove(instrument);
+        }
+    }
+}
+
+// Log a streamed price as the tick log is configured, e.g. log_tick(Level::Info, "", &price).
+// `label` goes between the instrument and the prices, e.g. "[PRICE]" or " Backfilled".
+pub fn log_tick(level: Level, label: &str, price: &Price) {
+    let mut guard = match TICK_LOG.lock() {
+        Ok(guard) => guard,
+        Err(_) => return,
+    };
+    let ticks = &mut *guard;
+    let seen = count(&mut ticks.seen, &price.instrument);
+    let sampled = ticks.config.sample > 0 && (seen - 1).is_multiple_of(ticks.config.sample);
+    if sampled || ticks.config.full.contains(&price.instrument) {
+        log::log!(
+            level,
+            "[{}]{} Bid: {:.5} Ask: {:.5}",
+            price.instrument,
+            label,
+            price.bid,
+            price.ask
+        );
+    }
+
+    if ticks.config.summary_interval == 0 {
+        return;
+    }
+    let window_start = *ticks.window_start.get_or_insert(price.time);
+    count(&mut ticks.window, &price.instrument);
+    let elapsed = price.time.saturating_sub(window_start);
+    if elapsed >= ticks.config.summary_interval * 1000 {
+        let window = std::mem::take(&mut ticks.window);
+        let counts: Vec<String> = window
+            .iter()
+            .map(|(instrument, count)| format!("{} {}", instrument, count))
+            .collect();
+        log::log!(
+            level,
+            "{} ticks of {} instruments in {}s: {}",
+            window.values().sum::<u64>(),
+            window.len(),
+            elapsed / 1000,
+            counts.join(", ")
+        );
+        ticks.window_start = Some(price.time);
+    }
+}
+
+// Add a tick to an instrument's count, only allocating its name the first time
+fn count(counts: &mut BTreeMap<String, u64>, instrument: &str) -> u64 {
+    match counts.get_mut(instrument) {
+        Some(count) => {
+            *count += 1;
+            *count
+        }
+        None => {
+            counts.insert(instrument.to_string(), 1);
+            1
+        }
+    }
+}
@@ -1,5 +1,8 @@
 use rand::Rng;
 
+use crate::models::{PriceBasis, TickMomentum};
+#[cfg(feature = "scripting")]
+use crate::models::{ScriptConfig, ScriptedModel};
 use crate::oanda::objects::Price;
 use crate::{models::TradingSignal, util::TradingConfig};
 use serde::{Deserialize, Serialize};
@@ -14,6 +17,62 @@ pub trait AlphaModel {
 pub enum AlphaModels {
     Random(RandomStrategy),
     ExponentialMovingAverage(ExponentialMovingAverage),
+    TickMomentum(TickMomentum),
+    #[cfg(feature = "scripting")]
+    Script(Box<ScriptedModel>),
+}
+
+// Model state is saved as JSON so that checkpoints are portable between the trader and backtests
+impl AlphaModels {
+    // The model name used in configs
+    pub fn name(&self) -> &'static str {
+        match self {
+            AlphaModels::Random(_) => "random",
+            AlphaModels::ExponentialMovingAverage(_) => "ema",
+            AlphaModels::TickMomentum(_) => "tickMomentum",
+            #[cfg(feature = "scripting")]
+            AlphaModels::Script(_) => "script",
+        }
+    }
+
+    // Whether a model (by its name in configs) signals on each instrument from that instrument's
+    // prices alone, keeping its state as an object by instrument, so separate copies of it can
+    // each see some of the instruments. The EMA averages every instrument's prices together.
+    pub fn per_instrument(model: &str) -> bool {
+        !matches!(model, "ema")
+    }
+
+    pub fn state(&self) -> serde_json::Value {
+        match self {
+            AlphaModels::Random(_) => serde_json::Value::Null,
+            AlphaModels::ExponentialMovingAverage(strategy) => strategy.state(),
+            AlphaModels::TickMomentum(strategy) => strategy.state(),
+            #[cfg(feature = "scripting")]
+            AlphaModels::Script(strategy) => strategy.state(),
+        }
+    }
+
+    // The internal variables the model declares for an instrument, as they stand, to record
+    // while it trades (see ModelStateConfig)
+    pub fn variables(&self, instrument: &str) -> Vec<(String, f64)> {
+        match self {
+            AlphaModels::Random(_) => Vec::new(),
+            AlphaModels::ExponentialMovingAverage(strategy) => strategy.variables(),
+            AlphaModels::TickMomentum(strategy) => strategy.variables(instrument),
+            #[cfg(feature = "scripting")]
+            AlphaModels::Script(strategy) => strategy.variables(instrument),
+        }
+    }
+
+    pub fn restore(&mut self, state: &serde_json::Value) -> Result<(), Box<dyn std::error::Error>> {
+        match self {
+            AlphaModels::Random(_) => Ok(()),
+            AlphaModels::ExponentialMovingAverage(strategy) => strategy.restore(state),
+            AlphaModels::TickMomentum(strategy) => strategy.restore(state),
+            #[cfg(feature = "scripting")]
+            AlphaModels::Script(strategy) => strategy.restore(state),
+        }
+    }
 }
 
 impl AlphaModel for AlphaModels {
@@ -21,6 +80,9 @@ impl AlphaModel for AlphaModels {
         match self {
             AlphaModels::Random(strategy) => strategy.tick(price),
             AlphaModels::ExponentialMovingAverage(strategy) => strategy.tick(price),
+            AlphaModels::TickMomentum(strategy) => strategy.tick(price),
+            #[cfg(feature = "scripting")]
+            AlphaModels::Script(strategy) => strategy.tick(price),
         }
     }
 
@@ -39,9 +101,27 @@ impl AlphaModel for AlphaModels {
             "ema" => {
                 let slow_ma_weight = config.model_config["slowWeight"].as_f64().unwrap();
                 let fast_ma_weight = config.model_config["fastWeight"].as_f64().unwrap();
-                let strategy = ExponentialMovingAverage::new(slow_ma_weight, fast_ma_weight);
+                let strategy = ExponentialMovingAverage::new(slow_ma_weight, fast_ma_weight)
+                    .with_price_basis(config.price_basis);
                 Ok(AlphaModels::ExponentialMovingAverage(strategy))
             }
+            "tickMomentum" => {
+                let window = config.model_config["window"].as_u64().unwrap() as usize;
+                let imbalance_threshold =
+                    config.model_config["imbalanceThreshold"].as_f64().unwrap();
+                let momentum_threshold = config.model_config["momentumThreshold"].as_f64().unwrap();
+                let strategy = TickMomentum::new(window, imbalance_threshold, momentum_threshold)
+                    .with_price_basis(config.price_basis);
+                Ok(AlphaModels::TickMomentum(strategy))
+            }
+            #[cfg(feature = "scripting")]
+            "script" => {
+                let script: ScriptConfig = serde_json::from_value(config.model_config.clone())?;
+                let strategy = ScriptedModel::new(script)?.with_price_basis(config.price_basis);
+                Ok(AlphaModels::Script(Box::new(strategy)))
+            }
+            #[cfg(not(feature = "scripting"))]
+            "script" => Err("Built without scripting, the script model isn't available".into()),
             _ => panic!("Unknown model: {}", config.model),
         }
     }
@@ -54,6 +134,10 @@ pub struct ExponentialMovingAverage {
     #[serde(rename = "fastWeight")]
     fast_ma_weight: f64,
 
+    #[serde(default)]
+    #[serde(rename = "priceBasis")]
+    price_basis: PriceBasis,
+
     #[serde(skip)]
     slow_ma: f64,
     #[serde(skip)]
@@ -65,29 +149,59 @@ impl ExponentialMovingAverage {
         ExponentialMovingAverage {
             slow_ma_weight,
             fast_ma_weight,
+            price_basis: PriceBasis::default(),
             slow_ma: -1.0,
             fast_ma: -1.0,
         }
     }
 
+    pub fn with_price_basis(mut self, price_basis: PriceBasis) -> Self {
+        self.price_basis = price_basis;
+        self
+    }
+
+    pub fn state(&self) -> serde_json::Value {
+        serde_json::json!({"slowMa": self.slow_ma, "fastMa": self.fast_ma})
+    }
+
+    // The averages are of every instrument's prices together, none until the first price
+    pub fn variables(&self) -> Vec<(String, f64)> {
+        if self.slow_ma < 0.0 || self.fast_ma < 0.0 {
+            return Vec::new();
+        }
+        vec![
+            ("fastMa".to_string(), self.fast_ma),
+            ("slowMa".to_string(), self.slow_ma),
+        ]
+    }
+
+    pub fn restore(&mut self, state: &serde_json::Value) -> Result<(), Box<dyn std::error::Error>> {
+        self.slow_ma = state["slowMa"]
+            .as_f64()
+            .ok_or("Missing slowMa in EMA state")?;
+        self.fast_ma = state["fastMa"]
+            .as_f64()
+            .ok_or("Missing fastMa in EMA state")?;
+        Ok(())
+    }
+
     pub fn tick(
         &mut self,
         price: &Price,
     ) -> Result<Option<TradingSignal>, Box<dyn std::error::Error>> {
         let mut signal = None;
+        let value = self.price_basis.value(price);
 
         // If we don't have a slow or fast moving average yet, set them to the current price
         if self.slow_ma < 0.0 || self.fast_ma < 0.0 {
-            self.fast_ma = price.ask as f64;
-            self.slow_ma = price.ask as f64;
+            self.fast_ma = value;
+            self.slow_ma = value;
             return Ok(None);
         }
 
         // Calculate the new moving averages
-        let new_slow_ma =
-            self.slow_ma_weight * price.ask as f64 + (1.0 - self.slow_ma_weight) * self.slow_ma;
-        let new_fast_ma =
-            self.fast_ma_weight * price.ask as f64 + (1.0 - self.fast_ma_weight) * self.fast_ma;
+        let new_slow_ma = self.slow_ma_weight * value + (1.0 - self.slow_ma_weight) * self.slow_ma;
+        let new_fast_ma = self.fast_ma_weight * value + (1.0 - self.fast_ma_weight) * self.fast_ma;
 
         // If the fast moving average crosses above the slow moving average, buy
         if new_fast_ma > new_slow_ma && self.fast_ma < self.slow_ma {
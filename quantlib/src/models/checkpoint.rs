@@ -0,0 +1,68 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Write};
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::models::{ConflictPolicy, StandingForecast};
+
+// Bumped whenever the layout of a checkpoint or of any model's state changes
+pub const CHECKPOINT_VERSION: u32 = 1;
+
+// Internal state of one strategy of a signal bus
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ModelCheckpoint {
+    pub name: String,
+    pub model: String,
+    pub state: serde_json::Value,
+}
+
+// Everything the strategies of a config need to carry on exactly where they left off.
+// Checkpoints written by the live trader and by the backtester are interchangeable, so a
+// backtest can start from the live state at a given moment and the trader from a backtest's.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct StrategyCheckpoint {
+    pub version: u32,
+
+    // Time of the last price the strategies saw, in milliseconds since the UNIX epoch
+    pub time: u64,
+
+    pub policy: ConflictPolicy,
+    pub models: Vec<ModelCheckpoint>,
+
+    // Standing forecasts of each strategy and the last resolved forecast, per instrument
+    pub(crate) forecasts: HashMap<String, Vec<StandingForecast>>,
+    pub(crate) resolved: HashMap<String, f64>,
+    pub(crate) sequence: u64,
+}
+
+impl StrategyCheckpoint {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, Box<dyn std::error::Error>> {
+        log::info!("Loading strategy checkpoint from {:?}", path.as_ref());
+        let reader = BufReader::new(File::open(path)?);
+        let checkpoint: StrategyCheckpoint = serde_json::from_reader(reader)?;
+        if checkpoint.version != CHECKPOINT_VERSION {
+            return Err(format!(
+                "Checkpoint version {} is not supported (expected {})",
+                checkpoint.version, CHECKPOINT_VERSION
+            )
+            .into());
+        }
+        Ok(checkpoint)
+    }
+
+    // Written to a temporary file first so a crash never leaves a truncated checkpoint behind
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), Box<dyn std::error::Error>> {
+        let path = path.as_ref();
+        let temporary = path.with_extension("tmp");
+
+        let mut writer = BufWriter::new(File::create(&temporary)?);
+        serde_json::to_writer_pretty(&mut writer, self)?;
+        writer.flush()?;
+        drop(writer);
+
+        std::fs::rename(temporary, path)?;
+        Ok(())
+    }
+}
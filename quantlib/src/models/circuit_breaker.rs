@@ -0,0 +1,119 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use serde::{Deserialize, Serialize};
+
+// Most orders allowed for one instrument within a rolling window, e.g.
+// "orderRateLimit": {"maxOrders": 5, "windowSeconds": 60}
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct OrderRateLimit {
+    #[serde(rename = "maxOrders")]
+    pub max_orders: usize,
+
+    #[serde(default = "default_window_seconds")]
+    #[serde(rename = "windowSeconds")]
+    pub window_seconds: u64,
+}
+
+fn default_window_seconds() -> u64 {
+    60
+}
+
+// Raised when orders for an instrument come faster than the rate limit allows
+#[derive(Debug, Clone)]
+pub struct CircuitBreakerTrip {
+    pub time: u64,
+    pub instrument: String,
+    pub orders: usize,
+
+    // Strategies that decided the orders, which are now halted
+    pub strategies: Vec<String>,
+}
+
+// Guards against a runaway strategy (e.g. one flipping its signal every tick) firing market orders
+// continuously. Times are in milliseconds since the UNIX epoch, taken from prices, so the breaker
+// behaves the same in backtests as it does live.
+pub struct CircuitBreaker {
+    limit: OrderRateLimit,
+    orders: HashMap<String, VecDeque<u64>>,
+    halted: HashSet<String>,
+}
+
+// The orders in each instrument's window and the strategies a CircuitBreaker has halted, for a
+// backtest to resume with
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct CircuitBreakerState {
+    pub orders: HashMap<String, VecDeque<u64>>,
+    pub halted: HashSet<String>,
+}
+
+impl CircuitBreaker {
+    pub fn new(limit: OrderRateLimit) -> Self {
+        CircuitBreaker {
+            limit,
+            orders: HashMap::new(),
+            halted: HashSet::new(),
+        }
+    }
+
+    pub fn state(&self) -> CircuitBreakerState {
+        CircuitBreakerState {
+            orders: self.orders.clone(),
+            halted: self.halted.clone(),
+        }
+    }
+
+    pub fn restore(&mut self, state: CircuitBreakerState) {
+        self.orders = state.orders;
+        self.halted = state.halted;
+    }
+
+    pub fn is_halted(&self, strategy: &str) -> bool {
+        self.halted.contains(strategy)
+    }
+
+    // Re-enable a halted strategy
+    pub fn reset(&mut self, strategy: &str) {
+        self.halted.remove(strategy);
+    }
+
+    // Record an order for an instrument. Trips the breaker for the deciding strategies if the
+    // instrument has now had more orders than allowed within the window.
+    pub fn record_order(
+        &mut self,
+        time: u64,
+        instrument: &str,
+        strategies: &[String],
+    ) -> Option<CircuitBreakerTrip> {
+        let window = self.limit.window_seconds * 1000;
+        let orders = self.orders.entry(instrument.to_string()).or_default();
+        orders.push_back(time);
+        while orders.front().is_some_and(|first| *first + window <= time) {
+            orders.pop_front();
+        }
+
+        if orders.len() <= self.limit.max_orders {
+            return None;
+        }
+
+        let count = orders.len();
+        orders.clear();
+        let strategies: Vec<String> = strategies
+            .iter()
+            .filter(|strategy| self.halted.insert(strategy.to_string()))
+            .cloned()
+            .collect();
+        log::error!(
+            "[{}] Circuit breaker tripped: {} orders within {} seconds, halting {:?}",
+            instrument,
+            count,
+            self.limit.window_seconds,
+            strategies
+        );
+        Some(CircuitBreakerTrip {
+            time,
+            instrument: instrument.to_string(),
+            orders: count,
+            strategies,
+        })
+    }
+}
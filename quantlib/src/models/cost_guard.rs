@@ -0,0 +1,146 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::oanda::objects::{InstrumentFinancing, PriceBucket, PriceDepth};
+
+// Refuses orders that would cost too much to get in and out of, set by "costGuard" in the trading
+// config, e.g. {"maxCost": 0.0005, "instruments": {"EUR_TRY": 0.003}, "estimate": true}. Only the
+// exposure an order adds is checked, so a flip that costs too much still closes the position.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CostGuardConfig {
+    // Largest round-trip cost allowed, as a fraction of the position's value (0.0005 is 5 bps)
+    #[serde(rename = "maxCost")]
+    pub max_cost: f64,
+
+    // Limits of instruments that differ from maxCost
+    #[serde(default)]
+    pub instruments: HashMap<String, f64>,
+
+    // Ask OANDA for the depth of the book and the financing rates before each order, rather than
+    // only looking at the spread of the latest streamed price. Costs two requests per order, and
+    // isn't available through a broker backend.
+    #[serde(default)]
+    pub estimate: bool,
+
+    // Days of financing a position is expected to be held for, included in estimates
+    #[serde(default = "default_holding_days")]
+    #[serde(rename = "holdingDays")]
+    pub holding_days: f64,
+}
+
+fn default_holding_days() -> f64 {
+    1.0
+}
+
+// What a round trip of an order is expected to cost, as fractions of the position's value
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct CostEstimate {
+    // Crossing the best bid and ask
+    pub spread: f64,
+
+    // Walking the book past the best prices for the size of the order
+    pub depth: f64,
+
+    // Holding the position for the configured days, negative if it earns financing
+    pub financing: f64,
+}
+
+impl CostEstimate {
+    pub fn total(&self) -> f64 {
+        self.spread + self.depth + self.financing
+    }
+}
+
+impl CostGuardConfig {
+    pub fn max_cost(&self, instrument: &str) -> f64 {
+        self.instruments
+            .get(instrument)
+            .copied()
+            .unwrap_or(self.max_cost)
+    }
+
+    // Whether an order may go ahead at the estimated cost
+    pub fn check(&self, instrument: &str, estimate: &CostEstimate) -> Result<(), String> {
+        let limit = self.max_cost(instrument);
+        if estimate.total() > limit {
+            return Err(format!(
+                "round trip would cost {:.1} bps (spread {:.1}, depth {:.1}, financing {:.1}), \
+                 the limit is {:.1} bps",
+                estimate.total() * 10_000.0,
+                estimate.spread * 10_000.0,
+                estimate.depth * 10_000.0,
+                estimate.financing * 10_000.0,
+                limit * 10_000.0
+            ));
+        }
+        Ok(())
+    }
+}
+
+// Cost of only crossing the spread of a quote
+pub fn spread_cost(bid: f64, ask: f64) -> Option<CostEstimate> {
+    let mid = (bid + ask) / 2.0;
+    if mid <= 0.0 {
+        return None;
+    }
+    Some(CostEstimate {
+        spread: (ask - bid) / mid,
+        depth: 0.0,
+        financing: 0.0,
+    })
+}
+
+// Cost of opening `units` (negative to sell) against the book and closing them again against
+// the other side of it, plus financing for `holding_days`. None if the book isn't deep enough to
+// fill the order.
+pub fn estimate_cost(
+    depth: &PriceDepth,
+    financing: Option<&InstrumentFinancing>,
+    units: f64,
+    holding_days: f64,
+) -> Option<CostEstimate> {
+    let best_bid = depth.bids.first()?.price;
+    let best_ask = depth.asks.first()?.price;
+    let mut estimate = spread_cost(best_bid, best_ask)?;
+    let mid = (best_bid + best_ask) / 2.0;
+
+    // Buying walks up the asks and selling back down the bids, or the other way round
+    let (entry, exit) = if units > 0.0 {
+        (&depth.asks, &depth.bids)
+    } else {
+        (&depth.bids, &depth.asks)
+    };
+    let entry_price = average_fill(entry, units.abs())?;
+    let exit_price = average_fill(exit, units.abs())?;
+    estimate.depth = (entry_price - exit_price).abs() / mid - estimate.spread;
+
+    // Rates are annual, and positive when the side earns financing
+    if let Some(financing) = financing {
+        let rate = if units > 0.0 {
+            financing.long_rate
+        } else {
+            financing.short_rate
+        };
+        estimate.financing = -rate * holding_days / 365.0;
+    }
+    Some(estimate)
+}
+
+// Average price of filling `units` from the best bucket outwards
+fn average_fill(buckets: &[PriceBucket], units: f64) -> Option<f64> {
+    if units <= 0.0 {
+        return buckets.first().map(|bucket| bucket.price);
+    }
+    let mut remaining = units;
+    let mut value = 0.0;
+    for bucket in buckets {
+        let filled = remaining.min(bucket.liquidity);
+        value += filled * bucket.price;
+        remaining -= filled;
+        if remaining <= 0.0 {
+            return Some(value / units);
+        }
+    }
+    None
+}
@@ -0,0 +1,101 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::fx::{split_instrument, Converter};
+
+// Leverage limits, shared by the backtest's simulated account and the live pre-trade check so
+// that both refuse the same orders, e.g.
+// "margin": {"leverage": 30, "instruments": {"EUR_USD": 50}, "maxUtilization": 0.8}
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct MarginConfig {
+    // Margin needed for a position is its value in the account currency divided by the leverage
+    #[serde(default = "default_leverage")]
+    pub leverage: f64,
+
+    // Leverage of instruments that differ from the default
+    #[serde(default)]
+    pub instruments: HashMap<String, f64>,
+
+    // Largest fraction of NAV that may be used as margin after an order. Orders that reduce the
+    // margin used are always allowed, so positions can be closed however much margin is used, and
+    // a flip that would go over the limit still closes the position.
+    #[serde(default = "default_max_utilization")]
+    #[serde(rename = "maxUtilization")]
+    pub max_utilization: f64,
+}
+
+// OANDA's (and ESMA's) limit for retail accounts on major pairs
+fn default_leverage() -> f64 {
+    30.0
+}
+
+fn default_max_utilization() -> f64 {
+    1.0
+}
+
+impl Default for MarginConfig {
+    fn default() -> Self {
+        MarginConfig {
+            leverage: default_leverage(),
+            instruments: HashMap::new(),
+            max_utilization: default_max_utilization(),
+        }
+    }
+}
+
+impl MarginConfig {
+    pub fn margin_rate(&self, instrument: &str) -> f64 {
+        let leverage = self
+            .instruments
+            .get(instrument)
+            .copied()
+            .unwrap_or(self.leverage);
+        1.0 / leverage.max(1.0)
+    }
+
+    // Margin for a position of `units` (a unit being one of the base currency) in `currency`,
+    // or None if the base currency can't be converted into it yet
+    pub fn position_margin(
+        &self,
+        converter: &Converter,
+        instrument: &str,
+        units: f64,
+        currency: &str,
+    ) -> Option<f64> {
+        if units == 0.0 {
+            return Some(0.0);
+        }
+        let (base, _) = split_instrument(instrument)?;
+        let value = converter.convert(units.abs(), base, currency)?;
+        Some(value * self.margin_rate(instrument))
+    }
+
+    // Whether an order may take the margin used from `before` to `after` on an account worth `nav`
+    pub fn check(&self, before: f64, after: f64, nav: f64) -> Result<(), String> {
+        if after <= before {
+            return Ok(());
+        }
+        if nav <= 0.0 {
+            return Err("there is no NAV left to use as margin".to_string());
+        }
+        let utilization = margin_utilization(after, nav);
+        if utilization > self.max_utilization {
+            return Err(format!(
+                "margin utilization would be {:.1}%, the limit is {:.1}%",
+                utilization * 100.0,
+                self.max_utilization * 100.0
+            ));
+        }
+        Ok(())
+    }
+}
+
+// Fraction of NAV used as margin, 0 if the account has no NAV left
+pub fn margin_utilization(margin_used: f64, nav: f64) -> f64 {
+    if nav > 0.0 {
+        margin_used / nav
+    } else {
+        0.0
+    }
+}
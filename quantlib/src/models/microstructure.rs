@@ -0,0 +1,155 @@
+use std::collections::{HashMap, VecDeque};
+
+use serde::{Deserialize, Serialize};
+
+use crate::models::{PriceBasis, TradingSignal};
+use crate::oanda::objects::Price;
+
+// Rolling window of the most recent ticks of a single instrument
+#[derive(Serialize, Deserialize)]
+struct TickWindow {
+    values: VecDeque<f64>,
+
+    // +1 for an uptick in the price, -1 for a downtick, unchanged ticks are not counted
+    directions: VecDeque<i8>,
+    imbalance: i64,
+
+    // Direction of the last signal, so a signal is only sent when it changes
+    #[serde(rename = "lastSignal")]
+    last_signal: i8,
+
+    // Move over the window in spreads, as of the last tick with a full window
+    #[serde(skip)]
+    momentum: Option<f64>,
+}
+
+// Trades short-horizon order flow: over the last `window` ticks, the imbalance between upticks
+// and downticks of the price, confirmed by the move in the price measured in spreads.
+// Buys when both are strongly positive, sells when both are strongly negative, and goes flat
+// when neither direction is clear.
+pub struct TickMomentum {
+    window: usize,
+
+    // Minimum (upticks - downticks) / (upticks + downticks), between 0 and 1
+    imbalance_threshold: f64,
+
+    // Minimum move in the price over the window, in multiples of the current spread
+    momentum_threshold: f64,
+
+    price_basis: PriceBasis,
+
+    ticks: HashMap<String, TickWindow>,
+}
+
+impl TickMomentum {
+    pub fn new(window: usize, imbalance_threshold: f64, momentum_threshold: f64) -> Self {
+        TickMomentum {
+            window: window.max(2),
+            imbalance_threshold,
+            momentum_threshold,
+            price_basis: PriceBasis::default(),
+            ticks: HashMap::new(),
+        }
+    }
+
+    pub fn with_price_basis(mut self, price_basis: PriceBasis) -> Self {
+        self.price_basis = price_basis;
+        self
+    }
+
+    // The windows of every instrument
+    pub fn state(&self) -> serde_json::Value {
+        serde_json::to_value(&self.ticks).unwrap_or_default()
+    }
+
+    // The instrument's imbalance once it has had a tick in either direction, and its momentum
+    // once it has had a full window
+    pub fn variables(&self, instrument: &str) -> Vec<(String, f64)> {
+        let ticks = match self.ticks.get(instrument) {
+            Some(ticks) if !ticks.directions.is_empty() => ticks,
+            _ => return Vec::new(),
+        };
+        let mut variables = vec![(
+            "imbalance".to_string(),
+            ticks.imbalance as f64 / ticks.directions.len() as f64,
+        )];
+        if let Some(momentum) = ticks.momentum {
+            variables.push(("momentum".to_string(), momentum));
+        }
+        variables
+    }
+
+    pub fn restore(&mut self, state: &serde_json::Value) -> Result<(), Box<dyn std::error::Error>> {
+        self.ticks = serde_json::from_value(state.clone())?;
+        Ok(())
+    }
+
+    pub fn tick(
+        &mut self,
+        price: &Price,
+    ) -> Result<Option<TradingSignal>, Box<dyn std::error::Error>> {
+        let value = self.price_basis.value(price);
+        let spread = (price.ask - price.bid) as f64;
+
+        let ticks = self
+            .ticks
+            .entry(price.instrument.clone())
+            .or_insert_with(|| TickWindow {
+                values: VecDeque::new(),
+                directions: VecDeque::new(),
+                imbalance: 0,
+                last_signal: 0,
+                momentum: None,
+            });
+
+        if let Some(previous) = ticks.values.back() {
+            let direction = if value > *previous {
+                1
+            } else if value < *previous {
+                -1
+            } else {
+                0
+            };
+            if direction != 0 {
+                ticks.directions.push_back(direction);
+                ticks.imbalance += direction as i64;
+                if ticks.directions.len() > self.window {
+                    let expired = ticks.directions.pop_front().unwrap();
+                    ticks.imbalance -= expired as i64;
+                }
+            }
+        }
+        ticks.values.push_back(value);
+        if ticks.values.len() > self.window {
+            ticks.values.pop_front();
+        }
+
+        // Wait for a full window before trading
+        if ticks.values.len() < self.window || ticks.directions.is_empty() || spread <= 0.0 {
+            return Ok(None);
+        }
+
+        let imbalance = ticks.imbalance as f64 / ticks.directions.len() as f64;
+        let momentum = (value - ticks.values.front().unwrap()) / spread;
+        ticks.momentum = Some(momentum);
+
+        let signal = if imbalance >= self.imbalance_threshold && momentum >= self.momentum_threshold
+        {
+            1
+        } else if imbalance <= -self.imbalance_threshold && momentum <= -self.momentum_threshold {
+            -1
+        } else {
+            0
+        };
+
+        if signal == ticks.last_signal {
+            return Ok(None);
+        }
+        ticks.last_signal = signal;
+
+        Ok(Some(TradingSignal {
+            instrument: price.instrument.clone(),
+            forecast: signal as f64,
+        }))
+    }
+}
@@ -1,7 +1,45 @@
 pub mod alpha_model;
+pub mod checkpoint;
+pub mod circuit_breaker;
+pub mod cost_guard;
+pub mod margin;
+pub mod microstructure;
+pub mod order_sizing;
+pub mod passive_execution;
 pub mod portfolio_construction_models;
+pub mod position_sizing;
+pub mod price_basis;
+pub mod price_filter;
+#[cfg(feature = "scripting")]
+pub mod script;
+pub mod signal_bus;
+pub mod signal_validity;
+pub mod state_recorder;
+pub mod strategy_guard;
+pub mod strategy_pool;
 pub mod trading_signal;
+pub mod trailing_stop;
+pub mod volatility_target;
 
 pub use alpha_model::*;
+pub use checkpoint::*;
+pub use circuit_breaker::*;
+pub use cost_guard::*;
+pub use margin::*;
+pub use microstructure::*;
+pub use order_sizing::*;
+pub use passive_execution::*;
 pub use portfolio_construction_models::*;
+pub use position_sizing::*;
+pub use price_basis::*;
+pub use price_filter::*;
+#[cfg(feature = "scripting")]
+pub use script::*;
+pub use signal_bus::*;
+pub use signal_validity::*;
+pub use state_recorder::*;
+pub use strategy_guard::*;
+pub use strategy_pool::*;
 pub use trading_signal::*;
+pub use trailing_stop::*;
+pub use volatility_target::*;
@@ -0,0 +1,71 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::oanda::objects::Instrument;
+
+// How computed units are brought onto an instrument's trade unit precision
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "camelCase")]
+pub enum UnitRounding {
+    // Towards zero, so an order is never larger than intended
+    #[default]
+    Down,
+    Nearest,
+}
+
+// Makes order sizes acceptable to OANDA: rounded to the instrument's unit precision, capped at its
+// maximum order size, and skipped entirely if below its minimum trade size.
+// Instruments without metadata are passed through unchanged.
+pub struct OrderSizer {
+    instruments: HashMap<String, Instrument>,
+    rounding: UnitRounding,
+}
+
+impl OrderSizer {
+    pub fn new(instruments: Vec<Instrument>, rounding: UnitRounding) -> Self {
+        OrderSizer {
+            instruments: instruments
+                .into_iter()
+                .map(|instrument| (instrument.name.clone(), instrument))
+                .collect(),
+            rounding,
+        }
+    }
+
+    // Units to actually order, or None if the order should be skipped
+    pub fn size(&self, instrument: &str, units: f64) -> Option<f64> {
+        let metadata = match self.instruments.get(instrument) {
+            Some(metadata) => metadata,
+            None => return if units != 0.0 { Some(units) } else { None },
+        };
+
+        let scale = 10f64.powi(metadata.trade_units_precision);
+        let scaled = units.abs() * scale;
+        let mut size = match self.rounding {
+            UnitRounding::Down => scaled.floor(),
+            UnitRounding::Nearest => scaled.round(),
+        } / scale;
+
+        if metadata.maximum_order_units > 0.0 && size > metadata.maximum_order_units {
+            log::warn!(
+                "[{}] Order of {} units capped at the maximum of {} units",
+                instrument,
+                units,
+                metadata.maximum_order_units
+            );
+            size = metadata.maximum_order_units;
+        }
+
+        if size == 0.0 || size < metadata.minimum_trade_size {
+            log::info!(
+                "[{}] Skipping order of {} units, below the minimum of {} units",
+                instrument,
+                units,
+                metadata.minimum_trade_size
+            );
+            return None;
+        }
+        Some(size.copysign(units))
+    }
+}
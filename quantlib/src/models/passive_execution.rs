@@ -0,0 +1,229 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::backtest::{RestingOrder, RestingOrderType};
+use crate::broker::OrderTags;
+
+// Works small changes to a position with a limit order rather than crossing the spread,
+// "passiveExecution" in the trading config, e.g. {"maxUnits": 5000, "inside": 0.25, "timeout": 30}.
+// An order of at most `maxUnits` is posted on the near side of the spread, at the bid to buy and
+// the ask to sell, moved `inside` of the spread towards the far side (0.5 is the mid). Whatever
+// hasn't filled after `timeout` seconds is cancelled and sent as a market order. Larger orders
+// cross the spread as before. A new signal for the instrument cancels its resting order first.
+// Paper and backtest orders fill once the market trades through their price: a buy once the ask
+// is at or below it, a sell once the bid is at or above it. Only OANDA accounts take passive
+// orders live, and hedging accounts don't.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PassiveExecutionConfig {
+    #[serde(rename = "maxUnits")]
+    pub max_units: f64,
+
+    #[serde(default)]
+    pub inside: f64,
+
+    #[serde(default = "default_timeout")]
+    pub timeout: u64,
+}
+
+fn default_timeout() -> u64 {
+    30
+}
+
+impl PassiveExecutionConfig {
+    // Whether an order of `units` is small enough to post
+    pub fn applies(&self, units: f64) -> bool {
+        units != 0.0 && units.abs() <= self.max_units
+    }
+
+    // The price to post an order of `units` at, given the bid and ask
+    pub fn limit_price(&self, units: f64, bid: f64, ask: f64) -> f64 {
+        let inside = (ask - bid) * self.inside.clamp(0.0, 1.0);
+        if units > 0.0 {
+            bid + inside
+        } else {
+            ask - inside
+        }
+    }
+
+    // An order of `units` to post at `time` given the bid and ask, timing out after `timeout`
+    pub fn order(
+        &self,
+        instrument: &str,
+        units: f64,
+        bid: f64,
+        ask: f64,
+        time: u64,
+    ) -> RestingOrder {
+        RestingOrder {
+            instrument: instrument.to_string(),
+            units,
+            kind: RestingOrderType::Limit,
+            price: self.limit_price(units, bid, ask),
+            reason: "signal".to_string(),
+            strategies: Vec::new(),
+            active_after: time,
+            expires: Some(time + self.timeout * 1000),
+            immediate: false,
+            passive: true,
+            order_id: None,
+            forecast: 0.0,
+            tags: OrderTags::default(),
+            filled: 0.0,
+        }
+    }
+}
+
+// How a posted order ended
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum PassiveOutcome {
+    // Filled in full at its price
+    Filled,
+
+    // Timed out, the rest sent as a market order
+    MarketFallback,
+
+    // Replaced by a newer signal for the instrument
+    Cancelled,
+}
+
+// A posted order that ended, with every fill it led to, passive or at the market
+#[derive(Debug, Clone)]
+pub struct PassiveReport<F> {
+    pub order: RestingOrder,
+    pub outcome: PassiveOutcome,
+    pub fills: Vec<F>,
+}
+
+impl<F> PassiveReport<F> {
+    pub fn map_fills<G>(self, convert: impl Fn(&F) -> G) -> PassiveReport<G> {
+        PassiveReport {
+            fills: self.fills.iter().map(convert).collect(),
+            order: self.order,
+            outcome: self.outcome,
+        }
+    }
+}
+
+// The resting order of each instrument, at most one per instrument, and the fills of `F` (the
+// venue's fills) it has had so far
+#[derive(Debug, Clone)]
+pub struct PassiveOrders<F> {
+    config: PassiveExecutionConfig,
+    resting: HashMap<String, (RestingOrder, Vec<F>)>,
+}
+
+impl<F> PassiveOrders<F> {
+    pub fn new(config: PassiveExecutionConfig) -> Self {
+        PassiveOrders {
+            config,
+            resting: HashMap::new(),
+        }
+    }
+
+    pub fn config(&self) -> &PassiveExecutionConfig {
+        &self.config
+    }
+
+    pub fn post(&mut self, order: RestingOrder) {
+        log::info!(
+            "[{}] Posted {} units at {}",
+            order.instrument,
+            order.units,
+            order.price
+        );
+        self.resting
+            .insert(order.instrument.clone(), (order, Vec::new()));
+    }
+
+    pub fn get(&self, instrument: &str) -> Option<&RestingOrder> {
+        self.resting.get(instrument).map(|(order, _)| order)
+    }
+
+    // The instrument of the resting order with the broker's ID, if it's ours
+    pub fn instrument_of(&self, order_id: &str) -> Option<String> {
+        self.resting
+            .values()
+            .find(|(order, _)| order.order_id.as_deref() == Some(order_id))
+            .map(|(order, _)| order.instrument.clone())
+    }
+
+    // Whether the instrument's resting order has waited out the timeout at `now`
+    pub fn timed_out(&self, instrument: &str, now: u64) -> bool {
+        self.get(instrument)
+            .and_then(|order| order.expires)
+            .is_some_and(|expires| now >= expires)
+    }
+
+    // A fill of `units` of the instrument's resting order, returning whether it's now filled in
+    // full
+    pub fn fill(&mut self, instrument: &str, units: f64, fill: F) -> bool {
+        match self.resting.get_mut(instrument) {
+            Some((order, fills)) => {
+                order.filled += units;
+                fills.push(fill);
+                order.remaining().abs() < 1e-9
+            }
+            None => false,
+        }
+    }
+
+    // Take the instrument's resting order off the book, reporting how it ended
+    pub fn finish(
+        &mut self,
+        instrument: &str,
+        outcome: PassiveOutcome,
+    ) -> Option<PassiveReport<F>> {
+        let (order, fills) = self.resting.remove(instrument)?;
+        log::info!(
+            "[{}] Posted order of {} units at {} ended {:?}, {} filled",
+            instrument,
+            order.units,
+            order.price,
+            outcome,
+            order.filled
+        );
+        Some(PassiveReport {
+            order,
+            outcome,
+            fills,
+        })
+    }
+}
+
+// Share of the units posted passively that filled at their price, and how posted orders ended
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct PassiveStats {
+    pub posted: u64,
+    pub filled: u64,
+
+    #[serde(rename = "marketFallbacks")]
+    pub market_fallbacks: u64,
+
+    pub cancelled: u64,
+
+    #[serde(rename = "unitsPosted")]
+    pub units_posted: f64,
+
+    #[serde(rename = "unitsFilled")]
+    pub units_filled: f64,
+}
+
+impl PassiveStats {
+    pub fn record(&mut self, order: &RestingOrder, outcome: PassiveOutcome) {
+        self.posted += 1;
+        match outcome {
+            PassiveOutcome::Filled => self.filled += 1,
+            PassiveOutcome::MarketFallback => self.market_fallbacks += 1,
+            PassiveOutcome::Cancelled => self.cancelled += 1,
+        }
+        self.units_posted += order.units.abs();
+        self.units_filled += order.filled.abs();
+    }
+
+    // None until an order has ended
+    pub fn fill_ratio(&self) -> Option<f64> {
+        (self.units_posted > 0.0).then(|| self.units_filled / self.units_posted)
+    }
+}
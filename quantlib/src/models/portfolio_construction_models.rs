@@ -1,55 +1,941 @@
-use crate::models::TradingSignal;
+// Only the live portfolio builder needs any of these
+#[cfg(feature = "trading")]
+use std::collections::{HashMap, HashSet};
+#[cfg(feature = "trading")]
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+#[cfg(feature = "trading")]
+use crate::backtest::RestingOrder;
+#[cfg(feature = "trading")]
+use crate::broker::OrderTags;
+#[cfg(feature = "trading")]
+use crate::fx::Converter;
+#[cfg(feature = "trading")]
+use crate::models::{
+    estimate_cost, spread_cost, CostGuardConfig, ForecastMapping, MarginConfig, OrderSizer,
+    PassiveExecutionConfig, PassiveOrders, PassiveOutcome, PassiveReport, PositionSizer,
+    PositionSizing, TargetSmoother, TargetSmoothing, TargetTolerance, TradingSignal,
+    TrailingStopDistance, TrailingStopManager, VolatilityTarget,
+};
+#[cfg(feature = "trading")]
 use crate::oanda;
-use crate::oanda::objects::{Position, Settings};
+#[cfg(feature = "trading")]
+use crate::oanda::objects::{Position, PositionFill, PositionSide, Price, Transaction};
+#[cfg(feature = "trading")]
+use crate::oanda::order_client::OrderClient;
+#[cfg(feature = "trading")]
+use crate::util::Settings;
 
 // The portfolio construction model takes in a collection of trading signals, determines desired position sizes,
 // and returns a collection of trades to be executed by the execution model.
 // Currently, trades are simple enough that the portfolio construction model can just place them directly.
 
+// Net position a signal asks for: the configured units in the direction of the forecast, or flat.
+// Every path sizes through PositionSizer::target, so that simulated orders are the same as live ones.
+pub fn target_units(forecast: f64, units: f64) -> f64 {
+    if forecast > 0.0 {
+        units
+    } else if forecast < 0.0 {
+        -units
+    } else {
+        0.0
+    }
+}
+
+// A change to a position that the portfolio builder didn't make itself, e.g. a position closed
+// manually in the OANDA web UI, or one the cache missed and a reconcile found
+#[derive(Debug, Clone)]
+pub struct ExternalActivity {
+    pub time: String,
+    pub instrument: String,
+    pub units: f64,
+    pub transaction_id: Option<String>,
+    pub reason: Option<String>,
+}
+
+// A cost the account was charged (or credited) from the transaction stream that isn't on the
+// fill of an order we placed: the daily financing of a held position, or the commission and
+// financing of an external fill. The amount is the change to the balance, negative for a charge.
+#[derive(Debug, Clone)]
+pub struct AccountCost {
+    pub time: String,
+    pub instrument: String,
+    pub kind: CostKind,
+    pub amount: f64,
+    pub transaction_id: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum CostKind {
+    Financing,
+    Commission,
+}
+
+#[cfg(feature = "trading")]
 pub struct PortfolioBuilder<'a> {
     settings: &'a Settings,
     positions: Vec<Position>,
+    trailing_stops: Option<TrailingStopManager>,
+    order_sizer: Option<OrderSizer>,
+    position_sizer: PositionSizer,
+
+    // Orders adding margin are checked against these limits first, converting position values
+    // at the latest prices
+    margin: Option<MarginConfig>,
+    converter: Converter,
+
+    // Orders adding exposure are checked against this cost limit too, estimated from the latest
+    // streamed bid and ask or from the book OANDA quotes
+    cost_guard: Option<CostGuardConfig>,
+    quotes: HashMap<String, (f64, f64)>,
+
+    // Positions this close to their target are left alone rather than corrected
+    target_tolerance: TargetTolerance,
+    target_smoother: TargetSmoother,
+
+    // Whether the account keeps long and short legs separately rather than netting them
+    hedging: bool,
+
+    // Positions are cached locally and updated from order fills and the transaction stream.
+    // A full refresh only happens every reconcile interval, and returns the ID of the last
+    // transaction it reflects so that older transactions aren't applied twice.
+    snapshot_transaction_id: u64,
+    applied_transactions: HashSet<u64>,
+    reconcile_interval: Option<Duration>,
+    last_reconcile: Instant,
+
+    // Fills of orders we placed ourselves, anything else that changes a position is external.
+    // When a position is reduced externally, signals in its old direction are ignored until the
+    // strategy changes its mind, so a manually closed position isn't immediately re-opened.
+    own_transactions: HashSet<u64>,
+    external_activity: Vec<ExternalActivity>,
+    suppressed: HashMap<String, f64>,
+
+    // Financing and commissions charged outside our own fills, for the journal
+    costs: Vec<AccountCost>,
+
+    // Small orders posted as limit orders rather than crossing the spread, see
+    // PassiveExecutionConfig, and the posted orders that ended, for the journal
+    passive: Option<PassiveOrders<Transaction>>,
+    passive_reports: Vec<PassiveReport<Transaction>>,
+
+    // Orders go through this when given, otherwise through a client made for each order
+    order_client: Option<OrderClient>,
 }
 
+#[cfg(feature = "trading")]
 impl<'a> PortfolioBuilder<'a> {
     pub fn new(settings: &'a Settings) -> Self {
         PortfolioBuilder {
             settings,
             positions: Vec::new(),
+            trailing_stops: None,
+            order_sizer: None,
+            position_sizer: PositionSizer::new(&PositionSizing::default(), settings.trading.units)
+                .with_max_units(settings.risk.max_units),
+            margin: None,
+            converter: Converter::new(),
+            cost_guard: None,
+            quotes: HashMap::new(),
+            target_tolerance: TargetTolerance::default(),
+            target_smoother: TargetSmoother::new(&TargetSmoothing::default()),
+            hedging: false,
+            snapshot_transaction_id: 0,
+            applied_transactions: HashSet::new(),
+            reconcile_interval: None,
+            last_reconcile: Instant::now(),
+            own_transactions: HashSet::new(),
+            external_activity: Vec::new(),
+            suppressed: HashMap::new(),
+            costs: Vec::new(),
+            passive: None,
+            passive_reports: Vec::new(),
+            order_client: None,
         }
         // TODO: initialize positions
     }
 
+    // Place orders over a long-lived client with its connection kept open, see OrderClient
+    pub fn with_order_client(mut self, order_client: OrderClient) -> Self {
+        self.order_client = Some(order_client);
+        self
+    }
+
+    // Fully refresh the cached positions from OANDA at most this often
+    pub fn with_reconcile_interval(mut self, interval: Duration) -> Self {
+        self.reconcile_interval = Some(interval);
+        self
+    }
+
+    // Close positions once price retraces the given distance from its best level since entry
+    pub fn with_trailing_stop(mut self, distance: TrailingStopDistance) -> Self {
+        self.trailing_stops = Some(TrailingStopManager::new(distance));
+        self
+    }
+
+    // Round and validate order sizes against the instruments' trading limits
+    pub fn with_order_sizer(mut self, order_sizer: OrderSizer) -> Self {
+        self.order_sizer = Some(order_sizer);
+        self
+    }
+
+    pub fn with_position_sizing(mut self, sizing: &PositionSizing) -> Self {
+        self.position_sizer = PositionSizer::new(sizing, self.settings.trading.units)
+            .with_max_units(self.settings.risk.max_units);
+        self
+    }
+
+    // Scale positions to a portfolio volatility target, after with_position_sizing
+    pub fn with_volatility_target(mut self, target: Option<VolatilityTarget>) -> Self {
+        self.position_sizer = self.position_sizer.with_volatility_target(target);
+        self
+    }
+
+    // Map forecasts to positions as configured, after with_position_sizing
+    pub fn with_forecast_mapping(mut self, mapping: &ForecastMapping) -> Self {
+        self.position_sizer = self.position_sizer.with_forecast_mapping(mapping);
+        self
+    }
+
+    pub fn with_margin(mut self, margin: Option<MarginConfig>) -> Self {
+        self.margin = margin;
+        self
+    }
+
+    pub fn with_cost_guard(mut self, cost_guard: Option<CostGuardConfig>) -> Self {
+        self.cost_guard = cost_guard;
+        self
+    }
+
+    pub fn with_target_tolerance(mut self, tolerance: &TargetTolerance) -> Self {
+        self.target_tolerance = tolerance.clone();
+        self
+    }
+
+    pub fn with_target_smoothing(mut self, smoothing: &TargetSmoothing) -> Self {
+        self.target_smoother = TargetSmoother::new(smoothing);
+        self
+    }
+
+    // Post small orders as limit orders, on netting accounts only
+    pub fn with_passive_execution(mut self, config: Option<PassiveExecutionConfig>) -> Self {
+        self.passive = config.map(PassiveOrders::new);
+        self
+    }
+
+    // Units to order for the given computed units, or None if no order should be placed
+    fn size_order(&self, instrument: &str, units: f64) -> Option<f64> {
+        match &self.order_sizer {
+            Some(order_sizer) => order_sizer.size(instrument, units),
+            None if units != 0.0 => Some(units),
+            None => None,
+        }
+    }
+
+    async fn market_order(
+        &self,
+        instrument: &str,
+        units: f64,
+        position_fill: PositionFill,
+        tags: &OrderTags,
+    ) -> Result<Option<Transaction>, Box<dyn std::error::Error>> {
+        match &self.order_client {
+            Some(order_client) => {
+                order_client
+                    .market_order(instrument, units, position_fill, tags)
+                    .await
+            }
+            None => {
+                oanda::place_market_order_with_fill(
+                    instrument,
+                    units,
+                    position_fill,
+                    tags,
+                    &self.settings.credentials.oanda,
+                )
+                .await
+            }
+        }
+    }
+
     // Update the positions held by the portfolio builder to reflect the current state of the account
     pub async fn update_positions(&mut self) -> Result<(), Box<dyn std::error::Error>> {
-        self.positions = oanda::get_positions(&self.settings.oanda).await?;
+        self.refresh_positions(None).await
+    }
+
+    // Work out what became of an order whose state was unknown (see OrderStateUnknownError) from
+    // its client order ID, returning its fill if it had one. If even that fails the positions
+    // are refreshed, taking a change to the instrument's position as the order's rather than as
+    // external activity, so it isn't suppressed as if someone else had made it.
+    pub async fn resolve_unknown_order(
+        &mut self,
+        instrument: &str,
+        client_order_id: &str,
+    ) -> Result<Vec<Transaction>, Box<dyn std::error::Error>> {
+        match oanda::get_order_outcome(client_order_id, &self.settings.credentials.oanda).await {
+            Ok(outcome) => {
+                let fills: Vec<Transaction> = outcome.flatten().into_iter().collect();
+                self.apply_order_fills(&fills);
+                Ok(fills)
+            }
+            Err(e) => {
+                log::warn!(
+                    "[{}] Couldn't look up order {}: {}, reconciling positions...",
+                    instrument,
+                    client_order_id,
+                    e
+                );
+                self.refresh_positions(Some((instrument, client_order_id)))
+                    .await?;
+                Ok(Vec::new())
+            }
+        }
+    }
+
+    // Replace the cached positions with the account's, recording any difference as external
+    // activity unless it's in the instrument of an order whose state is unknown
+    async fn refresh_positions(
+        &mut self,
+        unknown_order: Option<(&str, &str)>,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let snapshot = oanda::get_positions_snapshot(&self.settings.credentials.oanda).await?;
+
+        // The cache should always agree with OANDA, if it doesn't we've missed a transaction.
+        // Positions closed entirely are missing from the snapshot, so check the cache against it too.
+        if self.snapshot_transaction_id > 0 {
+            let mut instruments: Vec<String> = snapshot
+                .positions
+                .iter()
+                .chain(self.positions.iter())
+                .map(|p| p.instrument.clone())
+                .collect();
+            instruments.sort();
+            instruments.dedup();
+
+            for instrument in instruments {
+                let cached_units = self.net_units(&instrument);
+                let account_units = snapshot
+                    .positions
+                    .iter()
+                    .find(|p| p.instrument == instrument)
+                    .map(|p| p.units())
+                    .unwrap_or(0.0);
+                if cached_units != account_units {
+                    if let Some((_, client_order_id)) =
+                        unknown_order.filter(|(unknown, _)| *unknown == instrument)
+                    {
+                        log::warn!(
+                            "[{}] Taking the change of {} units as order {}'s",
+                            instrument,
+                            account_units - cached_units,
+                            client_order_id
+                        );
+                        continue;
+                    }
+                    log::warn!(
+                        "[{}] Cached position of {} units differs from account position of {} units",
+                        instrument,
+                        cached_units,
+                        account_units
+                    );
+                    self.record_external(
+                        ExternalActivity {
+                            time: chrono::Utc::now().to_rfc3339(),
+                            instrument,
+                            units: account_units - cached_units,
+                            transaction_id: None,
+                            reason: Some("RECONCILE".to_string()),
+                        },
+                        cached_units,
+                        account_units,
+                    );
+                }
+            }
+        }
+
+        self.snapshot_transaction_id = snapshot.last_transaction_id.parse().unwrap_or(0);
+        let snapshot_transaction_id = self.snapshot_transaction_id;
+        self.applied_transactions
+            .retain(|id| *id > snapshot_transaction_id);
+        self.own_transactions
+            .retain(|id| *id > snapshot_transaction_id);
+        self.positions = snapshot.positions;
+        self.last_reconcile = Instant::now();
         Ok(())
     }
 
+    // Refresh the cached positions if the reconcile interval has passed
+    pub async fn reconcile_if_due(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        if let Some(interval) = self.reconcile_interval {
+            if self.last_reconcile.elapsed() >= interval {
+                log::debug!("Reconciling cached positions with account...");
+                self.update_positions().await?;
+            }
+        }
+        Ok(())
+    }
+
+    // Apply a transaction from the transaction stream to the cached positions
+    // Fills of our own orders are applied from the order response first, so each transaction is
+    // only applied once, and any other fill is external activity on the account
+    pub fn apply_transaction(&mut self, transaction: &Transaction) {
+        if transaction.is_daily_financing() {
+            self.apply_financing(transaction);
+        }
+        if transaction.kind == "ORDER_CANCEL" {
+            self.apply_cancel(transaction);
+        }
+        self.apply_fill(transaction, false);
+    }
+
+    // A resting order OANDA cancelled itself, e.g. at its GTD time
+    fn apply_cancel(&mut self, transaction: &Transaction) {
+        let passive = match &mut self.passive {
+            Some(passive) => passive,
+            None => return,
+        };
+        let instrument = match transaction
+            .order_id
+            .as_deref()
+            .and_then(|order_id| passive.instrument_of(order_id))
+        {
+            Some(instrument) => instrument,
+            None => return,
+        };
+        log::warn!(
+            "[{}] Posted order was cancelled by OANDA: {}",
+            instrument,
+            transaction.reason.as_deref().unwrap_or("unknown reason")
+        );
+        self.passive_reports
+            .extend(passive.finish(&instrument, PassiveOutcome::Cancelled));
+    }
+
+    fn apply_financing(&mut self, transaction: &Transaction) {
+        let id = match transaction.id_number() {
+            Some(id) => id,
+            None => return,
+        };
+        if id <= self.snapshot_transaction_id || !self.applied_transactions.insert(id) {
+            return;
+        }
+        for position in &transaction.position_financings {
+            if position.financing == 0.0 {
+                continue;
+            }
+            log::info!(
+                "[{}] Financing of {:.2}",
+                position.instrument,
+                position.financing
+            );
+            self.costs.push(AccountCost {
+                time: transaction.time.clone(),
+                instrument: position.instrument.clone(),
+                kind: CostKind::Financing,
+                amount: position.financing,
+                transaction_id: transaction.id.clone(),
+            });
+        }
+    }
+
+    fn apply_fill(&mut self, transaction: &Transaction, own: bool) {
+        if !transaction.is_order_fill() {
+            return;
+        }
+
+        let id = match transaction.id_number() {
+            Some(id) => id,
+            None => return,
+        };
+        if own {
+            self.own_transactions.insert(id);
+        }
+        // Fills of our resting orders are our own too, reported once the order ends
+        let resting = transaction
+            .order_id
+            .as_deref()
+            .and_then(|order_id| self.passive.as_ref()?.instrument_of(order_id));
+        if resting.is_some() {
+            self.own_transactions.insert(id);
+        }
+        if id <= self.snapshot_transaction_id || !self.applied_transactions.insert(id) {
+            return;
+        }
+
+        let instrument = match &transaction.instrument {
+            Some(instrument) => instrument,
+            None => return,
+        };
+
+        let index = match self
+            .positions
+            .iter()
+            .position(|p| &p.instrument == instrument)
+        {
+            Some(index) => index,
+            None => {
+                self.positions.push(Position::new(instrument));
+                self.positions.len() - 1
+            }
+        };
+        let units_before = self.positions[index].units();
+        self.positions[index].apply_fill(transaction);
+        let units_after = self.positions[index].units();
+        if let (Some(resting), Some(passive)) = (&resting, &mut self.passive) {
+            let units = transaction.units.unwrap_or(units_after - units_before);
+            if passive.fill(resting, units, transaction.clone()) {
+                self.passive_reports
+                    .extend(passive.finish(resting, PassiveOutcome::Filled));
+            }
+        }
+        if !self.own_transactions.contains(&id) {
+            let costs = [
+                (CostKind::Commission, -transaction.commission.unwrap_or(0.0)),
+                (CostKind::Financing, transaction.financing.unwrap_or(0.0)),
+            ];
+            for (kind, amount) in costs {
+                if amount != 0.0 {
+                    self.costs.push(AccountCost {
+                        time: transaction.time.clone(),
+                        instrument: instrument.clone(),
+                        kind,
+                        amount,
+                        transaction_id: transaction.id.clone(),
+                    });
+                }
+            }
+            self.record_external(
+                ExternalActivity {
+                    time: transaction.time.clone(),
+                    instrument: instrument.clone(),
+                    units: transaction.units.unwrap_or(units_after - units_before),
+                    transaction_id: transaction.id.clone(),
+                    reason: transaction.reason.clone(),
+                },
+                units_before,
+                units_after,
+            );
+        }
+        log::debug!(
+            "[{}] Applied fill {}, position is now {} units",
+            instrument,
+            id,
+            self.positions[index].units()
+        );
+    }
+
+    pub fn apply_transactions(&mut self, transactions: &[Transaction]) {
+        for transaction in transactions {
+            self.apply_transaction(transaction);
+        }
+    }
+
+    // Apply the fills from the responses to orders we placed
+    fn apply_order_fills(&mut self, fills: &[Transaction]) {
+        for fill in fills {
+            self.apply_fill(fill, true);
+        }
+    }
+
+    fn record_external(&mut self, activity: ExternalActivity, units_before: f64, units_after: f64) {
+        log::warn!(
+            "[{}] External change of {} units ({}), position is now {} units",
+            activity.instrument,
+            activity.units,
+            activity.reason.as_deref().unwrap_or("unknown reason"),
+            units_after
+        );
+
+        // Reduced or closed: don't fight it by re-entering in the same direction
+        let reduced = units_before != 0.0
+            && (units_after.abs() < units_before.abs()
+                || units_after.signum() != units_before.signum());
+        if reduced {
+            self.suppressed
+                .insert(activity.instrument.clone(), units_before.signum());
+        }
+        self.external_activity.push(activity);
+    }
+
+    // External activity found since the last call, for alerting and the journal
+    pub fn take_external_activity(&mut self) -> Vec<ExternalActivity> {
+        std::mem::take(&mut self.external_activity)
+    }
+
+    // Costs charged since the last call, for the journal
+    pub fn take_costs(&mut self) -> Vec<AccountCost> {
+        std::mem::take(&mut self.costs)
+    }
+
+    // Posted orders that ended since the last call, for the journal
+    pub fn take_passive_reports(&mut self) -> Vec<PassiveReport<Transaction>> {
+        std::mem::take(&mut self.passive_reports)
+    }
+
+    pub fn resting_order(&self, instrument: &str) -> Option<&RestingOrder> {
+        self.passive.as_ref()?.get(instrument)
+    }
+
+    // Post a small order as a limit order when passive execution is configured, otherwise send
+    // it at the market. A posted order's later fills come with its report rather than from here,
+    // only a fill it gets straight away is returned.
+    async fn order(
+        &mut self,
+        instrument: &str,
+        units: f64,
+        forecast: f64,
+        tags: &OrderTags,
+    ) -> Result<Option<Transaction>, Box<dyn std::error::Error>> {
+        let quote = self.quotes.get(instrument).copied();
+        let now = chrono::Utc::now();
+        let order = match (&self.passive, quote) {
+            (Some(passive), Some((bid, ask))) if passive.config().applies(units) => {
+                let time = now.timestamp_millis() as u64;
+                passive.config().order(instrument, units, bid, ask, time)
+            }
+            _ => {
+                return self
+                    .market_order(instrument, units, PositionFill::Default, tags)
+                    .await
+            }
+        };
+        // OANDA only cancels it if the trader stops before it times out
+        let timeout = self
+            .passive
+            .as_ref()
+            .map_or(0, |passive| passive.config().timeout);
+        let gtd_time = now + chrono::Duration::seconds(timeout as i64 + 60);
+        let (order_id, fill) = oanda::place_limit_order(
+            instrument,
+            units,
+            order.price,
+            &gtd_time.to_rfc3339(),
+            tags,
+            &self.settings.credentials.oanda,
+        )
+        .await?;
+        // Applied before the order is on the book, so it's journaled with the signal rather than
+        // with the order's report
+        let filled = match &fill {
+            Some(fill) => {
+                self.apply_order_fills(std::slice::from_ref(fill));
+                fill.units.unwrap_or(0.0)
+            }
+            None => 0.0,
+        };
+        if (units - filled).abs() < 1e-9 {
+            return Ok(fill);
+        }
+        if let Some(passive) = &mut self.passive {
+            passive.post(RestingOrder {
+                order_id: Some(order_id),
+                forecast,
+                tags: tags.clone(),
+                filled,
+                ..order
+            });
+        }
+        Ok(fill)
+    }
+
+    // Cancel the instrument's resting order and take it off the book. None if there isn't one,
+    // or if it turns out to have filled and its fill already reported it.
+    async fn withdraw(
+        &mut self,
+        instrument: &str,
+        outcome: PassiveOutcome,
+    ) -> Result<Option<PassiveReport<Transaction>>, Box<dyn std::error::Error>> {
+        let order_id = match self.resting_order(instrument) {
+            Some(order) => order.order_id.clone(),
+            None => return Ok(None),
+        };
+        if let Some(order_id) = order_id {
+            if !oanda::cancel_order(&order_id, &self.settings.credentials.oanda).await? {
+                // It filled or went before it could be cancelled. Its fill is looked up rather
+                // than waited for, as one older than the last resync is never applied to it.
+                let fill =
+                    oanda::get_order_fill(&order_id, &self.settings.credentials.oanda).await?;
+                log::info!(
+                    "[{}] Posted order {} is no longer pending, {}",
+                    instrument,
+                    order_id,
+                    if fill.is_some() { "filled" } else { "unfilled" }
+                );
+                if let Some(fill) = fill {
+                    self.apply_order_fills(std::slice::from_ref(&fill));
+                    let passive = match &mut self.passive {
+                        Some(passive) => passive,
+                        None => return Ok(None),
+                    };
+                    let remaining = match passive.get(instrument) {
+                        Some(order) => order.remaining(),
+                        None => return Ok(None),
+                    };
+                    passive.fill(instrument, remaining, fill);
+                    return Ok(passive.finish(instrument, PassiveOutcome::Filled));
+                }
+            }
+        }
+        Ok(self
+            .passive
+            .as_mut()
+            .and_then(|passive| passive.finish(instrument, outcome)))
+    }
+
+    // Cancel the instrument's resting order if it has one. One that filled before it could be
+    // cancelled is reported as filled, and the position then includes its fill.
+    pub async fn cancel_resting(
+        &mut self,
+        instrument: &str,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        if let Some(report) = self.withdraw(instrument, PassiveOutcome::Cancelled).await? {
+            self.passive_reports.push(report);
+        }
+        Ok(())
+    }
+
+    // Send what's left of the instrument's resting order at the market once it has timed out
+    async fn check_resting(&mut self, instrument: &str) -> Result<(), Box<dyn std::error::Error>> {
+        let now = chrono::Utc::now().timestamp_millis() as u64;
+        if !self
+            .passive
+            .as_ref()
+            .is_some_and(|passive| passive.timed_out(instrument, now))
+        {
+            return Ok(());
+        }
+        let mut report = match self
+            .withdraw(instrument, PassiveOutcome::MarketFallback)
+            .await?
+        {
+            Some(report) => report,
+            None => return Ok(()),
+        };
+        let fill = match self.size_order(instrument, report.order.remaining()) {
+            Some(units) => {
+                let tags = report.order.tags.clone();
+                self.market_order(instrument, units, PositionFill::Default, &tags)
+                    .await
+            }
+            None => Ok(None),
+        };
+        // The order is off the book either way
+        let fill = match fill {
+            Ok(fill) => fill,
+            Err(e) => {
+                self.passive_reports.push(report);
+                return Err(e);
+            }
+        };
+        let fills: Vec<Transaction> = fill.into_iter().collect();
+        self.apply_order_fills(&fills);
+        report.fills.extend(fills);
+        self.passive_reports.push(report);
+        Ok(())
+    }
+
+    // Net units held in an instrument
+    pub fn net_units(&self, instrument: &str) -> f64 {
+        self.positions
+            .iter()
+            .find(|p| p.instrument == instrument)
+            .map(|p| p.units())
+            .unwrap_or(0.0)
+    }
+
+    pub fn settings(&self) -> &'a Settings {
+        self.settings
+    }
+
+    // The signal's full, unsmoothed target position, or None if the instrument can't be sized yet
+    pub fn target(&mut self, signal: &TradingSignal) -> Option<f64> {
+        self.position_sizer
+            .target(&signal.instrument, signal.forecast)
+    }
+
+    // Whether the instrument's position is already within tolerance of the signal's full,
+    // unsmoothed target
+    pub fn target_reached(&mut self, signal: &TradingSignal) -> bool {
+        let current = self.net_units(&signal.instrument);
+        self.target(signal)
+            .is_some_and(|target| self.target_tolerance.within(current, target))
+    }
+
+    // Instruments with an open position, and their net units
+    pub fn open_positions(&self) -> Vec<(String, f64)> {
+        self.positions
+            .iter()
+            .filter(|p| {
+                p.leg_units(PositionSide::Long) > 0.0 || p.leg_units(PositionSide::Short) > 0.0
+            })
+            .map(|p| (p.instrument.clone(), p.units()))
+            .collect()
+    }
+
+    // Close every leg of the position in an instrument, returning the fills
+    pub async fn close(
+        &mut self,
+        instrument: &str,
+    ) -> Result<Vec<Transaction>, Box<dyn std::error::Error>> {
+        let mut fills = Vec::new();
+        for side in [PositionSide::Long, PositionSide::Short] {
+            if self.leg_units(instrument, side) > 0.0 {
+                fills.extend(
+                    oanda::close_position(instrument, side, None, &self.settings.credentials.oanda)
+                        .await?,
+                );
+            }
+        }
+        self.apply_order_fills(&fills);
+        Ok(fills)
+    }
+
+    // Check whether the account is in hedging mode, which changes how positions are adjusted
+    pub async fn update_account_mode(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        let summary = oanda::get_account_summary(&self.settings.credentials.oanda).await?;
+        self.hedging = summary.hedging_enabled;
+        log::info!(
+            "Account is in {} mode",
+            if self.hedging { "hedging" } else { "netting" }
+        );
+        Ok(())
+    }
+
+    pub fn is_hedging(&self) -> bool {
+        self.hedging
+    }
+
+    // The account's NAV, in the account currency
+    pub async fn equity(&self) -> Result<f64, Box<dyn std::error::Error>> {
+        Ok(oanda::get_account_summary(&self.settings.credentials.oanda)
+            .await?
+            .nav)
+    }
+
+    // The currency the account is valued in
+    pub async fn account_currency(&self) -> Result<String, Box<dyn std::error::Error>> {
+        Ok(oanda::get_account_summary(&self.settings.credentials.oanda)
+            .await?
+            .currency)
+    }
+
+    // Unrealized profit of each open position as OANDA has it, in the account currency
+    pub async fn position_pl(&self) -> Result<Vec<(String, f64)>, Box<dyn std::error::Error>> {
+        Ok(oanda::get_positions(&self.settings.credentials.oanda)
+            .await?
+            .into_iter()
+            .filter(|p| p.long.units != 0.0 || p.short.units != 0.0)
+            .map(|p| {
+                let pl = p.unrealized_pl();
+                (p.instrument, pl)
+            })
+            .collect())
+    }
+
+    pub fn set_risk_scale(&mut self, scale: f64) {
+        self.position_sizer.set_risk_scale(scale);
+    }
+
+    // Whether positions are sized differently since the last call, see PositionSizer
+    pub fn take_rescaled(&mut self) -> bool {
+        self.position_sizer.take_rescaled()
+    }
+
+    // Size of one leg of the position in an instrument, always positive
+    pub fn leg_units(&self, instrument: &str, side: PositionSide) -> f64 {
+        self.positions
+            .iter()
+            .find(|p| p.instrument == instrument)
+            .map(|p| p.leg_units(side))
+            .unwrap_or(0.0)
+    }
+
     // Given a trading signal, determine the desired position size and either buy or sell to reach that position
+    // Returns the fills of any orders placed
     // TODO: in the future, this should produce a trade to be executed by the execution model
     // TODO: in the future, this should account for confidence in the signal
     pub async fn handle_signal(
         &mut self,
         signal: TradingSignal,
-    ) -> Result<(), Box<dyn std::error::Error>> {
+    ) -> Result<Vec<Transaction>, Box<dyn std::error::Error>> {
+        self.handle_tagged_signal(signal, &OrderTags::default())
+            .await
+    }
+
+    // As handle_signal, with the orders carrying tags tracing them back to the strategy
+    pub async fn handle_tagged_signal(
+        &mut self,
+        signal: TradingSignal,
+        tags: &OrderTags,
+    ) -> Result<Vec<Transaction>, Box<dyn std::error::Error>> {
+        // A newer signal replaces the instrument's resting order, sized from the position after
+        // whatever it filled
+        self.cancel_resting(&signal.instrument).await?;
+
+        if let Some(direction) = self.suppressed.get(&signal.instrument) {
+            if signal.forecast.signum() == *direction && signal.forecast != 0.0 {
+                log::info!(
+                    "[{}] Ignoring signal of {}, the position was reduced externally",
+                    signal.instrument,
+                    signal.forecast
+                );
+                return Ok(Vec::new());
+            }
+            self.suppressed.remove(&signal.instrument);
+        }
+
+        let target = match self
+            .position_sizer
+            .target(&signal.instrument, signal.forecast)
+        {
+            Some(target) => target,
+            None => {
+                log::warn!(
+                    "[{}] Can't size a position without conversion rates yet, ignoring signal",
+                    signal.instrument
+                );
+                return Ok(Vec::new());
+            }
+        };
+        let current = self.net_units(&signal.instrument);
+        let target = self
+            .target_smoother
+            .smooth(&signal.instrument, current, target);
+        let target = match self.check_margin(&signal.instrument, target).await? {
+            Some(target) => target,
+            None => return Ok(Vec::new()),
+        };
+        let target = match self.check_cost(&signal.instrument, target).await? {
+            Some(target) => target,
+            None => return Ok(Vec::new()),
+        };
+        if self.hedging {
+            return self.handle_signal_hedged(signal, target, tags).await;
+        }
+
+        let fill;
         let current_position = self
             .positions
             .iter()
             .find(|p| p.instrument == signal.instrument);
         if let Some(position) = current_position {
             // Determine the desired position size
-            let desired_position = if signal.forecast > 0.0 {
-                self.settings.units
-            } else if signal.forecast < 0.0 {
-                -self.settings.units
-            } else {
-                0.0
-            };
+            let desired_position = target;
 
             println!("Desired position: {}", desired_position);
             println!("Current position: {}", position.units());
 
+            // Close enough to the desired position already, a small correction would only churn
+            if self
+                .target_tolerance
+                .within(position.units(), desired_position)
+            {
+                return Ok(Vec::new());
+            }
+
             // Determine the required changes to the current position to reach the desired position
             let required_units = desired_position - position.units();
             println!("Required units: {}", required_units);
@@ -57,44 +943,292 @@ impl<'a> PortfolioBuilder<'a> {
             // If no changes are required, do nothing
             // This automatically handles the case where the desired position is 0.0,
             // as well as preventing repeated signals from increasing the position size
-            if required_units == 0.0 {
-                return Ok(());
-            } else {
-                oanda::place_market_order(&signal.instrument, required_units, &self.settings.oanda)
-                    .await?;
-            }
+            let required_units = match self.size_order(&signal.instrument, required_units) {
+                Some(units) => units,
+                None => return Ok(Vec::new()),
+            };
+            fill = self
+                .order(&signal.instrument, required_units, signal.forecast, tags)
+                .await?;
         } else {
             // If no position exists, open a new position
-            if signal.forecast > 0.0 {
-                oanda::place_market_order(
-                    &signal.instrument,
-                    self.settings.units,
-                    &self.settings.oanda,
-                )
+            // A flat forecast (e.g. strategies cancelling out) leaves the instrument flat
+            let units = target;
+            let units = match self.size_order(&signal.instrument, units) {
+                Some(units) => units,
+                None => return Ok(Vec::new()),
+            };
+            fill = self
+                .order(&signal.instrument, units, signal.forecast, tags)
                 .await?;
+        }
+
+        // Update the positions held by the portfolio builder to reflect the fill
+        let fills: Vec<Transaction> = fill.into_iter().collect();
+        self.apply_order_fills(&fills);
+        Ok(fills)
+    }
+
+    // The position the margin limits allow on the way to `target` units: all of it if it stays
+    // within them, None if it doesn't. Margin used and NAV come from the account, so this costs a
+    // request for every order that adds margin; the margin the order adds is estimated with the
+    // configured leverage, and refused if it can't be. A flip the limits refuse still closes the
+    // position, refusing only the opposite one.
+    async fn check_margin(
+        &self,
+        instrument: &str,
+        target: f64,
+    ) -> Result<Option<f64>, Box<dyn std::error::Error>> {
+        let margin = match &self.margin {
+            Some(margin) => margin,
+            None => return Ok(Some(target)),
+        };
+        let current = self.net_units(instrument);
+        let flip = current * target < 0.0;
+        if !flip && target.abs() <= current.abs() {
+            return Ok(Some(target));
+        }
+
+        let account = oanda::get_account_summary(&self.settings.credentials.oanda).await?;
+        let change = margin
+            .position_margin(&self.converter, instrument, target, &account.currency)
+            .zip(margin.position_margin(&self.converter, instrument, current, &account.currency))
+            .map(|(after, before)| after - before);
+        let result = match change {
+            Some(change) => margin.check(
+                account.margin_used,
+                account.margin_used + change,
+                account.nav,
+            ),
+            None => Err(format!("no conversion rate into {} yet", account.currency)),
+        };
+        match result {
+            Ok(()) => Ok(Some(target)),
+            Err(reason) if flip => {
+                log::warn!(
+                    "[{}] Closing rather than moving to {} units: {}",
+                    instrument,
+                    target,
+                    reason
+                );
+                Ok(Some(0.0))
+            }
+            Err(reason) => {
+                log::warn!(
+                    "[{}] Not moving to {} units: {}",
+                    instrument,
+                    target,
+                    reason
+                );
+                Ok(None)
+            }
+        }
+    }
+
+    // The position the cost guard allows on the way to `target` units: all of it if the exposure
+    // it adds costs no more than the guard's limit to get in and out of, None if it doesn't.
+    // Reducing the position is always allowed, so a flip the guard refuses still closes the
+    // position, refusing only the opposite one.
+    async fn check_cost(
+        &self,
+        instrument: &str,
+        target: f64,
+    ) -> Result<Option<f64>, Box<dyn std::error::Error>> {
+        let guard = match &self.cost_guard {
+            Some(guard) => guard,
+            None => return Ok(Some(target)),
+        };
+        let current = self.net_units(instrument);
+        // On a flip the exposure added is the whole opposite position, from flat
+        let from = if current * target < 0.0 { 0.0 } else { current };
+        if target.abs() <= from.abs() {
+            return Ok(Some(target));
+        }
+
+        let units = target - from;
+        let estimate = if guard.estimate {
+            let instruments = [instrument.to_string()];
+            let depth =
+                oanda::get_price_depth(&instruments, &self.settings.credentials.oanda).await?;
+            let financing = if guard.holding_days > 0.0 {
+                oanda::get_instruments(&instruments, &self.settings.credentials.oanda)
+                    .await?
+                    .into_iter()
+                    .next()
+                    .and_then(|instrument| instrument.financing)
+            } else {
+                None
+            };
+            depth.first().and_then(|depth| {
+                estimate_cost(depth, financing.as_ref(), units, guard.holding_days)
+            })
+        } else {
+            self.quotes
+                .get(instrument)
+                .and_then(|(bid, ask)| spread_cost(*bid, *ask))
+        };
+
+        let result = match &estimate {
+            Some(estimate) => {
+                log::info!(
+                    "[{}] Estimated round trip cost of {} units: {:.1} bps",
+                    instrument,
+                    units,
+                    estimate.total() * 10_000.0
+                );
+                guard.check(instrument, estimate)
+            }
+            None => Err(
+                "its cost can't be estimated, there is no price or the book is too thin"
+                    .to_string(),
+            ),
+        };
+        match result {
+            Ok(()) => Ok(Some(target)),
+            Err(reason) if from != current => {
+                log::warn!(
+                    "[{}] Closing rather than moving to {} units: {}",
+                    instrument,
+                    target,
+                    reason
+                );
+                Ok(Some(from))
+            }
+            Err(reason) => {
+                log::warn!(
+                    "[{}] Not moving to {} units: {}",
+                    instrument,
+                    target,
+                    reason
+                );
+                Ok(None)
+            }
+        }
+    }
+
+    // On hedging accounts each leg is adjusted on its own: the signal's side is opened with OPEN_ONLY
+    // orders and the opposite side is closed explicitly, since an opposing order would open a new leg
+    async fn handle_signal_hedged(
+        &mut self,
+        signal: TradingSignal,
+        target: f64,
+        tags: &OrderTags,
+    ) -> Result<Vec<Transaction>, Box<dyn std::error::Error>> {
+        let mut fills = Vec::new();
+        let desired_long = target.max(0.0);
+        let desired_short = (-target).max(0.0);
+
+        for (side, desired) in [
+            (PositionSide::Long, desired_long),
+            (PositionSide::Short, desired_short),
+        ] {
+            let leg_units = self.leg_units(&signal.instrument, side);
+            if desired != 0.0 && self.target_tolerance.within(leg_units, desired) {
+                continue;
+            }
+            let required_units = desired - leg_units;
+            // Closing a whole leg is always allowed, anything else is sized like a netting order
+            let required_units = if desired == 0.0 {
+                required_units
             } else {
-                oanda::place_market_order(
+                match self.size_order(&signal.instrument, required_units) {
+                    Some(units) => units,
+                    None => continue,
+                }
+            };
+            if required_units > 0.0 {
+                let fill = self
+                    .market_order(
+                        &signal.instrument,
+                        side.sign() * required_units,
+                        PositionFill::OpenOnly,
+                        tags,
+                    )
+                    .await?;
+                let fill: Vec<Transaction> = fill.into_iter().collect();
+                self.apply_order_fills(&fill);
+                fills.extend(fill);
+            } else if required_units < 0.0 {
+                let units = if desired == 0.0 {
+                    None
+                } else {
+                    Some(-required_units)
+                };
+                let closes = oanda::close_position(
                     &signal.instrument,
-                    -self.settings.units,
-                    &self.settings.oanda,
+                    side,
+                    units,
+                    &self.settings.credentials.oanda,
                 )
                 .await?;
+                self.apply_order_fills(&closes);
+                fills.extend(closes);
             }
         }
+        Ok(fills)
+    }
 
-        // Update the positions held by the portfolio builder to reflect the current state of the account
-        self.update_positions().await?;
-        Ok(())
+    // Given a new price, close the position in that instrument if its trailing stop has been hit,
+    // returning the fills of the close
+    pub async fn handle_price(
+        &mut self,
+        price: &Price,
+    ) -> Result<Vec<Transaction>, Box<dyn std::error::Error>> {
+        self.position_sizer.update(price);
+        self.target_smoother.update(price);
+        self.converter.update(price);
+        self.quotes.insert(
+            price.instrument.clone(),
+            (price.bid as f64, price.ask as f64),
+        );
+        self.check_resting(&price.instrument).await?;
+        let position_units = self.net_units(&price.instrument);
+        let trailing_stops = match &mut self.trailing_stops {
+            Some(trailing_stops) => trailing_stops,
+            None => return Ok(Vec::new()),
+        };
+
+        let mut fills = Vec::new();
+        if let Some(exit_units) = trailing_stops.tick(price, position_units) {
+            if self.hedging {
+                // Flatten both legs rather than opening an opposing one
+                for side in [PositionSide::Long, PositionSide::Short] {
+                    if self.leg_units(&price.instrument, side) > 0.0 {
+                        fills.extend(
+                            oanda::close_position(
+                                &price.instrument,
+                                side,
+                                None,
+                                &self.settings.credentials.oanda,
+                            )
+                            .await?,
+                        );
+                    }
+                }
+            } else {
+                let tags = OrderTags {
+                    strategy: "trailingStop".to_string(),
+                    ..OrderTags::default()
+                };
+                fills.extend(
+                    self.market_order(&price.instrument, exit_units, PositionFill::Default, &tags)
+                        .await?,
+                );
+            }
+            self.apply_order_fills(&fills);
+        }
+        Ok(fills)
     }
 
     // Given a collection of trading signals, determine the desired position sizes and either buy or sell to reach those positions
     pub async fn handle_signals(
         &mut self,
         signals: Vec<TradingSignal>,
-    ) -> Result<(), Box<dyn std::error::Error>> {
+    ) -> Result<Vec<Transaction>, Box<dyn std::error::Error>> {
+        let mut fills = Vec::new();
         for signal in signals {
-            self.handle_signal(signal).await?;
+            fills.extend(self.handle_signal(signal).await?);
         }
-        Ok(())
+        Ok(fills)
     }
 }
@@ -0,0 +1,440 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::fx::{split_instrument, Converter};
+use crate::oanda::objects::Price;
+
+use super::{target_units, SignalValidity, VolatilityState, VolatilityTarget, VolatilityTargeter};
+
+// How many units a full position in each instrument is, set by "positionSizing" in the trading
+// config, e.g. {"mode": "units", "overrides": {"USD_JPY": 5000}} or
+// {"mode": "notional", "notional": 10000, "currency": "USD"}
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "mode", rename_all = "camelCase")]
+pub enum PositionSizing {
+    // The same number of units for every instrument (settings.json's trading units), unless
+    // overridden
+    Units {
+        #[serde(default)]
+        overrides: HashMap<String, f64>,
+    },
+
+    // Units worth `notional` in `currency`, converted at the latest rates, e.g. $10,000 of
+    // EUR_USD and of USD_JPY alike. Overridden instruments use a fixed number of units instead.
+    Notional {
+        notional: f64,
+        #[serde(default = "default_currency")]
+        currency: String,
+
+        // Decimal places the converted units are rounded to
+        #[serde(default)]
+        precision: i32,
+
+        #[serde(default)]
+        overrides: HashMap<String, f64>,
+    },
+}
+
+fn default_currency() -> String {
+    "USD".to_string()
+}
+
+impl Default for PositionSizing {
+    fn default() -> Self {
+        PositionSizing::Units {
+            overrides: HashMap::new(),
+        }
+    }
+}
+
+// How a forecast maps to a share of the full position, "forecastMapping" in the trading config,
+// e.g. {"type": "clippedLinear", "scale": 2.0},
+// {"type": "step", "steps": [{"forecast": 0.3, "position": 0.5}, {"forecast": 0.7, "position": 1}]}
+// or {"type": "sigmoid", "steepness": 3}. By default any forecast is a full position in its
+// direction. Shares are of the full position, so 1 is a full long and -1 a full short.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum ForecastMapping {
+    // A full position in the direction of the forecast, flat at 0
+    #[default]
+    Sign,
+
+    // forecast * scale, however large that gets
+    Linear {
+        #[serde(default = "default_mapping_scale")]
+        scale: f64,
+    },
+
+    // forecast * scale, but never more than `max` either way
+    ClippedLinear {
+        #[serde(default = "default_mapping_scale")]
+        scale: f64,
+
+        #[serde(default = "default_mapping_max")]
+        max: f64,
+    },
+
+    // The position of the largest step the forecast reaches either way, flat below the first
+    Step {
+        steps: Vec<ForecastStep>,
+    },
+
+    // An S-curve through 0, scaled so that a forecast of ±1 is a full position. The steeper it
+    // is, the closer weak forecasts get to a full position.
+    Sigmoid {
+        #[serde(default = "default_steepness")]
+        steepness: f64,
+    },
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ForecastStep {
+    pub forecast: f64,
+    pub position: f64,
+}
+
+fn default_mapping_scale() -> f64 {
+    1.0
+}
+
+fn default_mapping_max() -> f64 {
+    1.0
+}
+
+fn default_steepness() -> f64 {
+    2.0
+}
+
+impl ForecastMapping {
+    // Share of the full position a forecast asks for, none for a flat forecast whatever the
+    // mapping
+    pub fn position(&self, forecast: f64) -> f64 {
+        if forecast == 0.0 {
+            return 0.0;
+        }
+        match self {
+            ForecastMapping::Sign => target_units(forecast, 1.0),
+            ForecastMapping::Linear { scale } => forecast * scale,
+            ForecastMapping::ClippedLinear { scale, max } => {
+                (forecast * scale).clamp(-max.abs(), max.abs())
+            }
+            ForecastMapping::Step { steps } => {
+                let reached = steps
+                    .iter()
+                    .filter(|step| forecast.abs() >= step.forecast)
+                    .max_by(|a, b| a.forecast.total_cmp(&b.forecast));
+                reached.map_or(0.0, |step| step.position * forecast.signum())
+            }
+            ForecastMapping::Sigmoid { steepness } if *steepness > 0.0 => {
+                (forecast * steepness).tanh() / steepness.tanh()
+            }
+            ForecastMapping::Sigmoid { .. } => forecast,
+        }
+    }
+}
+
+// How far a position may be from its target before an order corrects it, "targetTolerance" in
+// the trading config, e.g. {"units": 10} or {"percent": 1.0} of the target. The wider of the two
+// applies, and by default only an exact match places no order. Going flat is never held back.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct TargetTolerance {
+    #[serde(default)]
+    pub units: f64,
+
+    #[serde(default)]
+    pub percent: f64,
+}
+
+impl TargetTolerance {
+    // Whether `current` units are close enough to `target` to be left as they are
+    pub fn within(&self, current: f64, target: f64) -> bool {
+        if target == 0.0 {
+            return current == 0.0;
+        }
+        let band = self.units.max(target.abs() * self.percent / 100.0);
+        (target - current).abs() <= band
+    }
+}
+
+// Gradual moves towards the target, "targetSmoothing" in the trading config, so that a noisy
+// forecast adjusts the position a little at a time rather than reversing it on every crossover.
+// {"halfLife": 300} closes half of the distance between the previous target and the new one
+// every 5 minutes, {"maxChange": 1000, "interval": 60} moves the target by at most 1000 units a
+// minute, and the two can be combined. Targets aren't smoothed by default.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TargetSmoothing {
+    // Seconds
+    #[serde(default)]
+    #[serde(rename = "halfLife")]
+    pub half_life: Option<f64>,
+
+    #[serde(default)]
+    #[serde(rename = "maxChange")]
+    pub max_change: Option<f64>,
+
+    // Seconds maxChange applies to
+    #[serde(default = "default_smoothing_interval")]
+    pub interval: u64,
+}
+
+fn default_smoothing_interval() -> u64 {
+    60
+}
+
+impl Default for TargetSmoothing {
+    fn default() -> Self {
+        TargetSmoothing {
+            half_life: None,
+            max_change: None,
+            interval: default_smoothing_interval(),
+        }
+    }
+}
+
+impl TargetSmoothing {
+    pub fn is_enabled(&self) -> bool {
+        self.half_life.is_some() || self.max_change.is_some()
+    }
+
+    // A smoothed target only moves part of the way on its signal, so signals are worked until
+    // their target is reached, moving it again every `interval` seconds without expiring
+    pub fn working_validity(&self) -> Option<SignalValidity> {
+        self.is_enabled().then(|| SignalValidity {
+            seconds: None,
+            price_move: None,
+            retry_interval: self.interval.max(1),
+        })
+    }
+}
+
+// The smoothed target of each instrument. Time is taken from the prices seen, so backtests
+// smooth the same way as live trading.
+pub struct TargetSmoother {
+    smoothing: TargetSmoothing,
+    time: u64,
+    targets: HashMap<String, SmoothedTarget>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct SmoothedTarget {
+    units: f64,
+    time: u64,
+
+    // Start of the current maxChange interval and how far the target has moved within it
+    interval_start: u64,
+    interval_change: f64,
+}
+
+// The smoothed targets of a TargetSmoother, for a backtest to resume with
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct TargetSmootherState {
+    pub time: u64,
+    pub targets: HashMap<String, SmoothedTarget>,
+}
+
+impl TargetSmoother {
+    pub fn new(smoothing: &TargetSmoothing) -> Self {
+        TargetSmoother {
+            smoothing: smoothing.clone(),
+            time: 0,
+            targets: HashMap::new(),
+        }
+    }
+
+    pub fn state(&self) -> TargetSmootherState {
+        TargetSmootherState {
+            time: self.time,
+            targets: self.targets.clone(),
+        }
+    }
+
+    pub fn restore(&mut self, state: TargetSmootherState) {
+        self.time = state.time;
+        self.targets = state.targets;
+    }
+
+    pub fn update(&mut self, price: &Price) {
+        self.time = self.time.max(price.time);
+    }
+
+    // Units to hold now on the way to `target`. Smoothing starts from the `current` position the
+    // first time an instrument is seen, and from the last smoothed target after that.
+    pub fn smooth(&mut self, instrument: &str, current: f64, target: f64) -> f64 {
+        if !self.smoothing.is_enabled() {
+            return target;
+        }
+
+        let now = self.time;
+        let state = self
+            .targets
+            .entry(instrument.to_string())
+            .or_insert(SmoothedTarget {
+                units: current,
+                time: now,
+                interval_start: now,
+                interval_change: 0.0,
+            });
+
+        let mut next = target;
+        if let Some(half_life) = self.smoothing.half_life {
+            let elapsed = now.saturating_sub(state.time) as f64 / 1000.0;
+            let weight = match half_life > 0.0 {
+                true => 1.0 - 0.5f64.powf(elapsed / half_life),
+                false => 1.0,
+            };
+            next = state.units + (target - state.units) * weight;
+            // Within a unit the target counts as reached, so going flat leaves nothing behind
+            if (target - next).abs() < 1.0 {
+                next = target;
+            }
+        }
+        if let Some(max_change) = self.smoothing.max_change {
+            if now >= state.interval_start + self.smoothing.interval.max(1) * 1000 {
+                state.interval_start = now;
+                state.interval_change = 0.0;
+            }
+            let allowed = (max_change - state.interval_change).max(0.0);
+            let change = (next - state.units).clamp(-allowed, allowed);
+            state.interval_change += change.abs();
+            next = state.units + change;
+        }
+
+        state.units = next;
+        state.time = now;
+        next
+    }
+}
+
+// Full position size of each instrument under a sizing mode, scaled to the volatility target if
+// there is one. Notional sizing and volatility targeting use the latest prices, so the sizer
+// needs to see every price.
+pub struct PositionSizer {
+    sizing: PositionSizing,
+    units: f64,
+    converter: Converter,
+    volatility: Option<VolatilityTargeter>,
+    mapping: ForecastMapping,
+
+    // Multiplier set by the risk checks, e.g. for drawdown scaling
+    risk_scale: f64,
+
+    // Largest position in any instrument, settings.json's "risk" "maxUnits"
+    max_units: Option<f64>,
+}
+
+impl PositionSizer {
+    // `units` is the size of instruments that aren't overridden in units mode
+    pub fn new(sizing: &PositionSizing, units: f64) -> Self {
+        PositionSizer {
+            sizing: sizing.clone(),
+            units,
+            converter: Converter::new(),
+            volatility: None,
+            mapping: ForecastMapping::default(),
+            risk_scale: 1.0,
+            max_units: None,
+        }
+    }
+
+    pub fn with_volatility_target(mut self, target: Option<VolatilityTarget>) -> Self {
+        self.volatility = target.map(VolatilityTargeter::new);
+        self
+    }
+
+    // The volatility targeter's estimates, None without a volatility target
+    pub fn volatility_state(&self) -> Option<VolatilityState> {
+        self.volatility.as_ref().map(VolatilityTargeter::state)
+    }
+
+    pub fn restore_volatility(&mut self, state: VolatilityState) {
+        if let Some(volatility) = &mut self.volatility {
+            volatility.restore(state);
+        }
+    }
+
+    pub fn with_forecast_mapping(mut self, mapping: &ForecastMapping) -> Self {
+        self.mapping = mapping.clone();
+        self
+    }
+
+    pub fn with_max_units(mut self, max_units: Option<f64>) -> Self {
+        self.max_units = max_units;
+        self
+    }
+
+    pub fn set_risk_scale(&mut self, scale: f64) {
+        self.risk_scale = scale;
+    }
+
+    // Whether the volatility target's scale has changed since the last call
+    pub fn take_rescaled(&mut self) -> bool {
+        self.volatility
+            .as_mut()
+            .is_some_and(VolatilityTargeter::take_rescaled)
+    }
+
+    pub fn update(&mut self, price: &Price) {
+        if let PositionSizing::Notional { .. } = self.sizing {
+            self.converter.update(price);
+        }
+        if let Some(volatility) = &mut self.volatility {
+            volatility.update(price);
+        }
+    }
+
+    // Net position a signal asks for by the forecast mapping, scaled to the volatility target and
+    // by the risk scale and rounded as units are, or None if the instrument can't be sized yet.
+    // The unscaled position is what the next volatility estimate is of, so the scale doesn't
+    // feed back on itself. Positions are capped at max_units either way.
+    pub fn target(&mut self, instrument: &str, forecast: f64) -> Option<f64> {
+        let target = self.scaled(instrument, forecast)?;
+        Some(match self.max_units {
+            Some(max_units) => target.clamp(-max_units, max_units),
+            None => target,
+        })
+    }
+
+    fn scaled(&mut self, instrument: &str, forecast: f64) -> Option<f64> {
+        let full = self.units(instrument)? * self.mapping.position(forecast);
+        let mut scale = self.risk_scale;
+        if let Some(volatility) = &mut self.volatility {
+            volatility.record(instrument, full);
+            scale *= volatility.scale();
+        }
+        if scale == 1.0 && full.fract() == 0.0 {
+            return Some(full);
+        }
+        let precision = match &self.sizing {
+            PositionSizing::Notional { precision, .. } => *precision,
+            PositionSizing::Units { .. } => 0,
+        };
+        let rounding = 10f64.powi(precision);
+        Some((full * scale * rounding).round() / rounding)
+    }
+
+    // Units of a full position in the instrument, or None if its notional can't be converted yet
+    pub fn units(&self, instrument: &str) -> Option<f64> {
+        match &self.sizing {
+            PositionSizing::Units { overrides } => {
+                Some(overrides.get(instrument).copied().unwrap_or(self.units))
+            }
+            PositionSizing::Notional {
+                notional,
+                currency,
+                precision,
+                overrides,
+            } => {
+                if let Some(units) = overrides.get(instrument) {
+                    return Some(*units);
+                }
+                // A unit of an instrument is a unit of its base currency
+                let (base, _) = split_instrument(instrument)?;
+                let units = self.converter.convert(*notional, currency, base)?;
+                let scale = 10f64.powi(*precision);
+                Some((units * scale).round() / scale)
+            }
+        }
+    }
+}
@@ -0,0 +1,25 @@
+use serde::{Deserialize, Serialize};
+
+use crate::oanda::objects::Price;
+
+// Which side of the quote models compute their signals from.
+// Mixing sides between models (or between a model's averages) biases signals by the spread,
+// so every model of a strategy uses the same basis, set by "priceBasis" in its config.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "camelCase")]
+pub enum PriceBasis {
+    Bid,
+    Ask,
+    #[default]
+    Mid,
+}
+
+impl PriceBasis {
+    pub fn value(&self, price: &Price) -> f64 {
+        match self {
+            PriceBasis::Bid => price.bid as f64,
+            PriceBasis::Ask => price.ask as f64,
+            PriceBasis::Mid => (price.bid as f64 + price.ask as f64) / 2.0,
+        }
+    }
+}
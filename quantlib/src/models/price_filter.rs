@@ -0,0 +1,15 @@
+use serde::{Deserialize, Serialize};
+
+// What strategies do with prices OANDA marks as not tradeable, e.g. the indicative prices streamed
+// around the daily close. Set by "nonTradeablePrices" in the trading config.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "camelCase")]
+pub enum NonTradeablePrices {
+    // Strategies never see them
+    #[default]
+    Skip,
+    // Strategies update their state on them, but any signal they cause is dropped
+    Observe,
+    // Treated like any other price
+    Trade,
+}
@@ -0,0 +1,304 @@
+use std::collections::{HashMap, VecDeque};
+use std::path::PathBuf;
+
+use rhai::{CallFnOptions, Dynamic, Engine, Map, Module, Scope, AST};
+use serde::{Deserialize, Serialize};
+
+use crate::models::{PriceBasis, TradingSignal};
+use crate::oanda::objects::Price;
+
+// A strategy written as a Rhai script, the "script" model, for trying out an idea without
+// rebuilding the trader, e.g. {"model": "script", "script": "strategies/breakout.rhai",
+// "window": 200, "params": {"threshold": 2.0}}. The script defines
+//
+//     fn tick(price, stats) { ... }
+//
+// which is called on every price with `this` bound to the instrument's own state, a map the
+// script can keep anything in between ticks. `price` has instrument, time, bid, ask, mid, spread,
+// value (by the price basis) and tradeable; `stats` has count, mean, std, min, max, first and
+// last of the last `window` values. It returns the forecast, and a signal is sent whenever that
+// changes. Returning nothing leaves the forecast as it was. The config's params are constants in
+// the `params` namespace, e.g. params::threshold. A tick that fails (an error, running out of
+// operations, or a forecast that isn't a finite number) is logged and skipped, and after
+// `maxErrors` of them in a row the script is disabled, withdrawing its forecasts.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ScriptConfig {
+    pub script: PathBuf,
+
+    #[serde(default = "default_window")]
+    pub window: usize,
+
+    #[serde(default)]
+    pub params: serde_json::Value,
+
+    // Limit on the work done per tick, so a script stuck in a loop fails instead of hanging
+    #[serde(default = "default_max_operations")]
+    #[serde(rename = "maxOperations")]
+    pub max_operations: u64,
+
+    #[serde(default = "default_max_errors")]
+    #[serde(rename = "maxErrors")]
+    pub max_errors: u32,
+}
+
+fn default_window() -> usize {
+    100
+}
+
+fn default_max_operations() -> u64 {
+    1_000_000
+}
+
+fn default_max_errors() -> u32 {
+    10
+}
+
+#[derive(Serialize, Deserialize)]
+struct ScriptInstrument {
+    values: VecDeque<f64>,
+    state: Dynamic,
+
+    #[serde(rename = "lastForecast")]
+    last_forecast: Option<f64>,
+}
+
+pub struct ScriptedModel {
+    config: ScriptConfig,
+    engine: Engine,
+    ast: AST,
+    scope: Scope<'static>,
+    price_basis: PriceBasis,
+    instruments: HashMap<String, ScriptInstrument>,
+
+    // Ticks failed in a row, and whether that's been enough to disable the script
+    errors: u32,
+    disabled: bool,
+}
+
+impl ScriptedModel {
+    pub fn new(config: ScriptConfig) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut engine = Engine::new();
+        engine.set_max_operations(config.max_operations);
+        engine.on_print(|text| log::info!("[SCRIPT] {}", text));
+        engine.on_debug(|text, _, _| log::debug!("[SCRIPT] {}", text));
+        let mut params = Module::new();
+        if let Some(values) = config.params.as_object() {
+            for (name, value) in values {
+                params.set_var(
+                    name,
+                    rhai::serde::to_dynamic(value).map_err(|e| e.to_string())?,
+                );
+            }
+        }
+        engine.register_static_module("params", params.into());
+
+        let ast = engine
+            .compile_file(config.script.clone())
+            .map_err(|e| format!("Failed to compile {}: {}", config.script.display(), e))?;
+        if !ast
+            .iter_functions()
+            .any(|f| f.name == "tick" && f.params.len() == 2)
+        {
+            return Err(format!("{} has no tick(price, stats)", config.script.display()).into());
+        }
+
+        // Top level statements run once, e.g. to log the script's settings
+        let mut scope = Scope::new();
+        engine
+            .run_ast_with_scope(&mut scope, &ast)
+            .map_err(|e| format!("Failed to run {}: {}", config.script.display(), e))?;
+
+        log::info!("Loaded strategy script {}", config.script.display());
+        Ok(ScriptedModel {
+            config,
+            engine,
+            ast,
+            scope,
+            price_basis: PriceBasis::default(),
+            instruments: HashMap::new(),
+            errors: 0,
+            disabled: false,
+        })
+    }
+
+    pub fn with_price_basis(mut self, price_basis: PriceBasis) -> Self {
+        self.price_basis = price_basis;
+        self
+    }
+
+    // The windows and script state of every instrument
+    pub fn state(&self) -> serde_json::Value {
+        serde_json::to_value(&self.instruments).unwrap_or_default()
+    }
+
+    // The numbers the script keeps in the instrument's `this`, sorted by name, and its forecast
+    pub fn variables(&self, instrument: &str) -> Vec<(String, f64)> {
+        let instrument = match self.instruments.get(instrument) {
+            Some(instrument) => instrument,
+            None => return Vec::new(),
+        };
+        let mut variables: Vec<(String, f64)> = match instrument.state.read_lock::<Map>() {
+            Some(state) => state
+                .iter()
+                .filter_map(|(name, value)| {
+                    let value = value
+                        .as_float()
+                        .ok()
+                        .or_else(|| value.as_int().ok().map(|value| value as f64))?;
+                    Some((name.to_string(), value))
+                })
+                .collect(),
+            None => Vec::new(),
+        };
+        if let Some(forecast) = instrument.last_forecast {
+            variables.push(("forecast".to_string(), forecast));
+        }
+        variables
+    }
+
+    pub fn restore(&mut self, state: &serde_json::Value) -> Result<(), Box<dyn std::error::Error>> {
+        self.instruments = serde_json::from_value(state.clone())?;
+        Ok(())
+    }
+
+    // The signal of the script's forecast on the price if it changed. A failed tick isn't an
+    // error, so one bad price or bug doesn't stop the trader.
+    pub fn tick(
+        &mut self,
+        price: &Price,
+    ) -> Result<Option<TradingSignal>, Box<dyn std::error::Error>> {
+        if self.disabled {
+            return Ok(self.signal(price, 0.0));
+        }
+        match self.evaluate(price) {
+            Ok(forecast) => {
+                self.errors = 0;
+                Ok(forecast.and_then(|forecast| self.signal(price, forecast)))
+            }
+            Err(e) => {
+                self.errors += 1;
+                log::error!("[{}] {}, skipping the price", price.instrument, e);
+                if self.errors < self.config.max_errors.max(1) {
+                    return Ok(None);
+                }
+                log::error!(
+                    "Disabling {} after {} failed ticks in a row, its forecasts are withdrawn",
+                    self.config.script.display(),
+                    self.errors
+                );
+                self.disabled = true;
+                Ok(self.signal(price, 0.0))
+            }
+        }
+    }
+
+    // A signal of the forecast if it's not the instrument's last one
+    fn signal(&mut self, price: &Price, forecast: f64) -> Option<TradingSignal> {
+        let instrument = self.instruments.get_mut(&price.instrument)?;
+        if instrument.last_forecast.unwrap_or(0.0) == forecast {
+            return None;
+        }
+        instrument.last_forecast = Some(forecast);
+        Some(TradingSignal {
+            instrument: price.instrument.clone(),
+            forecast,
+        })
+    }
+
+    // Run the script on the price, returning its forecast if it gave one
+    fn evaluate(&mut self, price: &Price) -> Result<Option<f64>, String> {
+        let value = self.price_basis.value(price);
+        let instrument = self
+            .instruments
+            .entry(price.instrument.clone())
+            .or_insert_with(|| ScriptInstrument {
+                values: VecDeque::new(),
+                state: Dynamic::from_map(Map::new()),
+                last_forecast: None,
+            });
+        instrument.values.push_back(value);
+        if instrument.values.len() > self.config.window.max(1) {
+            instrument.values.pop_front();
+        }
+
+        let mut inputs = Map::new();
+        inputs.insert("instrument".into(), price.instrument.clone().into());
+        inputs.insert("time".into(), (price.time as i64).into());
+        inputs.insert("bid".into(), (price.bid as f64).into());
+        inputs.insert("ask".into(), (price.ask as f64).into());
+        inputs.insert(
+            "mid".into(),
+            ((price.bid as f64 + price.ask as f64) / 2.0).into(),
+        );
+        inputs.insert("spread".into(), ((price.ask - price.bid) as f64).into());
+        inputs.insert("value".into(), value.into());
+        inputs.insert("tradeable".into(), price.is_tradeable().into());
+
+        let options = CallFnOptions::new()
+            .eval_ast(false)
+            .bind_this_ptr(&mut instrument.state);
+        let result: Dynamic = self
+            .engine
+            .call_fn_with_options(
+                options,
+                &mut self.scope,
+                &self.ast,
+                "tick",
+                (inputs, stats(&instrument.values)),
+            )
+            .map_err(|e| format!("Script error: {}", e))?;
+
+        let forecast = if result.is_unit() {
+            return Ok(None);
+        } else if let Ok(forecast) = result.as_float() {
+            forecast
+        } else if let Ok(forecast) = result.as_int() {
+            forecast as f64
+        } else {
+            return Err(format!(
+                "Script returned a {} instead of a forecast",
+                result.type_name()
+            ));
+        };
+        if !forecast.is_finite() {
+            return Err(format!("Script returned a forecast of {}", forecast));
+        }
+        Ok(Some(forecast))
+    }
+}
+
+// Summary statistics of a window of values, which is never empty
+fn stats(values: &VecDeque<f64>) -> Map {
+    let count = values.len() as f64;
+    let mean = values.iter().sum::<f64>() / count;
+    let std = match values.len() {
+        1 => 0.0,
+        _ => (values.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / (count - 1.0)).sqrt(),
+    };
+
+    let mut stats = Map::new();
+    stats.insert("count".into(), (values.len() as i64).into());
+    stats.insert("mean".into(), mean.into());
+    stats.insert("std".into(), std.into());
+    stats.insert(
+        "min".into(),
+        values.iter().copied().fold(f64::INFINITY, f64::min).into(),
+    );
+    stats.insert(
+        "max".into(),
+        values
+            .iter()
+            .copied()
+            .fold(f64::NEG_INFINITY, f64::max)
+            .into(),
+    );
+    stats.insert(
+        "first".into(),
+        values.front().copied().unwrap_or_default().into(),
+    );
+    stats.insert(
+        "last".into(),
+        values.back().copied().unwrap_or_default().into(),
+    );
+    stats
+}
@@ -0,0 +1,651 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+use tokio::sync::Notify;
+
+use crate::models::{
+    AlphaModel, AlphaModels, Completed, ForecastMapping, ModelCheckpoint, ModelStateConfig,
+    ModelStateRecorder, SignalValidity, StrategyCheckpoint, StrategyPool, StrategyWorkersConfig,
+    TradingSignal, CHECKPOINT_VERSION,
+};
+use crate::oanda::objects::Price;
+use crate::util::TradingConfig;
+
+// How the forecasts of several strategies for the same instrument are combined
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "camelCase")]
+pub enum ConflictPolicy {
+    // Sum of all forecasts, so opposing strategies cancel out
+    #[default]
+    Net,
+
+    // The strategy that took a position first keeps control until it goes flat
+    FirstWins,
+
+    // The forecast with the highest confidence, ties go to the strategy that signalled first
+    StrongestWins,
+
+    // Any disagreement in direction keeps the instrument flat, otherwise forecasts are netted
+    Veto,
+}
+
+// Share of the position size each strategy's forecasts carry, "allocation" in the trading config,
+// e.g. {"weights": {"fastEma": 0.6, "tickMomentum": 0.4}} as written by research's allocate
+// tool. Forecasts are scaled by their strategy's weight before they're combined, so the weights
+// only size positions through a forecastMapping that sizes them by the forecast, e.g. linear,
+// where with the net policy two strategies at full forecast make a full position between them.
+// The default sign mapping would take any weighted forecast as a full position, so an allocation
+// without another mapping is refused. Strategies left out aren't allocated anything.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct Allocation {
+    pub weights: HashMap<String, f64>,
+}
+
+// A signal after conflicts between strategies have been resolved
+#[derive(Debug)]
+pub struct ResolvedSignal {
+    pub signal: TradingSignal,
+    pub policy: ConflictPolicy,
+
+    // Strategies whose forecasts decided the signal
+    pub strategies: Vec<String>,
+
+    // The tightest validity of those strategies, if any of them has one
+    pub validity: Option<SignalValidity>,
+}
+
+// What one strategy made of an instrument when a signal was resolved
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct StrategySnapshot {
+    pub name: String,
+    pub model: String,
+
+    // Its standing forecast for the instrument, withdrawn (0) while it's halted
+    pub forecast: f64,
+    pub halted: bool,
+
+    // The model's internal state once it had seen the price
+    pub state: serde_json::Value,
+}
+
+// Latest forecast of one strategy for one instrument
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default)]
+pub(crate) struct StandingForecast {
+    forecast: f64,
+
+    // Order in which the forecast took its current direction, for first-wins and tie breaks
+    since: u64,
+}
+
+// Runs every strategy of a config on each price and combines their signals into one per
+// instrument. Models only signal on changes, so each strategy's last forecast stands until it
+// sends a new one, and a resolved signal is only sent when the combined forecast changes.
+pub struct SignalBus {
+    names: Vec<String>,
+    policy: ConflictPolicy,
+
+    // The strategies' models, run in line, or on worker threads with a pool (see
+    // StrategyWorkersConfig) and then the name of each strategy's model
+    models: Vec<AlphaModels>,
+    pool: Option<(StrategyPool, Vec<String>)>,
+
+    // Signals resolved by the pool's models that haven't been taken yet
+    completed: Vec<(Price, ResolvedSignal)>,
+
+    // Where the models' variables are recorded, if anywhere
+    recorder: Option<ModelStateRecorder>,
+
+    // Allocated to each strategy, in the same order, 1 without an allocation
+    weights: Vec<f64>,
+    validities: Vec<Option<SignalValidity>>,
+    forecasts: HashMap<String, Vec<StandingForecast>>,
+    resolved: HashMap<String, f64>,
+    sequence: u64,
+    last_time: u64,
+
+    // Strategies stopped by a circuit breaker, their forecasts no longer count
+    halted: HashSet<String>,
+}
+
+impl SignalBus {
+    pub fn new(policy: ConflictPolicy) -> Self {
+        SignalBus {
+            names: Vec::new(),
+            policy,
+            models: Vec::new(),
+            pool: None,
+            completed: Vec::new(),
+            recorder: None,
+            weights: Vec::new(),
+            validities: Vec::new(),
+            forecasts: HashMap::new(),
+            resolved: HashMap::new(),
+            sequence: 0,
+            last_time: 0,
+            halted: HashSet::new(),
+        }
+    }
+
+    pub fn add_strategy(mut self, name: &str, model: AlphaModels) -> Self {
+        self.names.push(name.to_string());
+        self.models.push(model);
+        self.weights.push(1.0);
+        self.validities.push(None);
+        self
+    }
+
+    // How long the signals the last strategy added decides stand, see SignalValidity
+    pub fn with_validity(mut self, validity: Option<SignalValidity>) -> Self {
+        if let Some(last) = self.validities.last_mut() {
+            *last = validity;
+        }
+        self
+    }
+
+    pub fn with_allocation(mut self, allocation: &Allocation) -> Self {
+        for (name, weight) in self.names.iter().zip(self.weights.iter_mut()) {
+            *weight = match allocation.weights.get(name) {
+                Some(weight) => *weight,
+                None => {
+                    log::warn!(
+                        "Strategy {} has no allocation, its signals won't trade",
+                        name
+                    );
+                    0.0
+                }
+            };
+        }
+        self
+    }
+
+    // A config with a "strategies" list runs each of them, otherwise its own model is the only one
+    pub fn from_config(config: &TradingConfig) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut bus = SignalBus::new(config.conflict_policy);
+        for (name, strategy_config, validity) in strategy_configs(config) {
+            bus = bus
+                .add_strategy(&name, AlphaModels::from_config(&strategy_config)?)
+                .with_validity(validity);
+        }
+        if let Some(allocation) = allocation(config)? {
+            bus = bus.with_allocation(allocation);
+        }
+        Ok(bus)
+    }
+
+    // The strategies of a config with their models on worker threads. Signals are resolved in
+    // the background, tick() and warm_up() only send prices to the models and the signals are
+    // taken with completed().
+    pub fn pooled(
+        config: &TradingConfig,
+        workers: &StrategyWorkersConfig,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut bus = SignalBus::new(config.conflict_policy);
+        let mut configs = Vec::new();
+        let mut models = Vec::new();
+        for (name, strategy_config, validity) in strategy_configs(config) {
+            bus.names.push(name);
+            bus.weights.push(1.0);
+            bus.validities.push(validity);
+            models.push(strategy_config.model.clone());
+            configs.push(strategy_config);
+        }
+        bus.pool = Some((StrategyPool::start(workers, configs)?, models));
+        if let Some(allocation) = allocation(config)? {
+            bus = bus.with_allocation(allocation);
+        }
+        Ok(bus)
+    }
+
+    // Record the models' variables on every price they see from now on, appending to the file or
+    // replacing it
+    pub fn with_model_state(
+        mut self,
+        config: &ModelStateConfig,
+        append: bool,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        self.recorder = Some(ModelStateRecorder::open(config, append)?);
+        if let Some((pool, _)) = &self.pool {
+            pool.record_variables();
+        }
+        Ok(self)
+    }
+
+    pub fn policy(&self) -> ConflictPolicy {
+        self.policy
+    }
+
+    // Feed prices to the models without acting on them, e.g. a pricing snapshot at startup so
+    // that the first live ticks aren't compared against empty state. Any signals are dropped.
+    pub fn warm_up(&mut self, prices: &[Price]) -> Result<(), Box<dyn std::error::Error>> {
+        for price in prices {
+            if let Some((pool, _)) = &mut self.pool {
+                pool.submit(price, false)?;
+                continue;
+            }
+            for (index, model) in self.models.iter_mut().enumerate() {
+                model.tick(price)?;
+                if let Some(recorder) = &mut self.recorder {
+                    recorder.record(
+                        price,
+                        &self.names[index],
+                        model.variables(&price.instrument),
+                    )?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Stop a strategy until it's resumed. Its standing forecasts are withdrawn, returning the
+    // instruments it had one in, whose signals should be restated without it.
+    pub fn halt(&mut self, name: &str) -> Vec<String> {
+        let mut withdrawn = Vec::new();
+        if let Some(index) = self.names.iter().position(|n| n == name) {
+            if let Some((pool, _)) = &self.pool {
+                pool.halt(index);
+            }
+            self.halted.insert(name.to_string());
+            for (instrument, forecasts) in self.forecasts.iter_mut() {
+                if forecasts[index].forecast != 0.0 {
+                    withdrawn.push(instrument.clone());
+                }
+                forecasts[index].forecast = 0.0;
+            }
+        }
+        withdrawn.sort();
+        withdrawn
+    }
+
+    // Let a halted strategy trade again from its next signal
+    pub fn resume(&mut self, name: &str) {
+        if let (Some(index), Some((pool, _))) =
+            (self.names.iter().position(|n| n == name), &self.pool)
+        {
+            pool.resume(index);
+        }
+        self.halted.remove(name);
+    }
+
+    // Halted strategies, sorted
+    pub fn halted(&self) -> Vec<String> {
+        let mut halted: Vec<String> = self.halted.iter().cloned().collect();
+        halted.sort();
+        halted
+    }
+
+    pub fn has_strategy(&self, name: &str) -> bool {
+        self.names.iter().any(|n| n == name)
+    }
+
+    // The price's resolved signal, if it changed. With a pool, the price is only sent to the
+    // models and this is always None.
+    pub fn tick(
+        &mut self,
+        price: &Price,
+    ) -> Result<Option<ResolvedSignal>, Box<dyn std::error::Error>> {
+        if let Some((pool, _)) = &mut self.pool {
+            pool.submit(price, true)?;
+            return Ok(None);
+        }
+        let mut signals = Vec::new();
+        for (index, model) in self.models.iter_mut().enumerate() {
+            if self.halted.contains(&self.names[index]) {
+                continue;
+            }
+            if let Some(signal) = model.tick(price)? {
+                signals.push((index, signal));
+            }
+            if let Some(recorder) = &mut self.recorder {
+                recorder.record(
+                    price,
+                    &self.names[index],
+                    model.variables(&price.instrument),
+                )?;
+            }
+        }
+        Ok(self.combine(price, signals))
+    }
+
+    // Signals the pool's models resolved since the last call, with the prices they were resolved
+    // on, in the order of the prices. With `wait`, every price sent to the models is finished
+    // first. Always empty without a pool.
+    pub fn completed(
+        &mut self,
+        wait: bool,
+    ) -> Result<Vec<(Price, ResolvedSignal)>, Box<dyn std::error::Error>> {
+        if let Some((pool, _)) = &mut self.pool {
+            let completed = pool.collect(wait)?;
+            self.take(completed)?;
+        }
+        Ok(std::mem::take(&mut self.completed))
+    }
+
+    // Notified whenever the pool's workers have ticked a price, so their signals can be taken
+    // without waiting for the next one. None without a pool.
+    pub fn completions(&self) -> Option<Arc<Notify>> {
+        self.pool.as_ref().map(|(pool, _)| pool.ticked())
+    }
+
+    // Prices of each instrument the pool dropped for a later one since the last call, see
+    // StrategyWorkersConfig. Always empty without a pool.
+    pub fn take_dropped(&mut self) -> BTreeMap<String, u64> {
+        match &mut self.pool {
+            Some((pool, _)) => pool.take_dropped(),
+            None => BTreeMap::new(),
+        }
+    }
+
+    // Bring the state of the pool's models up to date, e.g. before a checkpoint. Nothing to do
+    // without a pool.
+    pub fn sync(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        if let Some((pool, _)) = &mut self.pool {
+            let completed = pool.sync(None)?;
+            self.take(completed)?;
+        }
+        Ok(())
+    }
+
+    fn take(&mut self, completed: Vec<Completed>) -> Result<(), Box<dyn std::error::Error>> {
+        for Completed {
+            price,
+            trade,
+            signals,
+            variables,
+        } in completed
+        {
+            if let Some(recorder) = &mut self.recorder {
+                for (index, variables) in variables {
+                    recorder.record(&price, &self.names[index], variables)?;
+                }
+            }
+            if !trade {
+                continue;
+            }
+            // Signals of prices sent before a strategy was halted
+            let signals = signals
+                .into_iter()
+                .filter(|(index, _)| !self.halted.contains(&self.names[*index]))
+                .collect();
+            if let Some(resolved) = self.combine(&price, signals) {
+                self.completed.push((price, resolved));
+            }
+        }
+        Ok(())
+    }
+
+    // Take the signals of the strategies (by index) on a price, resolving the instrument's signal
+    // if it changed
+    fn combine(
+        &mut self,
+        price: &Price,
+        signals: Vec<(usize, TradingSignal)>,
+    ) -> Option<ResolvedSignal> {
+        // A pool finishes the prices of different instruments out of order
+        self.last_time = self.last_time.max(price.time);
+        let count = self.names.len();
+        let changed = !signals.is_empty();
+        for (index, signal) in signals {
+            log::debug!(
+                "[{}][{}] Forecast: {}",
+                signal.instrument,
+                self.names[index],
+                signal.forecast
+            );
+
+            let forecasts = self
+                .forecasts
+                .entry(signal.instrument.clone())
+                .or_insert_with(|| vec![StandingForecast::default(); count]);
+            let standing = &mut forecasts[index];
+            if standing.forecast.signum() != signal.forecast.signum() || standing.forecast == 0.0 {
+                self.sequence += 1;
+                standing.since = self.sequence;
+            }
+            standing.forecast = signal.forecast * self.weights[index];
+        }
+
+        if !changed {
+            return None;
+        }
+
+        let (forecast, deciding) = self.resolve(&price.instrument);
+        let previous = self.resolved.insert(price.instrument.clone(), forecast);
+        if previous == Some(forecast) {
+            return None;
+        }
+        Some(self.resolved_signal(&price.instrument, forecast, deciding))
+    }
+
+    // Send the next signal of every instrument whether or not it changed, e.g. after restoring a
+    // checkpoint into an account that doesn't hold the positions its signals were acted on with
+    pub fn forget_resolved(&mut self) {
+        self.resolved.clear();
+    }
+
+    // The instrument's signal as the strategies' forecasts stand, whether or not it has changed,
+    // e.g. to open a position again after closing it. None while it's flat.
+    pub fn standing(&self, instrument: &str) -> Option<ResolvedSignal> {
+        let (forecast, deciding) = self.resolve(instrument);
+        (forecast != 0.0).then(|| self.resolved_signal(instrument, forecast, deciding))
+    }
+
+    // The instrument's signal as the strategies' forecasts stand, flat or not, which is taken as
+    // its resolved signal from now on, e.g. to act on a halted strategy's withdrawn forecasts or
+    // to size a position afresh
+    pub fn restate(&mut self, instrument: &str) -> ResolvedSignal {
+        let (forecast, deciding) = self.resolve(instrument);
+        self.resolved.insert(instrument.to_string(), forecast);
+        self.resolved_signal(instrument, forecast, deciding)
+    }
+
+    fn resolved_signal(
+        &self,
+        instrument: &str,
+        forecast: f64,
+        deciding: Vec<usize>,
+    ) -> ResolvedSignal {
+        let validity = deciding
+            .iter()
+            .filter_map(|index| self.validities[*index].as_ref())
+            .fold(None, |tightest: Option<SignalValidity>, validity| {
+                Some(match tightest {
+                    Some(tightest) => tightest.tightest(validity),
+                    None => validity.clone(),
+                })
+            });
+        ResolvedSignal {
+            signal: TradingSignal {
+                instrument: instrument.to_string(),
+                forecast,
+            },
+            policy: self.policy,
+            strategies: deciding
+                .into_iter()
+                .map(|index| self.names[index].clone())
+                .collect(),
+            validity,
+        }
+    }
+
+    // Every strategy's view of an instrument, to explain the last signal resolved for it. A
+    // pool's models are as they were when they last signalled.
+    pub fn snapshot(&self, instrument: &str) -> Vec<StrategySnapshot> {
+        let forecasts = self.forecasts.get(instrument);
+        self.names
+            .iter()
+            .enumerate()
+            .map(|(index, name)| StrategySnapshot {
+                name: name.clone(),
+                model: self.model_name(index).to_string(),
+                forecast: forecasts.map_or(0.0, |forecasts| forecasts[index].forecast),
+                halted: self.halted.contains(name),
+                state: self.model_state(index),
+            })
+            .collect()
+    }
+
+    fn model_name(&self, index: usize) -> &str {
+        match &self.pool {
+            Some((_, models)) => &models[index],
+            None => self.models[index].name(),
+        }
+    }
+
+    fn model_state(&self, index: usize) -> serde_json::Value {
+        match &self.pool {
+            Some((pool, _)) => pool.states[index].clone(),
+            None => self.models[index].state(),
+        }
+    }
+
+    // A pool's models are as of their last sync()
+    pub fn checkpoint(&self) -> StrategyCheckpoint {
+        StrategyCheckpoint {
+            version: CHECKPOINT_VERSION,
+            time: self.last_time,
+            policy: self.policy,
+            models: (0..self.names.len())
+                .map(|index| ModelCheckpoint {
+                    name: self.names[index].clone(),
+                    model: self.model_name(index).to_string(),
+                    state: self.model_state(index),
+                })
+                .collect(),
+            forecasts: self.forecasts.clone(),
+            resolved: self.resolved.clone(),
+            sequence: self.sequence,
+        }
+    }
+
+    // The checkpoint must be of the same strategies, in the same order, as this bus
+    pub fn restore(
+        &mut self,
+        checkpoint: &StrategyCheckpoint,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let matches = checkpoint.models.len() == self.names.len()
+            && checkpoint.models.iter().enumerate().all(|(index, saved)| {
+                saved.name == self.names[index] && saved.model == self.model_name(index)
+            });
+        if !matches {
+            return Err("Checkpoint doesn't match the configured strategies".into());
+        }
+        if checkpoint.policy != self.policy {
+            log::warn!(
+                "Checkpoint was taken with the {:?} conflict policy, now using {:?}",
+                checkpoint.policy,
+                self.policy
+            );
+        }
+
+        match &mut self.pool {
+            Some((pool, _)) => {
+                let states: Vec<serde_json::Value> = checkpoint
+                    .models
+                    .iter()
+                    .map(|saved| saved.state.clone())
+                    .collect();
+                let completed = pool.sync(Some(&states))?;
+                self.take(completed)?;
+            }
+            None => {
+                for (saved, model) in checkpoint.models.iter().zip(&mut self.models) {
+                    model.restore(&saved.state)?;
+                }
+            }
+        }
+        self.forecasts = checkpoint.forecasts.clone();
+        self.resolved = checkpoint.resolved.clone();
+        self.sequence = checkpoint.sequence;
+        self.last_time = checkpoint.time;
+
+        // Halted strategies' forecasts stay withdrawn
+        for name in self.halted.clone() {
+            self.halt(&name);
+        }
+        Ok(())
+    }
+
+    // Combined forecast of an instrument, and the indices of the strategies that decided it
+    fn resolve(&self, instrument: &str) -> (f64, Vec<usize>) {
+        let forecasts = match self.forecasts.get(instrument) {
+            Some(forecasts) => forecasts,
+            None => return (0.0, Vec::new()),
+        };
+        let active: Vec<usize> = (0..forecasts.len())
+            .filter(|index| forecasts[*index].forecast != 0.0)
+            .collect();
+        let net = || {
+            let sum: f64 = active.iter().map(|index| forecasts[*index].forecast).sum();
+            (sum.clamp(-1.0, 1.0), active.clone())
+        };
+        let earliest = |a: &usize, b: &usize| forecasts[*a].since.cmp(&forecasts[*b].since);
+
+        match self.policy {
+            ConflictPolicy::Net => net(),
+            ConflictPolicy::FirstWins => match active.iter().min_by(|a, b| earliest(a, b)) {
+                Some(index) => (forecasts[*index].forecast, vec![*index]),
+                None => (0.0, Vec::new()),
+            },
+            ConflictPolicy::StrongestWins => {
+                let strongest = active.iter().min_by(|a, b| {
+                    let (a_strength, b_strength) =
+                        (forecasts[**a].forecast.abs(), forecasts[**b].forecast.abs());
+                    b_strength
+                        .total_cmp(&a_strength)
+                        .then_with(|| earliest(a, b))
+                });
+                match strongest {
+                    Some(index) => (forecasts[*index].forecast, vec![*index]),
+                    None => (0.0, Vec::new()),
+                }
+            }
+            ConflictPolicy::Veto => {
+                let long = active.iter().any(|index| forecasts[*index].forecast > 0.0);
+                let short = active.iter().any(|index| forecasts[*index].forecast < 0.0);
+                if long && short {
+                    (0.0, active)
+                } else {
+                    net()
+                }
+            }
+        }
+    }
+}
+
+// The config's allocation, refused under the sign mapping which would ignore its weights
+fn allocation(config: &TradingConfig) -> Result<Option<&Allocation>, Box<dyn std::error::Error>> {
+    match &config.allocation {
+        Some(_) if matches!(config.forecast_mapping, ForecastMapping::Sign) => Err(
+            "An allocation needs a forecastMapping that sizes positions by the forecast, the \
+             default sign mapping ignores its weights"
+                .into(),
+        ),
+        allocation => Ok(allocation.as_ref()),
+    }
+}
+
+// Name, config and validity of each strategy of a config: those of its "strategies" list, or the
+// config's own model without one
+fn strategy_configs(
+    config: &TradingConfig,
+) -> Vec<(String, TradingConfig, Option<SignalValidity>)> {
+    if config.strategies.is_empty() {
+        return vec![(
+            config.model.clone(),
+            config.clone(),
+            config.validity.clone(),
+        )];
+    }
+    config
+        .strategies
+        .iter()
+        .map(|strategy| {
+            let mut strategy_config = config.clone();
+            strategy_config.model = strategy.model.clone();
+            strategy_config.model_config = strategy.model_config.clone();
+            let name = strategy.name.clone().unwrap_or(strategy.model.clone());
+            (name, strategy_config, strategy.validity.clone())
+        })
+        .collect()
+}
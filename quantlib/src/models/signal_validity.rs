@@ -0,0 +1,185 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::models::{ConflictPolicy, PriceBasis, ResolvedSignal, TradingSignal};
+use crate::oanda::objects::Price;
+
+// How long a strategy's signals stand, "validity" of a strategy in the trading config (or of the
+// config itself with a single model), e.g. {"seconds": 300, "priceMove": 0.002}. A signal with a
+// validity is worked until its target is reached rather than tried once: it's tried again every
+// `retryInterval` seconds while orders are held back or only part of the way is traded, e.g. by
+// target smoothing. It expires `seconds` after it was resolved, or once the mid price has moved
+// `priceMove` (a fraction) from where it was, whichever comes first, and from then on nothing
+// more is traded for it. Signals decided by several strategies take the tightest of their
+// validities. Signals without one are tried once, as they always have been, unless targets are
+// smoothed (see TargetSmoothing::working_validity).
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct SignalValidity {
+    #[serde(default)]
+    pub seconds: Option<u64>,
+
+    #[serde(default)]
+    #[serde(rename = "priceMove")]
+    pub price_move: Option<f64>,
+
+    #[serde(default = "default_retry_interval")]
+    #[serde(rename = "retryInterval")]
+    pub retry_interval: u64,
+}
+
+fn default_retry_interval() -> u64 {
+    5
+}
+
+impl SignalValidity {
+    // The tighter of each condition of two validities
+    pub fn tightest(&self, other: &SignalValidity) -> SignalValidity {
+        SignalValidity {
+            seconds: tighter(self.seconds, other.seconds),
+            price_move: tighter(self.price_move, other.price_move),
+            retry_interval: self.retry_interval.min(other.retry_interval),
+        }
+    }
+}
+
+fn tighter<T: PartialOrd>(a: Option<T>, b: Option<T>) -> Option<T> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(if b < a { b } else { a }),
+        (a, b) => a.or(b),
+    }
+}
+
+// A signal being worked towards its target until it's reached or its validity runs out
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct WorkingTarget {
+    pub instrument: String,
+    pub forecast: f64,
+    pub strategies: Vec<String>,
+    pub validity: SignalValidity,
+
+    // When the signal was resolved and the mid price it was resolved on
+    pub since: u64,
+    pub price: f64,
+
+    #[serde(rename = "lastAttempt")]
+    pub last_attempt: u64,
+}
+
+impl WorkingTarget {
+    // Why the signal no longer stands at this price of its instrument, if it doesn't
+    pub fn expiry(&self, price: &Price) -> Option<String> {
+        if let Some(seconds) = self
+            .validity
+            .seconds
+            .filter(|seconds| price.time >= self.since + seconds * 1000)
+        {
+            return Some(format!("good for {}s", seconds));
+        }
+        let moved = (PriceBasis::Mid.value(price) / self.price - 1.0).abs();
+        if let Some(price_move) = self.validity.price_move.filter(|max| moved >= *max) {
+            return Some(format!(
+                "price moved {:.4}%, good for {:.4}%",
+                moved * 100.0,
+                price_move * 100.0
+            ));
+        }
+        None
+    }
+
+    pub fn signal(&self) -> TradingSignal {
+        TradingSignal {
+            instrument: self.instrument.clone(),
+            forecast: self.forecast,
+        }
+    }
+}
+
+// What became of a working target at a price of its instrument
+pub enum WorkingUpdate {
+    // Try to reach it again
+    Retry(ResolvedSignal),
+    Expired(WorkingTarget, String),
+}
+
+// The signals of each instrument that have a validity and haven't reached their target
+#[derive(Debug, Default)]
+pub struct WorkingTargets {
+    targets: HashMap<String, WorkingTarget>,
+
+    // Validity of the signals resolved without one, which aren't worked without it
+    fallback: Option<SignalValidity>,
+}
+
+impl WorkingTargets {
+    pub fn new(targets: Vec<WorkingTarget>) -> Self {
+        WorkingTargets {
+            targets: targets
+                .into_iter()
+                .map(|target| (target.instrument.clone(), target))
+                .collect(),
+            fallback: None,
+        }
+    }
+
+    // Work signals without a validity of their own for as long as this one has them stand
+    pub fn with_fallback(mut self, validity: Option<SignalValidity>) -> Self {
+        self.fallback = validity;
+        self
+    }
+
+    // A newly resolved signal replaces whatever was being worked for its instrument
+    pub fn resolved(&mut self, resolved: &ResolvedSignal, price: &Price) {
+        let instrument = &resolved.signal.instrument;
+        match resolved.validity.as_ref().or(self.fallback.as_ref()) {
+            Some(validity) => {
+                self.targets.insert(
+                    instrument.clone(),
+                    WorkingTarget {
+                        instrument: instrument.clone(),
+                        forecast: resolved.signal.forecast,
+                        strategies: resolved.strategies.clone(),
+                        validity: validity.clone(),
+                        since: price.time,
+                        price: PriceBasis::Mid.value(price),
+                        last_attempt: price.time,
+                    },
+                );
+            }
+            None => {
+                self.targets.remove(instrument);
+            }
+        }
+    }
+
+    // The instrument's working target at this price: expired, due another attempt or neither
+    pub fn update(&mut self, price: &Price, policy: ConflictPolicy) -> Option<WorkingUpdate> {
+        let target = self.targets.get_mut(&price.instrument)?;
+        if let Some(reason) = target.expiry(price) {
+            let target = self.targets.remove(&price.instrument)?;
+            return Some(WorkingUpdate::Expired(target, reason));
+        }
+        if price.time < target.last_attempt + target.validity.retry_interval * 1000 {
+            return None;
+        }
+        target.last_attempt = price.time;
+        Some(WorkingUpdate::Retry(ResolvedSignal {
+            signal: target.signal(),
+            policy,
+            strategies: target.strategies.clone(),
+            validity: Some(target.validity.clone()),
+        }))
+    }
+
+    // An attempt found nothing left to trade
+    pub fn reached(&mut self, instrument: &str) {
+        self.targets.remove(instrument);
+    }
+
+    // Sorted by instrument, to save and restore
+    pub fn targets(&self) -> Vec<WorkingTarget> {
+        let mut targets: Vec<WorkingTarget> = self.targets.values().cloned().collect();
+        targets.sort_by(|a, b| a.instrument.cmp(&b.instrument));
+        targets
+    }
+}
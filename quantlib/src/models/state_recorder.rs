@@ -0,0 +1,293 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::data::is_compressed;
+use crate::oanda::objects::Price;
+
+// Internal variables of the strategies' models recorded as prices come in, "modelState" in the
+// trading config, e.g. {"path": "logs/model_state.jsonl.sz", "interval": 60}, to plot what the
+// models saw against the price with research's model_state tool. Each model declares its own
+// variables: the ema model its fast and slow averages, tickMomentum its imbalance and momentum
+// and scripts the numbers they keep in `this`. Without an interval they're recorded on every
+// tick, with one (in seconds) once per candle of that length, as they stood at its last tick.
+// Paths ending in .sz are compressed like tick files. The trader appends to the file, a backtest
+// replaces it.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ModelStateConfig {
+    pub path: PathBuf,
+
+    #[serde(default)]
+    pub interval: u64,
+}
+
+// The file is JSON lines: a series line naming the strategy, instrument and variables of a
+// series, then a sample line for each recording of it, [series, time, bid, ask, values...].
+// Series are numbered afresh each time the file is opened and declared again when their
+// variables change.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct SeriesLine {
+    series: usize,
+    strategy: String,
+    instrument: String,
+    variables: Vec<String>,
+}
+
+struct Series {
+    id: usize,
+    variables: Vec<String>,
+
+    // The candle the pending sample is in, and the sample's line, with an interval
+    candle: u64,
+    pending: Option<String>,
+}
+
+pub struct ModelStateRecorder {
+    writer: BufWriter<Box<dyn Write + Send>>,
+    interval: u64,
+    series: HashMap<(String, String), Series>,
+    next: usize,
+}
+
+impl ModelStateRecorder {
+    pub fn open(
+        config: &ModelStateConfig,
+        append: bool,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        if let Some(parent) = config.path.parent() {
+            if !parent.as_os_str().is_empty() {
+                std::fs::create_dir_all(parent)?;
+            }
+        }
+        let file = OpenOptions::new()
+            .create(true)
+            .append(append)
+            .write(true)
+            .truncate(!append)
+            .open(&config.path)?;
+        let writer: Box<dyn Write + Send> = if is_compressed(&config.path) {
+            Box::new(snap::write::FrameEncoder::new(file))
+        } else {
+            Box::new(file)
+        };
+        log::info!("Recording model state to {}", config.path.display());
+        Ok(ModelStateRecorder {
+            writer: BufWriter::new(writer),
+            interval: config.interval * 1000,
+            series: HashMap::new(),
+            next: 0,
+        })
+    }
+
+    // A strategy's variables for the price's instrument once its model has seen the price
+    pub fn record(
+        &mut self,
+        price: &Price,
+        strategy: &str,
+        variables: Vec<(String, f64)>,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        if variables.is_empty() {
+            return Ok(());
+        }
+        let key = (strategy.to_string(), price.instrument.clone());
+        let (names, values): (Vec<String>, Vec<f64>) = variables.into_iter().unzip();
+        let candle = match self.interval {
+            0 => price.time,
+            interval => price.time - price.time % interval,
+        };
+
+        let declared = self
+            .series
+            .get(&key)
+            .is_some_and(|series| series.variables == names);
+        if !declared {
+            if let Some(series) = self.series.remove(&key) {
+                self.write_pending(series)?;
+            }
+            let line = SeriesLine {
+                series: self.next,
+                strategy: key.0.clone(),
+                instrument: key.1.clone(),
+                variables: names.clone(),
+            };
+            writeln!(self.writer, "{}", serde_json::to_string(&line)?)?;
+            self.series.insert(
+                key.clone(),
+                Series {
+                    id: self.next,
+                    variables: names,
+                    candle,
+                    pending: None,
+                },
+            );
+            self.next += 1;
+        }
+
+        let series = self.series.get_mut(&key).unwrap();
+        // Written by hand to keep it short, values that aren't numbers in JSON are null
+        let values: Vec<String> = values
+            .iter()
+            .map(|value| match value.is_finite() {
+                true => value.to_string(),
+                false => "null".to_string(),
+            })
+            .collect();
+        let sample = format!(
+            "[{},{},{},{},{}]",
+            series.id,
+            price.time,
+            price.bid,
+            price.ask,
+            values.join(",")
+        );
+        if self.interval == 0 {
+            writeln!(self.writer, "{}", sample)?;
+            return Ok(());
+        }
+        if candle != series.candle {
+            if let Some(pending) = series.pending.take() {
+                writeln!(self.writer, "{}", pending)?;
+            }
+            series.candle = candle;
+        }
+        series.pending = Some(sample);
+        Ok(())
+    }
+
+    fn write_pending(&mut self, series: Series) -> Result<(), Box<dyn std::error::Error>> {
+        if let Some(pending) = series.pending {
+            writeln!(self.writer, "{}", pending)?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for ModelStateRecorder {
+    // The candles still open are written as they stand
+    fn drop(&mut self) {
+        let series: Vec<Series> = self.series.drain().map(|(_, series)| series).collect();
+        for series in series {
+            if let Err(e) = self.write_pending(series) {
+                log::error!("Failed to write model state: {}", e);
+            }
+        }
+        if let Err(e) = self.writer.flush() {
+            log::error!("Failed to write model state: {}", e);
+        }
+    }
+}
+
+// One recording of a strategy's variables for an instrument, None where a value wasn't a number
+#[derive(Debug, Clone)]
+pub struct ModelStateSample {
+    pub time: u64,
+    pub bid: f64,
+    pub ask: f64,
+    pub strategy: String,
+    pub values: Vec<(String, Option<f64>)>,
+}
+
+// The samples of one instrument in a model state recording, in the order they were recorded
+pub fn read_model_state<P: AsRef<Path>>(
+    path: P,
+    instrument: &str,
+) -> Result<Vec<ModelStateSample>, Box<dyn std::error::Error>> {
+    let path = path.as_ref();
+    let file = File::open(path)?;
+    let reader: Box<dyn Read> = if is_compressed(path) {
+        Box::new(snap::read::FrameDecoder::new(file))
+    } else {
+        Box::new(file)
+    };
+
+    let mut series: HashMap<usize, SeriesLine> = HashMap::new();
+    let mut samples = Vec::new();
+    for line in BufReader::new(reader).lines() {
+        let line = line?;
+        if line.starts_with('{') {
+            let declared: SeriesLine = serde_json::from_str(&line)?;
+            series.insert(declared.series, declared);
+            continue;
+        }
+        let sample: Vec<Option<f64>> = serde_json::from_str(&line)?;
+        if sample.len() < 4 || sample[..4].iter().any(|field| field.is_none()) {
+            return Err(format!("Malformed model state sample: {}", line).into());
+        }
+        let field = |index: usize| sample[index].unwrap_or_default();
+        let declared = match series.get(&(field(0) as usize)) {
+            Some(declared) if declared.instrument == instrument => declared,
+            Some(_) => continue,
+            None => return Err(format!("Sample of an undeclared series: {}", line).into()),
+        };
+        samples.push(ModelStateSample {
+            time: field(1) as u64,
+            bid: field(2),
+            ask: field(3),
+            strategy: declared.strategy.clone(),
+            values: declared
+                .variables
+                .iter()
+                .cloned()
+                .zip(sample[4..].iter().copied())
+                .collect(),
+        });
+    }
+    Ok(samples)
+}
+
+// Write an instrument's samples as CSV for plotting against the price: a row for each time
+// something was recorded, with the bid, ask and mid then a "strategy.variable" column for each
+// variable, carrying each forward until it's next recorded
+pub fn export_model_state_csv<P: AsRef<Path>>(
+    samples: &[ModelStateSample],
+    path: P,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut columns: Vec<String> = Vec::new();
+    for sample in samples {
+        for (name, _) in &sample.values {
+            let column = format!("{}.{}", sample.strategy, name);
+            if !columns.contains(&column) {
+                columns.push(column);
+            }
+        }
+    }
+
+    let mut writer = BufWriter::new(File::create(path)?);
+    writeln!(writer, "time,bid,ask,mid,{}", columns.join(","))?;
+    let mut current: BTreeMap<usize, Option<f64>> = BTreeMap::new();
+    for (index, sample) in samples.iter().enumerate() {
+        for (name, value) in &sample.values {
+            let column = format!("{}.{}", sample.strategy, name);
+            if let Some(position) = columns.iter().position(|c| *c == column) {
+                current.insert(position, *value);
+            }
+        }
+        // Samples of several strategies at the same time make one row
+        if samples
+            .get(index + 1)
+            .is_some_and(|next| next.time == sample.time)
+        {
+            continue;
+        }
+        let values: Vec<String> = (0..columns.len())
+            .map(|position| match current.get(&position) {
+                Some(Some(value)) => value.to_string(),
+                _ => String::new(),
+            })
+            .collect();
+        writeln!(
+            writer,
+            "{},{},{},{},{}",
+            sample.time,
+            sample.bid,
+            sample.ask,
+            (sample.bid + sample.ask) / 2.0,
+            values.join(",")
+        )?;
+    }
+    writer.flush()?;
+    Ok(())
+}
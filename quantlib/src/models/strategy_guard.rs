@@ -0,0 +1,153 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use serde::{Deserialize, Serialize};
+
+use crate::journal::JournalEntry;
+use crate::oanda::helpers::parse_time;
+
+// Loss limits per strategy, in the account currency, e.g.
+// "strategyLimits": {"maxLoss": 500, "windowHours": 24, "maxDrawdown": 1000}
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct StrategyLimits {
+    // Most realized loss allowed within the trailing window
+    #[serde(default)]
+    #[serde(rename = "maxLoss")]
+    pub max_loss: Option<f64>,
+
+    #[serde(default = "default_window_hours")]
+    #[serde(rename = "windowHours")]
+    pub window_hours: u64,
+
+    // Most realized P&L allowed to be given back from its highest point
+    #[serde(default)]
+    #[serde(rename = "maxDrawdown")]
+    pub max_drawdown: Option<f64>,
+}
+
+fn default_window_hours() -> u64 {
+    24
+}
+
+#[derive(Default)]
+struct StrategyPl {
+    // Realized P&L of recent fills, (time, pl)
+    recent: VecDeque<(u64, f64)>,
+    total: f64,
+    peak: f64,
+}
+
+// Pauses a strategy whose realized P&L breaches its limits. A paused strategy stays paused
+// until it is enabled again by hand, e.g. over the control socket.
+// Fills decided by several strategies have their P&L split evenly between them. Commissions
+// and financing count against the strategies they're attributed to, like losses.
+pub struct StrategyGuard {
+    limits: StrategyLimits,
+    strategies: HashMap<String, StrategyPl>,
+    paused: HashSet<String>,
+}
+
+impl StrategyGuard {
+    pub fn new(limits: StrategyLimits) -> Self {
+        StrategyGuard {
+            limits,
+            strategies: HashMap::new(),
+            paused: HashSet::new(),
+        }
+    }
+
+    // Rebuild the P&L history (and which strategies are paused) from a journal, so that a restart
+    // doesn't give a failing strategy a clean slate
+    pub fn replay(&mut self, entries: &[JournalEntry]) -> Result<(), String> {
+        for entry in entries {
+            match entry {
+                JournalEntry::Order {
+                    time,
+                    pl,
+                    commission,
+                    financing,
+                    strategies,
+                    ..
+                } => {
+                    let pl =
+                        pl.unwrap_or(0.0) - commission.unwrap_or(0.0) + financing.unwrap_or(0.0);
+                    let (time, _) = parse_time(time)?;
+                    self.record(time, strategies, pl);
+                }
+                JournalEntry::Cost {
+                    time,
+                    amount,
+                    strategies,
+                    ..
+                } => {
+                    let (time, _) = parse_time(time)?;
+                    self.record(time, strategies, *amount);
+                }
+                JournalEntry::Paused { strategy, .. } => {
+                    self.paused.insert(strategy.clone());
+                }
+                JournalEntry::Enabled { strategy, .. } => self.enable(strategy),
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    pub fn paused(&self) -> impl Iterator<Item = &String> {
+        self.paused.iter()
+    }
+
+    // Record the realized P&L of a fill, returning the strategies it caused to be paused
+    pub fn record(&mut self, time: u64, strategies: &[String], pl: f64) -> Vec<(String, String)> {
+        if strategies.is_empty() || pl == 0.0 {
+            return Vec::new();
+        }
+
+        let window = self.limits.window_hours * 3_600_000;
+        let share = pl / strategies.len() as f64;
+        let mut breaches = Vec::new();
+        for strategy in strategies {
+            let history = self.strategies.entry(strategy.clone()).or_default();
+            history.recent.push_back((time, share));
+            while history
+                .recent
+                .front()
+                .is_some_and(|(first, _)| *first + window <= time)
+            {
+                history.recent.pop_front();
+            }
+            history.total += share;
+            history.peak = history.peak.max(history.total);
+
+            let loss = -history.recent.iter().map(|(_, pl)| pl).sum::<f64>();
+            let drawdown = history.peak - history.total;
+            let reason = match (self.limits.max_loss, self.limits.max_drawdown) {
+                (Some(max_loss), _) if loss > max_loss => Some(format!(
+                    "lost {:.2} in {} hours, limit is {:.2}",
+                    loss, self.limits.window_hours, max_loss
+                )),
+                (_, Some(max_drawdown)) if drawdown > max_drawdown => Some(format!(
+                    "drawdown of {:.2}, limit is {:.2}",
+                    drawdown, max_drawdown
+                )),
+                _ => None,
+            };
+
+            if let Some(reason) = reason {
+                if self.paused.insert(strategy.clone()) {
+                    log::error!("Pausing strategy {}: {}", strategy, reason);
+                    breaches.push((strategy.clone(), reason));
+                }
+            }
+        }
+        breaches
+    }
+
+    // Let a paused strategy trade again, judged only on its P&L from now on
+    pub fn enable(&mut self, strategy: &str) {
+        self.paused.remove(strategy);
+        if let Some(history) = self.strategies.get_mut(strategy) {
+            history.recent.clear();
+            history.peak = history.total;
+        }
+    }
+}
@@ -0,0 +1,593 @@
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::sync::mpsc::{channel, Receiver, Sender, TryRecvError};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+
+use serde::{Deserialize, Serialize};
+use tokio::sync::Notify;
+
+use crate::models::{AlphaModel, AlphaModels, TradingSignal};
+use crate::oanda::objects::Price;
+use crate::util::TradingConfig;
+
+// Runs the strategies' models on worker threads instead of the engine's, "strategyWorkers" in the
+// trading config, e.g. {"threads": 2, "maxInFlight": 256}, for models heavy enough (ONNX,
+// statistics over long windows) to hold up the prices behind them. The work is split by
+// instrument: every thread has its own copy of each strategy's model and sees the prices of its
+// share of the instruments, so even a single heavy strategy runs on all of them. Models whose
+// state isn't kept by instrument (see AlphaModels::per_instrument) can't be split, each of those
+// lives on one of the threads and sees every price. Signals are combined on the engine as soon as
+// a price is finished, keeping every instrument's signals in the order of its prices, and the
+// engine is woken for them without waiting for the next price. Once `maxInFlight` prices are
+// waiting on the models, newer ones are held back keeping only the latest price of each
+// instrument, so a trader that falls behind skips prices rather than queueing ever older ones.
+// Warm-up prices are never skipped, they wait for the models to catch up instead.
+// How many it skipped of each instrument is journaled every `droppedTicksPeriod` seconds in
+// which any were, for the drift monitor (see DroppedTicks). Backtests always run their models in
+// line.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct StrategyWorkersConfig {
+    #[serde(default = "default_threads")]
+    pub threads: usize,
+
+    #[serde(default = "default_max_in_flight")]
+    #[serde(rename = "maxInFlight")]
+    pub max_in_flight: usize,
+
+    #[serde(default = "default_dropped_ticks_period")]
+    #[serde(rename = "droppedTicksPeriod")]
+    pub dropped_ticks_period: u64,
+}
+
+fn default_threads() -> usize {
+    1
+}
+
+fn default_max_in_flight() -> usize {
+    256
+}
+
+fn default_dropped_ticks_period() -> u64 {
+    60
+}
+
+// States of models, by the index of their strategy
+type States = Vec<(usize, serde_json::Value)>;
+
+enum Job {
+    // A price, and whether its instrument is one of the worker's, which its per-instrument models
+    // only see then
+    Tick(u64, Price, bool),
+    Halt(usize),
+    Resume(usize),
+
+    // Send the models' variables along with every tick from now on
+    Variables,
+
+    // Restore the given states of the worker's models first, if any, then reply with them all
+    State(Option<States>),
+}
+
+#[derive(Default)]
+struct Ticked {
+    signals: Vec<(usize, TradingSignal)>,
+
+    // States of the worker's models, sent along when any of them signalled
+    states: States,
+
+    // Variables of the models that saw the price, when they're being recorded
+    variables: Vec<(usize, Vec<(String, f64)>)>,
+}
+
+enum Reply {
+    Ticked(u64, Result<Ticked, String>),
+    State(Result<States, String>),
+}
+
+// A price sent to the workers, until every one of them has ticked it
+struct InFlight {
+    price: Price,
+    trade: bool,
+    remaining: usize,
+    signals: Vec<(usize, TradingSignal)>,
+    variables: Vec<(usize, Vec<(String, f64)>)>,
+}
+
+// A price every worker has ticked, with the signals of the strategies (by index) that signalled
+pub(crate) struct Completed {
+    pub(crate) price: Price,
+
+    // Whether its signals are traded on, rather than only warming the models up
+    pub(crate) trade: bool,
+    pub(crate) signals: Vec<(usize, TradingSignal)>,
+    pub(crate) variables: Vec<(usize, Vec<(String, f64)>)>,
+}
+
+pub(crate) struct StrategyPool {
+    jobs: Vec<Sender<Job>>,
+    threads: Vec<JoinHandle<()>>,
+    replies: Receiver<Reply>,
+
+    // Notified by the workers whenever they've ticked a price
+    ticked: Arc<Notify>,
+
+    // Worker each strategy runs on, None for those split by instrument across all of them, and
+    // the latest known state of its model, the workers' states merged for a split one
+    owners: Vec<Option<usize>>,
+    pub(crate) states: Vec<serde_json::Value>,
+
+    // Worker each instrument's prices go to, assigned in turn as the instruments are first seen
+    routes: HashMap<String, usize>,
+
+    max_in_flight: usize,
+    sequence: u64,
+    in_flight: BTreeMap<u64, InFlight>,
+
+    // Prices held back while too many are in flight, the latest of each instrument in the order
+    // the instruments came in
+    waiting: VecDeque<String>,
+    latest: HashMap<String, (Price, bool)>,
+    coalesced: u64,
+
+    // Prices replaced by a later one of their instrument before being sent, since last taken
+    dropped: BTreeMap<String, u64>,
+
+    // Prices finished while waiting to send a warm-up price, until they're collected
+    finished: Vec<Completed>,
+}
+
+impl StrategyPool {
+    // Start the workers, each building its strategies' models from their configs (by index), as
+    // models don't move between threads
+    pub(crate) fn start(
+        config: &StrategyWorkersConfig,
+        strategies: Vec<TradingConfig>,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let split = strategies
+            .iter()
+            .any(|strategy| AlphaModels::per_instrument(&strategy.model));
+        // Without a model to split, there's no more work than there are strategies
+        let count = match split {
+            true => config.threads.max(1),
+            false => config.threads.clamp(1, strategies.len().max(1)),
+        };
+        let mut whole = 0;
+        let mut owners = Vec::new();
+        for strategy in &strategies {
+            if AlphaModels::per_instrument(&strategy.model) {
+                owners.push(None);
+            } else {
+                owners.push(Some(whole % count));
+                whole += 1;
+            }
+        }
+        let mut assigned: Vec<Vec<(usize, TradingConfig)>> = vec![Vec::new(); count];
+        for (index, strategy) in strategies.into_iter().enumerate() {
+            match owners[index] {
+                Some(owner) => assigned[owner].push((index, strategy)),
+                None => {
+                    for worker in assigned.iter_mut() {
+                        worker.push((index, strategy.clone()));
+                    }
+                }
+            }
+        }
+
+        let ticked = Arc::new(Notify::new());
+        let (reply_sender, replies) = channel();
+        let mut jobs = Vec::new();
+        let mut threads = Vec::new();
+        for (worker, strategies) in assigned.into_iter().enumerate() {
+            let (job_sender, job_receiver) = channel();
+            let reply_sender = reply_sender.clone();
+            let ticked = ticked.clone();
+            let thread = std::thread::Builder::new()
+                .name(format!("strategy-{}", worker))
+                .spawn(move || work(strategies, job_receiver, reply_sender, ticked))?;
+            jobs.push(job_sender);
+            threads.push(thread);
+        }
+        drop(reply_sender);
+
+        let mut pool = StrategyPool {
+            jobs,
+            threads,
+            replies,
+            ticked,
+            states: vec![serde_json::Value::Null; owners.len()],
+            owners,
+            routes: HashMap::new(),
+            max_in_flight: config.max_in_flight.max(1),
+            sequence: 0,
+            in_flight: BTreeMap::new(),
+            waiting: VecDeque::new(),
+            latest: HashMap::new(),
+            coalesced: 0,
+            dropped: BTreeMap::new(),
+            finished: Vec::new(),
+        };
+        // Each worker reports its models' states once they're built
+        let mut states = Vec::new();
+        while states.len() < count {
+            let reply = pool.replies.recv()?;
+            states.extend(pool.receive(reply)?);
+        }
+        pool.replace_states(states);
+        log::info!(
+            "Running {} strategies on {} worker threads",
+            pool.owners.len(),
+            count
+        );
+        Ok(pool)
+    }
+
+    // Notified whenever a worker has ticked a price, so its signals can be collected without
+    // waiting for anything else to happen
+    pub(crate) fn ticked(&self) -> Arc<Notify> {
+        self.ticked.clone()
+    }
+
+    // Send a price to the models, or hold it back if too many are already in flight. A warm-up
+    // price (not traded) waits until there's room for it instead, as it must not be skipped.
+    pub(crate) fn submit(
+        &mut self,
+        price: &Price,
+        trade: bool,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        if !trade {
+            while self.in_flight.len() >= self.max_in_flight || !self.waiting.is_empty() {
+                let reply = self.replies.recv()?;
+                self.receive(reply)?;
+                let completed = self.complete()?;
+                self.finished.extend(completed);
+            }
+        }
+        if self.in_flight.len() < self.max_in_flight && self.waiting.is_empty() {
+            return self.send(price.clone(), trade);
+        }
+        let instrument = price.instrument.clone();
+        if self
+            .latest
+            .insert(instrument.clone(), (price.clone(), trade))
+            .is_some()
+        {
+            if self.coalesced.is_multiple_of(1000) {
+                log::warn!(
+                    "Strategies are behind the prices, {} skipped so far",
+                    self.coalesced + 1
+                );
+            }
+            self.coalesced += 1;
+            *self.dropped.entry(instrument).or_default() += 1;
+        } else {
+            self.waiting.push_back(instrument);
+        }
+        Ok(())
+    }
+
+    // Prices the workers have finished with since the last call, in the order they were sent.
+    // With `wait`, blocks until every price sent or held back is finished.
+    pub(crate) fn collect(
+        &mut self,
+        wait: bool,
+    ) -> Result<Vec<Completed>, Box<dyn std::error::Error>> {
+        let mut completed = std::mem::take(&mut self.finished);
+        loop {
+            completed.extend(self.complete()?);
+            if self.in_flight.is_empty() {
+                return Ok(completed);
+            }
+            let reply = match wait {
+                true => self.replies.recv()?,
+                false => match self.replies.try_recv() {
+                    Ok(reply) => reply,
+                    Err(TryRecvError::Empty) => return Ok(completed),
+                    Err(e) => return Err(e.into()),
+                },
+            };
+            self.receive(reply)?;
+        }
+    }
+
+    // Bring the models' states up to date once they've ticked every price sent so far, restoring
+    // the given states first, if any. Returns the prices finished meanwhile.
+    pub(crate) fn sync(
+        &mut self,
+        restore: Option<&[serde_json::Value]>,
+    ) -> Result<Vec<Completed>, Box<dyn std::error::Error>> {
+        if let Some(states) = restore {
+            // Instruments only known from the states still need a worker to restore them on
+            for (index, state) in states.iter().enumerate() {
+                if let (None, Some(instruments)) = (self.owners[index], state.as_object()) {
+                    for instrument in instruments.keys() {
+                        self.route(instrument);
+                    }
+                }
+            }
+        }
+        for (worker, jobs) in self.jobs.iter().enumerate() {
+            let states = restore.map(|states| self.restored(states, worker));
+            jobs.send(Job::State(states))?;
+        }
+        let mut states = Vec::new();
+        while states.len() < self.jobs.len() {
+            let reply = self.replies.recv()?;
+            states.extend(self.receive(reply)?);
+        }
+        self.replace_states(states);
+        let mut completed = std::mem::take(&mut self.finished);
+        completed.extend(self.complete()?);
+        Ok(completed)
+    }
+
+    // Prices of each instrument dropped for a later one since the last call
+    pub(crate) fn take_dropped(&mut self) -> BTreeMap<String, u64> {
+        std::mem::take(&mut self.dropped)
+    }
+
+    pub(crate) fn halt(&self, index: usize) {
+        for worker in self.workers_of(index) {
+            let _ = self.jobs[worker].send(Job::Halt(index));
+        }
+    }
+
+    pub(crate) fn resume(&self, index: usize) {
+        for worker in self.workers_of(index) {
+            let _ = self.jobs[worker].send(Job::Resume(index));
+        }
+    }
+
+    // Have the models' variables sent with the prices they see from now on
+    pub(crate) fn record_variables(&self) {
+        for jobs in &self.jobs {
+            let _ = jobs.send(Job::Variables);
+        }
+    }
+
+    // Workers a strategy's model runs on
+    fn workers_of(&self, index: usize) -> Vec<usize> {
+        match self.owners[index] {
+            Some(owner) => vec![owner],
+            None => (0..self.jobs.len()).collect(),
+        }
+    }
+
+    // Worker an instrument's prices go to, assigning it the next one if it's new
+    fn route(&mut self, instrument: &str) -> usize {
+        let next = self.routes.len() % self.jobs.len();
+        *self.routes.entry(instrument.to_string()).or_insert(next)
+    }
+
+    // The states to restore on a worker: the whole state of the models it owns, and the
+    // instruments it's routed of those split by instrument
+    fn restored(&self, states: &[serde_json::Value], worker: usize) -> States {
+        let mut restored = Vec::new();
+        for (index, state) in states.iter().enumerate() {
+            match (self.owners[index], state.as_object()) {
+                (Some(owner), _) if owner != worker => {}
+                (None, Some(instruments)) => {
+                    let mine = instruments
+                        .iter()
+                        .filter(|(instrument, _)| self.routes.get(*instrument) == Some(&worker))
+                        .map(|(instrument, state)| (instrument.clone(), state.clone()))
+                        .collect();
+                    restored.push((index, serde_json::Value::Object(mine)));
+                }
+                _ => restored.push((index, state.clone())),
+            }
+        }
+        restored
+    }
+
+    // The states of the workers' replies with them all, in place of those known so far
+    fn replace_states(&mut self, replies: Vec<States>) {
+        let mut states = vec![serde_json::Value::Null; self.owners.len()];
+        for (index, state) in replies.into_iter().flatten() {
+            merge(&mut states[index], state);
+        }
+        self.states = states;
+    }
+
+    fn send(&mut self, price: Price, trade: bool) -> Result<(), Box<dyn std::error::Error>> {
+        self.sequence += 1;
+        // The instrument's own worker if any model is split by instrument, and the owners of
+        // those that aren't, which see every price
+        let mut workers = Vec::new();
+        if self.owners.iter().any(Option::is_none) {
+            workers.push((self.route(&price.instrument), true));
+        }
+        for owner in self.owners.iter().flatten() {
+            if !workers.iter().any(|(worker, _)| worker == owner) {
+                workers.push((*owner, false));
+            }
+        }
+        for (worker, routed) in &workers {
+            self.jobs[*worker]
+                .send(Job::Tick(self.sequence, price.clone(), *routed))
+                .map_err(|_| "A strategy worker has stopped")?;
+        }
+        self.in_flight.insert(
+            self.sequence,
+            InFlight {
+                price,
+                trade,
+                remaining: workers.len(),
+                signals: Vec::new(),
+                variables: Vec::new(),
+            },
+        );
+        Ok(())
+    }
+
+    // Take a worker's reply, returning the states of its models if it was a reply with them all
+    fn receive(&mut self, reply: Reply) -> Result<Option<States>, Box<dyn std::error::Error>> {
+        match reply {
+            Reply::Ticked(sequence, ticked) => {
+                let ticked = ticked?;
+                for (index, state) in ticked.states {
+                    merge(&mut self.states[index], state);
+                }
+                if let Some(in_flight) = self.in_flight.get_mut(&sequence) {
+                    in_flight.remaining -= 1;
+                    in_flight.signals.extend(ticked.signals);
+                    in_flight.variables.extend(ticked.variables);
+                }
+                Ok(None)
+            }
+            Reply::State(states) => Ok(Some(states?)),
+        }
+    }
+
+    // Take the prices every worker has ticked that no earlier price of their instrument is still
+    // waiting on, and send held back prices in their place
+    fn complete(&mut self) -> Result<Vec<Completed>, Box<dyn std::error::Error>> {
+        let mut pending = HashSet::new();
+        let mut finished = Vec::new();
+        for (sequence, in_flight) in &self.in_flight {
+            if in_flight.remaining > 0 || pending.contains(&in_flight.price.instrument) {
+                pending.insert(in_flight.price.instrument.clone());
+            } else {
+                finished.push(*sequence);
+            }
+        }
+        let mut completed = Vec::new();
+        for sequence in finished {
+            let mut in_flight = self.in_flight.remove(&sequence).unwrap();
+            // As the strategies would have signalled running one after the other
+            in_flight.signals.sort_by_key(|(index, _)| *index);
+            in_flight.variables.sort_by_key(|(index, _)| *index);
+            completed.push(Completed {
+                price: in_flight.price,
+                trade: in_flight.trade,
+                signals: in_flight.signals,
+                variables: in_flight.variables,
+            });
+        }
+        while self.in_flight.len() < self.max_in_flight {
+            let (price, trade) = match self.waiting.pop_front() {
+                Some(instrument) => self.latest.remove(&instrument).unwrap(),
+                None => break,
+            };
+            self.send(price, trade)?;
+        }
+        Ok(completed)
+    }
+}
+
+// Merge a worker's state of a model into what's known of it: a model split by instrument keeps
+// its state by instrument, and each worker only has its own instruments'
+fn merge(merged: &mut serde_json::Value, state: serde_json::Value) {
+    match (merged, state) {
+        (serde_json::Value::Object(merged), serde_json::Value::Object(state)) => {
+            merged.extend(state)
+        }
+        (merged, state) => *merged = state,
+    }
+}
+
+impl Drop for StrategyPool {
+    // Workers stop once their jobs are closed
+    fn drop(&mut self) {
+        self.jobs.clear();
+        for thread in self.threads.drain(..) {
+            let _ = thread.join();
+        }
+    }
+}
+
+fn work(
+    strategies: Vec<(usize, TradingConfig)>,
+    jobs: Receiver<Job>,
+    replies: Sender<Reply>,
+    woken: Arc<Notify>,
+) {
+    let mut models = Vec::new();
+    let mut split = HashSet::new();
+    for (index, config) in strategies {
+        if AlphaModels::per_instrument(&config.model) {
+            split.insert(index);
+        }
+        match AlphaModels::from_config(&config) {
+            Ok(model) => models.push((index, model)),
+            Err(e) => {
+                let _ = replies.send(Reply::State(Err(e.to_string())));
+                return;
+            }
+        }
+    }
+    let states = |models: &Vec<(usize, AlphaModels)>| {
+        models
+            .iter()
+            .map(|(index, model)| (*index, model.state()))
+            .collect::<Vec<_>>()
+    };
+    if replies.send(Reply::State(Ok(states(&models)))).is_err() {
+        return;
+    }
+
+    // Halted strategies' models don't see prices, as when they run in line
+    let mut halted = HashSet::new();
+    let mut variables = false;
+    for job in jobs {
+        let reply = match job {
+            Job::Tick(sequence, price, routed) => {
+                let mut ticked = Ticked::default();
+                let mut result = Ok(());
+                for (index, model) in models.iter_mut() {
+                    if halted.contains(index) || (split.contains(index) && !routed) {
+                        continue;
+                    }
+                    match model.tick(&price) {
+                        Ok(Some(signal)) => ticked.signals.push((*index, signal)),
+                        Ok(None) => {}
+                        Err(e) => {
+                            result = Err(e.to_string());
+                            break;
+                        }
+                    }
+                    if variables {
+                        ticked
+                            .variables
+                            .push((*index, model.variables(&price.instrument)));
+                    }
+                }
+                if !ticked.signals.is_empty() {
+                    ticked.states = states(&models);
+                }
+                let reply = Reply::Ticked(sequence, result.map(|_| ticked));
+                if replies.send(reply).is_err() {
+                    return;
+                }
+                woken.notify_one();
+                continue;
+            }
+            Job::Halt(index) => {
+                halted.insert(index);
+                continue;
+            }
+            Job::Resume(index) => {
+                halted.remove(&index);
+                continue;
+            }
+            Job::Variables => {
+                variables = true;
+                continue;
+            }
+            Job::State(restore) => {
+                let mut result = Ok(());
+                for (index, state) in restore.unwrap_or_default() {
+                    if let Some((_, model)) = models.iter_mut().find(|(i, _)| *i == index) {
+                        if let Err(e) = model.restore(&state) {
+                            result = Err(e.to_string());
+                            break;
+                        }
+                    }
+                }
+                Reply::State(result.map(|_| states(&models)))
+            }
+        };
+        if replies.send(reply).is_err() {
+            return;
+        }
+    }
+}
@@ -0,0 +1,246 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::oanda::objects::Price;
+
+// Size of a single pip for an instrument, JPY-quoted pairs are priced to two decimal places
+pub fn pip_size(instrument: &str) -> f64 {
+    if instrument.ends_with("_JPY") {
+        0.01
+    } else {
+        0.0001
+    }
+}
+
+// How far price is allowed to retrace from its best level before a position is closed
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub enum TrailingStopDistance {
+    // A fixed number of pips
+    Pips(f64),
+
+    // A multiple of the average true range, computed over bars of `bar_seconds` seconds
+    Atr {
+        multiple: f64,
+        period: usize,
+        #[serde(rename = "barSeconds")]
+        bar_seconds: u64,
+    },
+}
+
+// Wilder's average true range, built from ticks aggregated into fixed length bars of the mid price
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct AverageTrueRange {
+    period: usize,
+    bar_length: u64,
+
+    bar_start: u64,
+    high: f64,
+    low: f64,
+    close: f64,
+    previous_close: Option<f64>,
+
+    bars: usize,
+    value: f64,
+}
+
+impl AverageTrueRange {
+    pub fn new(period: usize, bar_seconds: u64) -> Self {
+        AverageTrueRange {
+            period,
+            bar_length: bar_seconds * 1000,
+            bar_start: 0,
+            high: 0.0,
+            low: 0.0,
+            close: 0.0,
+            previous_close: None,
+            bars: 0,
+            value: 0.0,
+        }
+    }
+
+    pub fn tick(&mut self, price: &Price) {
+        let mid = (price.bid as f64 + price.ask as f64) / 2.0;
+
+        // First tick, start the first bar
+        if self.bar_start == 0 {
+            self.start_bar(price.time, mid);
+            return;
+        }
+
+        // Close the current bar once its time is up, and start a new one with this tick
+        if price.time >= self.bar_start + self.bar_length {
+            self.close_bar();
+            self.start_bar(price.time, mid);
+            return;
+        }
+
+        self.high = self.high.max(mid);
+        self.low = self.low.min(mid);
+        self.close = mid;
+    }
+
+    // The current ATR, or None until `period` bars have been completed
+    pub fn value(&self) -> Option<f64> {
+        if self.bars >= self.period {
+            Some(self.value)
+        } else {
+            None
+        }
+    }
+
+    fn start_bar(&mut self, time: u64, mid: f64) {
+        // Align bars to multiples of the bar length so they line up across instruments
+        self.bar_start = time - time % self.bar_length;
+        self.high = mid;
+        self.low = mid;
+        self.close = mid;
+    }
+
+    fn close_bar(&mut self) {
+        let true_range = match self.previous_close {
+            Some(previous_close) => (self.high - self.low)
+                .max((self.high - previous_close).abs())
+                .max((self.low - previous_close).abs()),
+            None => self.high - self.low,
+        };
+
+        // Simple average for the first `period` bars, Wilder's smoothing afterwards
+        self.bars += 1;
+        let n = self.bars.min(self.period) as f64;
+        self.value = (self.value * (n - 1.0) + true_range) / n;
+        self.previous_close = Some(self.close);
+    }
+}
+
+// Tracks the best price reached since a position was opened
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct TrailingStop {
+    pub long: bool,
+    pub best_price: f64,
+}
+
+// Tracks a trailing stop for every open position and decides when a position should be closed.
+// This only depends on prices and position sizes, so the same logic runs live and in backtests.
+pub struct TrailingStopManager {
+    distance: TrailingStopDistance,
+    stops: HashMap<String, TrailingStop>,
+    atrs: HashMap<String, AverageTrueRange>,
+}
+
+// The stops and ATRs of a TrailingStopManager, for a backtest to resume with
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct TrailingStopState {
+    pub stops: HashMap<String, TrailingStop>,
+    pub atrs: HashMap<String, AverageTrueRange>,
+}
+
+impl TrailingStopManager {
+    pub fn new(distance: TrailingStopDistance) -> Self {
+        TrailingStopManager {
+            distance,
+            stops: HashMap::new(),
+            atrs: HashMap::new(),
+        }
+    }
+
+    pub fn state(&self) -> TrailingStopState {
+        TrailingStopState {
+            stops: self.stops.clone(),
+            atrs: self.atrs.clone(),
+        }
+    }
+
+    pub fn restore(&mut self, state: TrailingStopState) {
+        self.stops = state.stops;
+        self.atrs = state.atrs;
+    }
+
+    pub fn stop(&self, instrument: &str) -> Option<&TrailingStop> {
+        self.stops.get(instrument)
+    }
+
+    // Given a new price and the current net position in that instrument, returns the units
+    // required to close the position if the trailing stop has been hit
+    pub fn tick(&mut self, price: &Price, position_units: f64) -> Option<f64> {
+        // The ATR has to keep updating whether or not there is a position to protect
+        if let TrailingStopDistance::Atr {
+            period,
+            bar_seconds,
+            ..
+        } = self.distance
+        {
+            self.atrs
+                .entry(price.instrument.clone())
+                .or_insert_with(|| AverageTrueRange::new(period, bar_seconds))
+                .tick(price);
+        }
+
+        if position_units == 0.0 {
+            self.stops.remove(&price.instrument);
+            return None;
+        }
+
+        // Long positions are closed at the bid, short positions at the ask
+        let long = position_units > 0.0;
+        let exit_price = if long {
+            price.bid as f64
+        } else {
+            price.ask as f64
+        };
+
+        // Start tracking a new stop if the position was just opened or has flipped sides
+        let stop = self
+            .stops
+            .entry(price.instrument.clone())
+            .or_insert(TrailingStop {
+                long,
+                best_price: exit_price,
+            });
+        if stop.long != long {
+            *stop = TrailingStop {
+                long,
+                best_price: exit_price,
+            };
+        }
+
+        if long {
+            stop.best_price = stop.best_price.max(exit_price);
+        } else {
+            stop.best_price = stop.best_price.min(exit_price);
+        }
+
+        let distance = match self.distance {
+            TrailingStopDistance::Pips(pips) => pips * pip_size(&price.instrument),
+            TrailingStopDistance::Atr { multiple, .. } => {
+                // Without a full ATR history there is nothing to measure the retracement against
+                match self.atrs.get(&price.instrument).and_then(|atr| atr.value()) {
+                    Some(atr) => multiple * atr,
+                    None => return None,
+                }
+            }
+        };
+
+        let retracement = if long {
+            stop.best_price - exit_price
+        } else {
+            exit_price - stop.best_price
+        };
+
+        if retracement >= distance {
+            log::info!(
+                "[{}] Trailing stop hit, best price {:.5}, exit price {:.5}",
+                price.instrument,
+                stop.best_price,
+                exit_price
+            );
+            self.stops.remove(&price.instrument);
+            return Some(-position_units);
+        }
+
+        None
+    }
+}
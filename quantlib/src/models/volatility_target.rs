@@ -0,0 +1,269 @@
+use std::collections::{HashMap, VecDeque};
+
+use serde::{Deserialize, Serialize};
+
+use crate::fx::{split_instrument, Converter};
+use crate::oanda::objects::Price;
+
+const YEAR_SECONDS: f64 = 365.0 * 24.0 * 60.0 * 60.0;
+
+// Scales every position so the portfolio's estimated annualized volatility stays near a target,
+// "volatilityTarget" in the trading config, e.g. {"target": 0.1, "capital": 10000, "currency":
+// "USD"} for 10% a year of 10000 USD. Each instrument's volatility and the correlations between
+// them are estimated from returns of the mid price sampled every sampleSeconds, over the last
+// lookback samples. The scale is recomputed every recomputeSeconds for the positions the latest
+// signals ask for at full size, and open positions are resized to it as soon as it changes,
+// unless they're already within the target tolerance of their new size.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct VolatilityTarget {
+    // Annualized, e.g. 0.1 for 10%
+    pub target: f64,
+
+    // What the volatility is a fraction of, in currency
+    pub capital: f64,
+    pub currency: String,
+
+    #[serde(default = "default_sample_seconds")]
+    #[serde(rename = "sampleSeconds")]
+    pub sample_seconds: u64,
+
+    #[serde(default = "default_lookback")]
+    pub lookback: usize,
+
+    #[serde(default = "default_recompute_seconds")]
+    #[serde(rename = "recomputeSeconds")]
+    pub recompute_seconds: u64,
+
+    // Bounds on the scale, so that a quiet market can't lever positions up without limit
+    #[serde(default)]
+    #[serde(rename = "minScale")]
+    pub min_scale: f64,
+
+    #[serde(default = "default_max_scale")]
+    #[serde(rename = "maxScale")]
+    pub max_scale: f64,
+}
+
+fn default_sample_seconds() -> u64 {
+    3600
+}
+
+fn default_lookback() -> usize {
+    500
+}
+
+fn default_recompute_seconds() -> u64 {
+    3600
+}
+
+fn default_max_scale() -> f64 {
+    2.0
+}
+
+// The portfolio's volatility estimate and the scale it gives. Positions are full size until
+// there are enough returns to estimate from.
+pub struct VolatilityTargeter {
+    config: VolatilityTarget,
+    converter: Converter,
+    mids: HashMap<String, f64>,
+
+    // Mid of each instrument at the last sample, and the log returns between samples since
+    sampled: HashMap<String, f64>,
+    returns: HashMap<String, VecDeque<f64>>,
+    next_sample: u64,
+    next_recompute: u64,
+
+    // Full size position each instrument's latest signal asks for, negative for short
+    positions: HashMap<String, f64>,
+    scale: f64,
+
+    // Whether the scale has changed since the last look, see take_rescaled
+    rescaled: bool,
+}
+
+// What a VolatilityTargeter has estimated from, for a backtest to resume with. Conversion rates
+// aren't kept, they come back with the next prices.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct VolatilityState {
+    pub mids: HashMap<String, f64>,
+    pub sampled: HashMap<String, f64>,
+    pub returns: HashMap<String, VecDeque<f64>>,
+    pub next_sample: u64,
+    pub next_recompute: u64,
+    pub positions: HashMap<String, f64>,
+    pub scale: f64,
+}
+
+impl VolatilityTargeter {
+    pub fn new(config: VolatilityTarget) -> Self {
+        VolatilityTargeter {
+            config,
+            converter: Converter::new(),
+            mids: HashMap::new(),
+            sampled: HashMap::new(),
+            returns: HashMap::new(),
+            next_sample: 0,
+            next_recompute: 0,
+            positions: HashMap::new(),
+            scale: 1.0,
+            rescaled: false,
+        }
+    }
+
+    pub fn scale(&self) -> f64 {
+        self.scale
+    }
+
+    // Whether the scale has changed since the last call, so open positions need resizing
+    pub fn take_rescaled(&mut self) -> bool {
+        std::mem::take(&mut self.rescaled)
+    }
+
+    pub fn state(&self) -> VolatilityState {
+        VolatilityState {
+            mids: self.mids.clone(),
+            sampled: self.sampled.clone(),
+            returns: self.returns.clone(),
+            next_sample: self.next_sample,
+            next_recompute: self.next_recompute,
+            positions: self.positions.clone(),
+            scale: self.scale,
+        }
+    }
+
+    pub fn restore(&mut self, state: VolatilityState) {
+        self.mids = state.mids;
+        self.sampled = state.sampled;
+        self.returns = state.returns;
+        self.next_sample = state.next_sample;
+        self.next_recompute = state.next_recompute;
+        self.positions = state.positions;
+        self.scale = state.scale;
+        self.rescaled = false;
+    }
+
+    pub fn update(&mut self, price: &Price) {
+        self.converter.update(price);
+        self.mids.insert(
+            price.instrument.clone(),
+            (price.bid as f64 + price.ask as f64) / 2.0,
+        );
+
+        let interval = self.config.sample_seconds.max(1) * 1000;
+        if price.time >= self.next_sample {
+            if self.next_sample != 0 {
+                self.sample();
+            }
+            self.next_sample = (price.time / interval + 1) * interval;
+        }
+
+        if price.time >= self.next_recompute {
+            if self.next_recompute != 0 {
+                self.recompute();
+            }
+            self.next_recompute = price.time + self.config.recompute_seconds.max(1) * 1000;
+        }
+    }
+
+    // Record the full size position a signal asks for, before scaling
+    pub fn record(&mut self, instrument: &str, units: f64) {
+        if units == 0.0 {
+            self.positions.remove(instrument);
+        } else {
+            self.positions.insert(instrument.to_string(), units);
+        }
+    }
+
+    fn sample(&mut self) {
+        for (instrument, mid) in &self.mids {
+            if let Some(previous) = self.sampled.insert(instrument.clone(), *mid) {
+                let returns = self.returns.entry(instrument.clone()).or_default();
+                returns.push_back((mid / previous).ln());
+                if returns.len() > self.config.lookback.max(2) {
+                    returns.pop_front();
+                }
+            }
+        }
+    }
+
+    // Annualized volatility of the full size positions as a fraction of capital, None if an
+    // instrument with a position doesn't have enough returns or can't be converted yet
+    pub fn estimate(&self) -> Option<f64> {
+        let mut exposures = Vec::new();
+        for (instrument, units) in &self.positions {
+            let (_, quote) = split_instrument(instrument)?;
+            let value = self.converter.convert(
+                units * self.mids.get(instrument)?,
+                quote,
+                &self.config.currency,
+            )?;
+            let returns = self.returns.get(instrument).filter(|r| r.len() >= 2)?;
+            exposures.push((value / self.config.capital, returns));
+        }
+
+        let mut variance = 0.0;
+        for (weight_a, returns_a) in &exposures {
+            for (weight_b, returns_b) in &exposures {
+                variance += weight_a
+                    * weight_b
+                    * volatility(returns_a)
+                    * volatility(returns_b)
+                    * correlation(returns_a, returns_b);
+            }
+        }
+        let periods = YEAR_SECONDS / self.config.sample_seconds.max(1) as f64;
+        Some((variance.max(0.0) * periods).sqrt())
+    }
+
+    fn recompute(&mut self) {
+        let estimate = match self.estimate() {
+            Some(estimate) if estimate > 0.0 => estimate,
+            _ => return,
+        };
+        let scale =
+            (self.config.target / estimate).clamp(self.config.min_scale, self.config.max_scale);
+        if scale != self.scale {
+            log::info!(
+                "Estimated portfolio volatility {:.2}%, targeting {:.2}% by scaling positions by {:.3}",
+                estimate * 100.0,
+                self.config.target * 100.0,
+                scale
+            );
+            self.rescaled = true;
+        }
+        self.scale = scale;
+    }
+}
+
+// Standard deviation of returns per sample
+fn volatility(returns: &VecDeque<f64>) -> f64 {
+    let n = returns.len() as f64;
+    let mean = returns.iter().sum::<f64>() / n;
+    (returns.iter().map(|r| (r - mean).powi(2)).sum::<f64>() / (n - 1.0)).sqrt()
+}
+
+// Correlation over the samples both have, which are the latest ones as every instrument is
+// sampled at the same times
+fn correlation(a: &VecDeque<f64>, b: &VecDeque<f64>) -> f64 {
+    let n = a.len().min(b.len());
+    if n < 2 {
+        return 0.0;
+    }
+    let a: Vec<f64> = a.iter().skip(a.len() - n).copied().collect();
+    let b: Vec<f64> = b.iter().skip(b.len() - n).copied().collect();
+    let mean_a = a.iter().sum::<f64>() / n as f64;
+    let mean_b = b.iter().sum::<f64>() / n as f64;
+    let mut covariance = 0.0;
+    let mut variance_a = 0.0;
+    let mut variance_b = 0.0;
+    for (x, y) in a.iter().zip(b.iter()) {
+        covariance += (x - mean_a) * (y - mean_b);
+        variance_a += (x - mean_a).powi(2);
+        variance_b += (y - mean_b).powi(2);
+    }
+    if variance_a == 0.0 || variance_b == 0.0 {
+        return 0.0;
+    }
+    covariance / (variance_a * variance_b).sqrt()
+}
@@ -0,0 +1,110 @@
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+// Somewhere notifications for an operator are sent, e.g. {"type": "log"},
+// {"type": "file", "path": "logs/alerts.jsonl"} or
+// {"type": "webhook", "url": "https://hooks.slack.com/services/..."}
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum NotificationSink {
+    // A warning in the log of whatever sent it
+    Log,
+
+    // Appended to the file as a line of JSON
+    File { path: PathBuf },
+
+    // POSTed as JSON, with the text in "text" as chat webhooks (Slack, Mattermost) expect
+    Webhook { url: String },
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Notification {
+    pub time: String,
+
+    // What sent it, e.g. "alert"
+    pub source: String,
+
+    #[serde(default)]
+    pub instrument: Option<String>,
+
+    pub text: String,
+}
+
+impl NotificationSink {
+    // Send without waiting for it to arrive. Failures are only logged, a notification that can't
+    // be sent mustn't stop what sent it.
+    pub fn send(&self, notification: &Notification) {
+        match self {
+            NotificationSink::Log => log::warn!("[{}] {}", notification.source, notification.text),
+            NotificationSink::File { path } => {
+                if let Err(e) = append(path, notification) {
+                    log::error!("Failed to write notification to {}: {}", path.display(), e);
+                }
+            }
+            NotificationSink::Webhook { url } => {
+                let runtime = match tokio::runtime::Handle::try_current() {
+                    Ok(runtime) => runtime,
+                    Err(_) => {
+                        log::error!("No runtime to send notification to {} from", url);
+                        return;
+                    }
+                };
+                let url = url.clone();
+                let notification = notification.clone();
+                runtime.spawn(async move { post(&url, &notification).await });
+            }
+        }
+    }
+}
+
+// Send a notification to every sink
+pub fn notify(sinks: &[NotificationSink], notification: &Notification) {
+    for sink in sinks {
+        sink.send(notification);
+    }
+}
+
+// Send a notification to every sink and wait for webhooks to take it, for commands that exit
+// straight after
+pub async fn deliver(sinks: &[NotificationSink], notification: &Notification) {
+    for sink in sinks {
+        match sink {
+            NotificationSink::Webhook { url } => post(url, notification).await,
+            NotificationSink::Log | NotificationSink::File { .. } => sink.send(notification),
+        }
+    }
+}
+
+async fn post(url: &str, notification: &Notification) {
+    // Serializing strings can't fail
+    let body = serde_json::to_string(notification).unwrap_or_default();
+    let response = reqwest::Client::new()
+        .post(url)
+        .header("Content-Type", "application/json")
+        .body(body)
+        .send()
+        .await
+        .and_then(|response| response.error_for_status());
+    if let Err(e) = response {
+        log::error!("Failed to send notification to {}: {}", url, e);
+    }
+}
+
+fn append(path: &Path, notification: &Notification) -> Result<(), Box<dyn std::error::Error>> {
+    if let Some(parent) = path.parent() {
+        if !parent.as_os_str().is_empty() {
+            std::fs::create_dir_all(parent)?;
+        }
+    }
+    let mut line = serde_json::to_string(notification)?;
+    line.push('\n');
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)?
+        .write_all(line.as_bytes())?;
+    Ok(())
+}
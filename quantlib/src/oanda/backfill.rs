@@ -0,0 +1,146 @@
+use std::collections::HashMap;
+
+use crate::oanda::helpers::parse_time;
+use crate::oanda::objects::{Candle, OandaSettings, Price, PriceStatus, StreamItem};
+use crate::oanda::pricing_api::get_candles;
+
+// The finest candles OANDA has, each backfilled price is the close of one
+pub const BACKFILL_GRANULARITY: &str = "S5";
+const CANDLE_MILLIS: u64 = 5_000;
+
+// OANDA returns at most 5000 candles per request, a little under 7 hours of S5 candles. Longer
+// outages are only backfilled for their last 7 hours.
+const MAX_CANDLES: u64 = 5_000;
+
+// Requests made going back for recent candles, enough to step over a long weekend
+const MAX_REQUESTS: usize = 8;
+
+// Prices streamed while a connection is down are lost for good. GapTracker remembers the last
+// price of each instrument, and once the stream has reconnected fetches candles covering the
+// time since from OANDA, to stand in for what was missed. Best-effort: instruments whose
+// candles can't be fetched are skipped, and a candle only gives the last price of its 5 seconds.
+#[derive(Debug, Default, Clone)]
+pub struct GapTracker {
+    last: HashMap<String, u64>,
+}
+
+impl GapTracker {
+    pub fn record(&mut self, item: &StreamItem) {
+        match item {
+            StreamItem::Price(price) | StreamItem::Backfill(price) => {
+                let last = self.last.entry(price.instrument.clone()).or_default();
+                *last = (*last).max(price.time);
+            }
+            StreamItem::Dropped(_) | StreamItem::Heartbeat(_) => {}
+        }
+    }
+
+    // Backfilled prices between the last price of each instrument and `until` (milliseconds
+    // since the epoch, normally when the stream reconnected), in timestamp order
+    pub async fn backfill(&mut self, settings: &OandaSettings, until: u64) -> Vec<StreamItem> {
+        let mut prices = Vec::new();
+        for (instrument, last) in self.last.iter() {
+            let from = (*last).max(until.saturating_sub(MAX_CANDLES * CANDLE_MILLIS));
+            if from >= until {
+                continue;
+            }
+
+            let candles =
+                match get_candles(instrument, BACKFILL_GRANULARITY, from, until, settings).await {
+                    Ok(candles) => candles,
+                    Err(err) => {
+                        log::warn!("[{}] Failed to backfill: {}", instrument, err);
+                        continue;
+                    }
+                };
+            let before = prices.len();
+            prices.extend(
+                candles
+                    .iter()
+                    .filter_map(|candle| candle_price(instrument, candle, CANDLE_MILLIS, until))
+                    .filter(|price| price.time > *last),
+            );
+            log::info!(
+                "[{}] Backfilled {} prices since {}",
+                instrument,
+                prices.len() - before,
+                last
+            );
+        }
+
+        prices.sort_by_key(|price| price.time);
+        let items: Vec<StreamItem> = prices.into_iter().map(StreamItem::Backfill).collect();
+        for item in &items {
+            self.record(item);
+        }
+        items
+    }
+}
+
+// The last `count` complete candles of an instrument before `until` as prices, the close of
+// each, e.g. for strategies to warm up on. Goes back over weekends and holidays without
+// candles, giving fewer than `count` if there aren't that many within a few requests.
+pub async fn recent_prices(
+    instrument: &str,
+    granularity: &str,
+    count: u64,
+    until: u64,
+    settings: &OandaSettings,
+) -> Result<Vec<Price>, Box<dyn std::error::Error>> {
+    let millis = granularity_millis(granularity)
+        .ok_or_else(|| format!("Unknown candle granularity {}", granularity))?;
+    let mut prices: Vec<Price> = Vec::new();
+    let mut to = until;
+    for _ in 0..MAX_REQUESTS {
+        let missing = count.saturating_sub(prices.len() as u64);
+        if missing == 0 {
+            break;
+        }
+        let from = to.saturating_sub(missing.min(MAX_CANDLES) * millis);
+        let candles = get_candles(instrument, granularity, from, to, settings).await?;
+        let mut earlier: Vec<Price> = candles
+            .iter()
+            .filter(|candle| candle.complete)
+            .filter_map(|candle| candle_price(instrument, candle, millis, until))
+            .collect();
+        earlier.append(&mut prices);
+        prices = earlier;
+        to = from;
+    }
+    let excess = prices.len().saturating_sub(count as usize);
+    Ok(prices.split_off(excess))
+}
+
+// Length of a candle of one of OANDA's granularities, e.g. "S5", "M15", "H4" or "D"
+pub fn granularity_millis(granularity: &str) -> Option<u64> {
+    let (unit, count) = (granularity.get(..1)?, granularity.get(1..)?);
+    let count: u64 = if count.is_empty() {
+        1
+    } else {
+        count.parse().ok()?
+    };
+    let unit = match unit {
+        "S" => 1_000,
+        "M" => 60_000,
+        "H" => 3_600_000,
+        "D" => 86_400_000,
+        "W" => 7 * 86_400_000,
+        _ => return None,
+    };
+    Some(unit * count)
+}
+
+// The close of a candle, timed at the end of the candle (or `until` if that's earlier) so it's
+// never older than the prices it stands in for
+fn candle_price(instrument: &str, candle: &Candle, millis: u64, until: u64) -> Option<Price> {
+    let (start, _) = parse_time(&candle.time).ok()?;
+    Some(Price {
+        bid: candle.bid.as_ref()?.c as f32,
+        ask: candle.ask.as_ref()?.c as f32,
+        time: (start + millis - 1).min(until),
+        nanos: 0,
+        instrument: instrument.to_string(),
+        tradeable: true,
+        status: PriceStatus::Tradeable,
+    })
+}
@@ -0,0 +1,183 @@
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+
+const MINUTE: u64 = 60_000;
+
+// Counts of what was received from the stream
+#[derive(Debug, Default, Clone)]
+pub struct ConnectionStats {
+    pub heartbeats: u64,
+    pub chunks: u64,
+    pub bytes: u64,
+    pub parse_errors: u64,
+    pub reconnects: u64,
+
+    // Longest time between two heartbeats, in milliseconds
+    pub max_heartbeat_gap: u64,
+}
+
+// A line of the connection quality log
+#[derive(Debug, Clone)]
+pub enum ConnectionLogEntry {
+    // Activity during the minute starting at `start`
+    Minute { start: u64, stats: ConnectionStats },
+    Reconnect { time: u64, reason: String },
+}
+
+// Compact log of the health of a streaming connection, used to rule out data loss when
+// investigating gaps. Activity is aggregated into one line per minute, reconnects get their own line:
+// M,<minute start ms>,<heartbeats>,<chunks>,<bytes>,<parse errors>,<longest heartbeat gap ms>
+// R,<time ms>,<reason>
+pub struct ConnectionQualityLog {
+    writer: BufWriter<File>,
+    minute: u64,
+    current: ConnectionStats,
+    last_heartbeat: Option<u64>,
+
+    // Running totals since the log was opened
+    pub totals: ConnectionStats,
+}
+
+impl ConnectionQualityLog {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, Box<dyn std::error::Error>> {
+        let file = std::fs::OpenOptions::new()
+            .append(true)
+            .create(true)
+            .open(path)?;
+
+        Ok(ConnectionQualityLog {
+            writer: BufWriter::new(file),
+            minute: 0,
+            current: ConnectionStats::default(),
+            last_heartbeat: None,
+            totals: ConnectionStats::default(),
+        })
+    }
+
+    pub fn record_chunk(&mut self, bytes: usize) {
+        self.roll(now());
+        self.current.chunks += 1;
+        self.current.bytes += bytes as u64;
+        self.totals.chunks += 1;
+        self.totals.bytes += bytes as u64;
+    }
+
+    pub fn record_heartbeat(&mut self) {
+        let now = now();
+        self.roll(now);
+
+        if let Some(last_heartbeat) = self.last_heartbeat {
+            let gap = now.saturating_sub(last_heartbeat);
+            self.current.max_heartbeat_gap = self.current.max_heartbeat_gap.max(gap);
+            self.totals.max_heartbeat_gap = self.totals.max_heartbeat_gap.max(gap);
+        }
+        self.last_heartbeat = Some(now);
+
+        self.current.heartbeats += 1;
+        self.totals.heartbeats += 1;
+    }
+
+    pub fn record_parse_errors(&mut self, count: usize) {
+        if count == 0 {
+            return;
+        }
+        self.roll(now());
+        self.current.parse_errors += count as u64;
+        self.totals.parse_errors += count as u64;
+    }
+
+    pub fn record_reconnect(&mut self, reason: &str) {
+        let now = now();
+        self.roll(now);
+        self.totals.reconnects += 1;
+
+        // Commas would break the line format
+        let reason = reason.replace([',', '\n'], " ");
+        if let Err(err) = writeln!(self.writer, "R,{},{}", now, reason) {
+            log::error!("Failed to write to connection quality log: {}", err);
+        }
+    }
+
+    pub fn flush(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        self.write_minute();
+        self.writer.flush()?;
+        Ok(())
+    }
+
+    // Write out the current minute's stats once a new minute starts
+    fn roll(&mut self, now: u64) {
+        let minute = now - now % MINUTE;
+        if minute != self.minute {
+            self.write_minute();
+            self.minute = minute;
+        }
+    }
+
+    fn write_minute(&mut self) {
+        if self.minute == 0 {
+            return;
+        }
+
+        let stats = std::mem::take(&mut self.current);
+        let result = writeln!(
+            self.writer,
+            "M,{},{},{},{},{},{}",
+            self.minute,
+            stats.heartbeats,
+            stats.chunks,
+            stats.bytes,
+            stats.parse_errors,
+            stats.max_heartbeat_gap
+        );
+        if let Err(err) = result {
+            log::error!("Failed to write to connection quality log: {}", err);
+        }
+    }
+}
+
+impl Drop for ConnectionQualityLog {
+    fn drop(&mut self) {
+        let _ = self.flush();
+    }
+}
+
+pub fn read_connection_log<P: AsRef<Path>>(
+    path: P,
+) -> Result<Vec<ConnectionLogEntry>, Box<dyn std::error::Error>> {
+    let reader = BufReader::new(File::open(path)?);
+
+    let mut entries = Vec::new();
+    for line in reader.lines() {
+        let line = line?;
+        let fields: Vec<&str> = line.split(',').collect();
+        match fields.as_slice() {
+            ["M", start, heartbeats, chunks, bytes, parse_errors, max_heartbeat_gap] => {
+                entries.push(ConnectionLogEntry::Minute {
+                    start: start.parse()?,
+                    stats: ConnectionStats {
+                        heartbeats: heartbeats.parse()?,
+                        chunks: chunks.parse()?,
+                        bytes: bytes.parse()?,
+                        parse_errors: parse_errors.parse()?,
+                        reconnects: 0,
+                        max_heartbeat_gap: max_heartbeat_gap.parse()?,
+                    },
+                });
+            }
+            ["R", time, reason] => {
+                entries.push(ConnectionLogEntry::Reconnect {
+                    time: time.parse()?,
+                    reason: reason.to_string(),
+                });
+            }
+            _ => log::warn!("Skipping malformed connection log line: {}", line),
+        }
+    }
+
+    Ok(entries)
+}
+
+fn now() -> u64 {
+    chrono::Utc::now().timestamp_millis() as u64
+}
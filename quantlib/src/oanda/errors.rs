@@ -9,4 +9,152 @@ impl std::fmt::Display for EmptyChunkError {
     }
 }
 
-impl std::error::Error for EmptyChunkError {}
\ No newline at end of file
+impl std::error::Error for EmptyChunkError {}
+
+#[derive(Debug)]
+pub struct StreamTimeoutError {
+    pub message: String,
+}
+
+impl std::fmt::Display for StreamTimeoutError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "StreamTimeoutError: {}", self.message)
+    }
+}
+
+impl std::error::Error for StreamTimeoutError {}
+
+// An order may or may not have reached OANDA, and checking its status also failed
+// The caller must reconcile positions before trading this instrument again
+#[derive(Debug)]
+pub struct OrderStateUnknownError {
+    pub client_order_id: String,
+    pub message: String,
+}
+
+impl std::fmt::Display for OrderStateUnknownError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "OrderStateUnknownError: order {}: {}",
+            self.client_order_id, self.message
+        )
+    }
+}
+
+impl std::error::Error for OrderStateUnknownError {}
+
+// OANDA rejected an order for carrying the client order ID of one it already has, i.e. an
+// earlier attempt at the same order got through
+#[derive(Debug)]
+pub struct DuplicateClientOrderIdError {
+    pub client_order_id: String,
+}
+
+impl std::fmt::Display for DuplicateClientOrderIdError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "DuplicateClientOrderIdError: order {} was already placed",
+            self.client_order_id
+        )
+    }
+}
+
+impl std::error::Error for DuplicateClientOrderIdError {}
+
+// OANDA answered a request with a status other than success
+#[derive(Debug)]
+pub struct ApiStatusError {
+    pub status: reqwest::StatusCode,
+    pub endpoint: String,
+}
+
+impl std::fmt::Display for ApiStatusError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "Received non-success status code {} from {}",
+            self.status, self.endpoint
+        )
+    }
+}
+
+impl std::error::Error for ApiStatusError {}
+
+// A stream sent more than the parser's buffer limit without finishing a line
+#[derive(Debug)]
+pub struct ParseBufferOverflowError {
+    pub message: String,
+}
+
+impl std::fmt::Display for ParseBufferOverflowError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "ParseBufferOverflowError: {}", self.message)
+    }
+}
+
+impl std::error::Error for ParseBufferOverflowError {}
+
+// OANDA refused to open a stream for the instruments asked for, e.g. because there were too many
+#[derive(Debug)]
+pub struct StreamRejectedError {
+    pub status: u16,
+    pub message: String,
+}
+
+impl std::fmt::Display for StreamRejectedError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "StreamRejectedError: status {}: {}",
+            self.status, self.message
+        )
+    }
+}
+
+impl std::error::Error for StreamRejectedError {}
+
+impl StreamRejectedError {
+    // Whether it was for the number of instruments, rather than e.g. one that doesn't exist, so
+    // that asking for fewer can succeed
+    pub fn too_many_instruments(&self) -> bool {
+        let message = self.message.to_lowercase();
+        self.status == 414 || message.contains("too many") || message.contains("exceed")
+    }
+}
+
+// OANDA refused the stream's token or account (401 or 403), which no reconnect will change
+#[derive(Debug, Clone)]
+pub struct StreamAuthError {
+    pub status: u16,
+    pub message: String,
+}
+
+impl std::fmt::Display for StreamAuthError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "StreamAuthError: status {}: {}",
+            self.status, self.message
+        )
+    }
+}
+
+impl std::error::Error for StreamAuthError {}
+
+// A stream stopped sending anything that parses, e.g. because OANDA changed its format. The
+// lines that didn't parse are kept to be looked at.
+#[derive(Debug, Clone)]
+pub struct StreamProtocolError {
+    pub message: String,
+    pub payload: String,
+}
+
+impl std::fmt::Display for StreamProtocolError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "StreamProtocolError: {}", self.message)
+    }
+}
+
+impl std::error::Error for StreamProtocolError {}
@@ -0,0 +1,81 @@
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Write};
+use std::path::Path;
+
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+use crate::oanda::objects::{Price, StreamItem};
+use crate::oanda::parser::StreamParser;
+
+// A stream recorded chunk by chunk, exactly as the chunks arrived, so that tests can replay real
+// chunk boundaries (including JSON split over two chunks) instead of whole lines. Saved as JSON:
+// {"description": "...", "chunks": ["{\"type\":\"PRICE\",\"time\":\"2024-", "01-02T10:00:00Z\",...}\n"]}
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct StreamFixture {
+    #[serde(default)]
+    pub description: String,
+    pub chunks: Vec<String>,
+}
+
+impl StreamFixture {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, Box<dyn std::error::Error>> {
+        let reader = BufReader::new(File::open(path)?);
+        Ok(serde_json::from_reader(reader)?)
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), Box<dyn std::error::Error>> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        serde_json::to_writer_pretty(&mut writer, self)?;
+        writer.flush()?;
+        Ok(())
+    }
+
+    // OANDA's streams are plain ASCII, so chunks are kept as text to keep fixtures readable
+    pub fn record(&mut self, chunk: &[u8]) {
+        self.chunks
+            .push(String::from_utf8_lossy(chunk).into_owned());
+    }
+
+    // The same stream cut into chunks of `size` bytes (the last may be shorter) instead of the
+    // recorded boundaries, e.g. 1 to split every message at every possible point
+    pub fn rechunk(&self, size: usize) -> StreamFixture {
+        let bytes = self.chunks.concat().into_bytes();
+        StreamFixture {
+            description: format!("{} (in chunks of {} bytes)", self.description, size),
+            chunks: bytes
+                .chunks(size.max(1))
+                .map(|chunk| String::from_utf8_lossy(chunk).into_owned())
+                .collect(),
+        }
+    }
+
+    // Every item of the stream, parsed chunk by chunk the same way the streams parse them
+    pub fn parse<T: DeserializeOwned>(&self) -> Result<Vec<T>, Box<dyn std::error::Error>> {
+        self.parse_with(&mut StreamParser::new())
+    }
+
+    // The same with a given parser, e.g. to check its stats afterwards
+    pub fn parse_with<T: DeserializeOwned>(
+        &self,
+        parser: &mut StreamParser<T>,
+    ) -> Result<Vec<T>, Box<dyn std::error::Error>> {
+        let mut items = Vec::new();
+        for chunk in &self.chunks {
+            items.extend(parser.parse(chunk.as_bytes())?);
+        }
+        Ok(items)
+    }
+
+    // The prices of a recorded price stream, e.g. to feed a strategy
+    pub fn prices(&self) -> Result<Vec<Price>, Box<dyn std::error::Error>> {
+        let items = self.parse::<StreamItem>()?;
+        Ok(items
+            .into_iter()
+            .filter_map(|item| match item {
+                StreamItem::Price(price) => Some(price),
+                StreamItem::Backfill(_) | StreamItem::Dropped(_) | StreamItem::Heartbeat(_) => None,
+            })
+            .collect())
+    }
+}
@@ -14,16 +14,22 @@ where
     s.parse::<f32>().map_err(serde::de::Error::custom)
 }
 
-pub fn deserialize_time_in_millis_from_string<'de, D>(deserializer: D) -> Result<u64, D::Error>
+// Parse an OANDA timestamp into milliseconds since the UNIX epoch and the nanoseconds past that
+// millisecond. OANDA timestamps are in RFC3339 format: "2023-09-15T20:58:00.145575162Z"
+pub fn parse_time(s: &str) -> Result<(u64, u32), String> {
+    let datetime = chrono::DateTime::parse_from_rfc3339(s)
+        .map_err(|e| format!("Failed to parse datetime: {}", e))?
+        .with_timezone(&chrono::Utc);
+    let nanos = datetime.timestamp_subsec_nanos() % 1_000_000;
+    Ok((datetime.timestamp_millis() as u64, nanos))
+}
+pub fn deserialize_option_f64_from_string<'de, D>(deserializer: D) -> Result<Option<f64>, D::Error>
 where
     D: serde::Deserializer<'de>,
 {
-    let s: &str = serde::Deserialize::deserialize(deserializer)?;
-
-    // Parse time string into milliseconds since UNIX epoch
-    // OANDA timestamps are in RFC3339 format: "2023-09-15T20:58:00.145575162Z"
-    let datetime = chrono::DateTime::parse_from_rfc3339(s)
-        .map_err(|e| serde::de::Error::custom(format!("Failed to parse datetime: {}", e)))?;
-    let millis_since_epoch = datetime.timestamp_millis() as u64;
-    Ok(millis_since_epoch)
-}
\ No newline at end of file
+    let s: Option<&str> = serde::Deserialize::deserialize(deserializer)?;
+    match s {
+        Some(s) => s.parse::<f64>().map(Some).map_err(serde::de::Error::custom),
+        None => Ok(None),
+    }
+}
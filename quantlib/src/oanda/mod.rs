@@ -4,10 +4,53 @@ pub mod objects;
 pub mod helpers;
 // pub use helpers::*;
 
+pub mod parser;
+pub use parser::*;
+
+#[cfg(feature = "streaming")]
 pub mod streaming_api;
+#[cfg(feature = "streaming")]
 pub use streaming_api::*;
 
+#[cfg(feature = "streaming")]
+pub mod sharded_stream;
+#[cfg(feature = "streaming")]
+pub use sharded_stream::*;
+
+#[cfg(feature = "streaming")]
+pub mod backfill;
+#[cfg(feature = "streaming")]
+pub use backfill::*;
+
+#[cfg(feature = "streaming")]
+pub mod multiplexer;
+#[cfg(feature = "streaming")]
+pub use multiplexer::*;
+
+pub mod pricing_api;
+pub use pricing_api::*;
+
+#[cfg(feature = "trading")]
 pub mod trading_api;
+#[cfg(feature = "trading")]
 pub use trading_api::*;
 
-pub mod errors;
\ No newline at end of file
+#[cfg(feature = "trading")]
+pub mod order_client;
+#[cfg(feature = "trading")]
+pub use order_client::*;
+
+#[cfg(feature = "streaming")]
+pub mod connection_quality;
+
+pub mod subscription;
+pub use subscription::*;
+
+pub mod usage;
+
+pub mod errors;
+
+pub mod stream_errors;
+pub use stream_errors::*;
+
+pub mod fixtures;
\ No newline at end of file
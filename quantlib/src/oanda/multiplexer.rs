@@ -0,0 +1,215 @@
+use std::collections::{BTreeMap, HashSet};
+use std::sync::{Arc, Mutex};
+
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+
+use crate::conflation::DroppedTicks;
+use crate::oanda::objects::{DroppedPrices, StreamItem};
+use crate::oanda::sharded_stream::ShardedPriceStream;
+
+// Items buffered per subscriber before it is considered too slow and starts missing items
+const SUBSCRIBER_BUFFER: usize = 4096;
+
+// Prices missed by slow subscribers are logged by instrument once a minute
+const DROPPED_TICKS_PERIOD: u64 = 60_000;
+
+struct Subscriber {
+    // Instruments this subscriber wants prices for, heartbeats are sent to everyone
+    instruments: HashSet<String>,
+    sender: mpsc::Sender<StreamItem>,
+
+    // Prices of each instrument it missed while its buffer was full, not yet reported to it
+    dropped: BTreeMap<String, u64>,
+}
+
+impl Subscriber {
+    fn wants(&self, item: &StreamItem) -> bool {
+        match item {
+            StreamItem::Price(price) | StreamItem::Backfill(price) => {
+                self.instruments.contains(&price.instrument)
+            }
+            StreamItem::Dropped(_) | StreamItem::Heartbeat(_) => true,
+        }
+    }
+
+    // Send an item, counting a price as missed if the subscriber is behind. Returns whether it
+    // was sent, None once the subscriber has disconnected.
+    fn send(&mut self, item: StreamItem) -> Option<bool> {
+        match self.sender.try_send(item) {
+            Ok(()) => Some(true),
+            Err(mpsc::error::TrySendError::Full(item)) => {
+                if let StreamItem::Price(price) | StreamItem::Backfill(price) = item {
+                    *self.dropped.entry(price.instrument).or_default() += 1;
+                }
+                Some(false)
+            }
+            Err(mpsc::error::TrySendError::Closed(_)) => None,
+        }
+    }
+
+    // Tell the subscriber what it missed once it has room for it again
+    fn report_dropped(&mut self) {
+        if self.dropped.is_empty() || self.sender.capacity() == 0 {
+            return;
+        }
+        let dropped = std::mem::take(&mut self.dropped);
+        let _ = self.sender.try_send(StreamItem::Dropped(DroppedPrices {
+            time: chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Nanos, true),
+            dropped,
+        }));
+    }
+}
+
+// Fans a single price stream out to any number of subscribers, so that everything that needs
+// prices can share one set of OANDA connections instead of each opening its own.
+// Subscribers in other processes connect through the relay (see serve_relay). A subscriber too
+// slow to keep up misses prices rather than holding up the others, and is sent how many of each
+// instrument it missed (StreamItem::Dropped) once it has caught up enough to take it, so a trader
+// reading through the relay counts them with its own (see DroppedTicks).
+pub struct StreamMultiplexer {
+    subscribers: Arc<Mutex<Vec<Subscriber>>>,
+    instruments: HashSet<String>,
+    task: JoinHandle<()>,
+}
+
+impl StreamMultiplexer {
+    // Must be called from within a tokio runtime, the source is read by its own task
+    pub fn new(instruments: &[String], mut source: ShardedPriceStream) -> Self {
+        let subscribers: Arc<Mutex<Vec<Subscriber>>> = Arc::new(Mutex::new(Vec::new()));
+
+        let task_subscribers = subscribers.clone();
+        let task = tokio::spawn(async move {
+            let mut dropped = DroppedTicks::new(DROPPED_TICKS_PERIOD);
+            loop {
+                let item = match source.next_item().await {
+                    Ok(item) => item,
+                    Err(err) => {
+                        // Shards reconnect on their own, there is nothing to do but wait
+                        log::error!("Multiplexer source error: {}", err);
+                        continue;
+                    }
+                };
+
+                let mut subscribers = task_subscribers.lock().unwrap();
+                subscribers.retain_mut(|subscriber| {
+                    if !subscriber.wants(&item) {
+                        return true;
+                    }
+                    // Never let one slow subscriber hold up the others
+                    match subscriber.send(item.clone()) {
+                        Some(true) => {}
+                        Some(false) => {
+                            if let StreamItem::Price(price) | StreamItem::Backfill(price) = &item {
+                                dropped.record(&price.instrument, 1);
+                            }
+                        }
+                        None => {
+                            log::info!("Subscriber disconnected");
+                            return false;
+                        }
+                    }
+                    subscriber.report_dropped();
+                    true
+                });
+                drop(subscribers);
+
+                let now = chrono::Utc::now().timestamp_millis() as u64;
+                if dropped.due(now) {
+                    if let Some(period) = dropped.roll(now) {
+                        log::warn!(
+                            "Subscribers fell behind, {} prices dropped in the last {}s: {}",
+                            period.total(),
+                            (period.end - period.start) / 1000,
+                            period.summary()
+                        );
+                    }
+                }
+            }
+        });
+
+        StreamMultiplexer {
+            subscribers,
+            instruments: instruments.iter().cloned().collect(),
+            task,
+        }
+    }
+
+    // Receive every price for the given instruments, plus heartbeats
+    pub fn subscribe(&self, instruments: Vec<String>) -> mpsc::Receiver<StreamItem> {
+        for instrument in &instruments {
+            if !self.instruments.contains(instrument) {
+                log::warn!(
+                    "Subscription to {} which is not being streamed, no prices will be received",
+                    instrument
+                );
+            }
+        }
+
+        let (sender, receiver) = mpsc::channel(SUBSCRIBER_BUFFER);
+        self.subscribers.lock().unwrap().push(Subscriber {
+            instruments: instruments.into_iter().collect(),
+            sender,
+            dropped: BTreeMap::new(),
+        });
+        receiver
+    }
+
+    pub fn subscriber_count(&self) -> usize {
+        self.subscribers.lock().unwrap().len()
+    }
+}
+
+impl Drop for StreamMultiplexer {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+// Serve the multiplexed stream to other processes over TCP.
+// The protocol mirrors OANDA's: a client sends a comma separated list of instruments on one line,
+// and receives newline delimited JSON prices and heartbeats in OANDA's streaming format, so
+// clients parse it with the same code they use for OANDA (see ChunkSource::Relay).
+pub async fn serve_relay(
+    multiplexer: Arc<StreamMultiplexer>,
+    address: &str,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let listener = tokio::net::TcpListener::bind(address).await?;
+    log::info!("Relaying prices on {}", address);
+
+    loop {
+        let (socket, peer) = listener.accept().await?;
+        log::info!("Relay client connected from {}", peer);
+        tokio::spawn(handle_relay_client(socket, multiplexer.clone()));
+    }
+}
+
+async fn handle_relay_client(socket: tokio::net::TcpStream, multiplexer: Arc<StreamMultiplexer>) {
+    let (reader, mut writer) = socket.into_split();
+
+    let mut lines = BufReader::new(reader).lines();
+    let instruments: Vec<String> = match lines.next_line().await {
+        Ok(Some(line)) => line
+            .split(',')
+            .map(|instrument| instrument.trim().to_string())
+            .filter(|instrument| !instrument.is_empty())
+            .collect(),
+        _ => {
+            log::warn!("Relay client disconnected before subscribing");
+            return;
+        }
+    };
+
+    log::info!("Relay client subscribed to {}", instruments.join(","));
+    let mut receiver = multiplexer.subscribe(instruments);
+    while let Some(item) = receiver.recv().await {
+        if writer
+            .write_all(item.to_json_line().as_bytes())
+            .await
+            .is_err()
+        {
+            break;
+        }
+    }
+}
@@ -1,44 +1,90 @@
+use std::collections::BTreeMap;
+
 use serde::Deserialize;
 
+use crate::calendar;
 use crate::oanda::helpers::{
     deserialize_f32_from_string, deserialize_f64_from_string,
-    deserialize_time_in_millis_from_string,
+    deserialize_option_f64_from_string, parse_time,
 };
 
 pub const STREAMING_URL: &str = "https://stream-fxpractice.oanda.com";
 pub const API_URL: &str = "https://api-fxpractice.oanda.com";
 
-#[derive(Debug, Deserialize)]
-pub struct Settings {
-    pub instruments: Vec<String>,
-    pub units: f64,
-    pub oanda: OandaSettings,
-}
-
-#[derive(Debug, Deserialize)]
+#[derive(Deserialize, Clone)]
 pub struct OandaSettings {
     pub account_id: String,
+    // The API token, in plain text or age encrypted (see secrets)
     pub authorization: String,
 }
 
+// Never print the token
+impl std::fmt::Debug for OandaSettings {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("OandaSettings")
+            .field("account_id", &self.account_id)
+            .field("authorization", &"[REDACTED]")
+            .finish()
+    }
+}
+
 #[derive(Debug, Deserialize)]
 pub struct Response {
     pub prices: Vec<Price>,
 }
 
 #[derive(Debug, Deserialize)]
+#[serde(try_from = "PriceMessage")]
 pub struct Price {
-    #[serde(deserialize_with = "deserialize_f32_from_string")]
-    #[serde(rename = "closeoutBid")]
     pub bid: f32,
-
-    #[serde(deserialize_with = "deserialize_f32_from_string")]
-    #[serde(rename = "closeoutAsk")]
     pub ask: f32,
 
-    #[serde(deserialize_with = "deserialize_time_in_millis_from_string")]
+    // Milliseconds since the UNIX epoch, and the nanoseconds past that millisecond. OANDA's
+    // timestamps are precise to the nanosecond, but most uses only need milliseconds.
     pub time: u64,
+    pub nanos: u32,
+
     pub instrument: String,
+
+    // Whether the price can be traded on. Around the daily close and over weekends OANDA keeps
+    // streaming indicative prices that orders won't be filled at. Recorded prices don't keep
+    // these, so they are always tradeable.
+    pub tradeable: bool,
+    pub status: PriceStatus,
+}
+
+impl Price {
+    // Nanoseconds since the UNIX epoch
+    pub fn timestamp_nanos(&self) -> u64 {
+        self.time * 1_000_000 + self.nanos as u64
+    }
+
+    pub fn is_tradeable(&self) -> bool {
+        self.tradeable && self.status == PriceStatus::Tradeable
+    }
+}
+
+// OANDA's (deprecated but still sent) status of a price, "tradeable" unless the market is closed
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PriceStatus {
+    #[default]
+    #[serde(rename = "tradeable")]
+    Tradeable,
+    #[serde(rename = "non-tradeable")]
+    NonTradeable,
+    #[serde(rename = "invalid")]
+    #[serde(other)]
+    Invalid,
+}
+
+impl PriceStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PriceStatus::Tradeable => "tradeable",
+            PriceStatus::NonTradeable => "non-tradeable",
+            PriceStatus::Invalid => "invalid",
+        }
+    }
 }
 
 impl Clone for Price {
@@ -47,26 +93,268 @@ impl Clone for Price {
             bid: self.bid,
             ask: self.ask,
             time: self.time,
+            nanos: self.nanos,
             instrument: self.instrument.clone(),
+            tradeable: self.tradeable,
+            status: self.status,
         }
     }
 }
 
-#[derive(Debug, Deserialize)]
+// A price as OANDA sends it, with the timestamp still a string
+#[derive(Deserialize)]
+struct PriceMessage {
+    #[serde(deserialize_with = "deserialize_f32_from_string")]
+    #[serde(rename = "closeoutBid")]
+    bid: f32,
+
+    #[serde(deserialize_with = "deserialize_f32_from_string")]
+    #[serde(rename = "closeoutAsk")]
+    ask: f32,
+
+    time: String,
+    instrument: String,
+
+    // Both are missing from prices relayed by older versions, which only relayed tradeable ones
+    #[serde(default = "default_tradeable")]
+    tradeable: bool,
+    #[serde(default)]
+    status: PriceStatus,
+
+    // Set on backfilled prices passed on by a relay, which mustn't be taken for streamed ones
+    #[serde(default)]
+    backfilled: bool,
+}
+
+fn default_tradeable() -> bool {
+    true
+}
+
+impl TryFrom<PriceMessage> for Price {
+    type Error = String;
+
+    fn try_from(message: PriceMessage) -> Result<Self, Self::Error> {
+        if message.backfilled {
+            return Err("Backfilled price".to_string());
+        }
+        let (time, nanos) = parse_time(&message.time)?;
+        Ok(Price {
+            bid: message.bid,
+            ask: message.ask,
+            time,
+            nanos,
+            instrument: message.instrument,
+            tradeable: message.tradeable,
+            status: message.status,
+        })
+    }
+}
+
+#[derive(Debug, Deserialize, Clone)]
 pub struct Heartbeat {
     pub time: String,
 }
 
-#[derive(Debug, Deserialize)]
+impl Heartbeat {
+    // Milliseconds since the UNIX epoch, None if OANDA's timestamp can't be parsed
+    pub fn millis(&self) -> Option<u64> {
+        parse_time(&self.time).ok().map(|(time, _)| time)
+    }
+}
+
+// Prices of each instrument the stream relay dropped for a subscriber that fell behind, sent to
+// it once it has room again (see StreamMultiplexer)
+#[derive(Debug, Deserialize, Clone)]
+pub struct DroppedPrices {
+    pub time: String,
+    pub dropped: BTreeMap<String, u64>,
+}
+
+#[derive(Debug, Deserialize, Clone)]
 #[serde(untagged)]
 pub enum StreamItem {
     Price(Price),
+
+    // A price fetched after a reconnect to cover the outage rather than streamed (see
+    // oanda::backfill). It's the close of a short candle, so only an approximation of what was
+    // missed, and older than the prices streamed since.
+    #[serde(deserialize_with = "deserialize_backfill")]
+    Backfill(Price),
+
+    // Before heartbeats, which only need a time
+    Dropped(DroppedPrices),
+
     Heartbeat(Heartbeat),
 }
 
+fn deserialize_backfill<'de, D>(deserializer: D) -> Result<Price, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let mut message = PriceMessage::deserialize(deserializer)?;
+    if !message.backfilled {
+        return Err(serde::de::Error::custom("Not a backfilled price"));
+    }
+    message.backfilled = false;
+    Price::try_from(message).map_err(serde::de::Error::custom)
+}
+
+impl StreamItem {
+    // Serialize the item back into a line of OANDA's streaming format, so it can be relayed to
+    // other processes and parsed by the same code that parses OANDA's stream
+    pub fn to_json_line(&self) -> String {
+        match self {
+            StreamItem::Price(price) => price_line(price, ""),
+            StreamItem::Backfill(price) => price_line(price, ",\"backfilled\":true"),
+            StreamItem::Dropped(dropped) => format!(
+                "{{\"type\":\"DROPPED\",\"time\":\"{}\",\"dropped\":{}}}\n",
+                dropped.time,
+                serde_json::to_string(&dropped.dropped).unwrap_or_default()
+            ),
+            StreamItem::Heartbeat(heartbeat) => {
+                format!("{{\"type\":\"HEARTBEAT\",\"time\":\"{}\"}}\n", heartbeat.time)
+            }
+        }
+    }
+}
+
+fn price_line(price: &Price, extra: &str) -> String {
+    let time = (calendar::utc(price.time) + chrono::Duration::nanoseconds(price.nanos as i64))
+        .format("%Y-%m-%dT%H:%M:%S%.9fZ");
+    format!(
+        "{{\"type\":\"PRICE\",\"instrument\":\"{}\",\"time\":\"{}\",\"closeoutBid\":\"{}\",\"closeoutAsk\":\"{}\",\"status\":\"{}\",\"tradeable\":{}{}}}\n",
+        price.instrument, time, price.bid, price.ask, price.status.as_str(), price.tradeable, extra
+    )
+}
+
+// Trading limits of an instrument, from the account instruments endpoint
+#[derive(Debug, Deserialize, Clone)]
+pub struct Instrument {
+    pub name: String,
+
+    // Number of decimal places allowed in an order's units, 0 for whole units only
+    #[serde(rename = "tradeUnitsPrecision")]
+    pub trade_units_precision: i32,
+
+    #[serde(deserialize_with = "deserialize_f64_from_string")]
+    #[serde(rename = "minimumTradeSize")]
+    pub minimum_trade_size: f64,
+
+    #[serde(deserialize_with = "deserialize_f64_from_string")]
+    #[serde(rename = "maximumOrderUnits")]
+    pub maximum_order_units: f64,
+
+    #[serde(default)]
+    pub financing: Option<InstrumentFinancing>,
+}
+
+// Annual financing rates of holding each side, negative when holding it costs money
+#[derive(Debug, Deserialize, Clone)]
+pub struct InstrumentFinancing {
+    #[serde(deserialize_with = "deserialize_f64_from_string")]
+    #[serde(rename = "longRate")]
+    pub long_rate: f64,
+
+    #[serde(deserialize_with = "deserialize_f64_from_string")]
+    #[serde(rename = "shortRate")]
+    pub short_rate: f64,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct InstrumentsResponse {
+    pub instruments: Vec<Instrument>,
+}
+
+// A level of the book behind a price, e.g. 1,000,000 units available at 1.09512
+#[derive(Debug, Deserialize, Clone)]
+pub struct PriceBucket {
+    #[serde(deserialize_with = "deserialize_f64_from_string")]
+    pub price: f64,
+    pub liquidity: f64,
+}
+
+// A price with the depth of the book, from the pricing endpoint. Bids and asks are ordered from
+// the best price outwards.
+#[derive(Debug, Deserialize, Clone)]
+pub struct PriceDepth {
+    pub instrument: String,
+
+    #[serde(default = "default_tradeable")]
+    pub tradeable: bool,
+
+    pub bids: Vec<PriceBucket>,
+    pub asks: Vec<PriceBucket>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PriceDepthResponse {
+    pub prices: Vec<PriceDepth>,
+}
+
+// Open, high, low and close of one side of a candle
+#[derive(Debug, Deserialize, Clone)]
+pub struct CandlePrices {
+    #[serde(deserialize_with = "deserialize_f64_from_string")]
+    pub o: f64,
+    #[serde(deserialize_with = "deserialize_f64_from_string")]
+    pub h: f64,
+    #[serde(deserialize_with = "deserialize_f64_from_string")]
+    pub l: f64,
+    #[serde(deserialize_with = "deserialize_f64_from_string")]
+    pub c: f64,
+}
+
+// A candle from the instrument candles endpoint, with the sides that were asked for. The time is
+// the start of the candle, and the last candle is incomplete while it's still forming.
+#[derive(Debug, Deserialize, Clone)]
+pub struct Candle {
+    pub time: String,
+    pub complete: bool,
+    pub volume: u64,
+    pub bid: Option<CandlePrices>,
+    pub ask: Option<CandlePrices>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CandlesResponse {
+    pub candles: Vec<Candle>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AccountSummaryResponse {
+    pub account: AccountSummary,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AccountSummary {
+    pub currency: String,
+    #[serde(deserialize_with = "deserialize_f64_from_string")]
+    pub balance: f64,
+    #[serde(deserialize_with = "deserialize_f64_from_string")]
+    #[serde(rename = "NAV")]
+    pub nav: f64,
+    #[serde(deserialize_with = "deserialize_f64_from_string")]
+    #[serde(rename = "unrealizedPL")]
+    pub unrealized_pl: f64,
+    #[serde(deserialize_with = "deserialize_f64_from_string")]
+    #[serde(rename = "marginUsed")]
+    pub margin_used: f64,
+    #[serde(deserialize_with = "deserialize_f64_from_string")]
+    #[serde(rename = "marginAvailable")]
+    pub margin_available: f64,
+    #[serde(rename = "openPositionCount")]
+    pub open_position_count: u32,
+    // Hedging accounts allow long and short positions in the same instrument to coexist
+    #[serde(rename = "hedgingEnabled")]
+    pub hedging_enabled: bool,
+}
+
 #[derive(Debug, Deserialize)]
 pub struct PositionResponse {
     pub positions: Vec<Position>,
+    // ID of the most recent transaction reflected in the positions
+    #[serde(rename = "lastTransactionID")]
+    pub last_transaction_id: String,
 }
 
 #[derive(Debug, Deserialize)]
@@ -76,7 +364,7 @@ pub struct Position {
     pub short: PositionDetails,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Default)]
 pub struct PositionDetails {
     #[serde(deserialize_with = "deserialize_f64_from_string")]
     pub units: f64,
@@ -85,17 +373,232 @@ pub struct PositionDetails {
     pub unrealized_pl: f64,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PositionSide {
+    Long,
+    Short,
+}
+
+impl PositionSide {
+    // Sign of the units of an order that opens a position on this side
+    pub fn sign(&self) -> f64 {
+        match self {
+            PositionSide::Long => 1.0,
+            PositionSide::Short => -1.0,
+        }
+    }
+}
+
+// How an order interacts with existing positions, see OANDA's OrderPositionFill
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PositionFill {
+    Default,
+    OpenOnly,
+    ReduceFirst,
+    ReduceOnly,
+}
+
+impl PositionFill {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PositionFill::Default => "DEFAULT",
+            PositionFill::OpenOnly => "OPEN_ONLY",
+            PositionFill::ReduceFirst => "REDUCE_FIRST",
+            PositionFill::ReduceOnly => "REDUCE_ONLY",
+        }
+    }
+}
+
 impl Position {
+    pub fn new(instrument: &str) -> Self {
+        Position {
+            instrument: instrument.to_string(),
+            long: PositionDetails::default(),
+            short: PositionDetails::default(),
+        }
+    }
+
+    // Grow (positive units) or shrink (negative units) one leg of the position
+    pub fn adjust_leg(&mut self, side: PositionSide, units: f64) {
+        match side {
+            PositionSide::Long => self.long.units += units,
+            PositionSide::Short => self.short.units -= units,
+        }
+    }
+
+    // Update the legs of the position with the trades opened and closed by an order fill
+    pub fn apply_fill(&mut self, fill: &Transaction) {
+        // Opened trades grow the leg matching the sign of their units
+        if let Some(opened) = &fill.trade_opened {
+            let side = if opened.units > 0.0 {
+                PositionSide::Long
+            } else {
+                PositionSide::Short
+            };
+            self.adjust_leg(side, opened.units.abs());
+        }
+
+        // Closed or reduced trades are reported in the direction of the fill, so selling units reduces the long leg
+        for reduced in fill.trades_closed.iter().chain(fill.trade_reduced.iter()) {
+            let side = if reduced.units < 0.0 {
+                PositionSide::Long
+            } else {
+                PositionSide::Short
+            };
+            self.adjust_leg(side, -reduced.units.abs());
+        }
+    }
+
+    // Net units held, only meaningful for netting accounts or as a summary of a hedged position
     pub fn units(&self) -> f64 {
-        let net = self.long.units + self.short.units;
-        println!(
-            "Net units: {}+{}={}",
-            self.long.units, self.short.units, net
-        );
-        net
+        self.long.units + self.short.units
+    }
+
+    // Size of one leg of the position, always positive (OANDA reports short units as negative)
+    pub fn leg_units(&self, side: PositionSide) -> f64 {
+        match side {
+            PositionSide::Long => self.long.units,
+            PositionSide::Short => -self.short.units,
+        }
     }
 
     pub fn unrealized_pl(&self) -> f64 {
         self.long.unrealized_pl + self.short.unrealized_pl
     }
 }
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct TradeOpen {
+    #[serde(rename = "tradeID")]
+    pub trade_id: String,
+    #[serde(deserialize_with = "deserialize_f64_from_string")]
+    pub units: f64,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct TradeReduce {
+    #[serde(rename = "tradeID")]
+    pub trade_id: String,
+    #[serde(deserialize_with = "deserialize_f64_from_string")]
+    pub units: f64,
+    #[serde(default, deserialize_with = "deserialize_option_f64_from_string")]
+    #[serde(rename = "realizedPL")]
+    pub realized_pl: Option<f64>,
+}
+
+// A transaction on the account, only the fields we use are parsed
+// The transaction stream also sends heartbeats in this shape, with a type of "HEARTBEAT" and no id
+#[derive(Debug, Clone, Deserialize)]
+pub struct Transaction {
+    #[serde(default)]
+    pub id: Option<String>,
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub time: String,
+    #[serde(default)]
+    pub instrument: Option<String>,
+    #[serde(default, deserialize_with = "deserialize_option_f64_from_string")]
+    pub units: Option<f64>,
+    #[serde(default, deserialize_with = "deserialize_option_f64_from_string")]
+    pub price: Option<f64>,
+    #[serde(default, deserialize_with = "deserialize_option_f64_from_string")]
+    pub pl: Option<f64>,
+    #[serde(default)]
+    pub reason: Option<String>,
+    #[serde(default)]
+    #[serde(rename = "clientOrderID")]
+    pub client_order_id: Option<String>,
+    // The order a fill filled
+    #[serde(default)]
+    #[serde(rename = "orderID")]
+    pub order_id: Option<String>,
+
+    #[serde(default)]
+    #[serde(rename = "tradeOpened")]
+    pub trade_opened: Option<TradeOpen>,
+    #[serde(default)]
+    #[serde(rename = "tradesClosed")]
+    pub trades_closed: Vec<TradeReduce>,
+    #[serde(default)]
+    #[serde(rename = "tradeReduced")]
+    pub trade_reduced: Option<TradeReduce>,
+
+    // Costs in the account currency: the commission charged for a fill, and the financing paid
+    // (negative) or received on the trades a fill closes or, in a DAILY_FINANCING, on each
+    // position held overnight
+    #[serde(default, deserialize_with = "deserialize_option_f64_from_string")]
+    pub commission: Option<f64>,
+    #[serde(default, deserialize_with = "deserialize_option_f64_from_string")]
+    pub financing: Option<f64>,
+    #[serde(default)]
+    #[serde(rename = "positionFinancings")]
+    pub position_financings: Vec<PositionFinancing>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct PositionFinancing {
+    pub instrument: String,
+    #[serde(deserialize_with = "deserialize_f64_from_string")]
+    pub financing: f64,
+}
+
+impl Transaction {
+    pub fn is_heartbeat(&self) -> bool {
+        self.kind == "HEARTBEAT"
+    }
+
+    pub fn is_order_fill(&self) -> bool {
+        self.kind == "ORDER_FILL"
+    }
+
+    pub fn is_daily_financing(&self) -> bool {
+        self.kind == "DAILY_FINANCING"
+    }
+
+    // Transaction IDs are increasing integers, which makes them comparable
+    pub fn id_number(&self) -> Option<u64> {
+        self.id.as_ref().and_then(|id| id.parse().ok())
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct OrderResponse {
+    #[serde(default)]
+    #[serde(rename = "orderCreateTransaction")]
+    pub order_create_transaction: Option<Transaction>,
+    #[serde(default)]
+    #[serde(rename = "orderFillTransaction")]
+    pub order_fill_transaction: Option<Transaction>,
+    #[serde(default)]
+    #[serde(rename = "orderCancelTransaction")]
+    pub order_cancel_transaction: Option<Transaction>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Order {
+    pub id: String,
+    pub state: String,
+    #[serde(default)]
+    #[serde(rename = "fillingTransactionID")]
+    pub filling_transaction_id: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct GetOrderResponse {
+    pub order: Order,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct GetTransactionResponse {
+    pub transaction: Transaction,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ClosePositionResponse {
+    #[serde(default)]
+    #[serde(rename = "longOrderFillTransaction")]
+    pub long_order_fill_transaction: Option<Transaction>,
+    #[serde(default)]
+    #[serde(rename = "shortOrderFillTransaction")]
+    pub short_order_fill_transaction: Option<Transaction>,
+}
@@ -0,0 +1,325 @@
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::time::Duration;
+
+use reqwest::header::{HeaderMap, HeaderValue};
+use serde::Serialize;
+
+use crate::broker::OrderTags;
+use crate::errors::Context;
+use crate::oanda::errors::{ApiStatusError, DuplicateClientOrderIdError};
+use crate::oanda::objects::API_URL;
+use crate::oanda::objects::{OandaSettings, OrderResponse, PositionFill, Transaction};
+use crate::oanda::trading_api::{submit_market_order, SubmitPolicy};
+use crate::oanda::usage;
+
+// A market order for one instrument serialized up to its units, so that placing one only
+// appends the units, position fill and client extensions rather than building the whole body
+#[derive(Debug, Clone)]
+pub struct OrderTemplate {
+    prefix: String,
+}
+
+impl OrderTemplate {
+    pub fn new(instrument: &str) -> Self {
+        OrderTemplate {
+            prefix: format!(
+                "{{\"order\":{{\"instrument\":{},\"timeInForce\":\"FOK\",\"type\":\"MARKET\",\"units\":\"",
+                serde_json::Value::from(instrument)
+            ),
+        }
+    }
+
+    // The body of the order to POST to the orders endpoint
+    pub fn body(
+        &self,
+        units: f64,
+        position_fill: PositionFill,
+        client_order_id: &str,
+        tags: &OrderTags,
+    ) -> String {
+        let comment = comment(tags);
+        let mut extensions = ClientExtensions {
+            id: Some(client_order_id),
+            tag: truncate(&tags.strategy, 128),
+            comment: truncate(&comment, 128),
+        };
+
+        let mut body = String::with_capacity(self.prefix.len() + 256);
+        body.push_str(&self.prefix);
+        let _ = write!(body, "{}", units);
+        body.push_str("\",\"positionFill\":\"");
+        body.push_str(position_fill.as_str());
+        body.push_str("\",\"clientExtensions\":");
+        // Serializing strings can't fail
+        body.push_str(&serde_json::to_string(&extensions).unwrap_or_default());
+        // The trade opened keeps the tags too, as that's what the OANDA UI lists
+        extensions.id = None;
+        if !extensions.tag.is_empty() || !extensions.comment.is_empty() {
+            body.push_str(",\"tradeClientExtensions\":");
+            body.push_str(&serde_json::to_string(&extensions).unwrap_or_default());
+        }
+        body.push_str("}}");
+        body
+    }
+}
+
+// The body of a limit order resting until `gtd_time` (RFC 3339), with the same client extensions
+// as a market order, its price cut to the `decimals` OANDA accepts for the instrument
+pub fn limit_order_body(
+    instrument: &str,
+    units: f64,
+    price: f64,
+    decimals: usize,
+    gtd_time: &str,
+    tags: &OrderTags,
+) -> String {
+    let comment = comment(tags);
+    let extensions = ClientExtensions {
+        id: None,
+        tag: truncate(&tags.strategy, 128),
+        comment: truncate(&comment, 128),
+    };
+    let mut order = serde_json::json!({
+        "type": "LIMIT",
+        "instrument": instrument,
+        "units": units.to_string(),
+        "price": format!("{:.*}", decimals, price),
+        "timeInForce": "GTD",
+        "gtdTime": gtd_time,
+        "positionFill": PositionFill::Default.as_str(),
+    });
+    if !extensions.tag.is_empty() || !extensions.comment.is_empty() {
+        // Serializing strings can't fail
+        let extensions = serde_json::to_value(&extensions).unwrap_or_default();
+        order["clientExtensions"] = extensions.clone();
+        order["tradeClientExtensions"] = extensions;
+    }
+    serde_json::json!({ "order": order }).to_string()
+}
+
+// OANDA's client extensions of an order or trade: the strategies as the tag and the signal and
+// config version as the comment, each cut to the 128 characters OANDA allows
+#[derive(Serialize)]
+struct ClientExtensions<'a> {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    id: Option<&'a str>,
+
+    #[serde(skip_serializing_if = "str::is_empty")]
+    tag: &'a str,
+
+    #[serde(skip_serializing_if = "str::is_empty")]
+    comment: &'a str,
+}
+
+fn comment(tags: &OrderTags) -> String {
+    let mut comment = String::new();
+    if !tags.signal.is_empty() {
+        let _ = write!(comment, "signal {}", tags.signal);
+    }
+    if !tags.config_version.is_empty() {
+        if !comment.is_empty() {
+            comment.push(' ');
+        }
+        let _ = write!(comment, "config {}", tags.config_version);
+    }
+    comment
+}
+
+// At most `chars` characters of a string
+fn truncate(s: &str, chars: usize) -> &str {
+    match s.char_indices().nth(chars) {
+        Some((index, _)) => &s[..index],
+        None => s,
+    }
+}
+
+// Places market orders over one long-lived HTTP client, so that an order reuses a connection to
+// OANDA that's already open rather than paying for DNS, TCP and TLS each time, and with the
+// headers and each instrument's order template prepared up front. keep_warm stops the connection
+// going idle between signals. Clones share the client and its connections.
+#[derive(Clone)]
+pub struct OrderClient {
+    settings: OandaSettings,
+    client: reqwest::Client,
+    endpoint: String,
+    url: String,
+    headers: HeaderMap,
+    templates: HashMap<String, OrderTemplate>,
+}
+
+impl OrderClient {
+    pub fn new(settings: &OandaSettings) -> Result<Self, Box<dyn std::error::Error>> {
+        let client = reqwest::Client::builder()
+            .tcp_nodelay(true)
+            .tcp_keepalive(Duration::from_secs(30))
+            .pool_idle_timeout(None)
+            .build()?;
+
+        let endpoint = format!("/v3/accounts/{}/orders", settings.account_id);
+        let url = format!("{}{}", API_URL, endpoint);
+
+        let mut authorization =
+            HeaderValue::from_str(&format!("Bearer {}", settings.authorization))?;
+        authorization.set_sensitive(true);
+        let mut headers = HeaderMap::new();
+        headers.insert("Authorization", authorization);
+        headers.insert("Content-Type", HeaderValue::from_static("application/json"));
+
+        Ok(OrderClient {
+            settings: settings.clone(),
+            client,
+            endpoint,
+            url,
+            headers,
+            templates: HashMap::new(),
+        })
+    }
+
+    // Prepare the order templates of the instruments that will be traded. Others are still
+    // accepted, their template is just built with each order.
+    pub fn with_templates(mut self, instruments: &[String]) -> Self {
+        for instrument in instruments {
+            self.templates
+                .insert(instrument.clone(), OrderTemplate::new(instrument));
+        }
+        self
+    }
+
+    pub fn settings(&self) -> &OandaSettings {
+        &self.settings
+    }
+
+    // The body of a market order, from the instrument's template if it has one
+    pub fn body(
+        &self,
+        instrument: &str,
+        units: f64,
+        position_fill: PositionFill,
+        client_order_id: &str,
+        tags: &OrderTags,
+    ) -> String {
+        match self.templates.get(instrument) {
+            Some(template) => template.body(units, position_fill, client_order_id, tags),
+            None => {
+                OrderTemplate::new(instrument).body(units, position_fill, client_order_id, tags)
+            }
+        }
+    }
+
+    // Open a connection to the orders endpoint, or keep the open one busy, with a request that
+    // changes nothing: listing the pending orders
+    pub async fn warm(&self) -> Result<(), Box<dyn std::error::Error>> {
+        let response = usage::track(
+            "orders",
+            self.client
+                .get(&self.url)
+                .headers(self.headers.clone())
+                .send()
+                .await,
+        )?;
+        if !response.status().is_success() {
+            return Err(format!(
+                "Received non-success status code {} from {}",
+                response.status(),
+                self.endpoint
+            )
+            .into());
+        }
+        // Read the body so that the connection goes back to the pool
+        response.bytes().await?;
+        Ok(())
+    }
+
+    // Warm the connection now and then every `interval` in the background, for as long as the
+    // runtime runs. Failures are only logged, an order will open a new connection if it must.
+    pub fn keep_warm(&self, interval: Duration) {
+        let client = self.clone();
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            loop {
+                ticker.tick().await;
+                if let Err(e) = client.warm().await {
+                    log::warn!(
+                        "Failed to warm the connection to OANDA's orders endpoint: {}",
+                        e
+                    );
+                }
+            }
+        });
+    }
+
+    // Place a market order, retrying as submit_market_order does
+    pub async fn market_order(
+        &self,
+        instrument: &str,
+        units: f64,
+        position_fill: PositionFill,
+        tags: &OrderTags,
+    ) -> Result<Option<Transaction>, Box<dyn std::error::Error>> {
+        submit_market_order(
+            self,
+            instrument,
+            units,
+            position_fill,
+            tags,
+            &SubmitPolicy::default(),
+        )
+        .await
+    }
+
+    // A single attempt at placing a market order with the given client order ID
+    pub(crate) async fn send(
+        &self,
+        instrument: &str,
+        units: f64,
+        position_fill: PositionFill,
+        client_order_id: &str,
+        tags: &OrderTags,
+        policy: &SubmitPolicy,
+    ) -> Result<Option<Transaction>, Box<dyn std::error::Error>> {
+        let body = self.body(instrument, units, position_fill, client_order_id, tags);
+        let response = usage::track(
+            "orders",
+            self.client
+                .post(&self.url)
+                .headers(self.headers.clone())
+                .body(body)
+                .timeout(policy.timeout)
+                .send()
+                .await,
+        )?;
+
+        let status = response.status();
+        if !status.is_success() {
+            // A retry of an order that got through is rejected for its client order ID
+            let body = response.text().await.unwrap_or_default();
+            if status == reqwest::StatusCode::BAD_REQUEST
+                && body.contains("CLIENT_ORDER_ID_ALREADY_EXISTS")
+            {
+                return Err(Box::new(DuplicateClientOrderIdError {
+                    client_order_id: client_order_id.to_string(),
+                }));
+            }
+            return Err(Box::new(ApiStatusError {
+                status,
+                endpoint: self.endpoint.clone(),
+            }));
+        }
+
+        let body = response.text().await?;
+        let order_response: OrderResponse = serde_json::from_str(&body)
+            .with_context(|| format!("Parsing the response from {}", self.endpoint))?;
+
+        if let Some(cancel) = order_response.order_cancel_transaction {
+            log::warn!(
+                "[{}] Order for {} units was cancelled: {}",
+                instrument,
+                units,
+                cancel.reason.unwrap_or_default()
+            );
+        }
+
+        Ok(order_response.order_fill_transaction)
+    }
+}
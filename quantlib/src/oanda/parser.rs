@@ -0,0 +1,162 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use serde::de::DeserializeOwned;
+
+use crate::oanda::errors::{ParseBufferOverflowError, StreamProtocolError};
+
+// No OANDA message comes anywhere near this, a partial line this long means the stream is broken
+pub const DEFAULT_MAX_BUFFER: usize = 1024 * 1024;
+
+// Malformed lines in a row after which the stream's format is taken to have changed, rather than
+// a line having been garbled now and then. Each is kept up to MAX_KEPT_LINE bytes to be looked at.
+pub const MAX_MALFORMED_RUN: usize = 20;
+const MAX_KEPT_LINE: usize = 4096;
+
+// Malformed lines skipped by every parser in the process, for the trader's connection health
+static MALFORMED_LINES: AtomicU64 = AtomicU64::new(0);
+
+pub fn malformed_lines() -> u64 {
+    MALFORMED_LINES.load(Ordering::Relaxed)
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ParseStats {
+    pub items: u64,
+    // Complete lines that were not valid JSON (or not the expected item) and were skipped
+    pub malformed: u64,
+    // Partial lines dropped for growing past the buffer limit
+    pub overflows: u64,
+}
+
+// Incremental parser for OANDA's streams, which send one JSON object per line but split them
+// over chunks anywhere, including in the middle of a line. The trailing partial line of a chunk
+// is kept until the rest of it arrives; a malformed line is counted and skipped, without losing
+// the lines around it.
+pub struct StreamParser<T> {
+    buffer: Vec<u8>,
+    max_buffer: usize,
+
+    // After an overflow, everything up to the next newline belongs to the dropped line
+    skipping: bool,
+    // Items parsed from a chunk that also overflowed, returned with the next chunk
+    ready: Vec<T>,
+    stats: ParseStats,
+
+    // The malformed lines since the last one that parsed
+    malformed_run: Vec<String>,
+}
+
+impl<T: DeserializeOwned> StreamParser<T> {
+    pub fn new() -> Self {
+        StreamParser {
+            buffer: Vec::new(),
+            max_buffer: DEFAULT_MAX_BUFFER,
+            skipping: false,
+            ready: Vec::new(),
+            stats: ParseStats::default(),
+            malformed_run: Vec::new(),
+        }
+    }
+
+    pub fn with_max_buffer(mut self, bytes: usize) -> Self {
+        self.max_buffer = bytes;
+        self
+    }
+
+    // The items completed by this chunk. Errors if the current line grows past the buffer limit,
+    // in which case the line is dropped and parsing resumes after its end, and with a
+    // StreamProtocolError once MAX_MALFORMED_RUN lines in a row haven't parsed. Either way the
+    // items parsed from the chunk come out with the next one.
+    pub fn parse(&mut self, chunk: &[u8]) -> Result<Vec<T>, Box<dyn std::error::Error>> {
+        let mut chunk = chunk;
+        if self.skipping {
+            match chunk.iter().position(|&byte| byte == b'\n') {
+                Some(end) => {
+                    self.skipping = false;
+                    chunk = &chunk[end + 1..];
+                }
+                None => return Ok(std::mem::take(&mut self.ready)),
+            }
+        }
+        self.buffer.extend_from_slice(chunk);
+
+        let mut items = std::mem::take(&mut self.ready);
+        let mut start = 0;
+        while let Some(end) = self.buffer[start..].iter().position(|&byte| byte == b'\n') {
+            let line = &self.buffer[start..start + end];
+            start += end + 1;
+            if line.iter().all(|byte| byte.is_ascii_whitespace()) {
+                continue;
+            }
+
+            match serde_json::from_slice::<T>(line) {
+                Ok(item) => {
+                    self.stats.items += 1;
+                    self.malformed_run.clear();
+                    items.push(item);
+                }
+                Err(err) => {
+                    self.stats.malformed += 1;
+                    MALFORMED_LINES.fetch_add(1, Ordering::Relaxed);
+                    log::warn!(
+                        "Skipping malformed stream line ({}): {}",
+                        err,
+                        String::from_utf8_lossy(line)
+                    );
+                    let kept = &line[..line.len().min(MAX_KEPT_LINE)];
+                    self.malformed_run
+                        .push(String::from_utf8_lossy(kept).into_owned());
+                }
+            }
+        }
+        // Keep the partial line for the next chunk
+        self.buffer.drain(..start);
+
+        if self.malformed_run.len() >= MAX_MALFORMED_RUN {
+            let lines = std::mem::take(&mut self.malformed_run);
+            self.ready = items;
+            return Err(Box::new(StreamProtocolError {
+                message: format!("{} lines in a row didn't parse", lines.len()),
+                payload: lines.join("\n"),
+            }));
+        }
+
+        if self.buffer.len() > self.max_buffer {
+            let dropped = self.buffer.len();
+            self.buffer.clear();
+            self.skipping = true;
+            self.stats.overflows += 1;
+            self.ready = items;
+            return Err(Box::new(ParseBufferOverflowError {
+                message: format!(
+                    "Dropped {} bytes without a newline (limit {} bytes)",
+                    dropped, self.max_buffer
+                ),
+            }));
+        }
+
+        Ok(items)
+    }
+
+    // Drops the partial line, e.g. when reconnecting since the rest of it will never arrive
+    // Items already parsed are kept
+    pub fn clear(&mut self) {
+        self.buffer.clear();
+        self.skipping = false;
+        self.malformed_run.clear();
+    }
+
+    pub fn buffered(&self) -> usize {
+        self.buffer.len()
+    }
+
+    pub fn stats(&self) -> ParseStats {
+        self.stats
+    }
+}
+
+impl<T: DeserializeOwned> Default for StreamParser<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
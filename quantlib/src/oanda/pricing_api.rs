@@ -0,0 +1,197 @@
+use reqwest::header::{HeaderMap, HeaderValue};
+
+use crate::calendar;
+use crate::errors::Context;
+use crate::oanda::objects::API_URL;
+use crate::oanda::objects::{
+    Candle, CandlesResponse, Instrument, InstrumentsResponse, OandaSettings, Price, PriceDepth,
+    PriceDepthResponse, Response,
+};
+use crate::oanda::usage;
+
+// Market data from OANDA's REST API, which needs no access to orders or positions
+
+pub async fn get_latest_prices(
+    instruments: &[String],
+    settings: &OandaSettings,
+) -> Result<Vec<Price>, Box<dyn std::error::Error>> {
+    let instrument_list = instruments.join(",");
+    let authorization = format!("Bearer {}", &settings.authorization);
+    let account_id = &settings.account_id;
+
+    let endpoint = format!(
+        "/v3/accounts/{}/pricing?instruments={}",
+        account_id, instrument_list
+    );
+    let url = format!("{}{}", API_URL, endpoint);
+
+    let mut headers = HeaderMap::new();
+    headers.insert(
+        "Authorization",
+        HeaderValue::from_str(authorization.as_str())?,
+    );
+    headers.insert("Content-Type", HeaderValue::from_static("application/json"));
+
+    let body = usage::track(
+        "pricing",
+        reqwest::Client::new()
+            .get(&url)
+            .headers(headers)
+            .send()
+            .await,
+    )?
+    .text()
+    .await?;
+
+    let response: Response = serde_json::from_str(&body)
+        .with_context(|| format!("Parsing the response from {}", endpoint))?;
+    let prices = response.prices;
+
+    Ok(prices)
+}
+
+// Current prices with the depth of the book behind them, for estimating what an order would cost
+pub async fn get_price_depth(
+    instruments: &[String],
+    settings: &OandaSettings,
+) -> Result<Vec<PriceDepth>, Box<dyn std::error::Error>> {
+    let authorization = format!("Bearer {}", &settings.authorization);
+    let account_id = &settings.account_id;
+
+    let endpoint = format!(
+        "/v3/accounts/{}/pricing?instruments={}",
+        account_id,
+        instruments.join(",")
+    );
+    let url = format!("{}{}", API_URL, endpoint);
+
+    let mut headers = HeaderMap::new();
+    headers.insert(
+        "Authorization",
+        HeaderValue::from_str(authorization.as_str())?,
+    );
+    headers.insert("Content-Type", HeaderValue::from_static("application/json"));
+
+    let response = usage::track(
+        "pricing",
+        reqwest::Client::new()
+            .get(&url)
+            .headers(headers)
+            .send()
+            .await,
+    )?;
+
+    if !response.status().is_success() {
+        return Err(format!(
+            "Received non-success status code {} from {}",
+            response.status(),
+            endpoint
+        )
+        .into());
+    }
+
+    let body = response.text().await?;
+    let prices: PriceDepthResponse = serde_json::from_str(&body)
+        .with_context(|| format!("Parsing the response from {}", endpoint))?;
+
+    Ok(prices.prices)
+}
+
+// Bid and ask candles of an instrument between two times (milliseconds since the epoch). OANDA
+// returns at most 5000 candles, and refuses a `to` in the future.
+pub async fn get_candles(
+    instrument: &str,
+    granularity: &str,
+    from: u64,
+    to: u64,
+    settings: &OandaSettings,
+) -> Result<Vec<Candle>, Box<dyn std::error::Error>> {
+    let authorization = format!("Bearer {}", &settings.authorization);
+
+    let endpoint = format!(
+        "/v3/instruments/{}/candles?price=BA&granularity={}&from={}&to={}",
+        instrument,
+        granularity,
+        calendar::utc(from).to_rfc3339_opts(chrono::SecondsFormat::Millis, true),
+        calendar::utc(to).to_rfc3339_opts(chrono::SecondsFormat::Millis, true)
+    );
+    let url = format!("{}{}", API_URL, endpoint);
+
+    let mut headers = HeaderMap::new();
+    headers.insert(
+        "Authorization",
+        HeaderValue::from_str(authorization.as_str())?,
+    );
+    headers.insert("Content-Type", HeaderValue::from_static("application/json"));
+
+    let response = usage::track(
+        "candles",
+        reqwest::Client::new()
+            .get(&url)
+            .headers(headers)
+            .send()
+            .await,
+    )?;
+
+    if !response.status().is_success() {
+        return Err(format!(
+            "Received non-success status code {} from {}",
+            response.status(),
+            endpoint
+        )
+        .into());
+    }
+
+    let body = response.text().await?;
+    let candles: CandlesResponse = serde_json::from_str(&body)
+        .with_context(|| format!("Parsing the response from {}", endpoint))?;
+
+    Ok(candles.candles)
+}
+
+// Unit precision and size limits of the given instruments
+pub async fn get_instruments(
+    instruments: &[String],
+    settings: &OandaSettings,
+) -> Result<Vec<Instrument>, Box<dyn std::error::Error>> {
+    let authorization = format!("Bearer {}", &settings.authorization);
+    let account_id = &settings.account_id;
+
+    let endpoint = format!(
+        "/v3/accounts/{}/instruments?instruments={}",
+        account_id,
+        instruments.join(",")
+    );
+    let url = format!("{}{}", API_URL, endpoint);
+
+    let mut headers = HeaderMap::new();
+    headers.insert(
+        "Authorization",
+        HeaderValue::from_str(authorization.as_str())?,
+    );
+    headers.insert("Content-Type", HeaderValue::from_static("application/json"));
+
+    let response = usage::track(
+        "instruments",
+        reqwest::Client::new()
+            .get(&url)
+            .headers(headers)
+            .send()
+            .await,
+    )?;
+
+    if !response.status().is_success() {
+        return Err(format!(
+            "Received non-success status code {} from {}",
+            response.status(),
+            endpoint
+        )
+        .into());
+    }
+
+    let body = response.text().await?;
+    let instruments: InstrumentsResponse = serde_json::from_str(&body)
+        .with_context(|| format!("Parsing the response from {}", endpoint))?;
+
+    Ok(instruments.instruments)
+}
@@ -0,0 +1,414 @@
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashSet};
+use std::error::Error;
+use std::time::Duration;
+
+use tokio::sync::mpsc;
+use tokio::task::{AbortHandle, JoinHandle};
+use tokio::time::timeout;
+
+use crate::errors;
+use crate::oanda::backfill::GapTracker;
+use crate::oanda::errors::{StreamRejectedError, StreamTimeoutError};
+use crate::oanda::objects::{OandaSettings, StreamItem};
+use crate::oanda::parser::StreamParser;
+use crate::oanda::stream_errors::{fatal_error, ReconnectPolicy};
+use crate::oanda::streaming_api::initialize_price_stream;
+use crate::oanda::subscription::Subscription;
+
+// OANDA limits how many instruments a single streaming connection can carry reliably,
+// and one connection for everything means a single failure stops all data.
+// ShardedPriceStream splits the instruments over several connections, each running in its own
+// task and reconnecting on its own, and merges their items back into a single stream.
+// Instruments should be given in priority order (see oanda::prioritize), as a shard that is
+// rejected or keeps dropping trims its lowest priority instruments.
+// The instruments can change while streaming, see subscriptions. Only the shards whose
+// instruments change reconnect, and each keeps its old connection streaming until the new one
+// is open, so the other instruments don't miss a price.
+// A shard stops on an error no reconnect will fix, a refused token or a changed format, and the
+// stream returns it rather than carrying on without the shard's instruments.
+pub struct ShardedPriceStream {
+    receiver: mpsc::Receiver<StreamItem>,
+    sender: mpsc::Sender<StreamItem>,
+    fatal: mpsc::UnboundedReceiver<Box<dyn Error + Send + Sync>>,
+    fatal_sender: mpsc::UnboundedSender<Box<dyn Error + Send + Sync>>,
+    shards: Vec<Shard>,
+    settings: OandaSettings,
+    instruments_per_shard: usize,
+    timeout_duration: u64,
+    next_shard: usize,
+
+    // Instruments whose items are returned, anything else is from a connection being replaced
+    subscribed: HashSet<String>,
+    updates: mpsc::UnboundedReceiver<Vec<String>>,
+    update_sender: mpsc::UnboundedSender<Vec<String>>,
+
+    // Tasks of replaced shards, which stop once their replacement connects
+    retiring: Vec<JoinHandle<()>>,
+
+    // Items that have arrived but not yet been returned, oldest first
+    pending: BinaryHeap<Reverse<(u64, u64, PendingItem)>>,
+    sequence: u64,
+}
+
+struct Shard {
+    id: usize,
+    instruments: Vec<String>,
+    task: JoinHandle<()>,
+}
+
+// Wrapper so StreamItems can sit in the heap, ordering is entirely by the (time, sequence) key
+struct PendingItem(StreamItem);
+
+impl PartialEq for PendingItem {
+    fn eq(&self, _: &Self) -> bool {
+        true
+    }
+}
+
+impl Eq for PendingItem {}
+
+impl PartialOrd for PendingItem {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for PendingItem {
+    fn cmp(&self, _: &Self) -> std::cmp::Ordering {
+        std::cmp::Ordering::Equal
+    }
+}
+
+impl ShardedPriceStream {
+    // Must be called from within a tokio runtime, as each shard is spawned as a task
+    pub fn new(
+        instruments: Vec<String>,
+        settings: &OandaSettings,
+        instruments_per_shard: usize,
+        timeout_duration: u64,
+    ) -> Self {
+        let (sender, receiver) = mpsc::channel(4096);
+        let (update_sender, updates) = mpsc::unbounded_channel();
+        let (fatal_sender, fatal) = mpsc::unbounded_channel();
+
+        let mut stream = ShardedPriceStream {
+            receiver,
+            sender,
+            fatal,
+            fatal_sender,
+            shards: Vec::new(),
+            settings: settings.clone(),
+            instruments_per_shard: instruments_per_shard.max(1),
+            timeout_duration,
+            next_shard: 0,
+            subscribed: instruments.iter().cloned().collect(),
+            updates,
+            update_sender,
+            retiring: Vec::new(),
+            pending: BinaryHeap::new(),
+            sequence: 0,
+        };
+        for instruments in instruments.chunks(stream.instruments_per_shard) {
+            stream.spawn_shard(instruments.to_vec(), None);
+        }
+
+        log::info!(
+            "Streaming {} instruments over {} connections",
+            instruments.len(),
+            stream.shards.len()
+        );
+        stream
+    }
+
+    pub fn shard_count(&self) -> usize {
+        self.shards.len()
+    }
+
+    // A sender of new instrument lists for the stream, in priority order, taking effect before
+    // the next item is returned. For changing the instruments once the stream has been handed
+    // to something else, e.g. the engine.
+    pub fn subscriptions(&self) -> mpsc::UnboundedSender<Vec<String>> {
+        self.update_sender.clone()
+    }
+
+    // Stream the given instruments from now on. Shards keep the instruments they still have,
+    // instruments that are new fill up shards that are reconnecting anyway before getting
+    // shards of their own, and shards left with nothing are closed.
+    pub fn update_instruments(&mut self, instruments: Vec<String>) {
+        let wanted: HashSet<String> = instruments.iter().cloned().collect();
+        if wanted == self.subscribed {
+            return;
+        }
+        let mut added: Vec<String> = instruments
+            .into_iter()
+            .filter(|instrument| !self.subscribed.contains(instrument))
+            .collect();
+        log::info!(
+            "Changing the streamed instruments, adding {} and removing {}",
+            added.join(","),
+            self.subscribed
+                .iter()
+                .filter(|instrument| !wanted.contains(*instrument))
+                .cloned()
+                .collect::<Vec<_>>()
+                .join(",")
+        );
+        self.subscribed = wanted;
+        self.retiring.retain(|task| !task.is_finished());
+
+        let mut changed = Vec::new();
+        for (index, shard) in self.shards.iter_mut().enumerate() {
+            let before = shard.instruments.len();
+            shard
+                .instruments
+                .retain(|instrument| self.subscribed.contains(instrument));
+            if shard.instruments.len() != before {
+                changed.push(index);
+            }
+        }
+        for &index in &changed {
+            let shard = &mut self.shards[index];
+            let room = self
+                .instruments_per_shard
+                .saturating_sub(shard.instruments.len());
+            shard
+                .instruments
+                .extend(added.drain(..room.min(added.len())));
+        }
+
+        // Replace from the back so the indices still to go stay valid
+        for index in changed.into_iter().rev() {
+            let shard = self.shards.remove(index);
+            if shard.instruments.is_empty() {
+                log::info!("[shard {}] No instruments left, closing", shard.id);
+                shard.task.abort();
+            } else {
+                self.spawn_shard(shard.instruments, Some(shard.task));
+            }
+        }
+        for instruments in added.chunks(self.instruments_per_shard) {
+            self.spawn_shard(instruments.to_vec(), None);
+        }
+    }
+
+    fn spawn_shard(&mut self, instruments: Vec<String>, replaces: Option<JoinHandle<()>>) {
+        let id = self.next_shard;
+        self.next_shard += 1;
+        let old = replaces.map(|task| {
+            let abort = task.abort_handle();
+            self.retiring.push(task);
+            abort
+        });
+        let task = tokio::spawn(run_shard(
+            id,
+            instruments.clone(),
+            self.settings.clone(),
+            self.timeout_duration,
+            self.sender.clone(),
+            self.fatal_sender.clone(),
+            old,
+        ));
+        self.shards.push(Shard {
+            id,
+            instruments,
+            task,
+        });
+    }
+
+    // Returns the next item across all shards.
+    // Items that have already arrived are returned in timestamp order, so a burst from one shard
+    // doesn't get ahead of older prices waiting from another.
+    pub async fn next_item(&mut self) -> Result<StreamItem, Box<dyn std::error::Error>> {
+        self.check_fatal()?;
+        while let Ok(instruments) = self.updates.try_recv() {
+            self.update_instruments(instruments);
+        }
+
+        while self.pending.is_empty() {
+            let item = timeout(
+                Duration::from_millis(self.timeout_duration),
+                self.receiver.recv(),
+            )
+            .await;
+
+            match item {
+                Ok(Some(item)) => self.push_pending(item),
+                Ok(None) => return Err("All stream shards have stopped".into()),
+                Err(_) => {
+                    self.check_fatal()?;
+                    return Err(Box::new(StreamTimeoutError {
+                        message: "No data received from any stream shard".to_string(),
+                    }));
+                }
+            }
+        }
+
+        // Pick up everything else that is already waiting before choosing the oldest
+        while let Ok(item) = self.receiver.try_recv() {
+            self.push_pending(item);
+        }
+
+        let Reverse((_, _, PendingItem(item))) = self.pending.pop().unwrap();
+        Ok(item)
+    }
+
+    fn check_fatal(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        match self.fatal.try_recv() {
+            Ok(err) => Err(err),
+            Err(_) => Ok(()),
+        }
+    }
+
+    fn push_pending(&mut self, item: StreamItem) {
+        // Heartbeats and the relay's dropped prices don't carry a parsed time, so they go out as
+        // soon as possible
+        let time = match &item {
+            StreamItem::Price(price) | StreamItem::Backfill(price) => {
+                if !self.subscribed.contains(&price.instrument) {
+                    return;
+                }
+                price.time
+            }
+            StreamItem::Dropped(_) | StreamItem::Heartbeat(_) => 0,
+        };
+        self.sequence += 1;
+        self.pending
+            .push(Reverse((time, self.sequence, PendingItem(item))));
+    }
+}
+
+impl Drop for ShardedPriceStream {
+    fn drop(&mut self) {
+        for shard in &self.shards {
+            shard.task.abort();
+        }
+        for task in &self.retiring {
+            task.abort();
+        }
+    }
+}
+
+impl Iterator for ShardedPriceStream {
+    type Item = Result<StreamItem, Box<dyn std::error::Error>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        Some(futures::executor::block_on(self.next_item()))
+    }
+}
+
+// Stream prices for a subset of instruments, reconnecting whenever the connection fails until an
+// error is fatal (see StreamErrorClass), which is passed on to the stream.
+// A shard replacing another stops it once its own connection is open.
+async fn run_shard(
+    id: usize,
+    instruments: Vec<String>,
+    settings: OandaSettings,
+    timeout_duration: u64,
+    sender: mpsc::Sender<StreamItem>,
+    fatal: mpsc::UnboundedSender<Box<dyn Error + Send + Sync>>,
+    mut replaces: Option<AbortHandle>,
+) {
+    let mut gaps = GapTracker::default();
+    let mut subscription = Subscription::new(instruments);
+    let mut policy = ReconnectPolicy::default();
+    loop {
+        let response = match initialize_price_stream(subscription.active(), &settings).await {
+            Ok(response) => Some(response),
+            Err(err) => {
+                log::error!("[shard {}] Failed to open stream: {}", id, err);
+                if let Some(err) = fatal_error(err.as_ref()) {
+                    let _ = fatal.send(err);
+                    return;
+                }
+                // Try again straight away with fewer instruments, if there were too many
+                if errors::find::<StreamRejectedError>(err.as_ref()).is_some_and(|rejection| {
+                    subscription.rejected(rejection, &format!("shard {} rejected by OANDA", id))
+                }) {
+                    continue;
+                }
+                None
+            }
+        };
+        let mut response = match response {
+            Some(response) => response,
+            None => {
+                tokio::time::sleep(policy.delay()).await;
+                continue;
+            }
+        };
+
+        subscription.connected();
+        policy.connected();
+        if let Some(old) = replaces.take() {
+            old.abort();
+        }
+        log::info!(
+            "[shard {}] Connected, streaming {}",
+            id,
+            subscription.active().join(",")
+        );
+
+        // Stand in for the prices missed while disconnected, nothing is missing the first time
+        let now = chrono::Utc::now().timestamp_millis() as u64;
+        for item in gaps.backfill(&settings, now).await {
+            if sender.send(item).await.is_err() {
+                return;
+            }
+        }
+
+        let mut parser = StreamParser::<StreamItem>::new();
+        loop {
+            let chunk = timeout(Duration::from_millis(timeout_duration), response.chunk()).await;
+
+            let chunk = match chunk {
+                Ok(Ok(Some(chunk))) => chunk,
+                Ok(Ok(None)) => {
+                    log::error!("[shard {}] Empty chunk received, reconnecting...", id);
+                    break;
+                }
+                Ok(Err(err)) => {
+                    log::error!("[shard {}] {}, reconnecting...", id, err);
+                    break;
+                }
+                Err(_) => {
+                    log::error!("[shard {}] Connection timed out, reconnecting...", id);
+                    break;
+                }
+            };
+
+            let items = match parser.parse(&chunk) {
+                Ok(items) => items,
+                Err(err) => {
+                    if let Some(err) = fatal_error(err.as_ref()) {
+                        log::error!("[shard {}] {}, stopping", id, err);
+                        let _ = fatal.send(err);
+                        return;
+                    }
+                    log::error!("[shard {}] {}, reconnecting...", id, err);
+                    break;
+                }
+            };
+            for item in items {
+                gaps.record(&item);
+                if sender.send(item).await.is_err() {
+                    // The stream has been dropped, nobody is listening anymore
+                    return;
+                }
+            }
+            if subscription.restore() {
+                log::info!("[shard {}] Reconnecting for the instruments trimmed", id);
+                break;
+            }
+        }
+
+        // Connections that keep dropping soon after opening get the shard trimmed
+        if subscription.disconnected() {
+            log::error!(
+                "[shard {}] Dropped {} instruments",
+                id,
+                subscription.dropped().len()
+            );
+        }
+    }
+}
@@ -0,0 +1,143 @@
+use std::error::Error;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::errors;
+use crate::notify::{notify, Notification, NotificationSink};
+use crate::oanda::errors::{
+    EmptyChunkError, ParseBufferOverflowError, StreamAuthError, StreamProtocolError,
+    StreamRejectedError, StreamTimeoutError,
+};
+use crate::util::generate_timestamp_filename;
+
+// Reconnects after transient errors wait this long, doubling with every failed attempt up to
+// MAX_RECONNECT_DELAY until one connects
+const RECONNECT_DELAY: Duration = Duration::from_secs(1);
+const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(60);
+
+// How an error from a price or transaction stream is dealt with, by the collector and the trader
+// alike
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StreamErrorClass {
+    // The connection dropped, stalled or garbled a line: reconnect, backing off while it fails
+    Transient,
+
+    // OANDA refused the token or account: stop and alert, as reconnecting won't change that
+    Auth,
+
+    // The stream's format changed: stop, alert and keep what didn't parse to be looked at
+    Protocol,
+}
+
+impl StreamErrorClass {
+    // Anything not known to be fatal is taken to be transient, a stream that won't come back
+    // still shows up in the connection log and the health checks
+    pub fn of(error: &(dyn Error + 'static)) -> Self {
+        if errors::find::<StreamAuthError>(error).is_some() {
+            StreamErrorClass::Auth
+        } else if errors::find::<StreamProtocolError>(error).is_some() {
+            StreamErrorClass::Protocol
+        } else {
+            StreamErrorClass::Transient
+        }
+    }
+
+    pub fn is_fatal(&self) -> bool {
+        *self != StreamErrorClass::Transient
+    }
+}
+
+// What a transient error was, for the connection log's reconnects
+pub fn reconnect_reason(error: &(dyn Error + 'static)) -> &'static str {
+    if errors::find::<tokio::time::error::Elapsed>(error).is_some()
+        || errors::find::<StreamTimeoutError>(error).is_some()
+    {
+        "timeout"
+    } else if errors::find::<EmptyChunkError>(error).is_some() {
+        "empty chunk"
+    } else if errors::find::<ParseBufferOverflowError>(error).is_some() {
+        "parse overflow"
+    } else if errors::find::<StreamRejectedError>(error).is_some() {
+        "rejected"
+    } else if errors::find::<reqwest::Error>(error).is_some()
+        || errors::find::<std::io::Error>(error).is_some()
+    {
+        "network error"
+    } else {
+        "error"
+    }
+}
+
+// A fatal error that can be sent between tasks, e.g. from a stream shard to the stream, None
+// for a transient one
+pub fn fatal_error(error: &(dyn Error + 'static)) -> Option<Box<dyn Error + Send + Sync>> {
+    if let Some(auth) = errors::find::<StreamAuthError>(error) {
+        return Some(Box::new(auth.clone()));
+    }
+    errors::find::<StreamProtocolError>(error)
+        .map(|protocol| Box::new(protocol.clone()) as Box<dyn Error + Send + Sync>)
+}
+
+// Delays between attempts to reconnect after transient errors
+#[derive(Debug, Default)]
+pub struct ReconnectPolicy {
+    failures: u32,
+}
+
+impl ReconnectPolicy {
+    // How long to wait before the next attempt, counting it as failed
+    pub fn delay(&mut self) -> Duration {
+        let delay = RECONNECT_DELAY.saturating_mul(1 << self.failures.min(16));
+        self.failures += 1;
+        delay.min(MAX_RECONNECT_DELAY)
+    }
+
+    pub fn connected(&mut self) {
+        self.failures = 0;
+    }
+}
+
+// Deal with a fatal error before stopping: log it, alert the sinks and for a protocol error
+// write what didn't parse to `directory` as stream-payload-<time>.txt, returning where
+pub fn report_fatal(
+    error: &(dyn Error + 'static),
+    source: &str,
+    sinks: &[NotificationSink],
+    directory: &Path,
+) -> Option<PathBuf> {
+    let mut text = match StreamErrorClass::of(error) {
+        StreamErrorClass::Auth => format!("Stream refused, check the OANDA token: {}", error),
+        StreamErrorClass::Protocol => format!("Stream format changed: {}", error),
+        StreamErrorClass::Transient => return None,
+    };
+    let dumped = errors::find::<StreamProtocolError>(error).and_then(|protocol| {
+        let path = directory.join(format!(
+            "stream-payload-{}.txt",
+            generate_timestamp_filename()
+        ));
+        match std::fs::write(&path, &protocol.payload) {
+            Ok(()) => Some(path),
+            Err(e) => {
+                log::error!("Failed to write {}: {}", path.display(), e);
+                None
+            }
+        }
+    });
+    if let Some(path) = &dumped {
+        text.push_str(&format!(
+            ", lines that didn't parse are in {}",
+            path.display()
+        ));
+    }
+    log::error!("{}, stopping", text);
+    notify(
+        sinks,
+        &Notification {
+            time: chrono::Utc::now().to_rfc3339(),
+            source: source.to_string(),
+            instrument: None,
+            text,
+        },
+    );
+    dumped
+}
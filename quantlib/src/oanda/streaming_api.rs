@@ -1,14 +1,22 @@
-use serde_json::Deserializer;
+use futures::FutureExt;
 use reqwest::header::{HeaderMap, HeaderValue};
 use std::io::Write;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use tokio::time::timeout;
 
-use crate::oanda::errors::EmptyChunkError;
-use crate::oanda::objects::{STREAMING_URL, OandaSettings, Price, StreamItem};
+use crate::data::{self, encode_heartbeat, encode_price, StorageLayout, TimePrecision, TICK};
+use crate::oanda::backfill::GapTracker;
+use crate::oanda::connection_quality::ConnectionQualityLog;
+use crate::oanda::errors::{EmptyChunkError, StreamAuthError, StreamRejectedError, StreamTimeoutError};
+use crate::oanda::objects::{STREAMING_URL, Heartbeat, OandaSettings, Price, StreamItem, Transaction};
+use crate::oanda::parser::StreamParser;
+use crate::oanda::stream_errors::StreamErrorClass;
+use crate::oanda::subscription::Subscription;
+use crate::oanda::usage;
 
 
 // Raw functions for interacting with OANDA's streaming API
-async fn initialize_price_stream(instruments: &Vec<String>, settings: &OandaSettings) -> Result<reqwest::Response, Box<dyn std::error::Error>> {
+pub(crate) async fn initialize_price_stream(instruments: &[String], settings: &OandaSettings) -> Result<reqwest::Response, Box<dyn std::error::Error>> {
     let instrument_list = instruments.join(",");
     let authorization = format!("Bearer {}", &settings.authorization);
     let account_id = &settings.account_id;
@@ -25,66 +33,181 @@ async fn initialize_price_stream(instruments: &Vec<String>, settings: &OandaSett
         HeaderValue::from_str(authorization.as_str())?,
     );
 
-    let response = reqwest::Client::new()
-        .get(&url)
-        .headers(headers)
-        .send()
-        .await?;
+    let response = usage::track(
+        "pricing/stream",
+        reqwest::Client::new()
+            .get(&url)
+            .headers(headers)
+            .send()
+            .await,
+    )?;
+
+    // Too many instruments, or too long a list of them for a URL
+    let status = response.status();
+    if status == reqwest::StatusCode::BAD_REQUEST || status == reqwest::StatusCode::URI_TOO_LONG {
+        let message = response.text().await.unwrap_or_default();
+        return Err(Box::new(StreamRejectedError { status: status.as_u16(), message }));
+    }
+    let response = check_auth(status, response).await?;
+    if !status.is_success() {
+        return Err(format!("Received non-success status code {} from {}", status, endpoint).into());
+    }
+
+    Ok(response)
+}
+
+async fn initialize_transaction_stream(settings: &OandaSettings) -> Result<reqwest::Response, Box<dyn std::error::Error>> {
+    let authorization = format!("Bearer {}", &settings.authorization);
+    let account_id = &settings.account_id;
 
-    if !response.status().is_success() {
-        return Err(format!("Received non-success status code: {}", response.status()).into());
+    let endpoint = format!("/v3/accounts/{}/transactions/stream", account_id);
+    let url = format!("{}{}", STREAMING_URL, endpoint);
+
+    let mut headers = HeaderMap::new();
+    headers.insert(
+        "Authorization",
+        HeaderValue::from_str(authorization.as_str())?,
+    );
+
+    let response = usage::track(
+        "transactions/stream",
+        reqwest::Client::new()
+            .get(&url)
+            .headers(headers)
+            .send()
+            .await,
+    )?;
+
+    let status = response.status();
+    let response = check_auth(status, response).await?;
+    if !status.is_success() {
+        return Err(format!("Received non-success status code {} from {}", status, endpoint).into());
     }
 
     Ok(response)
 }
 
-async fn parse_chunk(buffer: &mut Vec<u8>, chunk: &[u8]) -> Vec<StreamItem> {
-    // Move chunk into buffer
-    buffer.extend_from_slice(&chunk);
-
-    // Parse buffer into stream of StreamItems (Price or Heartbeat) using serde_json
-    let stream = Deserializer::from_slice(&buffer);
-    let mut stream = stream.into_iter::<StreamItem>();
-
-    let mut items = Vec::new();
-    let mut last_parsed_index = 0;
-    log::trace!("Streaming JSON from deserializer...");
-    while let Some(result) = stream.next() {
-        match result {
-            Ok(item) => {
-                items.push(item);
-                last_parsed_index = stream.byte_offset();
-            }
-            Err(err) => {
-                log::debug!("Error parsing JSON: {:?}", err);
-                log::debug!("This is likely caused by a chunk boundary, the next chunk will be parsed correctly.");
+// A bad token or account won't come good by reconnecting, so it's told apart from other failures
+async fn check_auth(status: reqwest::StatusCode, response: reqwest::Response) -> Result<reqwest::Response, Box<dyn std::error::Error>> {
+    if status == reqwest::StatusCode::UNAUTHORIZED || status == reqwest::StatusCode::FORBIDDEN {
+        let message = response.text().await.unwrap_or_default();
+        return Err(Box::new(StreamAuthError { status: status.as_u16(), message }));
+    }
+    Ok(response)
+}
+
+
+// Where a price stream's raw bytes come from: OANDA directly, or a local relay that shares
+// a single OANDA connection between several processes (see oanda::multiplexer)
+pub enum ChunkSource {
+    Oanda(reqwest::Response),
+    Relay(tokio::net::TcpStream),
+}
+
+impl ChunkSource {
+    pub async fn connect(
+        instruments: &[String],
+        settings: &OandaSettings,
+        relay_address: Option<&str>,
+    ) -> Result<ChunkSource, Box<dyn std::error::Error>> {
+        match relay_address {
+            Some(address) => {
+                // The relay expects the instruments we want as the first line
+                let mut stream = tokio::net::TcpStream::connect(address).await?;
+                stream
+                    .write_all(format!("{}\n", instruments.join(",")).as_bytes())
+                    .await?;
+                Ok(ChunkSource::Relay(stream))
             }
+            None => Ok(ChunkSource::Oanda(
+                initialize_price_stream(instruments, settings).await?,
+            )),
         }
     }
 
-    // Remove parsed JSON strings from buffer
-    *buffer = buffer[last_parsed_index..].to_vec();
+    // Connect for the subscription's instruments, trimming it for as long as OANDA rejects them
+    pub async fn subscribe(
+        subscription: &mut Subscription,
+        settings: &OandaSettings,
+        relay_address: Option<&str>,
+    ) -> Result<ChunkSource, Box<dyn std::error::Error>> {
+        loop {
+            match Self::connect(subscription.active(), settings, relay_address).await {
+                Ok(source) => {
+                    subscription.connected();
+                    return Ok(source);
+                }
+                Err(err) => {
+                    let trimmed = crate::errors::find::<StreamRejectedError>(err.as_ref())
+                        .is_some_and(|rejection| {
+                            subscription.rejected(rejection, "rejected by OANDA")
+                        });
+                    if !trimmed {
+                        return Err(err);
+                    }
+                }
+            }
+        }
+    }
 
-    items
+    // Next chunk of raw bytes, or None if the connection was closed
+    pub async fn chunk(&mut self) -> Result<Option<bytes::Bytes>, Box<dyn std::error::Error>> {
+        match self {
+            ChunkSource::Oanda(response) => Ok(response.chunk().await?),
+            ChunkSource::Relay(stream) => {
+                let mut buffer = vec![0; 8 * 1024];
+                let read = stream.read(&mut buffer).await?;
+                if read == 0 {
+                    return Ok(None);
+                }
+                buffer.truncate(read);
+                Ok(Some(bytes::Bytes::from(buffer)))
+            }
+        }
+    }
 }
 
-
 pub trait PriceStream<'a>: Iterator<Item = Result<StreamItem, Box<dyn std::error::Error>>> {
     fn new(instruments: Vec<String>, settings: &'a OandaSettings, timeout_duration: u64) -> Self;
     fn refresh_connection(&mut self) -> Result<(), Box<dyn std::error::Error>>;
 }
 
-pub struct FastPriceStream<'a> {
-    pub response: reqwest::Response,
-    pub buffer: Vec<u8>,
+// Owns its settings so it can be handed to a thread of its own, see engine::PriceSource
+pub struct FastPriceStream {
+    pub source: ChunkSource,
+    pub parser: StreamParser<StreamItem>,
     pub item_buffer: std::collections::VecDeque<StreamItem>,
+    pub gaps: GapTracker,
 
-    pub settings: &'a OandaSettings,
+    pub settings: OandaSettings,
     pub instruments: Vec<String>,
-    pub timeout_duration: u64
+    pub timeout_duration: u64,
+    pub relay_address: Option<String>,
 }
 
-impl<'a> FastPriceStream<'a> {
+impl FastPriceStream {
+    // Stream prices from a local relay rather than opening another connection to OANDA
+    pub async fn with_relay(
+        instruments: Vec<String>,
+        settings: &OandaSettings,
+        relay_address: &str,
+        timeout_duration: u64,
+    ) -> Result<FastPriceStream, Box<dyn std::error::Error>> {
+        let source = ChunkSource::connect(&instruments, settings, Some(relay_address)).await?;
+
+        Ok(FastPriceStream {
+            source,
+            parser: StreamParser::new(),
+            item_buffer: std::collections::VecDeque::new(),
+            gaps: GapTracker::default(),
+
+            settings: settings.clone(),
+            instruments,
+            timeout_duration,
+            relay_address: Some(relay_address.to_string()),
+        })
+    }
+
     pub async fn next_items(
         &mut self,
         timeout_duration: u64,
@@ -93,13 +216,13 @@ impl<'a> FastPriceStream<'a> {
         log::trace!("Getting next chunk from OANDA...");
         let chunk = timeout(
             std::time::Duration::from_millis(timeout_duration),
-            self.response.chunk(),
+            self.source.chunk(),
         )
         .await??; // ?? because reqwest may return an error, and timeout may return an error
 
         if let Some(chunk) = chunk {
             log::trace!("Parsing chunk...");
-            let items = parse_chunk(&mut self.buffer, &chunk).await;
+            let items = self.parser.parse(&chunk)?;
             return Ok(items);
         } else {
             return Err(Box::new(EmptyChunkError {
@@ -109,32 +232,44 @@ impl<'a> FastPriceStream<'a> {
     }
 }
 
-impl<'a> PriceStream<'a> for FastPriceStream<'a> {
+impl<'a> PriceStream<'a> for FastPriceStream {
     fn new(instruments: Vec<String>, settings: &'a OandaSettings, timeout_duration: u64) -> Self {
         // Open connection to OANDA
         let response = futures::executor::block_on(initialize_price_stream(&instruments, &settings)).unwrap();
-        let buffer = Vec::new();
         let item_buffer = std::collections::VecDeque::new();
 
         FastPriceStream {
-            response,
-            buffer,
+            source: ChunkSource::Oanda(response),
+            parser: StreamParser::new(),
             item_buffer,
+            gaps: GapTracker::default(),
 
-            settings,
+            settings: settings.clone(),
             instruments,
-            timeout_duration
+            timeout_duration,
+            relay_address: None,
         }
     }
 
     fn refresh_connection(&mut self) -> Result<(), Box<dyn std::error::Error>> {
         // Refresh connection by closing the current one and opening a new one
-        self.response = futures::executor::block_on(initialize_price_stream(&self.instruments, &self.settings)).unwrap();
+        // Any partial item left in the buffer belongs to the old connection
+        self.source = futures::executor::block_on(ChunkSource::connect(
+            &self.instruments,
+            &self.settings,
+            self.relay_address.as_deref(),
+        ))?;
+        self.parser.clear();
+
+        // Stand in for the prices missed while disconnected, ahead of the newly streamed ones
+        let now = chrono::Utc::now().timestamp_millis() as u64;
+        let backfill = futures::executor::block_on(self.gaps.backfill(&self.settings, now));
+        self.item_buffer.extend(backfill);
         Ok(())
     }
 }
 
-impl<'a> Iterator for FastPriceStream<'a> {
+impl Iterator for FastPriceStream {
     type Item = Result<StreamItem, Box<dyn std::error::Error>>;
 
     fn next(&mut self) -> Option<Self::Item> {
@@ -150,10 +285,23 @@ impl<'a> Iterator for FastPriceStream<'a> {
             Ok(items) => {
                 for item in items {
                     // Add all items to buffer to be returned by next() calls
+                    self.gaps.record(&item);
                     self.item_buffer.push_back(item);
                 }
             }
             Err(err) => {
+                // Timeouts, empty chunks and dropped connections all mean the connection is dead,
+                // but a changed format would only fail the same way again on a new one
+                if StreamErrorClass::of(err.as_ref()).is_fatal() {
+                    return Some(Err(err));
+                }
+                log::error!("{}, reconnecting...", err);
+                if let Err(refresh_err) = self.refresh_connection() {
+                    log::error!("Failed to reconnect: {}", refresh_err);
+                    if StreamErrorClass::of(refresh_err.as_ref()).is_fatal() {
+                        return Some(Err(refresh_err));
+                    }
+                }
                 return Some(Err(err));
             }
         }
@@ -168,23 +316,103 @@ impl<'a> Iterator for FastPriceStream<'a> {
 }
 
 
+// Stream of transactions on the account (order fills, manual closes, financing, ...)
+// Unlike the price streams this is polled, so that checking it never delays handling prices
+pub struct TransactionStream<'a> {
+    pub response: reqwest::Response,
+    pub parser: StreamParser<Transaction>,
+
+    pub settings: &'a OandaSettings,
+    pub timeout_duration: u64,
+    last_received: std::time::Instant,
+}
+
+impl<'a> TransactionStream<'a> {
+    pub async fn new(
+        settings: &'a OandaSettings,
+        timeout_duration: u64,
+    ) -> Result<TransactionStream<'a>, Box<dyn std::error::Error>> {
+        let response = initialize_transaction_stream(settings).await?;
+
+        Ok(TransactionStream {
+            response,
+            parser: StreamParser::new(),
+            settings,
+            timeout_duration,
+            last_received: std::time::Instant::now(),
+        })
+    }
+
+    pub async fn refresh_connection(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        self.response = initialize_transaction_stream(self.settings).await?;
+        self.parser.clear();
+        self.last_received = std::time::Instant::now();
+        Ok(())
+    }
+
+    // Returns the transactions that have already arrived, without waiting for more
+    // Heartbeats are consumed here, they only tell us that the connection is still alive
+    pub async fn poll_transactions(&mut self) -> Result<Vec<Transaction>, Box<dyn std::error::Error>> {
+        let mut transactions = Vec::new();
+
+        while let Some(chunk) = self.response.chunk().now_or_never() {
+            let chunk = match chunk? {
+                Some(chunk) => chunk,
+                None => {
+                    return Err(Box::new(EmptyChunkError {
+                        message: "Received empty chunk from OANDA transaction stream".to_string(),
+                    }));
+                }
+            };
+
+            self.last_received = std::time::Instant::now();
+            let items = self.parser.parse(&chunk)?;
+            transactions.extend(items.into_iter().filter(|t| !t.is_heartbeat()));
+        }
+
+        // Heartbeats arrive every 5 seconds, so a long silence means the connection is dead
+        if self.last_received.elapsed() > std::time::Duration::from_millis(self.timeout_duration) {
+            return Err(Box::new(StreamTimeoutError {
+                message: "No data received from OANDA transaction stream".to_string(),
+            }));
+        }
+
+        Ok(transactions)
+    }
+}
+
+
 // TODO: Refactor such that LoggingPriceStream implements PriceStream
 // TODO: Create MockPriceStream for testing
 pub struct LoggingPriceStream<'a> {
     // Used for streaming data from OANDA
-    pub response: reqwest::Response,
-    pub buffer: Vec<u8>,
+    pub source: ChunkSource,
+    pub parser: StreamParser<StreamItem>,
     pub buffered_items: std::collections::VecDeque<StreamItem>,
+    pub gaps: GapTracker,
 
     // Config options
-    pub log_path: String,
+    pub layout: StorageLayout,
     pub timeout_duration: u64,
     pub settings: &'a OandaSettings,
     pub instruments: Vec<String>,
-
-    // File writers
-    pub raw_log_writer: std::io::BufWriter<std::fs::File>,
-    pub bin_log_writers: std::collections::HashMap<String, std::io::BufWriter<std::fs::File>>,
+    pub relay_address: Option<String>,
+
+    // The instruments actually streamed, which may have been trimmed from the end of instruments
+    pub subscription: Subscription,
+
+    // File writers, along with the path they write to so they can be rotated when it changes
+    pub raw_log_writer: (std::path::PathBuf, std::io::BufWriter<std::fs::File>),
+    pub bin_log_writers: std::collections::HashMap<
+        String,
+        (
+            std::path::PathBuf,
+            std::io::BufWriter<std::fs::File>,
+            TimePrecision,
+        ),
+    >,
+    pub heartbeat_writer: (std::path::PathBuf, std::io::BufWriter<std::fs::File>),
+    pub connection_log: ConnectionQualityLog,
 }
 
 impl<'a> LoggingPriceStream<'a> {
@@ -195,122 +423,204 @@ impl<'a> LoggingPriceStream<'a> {
         timeout_duration: u64,
         settings: &'a OandaSettings,
     ) -> Result<LoggingPriceStream<'a>, Box<dyn std::error::Error>> {
-        // Open connection to OANDA
-        let response = initialize_price_stream(&instruments, &settings).await?;
-        let buffer = Vec::new();
+        Self::with_relay(instruments, log_path, timeout_duration, settings, None).await
+    }
+
+    // Log prices received from a local relay (or directly from OANDA if relay_address is None)
+    pub async fn with_relay(
+        instruments: Vec<String>,
+        log_path: &str,
+        timeout_duration: u64,
+        settings: &'a OandaSettings,
+        relay_address: Option<&str>,
+    ) -> Result<LoggingPriceStream<'a>, Box<dyn std::error::Error>> {
+        let layout = StorageLayout::new(log_path);
+        Self::with_layout(instruments, layout, timeout_duration, settings, relay_address).await
+    }
+
+    // Log prices to the files described by layout
+    pub async fn with_layout(
+        instruments: Vec<String>,
+        layout: StorageLayout,
+        timeout_duration: u64,
+        settings: &'a OandaSettings,
+        relay_address: Option<&str>,
+    ) -> Result<LoggingPriceStream<'a>, Box<dyn std::error::Error>> {
+        if layout.read_only {
+            return Err(format!("Can't log prices to read only {}", layout.root.display()).into());
+        }
+
+        // Open connection to OANDA, dropping the lowest priority instruments if they're rejected
+        let mut subscription = Subscription::new(instruments.clone());
+        let source = ChunkSource::subscribe(&mut subscription, settings, relay_address).await?;
+        let parser = StreamParser::new();
         let buffered_items = std::collections::VecDeque::new();
 
         // Create buffered writers for raw data
-        let raw_log_path = format!("{}/raw.log", log_path);
-        let mut options = std::fs::OpenOptions::new();
-        let raw_log_file = options.append(true).create(true).open(raw_log_path)?;
-        let raw_log_writer = std::io::BufWriter::new(raw_log_file);
+        let now = chrono::Utc::now().timestamp_millis() as u64;
+        let raw_log_path = layout.raw_log_path(now);
+        log::info!("Saving raw data to {}...", raw_log_path.display());
+        let raw_log_writer = (raw_log_path.clone(), open_log_file(&raw_log_path, 8 * 1024)?);
+
+        // Record heartbeats, chunk sizes, parse errors and reconnects so gaps in the data can be explained
+        let connection_log_path = layout.connection_log_path(now);
+        create_parent_directory(&connection_log_path)?;
+        let connection_log = ConnectionQualityLog::open(connection_log_path)?;
+
+        // Heartbeats go to a sidecar file of their own, as the tick files hold nothing but prices
+        let heartbeats_path = layout.heartbeats_path(now);
+        let heartbeat_writer = (heartbeats_path.clone(), open_log_file(&heartbeats_path, 1024)?);
 
         // Create hashmap to store buffered writers for binary data, but don't open files yet
         // Binary files will be opened when the first price for each instrument is received
-        let bin_log_writers: std::collections::HashMap<String, std::io::BufWriter<std::fs::File>> =
-            std::collections::HashMap::new();
+        let bin_log_writers = std::collections::HashMap::new();
 
         Ok(LoggingPriceStream {
-            response,
-            buffer,
+            source,
+            parser,
             buffered_items,
+            gaps: GapTracker::default(),
 
             timeout_duration,
             settings,
             instruments,
-            log_path: log_path.to_string(),
+            relay_address: relay_address.map(|address| address.to_string()),
+            subscription,
+            layout,
 
             raw_log_writer,
             bin_log_writers,
+            heartbeat_writer,
+            connection_log,
         })
     }
 
     pub async fn refresh_connection(&mut self) -> Result<(), Box<dyn std::error::Error>> {
         // Refresh connection by closing the current one and opening a new one
-        self.response = initialize_price_stream(&self.instruments, &self.settings).await?;
+        // Any partial item left in the buffer belongs to the old connection
+        // Connections that keep dropping soon after opening get the subscription trimmed
+        self.subscription.disconnected();
+        self.source = ChunkSource::subscribe(
+            &mut self.subscription,
+            self.settings,
+            self.relay_address.as_deref(),
+        )
+        .await?;
+        self.parser.clear();
+
+        // Stand in for the prices missed while disconnected, ahead of the newly streamed ones
+        let now = chrono::Utc::now().timestamp_millis() as u64;
+        let backfill = self.gaps.backfill(self.settings, now).await;
+        self.buffered_items.extend(backfill);
         Ok(())
     }
 
     pub fn flush(&mut self) -> Result<(), Box<dyn std::error::Error>> {
         // Buffered writers need to be flushed before closing to avoid losing data
         // Buffer size is relatively large (8KB)
-        self.raw_log_writer.flush()?;
-        for (_, writer) in self.bin_log_writers.iter_mut() {
+        self.raw_log_writer.1.flush()?;
+        for (_, (_, writer, _)) in self.bin_log_writers.iter_mut() {
             writer.flush()?;
         }
+        self.heartbeat_writer.1.flush()?;
+        self.connection_log.flush()?;
         Ok(())
     }
 
     pub async fn log_price(&mut self, price: &Price) {
-        let timestamp: u64 = price.time;
-
-        // Attempt to get buffered writer for instrument from hashmap, otherwise create a new one
-        let bin_log_writer = self
-            .bin_log_writers
-            .entry(price.instrument.clone())
-            .or_insert_with(|| {
-                // Create binary log file for instrument with append and create flags
-                let path = format!("{}/bin/{}.bin", self.log_path, price.instrument);
-                let mut options = std::fs::OpenOptions::new();
-                let file = options
-                    .append(true)
-                    .create(true)
-                    .open(path)
-                    .unwrap_or_else(|err| {
-                        panic!("Failed to open binary log file: {}", err);
-                    });
-
-                // Optimal buffer size is likely 8KB as 4KB is the default page size on most systems
-                // 8KB = 250 32 byte records, unlikely to be less than 1 second of data
-                let writer = std::io::BufWriter::with_capacity(8 * 1024, file);
-                writer
+        // Files are opened when the first price for each instrument is received,
+        // and reopened whenever the templated path changes (e.g. at the start of a new day)
+        let path = self.layout.bin_path(&price.instrument, TICK, price.time);
+        let outdated = match self.bin_log_writers.get(&price.instrument) {
+            Some((current, _, _)) => *current != path,
+            None => true,
+        };
+
+        if outdated {
+            // Optimal buffer size is likely 8KB as 4KB is the default page size on most systems
+            // 8KB = 500 16 byte records, unlikely to be less than 1 second of data
+            let mut writer = open_log_file(&path, 8 * 1024).unwrap_or_else(|err| {
+                panic!("Failed to open binary log file: {}", err);
             });
 
-        // TODO: Standardize Price to binary format conversion in quantlib
-        if let Err(err) = bin_log_writer.write_all(&timestamp.to_be_bytes()) {
-            panic!("Failed to write timestamp to binary log file: {}", err);
+            // Appending to an existing file keeps its precision, a new one gets the configured one
+            let precision = match data::file_precision(&path) {
+                Ok(Some(precision)) => precision,
+                Ok(None) => {
+                    let precision = self.layout.time_precision;
+                    if let Some(header) = precision.header() {
+                        if let Err(err) = writer.write_all(&header) {
+                            panic!("Failed to write binary log file header: {}", err);
+                        }
+                    }
+                    precision
+                }
+                Err(err) => panic!("Failed to read binary log file header: {}", err),
+            };
+            if let Some((_, mut previous, _)) = self
+                .bin_log_writers
+                .insert(price.instrument.clone(), (path, writer, precision))
+            {
+                if let Err(err) = previous.flush() {
+                    log::error!("Failed to flush binary log file: {}", err);
+                }
+            }
+        }
+
+        let (_, bin_log_writer, precision) =
+            self.bin_log_writers.get_mut(&price.instrument).unwrap();
+        if let Err(err) = bin_log_writer.write_all(&encode_price(price, *precision)) {
+            panic!("Failed to write price to binary log file: {}", err);
         }
-        if let Err(err) = bin_log_writer.write_all(&price.bid.to_be_bytes()) {
-            panic!("Failed to write bid price to binary log file: {}", err);
+    }
+
+    pub fn log_heartbeat(&mut self, heartbeat: &Heartbeat) {
+        // Falls back on the time it was received if OANDA's time can't be read
+        let now = chrono::Utc::now().timestamp_millis() as u64;
+        let time = heartbeat.millis().unwrap_or(now);
+        let path = self.layout.heartbeats_path(time);
+        if path != self.heartbeat_writer.0 {
+            let writer = open_log_file(&path, 1024).unwrap_or_else(|err| {
+                panic!("Failed to open heartbeat file: {}", err);
+            });
+            let (_, mut previous) = std::mem::replace(&mut self.heartbeat_writer, (path, writer));
+            if let Err(err) = previous.flush() {
+                log::error!("Failed to flush heartbeat file: {}", err);
+            }
         }
-        if let Err(err) = bin_log_writer.write_all(&price.ask.to_be_bytes()) {
-            panic!("Failed to write ask price to binary log file: {}", err);
+        if let Err(err) = self.heartbeat_writer.1.write_all(&encode_heartbeat(time)) {
+            panic!("Failed to write heartbeat to heartbeat file: {}", err);
         }
     }
 
     pub async fn log_raw(&mut self, chunk: &[u8]) {
+        // Raw data has no timestamp of its own, so it is filed by the time it was received
+        let path = self
+            .layout
+            .raw_log_path(chrono::Utc::now().timestamp_millis() as u64);
+        if path != self.raw_log_writer.0 {
+            let writer = open_log_file(&path, 8 * 1024).unwrap_or_else(|err| {
+                panic!("Failed to open raw log file: {}", err);
+            });
+            let (_, mut previous) = std::mem::replace(&mut self.raw_log_writer, (path, writer));
+            if let Err(err) = previous.flush() {
+                log::error!("Failed to flush raw log file: {}", err);
+            }
+        }
+
         // Write the entire raw response to a file unmodified
         // In the future, we may want to parse the response differently, so we don't want to lose any data
-        self.raw_log_writer.write_all(&chunk).unwrap();
+        self.raw_log_writer.1.write_all(chunk).unwrap();
     }
 
-    async fn parse_chunk(&mut self, chunk: &[u8]) -> Vec<StreamItem> {
-        // Move chunk into buffer
-        self.buffer.extend_from_slice(&chunk);
-
-        // Parse buffer into stream of StreamItems (Price or Heartbeat) using serde_json
-        let stream = Deserializer::from_slice(&self.buffer);
-        let mut stream = stream.into_iter::<StreamItem>();
-
-        let mut items = Vec::new();
-        let mut last_parsed_index = 0;
-        log::trace!("Streaming JSON from deserializer...");
-        while let Some(result) = stream.next() {
-            match result {
-                Ok(item) => {
-                    items.push(item);
-                    last_parsed_index = stream.byte_offset();
-                }
-                Err(err) => {
-                    log::debug!("Error parsing JSON: {:?}", err);
-                    log::debug!("This is likely caused by a chunk boundary, the next chunk will be parsed correctly.");
-                }
-            }
-        }
+    fn parse_chunk(&mut self, chunk: &[u8]) -> Result<Vec<StreamItem>, Box<dyn std::error::Error>> {
+        let before = self.parser.stats();
+        let items = self.parser.parse(chunk);
 
-        // Remove parsed JSON strings from buffer
-        self.buffer = self.buffer[last_parsed_index..].to_vec();
+        // Malformed lines and overflowing partial lines both count as parse errors
+        let after = self.parser.stats();
+        let errors = (after.malformed - before.malformed) + (after.overflows - before.overflows);
+        self.connection_log.record_parse_errors(errors as usize);
 
         items
     }
@@ -319,31 +629,32 @@ impl<'a> LoggingPriceStream<'a> {
         &mut self,
         timeout_duration: u64,
     ) -> Result<Vec<StreamItem>, Box<dyn std::error::Error>> {
+        // Instruments trimmed for connections dropping are streamed again once it's stable
+        if self.subscription.restore() {
+            log::info!("Reconnecting for the instruments trimmed");
+            self.source = ChunkSource::subscribe(
+                &mut self.subscription,
+                self.settings,
+                self.relay_address.as_deref(),
+            )
+            .await?;
+            self.parser.clear();
+        }
         // Get next chunk from OANDA, timeout after timeout_duration milliseconds
         log::trace!("Getting next chunk from OANDA...");
         let chunk = timeout(
             std::time::Duration::from_millis(timeout_duration),
-            self.response.chunk(),
+            self.source.chunk(),
         )
         .await??; // ?? because reqwest may return an error, and timeout may return an error
 
         if let Some(chunk) = chunk {
             // Log raw response before parsing
             self.log_raw(&chunk).await;
-
-            // TEMPORARY TESTING CHUNK PARSING
-            // Split chunk in half and parse each half sequentially to ensure we're not losing data on chunk boundaries
-            // let half = chunk.len() / 2;
-            // let first_half = &chunk[..half];
-            // let second_half = &chunk[half..];
-            // let first_items = self.parse_chunk(&first_half).await;
-            // let second_items = self.parse_chunk(&second_half).await;
-            // let mut items = first_items;
-            // items.extend(second_items);
-            // return Ok(items);
+            self.connection_log.record_chunk(chunk.len());
 
             log::trace!("Parsing chunk...");
-            let items = self.parse_chunk(&chunk).await;
+            let items = self.parse_chunk(&chunk)?;
             return Ok(items);
         } else {
             return Err(Box::new(EmptyChunkError {
@@ -370,13 +681,24 @@ impl<'a> Iterator for LoggingPriceStream<'a> {
                 for item in items {
                     // Log prices to binary files
                     match &item {
-                        StreamItem::Price(price) => {
+                        StreamItem::Price(price) if price.is_tradeable() => {
                             futures::executor::block_on(self.log_price(price));
                         }
-                        _ => {}
+                        // Tick files have no room to mark indicative prices, which replays and
+                        // backtests would trade on, so only the raw log keeps them
+                        StreamItem::Price(_) => {}
+                        // Backfilled prices aren't ticks, so they're kept out of the tick files
+                        StreamItem::Backfill(_) => {}
+                        // Counted by whatever reads the stream, see DroppedTicks
+                        StreamItem::Dropped(_) => {}
+                        StreamItem::Heartbeat(heartbeat) => {
+                            self.log_heartbeat(heartbeat);
+                            self.connection_log.record_heartbeat();
+                        }
                     }
 
                     // Add all items to buffer to be returned by next() calls
+                    self.gaps.record(&item);
                     self.buffered_items.push_back(item);
                 }
             }
@@ -393,3 +715,26 @@ impl<'a> Iterator for LoggingPriceStream<'a> {
         }
     }
 }
+
+fn create_parent_directory(path: &std::path::Path) -> Result<(), Box<dyn std::error::Error>> {
+    if let Some(parent) = path.parent() {
+        if !parent.as_os_str().is_empty() && !parent.exists() {
+            log::info!("Creating directory {}...", parent.display());
+            std::fs::create_dir_all(parent)?;
+        }
+    }
+    Ok(())
+}
+
+// Open a file for appending, creating it and any missing directories
+fn open_log_file(
+    path: &std::path::Path,
+    capacity: usize,
+) -> Result<std::io::BufWriter<std::fs::File>, Box<dyn std::error::Error>> {
+    create_parent_directory(path)?;
+    let file = std::fs::OpenOptions::new()
+        .append(true)
+        .create(true)
+        .open(path)?;
+    Ok(std::io::BufWriter::with_capacity(capacity, file))
+}
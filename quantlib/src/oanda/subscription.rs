@@ -0,0 +1,130 @@
+use std::time::{Duration, Instant};
+
+use crate::oanda::errors::StreamRejectedError;
+
+// A connection that drops sooner than this after opening counts as a failure
+const DEGRADED_CONNECTION: Duration = Duration::from_secs(60);
+
+// Consecutive failed connections before the subscription is trimmed
+const MAX_FAILURES: u32 = 5;
+
+// A connection that stays up this long brings back the instruments trimmed for dropping
+const STABLE_CONNECTION: Duration = Duration::from_secs(30 * 60);
+
+// Instruments ordered by a priority list, those in it first and in its order, the rest after
+// them in their original order
+pub fn prioritize(instruments: Vec<String>, priority: &[String]) -> Vec<String> {
+    let rank = |instrument: &String| {
+        priority
+            .iter()
+            .position(|prioritized| prioritized == instrument)
+            .unwrap_or(priority.len())
+    };
+    let mut instruments = instruments;
+    instruments.sort_by_key(rank);
+    instruments
+}
+
+// The instruments a stream is subscribed to, in priority order. When OANDA rejects the
+// subscription for too many instruments (or a URL too long) or connections keep dropping soon
+// after opening, the lowest priority instruments are dropped, a quarter at a time, rather than
+// losing the whole stream. Trimming is logged as an error, as the dropped instruments get no data
+// meanwhile. Instruments dropped for connections dropping are brought back once a connection
+// stays up for STABLE_CONNECTION, those OANDA rejected only when the stream is restarted.
+#[derive(Debug, Clone)]
+pub struct Subscription {
+    instruments: Vec<String>,
+    active: usize,
+    failures: u32,
+    connected_at: Option<Instant>,
+
+    // Most instruments OANDA has accepted, all of them until it rejects some
+    accepted: usize,
+}
+
+impl Subscription {
+    pub fn new(instruments: Vec<String>) -> Self {
+        Subscription {
+            active: instruments.len(),
+            accepted: instruments.len(),
+            instruments,
+            failures: 0,
+            connected_at: None,
+        }
+    }
+
+    // Instruments still subscribed to
+    pub fn active(&self) -> &[String] {
+        &self.instruments[..self.active]
+    }
+
+    // Instruments dropped by trimming
+    pub fn dropped(&self) -> &[String] {
+        &self.instruments[self.active..]
+    }
+
+    pub fn connected(&mut self) {
+        self.connected_at = Some(Instant::now());
+    }
+
+    // Record the connection dropping, trimming if connections have kept dropping soon after
+    // opening. Returns whether it was trimmed.
+    pub fn disconnected(&mut self) -> bool {
+        match self.connected_at.take() {
+            Some(connected_at) if connected_at.elapsed() < DEGRADED_CONNECTION => {
+                self.failures += 1
+            }
+            Some(_) => self.failures = 0,
+            None => {}
+        }
+        self.failures >= MAX_FAILURES && self.trim("connection degraded")
+    }
+
+    // Record OANDA rejecting the subscription, trimming it if there were too many instruments
+    // rather than something wrong with one of them. Returns whether it was trimmed.
+    pub fn rejected(&mut self, rejection: &StreamRejectedError, reason: &str) -> bool {
+        if !rejection.too_many_instruments() || !self.trim(reason) {
+            return false;
+        }
+        self.accepted = self.active;
+        true
+    }
+
+    // Whether the connection has been up long enough to bring back the instruments trimmed for
+    // connections dropping, which it does if so. The stream reconnects to stream them.
+    pub fn restore(&mut self) -> bool {
+        let stable = self
+            .connected_at
+            .is_some_and(|connected_at| connected_at.elapsed() >= STABLE_CONNECTION);
+        if !stable || self.active >= self.accepted {
+            return false;
+        }
+        log::warn!(
+            "Connection stable, streaming {} again. Streaming {} of {} instruments",
+            self.instruments[self.active..self.accepted].join(","),
+            self.accepted,
+            self.instruments.len()
+        );
+        self.active = self.accepted;
+        true
+    }
+
+    // Drop the lowest priority instruments, keeping at least one. Returns false if there was
+    // nothing left to drop.
+    pub fn trim(&mut self, reason: &str) -> bool {
+        if self.active <= 1 {
+            return false;
+        }
+        let dropped = (self.active / 4).max(1);
+        self.active -= dropped;
+        self.failures = 0;
+        log::error!(
+            "Subscription trimmed ({}), no longer streaming {}. Streaming {} of {} instruments",
+            reason,
+            self.instruments[self.active..self.active + dropped].join(","),
+            self.active,
+            self.instruments.len()
+        );
+        true
+    }
+}
@@ -1,21 +1,192 @@
+use rand::Rng;
 use reqwest::header::{HeaderMap, HeaderValue};
+use std::time::Duration;
 
+use crate::broker::OrderTags;
+use crate::errors::{self, Context};
+use crate::models::pip_size;
+use crate::oanda::errors::{ApiStatusError, DuplicateClientOrderIdError, OrderStateUnknownError};
 use crate::oanda::objects::API_URL;
-use crate::oanda::objects::{Price, Response, Position, PositionResponse, OandaSettings};
+use crate::oanda::objects::{
+    AccountSummary, AccountSummaryResponse, ClosePositionResponse, GetOrderResponse,
+    GetTransactionResponse, OandaSettings, Order, OrderResponse, Position, PositionFill,
+    PositionResponse, PositionSide, Transaction,
+};
+use crate::oanda::order_client::{limit_order_body, OrderClient};
+use crate::oanda::usage;
 
+// How hard to try when submitting an order over an unreliable connection
+#[derive(Debug, Clone)]
+pub struct SubmitPolicy {
+    // How long to wait for OANDA to respond to a single attempt
+    pub timeout: Duration,
+    pub max_attempts: u32,
+}
+
+impl Default for SubmitPolicy {
+    fn default() -> Self {
+        SubmitPolicy {
+            timeout: Duration::from_secs(10),
+            max_attempts: 3,
+        }
+    }
+}
+
+// Unique ID attached to an order so we can look it up if we never see the response
+pub fn generate_client_order_id() -> String {
+    let suffix: u32 = rand::thread_rng().gen();
+    format!(
+        "ql-{}-{:08x}",
+        chrono::Utc::now().timestamp_millis(),
+        suffix
+    )
+}
 
-pub async fn get_latest_prices(
-    instruments: &[String],
+pub async fn place_market_order(
+    instrument: &str,
+    units: f64,
     settings: &OandaSettings,
-) -> Result<Vec<Price>, Box<dyn std::error::Error>> {
-    let instrument_list = instruments.join(",");
+) -> Result<Option<Transaction>, Box<dyn std::error::Error>> {
+    place_market_order_with_fill(
+        instrument,
+        units,
+        PositionFill::Default,
+        &OrderTags::default(),
+        settings,
+    )
+    .await
+}
+
+// Place a market order with explicit control over how it fills against existing positions.
+// On hedging accounts, OPEN_ONLY guarantees the order adds to the leg matching the sign of units.
+// Returns the fill transaction, or None if the order was cancelled (e.g. a FOK order that couldn't fill)
+pub async fn place_market_order_with_fill(
+    instrument: &str,
+    units: f64,
+    position_fill: PositionFill,
+    tags: &OrderTags,
+    settings: &OandaSettings,
+) -> Result<Option<Transaction>, Box<dyn std::error::Error>> {
+    OrderClient::new(settings)?
+        .market_order(instrument, units, position_fill, tags)
+        .await
+}
+
+// Submit a market order so that it is never placed twice.
+// Every attempt carries the same client order ID. When an attempt times out or OANDA answers with
+// a server error we look the order up by that ID: if OANDA has it we return its outcome, if not
+// it is safe to try again. OANDA also rejects a second order with the same client ID, so a retry
+// can never double up: that rejection means an earlier attempt got through after all, and its
+// outcome is looked up the same way.
+// If the order's state can't be determined an OrderStateUnknownError is returned.
+pub async fn submit_market_order(
+    client: &OrderClient,
+    instrument: &str,
+    units: f64,
+    position_fill: PositionFill,
+    tags: &OrderTags,
+    policy: &SubmitPolicy,
+) -> Result<Option<Transaction>, Box<dyn std::error::Error>> {
+    let client_order_id = generate_client_order_id();
+    let mut last_error: Box<dyn std::error::Error> =
+        format!("Order {} was never attempted", client_order_id).into();
+
+    for attempt in 1..=policy.max_attempts {
+        match client
+            .send(
+                instrument,
+                units,
+                position_fill,
+                &client_order_id,
+                tags,
+                policy,
+            )
+            .await
+        {
+            Ok(fill) => return Ok(fill),
+            Err(err) if errors::find::<DuplicateClientOrderIdError>(err.as_ref()).is_some() => {
+                log::warn!(
+                    "[{}] Order {} was already placed, looking it up...",
+                    instrument,
+                    client_order_id
+                );
+                return match get_order_outcome(&client_order_id, client.settings()).await {
+                    Ok(Some(outcome)) => Ok(outcome),
+                    Ok(None) => Err(Box::new(OrderStateUnknownError {
+                        client_order_id,
+                        message: format!("{}, but it can't be found", err),
+                    })),
+                    Err(verify_err) => Err(Box::new(OrderStateUnknownError {
+                        client_order_id,
+                        message: format!("{} (verification failed: {})", err, verify_err),
+                    })),
+                };
+            }
+            Err(err) => {
+                let sent = match errors::find::<reqwest::Error>(err.as_ref()) {
+                    // The request never left, so it definitely wasn't placed
+                    Some(err) if err.is_connect() => false,
+                    Some(err)
+                        if err.is_timeout()
+                            || err.is_request()
+                            || err.is_body()
+                            || err.is_decode() =>
+                    {
+                        true
+                    }
+                    // A server error doesn't say whether the order was placed before it happened
+                    _ if errors::find::<ApiStatusError>(err.as_ref())
+                        .is_some_and(|err| err.status.is_server_error()) =>
+                    {
+                        true
+                    }
+                    // Any other error means OANDA answered, e.g. a rejection, which retrying won't fix
+                    _ => return Err(err),
+                };
+
+                log::warn!(
+                    "[{}] Attempt {}/{} to place order {} failed: {}",
+                    instrument,
+                    attempt,
+                    policy.max_attempts,
+                    client_order_id,
+                    err
+                );
+
+                if sent {
+                    match get_order_outcome(&client_order_id, client.settings()).await {
+                        Ok(Some(outcome)) => return Ok(outcome),
+                        Ok(None) => {
+                            log::info!("Order {} never reached OANDA, retrying...", client_order_id)
+                        }
+                        Err(verify_err) => {
+                            return Err(Box::new(OrderStateUnknownError {
+                                client_order_id,
+                                message: format!("{} (verification failed: {})", err, verify_err),
+                            }));
+                        }
+                    }
+                }
+                last_error = err;
+            }
+        }
+    }
+
+    Err(last_error)
+}
+
+// Place a resting stop order that only reduces the position in an instrument, e.g. to protect a
+// position left open while the trader isn't running. Returns the ID of the order.
+pub async fn place_protective_stop(
+    instrument: &str,
+    units: f64,
+    price: f64,
+    settings: &OandaSettings,
+) -> Result<String, Box<dyn std::error::Error>> {
     let authorization = format!("Bearer {}", &settings.authorization);
     let account_id = &settings.account_id;
 
-    let endpoint = format!(
-        "/v3/accounts/{}/pricing?instruments={}",
-        account_id, instrument_list
-    );
+    let endpoint = format!("/v3/accounts/{}/orders", account_id);
     let url = format!("{}{}", API_URL, endpoint);
 
     let mut headers = HeaderMap::new();
@@ -25,25 +196,57 @@ pub async fn get_latest_prices(
     );
     headers.insert("Content-Type", HeaderValue::from_static("application/json"));
 
-    let body = reqwest::Client::new()
-        .get(&url)
-        .headers(headers)
-        .send()
-        .await?
-        .text()
-        .await?;
+    // OANDA rejects prices more precise than a tenth of a pip
+    let decimals = (-pip_size(instrument).log10()).round() as usize + 1;
+    let body = format!("{{\"order\": {{\"units\": \"{}\", \"instrument\": \"{}\", \"price\": \"{:.*}\", \"timeInForce\": \"GTC\", \"type\": \"STOP\", \"positionFill\": \"REDUCE_ONLY\"}}}}", units, instrument, decimals, price);
+
+    let response = usage::track(
+        "orders",
+        reqwest::Client::new()
+            .post(&url)
+            .headers(headers)
+            .body(body)
+            .send()
+            .await,
+    )?;
+
+    if !response.status().is_success() {
+        return Err(format!(
+            "Received non-success status code {} from {}",
+            response.status(),
+            endpoint
+        )
+        .into());
+    }
 
-    let response: Response = serde_json::from_str(&body).unwrap();
-    let prices = response.prices;
+    let body = response.text().await?;
+    let order_response: OrderResponse = serde_json::from_str(&body)
+        .with_context(|| format!("Parsing the response from {}", endpoint))?;
 
-    Ok(prices)
+    if let Some(cancel) = order_response.order_cancel_transaction {
+        return Err(format!(
+            "Stop order was cancelled: {}",
+            cancel.reason.unwrap_or_default()
+        )
+        .into());
+    }
+    order_response
+        .order_create_transaction
+        .and_then(|transaction| transaction.id)
+        .ok_or_else(|| "Stop order wasn't created".into())
 }
 
-pub async fn place_market_order(
+// Post a limit order resting until `gtd_time` (RFC 3339), by which OANDA cancels whatever hasn't
+// filled, so it doesn't outlive a trader that stops before cancelling it. Returns the ID of the
+// order and its fill if it filled straight away.
+pub async fn place_limit_order(
     instrument: &str,
     units: f64,
+    price: f64,
+    gtd_time: &str,
+    tags: &OrderTags,
     settings: &OandaSettings,
-) -> Result<(), Box<dyn std::error::Error>> {
+) -> Result<(String, Option<Transaction>), Box<dyn std::error::Error>> {
     let authorization = format!("Bearer {}", &settings.authorization);
     let account_id = &settings.account_id;
 
@@ -57,27 +260,350 @@ pub async fn place_market_order(
     );
     headers.insert("Content-Type", HeaderValue::from_static("application/json"));
 
-    let body = format!("{{\"order\": {{\"units\": \"{}\", \"instrument\": \"{}\", \"timeInForce\": \"FOK\", \"type\": \"MARKET\", \"positionFill\": \"DEFAULT\"}}}}", units, instrument);
+    // OANDA rejects prices more precise than a tenth of a pip
+    let decimals = (-pip_size(instrument).log10()).round() as usize + 1;
+    let body = limit_order_body(instrument, units, price, decimals, gtd_time, tags);
+
+    let response = usage::track(
+        "orders",
+        reqwest::Client::new()
+            .post(&url)
+            .headers(headers)
+            .body(body)
+            .send()
+            .await,
+    )?;
+
+    if !response.status().is_success() {
+        return Err(format!(
+            "Received non-success status code {} from {}",
+            response.status(),
+            endpoint
+        )
+        .into());
+    }
+
+    let body = response.text().await?;
+    let order_response: OrderResponse = serde_json::from_str(&body)
+        .with_context(|| format!("Parsing the response from {}", endpoint))?;
+
+    if let Some(cancel) = order_response.order_cancel_transaction {
+        return Err(format!(
+            "Limit order was cancelled: {}",
+            cancel.reason.unwrap_or_default()
+        )
+        .into());
+    }
+    let order_id = order_response
+        .order_create_transaction
+        .and_then(|transaction| transaction.id)
+        .ok_or("Limit order wasn't created")?;
+    Ok((order_id, order_response.order_fill_transaction))
+}
+
+// Cancel a pending order, returning false if it no longer exists to cancel, e.g. it filled
+pub async fn cancel_order(
+    order_id: &str,
+    settings: &OandaSettings,
+) -> Result<bool, Box<dyn std::error::Error>> {
+    let authorization = format!("Bearer {}", &settings.authorization);
+    let account_id = &settings.account_id;
+
+    let endpoint = format!("/v3/accounts/{}/orders/{}/cancel", account_id, order_id);
+    let url = format!("{}{}", API_URL, endpoint);
+
+    let mut headers = HeaderMap::new();
+    headers.insert(
+        "Authorization",
+        HeaderValue::from_str(authorization.as_str())?,
+    );
+
+    let response = usage::track(
+        "orders",
+        reqwest::Client::new()
+            .put(&url)
+            .headers(headers)
+            .send()
+            .await,
+    )?;
+
+    if response.status() == reqwest::StatusCode::NOT_FOUND {
+        return Ok(false);
+    }
+    if !response.status().is_success() {
+        return Err(format!(
+            "Received non-success status code {} from {}",
+            response.status(),
+            endpoint
+        )
+        .into());
+    }
+    // Read the body so that the connection goes back to the pool
+    response.bytes().await?;
+    Ok(true)
+}
+
+// Look up an order by its client order ID, returns None if OANDA has never seen it
+pub async fn get_order_by_client_id(
+    client_order_id: &str,
+    settings: &OandaSettings,
+) -> Result<Option<Order>, Box<dyn std::error::Error>> {
+    get_order(&format!("@{}", client_order_id), settings).await
+}
+
+// The fill of an order that is no longer pending, by its ID, or None if it went unfilled
+pub async fn get_order_fill(
+    order_id: &str,
+    settings: &OandaSettings,
+) -> Result<Option<Transaction>, Box<dyn std::error::Error>> {
+    let order = get_order(order_id, settings)
+        .await?
+        .ok_or_else(|| format!("Order {} wasn't found", order_id))?;
+    match (order.state.as_str(), order.filling_transaction_id) {
+        ("FILLED", Some(transaction_id)) => {
+            Ok(Some(get_transaction(&transaction_id, settings).await?))
+        }
+        ("FILLED", None) => {
+            Err(format!("Filled order {} has no filling transaction", order_id).into())
+        }
+        _ => Ok(None),
+    }
+}
+
+// Look up an order by its ID, or its client order ID after an "@", returns None if OANDA has
+// never seen it
+async fn get_order(
+    specifier: &str,
+    settings: &OandaSettings,
+) -> Result<Option<Order>, Box<dyn std::error::Error>> {
+    let authorization = format!("Bearer {}", &settings.authorization);
+    let account_id = &settings.account_id;
+
+    let endpoint = format!("/v3/accounts/{}/orders/{}", account_id, specifier);
+    let url = format!("{}{}", API_URL, endpoint);
+
+    let mut headers = HeaderMap::new();
+    headers.insert(
+        "Authorization",
+        HeaderValue::from_str(authorization.as_str())?,
+    );
+
+    let response = usage::track(
+        "orders",
+        reqwest::Client::new()
+            .get(&url)
+            .headers(headers)
+            .send()
+            .await,
+    )?;
+
+    if response.status() == reqwest::StatusCode::NOT_FOUND {
+        return Ok(None);
+    }
+    if !response.status().is_success() {
+        return Err(format!(
+            "Received non-success status code {} from {}",
+            response.status(),
+            endpoint
+        )
+        .into());
+    }
+
+    let body = response.text().await?;
+    let order_response: GetOrderResponse = serde_json::from_str(&body)
+        .with_context(|| format!("Parsing the response from {}", endpoint))?;
+
+    Ok(Some(order_response.order))
+}
+
+pub async fn get_transaction(
+    transaction_id: &str,
+    settings: &OandaSettings,
+) -> Result<Transaction, Box<dyn std::error::Error>> {
+    let authorization = format!("Bearer {}", &settings.authorization);
+    let account_id = &settings.account_id;
+
+    let endpoint = format!(
+        "/v3/accounts/{}/transactions/{}",
+        account_id, transaction_id
+    );
+    let url = format!("{}{}", API_URL, endpoint);
+
+    let mut headers = HeaderMap::new();
+    headers.insert(
+        "Authorization",
+        HeaderValue::from_str(authorization.as_str())?,
+    );
+
+    let response = usage::track(
+        "transactions",
+        reqwest::Client::new()
+            .get(&url)
+            .headers(headers)
+            .send()
+            .await,
+    )?;
+
+    if !response.status().is_success() {
+        return Err(format!(
+            "Received non-success status code {} from {}",
+            response.status(),
+            endpoint
+        )
+        .into());
+    }
+
+    let body = response.text().await?;
+    let transaction_response: GetTransactionResponse = serde_json::from_str(&body)
+        .with_context(|| format!("Parsing the response from {}", endpoint))?;
+
+    Ok(transaction_response.transaction)
+}
+
+// Work out what happened to an order whose response we never received
+// Ok(None) means the order was never placed, Ok(Some(fill)) is the same result placing it would have returned
+pub async fn get_order_outcome(
+    client_order_id: &str,
+    settings: &OandaSettings,
+) -> Result<Option<Option<Transaction>>, Box<dyn std::error::Error>> {
+    let order = match get_order_by_client_id(client_order_id, settings).await? {
+        Some(order) => order,
+        None => return Ok(None),
+    };
+
+    match order.state.as_str() {
+        "FILLED" => {
+            let transaction_id = order
+                .filling_transaction_id
+                .ok_or("Filled order has no filling transaction")?;
+            let fill = get_transaction(&transaction_id, settings).await?;
+            log::info!(
+                "Order {} was filled by transaction {}",
+                client_order_id,
+                transaction_id
+            );
+            Ok(Some(Some(fill)))
+        }
+        "CANCELLED" => {
+            log::warn!("Order {} was cancelled", client_order_id);
+            Ok(Some(None))
+        }
+        state => Err(format!("Order {} is still in state {}", client_order_id, state).into()),
+    }
+}
+
+// Close part or all (units = None) of one side of a position, returning the resulting fills
+// This is how a hedging account reduces a leg, as an opposing order would open the other leg instead
+pub async fn close_position(
+    instrument: &str,
+    side: PositionSide,
+    units: Option<f64>,
+    settings: &OandaSettings,
+) -> Result<Vec<Transaction>, Box<dyn std::error::Error>> {
+    let authorization = format!("Bearer {}", &settings.authorization);
+    let account_id = &settings.account_id;
+
+    let endpoint = format!("/v3/accounts/{}/positions/{}/close", account_id, instrument);
+    let url = format!("{}{}", API_URL, endpoint);
+
+    let mut headers = HeaderMap::new();
+    headers.insert(
+        "Authorization",
+        HeaderValue::from_str(authorization.as_str())?,
+    );
+    headers.insert("Content-Type", HeaderValue::from_static("application/json"));
+
+    let units = match units {
+        Some(units) => format!("{}", units.abs()),
+        None => "ALL".to_string(),
+    };
+    let body = match side {
+        PositionSide::Long => format!("{{\"longUnits\": \"{}\"}}", units),
+        PositionSide::Short => format!("{{\"shortUnits\": \"{}\"}}", units),
+    };
 
-    let response = reqwest::Client::new()
-        .post(&url)
-        .headers(headers)
-        .body(body)
-        .send()
-        .await?;
+    let response = usage::track(
+        "positions/close",
+        reqwest::Client::new()
+            .put(&url)
+            .headers(headers)
+            .body(body)
+            .send()
+            .await,
+    )?;
 
     if !response.status().is_success() {
-        return Err(format!("Received non-success status code: {}", response.status()).into());
+        return Err(format!(
+            "Received non-success status code {} from {}",
+            response.status(),
+            endpoint
+        )
+        .into());
     }
 
-    // TODO: Parse response body for order ID
-    Ok(())
+    let body = response.text().await?;
+    let close_response: ClosePositionResponse = serde_json::from_str(&body)
+        .with_context(|| format!("Parsing the response from {}", endpoint))?;
+
+    Ok(close_response
+        .long_order_fill_transaction
+        .into_iter()
+        .chain(close_response.short_order_fill_transaction)
+        .collect())
 }
 
+pub async fn get_account_summary(
+    settings: &OandaSettings,
+) -> Result<AccountSummary, Box<dyn std::error::Error>> {
+    let authorization = format!("Bearer {}", &settings.authorization);
+    let account_id = &settings.account_id;
+
+    let endpoint = format!("/v3/accounts/{}/summary", account_id);
+    let url = format!("{}{}", API_URL, endpoint);
+
+    let mut headers = HeaderMap::new();
+    headers.insert(
+        "Authorization",
+        HeaderValue::from_str(authorization.as_str())?,
+    );
+    headers.insert("Content-Type", HeaderValue::from_static("application/json"));
+
+    let response = usage::track(
+        "summary",
+        reqwest::Client::new()
+            .get(&url)
+            .headers(headers)
+            .send()
+            .await,
+    )?;
+
+    if !response.status().is_success() {
+        return Err(format!(
+            "Received non-success status code {} from {}",
+            response.status(),
+            endpoint
+        )
+        .into());
+    }
+
+    let body = response.text().await?;
+    let summary: AccountSummaryResponse = serde_json::from_str(&body)
+        .with_context(|| format!("Parsing the response from {}", endpoint))?;
+
+    Ok(summary.account)
+}
 
 pub async fn get_positions(
     settings: &OandaSettings,
 ) -> Result<Vec<Position>, Box<dyn std::error::Error>> {
+    Ok(get_positions_snapshot(settings).await?.positions)
+}
+
+// Positions along with the ID of the last transaction they reflect, so that streamed
+// transactions can be applied on top of the snapshot without double counting
+pub async fn get_positions_snapshot(
+    settings: &OandaSettings,
+) -> Result<PositionResponse, Box<dyn std::error::Error>> {
     let authorization = format!("Bearer {}", &settings.authorization);
     let account_id = &settings.account_id;
 
@@ -91,14 +617,22 @@ pub async fn get_positions(
     );
     headers.insert("Content-Type", HeaderValue::from_static("application/json"));
 
-    let response = reqwest::Client::new()
-        .get(&url)
-        .headers(headers)
-        .send()
-        .await?;
+    let response = usage::track(
+        "positions",
+        reqwest::Client::new()
+            .get(&url)
+            .headers(headers)
+            .send()
+            .await,
+    )?;
 
     if !response.status().is_success() {
-        return Err(format!("Received non-success status code: {}", response.status()).into());
+        return Err(format!(
+            "Received non-success status code {} from {}",
+            response.status(),
+            endpoint
+        )
+        .into());
     }
 
     let body = response.text().await?;
@@ -107,11 +641,13 @@ pub async fn get_positions(
 
     if json_response.is_err() {
         println!("API Response: {}", body);
-        return Err(format!("Error parsing JSON: {}", json_response.err().unwrap()).into());
+        return Err(errors::wrap(
+            json_response.err().unwrap(),
+            format!("Parsing the response from {}", endpoint),
+        ));
     }
-    let positions = json_response.unwrap().positions;
 
-    Ok(positions)
+    Ok(json_response.unwrap())
 }
 
 pub async fn get_position(
@@ -0,0 +1,211 @@
+use std::collections::BTreeMap;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::{Mutex, Once};
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+// Counts of calls to one OANDA endpoint. For the streaming endpoints every call is a connection,
+// so anything beyond the first of the day is a reconnect.
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct EndpointUsage {
+    pub requests: u64,
+
+    // Responses with status 429 Too Many Requests
+    #[serde(rename = "rateLimited")]
+    pub rate_limited: u64,
+
+    // Other non-success responses and requests that never got a response
+    pub errors: u64,
+}
+
+// API usage over one UTC day
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct DailyUsage {
+    pub date: String,
+    pub endpoints: BTreeMap<String, EndpointUsage>,
+}
+
+impl DailyUsage {
+    pub fn requests(&self) -> u64 {
+        self.endpoints.values().map(|usage| usage.requests).sum()
+    }
+}
+
+// Daily request budget to warn about, e.g. {"dailyRequests": 50000, "summaryLog": "api_usage.log"}
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct UsageConfig {
+    #[serde(default)]
+    #[serde(rename = "dailyRequests")]
+    pub daily_requests: Option<u64>,
+
+    // Warn once this fraction of the daily budget has been used
+    #[serde(default = "default_warn_fraction")]
+    #[serde(rename = "warnFraction")]
+    pub warn_fraction: f64,
+
+    // Each day's usage is appended here as a JSON line when the day ends, or when the process
+    // stops part way through it
+    #[serde(default)]
+    #[serde(rename = "summaryLog")]
+    pub summary_log: Option<PathBuf>,
+}
+
+fn default_warn_fraction() -> f64 {
+    0.8
+}
+
+struct ApiUsage {
+    today: DailyUsage,
+    config: Option<UsageConfig>,
+    warned: bool,
+    exceeded: bool,
+}
+
+// The API functions are free functions without a shared client, so usage is tracked process wide
+static USAGE: Mutex<ApiUsage> = Mutex::new(ApiUsage {
+    today: DailyUsage {
+        date: String::new(),
+        endpoints: BTreeMap::new(),
+    },
+    config: None,
+    warned: false,
+    exceeded: false,
+});
+
+static ROLLING: Once = Once::new();
+
+// Also starts a thread that ends each day at UTC midnight, so its summary is written then rather
+// than whenever the next request happens to come
+pub fn configure(config: UsageConfig) {
+    if let Ok(mut usage) = USAGE.lock() {
+        usage.config = Some(config);
+    }
+    ROLLING.call_once(|| {
+        std::thread::spawn(|| loop {
+            let now = chrono::Utc::now();
+            let midnight = (now.date_naive() + chrono::Days::new(1))
+                .and_hms_opt(0, 0, 0)
+                .unwrap_or_default()
+                .and_utc();
+            let wait = (midnight - now).to_std().unwrap_or_default();
+            std::thread::sleep(wait + Duration::from_secs(1));
+            if let Ok(mut usage) = USAGE.lock() {
+                usage.roll_over();
+            }
+        });
+    });
+}
+
+// Write the summary of the day so far, when shutting down
+pub fn finish() {
+    if let Ok(mut usage) = USAGE.lock() {
+        let ended = std::mem::take(&mut usage.today);
+        usage.summarize(ended);
+    }
+}
+
+// Record a request to an endpoint and pass its result through, e.g.
+// let response = usage::track("orders", client.post(&url).send().await)?;
+pub fn track(
+    endpoint: &str,
+    result: Result<reqwest::Response, reqwest::Error>,
+) -> Result<reqwest::Response, reqwest::Error> {
+    if let Ok(mut usage) = USAGE.lock() {
+        usage.roll_over();
+        let counts = usage
+            .today
+            .endpoints
+            .entry(endpoint.to_string())
+            .or_default();
+        counts.requests += 1;
+        match &result {
+            Ok(response) if response.status() == reqwest::StatusCode::TOO_MANY_REQUESTS => {
+                counts.rate_limited += 1;
+                log::warn!("Rate limited by OANDA on {}", endpoint);
+            }
+            Ok(response) if !response.status().is_success() => counts.errors += 1,
+            Ok(_) => {}
+            Err(_) => counts.errors += 1,
+        }
+        usage.check_limits();
+    }
+    result
+}
+
+impl ApiUsage {
+    // Start a new day, logging the summary of the one that ended
+    fn roll_over(&mut self) {
+        let date = chrono::Utc::now().format("%Y-%m-%d").to_string();
+        if self.today.date == date {
+            return;
+        }
+
+        let ended = std::mem::replace(
+            &mut self.today,
+            DailyUsage {
+                date,
+                endpoints: BTreeMap::new(),
+            },
+        );
+        self.warned = false;
+        self.exceeded = false;
+        self.summarize(ended);
+    }
+
+    // Log a day's usage and append it to the summary log, if anything was used
+    fn summarize(&self, ended: DailyUsage) {
+        if ended.date.is_empty() {
+            return;
+        }
+
+        log::info!(
+            "API usage for {}: {} requests {:?}",
+            ended.date,
+            ended.requests(),
+            ended.endpoints
+        );
+        if let Some(path) = self.config.as_ref().and_then(|c| c.summary_log.as_ref()) {
+            let written = serde_json::to_string(&ended).map(|line| {
+                OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(path)
+                    .and_then(|mut file| writeln!(file, "{}", line))
+            });
+            if let Ok(Err(e)) = written {
+                log::error!("Failed to write API usage summary: {}", e);
+            }
+        }
+    }
+
+    fn check_limits(&mut self) {
+        let (limit, warn_fraction) = match &self.config {
+            Some(UsageConfig {
+                daily_requests: Some(limit),
+                warn_fraction,
+                ..
+            }) => (*limit, *warn_fraction),
+            _ => return,
+        };
+
+        let requests = self.today.requests();
+        if requests >= limit && !self.exceeded {
+            self.exceeded = true;
+            log::error!(
+                "API usage of {} requests today has reached the limit of {}",
+                requests,
+                limit
+            );
+        } else if requests as f64 >= limit as f64 * warn_fraction && !self.warned {
+            self.warned = true;
+            log::warn!(
+                "API usage of {} requests today is approaching the limit of {}",
+                requests,
+                limit
+            );
+        }
+    }
+}
@@ -0,0 +1,265 @@
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use serde::{Deserialize, Serialize};
+
+use crate::calendar;
+use crate::catalog::{Catalog, DatasetManifest};
+use crate::data::{self, StorageLayout, TICK};
+use crate::errors::Context;
+use crate::upload;
+
+const DAY: u64 = 24 * 60 * 60 * 1000;
+
+// How long to keep one kind of file and whether to compress it meanwhile, e.g. {"keepDays": 30}
+// or {"compress": true}. Files are kept forever and left uncompressed by default.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct Retention {
+    // Files last written more than this many days ago are deleted
+    #[serde(default)]
+    #[serde(rename = "keepDays")]
+    pub keep_days: Option<u64>,
+
+    // Compress files once they're no longer being written
+    #[serde(default)]
+    pub compress: bool,
+}
+
+impl Retention {
+    fn action(&self, age: u64, compressed: bool) -> Option<CompactionAction> {
+        if self.keep_days.is_some_and(|days| age > days * DAY) {
+            return Some(CompactionAction::Delete);
+        }
+        (self.compress && !compressed).then_some(CompactionAction::Compress)
+    }
+}
+
+// "retention" in the collector config, e.g. {"rawLogs": {"keepDays": 30}, "ticks": {"compress":
+// true}} to keep raw logs for 30 days and ticks forever but compressed. Files still being
+// written are never touched. Ticks deleted before the weekly pipeline has archived them are
+// lost, so their keepDays should be well over a week.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct RetentionPolicy {
+    // Raw logs and connection logs can only be deleted, their readers expect plain text
+    #[serde(default)]
+    #[serde(rename = "rawLogs")]
+    pub raw_logs: Retention,
+
+    #[serde(default)]
+    #[serde(rename = "connectionLogs")]
+    pub connection_logs: Retention,
+
+    // Heartbeat files, needed to explain gaps in the ticks for as long as those are kept
+    #[serde(default)]
+    pub heartbeats: Retention,
+
+    // Binary files of the collected ticks
+    #[serde(default)]
+    pub ticks: Retention,
+
+    // Binary files of any other granularity, with a bin template containing {granularity}
+    #[serde(default)]
+    pub candles: Retention,
+
+    // Weeks of the archive written by the weekly pipeline, aged from the end of the week
+    #[serde(default)]
+    pub archive: Retention,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum CompactionAction {
+    Delete,
+    Compress,
+}
+
+// What was done to a file, or would be in a dry run, and how much space it freed
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Compaction {
+    pub path: PathBuf,
+    pub action: CompactionAction,
+
+    // Size of the file before, in bytes
+    pub size: u64,
+
+    // Bytes freed: all of them when deleted, the difference to the compressed size otherwise
+    pub reclaimed: u64,
+}
+
+impl RetentionPolicy {
+    // Apply the policy to a collector's data directory. Only files of past days are touched,
+    // so this is safe to run alongside the collector as long as its templates contain {date}.
+    pub fn compact_storage(
+        &self,
+        storage: &StorageLayout,
+        now: u64,
+        dry_run: bool,
+    ) -> Result<Vec<Compaction>, Box<dyn std::error::Error>> {
+        if self.raw_logs.compress || self.connection_logs.compress {
+            return Err("Raw logs and connection logs can't be compressed, only deleted".into());
+        }
+
+        // The files being written today are the ones today's date renders to
+        let mut files: Vec<(PathBuf, &Retention)> = Vec::new();
+        let current = storage.raw_log_path(now);
+        for path in storage.raw_logs()? {
+            if path != current {
+                files.push((path, &self.raw_logs));
+            }
+        }
+        let current = storage.connection_log_path(now);
+        for path in storage.connection_logs()? {
+            if path != current {
+                files.push((path, &self.connection_logs));
+            }
+        }
+        let current = storage.heartbeats_path(now);
+        for path in storage.heartbeat_files()? {
+            if path != current {
+                files.push((path, &self.heartbeats));
+            }
+        }
+        for (instrument, path) in storage.bin_files()? {
+            let granularity = storage
+                .granularity(&path)
+                .unwrap_or_else(|| TICK.to_string());
+            if path == storage.bin_path(&instrument, &granularity, now) {
+                continue;
+            }
+            let retention = match granularity == TICK {
+                true => &self.ticks,
+                false => &self.candles,
+            };
+            files.push((path, retention));
+        }
+
+        let mut compactions = Vec::new();
+        for (path, retention) in files {
+            let age = now.saturating_sub(modified(&path)?);
+            if let Some(action) = retention.action(age, data::is_compressed(&path)) {
+                compactions.push(compact(&path, action, dry_run)?);
+            }
+        }
+        Ok(compactions)
+    }
+
+    // Apply the policy's archive retention to the complete weeks of an archive, keeping the
+    // catalog and the weeks' manifests in step with their files
+    pub fn compact_archive(
+        &self,
+        archive_dir: &Path,
+        now: u64,
+        dry_run: bool,
+    ) -> Result<Vec<Compaction>, Box<dyn std::error::Error>> {
+        let mut catalog = Catalog::load(archive_dir)?;
+        let mut compactions = Vec::new();
+        let mut entries = Vec::new();
+        for mut entry in std::mem::take(&mut catalog.entries) {
+            let path = archive_dir.join(&entry.path);
+            let age = now.saturating_sub(calendar::week_end(entry.year, entry.week));
+            let action = match entry.complete {
+                true => self.archive.action(age, data::is_compressed(&path)),
+                false => None,
+            };
+            let action = match action {
+                Some(action) => action,
+                None => {
+                    entries.push(entry);
+                    continue;
+                }
+            };
+
+            compactions.push(compact(&path, action, dry_run)?);
+            if action == CompactionAction::Delete {
+                if !dry_run {
+                    remove_manifest(&path)?;
+                }
+                continue;
+            }
+
+            // The manifest's checksum is of the file as stored, so it's updated to the new file
+            let relative = data::compressed_path(&entry.path);
+            if !dry_run {
+                if let Some(mut manifest) = entry.manifest.take() {
+                    let compressed = archive_dir.join(&relative);
+                    manifest.checksum = upload::sha256_hex(&std::fs::read(&compressed)?);
+                    manifest.save(&compressed)?;
+                    remove_manifest(&path)?;
+                    entry.manifest = Some(manifest);
+                }
+            }
+            entry.path = relative;
+            entries.push(entry);
+        }
+
+        if !dry_run {
+            catalog.entries = entries;
+            catalog.save(archive_dir)?;
+        }
+        Ok(compactions)
+    }
+}
+
+// When a file was last written, milliseconds since the epoch
+fn modified(path: &Path) -> Result<u64, Box<dyn std::error::Error>> {
+    let modified = std::fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .with_context(|| format!("Reading the modification time of {}", path.display()))?;
+    Ok(modified.duration_since(UNIX_EPOCH)?.as_millis() as u64)
+}
+
+// Compressed files are written under a temporary name and renamed into place before the
+// original is removed, so the data is never only in a partially written file
+fn compact(
+    path: &Path,
+    action: CompactionAction,
+    dry_run: bool,
+) -> Result<Compaction, Box<dyn std::error::Error>> {
+    let size = std::fs::metadata(path)
+        .with_context(|| format!("Reading {}", path.display()))?
+        .len();
+    let reclaimed = match action {
+        CompactionAction::Delete => {
+            if !dry_run {
+                std::fs::remove_file(path)
+                    .with_context(|| format!("Deleting {}", path.display()))?;
+            }
+            size
+        }
+        CompactionAction::Compress => {
+            let target = data::compressed_path(path);
+            if target.exists() {
+                return Err(format!(
+                    "Can't compress {}, {} already exists",
+                    path.display(),
+                    target.display()
+                )
+                .into());
+            }
+            let compressed = data::compress(&std::fs::read(path)?)?;
+            if !dry_run {
+                let mut temporary = target.clone().into_os_string();
+                temporary.push(".tmp");
+                std::fs::write(&temporary, &compressed)
+                    .with_context(|| format!("Writing {}", target.display()))?;
+                std::fs::rename(&temporary, &target)?;
+                std::fs::remove_file(path)?;
+            }
+            size.saturating_sub(compressed.len() as u64)
+        }
+    };
+    Ok(Compaction {
+        path: path.to_path_buf(),
+        action,
+        size,
+        reclaimed,
+    })
+}
+
+fn remove_manifest(data_path: &Path) -> Result<(), Box<dyn std::error::Error>> {
+    let path = DatasetManifest::path(data_path);
+    if path.exists() {
+        std::fs::remove_file(&path).with_context(|| format!("Deleting {}", path.display()))?;
+    }
+    Ok(())
+}
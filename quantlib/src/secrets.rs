@@ -0,0 +1,113 @@
+use std::io::{Read, Write};
+use std::sync::Mutex;
+
+use age::armor::{ArmoredReader, ArmoredWriter, Format};
+use age::secrecy::{ExposeSecret, SecretString};
+
+// The OANDA token in settings.json can be age encrypted (see the encrypt_token bin), so that it
+// isn't kept in plain text on the server. It's decrypted at startup with the age identity in
+// OANDA_TOKEN_KEY, or with a passphrase from OANDA_TOKEN_PASSPHRASE or typed in at the terminal.
+const ENCRYPTED_HEADER: &str = "-----BEGIN AGE ENCRYPTED FILE-----";
+pub const KEY_VARIABLE: &str = "OANDA_TOKEN_KEY";
+pub const PASSPHRASE_VARIABLE: &str = "OANDA_TOKEN_PASSPHRASE";
+
+// Replaces secrets in log lines
+const REDACTED: &str = "[REDACTED]";
+
+// Secrets that must never be logged, registered as they're read
+static SECRETS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+pub fn is_encrypted(value: &str) -> bool {
+    value.trim_start().starts_with(ENCRYPTED_HEADER)
+}
+
+// Decrypt an armored age ciphertext, with the key in OANDA_TOKEN_KEY if set and otherwise with
+// a passphrase
+pub fn decrypt(ciphertext: &str) -> Result<String, Box<dyn std::error::Error>> {
+    let decryptor = age::Decryptor::new_buffered(ArmoredReader::new(ciphertext.trim().as_bytes()))?;
+
+    let mut reader = match std::env::var(KEY_VARIABLE) {
+        Ok(key) => {
+            let identity: age::x25519::Identity = key
+                .trim()
+                .parse()
+                .map_err(|e| format!("{} isn't an age identity: {}", KEY_VARIABLE, e))?;
+            decryptor.decrypt(std::iter::once(&identity as &dyn age::Identity))?
+        }
+        Err(_) => {
+            let identity =
+                age::scrypt::Identity::new(passphrase("Passphrase for the OANDA token: ")?);
+            decryptor.decrypt(std::iter::once(&identity as &dyn age::Identity))?
+        }
+    };
+
+    let mut plaintext = String::new();
+    reader.read_to_string(&mut plaintext)?;
+    Ok(plaintext.trim().to_string())
+}
+
+// Encrypt to an age recipient (age1...), or with a passphrase if there is none
+pub fn encrypt(
+    plaintext: &str,
+    recipient: Option<&str>,
+) -> Result<String, Box<dyn std::error::Error>> {
+    let encryptor = match recipient {
+        Some(recipient) => {
+            let recipient: age::x25519::Recipient = recipient
+                .parse()
+                .map_err(|e| format!("Not an age recipient: {}", e))?;
+            age::Encryptor::with_recipients(std::iter::once(&recipient as &dyn age::Recipient))?
+        }
+        None => {
+            let first = passphrase("New passphrase for the OANDA token: ")?;
+            let second = passphrase("Repeat the passphrase: ")?;
+            if first.expose_secret() != second.expose_secret() {
+                return Err("The passphrases don't match".into());
+            }
+            age::Encryptor::with_user_passphrase(first)
+        }
+    };
+
+    let mut ciphertext = Vec::new();
+    let armored = ArmoredWriter::wrap_output(&mut ciphertext, Format::AsciiArmor)?;
+    let mut writer = encryptor.wrap_output(armored)?;
+    writer.write_all(plaintext.as_bytes())?;
+    writer.finish()?.finish()?;
+    Ok(String::from_utf8(ciphertext)?)
+}
+
+// From OANDA_TOKEN_PASSPHRASE, or typed in without echoing it
+fn passphrase(prompt: &str) -> Result<SecretString, Box<dyn std::error::Error>> {
+    if let Ok(passphrase) = std::env::var(PASSPHRASE_VARIABLE) {
+        return Ok(SecretString::from(passphrase));
+    }
+    let passphrase = rpassword::prompt_password(prompt).map_err(|e| {
+        format!(
+            "No {} and no terminal to ask for it: {}",
+            PASSPHRASE_VARIABLE, e
+        )
+    })?;
+    Ok(SecretString::from(passphrase))
+}
+
+pub fn register(secret: &str) {
+    if secret.is_empty() {
+        return;
+    }
+    let mut secrets = SECRETS.lock().unwrap();
+    if !secrets.iter().any(|known| known == secret) {
+        secrets.push(secret.to_string());
+    }
+}
+
+// Text with every registered secret replaced
+pub fn redact(text: &str) -> String {
+    let secrets = SECRETS.lock().unwrap();
+    let mut text = text.to_string();
+    for secret in secrets.iter() {
+        if text.contains(secret.as_str()) {
+            text = text.replace(secret.as_str(), REDACTED);
+        }
+    }
+    text
+}
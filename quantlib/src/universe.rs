@@ -0,0 +1,83 @@
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+
+use crate::errors::Context;
+use crate::fx::split_instrument;
+
+// Named groups of instruments kept in one file that every config refers to with "universes",
+// e.g. {"majors": ["EUR_USD", "GBP_USD", "USD_JPY"], "jpyCrosses": ["EUR_JPY", "GBP_JPY"],
+// "traded": ["majors", "jpyCrosses", "AUD_USD"]}. Wherever those configs list instruments, a
+// group's name stands for its instruments, so the lists are maintained in one place. Groups can
+// include other groups, and anything that isn't a group has to be an instrument name (BASE_QUOTE),
+// so a misspelt group is an error rather than an instrument nothing trades.
+#[derive(Debug, Clone, Default)]
+pub struct Universes {
+    groups: BTreeMap<String, Vec<String>>,
+}
+
+impl Universes {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, Box<dyn std::error::Error>> {
+        let path = path.as_ref();
+        let file = File::open(path).with_context(|| format!("Opening {}", path.display()))?;
+        let groups = serde_json::from_reader(BufReader::new(file))
+            .with_context(|| format!("Parsing {}", path.display()))?;
+        Ok(Universes { groups })
+    }
+
+    // The instruments of a list of instruments and group names, each once, in the order they're
+    // first listed
+    pub fn expand(&self, names: &[String]) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+        let mut instruments = Vec::new();
+        for name in names {
+            self.expand_into(name, &mut Vec::new(), &mut instruments)?;
+        }
+        Ok(instruments)
+    }
+
+    fn expand_into(
+        &self,
+        name: &str,
+        path: &mut Vec<String>,
+        instruments: &mut Vec<String>,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let members = match self.groups.get(name) {
+            Some(members) => members,
+            None => {
+                let valid = split_instrument(name)
+                    .is_some_and(|(base, quote)| !base.is_empty() && !quote.is_empty());
+                if !valid {
+                    return Err(match path.last() {
+                        Some(group) => format!(
+                            "{} in instrument group {} is neither a group nor an instrument",
+                            name, group
+                        ),
+                        None => {
+                            format!("{} is neither an instrument group nor an instrument", name)
+                        }
+                    }
+                    .into());
+                }
+                if !instruments.iter().any(|instrument| instrument == name) {
+                    instruments.push(name.to_string());
+                }
+                return Ok(());
+            }
+        };
+        if path.iter().any(|group| group == name) {
+            return Err(format!(
+                "Instrument group {} includes itself through {}",
+                name,
+                path.join(" -> ")
+            )
+            .into());
+        }
+        path.push(name.to_string());
+        for member in members {
+            self.expand_into(member, path, instruments)?;
+        }
+        path.pop();
+        Ok(())
+    }
+}
@@ -0,0 +1,371 @@
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+// Header carrying the SHA-256 of the uncompressed file, checked after every upload and used to
+// skip files that were already uploaded unchanged
+const CHECKSUM_HEADER: &str = "x-amz-meta-sha256";
+
+// Somewhere collected data is copied to, so that the collector's disk isn't the only copy, e.g.
+// {"type": "s3", "name": "minio", "endpoint": "https://minio.example.com", "bucket": "ticks"}
+// or {"type": "directory", "name": "backup", "path": "/mnt/backup/ticks"}
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum ArchiveSink {
+    // Any S3 compatible object storage (S3, MinIO, ...), addressed path style
+    S3 {
+        name: String,
+        endpoint: String,
+        bucket: String,
+
+        #[serde(default = "default_region")]
+        region: String,
+
+        // Prepended to every key, e.g. "collector-1/"
+        #[serde(default)]
+        prefix: String,
+
+        // Default to the AWS_ACCESS_KEY_ID and AWS_SECRET_ACCESS_KEY environment variables, so
+        // credentials don't have to be kept in the config
+        #[serde(default)]
+        #[serde(rename = "accessKey")]
+        access_key: Option<String>,
+
+        #[serde(default)]
+        #[serde(rename = "secretKey")]
+        secret_key: Option<String>,
+
+        #[serde(default = "default_retries")]
+        retries: u32,
+    },
+
+    // A directory, e.g. on another disk or a network mount. A .sha256 file next to each copy
+    // holds the checksum of the uncompressed file.
+    Directory {
+        name: String,
+        path: PathBuf,
+    },
+}
+
+fn default_region() -> String {
+    "us-east-1".to_string()
+}
+
+fn default_retries() -> u32 {
+    3
+}
+
+// What became of a file sent to a sink
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UploadOutcome {
+    Uploaded,
+    // The sink already had the file with the same checksum
+    Unchanged,
+}
+
+impl ArchiveSink {
+    pub fn name(&self) -> &str {
+        match self {
+            ArchiveSink::S3 { name, .. } | ArchiveSink::Directory { name, .. } => name,
+        }
+    }
+
+    // Store the file at `path` (already compressed) under `key`, unless the sink already has a
+    // copy of the original with `checksum`. The file is streamed, not read into memory, and
+    // uploads are verified by reading the checksum back.
+    pub async fn put(
+        &self,
+        key: &str,
+        path: &Path,
+        checksum: &str,
+    ) -> Result<UploadOutcome, Box<dyn std::error::Error>> {
+        match self {
+            ArchiveSink::S3 { retries, .. } => {
+                let client = S3Client::from_sink(self)?;
+
+                // Backs off 1, 2, 4, ... seconds between attempts
+                let mut attempt = 0;
+                loop {
+                    match client.upload(key, path, checksum).await {
+                        Ok(outcome) => return Ok(outcome),
+                        Err(e) if attempt < *retries => {
+                            let delay = Duration::from_secs(1 << attempt.min(6));
+                            log::warn!(
+                                "[{}] Upload of {} failed: {}, retrying in {:?}",
+                                self.name(),
+                                key,
+                                e,
+                                delay
+                            );
+                            tokio::time::sleep(delay).await;
+                            attempt += 1;
+                        }
+                        Err(e) => return Err(e),
+                    }
+                }
+            }
+            ArchiveSink::Directory {
+                path: directory, ..
+            } => {
+                let destination = directory.join(key);
+                let checksum_path = checksum_file(&destination);
+                let existing = std::fs::read_to_string(&checksum_path).ok();
+                if existing.as_deref().map(str::trim) == Some(checksum) && destination.exists() {
+                    return Ok(UploadOutcome::Unchanged);
+                }
+
+                if let Some(parent) = destination.parent() {
+                    std::fs::create_dir_all(parent)?;
+                }
+                // Written under a temporary name first, so a copy is either complete or missing
+                let partial = destination.with_extension("partial");
+                std::fs::copy(path, &partial)?;
+                std::fs::rename(&partial, &destination)?;
+                std::fs::write(&checksum_path, format!("{}\n", checksum))?;
+                Ok(UploadOutcome::Uploaded)
+            }
+        }
+    }
+}
+
+fn checksum_file(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(".sha256");
+    PathBuf::from(name)
+}
+
+pub fn sha256_hex(data: &[u8]) -> String {
+    hex::encode(Sha256::digest(data))
+}
+
+// The SHA-256 of a file, read a piece at a time
+pub fn sha256_file(path: &Path) -> Result<String, Box<dyn std::error::Error>> {
+    let mut file = std::fs::File::open(path)?;
+    let mut hasher = Sha256::new();
+    std::io::copy(&mut file, &mut hasher)?;
+    Ok(hex::encode(hasher.finalize()))
+}
+
+// Just enough of the S3 API to put objects and read their metadata back, signed with AWS
+// Signature Version 4
+struct S3Client {
+    endpoint: String,
+    host: String,
+    bucket: String,
+    region: String,
+    prefix: String,
+    access_key: String,
+    secret_key: String,
+}
+
+impl S3Client {
+    fn from_sink(sink: &ArchiveSink) -> Result<Self, Box<dyn std::error::Error>> {
+        let (endpoint, bucket, region, prefix, access_key, secret_key) = match sink {
+            ArchiveSink::S3 {
+                endpoint,
+                bucket,
+                region,
+                prefix,
+                access_key,
+                secret_key,
+                ..
+            } => (endpoint, bucket, region, prefix, access_key, secret_key),
+            ArchiveSink::Directory { .. } => return Err("Not an S3 sink".into()),
+        };
+
+        let access_key = match access_key {
+            Some(key) => key.clone(),
+            None => std::env::var("AWS_ACCESS_KEY_ID")
+                .map_err(|_| "No accessKey configured and AWS_ACCESS_KEY_ID is not set")?,
+        };
+        let secret_key = match secret_key {
+            Some(key) => key.clone(),
+            None => std::env::var("AWS_SECRET_ACCESS_KEY")
+                .map_err(|_| "No secretKey configured and AWS_SECRET_ACCESS_KEY is not set")?,
+        };
+
+        let endpoint = endpoint.trim_end_matches('/').to_string();
+        let host = endpoint
+            .split("://")
+            .nth(1)
+            .unwrap_or(&endpoint)
+            .to_string();
+        Ok(S3Client {
+            endpoint,
+            host,
+            bucket: bucket.clone(),
+            region: region.clone(),
+            prefix: prefix.clone(),
+            access_key,
+            secret_key,
+        })
+    }
+
+    fn path(&self, key: &str) -> String {
+        format!(
+            "/{}/{}",
+            self.bucket,
+            uri_encode(&format!("{}{}", self.prefix, key))
+        )
+    }
+
+    async fn upload(
+        &self,
+        key: &str,
+        path: &Path,
+        checksum: &str,
+    ) -> Result<UploadOutcome, Box<dyn std::error::Error>> {
+        if self.checksum(key).await?.as_deref() == Some(checksum) {
+            return Ok(UploadOutcome::Unchanged);
+        }
+
+        self.put(key, path, checksum).await?;
+        match self.checksum(key).await? {
+            Some(stored) if stored == checksum => Ok(UploadOutcome::Uploaded),
+            stored => {
+                Err(format!("Checksum mismatch after upload of {}: {:?}", key, stored).into())
+            }
+        }
+    }
+
+    // The body is streamed from the file, with its length set as S3 doesn't take chunked uploads
+    async fn put(
+        &self,
+        key: &str,
+        file: &Path,
+        checksum: &str,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let path = self.path(key);
+        let payload_hash = sha256_file(file)?;
+        let headers = self.sign("PUT", &path, &payload_hash, &[(CHECKSUM_HEADER, checksum)]);
+        let body = tokio::fs::File::open(file).await?;
+        let length = body.metadata().await?.len();
+        let mut request = reqwest::Client::new()
+            .put(format!("{}{}", self.endpoint, path))
+            .header(reqwest::header::CONTENT_LENGTH, length)
+            .body(body);
+        for (name, value) in headers {
+            request = request.header(name, value);
+        }
+
+        let response = request.send().await?;
+        if !response.status().is_success() {
+            let status = response.status();
+            let body = response.text().await.unwrap_or_default();
+            return Err(format!("PUT {} failed with {}: {}", path, status, body).into());
+        }
+        Ok(())
+    }
+
+    // The checksum stored with an object, None if there is no such object
+    async fn checksum(&self, key: &str) -> Result<Option<String>, Box<dyn std::error::Error>> {
+        let path = self.path(key);
+        let headers = self.sign("HEAD", &path, &sha256_hex(&[]), &[]);
+        let mut request = reqwest::Client::new().head(format!("{}{}", self.endpoint, path));
+        for (name, value) in headers {
+            request = request.header(name, value);
+        }
+
+        let response = request.send().await?;
+        if response.status() == reqwest::StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        if !response.status().is_success() {
+            return Err(format!("HEAD {} failed with {}", path, response.status()).into());
+        }
+        Ok(response
+            .headers()
+            .get(CHECKSUM_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .map(|value| value.to_string()))
+    }
+
+    // Headers signing the request, including the extra headers (which must be lowercase), given
+    // the SHA-256 of its payload
+    fn sign(
+        &self,
+        method: &str,
+        path: &str,
+        payload_hash: &str,
+        extra: &[(&str, &str)],
+    ) -> Vec<(String, String)> {
+        let now = chrono::Utc::now();
+        let amz_date = now.format("%Y%m%dT%H%M%SZ").to_string();
+        let date = now.format("%Y%m%d").to_string();
+
+        let mut headers: Vec<(String, String)> = vec![
+            ("host".to_string(), self.host.clone()),
+            ("x-amz-content-sha256".to_string(), payload_hash.to_string()),
+            ("x-amz-date".to_string(), amz_date.clone()),
+        ];
+        for (name, value) in extra {
+            headers.push((name.to_string(), value.to_string()));
+        }
+        headers.sort();
+
+        let canonical_headers: String = headers
+            .iter()
+            .map(|(name, value)| format!("{}:{}\n", name, value.trim()))
+            .collect();
+        let signed_headers = headers
+            .iter()
+            .map(|(name, _)| name.as_str())
+            .collect::<Vec<_>>()
+            .join(";");
+        let canonical_request = format!(
+            "{}\n{}\n\n{}\n{}\n{}",
+            method, path, canonical_headers, signed_headers, payload_hash
+        );
+
+        let scope = format!("{}/{}/s3/aws4_request", date, self.region);
+        let string_to_sign = format!(
+            "AWS4-HMAC-SHA256\n{}\n{}\n{}",
+            amz_date,
+            scope,
+            sha256_hex(canonical_request.as_bytes())
+        );
+
+        let key = hmac(
+            format!("AWS4{}", self.secret_key).as_bytes(),
+            date.as_bytes(),
+        );
+        let key = hmac(&key, self.region.as_bytes());
+        let key = hmac(&key, b"s3");
+        let key = hmac(&key, b"aws4_request");
+        let signature = hex::encode(hmac(&key, string_to_sign.as_bytes()));
+
+        headers.push((
+            "authorization".to_string(),
+            format!(
+                "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+                self.access_key, scope, signed_headers, signature
+            ),
+        ));
+        // reqwest sets the host header itself
+        headers.retain(|(name, _)| name != "host");
+        headers
+    }
+}
+
+fn hmac(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC takes keys of any length");
+    mac.update(data);
+    mac.finalize().into_bytes().to_vec()
+}
+
+// Percent-encode everything but unreserved characters and the separators between segments
+fn uri_encode(key: &str) -> String {
+    let mut encoded = String::new();
+    for byte in key.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' | b'/' => {
+                encoded.push(byte as char)
+            }
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
@@ -1,14 +1,340 @@
-use serde::{Deserialize, Serialize};
+use serde::Deserialize;
+#[cfg(any(feature = "data", feature = "backtest"))]
+use serde::Serialize;
 use serde_json;
+#[cfg(feature = "backtest")]
+use sha2::{Digest, Sha256};
+#[cfg(any(feature = "data", feature = "backtest"))]
 use std::fs::File;
+#[cfg(any(feature = "data", feature = "backtest"))]
 use std::io::BufReader;
 use std::path::Path;
+#[cfg(feature = "data")]
+use std::path::PathBuf;
 
-use crate::oanda::objects::Settings;
+#[cfg(feature = "trading")]
+use crate::accounts::ReportingSettings;
+#[cfg(any(feature = "data", feature = "backtest"))]
+use crate::alerts::AlertConfig;
+use crate::broker::BinanceSettings;
+#[cfg(feature = "backtest")]
+use crate::broker::BrokerConfig;
+#[cfg(feature = "trading")]
+use crate::broker::FixSettings;
+#[cfg(feature = "backtest")]
+use crate::control::GrpcConfig;
+#[cfg(feature = "data")]
+use crate::data::StorageLayout;
+#[cfg(feature = "backtest")]
+use crate::engine::{
+    BootstrapConfig, ConnectionHealthConfig, DrawdownScaling, KillSwitchConfig, PaperConfig,
+    SessionLimits, ShadowConfig, ShutdownConfig, SignalLogConfig, StalenessConfig, WeekendConfig,
+};
+use crate::errors::Context;
+use crate::fx::split_instrument;
+#[cfg(any(feature = "data", feature = "backtest"))]
+use crate::logging::TickLogConfig;
+#[cfg(feature = "backtest")]
+use crate::models::{
+    Allocation, ConflictPolicy, CostGuardConfig, ForecastMapping, MarginConfig, ModelStateConfig,
+    NonTradeablePrices, OrderRateLimit, PassiveExecutionConfig, PositionSizing, PriceBasis,
+    SignalValidity, StrategyLimits, StrategyWorkersConfig, TargetSmoothing, TargetTolerance,
+    TrailingStopDistance, UnitRounding, VolatilityTarget,
+};
+use crate::notify::NotificationSink;
+use crate::oanda::objects::OandaSettings;
+#[cfg(feature = "data")]
+use crate::oanda::usage::UsageConfig;
+#[cfg(feature = "data")]
+use crate::retention::RetentionPolicy;
+use crate::secrets;
+#[cfg(feature = "data")]
+use crate::universe::Universes;
+#[cfg(feature = "data")]
+use crate::upload::ArchiveSink;
 
+// settings.json, the account's credentials and what the binaries share, in sections, e.g.
+// {"credentials": {"oanda": {"account_id": "...", "authorization": "..."},
+//                  "binance": {"apiKey": "...", "secretKey": "..."}, "fix": {...}},
+//  "data": {"instruments": ["EUR_USD", "GBP_USD"]},
+//  "trading": {"units": 1000.0},
+//  "risk": {"maxUnits": 100000.0},
+//  "notifications": {"sinks": [{"type": "webhook", "url": "https://..."}]},
+//  "reporting": {...}}
+// Only the credentials are needed, and keys that aren't known are errors rather than being
+// ignored, so a misspelt setting doesn't quietly fall back to its default. The flat file of
+// before, with "oanda", "instruments" and "units" at the top, is still read (see
+// migrate_settings) until it's converted with "investments settings migrate".
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Settings {
+    pub credentials: Credentials,
+
+    #[serde(default)]
+    pub data: DataSettings,
+
+    #[serde(default)]
+    pub trading: TradingSettings,
+
+    #[serde(default)]
+    pub risk: RiskSettings,
+
+    #[serde(default)]
+    pub notifications: NotificationSettings,
+
+    // Other accounts reported on alongside this one, see ReportingSettings
+    #[cfg(feature = "trading")]
+    #[serde(default)]
+    pub reporting: Option<ReportingSettings>,
+
+    // Still allowed in the file when built without trading
+    #[cfg(not(feature = "trading"))]
+    #[serde(default)]
+    #[allow(dead_code)]
+    reporting: Option<serde_json::Value>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Credentials {
+    pub oanda: OandaSettings,
+
+    // Only needed to trade through Binance
+    #[serde(default)]
+    pub binance: Option<BinanceSettings>,
+
+    // Only needed to route orders over FIX
+    #[cfg(feature = "trading")]
+    #[serde(default)]
+    pub fix: Option<FixSettings>,
+
+    #[cfg(not(feature = "trading"))]
+    #[serde(default)]
+    #[allow(dead_code)]
+    fix: Option<serde_json::Value>,
+}
+
+// What the stream relay and the fixture recorder stream when not told otherwise. The collector
+// records every instrument unless its own config lists some.
+#[derive(Debug, Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+pub struct DataSettings {
+    #[serde(default)]
+    pub instruments: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TradingSettings {
+    // Position size taken on a signal, unless the trading config's "positionSizing" says otherwise
+    #[serde(default = "default_units")]
+    pub units: f64,
+}
+
+fn default_units() -> f64 {
+    1000.0
+}
+
+impl Default for TradingSettings {
+    fn default() -> Self {
+        TradingSettings {
+            units: default_units(),
+        }
+    }
+}
+
+// Limits on the account that hold whatever trading config is run on it
+#[derive(Debug, Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+pub struct RiskSettings {
+    // Largest position the trader sizes in any instrument, live, on paper or through a broker
+    #[serde(default)]
+    #[serde(rename = "maxUnits")]
+    pub max_units: Option<f64>,
+}
+
+// Where every binary's alerts go as well as to the sinks of its own config, and where fatal
+// stream errors are reported without an alerts config, e.g. a webhook whose URL is better kept
+// out of configs that are shared
+#[derive(Debug, Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+pub struct NotificationSettings {
+    #[serde(default)]
+    pub sinks: Vec<NotificationSink>,
+}
+
+impl Settings {
+    // Settings from a file, flat or sectioned, checked but with any secrets still encrypted
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, Box<dyn std::error::Error>> {
+        let path = path.as_ref();
+        let text =
+            std::fs::read_to_string(path).with_context(|| format!("Reading {}", path.display()))?;
+        let mut value: serde_json::Value =
+            serde_json::from_str(&text).with_context(|| format!("Parsing {}", path.display()))?;
+        if let Some(migrated) = migrate_settings(&value) {
+            log::warn!(
+                "{} is in the old flat format, convert it with \"investments settings migrate\"",
+                path.display()
+            );
+            value = migrated;
+        }
+        let settings: Settings =
+            serde_json::from_value(value).with_context(|| format!("Parsing {}", path.display()))?;
+        let problems = settings.problems();
+        if !problems.is_empty() {
+            return Err(format!("Invalid {}: {}", path.display(), problems.join("; ")).into());
+        }
+        Ok(settings)
+    }
+
+    // Everything wrong with the settings, so they can all be fixed in one go
+    pub fn problems(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        check_account(&self.credentials.oanda, "credentials.oanda", &mut problems);
+        if let Some(binance) = &self.credentials.binance {
+            if binance.api_key.is_empty() || binance.secret_key.is_empty() {
+                problems.push("credentials.binance needs an apiKey and a secretKey".to_string());
+            }
+        }
+        #[cfg(feature = "trading")]
+        if let Some(fix) = &self.credentials.fix {
+            if fix.host.is_empty() || fix.port == 0 {
+                problems.push("credentials.fix needs a host and a port".to_string());
+            }
+        }
+
+        let mut seen = std::collections::HashSet::new();
+        for instrument in &self.data.instruments {
+            if split_instrument(instrument).is_none() {
+                problems.push(format!(
+                    "data.instruments: {} isn't an instrument like EUR_USD",
+                    instrument
+                ));
+            } else if !seen.insert(instrument) {
+                problems.push(format!("data.instruments: {} is listed twice", instrument));
+            }
+        }
+
+        if !(self.trading.units.is_finite() && self.trading.units > 0.0) {
+            problems.push(format!(
+                "trading.units must be more than 0, not {}",
+                self.trading.units
+            ));
+        }
+        if let Some(max_units) = self.risk.max_units {
+            if !(max_units.is_finite() && max_units > 0.0) {
+                problems.push(format!(
+                    "risk.maxUnits must be more than 0, not {}",
+                    max_units
+                ));
+            }
+        }
+
+        #[cfg(feature = "trading")]
+        for account in self
+            .reporting
+            .iter()
+            .flat_map(|reporting| reporting.accounts.iter())
+        {
+            let name = format!("reporting.accounts.{}", account.name);
+            check_account(&account.oanda, &name, &mut problems);
+        }
+        problems
+    }
+}
+
+// OANDA account ids are four groups of digits, e.g. 101-004-1234567-001
+fn check_account(account: &OandaSettings, name: &str, problems: &mut Vec<String>) {
+    let groups: Vec<&str> = account.account_id.split('-').collect();
+    let digits = |group: &&str| !group.is_empty() && group.bytes().all(|b| b.is_ascii_digit());
+    if groups.len() != 4 || !groups.iter().all(digits) {
+        problems.push(format!(
+            "{}.account_id {:?} isn't an OANDA account id like 101-004-1234567-001",
+            name, account.account_id
+        ));
+    }
+    if account.authorization.trim().is_empty() {
+        problems.push(format!("{}.authorization is empty", name));
+    }
+}
+
+// The sectioned form of settings in the flat format of before, e.g.
+// {"instruments": ["EUR_USD"], "units": 1000.0, "oanda": {...}, "binance": {...}, "fix": {...}}
+// or None if they're already sectioned. Anything else at the top is left where it is, to be
+// reported as unknown.
+pub fn migrate_settings(settings: &serde_json::Value) -> Option<serde_json::Value> {
+    let flat = settings.as_object()?;
+    if flat.contains_key("credentials") || !flat.contains_key("oanda") {
+        return None;
+    }
+
+    let mut sectioned = serde_json::Map::new();
+    let mut credentials = serde_json::Map::new();
+    let mut data = serde_json::Map::new();
+    let mut trading = serde_json::Map::new();
+    for (key, value) in flat {
+        let section = match key.as_str() {
+            "oanda" | "binance" | "fix" => &mut credentials,
+            "instruments" => &mut data,
+            "units" => &mut trading,
+            _ => &mut sectioned,
+        };
+        section.insert(key.clone(), value.clone());
+    }
+    for (name, section) in [
+        ("credentials", credentials),
+        ("data", data),
+        ("trading", trading),
+    ] {
+        if !section.is_empty() {
+            sectioned.insert(name.to_string(), serde_json::Value::Object(section));
+        }
+    }
+    Some(serde_json::Value::Object(sectioned))
+}
+
+// settings.json, checked and with its secrets decrypted
 pub fn read_settings() -> Result<Settings, Box<dyn std::error::Error>> {
-    let settings = std::fs::read_to_string("settings.json")?;
-    serde_json::from_str(&settings).map_err(|e| e.into())
+    let mut settings = Settings::load("settings.json")?;
+    let credentials = &mut settings.credentials;
+    if secrets::is_encrypted(&credentials.oanda.authorization) {
+        credentials.oanda.authorization = secrets::decrypt(&credentials.oanda.authorization)
+            .context("Failed to decrypt the OANDA token")?;
+    }
+    secrets::register(&credentials.oanda.authorization);
+    if let Some(binance) = &mut credentials.binance {
+        if secrets::is_encrypted(&binance.secret_key) {
+            binance.secret_key = secrets::decrypt(&binance.secret_key)
+                .context("Failed to decrypt the Binance secret key")?;
+        }
+        secrets::register(&binance.secret_key);
+    }
+    #[cfg(feature = "trading")]
+    if let Some(password) = credentials
+        .fix
+        .as_mut()
+        .and_then(|fix| fix.password.as_mut())
+    {
+        if secrets::is_encrypted(password) {
+            *password = secrets::decrypt(password).context("Failed to decrypt the FIX password")?;
+        }
+        secrets::register(password);
+    }
+    #[cfg(feature = "trading")]
+    for account in settings
+        .reporting
+        .iter_mut()
+        .flat_map(|reporting| reporting.accounts.iter_mut())
+    {
+        if secrets::is_encrypted(&account.oanda.authorization) {
+            account.oanda.authorization = secrets::decrypt(&account.oanda.authorization)
+                .with_context(|| {
+                    format!("Failed to decrypt the token of account {}", account.name)
+                })?;
+        }
+        secrets::register(&account.oanda.authorization);
+    }
+    Ok(settings)
 }
 
 pub fn generate_timestamp() -> String {
@@ -21,22 +347,410 @@ pub fn generate_timestamp_filename() -> String {
     now.format("%Y-%m-%d_%H-%M-%S").to_string()
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[cfg(feature = "backtest")]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct TradingConfig {
+    // Instruments and names of instrument groups in the universes file
     pub instruments: Vec<String>,
     pub model: String,
 
+    // File of named instrument groups, see Universes
+    #[serde(default)]
+    pub universes: Option<PathBuf>,
+
+    #[serde(default)]
+    #[serde(rename = "trailingStop")]
+    pub trailing_stop: Option<TrailingStopDistance>,
+
+    // Seconds between full refreshes of the locally cached positions
+    #[serde(default = "default_reconcile_interval")]
+    #[serde(rename = "reconcileInterval")]
+    pub reconcile_interval: u64,
+
+    // Instruments are split over several streaming connections of at most this many instruments
+    #[serde(default = "default_instruments_per_connection")]
+    #[serde(rename = "instrumentsPerConnection")]
+    pub instruments_per_connection: usize,
+
+    // Instruments to keep streaming longest, first to last, should a connection be rejected or
+    // keep dropping and have to stream fewer. Those not listed are dropped first.
+    #[serde(default)]
+    #[serde(rename = "instrumentPriority")]
+    pub instrument_priority: Vec<String>,
+
+    // Backend to trade through, e.g. {"type": "oanda"}, instead of OANDA's portfolio builder
+    #[serde(default)]
+    pub broker: Option<BrokerConfig>,
+
+    // Address of a stream-relay to receive prices from instead of connecting to OANDA
+    #[serde(default)]
+    #[serde(rename = "relayAddress")]
+    pub relay_address: Option<String>,
+
+    // Side of the quote the models trade on, mid by default
+    #[serde(default)]
+    #[serde(rename = "priceBasis")]
+    pub price_basis: PriceBasis,
+
+    // Whether strategies see prices OANDA marks as not tradeable, skipped by default
+    #[serde(default)]
+    #[serde(rename = "nonTradeablePrices")]
+    pub non_tradeable_prices: NonTradeablePrices,
+
+    // Several strategies to run side by side instead of the single model of this config
+    #[serde(default)]
+    pub strategies: Vec<StrategyConfig>,
+
+    // How opposing signals of different strategies for the same instrument are combined
+    #[serde(default)]
+    #[serde(rename = "conflictPolicy")]
+    pub conflict_policy: ConflictPolicy,
+
+    // How long the model's signals stand when there are no "strategies", see SignalValidity
+    #[serde(default)]
+    pub validity: Option<SignalValidity>,
+
+    // Weight of each strategy's forecasts, every strategy's are taken in full by default
+    #[serde(default)]
+    pub allocation: Option<Allocation>,
+
+    // Worker threads to run the strategies' models on, on the engine's own thread without
+    #[serde(default)]
+    #[serde(rename = "strategyWorkers")]
+    pub strategy_workers: Option<StrategyWorkersConfig>,
+
+    // Where the models' internal variables are recorded for debugging, see ModelStateConfig
+    #[serde(default)]
+    #[serde(rename = "modelState")]
+    pub model_state: Option<ModelStateConfig>,
+
+    // Identifies the config in the tags of orders, see version()
+    #[serde(default)]
+    #[serde(rename = "configVersion")]
+    pub config_version: Option<String>,
+
+    // Small orders posted as limit orders rather than crossing the spread, see
+    // PassiveExecutionConfig
+    #[serde(default)]
+    #[serde(rename = "passiveExecution")]
+    pub passive_execution: Option<PassiveExecutionConfig>,
+
+    // Orders placed are recorded in this file
+    #[serde(default = "default_journal")]
+    pub journal: PathBuf,
+
+    // The strategies' state is saved here every checkpoint interval (in seconds), and restored
+    // from it on startup. A checkpoint saved by a backtest can be used to start from as well.
+    #[serde(default)]
+    pub checkpoint: Option<PathBuf>,
+
+    #[serde(default = "default_checkpoint_interval")]
+    #[serde(rename = "checkpointInterval")]
+    pub checkpoint_interval: u64,
+
+    // Signals are logged here as they're handled, to resume those in flight after a crash
+    #[serde(default)]
+    #[serde(rename = "signalLog")]
+    pub signal_log: Option<SignalLogConfig>,
+
+    // How many units a full position in each instrument is, settings.json's units by default
+    #[serde(default)]
+    #[serde(rename = "positionSizing")]
+    pub position_sizing: PositionSizing,
+
+    // Share of the full position each forecast asks for, see ForecastMapping
+    #[serde(default)]
+    #[serde(rename = "forecastMapping")]
+    pub forecast_mapping: ForecastMapping,
+
+    // How close to its target a position must be for no order to be placed
+    #[serde(default)]
+    #[serde(rename = "targetTolerance")]
+    pub target_tolerance: TargetTolerance,
+
+    // How gradually positions move to new targets, at once by default
+    #[serde(default)]
+    #[serde(rename = "targetSmoothing")]
+    pub target_smoothing: TargetSmoothing,
+
+    // Annualized volatility positions are scaled to, unscaled by default
+    #[serde(default)]
+    #[serde(rename = "volatilityTarget")]
+    pub volatility_target: Option<VolatilityTarget>,
+
+    // Leverage limits orders are checked against, live as well as in backtests and on paper
+    #[serde(default)]
+    pub margin: Option<MarginConfig>,
+
+    // Round-trip cost limit live orders are checked against before they're placed
+    #[serde(default)]
+    #[serde(rename = "costGuard")]
+    pub cost_guard: Option<CostGuardConfig>,
+
+    // How order sizes are rounded to each instrument's trade unit precision
+    #[serde(default)]
+    #[serde(rename = "unitRounding")]
+    pub unit_rounding: UnitRounding,
+
+    // Halts strategies whose orders for an instrument come faster than this
+    #[serde(default)]
+    #[serde(rename = "orderRateLimit")]
+    pub order_rate_limit: Option<OrderRateLimit>,
+
+    // Daily OANDA API budget to warn about and where to log daily usage
+    #[serde(default)]
+    #[serde(rename = "apiUsage")]
+    pub api_usage: Option<UsageConfig>,
+
+    // How streamed prices are logged, see TickLogConfig
+    #[serde(default)]
+    #[serde(rename = "tickLog")]
+    pub tick_log: TickLogConfig,
+
+    // Pauses strategies whose realized losses breach these limits
+    #[serde(default)]
+    #[serde(rename = "strategyLimits")]
+    pub strategy_limits: Option<StrategyLimits>,
+
+    // Limits on each UTC day's realized loss, trades and exposure, past which only closing
+    // signals are executed
+    #[serde(default)]
+    #[serde(rename = "sessionLimits")]
+    pub session_limits: Option<SessionLimits>,
+
+    // Closing positions before the weekend close, and opening them again after the open
+    #[serde(default)]
+    pub weekend: Option<WeekendConfig>,
+
+    // Scales every position down as the account's equity falls from its peak
+    #[serde(default)]
+    #[serde(rename = "drawdownScaling")]
+    pub drawdown_scaling: Option<DrawdownScaling>,
+
+    // How the price connection's health is scored, and whether a poor score holds back orders
+    #[serde(default)]
+    #[serde(rename = "connectionHealth")]
+    pub connection_health: ConnectionHealthConfig,
+
+    // Rules alerting an operator to prices and positions, whatever the strategies are doing
+    #[serde(default)]
+    pub alerts: Option<AlertConfig>,
+
+    // Files through which an external watchdog or operator can stop all trading
+    #[serde(default)]
+    #[serde(rename = "killSwitch")]
+    pub kill_switch: Option<KillSwitchConfig>,
+
+    // No orders on a price older than this, see StalenessConfig
+    #[serde(default)]
+    #[serde(rename = "priceStaleness")]
+    pub price_staleness: Option<StalenessConfig>,
+
+    // Candles to warm the strategies up on when there's no checkpoint to restore, see
+    // BootstrapConfig
+    #[serde(default)]
+    pub bootstrap: Option<BootstrapConfig>,
+
+    // Unix socket accepting commands such as re-enabling a paused strategy
+    #[serde(default)]
+    #[serde(rename = "controlSocket")]
+    pub control_socket: Option<PathBuf>,
+
+    // gRPC service for the trader's status, commands and backtests
+    #[serde(default)]
+    pub grpc: Option<GrpcConfig>,
+
+    // Instruments whose signals aren't executed until they're enabled over the control socket
+    #[serde(default)]
+    #[serde(rename = "disabledInstruments")]
+    pub disabled_instruments: Vec<String>,
+
+    // Decisions kept per instrument for "status" queries over the control socket
+    #[serde(default = "default_decision_history")]
+    #[serde(rename = "decisionHistory")]
+    pub decision_history: usize,
+
+    // Simulated account used when trading on paper or replaying recorded prices
+    #[serde(default)]
+    pub paper: PaperConfig,
+
+    // What to do with open positions when the trader stops
+    #[serde(default)]
+    pub shutdown: ShutdownConfig,
+
+    // Candidate strategies to compare with these on paper, see ShadowConfig
+    #[serde(default)]
+    pub shadow: Option<ShadowConfig>,
+
+    #[serde(flatten)]
+    #[serde(rename = "modelConfig")]
+    pub model_config: serde_json::Value,
+}
+
+// One of several strategies of a trading config, e.g.
+// {"name": "fastEma", "model": "ema", "slowWeight": 0.01, "fastWeight": 0.1}
+#[cfg(feature = "backtest")]
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct StrategyConfig {
+    // Defaults to the model name
+    #[serde(default)]
+    pub name: Option<String>,
+    pub model: String,
+
+    // How long its signals stand, see SignalValidity
+    #[serde(default)]
+    pub validity: Option<SignalValidity>,
+
     #[serde(flatten)]
     #[serde(rename = "modelConfig")]
     pub model_config: serde_json::Value,
 }
 
+#[cfg(feature = "backtest")]
+fn default_journal() -> PathBuf {
+    PathBuf::from("journal.jsonl")
+}
+
+#[cfg(feature = "backtest")]
+fn default_decision_history() -> usize {
+    50
+}
+
+#[cfg(feature = "backtest")]
+fn default_checkpoint_interval() -> u64 {
+    60
+}
+
+#[cfg(feature = "backtest")]
+fn default_reconcile_interval() -> u64 {
+    300
+}
+
+#[cfg(feature = "backtest")]
+fn default_instruments_per_connection() -> usize {
+    20
+}
+
+#[cfg(feature = "backtest")]
 impl TradingConfig {
     pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, Box<dyn std::error::Error>> {
         log::info!("Loading config from {:?}", path.as_ref());
-        let file = File::open(path)?;
-        let reader = BufReader::new(file);
-        let config = serde_json::from_reader(reader)?;
+        let path = path.as_ref();
+        let file = File::open(path).with_context(|| format!("Opening {}", path.display()))?;
+        let mut config: Self = serde_json::from_reader(BufReader::new(file))
+            .with_context(|| format!("Parsing {}", path.display()))?;
+        config.expand_universes()?;
+        Ok(config)
+    }
+
+    // Replace the names of instrument groups in the instrument lists with their instruments
+    pub fn expand_universes(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        let universes = match &self.universes {
+            Some(path) => Universes::load(path)?,
+            None => return Ok(()),
+        };
+        self.instruments = universes.expand(&self.instruments)?;
+        self.instrument_priority = universes.expand(&self.instrument_priority)?;
+        self.disabled_instruments = universes.expand(&self.disabled_instruments)?;
+        Ok(())
+    }
+
+    // Keep the state of a run that isn't live apart from the live trader's, e.g. "paper": its
+    // journal, checkpoint, signal log and control socket get ".paper" appended, so the run
+    // neither reads nor writes the live ones
+    pub fn separate_state(&mut self, mode: &str) {
+        let suffixed = |path: &Path| {
+            let mut name = path.as_os_str().to_owned();
+            name.push(format!(".{}", mode));
+            PathBuf::from(name)
+        };
+        self.journal = suffixed(&self.journal);
+        self.checkpoint = self.checkpoint.as_deref().map(suffixed);
+        if let Some(signal_log) = &mut self.signal_log {
+            signal_log.path = suffixed(&signal_log.path);
+        }
+        self.control_socket = self.control_socket.as_deref().map(suffixed);
+    }
+
+    // The configured version, or the start of a hash of the whole config so that any change to
+    // it shows up in the orders' tags
+    pub fn version(&self) -> Result<String, Box<dyn std::error::Error>> {
+        if let Some(version) = &self.config_version {
+            return Ok(version.clone());
+        }
+        let digest = Sha256::digest(serde_json::to_vec(self)?);
+        Ok(hex::encode(&digest[..6]))
+    }
+}
+
+#[cfg(feature = "data")]
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct CollectorConfig {
+    // Instruments to collect, and names of instrument groups in the universes file. Every
+    // instrument the collector knows of if empty.
+    #[serde(default)]
+    pub instruments: Vec<String>,
+
+    // File of named instrument groups, see Universes
+    #[serde(default)]
+    pub universes: Option<PathBuf>,
+
+    // Address of a stream-relay to receive prices from instead of connecting to OANDA
+    #[serde(default)]
+    #[serde(rename = "relayAddress")]
+    pub relay_address: Option<String>,
+
+    // Instruments to keep collecting longest, first to last, should the connection be rejected
+    // or keep dropping and have to stream fewer. Those not listed are dropped first.
+    #[serde(default)]
+    #[serde(rename = "instrumentPriority")]
+    pub instrument_priority: Vec<String>,
+
+    #[serde(default)]
+    pub storage: StorageLayout,
+
+    // Daily OANDA API budget to warn about and where to log daily usage
+    #[serde(default)]
+    #[serde(rename = "apiUsage")]
+    pub api_usage: Option<UsageConfig>,
+
+    // How streamed prices are logged, see TickLogConfig
+    #[serde(default)]
+    #[serde(rename = "tickLog")]
+    pub tick_log: TickLogConfig,
+
+    // Whether the weekly pipeline compresses the weeks it archives
+    #[serde(default)]
+    #[serde(rename = "compressArchive")]
+    pub compress_archive: bool,
+
+    // Where upload_archive copies rotated raw logs and tick files to
+    #[serde(default)]
+    #[serde(rename = "archiveSinks")]
+    pub archive_sinks: Vec<ArchiveSink>,
+
+    // How long compact_data keeps each kind of file and which it compresses
+    #[serde(default)]
+    pub retention: RetentionPolicy,
+
+    // Rules alerting an operator to the prices collected. Position rules need the trader.
+    #[serde(default)]
+    pub alerts: Option<AlertConfig>,
+}
+
+#[cfg(feature = "data")]
+impl CollectorConfig {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, Box<dyn std::error::Error>> {
+        log::info!("Loading config from {:?}", path.as_ref());
+        let path = path.as_ref();
+        let file = File::open(path).with_context(|| format!("Opening {}", path.display()))?;
+        let mut config: Self = serde_json::from_reader(BufReader::new(file))
+            .with_context(|| format!("Parsing {}", path.display()))?;
+        if let Some(path) = &config.universes {
+            let universes = Universes::load(path)?;
+            config.instruments = universes.expand(&config.instruments)?;
+            config.instrument_priority = universes.expand(&config.instrument_priority)?;
+        }
         Ok(config)
     }
 }
@@ -0,0 +1,35 @@
+// Profit the simulated account realizes in a currency it has no rate for yet is held out of the
+// balance, not booked as if it were already in the account currency.
+
+use quantlib::backtest::SimulatedAccount;
+use quantlib::oanda::objects::{Price, PriceStatus};
+
+fn price(instrument: &str, mid: f64) -> Price {
+    Price {
+        instrument: instrument.to_string(),
+        time: 1_704_189_600_000,
+        nanos: 0,
+        bid: mid as f32,
+        ask: mid as f32,
+        tradeable: true,
+        status: PriceStatus::Tradeable,
+    }
+}
+
+#[test]
+fn profit_is_held_until_there_is_a_rate() {
+    let mut account = SimulatedAccount::new("USD", 10_000.0);
+    account.update_price(&price("EUR_GBP", 0.85));
+    account.market_order("EUR_GBP", 1_000.0, "signal").unwrap();
+    account.update_price(&price("EUR_GBP", 0.95));
+    let fill = account.market_order("EUR_GBP", -1_000.0, "signal").unwrap();
+
+    assert_eq!(fill.realized_pl, 0.0);
+    assert!((fill.unconverted_pl.unwrap() - 100.0).abs() < 1e-3);
+    assert_eq!(account.balance, 10_000.0);
+    assert!((account.unconverted()["GBP"] - 100.0).abs() < 1e-3);
+
+    account.update_price(&price("GBP_USD", 1.25));
+    assert!(account.unconverted().is_empty());
+    assert!((account.balance - 10_125.0).abs() < 1e-3);
+}
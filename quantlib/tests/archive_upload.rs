@@ -0,0 +1,37 @@
+// Archive uploads stream files: compressed into a staging file and copied to the sink from it,
+// skipped when the sink already has the same contents.
+
+use quantlib::data;
+use quantlib::upload::{self, ArchiveSink, UploadOutcome};
+
+#[tokio::test]
+async fn files_are_staged_compressed_and_uploaded_once() {
+    let directory = std::env::temp_dir().join(format!("archive-upload-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&directory);
+    std::fs::create_dir_all(&directory).unwrap();
+
+    let original = directory.join("raw.log");
+    let contents: Vec<u8> = (0..200_000u32).flat_map(|n| n.to_be_bytes()).collect();
+    std::fs::write(&original, &contents).unwrap();
+
+    let staged = directory.join("raw.log.sz");
+    let checksum = data::compress_file(&original, &staged).unwrap();
+    assert_eq!(checksum, upload::sha256_hex(&contents));
+    assert_eq!(data::read_uncompressed(&staged).unwrap(), contents);
+
+    let sink = ArchiveSink::Directory {
+        name: "backup".to_string(),
+        path: directory.join("backup"),
+    };
+    let key = "logs/raw.log.sz";
+    assert_eq!(
+        sink.put(key, &staged, &checksum).await.unwrap(),
+        UploadOutcome::Uploaded
+    );
+    assert_eq!(
+        sink.put(key, &staged, &checksum).await.unwrap(),
+        UploadOutcome::Unchanged
+    );
+    let copy = directory.join("backup").join(key);
+    assert_eq!(data::read_uncompressed(&copy).unwrap(), contents);
+}
@@ -0,0 +1,116 @@
+// The engine's event queue: events come out by priority, and in the order they were queued within
+// a priority, so a simulation acts on the same events in the same order every time it's run.
+
+use quantlib::control::ControlCommand;
+use quantlib::engine::{EngineEvent, EventQueue, Timer};
+use quantlib::oanda::objects::{Price, PriceStatus};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+fn price(time: u64) -> Price {
+    Price {
+        bid: 1.1,
+        ask: 1.1002,
+        time,
+        nanos: 0,
+        instrument: "EUR_USD".to_string(),
+        tradeable: true,
+        status: PriceStatus::Tradeable,
+    }
+}
+
+// What an event is, identifying those that carry an id
+fn label(event: &EngineEvent) -> String {
+    match event {
+        EngineEvent::Control(ControlCommand::Pause(id)) => format!("control {}", id),
+        EngineEvent::Timer(timer) => format!("timer {:?}", timer),
+        EngineEvent::Price(price) => format!("price {}", price.time),
+        EngineEvent::StreamError(id) => format!("error {}", id),
+        EngineEvent::Heartbeat => "heartbeat".to_string(),
+        _ => "other".to_string(),
+    }
+}
+
+fn drain(queue: &mut EventQueue) -> Vec<String> {
+    std::iter::from_fn(|| queue.pop())
+        .map(|e| label(&e))
+        .collect()
+}
+
+#[test]
+fn higher_priorities_come_first() {
+    let mut queue = EventQueue::default();
+    queue.push(EngineEvent::Price(price(1)));
+    queue.push(EngineEvent::Timer(Timer::Status));
+    queue.push(EngineEvent::Heartbeat);
+    queue.push(EngineEvent::Control(ControlCommand::Pause("a".into())));
+    queue.push(EngineEvent::Timer(Timer::Checkpoint));
+    queue.push(EngineEvent::Price(price(2)));
+
+    assert_eq!(
+        drain(&mut queue),
+        [
+            "control a",
+            "timer Status",
+            "timer Checkpoint",
+            "price 1",
+            "heartbeat",
+            "price 2"
+        ]
+    );
+    assert!(queue.is_empty());
+}
+
+#[test]
+fn same_priority_keeps_the_order_queued() {
+    let mut queue = EventQueue::default();
+    for time in 0..1000 {
+        queue.push(EngineEvent::Price(price(time)));
+    }
+    let expected: Vec<String> = (0..1000).map(|time| format!("price {}", time)).collect();
+    assert_eq!(drain(&mut queue), expected);
+}
+
+// Random pushes and pops, the same every run: whatever was queued comes out sorted by priority
+// and then by when it was queued, whatever the heap does inside
+#[test]
+fn order_only_depends_on_what_was_queued() {
+    let run = |seed: u64| {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let mut queue = EventQueue::default();
+        let mut popped = Vec::new();
+        for id in 0..5000u64 {
+            let event = match rng.gen_range(0..3) {
+                0 => EngineEvent::Control(ControlCommand::Pause(id.to_string())),
+                1 => EngineEvent::StreamError(id.to_string()),
+                _ => EngineEvent::Price(price(id)),
+            };
+            queue.push(event);
+            if rng.gen::<f64>() < 0.3 {
+                popped.extend(queue.pop().map(|e| (e.priority(), label(&e))));
+            }
+        }
+        popped.extend(std::iter::from_fn(|| queue.pop()).map(|e| (e.priority(), label(&e))));
+        popped
+    };
+
+    let first = run(7);
+    assert_eq!(first, run(7));
+    assert_eq!(first.len(), 5000);
+
+    // Drained in one go, the queue is fully sorted
+    let mut rng = StdRng::seed_from_u64(11);
+    let mut queue = EventQueue::default();
+    let mut queued = Vec::new();
+    for id in 0..2000u64 {
+        let event = match rng.gen_range(0..2) {
+            0 => EngineEvent::Control(ControlCommand::Pause(id.to_string())),
+            _ => EngineEvent::Price(price(id)),
+        };
+        queued.push((event.priority(), id, label(&event)));
+        queue.push(event);
+    }
+    queued.sort_by_key(|(priority, id, _)| (*priority, *id));
+    let expected: Vec<String> = queued.into_iter().map(|(_, _, label)| label).collect();
+    assert_eq!(drain(&mut queue), expected);
+}
@@ -0,0 +1,221 @@
+// The FIX order router: messages encode and parse back, arrive split or run together over the
+// wire, and a session against a fake counterparty places orders, answers resend requests with a
+// gap fill and asks again for what it missed, keeping its sequence numbers on disk.
+
+use std::path::PathBuf;
+
+use quantlib::broker::{take_message, FixMessage, FixOrderRouter, FixSettings, SequenceNumbers};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+const SENDING_TIME: &str = "20240102-13:45:01.123";
+
+fn order() -> FixMessage {
+    FixMessage::new("D")
+        .with(11, "1704203101123-0")
+        .with(55, "EUR/USD")
+        .with(54, 1)
+        .with(38, 1000)
+}
+
+// A message as raw bytes, with the checksum worked out over the bytes as they are
+fn raw_message(body: &[u8]) -> Vec<u8> {
+    let mut message = format!("8=FIX.4.4\x019={}\x01", body.len()).into_bytes();
+    message.extend_from_slice(body);
+    let checksum = message.iter().fold(0u32, |sum, byte| sum + *byte as u32) % 256;
+    message.extend_from_slice(format!("10={:03}\x01", checksum).as_bytes());
+    message
+}
+
+#[test]
+fn encoded_messages_parse_back() {
+    let raw = order().encode("US", "BROKER", 7, SENDING_TIME);
+    let text = String::from_utf8(raw.clone()).unwrap();
+    assert!(text.starts_with("8=FIX.4.4\x019="));
+    assert!(text.contains("\x0135=D\x0149=US\x0156=BROKER\x0134=7\x01"));
+
+    let message = FixMessage::parse(&raw).unwrap();
+    assert_eq!(message.msg_type, "D");
+    assert_eq!(message.get(34), Some("7"));
+    assert_eq!(message.get(52), Some(SENDING_TIME));
+    assert_eq!(message.get(55), Some("EUR/USD"));
+    assert_eq!(message.get(38), Some("1000"));
+
+    // BodyLength counts from after its own field up to the checksum
+    let body_start = text.find("\x0135=").unwrap() + 1;
+    let body_end = text.rfind("10=").unwrap();
+    assert_eq!(
+        message.get(9),
+        Some((body_end - body_start).to_string().as_str())
+    );
+}
+
+#[test]
+fn bad_checksums_are_refused() {
+    let mut raw = order().encode("US", "BROKER", 7, SENDING_TIME);
+    let symbol = raw.windows(3).position(|window| window == b"EUR").unwrap();
+    raw[symbol] = b'G';
+    assert!(FixMessage::parse(&raw).unwrap_err().contains("checksum"));
+
+    let without = raw_message(b"35=0\x0134=1\x01");
+    let without = &without[..without.len() - "10=000\x01".len()];
+    assert!(FixMessage::parse(without).is_err());
+}
+
+#[test]
+fn checksums_are_found_in_bytes_that_arent_utf8() {
+    // Each invalid byte becomes a three byte replacement character in a lossy copy, which would
+    // put the checksum somewhere else
+    let raw = raw_message(b"35=8\x0134=2\x0158=\xff\xfe rejected\x01");
+    let message = FixMessage::parse(&raw).unwrap();
+    assert_eq!(message.msg_type, "8");
+    assert_eq!(message.get(34), Some("2"));
+}
+
+#[test]
+fn messages_are_taken_whole_off_the_buffer() {
+    let first = order().encode("US", "BROKER", 1, SENDING_TIME);
+    let second = FixMessage::new("0").encode("US", "BROKER", 2, SENDING_TIME);
+
+    // Split anywhere, nothing is taken until the whole message is there
+    for split in [0, 5, 12, first.len() - 1] {
+        let mut buffer = first[..split].to_vec();
+        assert_eq!(take_message(&mut buffer), Ok(None));
+        assert_eq!(buffer.len(), split);
+        buffer.extend_from_slice(&first[split..]);
+        assert_eq!(take_message(&mut buffer), Ok(Some(first.clone())));
+        assert!(buffer.is_empty());
+    }
+
+    // Run together, they come off one at a time
+    let mut buffer = [first.clone(), second.clone(), second[..4].to_vec()].concat();
+    assert_eq!(take_message(&mut buffer), Ok(Some(first)));
+    assert_eq!(take_message(&mut buffer), Ok(Some(second.clone())));
+    assert_eq!(take_message(&mut buffer), Ok(None));
+    assert_eq!(buffer, second[..4].to_vec());
+
+    let mut garbled = b"8=FIX.4.4\x019=abc\x0135=0\x01".to_vec();
+    assert!(take_message(&mut garbled).is_err());
+}
+
+// The broker's end of the session
+struct Counterparty {
+    stream: TcpStream,
+    buffer: Vec<u8>,
+}
+
+impl Counterparty {
+    async fn receive(&mut self) -> FixMessage {
+        let mut chunk = [0u8; 4096];
+        loop {
+            if let Some(raw) = take_message(&mut self.buffer).unwrap() {
+                return FixMessage::parse(&raw).unwrap();
+            }
+            let read = self.stream.read(&mut chunk).await.unwrap();
+            assert!(read > 0, "The session closed the connection");
+            self.buffer.extend_from_slice(&chunk[..read]);
+        }
+    }
+
+    async fn send(&mut self, message: FixMessage, seq: u64) {
+        let raw = message.encode("BROKER", "US", seq, SENDING_TIME);
+        self.stream.write_all(&raw).await.unwrap();
+    }
+}
+
+fn sequence_store() -> PathBuf {
+    let path = std::env::temp_dir().join(format!("fix-session-{}.json", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    path
+}
+
+#[tokio::test]
+async fn session_places_orders_and_keeps_its_sequences() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let store = sequence_store();
+    let settings: FixSettings = serde_json::from_value(serde_json::json!({
+        "host": "127.0.0.1",
+        "port": listener.local_addr().unwrap().port(),
+        "senderCompId": "US",
+        "targetCompId": "BROKER",
+        "tls": false,
+        "sequenceStore": store,
+    }))
+    .unwrap();
+    let router = FixOrderRouter::new(&settings);
+
+    let (stream, _) = listener.accept().await.unwrap();
+    let mut broker = Counterparty {
+        stream,
+        buffer: Vec::new(),
+    };
+    let logon = broker.receive().await;
+    assert_eq!((logon.msg_type.as_str(), logon.get(34)), ("A", Some("1")));
+    broker
+        .send(FixMessage::new("A").with(98, 0).with(108, 30), 1)
+        .await;
+
+    // A market order fills in two parts
+    let counterparty = async {
+        let order = broker.receive().await;
+        assert_eq!(order.msg_type, "D");
+        assert_eq!(order.get(34), Some("2"));
+        assert_eq!(order.get(55), Some("EUR/USD"));
+        assert_eq!(order.get(54), Some("2"));
+        assert_eq!(order.get(38), Some("1000"));
+        let cl_ord_id = order.get(11).unwrap().to_string();
+        for (seq, status, quantity, price) in [(2, "1", 600, 1.1), (3, "2", 400, 1.2)] {
+            let report = FixMessage::new("8")
+                .with(11, &cl_ord_id)
+                .with(37, "ORDER-1")
+                .with(150, "F")
+                .with(39, status)
+                .with(32, quantity)
+                .with(31, price);
+            broker.send(report, seq).await;
+        }
+    };
+    let (fill, ()) = tokio::join!(router.market_order("EUR_USD", -1000.0), counterparty);
+    let fill = fill.unwrap().expect("The order filled");
+    assert_eq!(fill.units, -1000.0);
+    assert!((fill.price.unwrap() - 1.14).abs() < 1e-9);
+    assert_eq!(fill.id.as_deref(), Some("ORDER-1"));
+
+    // Messages the counterparty missed are skipped with a gap fill rather than sent again
+    broker
+        .send(FixMessage::new("2").with(7, 1).with(16, 0), 4)
+        .await;
+    let gap_fill = broker.receive().await;
+    assert_eq!(gap_fill.msg_type, "4");
+    assert_eq!(gap_fill.get(34), Some("1"));
+    assert_eq!(gap_fill.get(123), Some("Y"));
+    assert_eq!(gap_fill.get(36), Some("3"));
+
+    // Messages we missed are asked for again
+    broker.send(FixMessage::new("0"), 8).await;
+    let resend = broker.receive().await;
+    assert_eq!(resend.msg_type, "2");
+    assert_eq!(resend.get(34), Some("3"));
+    assert_eq!(resend.get(7), Some("5"));
+
+    // Once they're skipped with a gap fill, the session carries on from after them
+    broker
+        .send(FixMessage::new("4").with(123, "Y").with(36, 9), 5)
+        .await;
+    broker.send(FixMessage::new("1").with(112, "ping"), 9).await;
+    let heartbeat = broker.receive().await;
+    assert_eq!(heartbeat.msg_type, "0");
+    assert_eq!(heartbeat.get(34), Some("4"));
+    assert_eq!(heartbeat.get(112), Some("ping"));
+
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+    assert_eq!(
+        SequenceNumbers::load(&store).unwrap(),
+        SequenceNumbers {
+            outgoing: 5,
+            incoming: 10,
+        }
+    );
+    drop(router);
+    let _ = std::fs::remove_file(&store);
+}
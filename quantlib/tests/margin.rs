@@ -0,0 +1,52 @@
+// Margin limits on the simulated account: an order adding margin over the limit is refused, but a
+// flip over it still closes the position.
+
+use quantlib::backtest::SimulatedAccount;
+use quantlib::models::MarginConfig;
+use quantlib::oanda::objects::{Price, PriceStatus};
+
+fn price(instrument: &str, mid: f64) -> Price {
+    Price {
+        instrument: instrument.to_string(),
+        time: 1_704_189_600_000,
+        nanos: 0,
+        bid: mid as f32,
+        ask: mid as f32,
+        tradeable: true,
+        status: PriceStatus::Tradeable,
+    }
+}
+
+fn account() -> SimulatedAccount {
+    let margin = MarginConfig {
+        leverage: 1.0,
+        max_utilization: 0.5,
+        ..MarginConfig::default()
+    };
+    let mut account = SimulatedAccount::new("USD", 10_000.0).with_margin(Some(margin));
+    account.update_price(&price("EUR_USD", 1.0));
+    account
+}
+
+#[test]
+fn a_flip_over_the_limit_only_closes() {
+    let mut account = account();
+    assert!(account.market_order("EUR_USD", 4_000.0, "signal").is_some());
+    assert!(account.market_order("EUR_USD", 2_000.0, "signal").is_none());
+
+    let fill = account
+        .market_order("EUR_USD", -12_000.0, "signal")
+        .unwrap();
+    assert_eq!(fill.units, -4_000.0);
+    assert_eq!(account.units("EUR_USD"), 0.0);
+    assert_eq!(account.refused_orders, 2);
+}
+
+#[test]
+fn unknown_margin_refuses_new_positions() {
+    let mut account = account();
+    // Nothing converts CHF into USD yet
+    account.update_price(&price("CHF_JPY", 170.0));
+    assert!(account.market_order("CHF_JPY", 1_000.0, "signal").is_none());
+    assert_eq!(account.refused_orders, 1);
+}
@@ -0,0 +1,73 @@
+// Passive execution in backtests: small orders are posted at the near side of the spread and
+// fill there once the market trades through them, or go to the market once they time out.
+
+use quantlib::backtest::{BacktestConfig, BacktestReport, Backtester};
+use quantlib::oanda::objects::{Price, PriceStatus};
+
+const START: u64 = 1_704_189_600_000; // 2024-01-02 10:00
+
+fn price(time: u64, mid: f64) -> Price {
+    Price {
+        instrument: "EUR_USD".to_string(),
+        time,
+        nanos: 0,
+        bid: (mid - 0.00005) as f32,
+        ask: (mid + 0.00005) as f32,
+        tradeable: true,
+        status: PriceStatus::Tradeable,
+    }
+}
+
+// A dip and then a rise that holds, which the EMA crossover signals long on, with a price every
+// 10 seconds for two minutes after it at `after`
+fn one_signal(after: f64) -> Vec<Price> {
+    let mut prices = vec![price(START, 1.1), price(START + 10_000, 1.09)];
+    prices.push(price(START + 20_000, 1.12));
+    for step in 3..15 {
+        prices.push(price(START + step * 10_000, after));
+    }
+    prices
+}
+
+fn run(prices: Vec<Price>) -> BacktestReport {
+    let config = serde_json::json!({
+        "strategy": {
+            "instruments": ["EUR_USD"],
+            "model": "ema",
+            "slowWeight": 0.01,
+            "fastWeight": 0.5,
+            "passiveExecution": {"maxUnits": 20000, "timeout": 60}
+        },
+        "units": 10000
+    });
+    let config: BacktestConfig = serde_json::from_value(config).unwrap();
+    Backtester::new(config).unwrap().run(prices).unwrap()
+}
+
+#[test]
+fn posted_order_fills_at_its_price() {
+    // The market comes down a spread, through the bid the order was posted at
+    let report = run(one_signal(1.1199));
+    let passive = report.metrics.passive.unwrap();
+    assert_eq!((passive.posted, passive.filled), (1, 1));
+
+    let fill = &report.fills[0];
+    assert_eq!(fill.reason, "passive");
+    assert_eq!(fill.units, 10_000.0);
+    assert!(
+        (fill.price - 1.11995).abs() < 1e-6,
+        "Filled at {}",
+        fill.price
+    );
+}
+
+#[test]
+fn posted_order_falls_back_to_the_market() {
+    let report = run(one_signal(1.12));
+    let passive = report.metrics.passive.unwrap();
+    assert_eq!((passive.posted, passive.market_fallbacks), (1, 1));
+
+    let fill = &report.fills[0];
+    assert_eq!(fill.reason, "passiveFallback");
+    assert_eq!(fill.units, 10_000.0);
+}
@@ -0,0 +1,66 @@
+// Rebuilding the account from the journal: positions held before it starts count, exposure is
+// notional, and profit there's no rate for yet is held out of the balance rather than booked as is.
+
+use quantlib::backtest::{reconstruct, JournalFill, PositionSnapshot};
+use quantlib::oanda::objects::{Price, PriceStatus};
+
+const START: u64 = 1_704_189_600_000; // 2024-01-02 10:00
+
+fn price(instrument: &str, time: u64, mid: f64) -> Price {
+    Price {
+        instrument: instrument.to_string(),
+        time,
+        nanos: 0,
+        bid: mid as f32,
+        ask: mid as f32,
+        tradeable: true,
+        status: PriceStatus::Tradeable,
+    }
+}
+
+fn fill(instrument: &str, time: u64, units: f64, price: f64) -> JournalFill {
+    JournalFill {
+        time,
+        instrument: instrument.to_string(),
+        units,
+        price: Some(price),
+        pl: None,
+        transaction_id: None,
+        external: false,
+    }
+}
+
+#[test]
+fn starting_positions_are_held_and_valued_at_notional() {
+    let start = [PositionSnapshot::held("EUR_USD", 1_000.0, 1.0)];
+    let prices = vec![price("EUR_USD", START, 1.5)];
+    let snapshots = reconstruct(&[], prices, &[START], "USD", 10_000.0, &start);
+    let snapshot = &snapshots[0];
+    assert_eq!(snapshot.positions.len(), 1);
+    assert_eq!(snapshot.unrealized_pl, 500.0);
+    assert_eq!(snapshot.exposure, 1_500.0);
+}
+
+#[test]
+fn profit_without_a_rate_is_held_until_there_is_one() {
+    let fills = [
+        fill("EUR_GBP", START, 1_000.0, 0.85),
+        fill("EUR_GBP", START + 1_000, -1_000.0, 0.95),
+    ];
+    let prices = vec![price("GBP_USD", START + 2_000, 1.25)];
+    let snapshots = reconstruct(
+        &fills,
+        prices,
+        &[START + 1_000, START + 2_000],
+        "USD",
+        10_000.0,
+        &[],
+    );
+
+    // 100 GBP realized, and nothing converts GBP into USD yet
+    assert_eq!(snapshots[0].balance, 10_000.0);
+    assert!((snapshots[0].unconverted["GBP"] - 100.0).abs() < 1e-6);
+
+    assert!(snapshots[1].unconverted.is_empty());
+    assert!((snapshots[1].balance - 10_125.0).abs() < 1e-6);
+}
@@ -0,0 +1,56 @@
+// The session's exposure limit: a signal is checked by the notional it would leave held in the
+// account currency, not by the units held before it.
+
+use quantlib::engine::{Execution, TradingEngine};
+use quantlib::oanda::objects::{Price, PriceStatus};
+use quantlib::util::TradingConfig;
+
+const START: u64 = 1_704_189_600_000; // 2024-01-02 10:00
+
+fn price(time: u64, mid: f64) -> Price {
+    Price {
+        instrument: "EUR_USD".to_string(),
+        time,
+        nanos: 0,
+        bid: (mid - 0.00005) as f32,
+        ask: (mid + 0.00005) as f32,
+        tradeable: true,
+        status: PriceStatus::Tradeable,
+    }
+}
+
+// A dip and then a rise that holds, which the EMA crossover signals long on
+fn one_signal() -> Vec<Price> {
+    let mut prices = vec![price(START, 1.1), price(START + 10_000, 1.09)];
+    for step in 2..10 {
+        prices.push(price(START + step * 10_000, 1.12));
+    }
+    prices
+}
+
+async fn final_units(max_exposure: f64) -> f64 {
+    let config = serde_json::json!({
+        "instruments": ["EUR_USD"],
+        "model": "ema",
+        "slowWeight": 0.01,
+        "fastWeight": 0.5,
+        "sessionLimits": {"maxExposure": max_exposure},
+        "journal": std::env::temp_dir()
+            .join(format!("session-{}-{}.jsonl", max_exposure, std::process::id()))
+    });
+    let config: TradingConfig = serde_json::from_value(config).unwrap();
+    let _ = std::fs::remove_file(&config.journal);
+    let mut engine = TradingEngine::simulation(config, one_signal(), 10_000.0).unwrap();
+    engine.run().await.unwrap();
+    match engine.execution() {
+        Execution::Paper(paper) => paper.account().units("EUR_USD"),
+        Execution::Live { .. } | Execution::Broker(_) => unreachable!(),
+    }
+}
+
+#[tokio::test]
+async fn max_exposure_checks_the_projected_notional() {
+    // 10,000 EUR is worth more than 10,000 USD, so the position would be over the limit
+    assert_eq!(final_units(10_500.0).await, 0.0);
+    assert_eq!(final_units(12_000.0).await, 10_000.0);
+}
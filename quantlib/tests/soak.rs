@@ -0,0 +1,244 @@
+// Soak tests: the whole trading engine (strategies, risk checks, journal, paper execution) run on
+// months of prices at full speed with a simulated clock, checking invariants that must hold
+// however the market moves.
+
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::path::PathBuf;
+
+use quantlib::engine::{Execution, TradingEngine};
+use quantlib::journal::{read_journal, JournalEntry};
+use quantlib::oanda::objects::{Price, PriceStatus};
+use quantlib::util::TradingConfig;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+const START: u64 = 1_704_067_200_000; // 2024-01-01
+const DAY: u64 = 86_400_000;
+
+// A random walk with changing volatility for each instrument, a tick every 30 seconds, in order
+fn prices(instruments: &[(&str, f64)], days: u64, seed: u64) -> Vec<Price> {
+    let mut rng = StdRng::seed_from_u64(seed);
+    let mut mids: Vec<f64> = instruments.iter().map(|(_, mid)| *mid).collect();
+    let mut prices = Vec::new();
+    let mut volatility = 0.0001;
+    for time in (START..START + days * DAY).step_by(30_000) {
+        if rng.gen::<f64>() < 0.001 {
+            volatility = rng.gen_range(0.00002..0.0005);
+        }
+        for ((instrument, _), mid) in instruments.iter().zip(&mut mids) {
+            *mid *= 1.0 + rng.gen_range(-1.0..1.0) * volatility;
+            let spread = *mid * 0.00005;
+            prices.push(Price {
+                instrument: instrument.to_string(),
+                time,
+                nanos: 0,
+                bid: (*mid - spread / 2.0) as f32,
+                ask: (*mid + spread / 2.0) as f32,
+                tradeable: true,
+                status: PriceStatus::Tradeable,
+            });
+        }
+    }
+    prices
+}
+
+fn config(name: &str, mut config: serde_json::Value) -> (TradingConfig, PathBuf) {
+    let directory = std::env::temp_dir().join(format!("soak-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&directory);
+    std::fs::create_dir_all(&directory).unwrap();
+    let journal = directory.join("journal.jsonl");
+    config["journal"] = journal.to_str().unwrap().into();
+    config["checkpoint"] = directory.join("checkpoint.json").to_str().unwrap().into();
+    (serde_json::from_value(config).unwrap(), journal)
+}
+
+async fn simulate(config: TradingConfig, prices: Vec<Price>) {
+    let mut engine = TradingEngine::simulation(config, prices, 10_000.0).unwrap();
+    engine.run().await.unwrap();
+    match engine.execution() {
+        Execution::Paper(paper) => {
+            assert!(paper.account().balance > 0.0, "Balance went negative");
+            assert!(paper.account().nav() > 0.0, "NAV went negative");
+        }
+        Execution::Live { .. } | Execution::Broker(_) => unreachable!(),
+    }
+}
+
+fn time(entry: &JournalEntry) -> String {
+    match entry {
+        JournalEntry::Order { time, .. }
+        | JournalEntry::External { time, .. }
+        | JournalEntry::Cost { time, .. }
+        | JournalEntry::PassiveOrder { time, .. }
+        | JournalEntry::Paused { time, .. }
+        | JournalEntry::Enabled { time, .. }
+        | JournalEntry::InstrumentDisabled { time, .. }
+        | JournalEntry::InstrumentEnabled { time, .. }
+        | JournalEntry::Activated { time, .. }
+        | JournalEntry::DrawdownScale { time, .. }
+        | JournalEntry::KillSwitch { time, .. }
+        | JournalEntry::DailySummary { time, .. }
+        | JournalEntry::AccountReport { time, .. }
+        | JournalEntry::SignalExpired { time, .. }
+        | JournalEntry::WeekendFlatten { time, .. }
+        | JournalEntry::DroppedTicks { time, .. }
+        | JournalEntry::Decision { time, .. } => time.clone(),
+        JournalEntry::CircuitBreaker { time, .. } => quantlib::engine::format_time(*time),
+    }
+}
+
+// Journal entries are written in simulated time order, and no halted strategy ever trades again
+fn check_journal(entries: &[JournalEntry]) {
+    let times: Vec<chrono::DateTime<chrono::FixedOffset>> = entries
+        .iter()
+        .map(|entry| chrono::DateTime::parse_from_rfc3339(&time(entry)).unwrap())
+        .collect();
+    assert!(
+        times.windows(2).all(|pair| pair[0] <= pair[1]),
+        "Journal out of order"
+    );
+
+    let mut halted = HashSet::new();
+    for entry in entries {
+        match entry {
+            JournalEntry::Order { strategies, .. } => {
+                for strategy in strategies {
+                    assert!(
+                        !halted.contains(strategy),
+                        "{} traded while halted",
+                        strategy
+                    );
+                }
+            }
+            JournalEntry::Paused { strategy, .. } => {
+                halted.insert(strategy.clone());
+            }
+            JournalEntry::CircuitBreaker { strategies, .. } => halted.extend(strategies.clone()),
+            JournalEntry::Enabled { strategy, .. } => {
+                halted.remove(strategy);
+            }
+            JournalEntry::External { .. }
+            | JournalEntry::Cost { .. }
+            | JournalEntry::PassiveOrder { .. }
+            | JournalEntry::InstrumentDisabled { .. }
+            | JournalEntry::InstrumentEnabled { .. }
+            | JournalEntry::Activated { .. }
+            | JournalEntry::DrawdownScale { .. }
+            | JournalEntry::KillSwitch { .. }
+            | JournalEntry::DailySummary { .. }
+            | JournalEntry::AccountReport { .. }
+            | JournalEntry::SignalExpired { .. }
+            | JournalEntry::WeekendFlatten { .. }
+            | JournalEntry::DroppedTicks { .. }
+            | JournalEntry::Decision { .. } => {}
+        }
+    }
+}
+
+#[tokio::test]
+async fn three_months_of_ema_crossovers() {
+    let (config, journal) = config(
+        "ema",
+        serde_json::json!({
+            "instruments": ["EUR_USD", "GBP_USD"],
+            "model": "ema",
+            "slowWeight": 0.001,
+            "fastWeight": 0.01,
+            "shutdown": {"positions": "flatten"}
+        }),
+    );
+    let prices = prices(&[("EUR_USD", 1.10), ("GBP_USD", 1.27)], 90, 1);
+    simulate(config, prices).await;
+
+    let entries = read_journal(&journal).unwrap();
+    assert!(!entries.is_empty());
+    check_journal(&entries);
+
+    // Flattened on shutdown, so every instrument's orders net to nothing
+    let mut units: HashMap<String, f64> = HashMap::new();
+    for entry in &entries {
+        if let JournalEntry::Order {
+            instrument,
+            units: order,
+            ..
+        } = entry
+        {
+            *units.entry(instrument.clone()).or_default() += order;
+        }
+    }
+    assert!(units.values().all(|units| units.abs() < 1e-6));
+}
+
+#[tokio::test]
+async fn loss_limits_pause_strategies_for_good() {
+    let (config, journal) = config(
+        "limits",
+        serde_json::json!({
+            "instruments": ["EUR_USD"],
+            "model": "ema",
+            "strategies": [
+                {"name": "fast", "model": "ema", "slowWeight": 0.01, "fastWeight": 0.5},
+                {"name": "slow", "model": "ema", "slowWeight": 0.001, "fastWeight": 0.01}
+            ],
+            "conflictPolicy": "strongestWins",
+            "strategyLimits": {"maxLoss": 5, "windowHours": 24, "maxDrawdown": 10}
+        }),
+    );
+    simulate(config, prices(&[("EUR_USD", 1.10)], 60, 2)).await;
+
+    let entries = read_journal(&journal).unwrap();
+    check_journal(&entries);
+    assert!(
+        entries
+            .iter()
+            .any(|entry| matches!(entry, JournalEntry::Paused { .. })),
+        "Limits this tight should have paused a strategy"
+    );
+}
+
+#[tokio::test]
+async fn order_rate_limit_holds() {
+    let (config, journal) = config(
+        "rate",
+        serde_json::json!({
+            "instruments": ["EUR_USD", "USD_JPY"],
+            "model": "random",
+            "buyThreshold": 0.2,
+            "sellThreshold": 0.8,
+            "orderRateLimit": {"maxOrders": 5, "windowSeconds": 600}
+        }),
+    );
+    let prices = prices(&[("EUR_USD", 1.10), ("USD_JPY", 145.0)], 30, 3);
+    simulate(config, prices).await;
+
+    let entries = read_journal(&journal).unwrap();
+    check_journal(&entries);
+
+    // The order that trips the breaker is the only one allowed over the limit, besides the one
+    // closing what the halted strategy asked for
+    let mut windows: HashMap<String, VecDeque<i64>> = HashMap::new();
+    for entry in &entries {
+        if let JournalEntry::Order {
+            instrument, time, ..
+        } = entry
+        {
+            let time = chrono::DateTime::parse_from_rfc3339(time)
+                .unwrap()
+                .timestamp_millis();
+            let window = windows.entry(instrument.clone()).or_default();
+            window.push_back(time);
+            while window.front().is_some_and(|first| first + 600_000 <= time) {
+                window.pop_front();
+            }
+            assert!(
+                window.len() <= 7,
+                "{} orders for {} within the window",
+                window.len(),
+                instrument
+            );
+        }
+    }
+    assert!(entries
+        .iter()
+        .any(|entry| matches!(entry, JournalEntry::CircuitBreaker { .. })));
+}
@@ -0,0 +1,76 @@
+// Strategy workers: a model split by instrument across worker threads trades the same as when it
+// runs in line.
+
+use quantlib::engine::TradingEngine;
+use quantlib::journal::{read_journal, JournalEntry};
+use quantlib::oanda::objects::{Price, PriceStatus};
+use quantlib::util::TradingConfig;
+
+const START: u64 = 1_704_189_600_000; // 2024-01-02 10:00
+
+fn price(instrument: &str, time: u64, mid: f64) -> Price {
+    Price {
+        instrument: instrument.to_string(),
+        time,
+        nanos: 0,
+        bid: (mid - 0.00005) as f32,
+        ask: (mid + 0.00005) as f32,
+        tradeable: true,
+        status: PriceStatus::Tradeable,
+    }
+}
+
+// EUR_USD rises and then falls while GBP_USD falls and then rises, a price of each every second
+fn prices() -> Vec<Price> {
+    let mut prices = Vec::new();
+    for step in 0..40u64 {
+        let trend = if step < 20 { step } else { 40 - step } as f64 * 0.0005;
+        prices.push(price("EUR_USD", START + step * 1_000, 1.1 + trend));
+        prices.push(price("GBP_USD", START + step * 1_000 + 500, 1.27 - trend));
+    }
+    prices
+}
+
+async fn orders(name: &str, workers: Option<serde_json::Value>) -> Vec<(String, f64)> {
+    let journal =
+        std::env::temp_dir().join(format!("workers-{}-{}.jsonl", name, std::process::id()));
+    let _ = std::fs::remove_file(&journal);
+    let mut config = serde_json::json!({
+        "instruments": ["EUR_USD", "GBP_USD"],
+        "model": "tickMomentum",
+        "window": 5,
+        "imbalanceThreshold": 0.6,
+        "momentumThreshold": 1.0,
+        "journal": journal.to_str().unwrap()
+    });
+    if let Some(workers) = workers {
+        config["strategyWorkers"] = workers;
+    }
+    let config: TradingConfig = serde_json::from_value(config).unwrap();
+    let mut engine = TradingEngine::simulation(config, prices(), 10_000.0).unwrap();
+    engine.run().await.unwrap();
+    read_journal(&journal)
+        .unwrap()
+        .into_iter()
+        .filter_map(|entry| match entry {
+            JournalEntry::Order {
+                instrument, units, ..
+            } => Some((instrument, units)),
+            _ => None,
+        })
+        .collect()
+}
+
+#[tokio::test]
+async fn split_by_instrument_trades_as_in_line() {
+    let in_line = orders("inLine", None).await;
+    assert!(in_line
+        .iter()
+        .any(|(instrument, _)| instrument == "EUR_USD"));
+    assert!(in_line
+        .iter()
+        .any(|(instrument, _)| instrument == "GBP_USD"));
+
+    let pooled = orders("pooled", Some(serde_json::json!({"threads": 2}))).await;
+    assert_eq!(pooled, in_line);
+}
@@ -0,0 +1,207 @@
+// Recorded streams (tests/fixtures) replayed chunk by chunk through the stream parser, with the
+// recorded chunk boundaries and with every other way the same bytes could have been split.
+
+use std::path::Path;
+
+use quantlib::models::ExponentialMovingAverage;
+use quantlib::oanda::fixtures::StreamFixture;
+use quantlib::oanda::objects::{DroppedPrices, Heartbeat, Price, StreamItem, Transaction};
+use quantlib::oanda::{ParseStats, StreamParser};
+
+fn fixture(name: &str) -> StreamFixture {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/fixtures")
+        .join(name);
+    StreamFixture::load(path).unwrap()
+}
+
+fn summary(prices: &[Price]) -> Vec<(String, u64, u32, f32, f32)> {
+    prices
+        .iter()
+        .map(|price| {
+            let instrument = price.instrument.clone();
+            (instrument, price.time, price.nanos, price.bid, price.ask)
+        })
+        .collect()
+}
+
+#[test]
+fn prices_split_over_chunks() {
+    let items: Vec<StreamItem> = fixture("price_stream.json").parse().unwrap();
+    assert_eq!(items.len(), 6);
+
+    let heartbeats = items
+        .iter()
+        .filter(|item| matches!(item, StreamItem::Heartbeat(_)))
+        .count();
+    assert_eq!(heartbeats, 1);
+
+    let prices = fixture("price_stream.json").prices().unwrap();
+    let instruments: Vec<&str> = prices.iter().map(|p| p.instrument.as_str()).collect();
+    assert_eq!(
+        instruments,
+        ["EUR_USD", "GBP_USD", "EUR_USD", "USD_JPY", "EUR_USD"]
+    );
+
+    // 2024-01-02T10:00:00.123456789Z
+    assert_eq!(prices[0].time, 1_704_189_600_123);
+    assert_eq!(prices[0].nanos, 456_789);
+    assert_eq!(prices[0].bid, 1.095);
+    assert_eq!(prices[1].bid, 1.2701);
+    assert_eq!(prices[3].nanos, 1_000);
+    assert!(prices.iter().all(|price| price.is_tradeable()));
+}
+
+#[test]
+fn prices_split_anywhere() {
+    let recorded = fixture("price_stream.json");
+    let expected = summary(&recorded.prices().unwrap());
+    for size in 1..=64 {
+        let prices = recorded.rechunk(size).prices().unwrap();
+        assert_eq!(summary(&prices), expected, "Chunks of {} bytes", size);
+    }
+}
+
+#[test]
+fn transactions_split_over_chunks() {
+    let recorded = fixture("transaction_stream.json");
+    for fixture in [recorded.clone(), recorded.rechunk(1), recorded.rechunk(7)] {
+        let transactions: Vec<Transaction> = fixture.parse().unwrap();
+        assert_eq!(transactions.len(), 3);
+
+        let fills: Vec<&Transaction> = transactions
+            .iter()
+            .filter(|transaction| !transaction.is_heartbeat())
+            .collect();
+        assert_eq!(fills.len(), 1);
+        assert!(fills[0].is_order_fill());
+        assert_eq!(fills[0].units, Some(-1000.0));
+        assert_eq!(fills[0].pl, Some(1.23));
+        assert_eq!(fills[0].client_order_id.as_deref(), Some("my-order-1"));
+        assert_eq!(fills[0].trades_closed[0].realized_pl, Some(1.23));
+    }
+}
+
+#[test]
+fn ema_buys_the_reversal() {
+    let prices = fixture("eur_usd_reversal.json").prices().unwrap();
+    assert_eq!(prices.len(), 120);
+
+    let mut model = ExponentialMovingAverage::new(0.05, 0.3);
+    let mut signals = Vec::new();
+    for price in &prices {
+        if let Some(signal) = model.tick(price).unwrap() {
+            signals.push((price.time, signal.forecast));
+        }
+    }
+
+    // The rally starts at 11:02:00, the crossover comes a few ticks into it
+    assert_eq!(signals.len(), 1);
+    let (time, forecast) = signals[0];
+    assert_eq!(forecast, 1.0);
+    assert!(time > 1_704_193_320_000);
+}
+
+#[test]
+fn malformed_lines_are_skipped() {
+    let recorded = fixture("malformed_stream.json");
+    for fixture in [recorded.clone(), recorded.rechunk(1), recorded.rechunk(33)] {
+        let mut parser = StreamParser::<StreamItem>::new();
+        let items = fixture.parse_with(&mut parser).unwrap();
+        assert_eq!(items.len(), 4);
+
+        let prices: Vec<(u64, u32)> = items
+            .iter()
+            .filter_map(|item| match item {
+                StreamItem::Price(price) => Some((price.time, price.nanos)),
+                StreamItem::Backfill(_) | StreamItem::Dropped(_) | StreamItem::Heartbeat(_) => None,
+            })
+            .collect();
+        assert_eq!(
+            prices,
+            [
+                (1_704_189_600_123, 456_789),
+                (1_704_189_606_999, 999_999),
+                (1_704_189_607_000, 1_000)
+            ]
+        );
+
+        // The truncated price, the HTML page and the price with a stray comma
+        let stats = parser.stats();
+        assert_eq!(
+            stats,
+            ParseStats {
+                items: 4,
+                malformed: 3,
+                overflows: 0
+            }
+        );
+        assert_eq!(parser.buffered(), 0);
+    }
+}
+
+#[test]
+fn partial_lines_wait_for_the_rest() {
+    let line = fixture("price_stream.json").chunks.concat();
+    let first = line.find('\n').unwrap() + 1;
+
+    let mut parser = StreamParser::<StreamItem>::new();
+    let items = parser.parse(&line.as_bytes()[..first - 10]).unwrap();
+    assert!(items.is_empty());
+    assert_eq!(parser.buffered(), first - 10);
+
+    let items = parser
+        .parse(&line.as_bytes()[first - 10..first + 5])
+        .unwrap();
+    assert_eq!(items.len(), 1);
+    assert_eq!(parser.buffered(), 5);
+
+    // A reconnect drops the partial line, the next one parses from scratch
+    parser.clear();
+    let items = parser.parse(&line.as_bytes()[first..]).unwrap();
+    assert_eq!(items.len(), 5);
+    assert_eq!(parser.stats().malformed, 0);
+}
+
+#[test]
+fn overflowing_lines_are_dropped_up_to_the_next_newline() {
+    let stream = fixture("price_stream.json").chunks.concat();
+    let mut parser = StreamParser::<StreamItem>::new().with_max_buffer(512);
+
+    // A complete price, then a line that never ends within the limit
+    let first = stream.find('\n').unwrap() + 1;
+    let mut chunk = stream.as_bytes()[..first].to_vec();
+    chunk.extend(std::iter::repeat_n(b'x', 600));
+    assert!(parser.parse(&chunk).is_err());
+    assert_eq!(parser.buffered(), 0);
+    assert_eq!(parser.stats().overflows, 1);
+
+    // The price parsed before the overflow comes out with the next chunk, the rest of the long
+    // line is discarded
+    assert_eq!(parser.parse(&[b'x'; 100]).unwrap().len(), 1);
+    let mut chunk = b"xxx\n".to_vec();
+    chunk.extend(&stream.as_bytes()[first..]);
+    let items = parser.parse(&chunk).unwrap();
+    assert_eq!(items.len(), 5);
+    assert_eq!(parser.stats().malformed, 0);
+}
+
+#[test]
+fn relayed_dropped_prices_are_not_heartbeats() {
+    let dropped = StreamItem::Dropped(DroppedPrices {
+        time: "2024-01-02T10:00:00.000000000Z".to_string(),
+        dropped: [("EUR_USD".to_string(), 3)].into_iter().collect(),
+    });
+    let heartbeat = StreamItem::Heartbeat(Heartbeat {
+        time: "2024-01-02T10:00:05.000000000Z".to_string(),
+    });
+    let lines = format!("{}{}", dropped.to_json_line(), heartbeat.to_json_line());
+    let items: Vec<StreamItem> = StreamParser::new().parse(lines.as_bytes()).unwrap();
+
+    match &items[..] {
+        [StreamItem::Dropped(dropped), StreamItem::Heartbeat(_)] => {
+            assert_eq!(dropped.dropped["EUR_USD"], 3);
+        }
+        items => panic!("Parsed {:?}", items),
+    }
+}
@@ -0,0 +1,86 @@
+// Smoothed targets: a single signal is worked on the prices after it until the position has
+// reached its full target, rather than stopping wherever its own price left it.
+
+use quantlib::engine::{Execution, TradingEngine};
+use quantlib::journal::{read_journal, JournalEntry};
+use quantlib::oanda::objects::{Price, PriceStatus};
+use quantlib::util::TradingConfig;
+
+const START: u64 = 1_704_189_600_000; // 2024-01-02 10:00
+
+fn price(time: u64, mid: f64) -> Price {
+    Price {
+        instrument: "EUR_USD".to_string(),
+        time,
+        nanos: 0,
+        bid: (mid - 0.00005) as f32,
+        ask: (mid + 0.00005) as f32,
+        tradeable: true,
+        status: PriceStatus::Tradeable,
+    }
+}
+
+// A dip and then a rise that holds, which the EMA crossover signals long on exactly once, with a
+// price every 10 seconds for an hour after it
+fn one_signal() -> Vec<Price> {
+    let mut prices = vec![price(START, 1.1), price(START + 10_000, 1.09)];
+    for step in 2..362 {
+        prices.push(price(START + step * 10_000, 1.12));
+    }
+    prices
+}
+
+fn config(name: &str, smoothing: serde_json::Value) -> (TradingConfig, std::path::PathBuf) {
+    let directory = std::env::temp_dir().join(format!("smoothing-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&directory);
+    std::fs::create_dir_all(&directory).unwrap();
+    let journal = directory.join("journal.jsonl");
+    let config = serde_json::json!({
+        "instruments": ["EUR_USD"],
+        "model": "ema",
+        "slowWeight": 0.01,
+        "fastWeight": 0.5,
+        "targetSmoothing": smoothing,
+        "journal": journal.to_str().unwrap()
+    });
+    (serde_json::from_value(config).unwrap(), journal)
+}
+
+async fn final_units(config: TradingConfig) -> f64 {
+    let mut engine = TradingEngine::simulation(config, one_signal(), 10_000.0).unwrap();
+    engine.run().await.unwrap();
+    match engine.execution() {
+        Execution::Paper(paper) => paper.account().units("EUR_USD"),
+        Execution::Live { .. } | Execution::Broker(_) => unreachable!(),
+    }
+}
+
+#[tokio::test]
+async fn half_life_converges_on_a_single_signal() {
+    let (config, journal) = config(
+        "halfLife",
+        serde_json::json!({"halfLife": 120, "interval": 30}),
+    );
+    assert_eq!(final_units(config).await, 10_000.0);
+
+    // Worked there in steps, not in one order
+    let orders: Vec<f64> = read_journal(&journal)
+        .unwrap()
+        .into_iter()
+        .filter_map(|entry| match entry {
+            JournalEntry::Order { units, .. } => Some(units),
+            _ => None,
+        })
+        .collect();
+    assert!(orders.len() > 2, "Only {} orders", orders.len());
+    assert!(orders.iter().all(|units| *units > 0.0 && *units < 10_000.0));
+}
+
+#[tokio::test]
+async fn max_change_converges_on_a_single_signal() {
+    let (config, _) = config(
+        "maxChange",
+        serde_json::json!({"maxChange": 1000, "interval": 60}),
+    );
+    assert_eq!(final_units(config).await, 10_000.0);
+}
@@ -0,0 +1,34 @@
+// Instrument groups: a name that's neither a group nor an instrument is an error, not an
+// instrument nothing trades.
+
+use quantlib::universe::Universes;
+
+fn universes(name: &str, groups: serde_json::Value) -> Universes {
+    let path = std::env::temp_dir().join(format!("universes-{}-{}.json", name, std::process::id()));
+    std::fs::write(&path, groups.to_string()).unwrap();
+    Universes::load(&path).unwrap()
+}
+
+fn names(names: &[&str]) -> Vec<String> {
+    names.iter().map(|name| name.to_string()).collect()
+}
+
+#[test]
+fn groups_expand_to_their_instruments() {
+    let universes = universes(
+        "expand",
+        serde_json::json!({"majors": ["EUR_USD", "GBP_USD"], "traded": ["majors", "AUD_USD"]}),
+    );
+    let instruments = universes.expand(&names(&["traded", "EUR_USD"])).unwrap();
+    assert_eq!(instruments, names(&["EUR_USD", "GBP_USD", "AUD_USD"]));
+}
+
+#[test]
+fn misspelt_groups_are_errors() {
+    let universes = universes(
+        "misspelt",
+        serde_json::json!({"majors": ["EUR_USD"], "traded": ["mjors"]}),
+    );
+    assert!(universes.expand(&names(&["majros"])).is_err());
+    assert!(universes.expand(&names(&["traded"])).is_err());
+}
@@ -0,0 +1,315 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use quantlib::backtest::BacktestReport;
+use quantlib::engine::format_time;
+use quantlib::journal::{read_journal, JournalEntry};
+use quantlib::models::Allocation;
+
+// How strategies are weighted against each other
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum AllocationMethod {
+    // The highest ratio of mean to volatility of the combined returns, without going short any
+    // strategy
+    MeanVariance,
+
+    // Every strategy contributes the same share of the combined volatility
+    RiskParity,
+}
+
+impl std::str::FromStr for AllocationMethod {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        serde_json::from_value(serde_json::Value::String(name.to_string()))
+            .map_err(|_| format!("Unknown allocation method {}", name))
+    }
+}
+
+// Daily profit of each strategy in the account currency, by date (YYYY-MM-DD)
+#[derive(Debug, Default)]
+pub struct StrategyReturns {
+    pub returns: BTreeMap<String, BTreeMap<String, f64>>,
+}
+
+impl StrategyReturns {
+    // Realized profit of the journaled orders after their commission and financing, split evenly
+    // between the strategies that decided each order, less the financing and other costs charged
+    // on the positions they held
+    pub fn add_journal<P: AsRef<Path>>(
+        &mut self,
+        path: P,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        for entry in read_journal(path)? {
+            let (time, pl, strategies) = match entry {
+                JournalEntry::Order {
+                    time,
+                    pl,
+                    commission,
+                    financing,
+                    strategies,
+                    ..
+                } if pl.is_some() || commission.is_some() || financing.is_some() => {
+                    let pl =
+                        pl.unwrap_or(0.0) - commission.unwrap_or(0.0) + financing.unwrap_or(0.0);
+                    (time, pl, strategies)
+                }
+                JournalEntry::Cost {
+                    time,
+                    amount,
+                    strategies,
+                    ..
+                } => (time, amount, strategies),
+                _ => continue,
+            };
+            let day = time.get(..10).ok_or("Journal entry without a date")?;
+            for strategy in &strategies {
+                *self
+                    .returns
+                    .entry(strategy.clone())
+                    .or_default()
+                    .entry(day.to_string())
+                    .or_default() += pl / strategies.len() as f64;
+            }
+        }
+        Ok(())
+    }
+
+    // Change in NAV each day of a backtest of the strategy on its own
+    pub fn add_report<P: AsRef<Path>>(
+        &mut self,
+        strategy: &str,
+        path: P,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let report = BacktestReport::load_json(path)?;
+        let mut closes = BTreeMap::new();
+        for row in &report.rows {
+            closes.insert(format_time(row.time)[..10].to_string(), row.nav);
+        }
+        let returns = self.returns.entry(strategy.to_string()).or_default();
+        let mut previous = report.config.initial_balance;
+        for (day, nav) in closes {
+            *returns.entry(day).or_default() += nav - previous;
+            previous = nav;
+        }
+        Ok(())
+    }
+
+    // One row per day any strategy has returns for, one column per strategy in name order. A
+    // strategy without returns on a day made nothing that day.
+    pub fn matrix(&self) -> (Vec<String>, Vec<Vec<f64>>) {
+        let days: BTreeSet<&String> = self.returns.values().flat_map(|r| r.keys()).collect();
+        let names: Vec<String> = self.returns.keys().cloned().collect();
+        let rows = days
+            .into_iter()
+            .map(|day| {
+                self.returns
+                    .values()
+                    .map(|returns| returns.get(day).copied().unwrap_or(0.0))
+                    .collect()
+            })
+            .collect();
+        (names, rows)
+    }
+}
+
+// What the allocation was found from, to judge it by
+#[derive(Debug)]
+pub struct AllocationReport {
+    pub method: AllocationMethod,
+    pub days: usize,
+
+    // Per strategy in name order: mean and standard deviation of daily profit, weight and share
+    // of the combined variance
+    pub strategies: Vec<(String, f64, f64, f64, f64)>,
+}
+
+impl AllocationReport {
+    pub fn print(&self) {
+        println!("{:?} allocation over {} days:", self.method, self.days);
+        for (name, mean, volatility, weight, risk) in &self.strategies {
+            println!(
+                "  {:<24} mean {:>10.2}  volatility {:>10.2}  weight {:>6.3}  risk {:>6.1}%",
+                name,
+                mean,
+                volatility,
+                weight,
+                risk * 100.0
+            );
+        }
+    }
+}
+
+// Weights summing to 1 over the strategies' daily returns
+pub fn allocate(
+    returns: &StrategyReturns,
+    method: AllocationMethod,
+) -> Result<(Allocation, AllocationReport), Box<dyn std::error::Error>> {
+    let (names, rows) = returns.matrix();
+    if names.is_empty() {
+        return Err("No strategy returns to allocate between".into());
+    }
+    if rows.len() < 2 {
+        return Err("At least two days of returns are needed".into());
+    }
+
+    let means = means(&rows);
+    let covariance = covariance(&rows, &means);
+    let weights = match method {
+        AllocationMethod::MeanVariance => mean_variance(&means, &covariance)?,
+        AllocationMethod::RiskParity => risk_parity(&covariance)?,
+    };
+
+    let marginal = multiply(&covariance, &weights);
+    let variance: f64 = weights.iter().zip(&marginal).map(|(w, m)| w * m).sum();
+    let strategies = names
+        .iter()
+        .enumerate()
+        .map(|(i, name)| {
+            let risk = match variance > 0.0 {
+                true => weights[i] * marginal[i] / variance,
+                false => 0.0,
+            };
+            (
+                name.clone(),
+                means[i],
+                covariance[i][i].sqrt(),
+                weights[i],
+                risk,
+            )
+        })
+        .collect();
+    let allocation = Allocation {
+        weights: names.into_iter().zip(weights).collect::<HashMap<_, _>>(),
+    };
+    let report = AllocationReport {
+        method,
+        days: rows.len(),
+        strategies,
+    };
+    Ok((allocation, report))
+}
+
+fn means(rows: &[Vec<f64>]) -> Vec<f64> {
+    let n = rows.len() as f64;
+    (0..rows[0].len())
+        .map(|j| rows.iter().map(|row| row[j]).sum::<f64>() / n)
+        .collect()
+}
+
+fn covariance(rows: &[Vec<f64>], means: &[f64]) -> Vec<Vec<f64>> {
+    let n = rows.len() as f64;
+    let k = means.len();
+    let mut covariance = vec![vec![0.0; k]; k];
+    for row in rows {
+        for a in 0..k {
+            for b in 0..k {
+                covariance[a][b] += (row[a] - means[a]) * (row[b] - means[b]) / (n - 1.0);
+            }
+        }
+    }
+    covariance
+}
+
+fn multiply(matrix: &[Vec<f64>], vector: &[f64]) -> Vec<f64> {
+    matrix
+        .iter()
+        .map(|row| row.iter().zip(vector).map(|(a, b)| a * b).sum())
+        .collect()
+}
+
+// Long only tangency portfolio: weights proportional to the inverse covariance times the means,
+// dropping strategies that would be shorted and solving again without them
+fn mean_variance(
+    means: &[f64],
+    covariance: &[Vec<f64>],
+) -> Result<Vec<f64>, Box<dyn std::error::Error>> {
+    let mut included: Vec<usize> = (0..means.len()).filter(|i| means[*i] > 0.0).collect();
+    loop {
+        if included.is_empty() {
+            return Err("No strategy has positive mean returns".into());
+        }
+        let sub_covariance: Vec<Vec<f64>> = included
+            .iter()
+            .map(|a| included.iter().map(|b| covariance[*a][*b]).collect())
+            .collect();
+        let sub_means: Vec<f64> = included.iter().map(|i| means[*i]).collect();
+        let solution = solve(sub_covariance, sub_means)?;
+
+        if solution.iter().all(|w| *w > 0.0) {
+            let total: f64 = solution.iter().sum();
+            let mut weights = vec![0.0; means.len()];
+            for (i, w) in included.iter().zip(solution) {
+                weights[*i] = w / total;
+            }
+            return Ok(weights);
+        }
+        included = included
+            .into_iter()
+            .zip(solution)
+            .filter(|(_, w)| *w > 0.0)
+            .map(|(i, _)| i)
+            .collect();
+    }
+}
+
+// Equal risk contributions, by repeatedly moving each weight towards the average contribution
+fn risk_parity(covariance: &[Vec<f64>]) -> Result<Vec<f64>, Box<dyn std::error::Error>> {
+    let k = covariance.len();
+    if (0..k).any(|i| covariance[i][i] <= 0.0) {
+        return Err("Every strategy needs returns that vary to weight by risk".into());
+    }
+    let mut weights: Vec<f64> = (0..k).map(|i| 1.0 / covariance[i][i].sqrt()).collect();
+    for _ in 0..1000 {
+        let marginal = multiply(covariance, &weights);
+        let contributions: Vec<f64> = weights.iter().zip(&marginal).map(|(w, m)| w * m).collect();
+        let target = contributions.iter().sum::<f64>() / k as f64;
+        for i in 0..k {
+            if contributions[i] > 0.0 {
+                weights[i] *= (target / contributions[i]).sqrt();
+            }
+        }
+        let total: f64 = weights.iter().sum();
+        weights.iter_mut().for_each(|w| *w /= total);
+    }
+    Ok(weights)
+}
+
+// Solve a x = b by Gaussian elimination with partial pivoting. A little ridge is added to the
+// diagonal so that perfectly correlated strategies don't make the system singular.
+fn solve(mut a: Vec<Vec<f64>>, mut b: Vec<f64>) -> Result<Vec<f64>, Box<dyn std::error::Error>> {
+    let n = b.len();
+    let ridge = (0..n).map(|i| a[i][i]).sum::<f64>() / n as f64 * 1e-9;
+    for (i, row) in a.iter_mut().enumerate() {
+        row[i] += ridge;
+    }
+
+    for column in 0..n {
+        let pivot = (column..n)
+            .max_by(|x, y| a[*x][column].abs().total_cmp(&a[*y][column].abs()))
+            .unwrap_or(column);
+        if a[pivot][column].abs() < f64::EPSILON {
+            return Err("Strategy returns are degenerate, can't allocate between them".into());
+        }
+        a.swap(column, pivot);
+        b.swap(column, pivot);
+        let pivot_row = a[column].clone();
+        for row in column + 1..n {
+            let factor = a[row][column] / pivot_row[column];
+            for (value, pivot_value) in a[row].iter_mut().zip(&pivot_row).skip(column) {
+                *value -= factor * pivot_value;
+            }
+            b[row] -= factor * b[column];
+        }
+    }
+
+    let mut x = vec![0.0; n];
+    for row in (0..n).rev() {
+        let sum: f64 = (row + 1..n).map(|k| a[row][k] * x[k]).sum();
+        x[row] = (b[row] - sum) / a[row][row];
+    }
+    Ok(x)
+}
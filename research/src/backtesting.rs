@@ -0,0 +1,75 @@
+use quantlib::backtest::{
+    load_scenarios, BacktestCache, BacktestConfig, BacktestReport, Backtester,
+};
+
+// Run a backtest, through the cache if BACKTEST_CACHE names one
+pub fn run(config: BacktestConfig) -> Result<BacktestReport, Box<dyn std::error::Error>> {
+    if let Some(cache) = BacktestCache::from_env() {
+        return cache.run(config);
+    }
+    let _claim = config.claim_data("backtest")?;
+    let prices = config.open_prices()?;
+    Backtester::new(config)?.run(prices)
+}
+
+// Backtest a config and save the report as `output`, then each stress scenario in the
+// scenarios file separately, saved alongside as <output>_<scenario>
+pub fn run_scenarios(
+    config: BacktestConfig,
+    output: &str,
+    scenarios: Option<&str>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let report = run(config.clone())?;
+    save(&report, output)?;
+
+    if let Some(path) = scenarios {
+        for scenario in load_scenarios(path)? {
+            println!();
+            println!("Scenario: {}", scenario.name);
+            let output = format!("{}_{}", output, scenario.name);
+            let mut config = config.clone();
+            config.scenario = Some(scenario);
+            config.save_checkpoint = None;
+            config.save_state = None;
+            config.strategy.model_state = None;
+            save(&run(config)?, &output)?;
+        }
+    }
+    Ok(())
+}
+
+// Save a report as <output>.csv, <output>.json, <output>_trades.csv and <output>.html and print
+// its metrics
+pub fn save(report: &BacktestReport, output: &str) -> Result<(), Box<dyn std::error::Error>> {
+    // The CSV is for plotting, the JSON has everything else for other tools to consume
+    report.save_csv(format!("{}.csv", output))?;
+    report.save_json(format!("{}.json", output))?;
+    report.save_trades_csv(format!("{}_trades.csv", output))?;
+
+    // The HTML has the charts and metrics in one page, for reviewing and sharing the run
+    report.save_html(format!("{}.html", output))?;
+    print_metrics(report);
+    Ok(())
+}
+
+pub fn print_metrics(report: &BacktestReport) {
+    println!("Ticks: {}", report.metrics.ticks);
+    println!("Fills: {}", report.metrics.fills);
+    println!("Final NAV: {:.2}", report.metrics.final_nav);
+    println!("Total return: {:.2}%", report.metrics.total_return * 100.0);
+    println!("Max drawdown: {:.2}%", report.metrics.max_drawdown * 100.0);
+    match report.metrics.sharpe_ratio {
+        Some(sharpe_ratio) => println!("Sharpe ratio: {:.2}", sharpe_ratio),
+        None => println!("Sharpe ratio: -"),
+    }
+    println!("Spread cost: {:.2}", report.metrics.spread_cost);
+    println!(
+        "Max margin utilization: {:.2}%",
+        report.metrics.max_margin_utilization * 100.0
+    );
+    println!("Refused orders: {}", report.metrics.refused_orders);
+    println!("Unfilled orders: {}", report.metrics.unfilled_orders);
+    println!("Trades: {}", report.metrics.trades.count);
+    println!("Win rate: {:.2}%", report.metrics.trades.win_rate * 100.0);
+    println!("Average P&L: {:.2}", report.metrics.trades.average_pl);
+}
@@ -0,0 +1,33 @@
+use research::allocation::{allocate, AllocationMethod, StrategyReturns};
+
+// Weights the strategies of a trading config from their daily profits, in a journal of the
+// trader running them together or backtest reports of each one alone, and writes them as the
+// allocation to put in the trading config
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let args: Vec<String> = std::env::args().collect();
+    if args.len() < 4 {
+        eprintln!(
+            "Usage: {} <meanVariance|riskParity> <output allocation> <journal | strategy=report>...",
+            args[0]
+        );
+        std::process::exit(1);
+    }
+
+    let method: AllocationMethod = args[1].parse()?;
+    let mut returns = StrategyReturns::default();
+    for input in &args[3..] {
+        match input.split_once('=') {
+            Some((strategy, report)) => returns.add_report(strategy, report)?,
+            None => returns.add_journal(input)?,
+        }
+    }
+
+    let (allocation, report) = allocate(&returns, method)?;
+    report.print();
+    std::fs::write(&args[2], serde_json::to_string_pretty(&allocation)?)?;
+    println!(
+        "Wrote the allocation to {}, which needs a forecastMapping that sizes positions by the forecast",
+        args[2]
+    );
+    Ok(())
+}
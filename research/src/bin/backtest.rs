@@ -0,0 +1,21 @@
+use quantlib::backtest::{BacktestConfig, REPORT_SCHEMA};
+use research::backtesting::run_scenarios;
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let args: Vec<String> = std::env::args().collect();
+    if args.len() == 2 && args[1] == "--schema" {
+        println!("{}", REPORT_SCHEMA);
+        return Ok(());
+    }
+    if args.len() < 3 {
+        eprintln!(
+            "Usage: {} <backtest config> <output name> [scenarios]",
+            args[0]
+        );
+        eprintln!("       {} --schema", args[0]);
+        std::process::exit(1);
+    }
+
+    let config = BacktestConfig::load(&args[1])?;
+    run_scenarios(config, &args[2], args.get(3).map(String::as_str))
+}
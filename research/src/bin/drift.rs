@@ -0,0 +1,92 @@
+use quantlib::backtest::compare_with_journal;
+use quantlib::data::MergedReader;
+use quantlib::journal::read_journal;
+use quantlib::util::{CollectorConfig, TradingConfig};
+
+// Recorded prices run through the strategies before the compared period, so that slow averages
+// have caught up with the live trader's
+const WARM_UP: u64 = 60 * 60 * 1000;
+
+// Live decisions are journaled on the wall clock, a little after the price they were taken on
+const TOLERANCE: u64 = 5_000;
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    // Replays the last hours of recorded prices through the trader's strategies and compares the
+    // signals with the decisions in its journal. Exits with 2 if they diverge, so it can be run
+    // from cron to catch drift between the live and backtest engines:
+    // 0 * * * * drift /etc/trading.json /etc/collector.json 24 drift.json
+    let args: Vec<String> = std::env::args().collect();
+    if args.len() < 3 {
+        eprintln!(
+            "Usage: {} <trading config> <collector config> [hours] [report]",
+            args[0]
+        );
+        std::process::exit(1);
+    }
+    let config = TradingConfig::load(&args[1])?;
+    let collector = CollectorConfig::load(&args[2])?;
+    let hours: u64 = match args.get(3) {
+        Some(hours) => hours.parse()?,
+        None => 24,
+    };
+
+    let storage = collector.storage.clone().read_only();
+    let _claim = storage.claim("drift")?;
+    let to = chrono::Utc::now().timestamp_millis() as u64;
+    let from = to.saturating_sub(hours * 60 * 60 * 1000);
+
+    let journal = read_journal(&config.journal)?;
+    let report = compare_with_journal(
+        &config,
+        MergedReader::open(&storage)?,
+        &journal,
+        from.saturating_sub(WARM_UP),
+        from,
+        to,
+        TOLERANCE,
+    )?;
+
+    println!("{} to {}", report.from, report.to);
+    println!("Ticks replayed: {}", report.ticks);
+    println!(
+        "Signals: {} live, {} replayed, {} matched",
+        report.live_signals, report.replayed_signals, report.matched
+    );
+    println!("Missing live: {}", report.missing.len());
+    println!("Unexpected live: {}", report.unexpected.len());
+    println!("Different forecast: {}", report.mismatched.len());
+    println!(
+        "Taken on unrecorded prices: {}",
+        report.unrecorded_ticks.len()
+    );
+    println!("Out of order: {}", report.out_of_order);
+    println!(
+        "Dropped live: {} ticks ({:.2}% of those replayed)",
+        report.dropped_ticks.values().sum::<u64>(),
+        report.dropped_share * 100.0
+    );
+    for (instrument, count) in &report.dropped_ticks {
+        println!("  {} {}", instrument, count);
+    }
+    println!(
+        "Lag: {:.0} ms mean, {} ms max",
+        report.mean_lag, report.max_lag
+    );
+    for drift in report
+        .missing
+        .iter()
+        .chain(&report.unexpected)
+        .chain(&report.mismatched)
+        .take(20)
+    {
+        println!("  {:?}", drift);
+    }
+
+    if let Some(path) = args.get(4) {
+        report.save_json(path)?;
+    }
+    if report.has_drift() {
+        std::process::exit(2);
+    }
+    Ok(())
+}
@@ -0,0 +1,22 @@
+use research::features::{AlignedBars, FeatureConfig, FeatureMatrix};
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let args: Vec<String> = std::env::args().collect();
+    if args.len() < 2 {
+        eprintln!("Usage: {} <feature config>", args[0]);
+        std::process::exit(1);
+    }
+
+    let config = FeatureConfig::load(&args[1])?;
+    let bars = AlignedBars::load(&config)?;
+    let matrix = FeatureMatrix::build(&config, &bars);
+    matrix.save(&config.output)?;
+
+    println!(
+        "Wrote {} rows of {} features to {}",
+        matrix.times.len(),
+        matrix.columns.len(),
+        config.output.display()
+    );
+    Ok(())
+}
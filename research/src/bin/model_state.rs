@@ -0,0 +1,29 @@
+use quantlib::models::{export_model_state_csv, read_model_state};
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    // Exports an instrument's model variables from a recording (see ModelStateConfig) as CSV
+    // aligned with its prices, e.g. to plot the ema model's averages over the EUR_USD mid:
+    // model_state logs/model_state.jsonl.sz EUR_USD ema_eur.csv
+    let args: Vec<String> = std::env::args().collect();
+    if args.len() < 4 {
+        eprintln!(
+            "Usage: {} <model state recording> <instrument> <output csv>",
+            args[0]
+        );
+        std::process::exit(1);
+    }
+
+    let samples = read_model_state(&args[1], &args[2])?;
+    if samples.is_empty() {
+        eprintln!("No model state recorded for {} in {}", args[2], args[1]);
+        std::process::exit(2);
+    }
+    export_model_state_csv(&samples, &args[3])?;
+    println!(
+        "Wrote {} samples of {} to {}",
+        samples.len(),
+        args[2],
+        args[3]
+    );
+    Ok(())
+}
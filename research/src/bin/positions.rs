@@ -0,0 +1,132 @@
+use quantlib::backtest::{
+    compare_with_statement, load_positions, load_statement, reconstruct, statement_positions,
+    JournalFill,
+};
+use quantlib::data::MergedReader;
+use quantlib::journal::read_journal;
+use quantlib::util::{CollectorConfig, TradingConfig};
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    // Rebuilds what the account held and was worth at each time given, from the trader's journal
+    // and the collector's recorded prices, e.g. what was held during a spike:
+    // positions /etc/trading.json /etc/collector.json 10000 2024-03-08T13:30:00Z
+    // With --statement, the journal's fills are checked against a saved OANDA transactions
+    // response first, and it exits with 2 if they differ. Positions held before the journal
+    // starts are taken from --start (a saved snapshot of them), or else from the statement's fills
+    // before the journal's first.
+    let mut args: Vec<String> = std::env::args().collect();
+    let mut currency = "USD".to_string();
+    let mut statement = None;
+    let mut start = None;
+    while let Some(index) = args
+        .iter()
+        .position(|arg| arg == "--currency" || arg == "--statement" || arg == "--start")
+    {
+        let value = match args.get(index + 1) {
+            Some(value) => value.clone(),
+            None => usage(&args[0]),
+        };
+        match args[index].as_str() {
+            "--currency" => currency = value,
+            "--start" => start = Some(value),
+            _ => statement = Some(value),
+        }
+        args.drain(index..index + 2);
+    }
+    if args.len() < 5 {
+        usage(&args[0]);
+    }
+
+    let config = TradingConfig::load(&args[1])?;
+    let collector = CollectorConfig::load(&args[2])?;
+    let balance: f64 = args[3].parse()?;
+    let mut times = Vec::new();
+    for time in &args[4..] {
+        times.push(chrono::DateTime::parse_from_rfc3339(time)?.timestamp_millis() as u64);
+    }
+
+    let fills = JournalFill::from_journal(&read_journal(&config.journal)?)?;
+    let mut mismatched = false;
+    let mut held = match &start {
+        Some(path) => load_positions(path)?,
+        None => Vec::new(),
+    };
+    if let Some(path) = statement {
+        let transactions = load_statement(&path)?;
+        if start.is_none() {
+            let first = fills.first().map_or(u64::MAX, |fill| fill.time);
+            held = statement_positions(&transactions, first)?;
+        }
+        let mismatches = compare_with_statement(&fills, &transactions)?;
+        println!("{} differences from {}", mismatches.len(), path);
+        for mismatch in &mismatches {
+            println!(
+                "  {} {} {}: {}",
+                mismatch.time, mismatch.transaction_id, mismatch.instrument, mismatch.problem
+            );
+        }
+        println!();
+        mismatched = !mismatches.is_empty();
+    }
+
+    let storage = collector.storage.clone().read_only();
+    let _claim = storage.claim("positions")?;
+    let snapshots = reconstruct(
+        &fills,
+        MergedReader::open(&storage)?,
+        &times,
+        &currency,
+        balance,
+        &held,
+    );
+    for snapshot in &snapshots {
+        println!("{}", snapshot.time);
+        println!(
+            "Balance {:.2}, unrealized {:.2}, NAV {:.2} {}",
+            snapshot.balance, snapshot.unrealized_pl, snapshot.nav, currency
+        );
+        println!(
+            "Exposure {:.2} {} after {} fills ({} unpriced)",
+            snapshot.exposure, currency, snapshot.fills, snapshot.unpriced_fills
+        );
+        if !snapshot.unpriced.is_empty() {
+            println!("Not valued: {}", snapshot.unpriced.join(", "));
+        }
+        for (from, amount) in &snapshot.unconverted {
+            println!(
+                "Realized {:.2} {} not in the balance, no rate",
+                amount, from
+            );
+        }
+        for position in &snapshot.positions {
+            println!(
+                "  {:<10}{:>12} @ {:<12.5} bid {:<12} ask {:<12} unrealized {}",
+                position.instrument,
+                position.units,
+                position.average_price,
+                format_option(position.bid),
+                format_option(position.ask),
+                format_option(position.unrealized_pl)
+            );
+        }
+        println!();
+    }
+
+    if mismatched {
+        std::process::exit(2);
+    }
+    Ok(())
+}
+
+fn usage(program: &str) -> ! {
+    eprintln!(
+        "Usage: {} <trading config> <collector config> <starting balance> <time>... \
+         [--currency USD] [--statement <OANDA transactions>] [--start <positions snapshot>]",
+        program
+    );
+    std::process::exit(1);
+}
+
+fn format_option(value: Option<f64>) -> String {
+    value.map_or("-".to_string(), |value| format!("{:.5}", value))
+}
@@ -0,0 +1,43 @@
+use std::io::Write;
+
+use quantlib::backtest::{BacktestConfig, RegimeLabeler};
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let args: Vec<String> = std::env::args().collect();
+    if args.len() < 3 {
+        eprintln!("Usage: {} <backtest config> <output csv>", args[0]);
+        std::process::exit(1);
+    }
+
+    // Label the strategy's instruments over the backtest data, with the backtest's regime settings
+    let config = BacktestConfig::load(&args[1])?;
+    let _claim = config.claim_data("regimes")?;
+    let mut labeler = RegimeLabeler::new(config.regimes.clone());
+    for price in config.open_prices()? {
+        if config.strategy.instruments.contains(&price.instrument) {
+            labeler.tick(&price);
+        }
+    }
+    let regimes = labeler.finish();
+
+    let mut writer = std::io::BufWriter::new(std::fs::File::create(&args[2])?);
+    writeln!(
+        writer,
+        "instrument,start,end,adx,volatility,trend,volatility_bucket"
+    )?;
+    for period in regimes.periods() {
+        writeln!(
+            writer,
+            "{},{},{},{},{},{:?},{:?}",
+            period.instrument,
+            period.start,
+            period.end,
+            period.adx,
+            period.volatility,
+            period.trend,
+            period.volatility_bucket
+        )?;
+    }
+    writer.flush()?;
+    Ok(())
+}
@@ -0,0 +1,250 @@
+use std::collections::BTreeMap;
+use std::io::{BufRead, Write};
+
+use quantlib::backtest::{BacktestCache, BacktestConfig, BacktestReport, Backtester};
+use quantlib::calendar;
+use quantlib::catalog::Catalog;
+use quantlib::engine::format_time;
+
+// Exploratory analysis without recompiling: commands are read one per line from stdin, and each
+// answer ends with a line of "ok" or "error: <message>", so a notebook or script can drive the
+// REPL through a pipe as easily as someone typing at it.
+const HELP: &str = "\
+load <backtest config>     load a backtest config, replacing any changes made with set
+set <field> <value>        change a field of the loaded config, e.g. set strategy.fastWeight 0.2
+show [field]               print the loaded config, or one field of it
+run                        backtest the loaded config and print its metrics, cached in $BACKTEST_CACHE
+export <output name>       save the last report as <name>.csv, <name>.json, <name>_trades.csv
+                           and <name>.html
+weeks                      describe each instrument's weeks of data in the loaded config
+help                       print this
+quit                       exit";
+
+// A week of an instrument's data in storage without an archive
+struct WeekSummary {
+    records: u64,
+    first: u64,
+    last: u64,
+    max_gap: u64,
+}
+
+#[derive(Default)]
+struct Session {
+    config: Option<BacktestConfig>,
+    report: Option<BacktestReport>,
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let args: Vec<String> = std::env::args().collect();
+    if args.len() > 2 {
+        eprintln!("Usage: {} [backtest config]", args[0]);
+        std::process::exit(1);
+    }
+
+    let mut session = Session::default();
+    let stdout = std::io::stdout();
+    let mut out = stdout.lock();
+    if let Some(path) = args.get(1) {
+        respond(&mut out, session.handle(&format!("load {}", path)))?;
+    }
+
+    for line in std::io::stdin().lock().lines() {
+        let line = line?;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if line == "quit" || line == "exit" {
+            break;
+        }
+        respond(&mut out, session.handle(line))?;
+    }
+    Ok(())
+}
+
+fn respond<W: Write>(
+    out: &mut W,
+    result: Result<String, Box<dyn std::error::Error>>,
+) -> std::io::Result<()> {
+    match result {
+        Ok(output) => {
+            if !output.is_empty() {
+                writeln!(out, "{}", output.trim_end())?;
+            }
+            writeln!(out, "ok")?;
+        }
+        // Kept to one line, so the end of the answer is still unambiguous
+        Err(e) => writeln!(out, "error: {}", e.to_string().replace('\n', " "))?,
+    }
+    out.flush()
+}
+
+impl Session {
+    fn handle(&mut self, line: &str) -> Result<String, Box<dyn std::error::Error>> {
+        let (command, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+        let rest = rest.trim();
+        match command {
+            "load" => {
+                if rest.is_empty() {
+                    return Err("Usage: load <backtest config>".into());
+                }
+                self.config = Some(BacktestConfig::load(rest)?);
+                self.report = None;
+                Ok(format!("Loaded {}", rest))
+            }
+            "set" => {
+                let (field, value) = rest
+                    .split_once(char::is_whitespace)
+                    .ok_or("Usage: set <field> <value>")?;
+                self.set(field, value.trim())?;
+                Ok(String::new())
+            }
+            "show" => {
+                let config = serde_json::to_value(self.config()?)?;
+                let value = if rest.is_empty() {
+                    &config
+                } else {
+                    field(&config, rest).ok_or_else(|| format!("No field {}", rest))?
+                };
+                Ok(serde_json::to_string_pretty(value)?)
+            }
+            "run" => {
+                let config = self.config()?.clone();
+                let report = match BacktestCache::from_env() {
+                    Some(cache) => cache.run(config)?,
+                    None => {
+                        let _claim = config.claim_data("repl")?;
+                        let prices = config.open_prices()?;
+                        Backtester::new(config)?.run(prices)?
+                    }
+                };
+                let output = describe_report(&report);
+                self.report = Some(report);
+                Ok(output)
+            }
+            "export" => {
+                if rest.is_empty() {
+                    return Err("Usage: export <output name>".into());
+                }
+                let report = self.report.as_ref().ok_or("Nothing to export, run first")?;
+                report.save_csv(format!("{}.csv", rest))?;
+                report.save_json(format!("{}.json", rest))?;
+                report.save_trades_csv(format!("{}_trades.csv", rest))?;
+                report.save_html(format!("{}.html", rest))?;
+                Ok(format!(
+                    "Saved {}.csv, {}.json, {}_trades.csv and {}.html",
+                    rest, rest, rest, rest
+                ))
+            }
+            "weeks" => self.weeks(),
+            "help" => Ok(HELP.to_string()),
+            _ => Err(format!("Unknown command {}, try help", command).into()),
+        }
+    }
+
+    fn config(&self) -> Result<&BacktestConfig, Box<dyn std::error::Error>> {
+        self.config
+            .as_ref()
+            .ok_or_else(|| "No config loaded, load one first".into())
+    }
+
+    // Fields are named as in the config file, nested ones separated by dots. Values are JSON,
+    // anything that isn't is taken as a string.
+    fn set(&mut self, path: &str, value: &str) -> Result<(), Box<dyn std::error::Error>> {
+        let mut config = serde_json::to_value(self.config()?)?;
+        let value = serde_json::from_str(value)
+            .unwrap_or_else(|_| serde_json::Value::String(value.to_string()));
+
+        let mut current = &mut config;
+        for key in path.split('.') {
+            current = current
+                .as_object_mut()
+                .ok_or_else(|| format!("{} isn't within an object", path))?
+                .entry(key)
+                .or_insert(serde_json::Value::Null);
+        }
+        *current = value;
+
+        self.config =
+            Some(serde_json::from_value(config).map_err(|e| format!("Invalid {}: {}", path, e))?);
+        self.report = None;
+        Ok(())
+    }
+
+    // The archive's catalog has each week already, plain storage is read through once
+    fn weeks(&self) -> Result<String, Box<dyn std::error::Error>> {
+        let config = self.config()?;
+        let mut lines = vec!["instrument,week,records,first,last,maxGap,complete".to_string()];
+        if let Some(archive) = &config.archive {
+            for entry in Catalog::load(archive)?.entries {
+                let max_gap = match &entry.manifest {
+                    Some(manifest) => manifest.max_gap.to_string(),
+                    None => "-".to_string(),
+                };
+                lines.push(format!(
+                    "{},{},{},{},{},{},{}",
+                    entry.instrument,
+                    calendar::week_name(entry.year, entry.week),
+                    entry.records,
+                    format_time(entry.first),
+                    format_time(entry.last),
+                    max_gap,
+                    entry.complete
+                ));
+            }
+            return Ok(lines.join("\n"));
+        }
+
+        let _claim = config.claim_data("repl")?;
+        let mut weeks: BTreeMap<(String, i32, u32), WeekSummary> = BTreeMap::new();
+        for price in config.open_prices()? {
+            let (year, week) = calendar::trading_week(price.time);
+            let summary = weeks
+                .entry((price.instrument.clone(), year, week))
+                .or_insert(WeekSummary {
+                    records: 0,
+                    first: price.time,
+                    last: price.time,
+                    max_gap: 0,
+                });
+            summary.records += 1;
+            summary.max_gap = summary.max_gap.max(price.time - summary.last);
+            summary.last = price.time;
+        }
+        for ((instrument, year, week), summary) in weeks {
+            lines.push(format!(
+                "{},{},{},{},{},{},-",
+                instrument,
+                calendar::week_name(year, week),
+                summary.records,
+                format_time(summary.first),
+                format_time(summary.last),
+                summary.max_gap
+            ));
+        }
+        Ok(lines.join("\n"))
+    }
+}
+
+fn field<'a>(value: &'a serde_json::Value, path: &str) -> Option<&'a serde_json::Value> {
+    path.split('.').try_fold(value, |value, key| value.get(key))
+}
+
+fn describe_report(report: &BacktestReport) -> String {
+    let metrics = &report.metrics;
+    let sharpe_ratio = match metrics.sharpe_ratio {
+        Some(sharpe_ratio) => format!("{:.2}", sharpe_ratio),
+        None => "-".to_string(),
+    };
+    [
+        format!("Ticks: {}", metrics.ticks),
+        format!("Fills: {}", metrics.fills),
+        format!("Final NAV: {:.2}", metrics.final_nav),
+        format!("Total return: {:.2}%", metrics.total_return * 100.0),
+        format!("Max drawdown: {:.2}%", metrics.max_drawdown * 100.0),
+        format!("Sharpe ratio: {}", sharpe_ratio),
+        format!("Trades: {}", metrics.trades.count),
+        format!("Win rate: {:.2}%", metrics.trades.win_rate * 100.0),
+    ]
+    .join("\n")
+}
@@ -0,0 +1,337 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use arrow::array::{ArrayRef, Float64Array, UInt64Array};
+use arrow::datatypes::{DataType, Field, Schema};
+use arrow::record_batch::RecordBatch;
+use chrono::{DateTime, Datelike, Timelike, Utc};
+use parquet::arrow::ArrowWriter;
+use serde::Deserialize;
+
+use quantlib::calendar;
+use quantlib::data::{MergedReader, StorageLayout};
+
+// Describes which features to build and where to write them, e.g.
+// {
+//     "instruments": ["EUR_USD", "GBP_USD"],
+//     "barSeconds": 60,
+//     "features": [{"type": "return", "horizon": 5}, {"type": "spread"}, {"type": "timeOfDay"}],
+//     "output": "features/eur_gbp.parquet"
+// }
+#[derive(Deserialize, Debug)]
+pub struct FeatureConfig {
+    pub instruments: Vec<String>,
+
+    #[serde(default)]
+    pub storage: StorageLayout,
+
+    // Ticks are aggregated into bars of this many seconds, every row of the output is one bar
+    #[serde(default = "default_bar_seconds")]
+    #[serde(rename = "barSeconds")]
+    pub bar_seconds: u64,
+
+    pub features: Vec<Feature>,
+
+    // Written as Parquet if the extension is .parquet, CSV otherwise
+    pub output: PathBuf,
+}
+
+fn default_bar_seconds() -> u64 {
+    60
+}
+
+impl FeatureConfig {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, Box<dyn std::error::Error>> {
+        let file = File::open(path)?;
+        let reader = BufReader::new(file);
+        let config = serde_json::from_reader(reader)?;
+        Ok(config)
+    }
+}
+
+// Features are computed from the mid price and spread at the close of each bar.
+// Per instrument features get one column per instrument, named <instrument>_<feature>.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum Feature {
+    // Log return of the mid price over a number of bars
+    Return { horizon: usize },
+
+    // Mean and standard deviation of one bar log returns over a window of bars
+    RollingMean { window: usize },
+    RollingStd { window: usize },
+
+    // Spread as a fraction of the mid price
+    Spread,
+
+    // Spread relative to its mean over a window, in standard deviations
+    SpreadZScore { window: usize },
+
+    // Sine and cosine encodings of the UTC time of day and day of week, shared by all instruments
+    TimeOfDay,
+    DayOfWeek,
+}
+
+// Bar closes of every instrument on a common time grid.
+// Bars without any ticks carry the previous close forward, so all series are aligned.
+pub struct AlignedBars {
+    pub times: Vec<u64>,
+    pub mids: HashMap<String, Vec<Option<f64>>>,
+    pub spreads: HashMap<String, Vec<Option<f64>>>,
+}
+
+impl AlignedBars {
+    pub fn load(config: &FeatureConfig) -> Result<Self, Box<dyn std::error::Error>> {
+        let bar_length = config.bar_seconds.max(1) * 1000;
+
+        // Last (mid, spread) of each instrument in each bar
+        let mut closes: BTreeMap<u64, HashMap<String, (f64, f64)>> = BTreeMap::new();
+        for price in MergedReader::open(&config.storage)? {
+            if !config.instruments.contains(&price.instrument) {
+                continue;
+            }
+            let mid = (price.bid as f64 + price.ask as f64) / 2.0;
+            let spread = (price.ask - price.bid) as f64 / mid;
+            closes
+                .entry(price.time - price.time % bar_length)
+                .or_default()
+                .insert(price.instrument, (mid, spread));
+        }
+
+        // Fill in bars with no data, so that horizons are measured in time rather than in ticks
+        let times: Vec<u64> = match (closes.keys().next(), closes.keys().next_back()) {
+            (Some(first), Some(last)) => (*first..=*last).step_by(bar_length as usize).collect(),
+            _ => Vec::new(),
+        };
+
+        let mut mids = HashMap::new();
+        let mut spreads = HashMap::new();
+        for instrument in &config.instruments {
+            let mut last = None;
+            let series: Vec<Option<(f64, f64)>> = times
+                .iter()
+                .map(|time| {
+                    if let Some(close) = closes.get(time).and_then(|bar| bar.get(instrument)) {
+                        last = Some(*close);
+                    }
+                    last
+                })
+                .collect();
+            mids.insert(
+                instrument.clone(),
+                series.iter().map(|close| close.map(|c| c.0)).collect(),
+            );
+            spreads.insert(
+                instrument.clone(),
+                series.iter().map(|close| close.map(|c| c.1)).collect(),
+            );
+        }
+
+        Ok(AlignedBars {
+            times,
+            mids,
+            spreads,
+        })
+    }
+}
+
+// A column for every feature, one row per bar. Values are None until there is enough history.
+pub struct FeatureMatrix {
+    pub times: Vec<u64>,
+    pub columns: Vec<(String, Vec<Option<f64>>)>,
+}
+
+impl FeatureMatrix {
+    pub fn build(config: &FeatureConfig, bars: &AlignedBars) -> Self {
+        let mut columns = Vec::new();
+        for feature in &config.features {
+            match feature {
+                Feature::TimeOfDay => {
+                    let seconds = |time: &u64| {
+                        let time = datetime(*time);
+                        time.num_seconds_from_midnight() as f64 / 86_400.0
+                    };
+                    columns.extend(cyclical("time_of_day", &bars.times, seconds));
+                }
+                Feature::DayOfWeek => {
+                    let day = |time: &u64| {
+                        let time = datetime(*time);
+                        time.weekday().num_days_from_monday() as f64 / 7.0
+                    };
+                    columns.extend(cyclical("day_of_week", &bars.times, day));
+                }
+                _ => {
+                    for instrument in &config.instruments {
+                        let name = format!("{}_{}", instrument, feature_name(feature));
+                        let mids = &bars.mids[instrument];
+                        let spreads = &bars.spreads[instrument];
+                        columns.push((name, instrument_feature(feature, mids, spreads)));
+                    }
+                }
+            }
+        }
+
+        FeatureMatrix {
+            times: bars.times.clone(),
+            columns,
+        }
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), Box<dyn std::error::Error>> {
+        let path = path.as_ref();
+        if let Some(parent) = path.parent() {
+            if !parent.as_os_str().is_empty() {
+                std::fs::create_dir_all(parent)?;
+            }
+        }
+
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some("parquet") => self.save_parquet(path),
+            _ => self.save_csv(path),
+        }
+    }
+
+    pub fn save_csv<P: AsRef<Path>>(&self, path: P) -> Result<(), Box<dyn std::error::Error>> {
+        let mut writer = csv::Writer::from_writer(BufWriter::new(File::create(path)?));
+
+        let mut header = vec!["time".to_string()];
+        header.extend(self.columns.iter().map(|(name, _)| name.clone()));
+        writer.write_record(&header)?;
+
+        // Missing values are left empty
+        for (row, time) in self.times.iter().enumerate() {
+            let mut record = vec![time.to_string()];
+            record.extend(self.columns.iter().map(|(_, values)| match values[row] {
+                Some(value) => value.to_string(),
+                None => String::new(),
+            }));
+            writer.write_record(&record)?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+
+    pub fn save_parquet<P: AsRef<Path>>(&self, path: P) -> Result<(), Box<dyn std::error::Error>> {
+        let mut fields = vec![Field::new("time", DataType::UInt64, false)];
+        let mut arrays: Vec<ArrayRef> = vec![Arc::new(UInt64Array::from(self.times.clone()))];
+        for (name, values) in &self.columns {
+            fields.push(Field::new(name, DataType::Float64, true));
+            arrays.push(Arc::new(Float64Array::from(values.clone())));
+        }
+
+        let schema = Arc::new(Schema::new(fields));
+        let batch = RecordBatch::try_new(schema.clone(), arrays)?;
+
+        let mut writer = ArrowWriter::try_new(File::create(path)?, schema, None)?;
+        writer.write(&batch)?;
+        writer.close()?;
+        Ok(())
+    }
+}
+
+fn feature_name(feature: &Feature) -> String {
+    match feature {
+        Feature::Return { horizon } => format!("return_{}", horizon),
+        Feature::RollingMean { window } => format!("mean_{}", window),
+        Feature::RollingStd { window } => format!("std_{}", window),
+        Feature::Spread => "spread".to_string(),
+        Feature::SpreadZScore { window } => format!("spread_z_{}", window),
+        Feature::TimeOfDay => "time_of_day".to_string(),
+        Feature::DayOfWeek => "day_of_week".to_string(),
+    }
+}
+
+fn instrument_feature(
+    feature: &Feature,
+    mids: &[Option<f64>],
+    spreads: &[Option<f64>],
+) -> Vec<Option<f64>> {
+    match feature {
+        Feature::Return { horizon } => log_returns(mids, *horizon),
+        Feature::RollingMean { window } => rolling(&log_returns(mids, 1), *window, mean),
+        Feature::RollingStd { window } => rolling(&log_returns(mids, 1), *window, std),
+        Feature::Spread => spreads.to_vec(),
+        Feature::SpreadZScore { window } => {
+            let means = rolling(spreads, *window, mean);
+            let stds = rolling(spreads, *window, std);
+            (0..spreads.len())
+                .map(|i| match (spreads[i], means[i], stds[i]) {
+                    (Some(spread), Some(mean), Some(std)) if std > 0.0 => {
+                        Some((spread - mean) / std)
+                    }
+                    _ => None,
+                })
+                .collect()
+        }
+        Feature::TimeOfDay | Feature::DayOfWeek => vec![None; mids.len()],
+    }
+}
+
+fn log_returns(mids: &[Option<f64>], horizon: usize) -> Vec<Option<f64>> {
+    (0..mids.len())
+        .map(|i| {
+            let start = mids.get(i.checked_sub(horizon.max(1))?)?.as_ref()?;
+            let end = mids[i]?;
+            Some((end / start).ln())
+        })
+        .collect()
+}
+
+// Apply a statistic to each full window of values ending at each row
+fn rolling(
+    values: &[Option<f64>],
+    window: usize,
+    statistic: fn(&[f64]) -> f64,
+) -> Vec<Option<f64>> {
+    let window = window.max(1);
+    (0..values.len())
+        .map(|i| {
+            let start = (i + 1).checked_sub(window)?;
+            let window: Option<Vec<f64>> = values[start..=i].iter().copied().collect();
+            window.map(|window| statistic(&window))
+        })
+        .collect()
+}
+
+fn mean(values: &[f64]) -> f64 {
+    values.iter().sum::<f64>() / values.len() as f64
+}
+
+fn std(values: &[f64]) -> f64 {
+    if values.len() < 2 {
+        return 0.0;
+    }
+    let mean = mean(values);
+    let variance =
+        values.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / (values.len() - 1) as f64;
+    variance.sqrt()
+}
+
+// Encode a value in [0, 1) that wraps around as a point on the unit circle
+fn cyclical(
+    name: &str,
+    times: &[u64],
+    fraction: impl Fn(&u64) -> f64,
+) -> Vec<(String, Vec<Option<f64>>)> {
+    let angles: Vec<f64> = times
+        .iter()
+        .map(|time| fraction(time) * 2.0 * std::f64::consts::PI)
+        .collect();
+    vec![
+        (
+            format!("{}_sin", name),
+            angles.iter().map(|a| Some(a.sin())).collect(),
+        ),
+        (
+            format!("{}_cos", name),
+            angles.iter().map(|a| Some(a.cos())).collect(),
+        ),
+    ]
+}
+
+fn datetime(time: u64) -> DateTime<Utc> {
+    calendar::utc(time)
+}
@@ -0,0 +1,4 @@
+pub mod allocation;
+pub mod backtesting;
+pub mod features;
+pub mod optimization;
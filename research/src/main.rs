@@ -1,4 +1,4 @@
-mod optimization;
+use research::optimization;
 
 fn test_func(x: f64, y: f64) -> f64 {
     (x - 1.0).powi(2) + (y - 2.0).powi(2)
@@ -12,4 +12,8 @@ fn main() {
     let initial = [100.0, 100.0];
     let result = optimization::optimize(initial, fitness, 10.0, 10.0);
     println!("Result: {:?}", result);
+
+    // How much worse the fitness gets within ±10% of each parameter of the optimum
+    let report = optimization::sensitivity(result, fitness, 0.1, 5, 0.5);
+    report.print(&["x", "y"]);
 }
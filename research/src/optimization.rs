@@ -4,6 +4,33 @@ pub fn optimize<const N: usize>(
     fitness: impl Fn([f64; N]) -> f64,
     perturbation: f64,
     speed: f64,
+) -> [f64; N] {
+    swarm(initial, fitness, perturbation, speed, |_, best| {
+        best <= 0.0001
+    })
+}
+
+// As optimize, for functions whose minimum isn't known to be 0: the best position found within
+// `iterations` iterations of the swarm
+pub fn optimize_iterations<const N: usize>(
+    initial: [f64; N],
+    fitness: impl Fn([f64; N]) -> f64,
+    perturbation: f64,
+    speed: f64,
+    iterations: usize,
+) -> [f64; N] {
+    swarm(initial, fitness, perturbation, speed, |iteration, _| {
+        iteration >= iterations
+    })
+}
+
+// Move the swarm until `done` says so, given the iterations so far and the best fitness
+fn swarm<const N: usize>(
+    initial: [f64; N],
+    fitness: impl Fn([f64; N]) -> f64,
+    perturbation: f64,
+    speed: f64,
+    done: impl Fn(usize, f64) -> bool,
 ) -> [f64; N] {
     const PARTICLE_COUNT: usize = 100;
     const INERTIA: f64 = 0.5;
@@ -42,7 +69,7 @@ pub fn optimize<const N: usize>(
 
     // Now we can start the optimization loop
     let mut iteration = 0;
-    while global_best_fitness > 0.0001 {
+    while !done(iteration, global_best_fitness) {
         iteration += 1;
         for i in 0..PARTICLE_COUNT {
             // Update the position of the particle
@@ -90,3 +117,105 @@ pub fn optimize<const N: usize>(
 
     global_best_position
 }
+
+// Fitness along one parameter with the others held at the optimum
+#[derive(Debug, Clone)]
+pub struct ParameterSlice {
+    pub index: usize,
+    pub optimum: f64,
+
+    // (offset from the optimum as a fraction of it, parameter value, fitness), in order of offset
+    pub points: Vec<(f64, f64, f64)>,
+
+    // Largest increase in fitness over the optimum's anywhere on the slice
+    pub worst_degradation: f64,
+
+    // Whether a small change in the parameter loses more than the tolerated fitness
+    pub fragile: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct SensitivityReport {
+    pub fitness: f64,
+    pub parameters: Vec<ParameterSlice>,
+}
+
+// Perturb each parameter of an optimum by up to ±`range` (0.1 is ±10%) in `steps` steps each way
+// and evaluate the fitness, to catch knife-edge optima where performance collapses with tiny
+// changes. A parameter is fragile if the fitness gets worse by more than `tolerance` anywhere
+// within the range. Parameters at 0 are perturbed by ±`range` itself rather than by a fraction.
+pub fn sensitivity<const N: usize>(
+    optimum: [f64; N],
+    fitness: impl Fn([f64; N]) -> f64,
+    range: f64,
+    steps: usize,
+    tolerance: f64,
+) -> SensitivityReport {
+    let best = fitness(optimum);
+    let steps = steps.max(1) as i64;
+
+    let mut parameters = Vec::with_capacity(N);
+    for index in 0..N {
+        let scale = if optimum[index] == 0.0 {
+            1.0
+        } else {
+            optimum[index].abs()
+        };
+
+        let mut points = Vec::new();
+        for step in -steps..=steps {
+            let offset = range * step as f64 / steps as f64;
+            let mut position = optimum;
+            position[index] += offset * scale;
+            let value = if step == 0 { best } else { fitness(position) };
+            points.push((offset, position[index], value));
+        }
+
+        let worst_degradation = points
+            .iter()
+            .map(|(_, _, value)| value - best)
+            .fold(0.0, f64::max);
+        parameters.push(ParameterSlice {
+            index,
+            optimum: optimum[index],
+            points,
+            worst_degradation,
+            fragile: worst_degradation > tolerance,
+        });
+    }
+
+    SensitivityReport {
+        fitness: best,
+        parameters,
+    }
+}
+
+impl SensitivityReport {
+    pub fn print(&self, names: &[&str]) {
+        println!(
+            "Sensitivity around the optimum (fitness {:.6}):",
+            self.fitness
+        );
+        for parameter in &self.parameters {
+            let name = names
+                .get(parameter.index)
+                .map(|name| name.to_string())
+                .unwrap_or_else(|| format!("#{}", parameter.index));
+            println!(
+                "  {} = {:.6}: worst degradation {:.6}{}",
+                name,
+                parameter.optimum,
+                parameter.worst_degradation,
+                if parameter.fragile { " (FRAGILE)" } else { "" }
+            );
+            for (offset, value, fitness) in &parameter.points {
+                println!(
+                    "    {:+6.1}%  {:>12.6}  {:.6}",
+                    offset * 100.0,
+                    value,
+                    fitness
+                );
+            }
+        }
+    }
+}
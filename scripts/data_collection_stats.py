@@ -1,6 +1,6 @@
 from collections import deque
 import subprocess
-from datetime import datetime
+from datetime import datetime, timezone
 
 class DataCollectionMonitor:
     def __init__(self, log_file_path):
@@ -34,7 +34,7 @@ class DataCollectionMonitor:
                     on_speed_update(self, timestamp, speed)
     
     def get_average_speed(self, interval: int = 60) -> float:
-        now = datetime.now()
+        now = datetime.now(timezone.utc)
         speeds_in_interval = []
         for timestamp, speed in reversed(self.speeds):
             if (diff := (now - timestamp).total_seconds()) <= interval:
@@ -103,8 +103,8 @@ class DataCollectionMonitor:
         # Extract the timestamp from the line
         timestamp_str = line[1:20]
 
-        # Convert the timestamp to a datetime object
-        return datetime.strptime(timestamp_str, '%Y-%m-%d %H:%M:%S')
+        # Convert the timestamp to a datetime object, log timestamps are in UTC
+        return datetime.strptime(timestamp_str, '%Y-%m-%d %H:%M:%S').replace(tzinfo=timezone.utc)
 
 def on_speed_update(monitor, timestamp, speed):
     print(f'[{timestamp}] {speed} pts/sec | {monitor.get_average_speed():.2f} pts/sec (60s)')
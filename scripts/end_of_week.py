@@ -73,7 +73,8 @@ def ensure_archive_dir(archive_dir: Path) -> None:
 
 
 def archive_data(data_dir: str, archive_dir: str, verbose: bool, week: int = None, year: int = None, force: bool = False) -> None:
-    calculated_year, calculated_week, _ = datetime.date.today().isocalendar()
+    # Weeks are UTC weeks, the same as the collector's
+    calculated_year, calculated_week, _ = datetime.datetime.now(datetime.timezone.utc).date().isocalendar()
     if week is None:
         week = calculated_week
     if year is None:
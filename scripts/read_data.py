@@ -9,10 +9,15 @@ import struct
 
 from pathlib import Path
 
+# Files with nanosecond timestamps (version 2) start with a header: this magic, the version and 4 unused bytes
+HEADER_MAGIC = b'QLTICKS\0'
+
 class Row:
-    def __init__(self, timestamp: int, bid: float, ask: float):
+    def __init__(self, timestamp: int, bid: float, ask: float, nanoseconds: bool = False):
         try:
-            self.timestamp = datetime.datetime.fromtimestamp(timestamp / 1000) # Convert from milliseconds to seconds and then to datetime
+            # Timestamps are in milliseconds (or nanoseconds) since the UNIX epoch, in UTC
+            seconds = timestamp / 1e9 if nanoseconds else timestamp / 1000
+            self.timestamp = datetime.datetime.fromtimestamp(seconds, tz=datetime.timezone.utc)
         except ValueError:
             raise ValueError(f'Invalid timestamp: {timestamp} (is the byte order correct?)')
 
@@ -26,10 +31,10 @@ class Row:
         return str(self)
     
     @staticmethod
-    def from_bytes(data: bytes, byte_order: str = 'little'):
+    def from_bytes(data: bytes, byte_order: str = 'little', nanoseconds: bool = False):
         byte_order = '>' if byte_order == 'big' else '<'
         timestamp, bid, ask = struct.unpack(byte_order + 'Qff', data)
-        return Row(timestamp, bid, ask)
+        return Row(timestamp, bid, ask, nanoseconds)
     
     def to_bytes(self):
         return struct.pack('Qff', int(self.timestamp.timestamp()), self.bid, self.ask)
@@ -40,12 +45,22 @@ def read_binary_file(binary_file: Path, byte_order: str = 'little', num_rows: in
         num_rows = int(1e9)
 
     with open(binary_file, 'rb') as f:
+        nanoseconds = False
+        header = f.read(16)
+        if header[:8] == HEADER_MAGIC:
+            version = struct.unpack('>I', header[8:12])[0]
+            if version != 2:
+                raise ValueError(f'Unsupported file version: {version}')
+            nanoseconds = True
+        else:
+            f.seek(0)
+
         for i in range(num_rows):
             data = f.read(16)
             if len(data) < 16:
                 print(f'End of file reached after {i} rows')
                 break
-            row = Row.from_bytes(data, byte_order=byte_order)
+            row = Row.from_bytes(data, byte_order=byte_order, nanoseconds=nanoseconds)
             yield row
 
 def main():
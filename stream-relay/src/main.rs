@@ -0,0 +1,35 @@
+use quantlib::logging;
+use quantlib::oanda::{serve_relay, ShardedPriceStream, StreamMultiplexer};
+use std::env;
+use std::error::Error;
+use std::sync::Arc;
+
+// Opens the OANDA price stream once and relays it to local processes (the trader, the collector)
+// so they don't each hold their own connections against the account's connection limit
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn Error>> {
+    let args: Vec<String> = env::args().collect();
+    if args.len() < 2 {
+        eprintln!("Usage: {} <address> [instrument,instrument,...]", args[0]);
+        std::process::exit(1);
+    }
+
+    logging::configure_logger("logs/stream-relay.log")?;
+
+    let settings = quantlib::util::read_settings().unwrap_or_else(|err| {
+        log::error!("Failed to read settings: {}", err);
+        std::process::exit(1);
+    });
+
+    // Relay the instruments given on the command line, or those in the settings file
+    let instruments: Vec<String> = match args.get(2) {
+        Some(list) => list.split(',').map(|i| i.to_string()).collect(),
+        None => settings.data.instruments.clone(),
+    };
+
+    let source =
+        ShardedPriceStream::new(instruments.clone(), &settings.credentials.oanda, 20, 10_000);
+    let multiplexer = Arc::new(StreamMultiplexer::new(&instruments, source));
+
+    serve_relay(multiplexer, &args[1]).await
+}